@@ -0,0 +1,66 @@
+//! Compares sending windows with and without read-ahead when both the disk
+//! and the network add latency.
+//!
+//! Run with `cargo bench --bench read_ahead`.
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use xtool::tftp::core::{ReadAhead, Window};
+
+const FILE_SIZE: usize = 8 * 1024 * 1024;
+const BLOCK_SIZE: u16 = 1428;
+const WINDOW_SIZE: u16 = 16;
+/// Time for a window to be sent and acknowledged
+const ROUND_TRIP: Duration = Duration::from_micros(500);
+/// Time for the disk to return one read
+const READ_LATENCY: Duration = Duration::from_micros(30);
+const READ_SIZE: usize = 4096;
+
+/// Yields zeroes, at most [`READ_SIZE`] bytes per read after [`READ_LATENCY`]
+struct SlowDisk {
+    remaining: usize,
+}
+
+impl Read for SlowDisk {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = buf.len().min(READ_SIZE).min(self.remaining);
+        if size > 0 {
+            thread::sleep(READ_LATENCY);
+        }
+        buf[..size].fill(0);
+        self.remaining -= size;
+        Ok(size)
+    }
+}
+
+fn send(mut window: Window<impl Read>) -> Duration {
+    let start = Instant::now();
+    loop {
+        let more = window.fill().unwrap();
+        thread::sleep(ROUND_TRIP);
+        window.remove(window.len()).unwrap();
+        if !more {
+            return start.elapsed();
+        }
+    }
+}
+
+fn main() {
+    let disk = || SlowDisk {
+        remaining: FILE_SIZE,
+    };
+
+    let baseline = send(Window::new(WINDOW_SIZE, BLOCK_SIZE, disk()));
+    println!("read-ahead off: {baseline:?}");
+
+    for depth in [1, 2, 4] {
+        let reader = ReadAhead::new(disk(), WINDOW_SIZE as usize * BLOCK_SIZE as usize, depth);
+        let elapsed = send(Window::new(WINDOW_SIZE, BLOCK_SIZE, reader));
+        println!(
+            "read-ahead {depth}: {elapsed:?} ({:.2}x)",
+            baseline.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -2,7 +2,7 @@ use log::info;
 use serde::{Deserialize, Serialize};
 use std::fs;
 
-use crate::serial::config::SerialConfig;
+use crate::serial::config::{NetTuning, SerialConfig};
 use crate::tftp::client::config::ClientConfig;
 use crate::tftp::client::config::TftpcConfigFile;
 use crate::tftp::server::config::Config as TftpdConfig;
@@ -56,12 +56,16 @@ impl AppConfig {
             tftpc: Some(TftpcConfigFile {
                 get: Some(ClientConfig::new("127.0.0.1".to_string(), 69)),
                 put: Some(ClientConfig::new("127.0.0.1".to_string(), 69)),
+                resolver: None,
             }),
             serial: Some(SerialConfig {
                 uart: Some("COM1".to_string()),
                 baud: Some(115200),
                 net_port: Some(5432),
                 net_bind: Some("0.0.0.0".to_string()),
+                netd: Some(NetTuning::netd()),
+                netc: Some(NetTuning::netc()),
+                milestones: None,
             }),
         };
 
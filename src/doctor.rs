@@ -0,0 +1,338 @@
+//! Environment diagnostics
+//!
+//! `xtool doctor` checks the setup problems users hit most often across the
+//! TFTP and serial tools, and prints a fix for each check that does not pass.
+
+use crate::i18n::tr_args;
+use anyhow::Result;
+use std::fmt;
+use std::net::UdpSocket;
+
+/// Outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    /// Not applicable on this system
+    Skip,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Status::Ok => " OK ",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+            Status::Skip => "SKIP",
+        };
+        write!(f, "[{label}]")
+    }
+}
+
+/// Result of one diagnostic
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// Command or action that fixes the problem
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// Runs all checks
+pub fn checks() -> Vec<Check> {
+    vec![
+        check_tftp_port(),
+        check_firewall(),
+        check_serial_permissions(),
+        check_mtu(),
+        check_time_sync(),
+    ]
+}
+
+/// Prints the result of all checks, failing if any of them failed
+pub fn run() -> Result<()> {
+    let checks = checks();
+    for check in &checks {
+        println!("{} {:<18} {}", check.status, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("       {}", tr_args("doctor.fix", &[fix]));
+        }
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!(tr_args("doctor.failed", &[&failed])));
+    }
+    Ok(())
+}
+
+fn check_tftp_port() -> Check {
+    const NAME: &str = "TFTP port 69";
+
+    match UdpSocket::bind("0.0.0.0:69") {
+        Ok(_) => Check::new(NAME, Status::Ok, "can bind UDP port 69"),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Check::new(
+            NAME,
+            Status::Fail,
+            "binding ports below 1024 needs elevated privileges",
+        )
+        .with_fix(if cfg!(windows) {
+            "run xtool from an administrator prompt"
+        } else {
+            "sudo setcap cap_net_bind_service=+eip $(which xtool)"
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Check::new(
+            NAME,
+            Status::Warn,
+            "UDP port 69 is in use, another TFTP server is probably running",
+        )
+        .with_fix("stop it (e.g. sudo systemctl stop tftpd-hpa) or serve on another port with -p"),
+        Err(e) => Check::new(NAME, Status::Fail, format!("cannot bind UDP port 69: {e}")),
+    }
+}
+
+fn check_firewall() -> Check {
+    const NAME: &str = "Firewall";
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::path::Path;
+
+        let ufw_enabled = std::fs::read_to_string("/etc/ufw/ufw.conf")
+            .map(|conf| conf.lines().any(|line| line.trim() == "ENABLED=yes"))
+            .unwrap_or(false);
+        if ufw_enabled {
+            return Check::new(
+                NAME,
+                Status::Warn,
+                "ufw is enabled and may drop TFTP traffic",
+            )
+            .with_fix("sudo ufw allow 69/udp (and the data ports, or use tftpd -s)");
+        }
+        if Path::new("/run/firewalld/firewalld.pid").exists()
+            || Path::new("/var/run/firewalld.pid").exists()
+        {
+            return Check::new(
+                NAME,
+                Status::Warn,
+                "firewalld is running and may drop TFTP traffic",
+            )
+            .with_fix(
+                "sudo firewall-cmd --add-service=tftp --permanent && sudo firewall-cmd --reload",
+            );
+        }
+        Check::new(NAME, Status::Ok, "no ufw or firewalld detected")
+    }
+
+    #[cfg(windows)]
+    {
+        Check::new(
+            NAME,
+            Status::Warn,
+            "Windows Defender Firewall blocks inbound UDP by default",
+        )
+        .with_fix("netsh advfirewall firewall add rule name=xtool-tftp dir=in action=allow protocol=UDP localport=69")
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        Check::new(NAME, Status::Skip, "only checked on Linux and Windows")
+    }
+}
+
+fn check_serial_permissions() -> Check {
+    const NAME: &str = "Serial devices";
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::MetadataExt;
+
+        let devices: Vec<_> = std::fs::read_dir("/dev")
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|n| n.to_str())
+                            .is_some_and(|n| n.starts_with("ttyUSB") || n.starts_with("ttyACM"))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        if devices.is_empty() {
+            return Check::new(NAME, Status::Skip, "no USB serial devices found");
+        }
+
+        let denied: Vec<_> = devices
+            .iter()
+            .filter(|path| {
+                let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+                    return true;
+                };
+                // SAFETY: `c_path` is a valid NUL terminated string for the call.
+                unsafe { libc::access(c_path.as_ptr(), libc::R_OK | libc::W_OK) != 0 }
+            })
+            .collect();
+        let Some(first) = denied.first() else {
+            return Check::new(
+                NAME,
+                Status::Ok,
+                format!("{} device(s) accessible", devices.len()),
+            );
+        };
+
+        let group = std::fs::metadata(first)
+            .ok()
+            .and_then(|m| {
+                let groups = std::fs::read_to_string("/etc/group").ok()?;
+                group_name(&groups, m.gid())
+            })
+            .unwrap_or_else(|| "dialout".to_string());
+        let names: Vec<_> = denied.iter().map(|p| p.display().to_string()).collect();
+        Check::new(
+            NAME,
+            Status::Fail,
+            format!("no read/write access to {}", names.join(", ")),
+        )
+        .with_fix(format!(
+            "sudo usermod -aG {group} $USER, then log out and back in"
+        ))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        Check::new(NAME, Status::Skip, "only checked on Linux")
+    }
+}
+
+fn check_mtu() -> Check {
+    const NAME: &str = "Interface MTU";
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut interfaces: Vec<(String, u32)> = std::fs::read_dir("/sys/class/net")
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        let path = entry.path();
+                        let up = std::fs::read_to_string(path.join("operstate"))
+                            .is_ok_and(|state| state.trim() == "up");
+                        let mtu = std::fs::read_to_string(path.join("mtu"))
+                            .ok()?
+                            .trim()
+                            .parse()
+                            .ok()?;
+                        (name != "lo" && up).then_some((name, mtu))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        interfaces.sort();
+
+        if interfaces.is_empty() {
+            return Check::new(NAME, Status::Warn, "no network interface is up");
+        }
+
+        let detail = interfaces
+            .iter()
+            .map(|(name, mtu)| format!("{name} {mtu} (blksize <= {})", largest_block_size(*mtu)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let smallest = interfaces.iter().map(|(_, mtu)| *mtu).min().unwrap_or(1500);
+        if smallest < 1500 {
+            Check::new(NAME, Status::Warn, detail).with_fix(format!(
+                "use -b {} or less to avoid IP fragmentation",
+                largest_block_size(smallest)
+            ))
+        } else {
+            Check::new(NAME, Status::Ok, detail)
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        Check::new(NAME, Status::Skip, "only checked on Linux")
+    }
+}
+
+fn check_time_sync() -> Check {
+    const NAME: &str = "Time sync";
+
+    let output = std::process::Command::new("timedatectl")
+        .args(["show", "--property=NTPSynchronized", "--value"])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            match String::from_utf8_lossy(&output.stdout).trim() {
+                "yes" => Check::new(NAME, Status::Ok, "clock is synchronized"),
+                _ => Check::new(
+                    NAME,
+                    Status::Warn,
+                    "clock is not synchronized, log timestamps will not match other hosts",
+                )
+                .with_fix("sudo timedatectl set-ntp true"),
+            }
+        }
+        _ => Check::new(NAME, Status::Skip, "timedatectl is not available"),
+    }
+}
+
+/// Largest TFTP block size that fits in one IP packet, after the IPv4, UDP
+/// and TFTP headers
+fn largest_block_size(mtu: u32) -> u32 {
+    mtu.saturating_sub(20 + 8 + 4)
+}
+
+/// Looks up the name of group `gid` in the content of `/etc/group`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn group_name(groups: &str, gid: u32) -> Option<String> {
+    groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?.parse::<u32>().ok()?;
+        (id == gid).then(|| name.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_largest_block_size() {
+        assert_eq!(largest_block_size(1500), 1468);
+        assert_eq!(largest_block_size(9000), 8968);
+        assert_eq!(largest_block_size(10), 0);
+    }
+
+    #[test]
+    fn test_group_name() {
+        let groups = "root:x:0:\ntty:x:5:\ndialout:x:20:alice,bob\n";
+        assert_eq!(group_name(groups, 20).as_deref(), Some("dialout"));
+        assert_eq!(group_name(groups, 0).as_deref(), Some("root"));
+        assert_eq!(group_name(groups, 99), None);
+    }
+}
@@ -0,0 +1,120 @@
+//! Events shared between subsystems
+//!
+//! Subsystems publish what happens to them on an [`EventBus`], and consumers
+//! such as progress views or telemetry subscribe to it instead of parsing
+//! the logs of each subsystem. [`EventBus::global()`] is the bus of the
+//! process, which the subsystems started from the command line publish to.
+
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::tftp::server::TransferInfo;
+
+/// Event `enum` is something that happened in a subsystem, published on an
+/// [`EventBus`]. Variants may be added, so matches need a wildcard arm.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A TFTP transfer began, its options negotiated
+    TransferStarted(TransferInfo),
+    /// Bytes of a TFTP transfer acknowledged or received so far
+    TransferProgress {
+        transfer: TransferInfo,
+        bytes: u64,
+    },
+    TransferCompleted {
+        transfer: TransferInfo,
+        bytes: u64,
+    },
+    TransferFailed {
+        transfer: TransferInfo,
+        error: String,
+    },
+    /// A client connected to the serial port shared by `xtool serial netd`
+    ClientConnected(SocketAddr),
+    ClientDisconnected(SocketAddr),
+    /// A line of the serial console contains `pattern`, one of the
+    /// milestones watched by the monitor
+    SerialPatternMatched {
+        pattern: String,
+        line: String,
+    },
+}
+
+/// EventBus `struct` delivers every published [`Event`] to all of its
+/// subscribers, in the order they were published. Clones share the
+/// subscribers.
+///
+/// Publishing never blocks, events are queued until the subscriber receives
+/// them. Subscribers whose receiver was dropped are removed.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::events::{Event, EventBus};
+///
+/// let bus = EventBus::new();
+/// let events = bus.subscribe();
+/// let peer = "192.168.1.20:1069".parse().unwrap();
+/// bus.publish(Event::ClientConnected(peer));
+/// assert_eq!(events.recv().unwrap(), Event::ClientConnected(peer));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    /// Returns the bus of the process.
+    pub fn global() -> &'static EventBus {
+        static BUS: OnceLock<EventBus> = OnceLock::new();
+        BUS.get_or_init(EventBus::new)
+    }
+
+    /// Returns the receiver of the events published from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    /// Delivers `event` to the subscribers.
+    pub fn publish(&self, event: Event) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_to_every_subscriber() {
+        let bus = EventBus::new();
+        let first = bus.subscribe();
+        let second = bus.clone().subscribe();
+        let peer: SocketAddr = "10.0.0.2:5432".parse().unwrap();
+
+        bus.publish(Event::ClientConnected(peer));
+        drop(first);
+        bus.publish(Event::ClientDisconnected(peer));
+
+        assert_eq!(
+            second.try_iter().collect::<Vec<_>>(),
+            [
+                Event::ClientConnected(peer),
+                Event::ClientDisconnected(peer)
+            ]
+        );
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,203 @@
+//! Curated command examples compiled into the binary
+//!
+//! `xtool examples` lists the topics, `xtool examples <topic>` prints the
+//! runnable commands of one topic. The examples are kept here rather than in
+//! web docs so they are available on air-gapped lab machines.
+
+use anyhow::Result;
+
+use crate::i18n::tr;
+
+/// A runnable example command
+pub struct Example {
+    /// What the command does
+    pub description: &'static str,
+    /// Command line to run
+    pub command: &'static str,
+    /// Additional hint printed below the command
+    pub note: Option<&'static str>,
+}
+
+/// A group of related examples
+pub struct Topic {
+    /// Name used on the command line
+    pub name: &'static str,
+    /// One line summary shown in the topic list
+    pub summary: &'static str,
+    pub examples: &'static [Example],
+}
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "pxe",
+        summary: "Serve boot images to PXE clients",
+        examples: &[
+            Example {
+                description: "Serve the boot files read-only on all interfaces",
+                command: "xtool tftpd -r /srv/tftp",
+                note: Some("Point the DHCP server's next-server/filename at this host, e.g. pxelinux.0"),
+            },
+            Example {
+                description: "Serve on one interface only, behind NAT or a firewall",
+                command: "xtool tftpd -i 192.168.1.10 -s /srv/tftp",
+                note: Some("Single port mode answers from port 69, only one port needs to be open"),
+            },
+            Example {
+                description: "Check the boot loader can be fetched like a PXE client would",
+                command: "xtool tftpc get 192.168.1.10 pxelinux.0 /tmp/pxelinux.0 -b 1468",
+                note: None,
+            },
+        ],
+    },
+    Topic {
+        name: "uboot",
+        summary: "Reach a U-Boot console over the network and load images",
+        examples: &[
+            Example {
+                description: "Share the board's serial console on TCP port 5432",
+                command: "xtool serial netd /dev/ttyUSB0 -b 115200 -p 5432",
+                note: None,
+            },
+            Example {
+                description: "Attach to the shared console from another machine",
+                command: "xtool serial netc -s 192.168.1.10 -p 5432",
+                note: Some("Interrupt autoboot, then run: setenv serverip 192.168.1.10; tftpboot ${loadaddr} zImage"),
+            },
+            Example {
+                description: "Serve the images U-Boot loads with tftpboot",
+                command: "xtool tftpd /srv/tftp",
+                note: None,
+            },
+            Example {
+                description: "Stream boot logs with fewer, larger TCP segments",
+                command: "xtool serial netd /dev/ttyUSB0 --nagle --coalesce 20",
+                note: None,
+            },
+        ],
+    },
+    Topic {
+        name: "tftp",
+        summary: "Transfer files with the TFTP client",
+        examples: &[
+            Example {
+                description: "Download a file",
+                command: "xtool tftpc get 192.168.1.100 firmware.bin",
+                note: None,
+            },
+            Example {
+                description: "Download with large blocks and verify the result",
+                command: "xtool tftpc get 192.168.1.100 firmware.bin -b 8192 --verify <SHA256>",
+                note: None,
+            },
+            Example {
+                description: "Continue an interrupted download",
+                command: "xtool tftpc get 192.168.1.100 rootfs.img --resume",
+                note: Some("Needs an xtool server, progress is kept in rootfs.img.xtool-resume"),
+            },
+            Example {
+                description: "Upload a file under another name",
+                command: "xtool tftpc put 192.168.1.100 build/dump.bin dump-001.bin",
+                note: None,
+            },
+            Example {
+                description: "Find servers on the local network",
+                command: "xtool tftpc discover",
+                note: None,
+            },
+        ],
+    },
+    Topic {
+        name: "serial",
+        summary: "Use local serial ports",
+        examples: &[
+            Example {
+                description: "List the available serial ports",
+                command: "xtool serial list",
+                note: None,
+            },
+            Example {
+                description: "Open an interactive console",
+                command: "xtool serial /dev/ttyUSB0 -b 115200",
+                note: Some("Press Ctrl + ] to exit"),
+            },
+            Example {
+                description: "Write the port and baud rate defaults to .xtool.toml",
+                command: "xtool genconfig",
+                note: Some("Edit [serial] in the generated file, then run: xtool serial"),
+            },
+        ],
+    },
+    Topic {
+        name: "flash",
+        summary: "Recipe: wait for a target to boot, then flash it",
+        examples: &[
+            Example {
+                description: "Wait up to 60 seconds for the target's TFTP service",
+                command: "xtool tftpc ping 192.168.1.50 --wait 60",
+                note: Some("Exits with an error if the target never answers"),
+            },
+            Example {
+                description: "Upload the image once the target answers",
+                command: "xtool tftpc ping 192.168.1.50 --wait 60 && xtool tftpc put 192.168.1.50 image.bin",
+                note: None,
+            },
+        ],
+    },
+];
+
+/// Looks up a topic by name, ignoring case
+pub fn find(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|t| t.name.eq_ignore_ascii_case(name))
+}
+
+/// Prints the topic list, or the examples of `topic`
+pub fn run(topic: Option<String>) -> Result<()> {
+    let Some(name) = topic else {
+        println!("{}", tr("examples.topics"));
+        for topic in TOPICS {
+            println!("  {:<8} {}", topic.name, topic.summary);
+        }
+        println!();
+        println!("{}", tr("examples.hint"));
+        return Ok(());
+    };
+
+    let topic = find(&name).ok_or_else(|| {
+        let names: Vec<&str> = TOPICS.iter().map(|t| t.name).collect();
+        anyhow::anyhow!(
+            "Unknown topic '{}', available topics: {}",
+            name,
+            names.join(", ")
+        )
+    })?;
+
+    println!("{}: {}", topic.name, topic.summary);
+    for example in topic.examples {
+        println!();
+        println!("  # {}", example.description);
+        println!("  {}", example.command);
+        if let Some(note) = example.note {
+            println!("  ({})", note);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics() {
+        for (i, topic) in TOPICS.iter().enumerate() {
+            assert!(!topic.examples.is_empty(), "{} has no examples", topic.name);
+            assert!(TOPICS[..i].iter().all(|t| t.name != topic.name));
+            for example in topic.examples {
+                assert!(example.command.starts_with("xtool "));
+            }
+        }
+
+        assert_eq!(find("PXE").map(|t| t.name), Some("pxe"));
+        assert!(find("unknown").is_none());
+    }
+}
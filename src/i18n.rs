@@ -0,0 +1,252 @@
+//! Localized CLI messages
+//!
+//! User-facing output of the commands is looked up by key in a small message
+//! catalog, in English or Simplified Chinese. The language is selected with
+//! `--lang`, the `XTOOL_LANG` environment variable or the system locale, in
+//! that order. Log messages stay in English so they can be searched.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Languages of the message catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    ZhCn,
+}
+
+impl FromStr for Lang {
+    type Err = anyhow::Error;
+
+    /// Parses a language tag or locale, e.g. `zh-CN` or `zh_CN.UTF-8`
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let tag = value
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+            .replace('_', "-");
+        match tag.as_str() {
+            "zh" | "zh-cn" | "zh-hans" | "zh-sg" => Ok(Lang::ZhCn),
+            "c" | "posix" => Ok(Lang::En),
+            tag if tag == "en" || tag.starts_with("en-") => Ok(Lang::En),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported language '{value}', expected en or zh-CN"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lang::En => write!(f, "en"),
+            Lang::ZhCn => write!(f, "zh-CN"),
+        }
+    }
+}
+
+impl Lang {
+    /// Language of the environment: `XTOOL_LANG`, then the locale variables,
+    /// English if none is set or supported
+    pub fn from_env() -> Lang {
+        if let Ok(value) = std::env::var("XTOOL_LANG")
+            && let Ok(lang) = value.parse()
+        {
+            return lang;
+        }
+
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(Lang::En)
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Selects the language of the messages, `lang` taking precedence over the
+/// environment. Only the first call has an effect.
+pub fn init(lang: Option<&str>) -> anyhow::Result<Lang> {
+    let lang = match lang {
+        Some(value) => value.parse()?,
+        None => Lang::from_env(),
+    };
+    Ok(*LANG.get_or_init(|| lang))
+}
+
+/// Language of the messages, detected from the environment if [`init`] was
+/// not called
+pub fn lang() -> Lang {
+    *LANG.get_or_init(Lang::from_env)
+}
+
+struct Message {
+    key: &'static str,
+    en: &'static str,
+    zh_cn: &'static str,
+}
+
+const MESSAGES: &[Message] = &[
+    Message {
+        key: "serial.no_ports",
+        en: "No serial ports found.",
+        zh_cn: "未找到串口。",
+    },
+    Message {
+        key: "serial.available",
+        en: "Available serial ports:",
+        zh_cn: "可用串口：",
+    },
+    Message {
+        key: "serial.product",
+        en: "Product: {}",
+        zh_cn: "产品：{}",
+    },
+    Message {
+        key: "serial.manufacturer",
+        en: "Manufacturer: {}",
+        zh_cn: "厂商：{}",
+    },
+    Message {
+        key: "serial.type",
+        en: "Type: {}",
+        zh_cn: "类型：{}",
+    },
+    Message {
+        key: "serial.bluetooth",
+        en: "Bluetooth",
+        zh_cn: "蓝牙",
+    },
+    Message {
+        key: "serial.unknown",
+        en: "Unknown",
+        zh_cn: "未知",
+    },
+    Message {
+        key: "monitor.connected",
+        en: "Connected to {} at {} baud. Press 'Ctrl + ]' to exit.",
+        zh_cn: "已连接 {}，波特率 {}。按 'Ctrl + ]' 退出。",
+    },
+    Message {
+        key: "monitor.serving",
+        en: "Serving {} over TFTP on port 69, transfers are shown along with the console milestones.",
+        zh_cn: "正在通过 TFTP 在端口 69 上提供 {}，传输进度与控制台里程碑一同显示。",
+    },
+    Message {
+        key: "monitor.disconnected",
+        en: "Disconnected.",
+        zh_cn: "已断开连接。",
+    },
+    Message {
+        key: "tftpc.no_servers",
+        en: "No TFTP servers found.",
+        zh_cn: "未发现 TFTP 服务器。",
+    },
+    Message {
+        key: "tftpc.discovered",
+        en: "Discovered TFTP servers:",
+        zh_cn: "发现的 TFTP 服务器：",
+    },
+    Message {
+        key: "tftpc.alive",
+        en: "{} is alive (rtt {})",
+        zh_cn: "{} 在线（往返时间 {}）",
+    },
+    Message {
+        key: "examples.topics",
+        en: "Available topics:",
+        zh_cn: "可用主题：",
+    },
+    Message {
+        key: "examples.hint",
+        en: "Run 'xtool examples <TOPIC>' to show the commands of a topic.",
+        zh_cn: "运行 'xtool examples <TOPIC>' 查看某个主题的命令。",
+    },
+    Message {
+        key: "doctor.fix",
+        en: "fix: {}",
+        zh_cn: "修复：{}",
+    },
+    Message {
+        key: "doctor.failed",
+        en: "{} check(s) failed",
+        zh_cn: "{} 项检查失败",
+    },
+];
+
+/// Returns the message `key` in the current language, the key itself if it
+/// is not in the catalog
+pub fn tr(key: &'static str) -> &'static str {
+    let Some(message) = MESSAGES.iter().find(|m| m.key == key) else {
+        return key;
+    };
+    match lang() {
+        Lang::En => message.en,
+        Lang::ZhCn => message.zh_cn,
+    }
+}
+
+/// Returns the message `key` with its `{}` placeholders replaced by `args`
+/// in order
+pub fn tr_args(key: &'static str, args: &[&dyn fmt::Display]) -> String {
+    fill(tr(key), args)
+}
+
+fn fill(template: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    let mut output = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            output.push_str(&arg.to_string());
+        }
+        output.push_str(part);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_languages() {
+        assert_eq!("en".parse::<Lang>().unwrap(), Lang::En);
+        assert_eq!("en_US.UTF-8".parse::<Lang>().unwrap(), Lang::En);
+        assert_eq!("C".parse::<Lang>().unwrap(), Lang::En);
+        assert_eq!("zh-CN".parse::<Lang>().unwrap(), Lang::ZhCn);
+        assert_eq!("zh_CN.UTF-8".parse::<Lang>().unwrap(), Lang::ZhCn);
+        assert!("fr".parse::<Lang>().is_err());
+    }
+
+    #[test]
+    fn catalog_is_complete() {
+        for (i, message) in MESSAGES.iter().enumerate() {
+            assert!(
+                MESSAGES[..i].iter().all(|m| m.key != message.key),
+                "duplicate key {}",
+                message.key
+            );
+            assert_eq!(
+                message.en.matches("{}").count(),
+                message.zh_cn.matches("{}").count(),
+                "placeholders of {} differ",
+                message.key
+            );
+        }
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            fill("Connected to {} at {} baud.", &[&"COM1", &115200]),
+            "Connected to COM1 at 115200 baud."
+        );
+        assert_eq!(fill("{} check(s) failed", &[]), " check(s) failed");
+        assert_eq!(tr("no.such.key"), "no.such.key");
+    }
+}
@@ -1,6 +1,12 @@
 pub mod config;
+pub mod doctor;
+pub mod events;
+pub mod examples;
+pub mod i18n;
+pub mod progress;
 pub mod serial;
 pub mod tftp;
+pub mod version;
 
 #[macro_use]
 extern crate log;
@@ -1,6 +1,14 @@
 mod config;
+mod doctor;
+// Only partly used by the binary
+#[allow(dead_code)]
+mod events;
+mod examples;
+mod i18n;
+mod progress;
 mod serial;
 mod tftp;
+mod version;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
@@ -14,6 +22,10 @@ extern crate log;
 #[command(name = "xtool")]
 #[command(version, about = "Amazing Tools", long_about = None)]
 struct Cli {
+    /// Language of the messages, e.g. en or zh-CN (defaults to XTOOL_LANG or the locale)
+    #[arg(long, global = true, value_name = "LANG")]
+    lang: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -34,6 +46,14 @@ enum Commands {
         #[arg(value_name = "PATH")]
         path: PathBuf,
 
+        /// Directory uploads are written to, instead of PATH
+        #[arg(long, value_name = "DIR")]
+        upload_dir: Option<PathBuf>,
+
+        /// Directory downloads are served from, instead of PATH
+        #[arg(long, value_name = "DIR")]
+        download_dir: Option<PathBuf>,
+
         /// Enable read-only mode
         #[arg(short, long)]
         read_only: bool,
@@ -41,6 +61,22 @@ enum Commands {
         /// Use single port mode (useful for NAT environments)
         #[arg(short, long)]
         single_port: bool,
+
+        /// Number of times a packet is resent without an answer before giving up (default 6)
+        #[arg(long, value_name = "N")]
+        retries: Option<usize>,
+
+        /// Seconds before resending a packet, unless negotiated by the client (default 5)
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+
+        /// Install, uninstall or run the server as a Windows service
+        #[arg(long, value_name = "ACTION")]
+        service: Option<tftp::server::service::ServiceAction>,
+
+        /// Directory the service runs from, set on install
+        #[arg(long, value_name = "DIR", hide = true)]
+        service_dir: Option<PathBuf>,
     },
 
     /// TFTP client - download or upload files
@@ -59,6 +95,10 @@ enum Commands {
         #[arg(short, long)]
         baud: Option<u32>,
 
+        /// Serve DIR over TFTP while monitoring, showing the transfers along with the console milestones
+        #[arg(long, value_name = "DIR")]
+        serve: Option<PathBuf>,
+
         #[command(subcommand)]
         subcommand: Option<serial::SerialSubcommand>,
     },
@@ -69,45 +109,61 @@ enum Commands {
         #[arg(long)]
         force: bool,
     },
+
+    /// Show runnable command examples, e.g. for PXE boot or U-Boot consoles
+    Examples {
+        /// Topic to show, lists the topics if omitted
+        #[arg(value_name = "TOPIC")]
+        topic: Option<String>,
+    },
+
+    /// Check the environment for common setup problems, e.g. port 69 or serial permissions
+    Doctor,
+
+    /// Show the version and capabilities of this build
+    Version {
+        /// Print as JSON, for scripts and orchestration tools
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 fn main() -> Result<()> {
-    // Initialize logger, default info level, display file line number and time
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format(|buf, record| {
-            use std::io::Write;
-            let level_style = buf.default_level_style(record.level());
-            writeln!(
-                buf,
-                "[{} {level_style}{}{level_style:#} {}:{}] {level_style}{}{level_style:#}",
-                chrono::Local::now().format("%H:%M:%S"),
-                record.level(),
-                record.target(),
-                record.line().unwrap_or(0),
-                record.args()
-            )
-        })
-        .init();
-
     let cli = Cli::parse();
 
-    // Try to load configuration file
+    // Services are started from the system directory, not the one they were installed from
+    if let Commands::Tftpd { service_dir: Some(dir), .. } = &cli.command {
+        std::env::set_current_dir(dir)?;
+    }
+
+    // Configuration file is loaded first, as it may set the log format
     let config_path = ".xtool.toml";
-    let app_config = if std::path::Path::new(config_path).exists() {
-        match config::AppConfig::load_from_file(config_path) {
-            Ok(cfg) => {
-                let abs_path = std::fs::canonicalize(config_path)
-                    .unwrap_or_else(|_| std::path::PathBuf::from(config_path));
-                info!("Using configuration file: {}", abs_path.display());
-                Some(cfg)
-            }
-            Err(e) => {
-                error!("Failed to load configuration file: {}, using defaults", e);
-                None
-            }
+    let loaded = std::path::Path::new(config_path)
+        .exists()
+        .then(|| config::AppConfig::load_from_file(config_path));
+    let json_log = matches!(cli.command, Commands::Tftpd { .. })
+        && loaded
+            .as_ref()
+            .and_then(|cfg| cfg.as_ref().ok())
+            .and_then(|cfg| cfg.tftpd.as_ref())
+            .and_then(|tftpd| tftpd.log_format)
+            == Some(tftp::server::LogFormat::Json);
+    init_logger(json_log);
+
+    i18n::init(cli.lang.as_deref())?;
+
+    let app_config = match loaded {
+        Some(Ok(cfg)) => {
+            let abs_path = std::fs::canonicalize(config_path)
+                .unwrap_or_else(|_| std::path::PathBuf::from(config_path));
+            info!("Using configuration file: {}", abs_path.display());
+            Some(cfg)
         }
-    } else {
-        None
+        Some(Err(e)) => {
+            error!("Failed to load configuration file: {}, using defaults", e);
+            None
+        }
+        None => None,
     };
 
     match cli.command {
@@ -115,37 +171,66 @@ fn main() -> Result<()> {
             ip,
             port,
             path,
+            upload_dir,
+            download_dir,
             read_only,
             single_port,
+            retries,
+            timeout,
+            service,
+            service_dir: _,
         } => {
-            tftp::server::run_with_config(
-                ip,
-                port,
-                path,
-                read_only,
-                single_port,
-                app_config.as_ref().and_then(|c| c.tftpd.clone()),
-            )?;
+            let config = app_config
+                .as_ref()
+                .and_then(|c| c.tftpd.clone())
+                .unwrap_or_default()
+                .merge_cli_directories(upload_dir, download_dir)
+                .merge_cli_tuning(retries, timeout);
+            match service {
+                Some(action) => tftp::server::run_service(
+                    action,
+                    ip,
+                    port,
+                    path,
+                    read_only,
+                    single_port,
+                    Some(config),
+                )?,
+                None => tftp::server::run_with_config(
+                    ip,
+                    port,
+                    path,
+                    read_only,
+                    single_port,
+                    Some(config),
+                )?,
+            }
         }
 
         Commands::Tftpc { action } => {
             // Client configuration merging is handled inside client::run_with_config
-            tftp::client::run_with_config(
+            let result = tftp::client::run_with_config(
                 action,
                 app_config.as_ref().and_then(|c| c.tftpc.as_ref()),
-            )?;
+            );
+            // Downloads keep acknowledging retransmissions of their last block
+            tftp::core::wait_for_dally();
+            result?;
         }
 
         Commands::Serial {
             uart,
             baud,
+            serve,
             subcommand,
         } => {
             serial::run(
                 subcommand,
                 uart,
                 baud,
+                serve,
                 app_config.as_ref().and_then(|c| c.serial.clone()),
+                app_config.as_ref().and_then(|c| c.tftpd.clone()),
             )?;
         }
 
@@ -155,7 +240,54 @@ fn main() -> Result<()> {
                 std::process::exit(1);
             }
         }
+
+        Commands::Examples { topic } => {
+            examples::run(topic)?;
+        }
+
+        Commands::Doctor => {
+            doctor::run()?;
+        }
+
+        Commands::Version { json } => {
+            version::run(json)?;
+        }
     }
 
     Ok(())
 }
+
+/// Initializes the logger, default info level, displaying file line number
+/// and time, or as JSON objects for log pipelines if `json`.
+fn init_logger(json: bool) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if json {
+        builder.format(|buf, record| {
+            use std::io::Write;
+            let object = serde_json::json!({
+                "time": chrono::Local::now()
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{object}")
+        });
+    } else {
+        builder.format(|buf, record| {
+            use std::io::Write;
+            let level_style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{} {level_style}{}{level_style:#} {}:{}] {level_style}{}{level_style:#}",
+                chrono::Local::now().format("%H:%M:%S"),
+                record.level(),
+                record.target(),
+                record.line().unwrap_or(0),
+                record.args()
+            )
+        });
+    }
+    builder.init();
+}
@@ -0,0 +1,214 @@
+//! Progress of TFTP transfers correlated with the serial console
+//!
+//! When the serial monitor serves files over TFTP, [`ProgressView`] follows
+//! the [`EventBus`](crate::events::EventBus) and interleaves the progress of
+//! the transfers with the milestones matched on the console, so that what
+//! the device printed can be read against how far its download was.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+use crate::events::Event;
+use crate::tftp::server::{Direction, TransferInfo};
+
+/// Transfer followed by the view, with the tenths of its size reported so far
+struct Progress {
+    transfer: TransferInfo,
+    size: Option<u64>,
+    bytes: u64,
+    reported: u64,
+}
+
+impl Progress {
+    fn percent(&self) -> Option<u64> {
+        self.size
+            .map(|size| (self.bytes * 100).checked_div(size).unwrap_or(100).min(100))
+    }
+}
+
+/// ProgressView `struct` turns the events of the bus into the lines of a
+/// correlated log: transfers are reported when they start, at every tenth of
+/// their size and when they end, and each serial milestone with the progress
+/// of the transfers at that moment.
+///
+/// Sizes are those of the files served from `root`, so percentages are only
+/// known for downloads.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::events::Event;
+/// use xtool::progress::ProgressView;
+///
+/// let mut view = ProgressView::new(None);
+/// let event = Event::SerialPatternMatched {
+///     pattern: "Starting kernel".to_string(),
+///     line: "Starting kernel ...".to_string(),
+/// };
+/// assert_eq!(view.on_event(&event).unwrap(), "[serial] Starting kernel ...");
+/// ```
+pub struct ProgressView {
+    root: Option<PathBuf>,
+    active: Vec<Progress>,
+    /// File name of the last transfer completed
+    completed: Option<String>,
+}
+
+impl ProgressView {
+    /// Creates a view sizing the downloads of the files in `root`.
+    pub fn new(root: Option<PathBuf>) -> ProgressView {
+        ProgressView {
+            root,
+            active: Vec::new(),
+            completed: None,
+        }
+    }
+
+    /// Returns the line reporting `event`, if any.
+    pub fn on_event(&mut self, event: &Event) -> Option<String> {
+        match event {
+            Event::TransferStarted(transfer) => {
+                let size = self.size_of(transfer);
+                self.active.push(Progress {
+                    transfer: transfer.clone(),
+                    size,
+                    bytes: 0,
+                    reported: 0,
+                });
+                Some(match size {
+                    Some(size) => format!("[tftp] {} started, {size} bytes", transfer.filename),
+                    None => format!("[tftp] {} started", transfer.filename),
+                })
+            }
+            Event::TransferProgress { transfer, bytes } => {
+                let progress = self
+                    .active
+                    .iter_mut()
+                    .find(|progress| progress.transfer == *transfer)?;
+                progress.bytes = *bytes;
+                let percent = progress.percent()?;
+                if percent / 10 <= progress.reported || percent == 100 {
+                    return None;
+                }
+                progress.reported = percent / 10;
+                Some(format!("[tftp] {} {percent}%", transfer.filename))
+            }
+            Event::TransferCompleted { transfer, bytes } => {
+                self.active
+                    .retain(|progress| progress.transfer != *transfer);
+                self.completed = Some(transfer.filename.clone());
+                Some(format!(
+                    "[tftp] {} completed, {bytes} bytes",
+                    transfer.filename
+                ))
+            }
+            Event::TransferFailed { transfer, error } => {
+                self.active
+                    .retain(|progress| progress.transfer != *transfer);
+                Some(format!("[tftp] {} failed: {error}", transfer.filename))
+            }
+            Event::SerialPatternMatched { line, .. } => {
+                let transfers: Vec<String> = self
+                    .active
+                    .iter()
+                    .map(|progress| match progress.percent() {
+                        Some(percent) => format!("{} at {percent}%", progress.transfer.filename),
+                        None => {
+                            format!("{} at {} bytes", progress.transfer.filename, progress.bytes)
+                        }
+                    })
+                    .collect();
+                Some(match (transfers.is_empty(), &self.completed) {
+                    (false, _) => format!("[serial] {line} ({})", transfers.join(", ")),
+                    (true, Some(filename)) => format!("[serial] {line} (after {filename})"),
+                    (true, None) => format!("[serial] {line}"),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes the lines of the events received to `output`, until the bus is
+    /// dropped. Lines end with `\r\n`, as terminals in raw mode need.
+    pub fn run(mut self, events: Receiver<Event>, mut output: impl Write) {
+        for event in events {
+            if let Some(line) = self.on_event(&event) {
+                let _ = write!(output, "\r\n{line}\r\n");
+                let _ = output.flush();
+            }
+        }
+    }
+
+    fn size_of(&self, transfer: &TransferInfo) -> Option<u64> {
+        if transfer.direction != Direction::Read {
+            return None;
+        }
+        let path = self
+            .root
+            .as_ref()?
+            .join(transfer.filename.trim_start_matches(['/', '\\']));
+        std::fs::metadata(path)
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlates_milestones_with_transfers() {
+        let root = std::env::temp_dir().join(format!("xtool_progress_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("zImage"), vec![0; 1000]).unwrap();
+        let transfer = TransferInfo {
+            peer: "192.168.1.50:1069".parse().unwrap(),
+            filename: "zImage".to_string(),
+            direction: Direction::Read,
+            netascii: false,
+            custom_options: vec![],
+        };
+        let milestone = |line: &str| Event::SerialPatternMatched {
+            pattern: "U-Boot".to_string(),
+            line: line.to_string(),
+        };
+        let progress = |bytes| Event::TransferProgress {
+            transfer: transfer.clone(),
+            bytes,
+        };
+
+        let mut view = ProgressView::new(Some(root.clone()));
+        let lines: Vec<String> = [
+            Event::TransferStarted(transfer.clone()),
+            progress(100),
+            progress(150),
+            milestone("U-Boot received"),
+            progress(350),
+            progress(1000),
+            Event::TransferCompleted {
+                transfer: transfer.clone(),
+                bytes: 1000,
+            },
+            milestone("Starting kernel ..."),
+        ]
+        .iter()
+        .filter_map(|event| view.on_event(event))
+        .collect();
+        assert_eq!(
+            lines,
+            [
+                "[tftp] zImage started, 1000 bytes",
+                "[tftp] zImage 10%",
+                "[serial] U-Boot received (zImage at 15%)",
+                "[tftp] zImage 35%",
+                "[tftp] zImage completed, 1000 bytes",
+                "[serial] Starting kernel ... (after zImage)",
+            ]
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SerialConfig {
@@ -10,4 +11,73 @@ pub struct SerialConfig {
     pub net_port: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub net_bind: Option<String>,
+    /// TCP tuning overrides for the network server (netd)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netd: Option<NetTuning>,
+    /// TCP tuning overrides for the network client (netc)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netc: Option<NetTuning>,
+    /// Console lines reported along with the transfers when the monitor
+    /// serves files, those of U-Boot loading an image by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub milestones: Option<Vec<String>>,
+}
+
+/// TCP tuning of the serial network bridge.
+///
+/// Interactive consoles want every keystroke and echo sent immediately
+/// (TCP_NODELAY, no coalescing), while log streaming is cheaper with Nagle
+/// enabled and writes batched over a short interval.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct NetTuning {
+    /// Disable Nagle's algorithm (TCP_NODELAY)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodelay: Option<bool>,
+    /// Time to gather data before writing it to the socket, 0 writes immediately
+    #[serde(skip_serializing_if = "Option::is_none", with = "humantime_serde")]
+    pub coalesce: Option<Duration>,
+    /// Size of the buffers used for reads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_buffer: Option<usize>,
+}
+
+impl NetTuning {
+    /// Defaults for the network server, forwarding serial output in small reads
+    pub fn netd() -> Self {
+        Self {
+            nodelay: Some(true),
+            coalesce: Some(Duration::ZERO),
+            read_buffer: Some(1024),
+        }
+    }
+
+    /// Defaults for the interactive network client
+    pub fn netc() -> Self {
+        Self {
+            nodelay: Some(true),
+            coalesce: Some(Duration::ZERO),
+            read_buffer: Some(2048),
+        }
+    }
+
+    /// Fills the fields not set in `self` from `other`
+    pub fn or(self, other: NetTuning) -> Self {
+        Self {
+            nodelay: self.nodelay.or(other.nodelay),
+            coalesce: self.coalesce.or(other.coalesce),
+            read_buffer: self.read_buffer.or(other.read_buffer),
+        }
+    }
+
+    pub fn nodelay(&self) -> bool {
+        self.nodelay.unwrap_or(true)
+    }
+
+    pub fn coalesce(&self) -> Duration {
+        self.coalesce.unwrap_or(Duration::ZERO)
+    }
+
+    pub fn read_buffer(&self) -> usize {
+        self.read_buffer.unwrap_or(1024).max(1)
+    }
 }
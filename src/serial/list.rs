@@ -1,33 +1,35 @@
 use anyhow::Result;
 use serialport::SerialPortType;
 
+use crate::i18n::{tr, tr_args};
+
 pub fn run() -> Result<()> {
     let ports = serialport::available_ports()?;
     if ports.is_empty() {
-        println!("No serial ports found.");
+        println!("{}", tr("serial.no_ports"));
         return Ok(());
     }
 
-    println!("Available serial ports:");
+    println!("{}", tr("serial.available"));
     for p in ports {
         println!("  {}", p.port_name);
         match p.port_type {
             SerialPortType::UsbPort(info) => {
                 if let Some(product) = info.product {
-                    println!("    Product: {}", product);
+                    println!("    {}", tr_args("serial.product", &[&product]));
                 }
                 if let Some(manufacturer) = info.manufacturer {
-                    println!("    Manufacturer: {}", manufacturer);
+                    println!("    {}", tr_args("serial.manufacturer", &[&manufacturer]));
                 }
             }
             SerialPortType::PciPort => {
-                println!("    Type: PCI");
+                println!("    {}", tr_args("serial.type", &[&"PCI"]));
             }
             SerialPortType::BluetoothPort => {
-                println!("    Type: Bluetooth");
+                println!("    {}", tr_args("serial.type", &[&tr("serial.bluetooth")]));
             }
             SerialPortType::Unknown => {
-                println!("    Type: Unknown");
+                println!("    {}", tr_args("serial.type", &[&tr("serial.unknown")]));
             }
         }
     }
@@ -0,0 +1,114 @@
+use crate::events::{Event, EventBus};
+
+/// Lines printed by U-Boot while it loads an image over TFTP and boots it
+pub const UBOOT_MILESTONES: &[&str] = &[
+    "TFTP from server",
+    "Filename '",
+    "Bytes transferred",
+    "Retry count exceeded",
+    "## Booting",
+    "Starting kernel",
+];
+
+/// Longest line kept, the rest of longer lines is not matched
+const MAX_LINE: usize = 4096;
+
+/// Milestones `struct` splits the output of the serial console into lines
+/// and publishes an [`Event::SerialPatternMatched`] for each line containing
+/// one of its patterns, so that progress views correlate them with the
+/// transfers of the TFTP server.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::events::{Event, EventBus};
+/// use xtool::serial::milestones::Milestones;
+///
+/// let bus = EventBus::new();
+/// let events = bus.subscribe();
+/// let mut milestones = Milestones::new(vec!["Starting kernel".to_string()], bus);
+/// milestones.feed(b"\r\nStarting ker");
+/// milestones.feed(b"nel ...\r\n");
+/// assert_eq!(
+///     events.try_recv().unwrap(),
+///     Event::SerialPatternMatched {
+///         pattern: "Starting kernel".to_string(),
+///         line: "Starting kernel ...".to_string(),
+///     }
+/// );
+/// ```
+pub struct Milestones {
+    patterns: Vec<String>,
+    line: Vec<u8>,
+    bus: EventBus,
+}
+
+impl Milestones {
+    pub fn new(patterns: Vec<String>, bus: EventBus) -> Milestones {
+        Milestones {
+            patterns,
+            line: Vec::new(),
+            bus,
+        }
+    }
+
+    /// Matches the lines completed by `bytes`, read from the console.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                b'\r' | b'\n' => {
+                    self.matches();
+                    self.line.clear();
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => (),
+            }
+        }
+    }
+
+    fn matches(&self) {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim();
+        if let Some(pattern) = self
+            .patterns
+            .iter()
+            .find(|pattern| line.contains(pattern.as_str()))
+        {
+            self.bus.publish(Event::SerialPatternMatched {
+                pattern: pattern.clone(),
+                line: line.to_string(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_uboot_output() {
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        let patterns = UBOOT_MILESTONES.iter().map(|p| p.to_string()).collect();
+        let mut milestones = Milestones::new(patterns, bus);
+        milestones.feed(b"Using ethernet@ device\r\nTFTP from server 192.168.1.1; ");
+        milestones.feed(b"our IP address is 192.168.1.50\r\nLoading: #################\r\n");
+        milestones.feed(b"Bytes transferred = 4194304 (400000 hex)\r\n=> ");
+
+        let lines: Vec<String> = events
+            .try_iter()
+            .map(|event| match event {
+                Event::SerialPatternMatched { line, .. } => line,
+                event => panic!("Unexpected event {event:?}"),
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "TFTP from server 192.168.1.1; our IP address is 192.168.1.50",
+                "Bytes transferred = 4194304 (400000 hex)",
+            ]
+        );
+    }
+}
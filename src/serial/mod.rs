@@ -1,14 +1,15 @@
 use anyhow::Result;
-use clap::Subcommand;
+use clap::{Args, Subcommand};
 use dialoguer::{theme::ColorfulTheme, Select};
 use serialport::SerialPortType;
 
 pub mod config;
 pub mod list;
+pub mod milestones;
 pub mod monitor;
 pub mod net;
 
-use config::SerialConfig;
+use config::{NetTuning, SerialConfig};
 
 #[derive(Subcommand)]
 pub enum SerialSubcommand {
@@ -28,6 +29,8 @@ pub enum SerialSubcommand {
         /// Listen IP
         #[arg(short = 's', long)]
         bind: Option<String>,
+        #[command(flatten)]
+        tuning: NetTuningArgs,
     },
     /// Network connect client (Connect to serial server)
     Netc {
@@ -37,24 +40,58 @@ pub enum SerialSubcommand {
         /// Server Port
         #[arg(short, long, default_value = "5432")]
         port: u16,
+        #[command(flatten)]
+        tuning: NetTuningArgs,
     }
 }
 
+/// TCP tuning options shared by netd and netc
+#[derive(Args)]
+pub struct NetTuningArgs {
+    /// Enable Nagle's algorithm (disable TCP_NODELAY), better for log streaming
+    #[arg(long)]
+    nagle: bool,
+    /// Gather data for this many milliseconds before each write
+    #[arg(long, value_name = "MS")]
+    coalesce: Option<u64>,
+    /// Read buffer size in bytes
+    #[arg(long, value_name = "BYTES")]
+    read_buffer: Option<usize>,
+}
+
+impl NetTuningArgs {
+    /// Applies the command line over the config file and frontend defaults
+    fn resolve(self, config: Option<NetTuning>, defaults: NetTuning) -> NetTuning {
+        NetTuning {
+            nodelay: self.nagle.then_some(false),
+            coalesce: self.coalesce.map(std::time::Duration::from_millis),
+            read_buffer: self.read_buffer,
+        }
+        .or(config.unwrap_or_default())
+        .or(defaults)
+    }
+}
+
+#[allow(clippy::collapsible_if)]
 pub fn run(
     subcommand: Option<SerialSubcommand>,
     uart: Option<String>,
     baud: Option<u32>,
+    serve: Option<std::path::PathBuf>,
     config: Option<SerialConfig>,
+    tftpd: Option<crate::tftp::server::Config>,
 ) -> Result<()> {
     match subcommand {
         Some(SerialSubcommand::List) => return list::run(),
-        Some(SerialSubcommand::Netd { uart, baud, port, bind }) => {
+        Some(SerialSubcommand::Netd { uart, baud, port, bind, tuning }) => {
+            let tuning = tuning.resolve(config.as_ref().and_then(|c| c.netd.clone()), NetTuning::netd());
             let rt = tokio::runtime::Runtime::new()?;
-            return rt.block_on(net::server::run(uart, baud, port, bind, config));
+            return rt.block_on(net::server::run(uart, baud, port, bind, config, tuning));
         },
-        Some(SerialSubcommand::Netc { server, port }) => {
+        Some(SerialSubcommand::Netc { server, port, tuning }) => {
+            let tuning = tuning.resolve(config.as_ref().and_then(|c| c.netc.clone()), NetTuning::netc());
             let rt = tokio::runtime::Runtime::new()?;
-            return rt.block_on(net::client::run(server, port));
+            return rt.block_on(net::client::run(server, port, tuning));
         },
         _ => {}
     }
@@ -96,5 +133,12 @@ pub fn run(
         }
     };
 
-    monitor::run(&uart_name, final_baud)
+    let milestones = config
+        .as_ref()
+        .and_then(|c| c.milestones.clone())
+        .unwrap_or_else(|| {
+            milestones::UBOOT_MILESTONES.iter().map(|p| p.to_string()).collect()
+        });
+
+    monitor::run(&uart_name, final_baud, serve.as_deref(), tftpd, milestones)
 }
@@ -1,4 +1,5 @@
 use std::io::{self, Write};
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
@@ -9,11 +10,49 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode},
 };
 
-pub fn run(port_name: &str, baud_rate: u32) -> anyhow::Result<()> {
+use crate::events::EventBus;
+use crate::i18n::{tr, tr_args};
+use crate::progress::ProgressView;
+use crate::tftp::server::{Config, Server};
+
+use super::milestones::Milestones;
+
+#[allow(clippy::collapsible_if, clippy::manual_range_contains)]
+pub fn run(
+    port_name: &str,
+    baud_rate: u32,
+    serve: Option<&Path>,
+    tftpd: Option<Config>,
+    milestones: Vec<String>,
+) -> anyhow::Result<()> {
     println!(
-        "Connected to {} at {} baud. Press 'Ctrl + ]' to exit.",
-        port_name, baud_rate
+        "{}",
+        tr_args("monitor.connected", &[&port_name, &baud_rate])
     );
+
+    // Files served over TFTP, their progress interleaved with the milestones of the console
+    let mut milestones = match serve {
+        Some(dir) => {
+            let bus = EventBus::global().clone();
+            // Listens as set under [tftpd], only serving DIR read-only
+            let config = Config {
+                directory: Some(dir.to_path_buf()),
+                send_directory: None,
+                receive_directory: None,
+                read_only: Some(true),
+                ..tftpd.unwrap_or_default()
+            }
+            .merge_cli("0.0.0.0".to_string(), 69, dir.to_path_buf(), true, false);
+            let mut server = Server::new(&config)?.with_handler(Arc::new(bus.clone()));
+            println!("{}", tr_args("monitor.serving", &[&dir.display()]));
+            let view = ProgressView::new(Some(dir.to_path_buf()));
+            let events = bus.subscribe();
+            thread::spawn(move || server.listen());
+            thread::spawn(move || view.run(events, io::stdout()));
+            Some(Milestones::new(milestones, bus))
+        }
+        None => None,
+    };
     println!("---------------------------------------------------------------");
 
     // 1. Open Serial Port
@@ -47,6 +86,9 @@ pub fn run(port_name: &str, baud_rate: u32) -> anyhow::Result<()> {
                     // For a robust monitor, we often just write raw bytes.
                     let _ = stdout.write_all(&buffer[..n]);
                     let _ = stdout.flush();
+                    if let Some(milestones) = milestones.as_mut() {
+                        milestones.feed(&buffer[..n]);
+                    }
                 }
                 Ok(_) => {} // Zero bytes read
                 Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
@@ -136,7 +178,7 @@ pub fn run(port_name: &str, baud_rate: u32) -> anyhow::Result<()> {
 
     // 5. Cleanup
     disable_raw_mode()?;
-    println!("\nDisconnected.");
+    println!("\n{}", tr("monitor.disconnected"));
 
     // Wait for RX thread to finish (optional, or just let it die with the process)
     // We set running to false, so it should exit on next timeout or read.
@@ -1,4 +1,5 @@
 use anyhow::{Result, Context};
+use crate::serial::config::NetTuning;
 // use log::info;
 use tokio::net::TcpStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -14,11 +15,14 @@ impl Drop for RawModeGuard {
     }
 }
 
-pub async fn run(server: String, port: u16) -> Result<()> {
+#[allow(clippy::manual_range_contains)]
+pub async fn run(server: String, port: u16, tuning: NetTuning) -> Result<()> {
     let addr = format!("{}:{}", server, port);
     info!("Connecting to {}...", addr);
     
     let mut stream = TcpStream::connect(&addr).await.with_context(|| format!("Failed to connect to {}", addr))?;
+    stream.set_nodelay(tuning.nodelay())?;
+    let coalesce = tuning.coalesce();
     let (mut ri, mut wi) = stream.split();
     
     info!("Connected. Press 'Ctrl + ]' to exit.");
@@ -76,7 +80,7 @@ pub async fn run(server: String, port: u16) -> Result<()> {
         }
     });
 
-    let mut buf = [0u8; 2048];
+    let mut buf = vec![0u8; tuning.read_buffer()];
     let mut stdout = tokio::io::stdout();
 
     loop {
@@ -101,7 +105,13 @@ pub async fn run(server: String, port: u16) -> Result<()> {
             // Read from Input Channel and write to TCP
             msg = rx.recv() => {
                 match msg {
-                    Some(data) => {
+                    Some(mut data) => {
+                        if !coalesce.is_zero() {
+                            let deadline = tokio::time::Instant::now() + coalesce;
+                            while let Ok(Some(more)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+                                data.extend_from_slice(&more);
+                            }
+                        }
                         if wi.write_all(&data).await.is_err() {
                             break;
                         }
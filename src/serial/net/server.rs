@@ -1,12 +1,13 @@
 use anyhow::{Result, Context};
-use crate::serial::config::SerialConfig;
+use crate::events::{Event, EventBus};
+use crate::serial::config::{NetTuning, SerialConfig};
 use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_serial::SerialPortBuilderExt;
 // Removed std::sync::Arc
 
-pub async fn run(uart: Option<String>, baud: Option<u32>, port: Option<u16>, bind: Option<String>, config: Option<SerialConfig>) -> Result<()> {
+pub async fn run(uart: Option<String>, baud: Option<u32>, port: Option<u16>, bind: Option<String>, config: Option<SerialConfig>, tuning: NetTuning) -> Result<()> {
     // Resolve UART and Baud
     let final_uart = uart.or(config.as_ref().and_then(|c| c.uart.clone()));
     let final_baud = baud.or(config.as_ref().and_then(|c| c.baud)).unwrap_or(115200);
@@ -19,6 +20,7 @@ pub async fn run(uart: Option<String>, baud: Option<u32>, port: Option<u16>, bin
 
     info!("Starting Netd: Serial <-> TCP Server (Multi-client broadcast)");
     info!("Serial Port: {}, Baud: {}", uart_name, final_baud);
+    info!("TCP_NODELAY: {}, Coalesce: {:?}, Read buffer: {} bytes", tuning.nodelay(), tuning.coalesce(), tuning.read_buffer());
 
     // Open Serial Port
     let mut serial_stream = tokio_serial::new(&uart_name, final_baud)
@@ -49,8 +51,9 @@ pub async fn run(uart: Option<String>, baud: Option<u32>, port: Option<u16>, bin
 
     // Task 1: Serial Reader -> Broadcast
     let b_tx = broadcast_tx.clone();
+    let read_buffer = tuning.read_buffer();
     tokio::spawn(async move {
-        let mut buf = [0u8; 1024];
+        let mut buf = vec![0u8; read_buffer];
         loop {
             match serial_reader.read(&mut buf).await {
                 Ok(n) if n > 0 => {
@@ -92,12 +95,18 @@ pub async fn run(uart: Option<String>, baud: Option<u32>, port: Option<u16>, bin
         match listener.accept().await {
             Ok((socket, peer_addr)) => {
                 info!("Client connected from {}", peer_addr);
+                EventBus::global().publish(Event::ClientConnected(peer_addr));
+
+                if let Err(e) = socket.set_nodelay(tuning.nodelay()) {
+                    warn!("Failed to set TCP_NODELAY for {}: {}", peer_addr, e);
+                }
                 
                 let client_b_rx = broadcast_tx.subscribe();
                 let client_m_tx = mpsc_tx.clone();
+                let client_tuning = tuning.clone();
                 
                 tokio::spawn(async move {
-                    handle_client(socket, client_b_rx, client_m_tx, peer_addr).await;
+                    handle_client(socket, client_b_rx, client_m_tx, peer_addr, client_tuning).await;
                 });
             }
             Err(e) => {
@@ -111,13 +120,16 @@ async fn handle_client(
     socket: tokio::net::TcpStream, 
     mut broadcast_rx: broadcast::Receiver<Vec<u8>>, 
     mpsc_tx: mpsc::Sender<Vec<u8>>,
-    peer_addr: std::net::SocketAddr
+    peer_addr: std::net::SocketAddr,
+    tuning: NetTuning
 ) {
     let (mut socket_read, mut socket_write) = socket.into_split();
+    let read_buffer = tuning.read_buffer();
+    let coalesce = tuning.coalesce();
     
     // Client specific tasks container
     let mut handle_read = tokio::task::spawn(async move {
-        let mut buf = [0u8; 1024];
+        let mut buf = vec![0u8; read_buffer];
         loop {
             match socket_read.read(&mut buf).await {
                 Ok(n) if n > 0 => {
@@ -133,7 +145,14 @@ async fn handle_client(
     });
 
     let mut handle_write = tokio::task::spawn(async move {
-        while let Ok(data) = broadcast_rx.recv().await {
+        while let Ok(mut data) = broadcast_rx.recv().await {
+            if !coalesce.is_zero() {
+                // Batch everything the serial port produces within the interval
+                let deadline = tokio::time::Instant::now() + coalesce;
+                while let Ok(Ok(more)) = tokio::time::timeout_at(deadline, broadcast_rx.recv()).await {
+                    data.extend_from_slice(&more);
+                }
+            }
             if socket_write.write_all(&data).await.is_err() {
                 break;
             }
@@ -154,4 +173,5 @@ async fn handle_client(
     handle_read.abort();
     handle_write.abort();
     info!("Client disconnected: {}", peer_addr);
+    EventBus::global().publish(Event::ClientDisconnected(peer_addr));
 }
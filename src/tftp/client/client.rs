@@ -1,11 +1,51 @@
-use std::fs::File;
-use std::io::{Read, Seek, Write};
-use std::net::{IpAddr, SocketAddr, UdpSocket};
-use std::path::Path;
-use std::time::Duration;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use super::config::ClientConfig;
-use crate::tftp::core::{OptionType, Packet, TransferOption};
+use super::discover;
+use super::error::ClientError;
+use super::manifest::{ManifestEntry, ManifestSummary};
+use super::resume::{RESUME_SAVE_INTERVAL, ResumeState};
+use crate::tftp::core::{
+    Convert, CustomOption, ErrorCode, Flow, MAX_DTLS_BLOCK_SIZE, OptionType, Packet, PcapWriter,
+    SessionRecorder, Socket, TftpTransport, TransferOption, dally, dtls_transport,
+    is_message_too_large, max_block_size, preallocate,
+};
+use crate::tftp::server::LISTING_FILENAME;
+
+const MAX_PING_PACKET_SIZE: usize = 1024;
+
+/// Progress callback invoked with the number of bytes transferred so far and
+/// the total size of the transfer, if known from the negotiated `tsize`.
+pub type ProgressFn = dyn Fn(u64, Option<u64>) + Send + Sync;
+
+/// Transport factory invoked with the address the socket of a transfer binds
+/// to, see [`Client::with_transport()`].
+pub type TransportFn = dyn Fn(SocketAddr) -> std::io::Result<Box<dyn TftpTransport>> + Send + Sync;
+
+/// File name requested by [`Client::ping()`], not expected to exist on servers
+pub const PING_FILENAME: &str = ".xtool-ping";
+
+/// Outcome of a [`Client::ping()`] probe
+#[derive(Debug, Clone, PartialEq)]
+pub enum PingStatus {
+    /// The server answered the probe after `rtt`
+    Alive {
+        /// Round trip time of the probe
+        rtt: Duration,
+        /// Error code of the answer, `None` if the server started a transfer
+        code: Option<ErrorCode>,
+    },
+    /// The host reported that nothing listens on the port (where the platform reports it)
+    Refused,
+    /// No answer before the timeout
+    NoResponse,
+}
 
 /// TFTP client
 ///
@@ -15,36 +55,268 @@ pub struct Client {
     server_port: u16,
     block_size: u16,
     timeout: Duration,
+    retries: u8,
+    request_retries: u8,
     window_size: u16,
     mode: String,
+    custom_options: Vec<CustomOption>,
+    acknowledged: Mutex<Vec<CustomOption>>,
+    progress: Option<Box<ProgressFn>>,
+    record: Option<PathBuf>,
+    pcap: Option<Arc<PcapWriter>>,
+    transport: Option<Box<TransportFn>>,
 }
 
 impl Client {
     /// Create a new TFTP client
-    pub fn new(config: ClientConfig) -> anyhow::Result<Self> {
+    pub fn new(config: ClientConfig) -> Result<Self, ClientError> {
         let server_str = config
             .server
-            .ok_or_else(|| anyhow::anyhow!("Server address not specified"))?;
-        let server_ip: IpAddr = server_str
-            .parse()
-            .map_err(|e| anyhow::anyhow!("Invalid server address '{}': {}", server_str, e))?;
+            .ok_or_else(|| ClientError::InvalidAddress("not specified".to_string()))?;
+        let timeout = config.timeout.unwrap_or(Duration::from_secs(5));
+        let transport = match &config.dtls {
+            Some(dtls) => Some(dtls_transport(dtls, &server_str)?),
+            None => None,
+        };
+        let mut block_size = config.block_size.unwrap_or(512);
+        if transport.is_some() && block_size > MAX_DTLS_BLOCK_SIZE {
+            log::warn!(
+                "Block size {block_size} exceeds the largest DTLS record, using {MAX_DTLS_BLOCK_SIZE}"
+            );
+            block_size = MAX_DTLS_BLOCK_SIZE;
+        }
+        let server_ip = config
+            .resolver
+            .unwrap_or_default()
+            .resolve(&server_str, timeout)?;
+
+        let pcap = match &config.pcap {
+            Some(path) => {
+                log::info!("Capturing packets to {}", path.display());
+                Some(Arc::new(PcapWriter::create(path)?))
+            }
+            None => None,
+        };
+
+        let custom_options = config
+            .custom_options
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| {
+                if OptionType::from_str(name.to_lowercase().as_str()).is_ok() {
+                    return Err(ClientError::OptionNegotiation(format!(
+                        "custom option '{}' conflicts with a built-in option",
+                        name
+                    )));
+                }
+                Ok(CustomOption { name, value })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Self {
             server_ip,
             server_port: config.port.unwrap_or(69),
-            block_size: config.block_size.unwrap_or(512),
-            timeout: config.timeout.unwrap_or(Duration::from_secs(5)),
+            block_size,
+            timeout,
+            retries: config.retries.unwrap_or(5),
+            request_retries: config.request_retries.or(config.retries).unwrap_or(5),
             window_size: config.window_size.unwrap_or(1),
             mode: config.mode.unwrap_or_else(|| "octet".to_string()),
+            custom_options,
+            acknowledged: Mutex::new(Vec::new()),
+            progress: None,
+            record: config.record,
+            pcap,
+            transport,
+        })
+    }
+
+    /// Register a callback to be notified of transfer progress
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(u64, Option<u64>) + Send + Sync + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Binds the sockets of transfers and probes with `bind` instead of UDP
+    /// sockets, e.g. to a [`MemoryNetwork`](crate::tftp::core::MemoryNetwork)
+    /// in tests.
+    #[allow(dead_code)]
+    pub fn with_transport<F>(mut self, bind: F) -> Self
+    where
+        F: Fn(SocketAddr) -> std::io::Result<Box<dyn TftpTransport>> + Send + Sync + 'static,
+    {
+        self.transport = Some(Box::new(bind));
+        self
+    }
+
+    /// Returns whether transfers are in netascii mode, their line endings
+    /// converted.
+    fn netascii(&self) -> bool {
+        self.mode.eq_ignore_ascii_case("netascii")
+    }
+
+    fn report_progress(&self, transferred: u64, total: Option<u64>) {
+        if let Some(progress) = &self.progress {
+            progress(transferred, total);
+        }
+    }
+
+    /// Custom options acknowledged by the server during the last transfer
+    pub fn acknowledged_options(&self) -> Vec<CustomOption> {
+        self.acknowledged
+            .lock()
+            .map(|options| options.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_acknowledged(&self, options: Vec<CustomOption>) {
+        for option in &options {
+            log::debug!("  Server acknowledged {}={}", option.name, option.value);
+        }
+        if let Ok(mut acknowledged) = self.acknowledged.lock() {
+            *acknowledged = options;
+        }
+    }
+
+    fn send_request(
+        &self,
+        socket: &TransferSocket,
+        packet: &Packet,
+        to: SocketAddr,
+    ) -> Result<(), ClientError> {
+        log::trace!("  Sending {packet}");
+        let bytes = packet
+            .serialize()
+            .map_err(|e| ClientError::Protocol(e.to_string()))?;
+        socket.send_to(&bytes, to)?;
+        Ok(())
+    }
+
+    /// Returns the address sockets bind to, of the family of the server.
+    fn local_addr(&self) -> SocketAddr {
+        let ip: IpAddr = match self.server_ip {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        SocketAddr::new(ip, 0)
+    }
+
+    /// Binds a socket to the address of the family of the server.
+    fn bind(&self) -> std::io::Result<Box<dyn TftpTransport>> {
+        match &self.transport {
+            Some(bind) => bind(self.local_addr()),
+            None => Ok(Box::new(UdpSocket::bind(self.local_addr())?)),
+        }
+    }
+
+    /// Binds the socket of a transfer, recording its packets if asked to.
+    fn bind_transfer_socket(&self) -> Result<TransferSocket, ClientError> {
+        let socket = self.bind()?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.set_write_timeout(Some(self.timeout))?;
+        let recorder = match &self.record {
+            Some(path) => {
+                log::info!("Recording session to {}", path.display());
+                Some(SessionRecorder::create(path)?)
+            }
+            None => None,
+        };
+        Ok(TransferSocket {
+            socket,
+            recorder,
+            pcap: self.pcap.clone(),
         })
     }
 
-    fn build_options(&self, transfer_size: u64) -> Vec<TransferOption> {
+    /// Probe whether the TFTP service answers, without transferring a file
+    ///
+    /// Sends a read request for [`PING_FILENAME`]; any answer, usually a
+    /// "file not found" error, means the service is up.
+    pub fn ping(&self) -> Result<PingStatus, ClientError> {
+        // Probes are not transfers, they are never recorded but captured
+        let socket = TransferSocket {
+            socket: self.bind()?,
+            recorder: None,
+            pcap: self.pcap.clone(),
+        };
+        let server_addr = SocketAddr::new(self.server_ip, self.server_port);
+        socket.socket.set_read_timeout(Some(self.timeout))?;
+
+        let rrq = Packet::Rrq {
+            filename: PING_FILENAME.to_string(),
+            mode: self.mode.clone(),
+            options: vec![],
+            custom: vec![],
+        };
+        let start = Instant::now();
+        send_packet(&socket, &rrq, server_addr)?;
+
+        let mut buf = vec![0; MAX_PING_PACKET_SIZE];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((amt, src)) => {
+                    if src.ip() != self.server_ip {
+                        continue;
+                    }
+                    let rtt = start.elapsed();
+
+                    let packet = Packet::deserialize(&buf[..amt]);
+                    if let Ok(packet) = &packet {
+                        log::trace!("  Received {packet}");
+                    }
+                    let code = match packet {
+                        Ok(Packet::Error { code, .. }) => Some(code),
+                        _ => {
+                            // The sentinel file exists, abort the transfer that just started
+                            let error = Packet::Error {
+                                code: ErrorCode::NotDefined,
+                                msg: "ping".to_string(),
+                            };
+                            send_packet(&socket, &error, src)?;
+                            None
+                        }
+                    };
+
+                    return Ok(PingStatus::Alive { rtt, code });
+                }
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    return Ok(PingStatus::NoResponse);
+                }
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::ConnectionRefused
+                        || e.kind() == std::io::ErrorKind::ConnectionReset =>
+                {
+                    return Ok(PingStatus::Refused);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Discover TFTP servers advertising `_tftp._udp.local` over mDNS
+    ///
+    /// Waits for `timeout` to collect answers and returns every advertised address.
+    pub fn discover(timeout: Duration) -> Result<Vec<SocketAddr>, ClientError> {
+        Ok(discover::browse(discover::TFTP_SERVICE, timeout)?)
+    }
+
+    fn build_options(
+        &self,
+        block_size: u16,
+        window_size: u16,
+        transfer_size: u64,
+    ) -> Vec<TransferOption> {
         let mut options = Vec::new();
 
         options.push(TransferOption {
             option: OptionType::BlockSize,
-            value: self.block_size as u64,
+            value: block_size as u64,
         });
 
         options.push(TransferOption {
@@ -54,7 +326,7 @@ impl Client {
 
         options.push(TransferOption {
             option: OptionType::WindowSize,
-            value: self.window_size as u64,
+            value: window_size as u64,
         });
 
         if transfer_size > 0 {
@@ -68,34 +340,225 @@ impl Client {
     }
 
     /// Download a file from the server (RRQ - Read Request)
-    pub fn get(&self, remote_file: &str, local_file: &Path) -> anyhow::Result<()> {
+    pub fn get(&self, remote_file: &str, local_file: &Path) -> Result<(), ClientError> {
+        self.download(remote_file, local_file, None)
+    }
+
+    /// Resume an interrupted download using the state saved next to `local_file`
+    ///
+    /// Without saved state, an existing `local_file` is taken as the start of
+    /// the remote file and continued from its current length. Falls back to a
+    /// full download if the state does not match or the server does not
+    /// support the `offset` extension.
+    pub fn resume(&self, remote_file: &str, local_file: &Path) -> Result<(), ClientError> {
+        let server = SocketAddr::new(self.server_ip, self.server_port).to_string();
+        let state = match ResumeState::load(local_file)? {
+            Some(state) if state.server == server && state.remote_file == remote_file => {
+                Some(state)
+            }
+            Some(_) => {
+                log::warn!("Resume state does not match this download, starting over");
+                None
+            }
+            None => match std::fs::metadata(local_file) {
+                Ok(metadata) if metadata.is_file() && metadata.len() > 0 => {
+                    log::info!(
+                        "No resume state for {}, continuing from its length",
+                        local_file.display()
+                    );
+                    let mut state = ResumeState::new(&server, remote_file, local_file);
+                    state.received = metadata.len();
+                    Some(state)
+                }
+                _ => {
+                    log::warn!(
+                        "No resume state for {}, starting over",
+                        local_file.display()
+                    );
+                    None
+                }
+            },
+        };
+
+        self.download(remote_file, local_file, state)
+    }
+
+    /// Download every file listed in `manifest` into `dest_dir`
+    ///
+    /// Files already present with the expected size and SHA-256 are skipped.
+    /// A failed entry does not stop the others, the error lists all of them.
+    pub fn get_manifest(
+        &self,
+        manifest: &Path,
+        dest_dir: &Path,
+    ) -> Result<ManifestSummary, ClientError> {
+        self.get_entries(ManifestEntry::load(manifest)?, dest_dir, 1)
+    }
+
+    /// Mirror the files of an xtool server into `dest_dir`
+    ///
+    /// Fetches the listing of the server root ([`LISTING_FILENAME`]) and
+    /// downloads the files missing locally or whose size or SHA-256 differ,
+    /// up to `jobs` at a time. Local files absent from the server are kept.
+    pub fn mirror(&self, dest_dir: &Path, jobs: usize) -> Result<ManifestSummary, ClientError> {
+        std::fs::create_dir_all(dest_dir)?;
+        let listing_file = dest_dir.join(LISTING_FILENAME);
+        let entries = self
+            .get(LISTING_FILENAME, &listing_file)
+            .and_then(|_| ManifestEntry::load(&listing_file));
+        let _ = std::fs::remove_file(&listing_file);
+
+        self.get_entries(entries?, dest_dir, jobs)
+    }
+
+    /// Download the manifest `entries` that are not up to date in
+    /// `dest_dir`, with `jobs` downloads in parallel
+    fn get_entries(
+        &self,
+        entries: Vec<ManifestEntry>,
+        dest_dir: &Path,
+        jobs: usize,
+    ) -> Result<ManifestSummary, ClientError> {
+        let entries = Mutex::new(entries.into_iter());
+        let summary = Mutex::new(ManifestSummary::default());
+        let failed = Mutex::new(Vec::new());
+
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.max(1) {
+                scope.spawn(|| {
+                    loop {
+                        let next = entries.lock().unwrap().next();
+                        let Some(entry) = next else {
+                            break;
+                        };
+
+                        let local_file = entry.local_path(dest_dir);
+                        if local_file.exists() && entry.verify(&local_file).is_ok() {
+                            log::info!("{} is up to date, skipping", entry.name);
+                            summary.lock().unwrap().skipped.push(entry.name);
+                            continue;
+                        }
+
+                        let result = local_file
+                            .parent()
+                            .map_or(Ok(()), std::fs::create_dir_all)
+                            .map_err(ClientError::from)
+                            .and_then(|_| self.get(&entry.name, &local_file))
+                            .and_then(|_| entry.verify(&local_file));
+                        match result {
+                            Ok(()) => summary.lock().unwrap().downloaded.push(entry.name),
+                            Err(e) => {
+                                log::error!("{}: {}", entry.name, e);
+                                failed.lock().unwrap().push(entry.name);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let failed = failed.into_inner().unwrap();
+        if !failed.is_empty() {
+            return Err(ClientError::Manifest(format!(
+                "{} file(s) failed: {}",
+                failed.len(),
+                failed.join(", ")
+            )));
+        }
+
+        Ok(summary.into_inner().unwrap())
+    }
+
+    fn download(
+        &self,
+        remote_file: &str,
+        local_file: &Path,
+        previous: Option<ResumeState>,
+    ) -> Result<(), ClientError> {
         log::info!("Downloading {} to {}", remote_file, local_file.display());
 
+        let server_addr = SocketAddr::new(self.server_ip, self.server_port);
+        let (mut file, mut state) = match previous {
+            Some(mut state) => {
+                let mut file = OpenOptions::new().write(true).open(local_file)?;
+                // Data past the last saved checkpoint may be incomplete
+                state.received = state.received.min(file.metadata()?.len());
+                rewind(&mut file, state.received)?;
+                log::info!("Resuming {} at offset {}", remote_file, state.received);
+                (file, state)
+            }
+            None => (
+                File::create(local_file)?,
+                ResumeState::new(&server_addr.to_string(), remote_file, local_file),
+            ),
+        };
+
+        let result = self.receive(remote_file, local_file, &mut file, &mut state);
+        match result {
+            Ok(()) => {
+                // Drop any preallocated space the server did not end up sending
+                file.set_len(state.received)?;
+                state.remove();
+                if self.netascii() {
+                    drop(file);
+                    from_netascii_in_place(local_file)?;
+                }
+            }
+            Err(_) => {
+                if let Err(err) = state.save() {
+                    log::warn!("Could not save resume state: {}", err);
+                }
+            }
+        }
+
+        result
+    }
+
+    #[allow(clippy::collapsible_match)]
+    fn receive(
+        &self,
+        remote_file: &str,
+        local_file: &Path,
+        file: &mut File,
+        state: &mut ResumeState,
+    ) -> Result<(), ClientError> {
         // Create local socket
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let socket = self.bind_transfer_socket()?;
         let mut server_addr = SocketAddr::new(self.server_ip, self.server_port);
         let mut tid_set = false;
 
-        socket.set_read_timeout(Some(self.timeout))?;
-        socket.set_write_timeout(Some(self.timeout))?;
-
         // Build options
-        let options = self.build_options(0);
+        let offset = state.received;
+        let mut requested = self.build_options(self.block_size, self.window_size, 0);
+        // A zero tsize asks the server for the size of the file, to preallocate it
+        requested.push(TransferOption {
+            option: OptionType::TransferSize,
+            value: 0,
+        });
+        if offset > 0 {
+            requested.push(TransferOption {
+                option: OptionType::Offset,
+                value: offset,
+            });
+        }
 
         // Send RRQ
         let rrq = Packet::Rrq {
             filename: remote_file.to_string(),
             mode: self.mode.clone(),
-            options,
+            options: requested.clone(),
+            custom: self.custom_options.clone(),
         };
-        let bytes = rrq.serialize()?;
-        socket.send_to(&bytes, server_addr)?;
+        self.set_acknowledged(Vec::new());
+        self.send_request(&socket, &rrq, server_addr)?;
 
         // Receive file
-        let mut file = File::create(local_file)?;
         let mut block_num: u16 = 1;
+        let mut negotiated = false;
+        let mut saved = state.received;
+        let mut total: Option<u64> = None;
         let mut retries = 0;
-        let max_retries = 5;
+        let max_retries = self.retries;
 
         loop {
             let mut buf = vec![0; self.block_size as usize + 4];
@@ -105,6 +568,7 @@ impl Client {
                         if src.ip() == self.server_ip {
                             server_addr = src;
                             tid_set = true;
+                            retries = 0;
                         } else {
                             continue;
                         }
@@ -112,36 +576,125 @@ impl Client {
                         continue;
                     }
 
-                    let packet = Packet::deserialize(&buf[..amt])?;
+                    let packet = Packet::deserialize(&buf[..amt])
+                        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+                    log::trace!("  Received {packet}");
                     match packet {
                         Packet::Data {
                             block_num: block,
                             data,
                         } => {
                             if block == block_num {
+                                if block == 1 && !negotiated && state.received > 0 {
+                                    log::warn!(
+                                        "Server ignored options, restarting from the beginning"
+                                    );
+                                    state.received = 0;
+                                    rewind(file, 0)?;
+                                }
+
                                 file.write_all(&data)?;
+                                state.received += data.len() as u64;
+                                self.report_progress(state.received, total);
+
+                                if state.received - saved >= RESUME_SAVE_INTERVAL {
+                                    saved = state.received;
+                                    if let Err(err) = state.save() {
+                                        log::warn!("Could not save resume state: {}", err);
+                                    }
+                                }
 
                                 // Send ACK
                                 let ack = Packet::Ack(block);
-                                socket.send_to(&ack.serialize()?, server_addr)?;
+                                send_packet(&socket, &ack, server_addr)?;
 
                                 block_num = block_num.wrapping_add(1);
                                 retries = 0;
 
                                 if data.len() < self.block_size as usize {
+                                    // Acknowledge retransmissions if this ACK gets lost
+                                    let socket = DallySocket {
+                                        socket,
+                                        server: server_addr,
+                                    };
+                                    dally(socket, self.block_size, self.timeout);
                                     break; // End of file
                                 }
                             }
                         }
                         Packet::Error { code, msg } => {
-                            return Err(anyhow::anyhow!("TFTP Error {:?}: {}", code, msg));
+                            return Err(ClientError::ServerError { code, msg });
                         }
-                        Packet::Oack(_) => {
+                        Packet::Oack { options, custom } => {
                             // Handle option negotiation
                             if block_num == 1 {
+                                if let Some(msg) = refused_option(&requested, &options) {
+                                    return Err(refuse_options(&socket, server_addr, msg));
+                                }
+                                self.set_acknowledged(custom);
+                                let value = |option: OptionType| {
+                                    options
+                                        .iter()
+                                        .find(|opt| opt.option == option)
+                                        .map(|opt| opt.value)
+                                };
+                                let tsize = value(OptionType::TransferSize);
+
+                                if offset > 0
+                                    && state.transfer_size.is_some()
+                                    && tsize != state.transfer_size
+                                {
+                                    state.received = 0;
+                                    return Err(refuse_options(
+                                        &socket,
+                                        server_addr,
+                                        format!(
+                                            "remote file {} changed since the interrupted download",
+                                            remote_file
+                                        ),
+                                    ));
+                                }
+
+                                // The server may clamp the offset or not support it at all
+                                let accepted = value(OptionType::Offset).unwrap_or(0);
+                                if accepted < offset {
+                                    log::warn!(
+                                        "Server accepted offset {} instead of {}, restarting from there",
+                                        accepted,
+                                        offset
+                                    );
+                                    state.received = accepted;
+                                    rewind(file, accepted)?;
+                                }
+
+                                negotiated = true;
+                                total = tsize;
+                                state.block_size = value(OptionType::BlockSize).map(|v| v as u16);
+                                state.window_size = value(OptionType::WindowSize).map(|v| v as u16);
+                                state.transfer_size = tsize;
+                                if let Err(err) = state.save() {
+                                    log::warn!("Could not save resume state: {}", err);
+                                }
+
+                                if let Some(tsize) = tsize
+                                    && let Err(err) = preallocate(file, tsize)
+                                {
+                                    let error = Packet::Error {
+                                        code: ErrorCode::DiskFull,
+                                        msg: "cannot allocate space for file".to_string(),
+                                    };
+                                    send_packet(&socket, &error, server_addr)?;
+                                    log::error!(
+                                        "Cannot preallocate {} bytes for {}",
+                                        tsize,
+                                        local_file.display()
+                                    );
+                                    return Err(err.into());
+                                }
+
                                 // Send ACK 0 to confirm options
                                 let ack = Packet::Ack(0);
-                                socket.send_to(&ack.serialize()?, server_addr)?;
+                                send_packet(&socket, &ack, server_addr)?;
                             }
                         }
                         _ => {}
@@ -151,15 +704,30 @@ impl Client {
                     if e.kind() == std::io::ErrorKind::WouldBlock
                         || e.kind() == std::io::ErrorKind::TimedOut =>
                 {
+                    // Without an answer the request, or the OACK, was lost
+                    if !tid_set {
+                        if retries >= self.request_retries {
+                            return Err(ClientError::Timeout);
+                        }
+                        retries += 1;
+                        log::warn!(
+                            "No answer, resending request... ({}/{})",
+                            retries,
+                            self.request_retries
+                        );
+                        self.send_request(&socket, &rrq, server_addr)?;
+                        continue;
+                    }
+
                     if retries >= max_retries {
-                        return Err(anyhow::anyhow!("Transfer timed out"));
+                        return Err(ClientError::Timeout);
                     }
                     retries += 1;
                     log::warn!("Timeout, retrying... ({}/{})", retries, max_retries);
 
                     // Resend last ACK
                     let ack = Packet::Ack(block_num.wrapping_sub(1));
-                    socket.send_to(&ack.serialize()?, server_addr)?;
+                    send_packet(&socket, &ack, server_addr)?;
                 }
                 Err(e) => return Err(e.into()),
             }
@@ -169,45 +737,72 @@ impl Client {
     }
 
     /// Upload a file to the server (WRQ - Write Request)
-    pub fn put(&self, local_file: &Path, remote_file: &str) -> anyhow::Result<()> {
+    pub fn put(&self, local_file: &Path, remote_file: &str) -> Result<(), ClientError> {
         log::info!("Uploading {} to {}", local_file.display(), remote_file);
 
-        let mut file = File::open(local_file)?;
+        let file = File::open(local_file)?;
+        if self.netascii() {
+            let size = Convert::netascii_len(File::open(local_file)?)?;
+            return self.put_reader(Convert::to_netascii(file), size, remote_file);
+        }
         let file_size = file.metadata()?.len();
+        self.put_reader(file, file_size, remote_file)
+    }
+
+    /// Upload `size` bytes read from `reader` to the server, for content that
+    /// is not in a local file such as generated data. The content is sent as
+    /// is, converted by the caller in netascii mode.
+    #[allow(clippy::collapsible_match)]
+    pub fn put_reader(
+        &self,
+        mut reader: impl Read,
+        size: u64,
+        remote_file: &str,
+    ) -> Result<(), ClientError> {
+        let block_size = max_block_size(self.block_size);
+        if block_size < self.block_size {
+            log::warn!(
+                "Block size {} exceeds the largest datagram this host can send, using {}",
+                self.block_size,
+                block_size
+            );
+        }
 
         // Create local socket
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let socket = self.bind_transfer_socket()?;
         let mut server_addr = SocketAddr::new(self.server_ip, self.server_port);
         let mut tid_set = false;
 
-        socket.set_read_timeout(Some(self.timeout))?;
-        socket.set_write_timeout(Some(self.timeout))?;
-
-        // Build options
-        let options = self.build_options(file_size);
+        // Build options, uploads wait for an ACK after every block
+        let requested = self.build_options(block_size, 1, size);
 
         // Send WRQ
         let wrq = Packet::Wrq {
             filename: remote_file.to_string(),
             mode: self.mode.clone(),
-            options,
+            options: requested.clone(),
+            custom: self.custom_options.clone(),
         };
-        let bytes = wrq.serialize()?;
-        socket.send_to(&bytes, server_addr)?;
+        self.set_acknowledged(Vec::new());
+        self.send_request(&socket, &wrq, server_addr)?;
 
         let mut block_num: u16 = 0;
         let mut retries = 0;
-        let max_retries = 5;
+        let max_retries = self.retries;
         let mut finished = false;
+        let mut acked: u64 = 0;
+        // Last block sent, kept for retransmissions since the reader cannot seek
+        let mut data: Vec<u8> = Vec::new();
 
         loop {
-            let mut buf = vec![0; self.block_size as usize + 4];
+            let mut buf = vec![0; block_size as usize + 4];
             match socket.recv_from(&mut buf) {
                 Ok((amt, src)) => {
                     if !tid_set {
                         if src.ip() == self.server_ip {
                             server_addr = src;
                             tid_set = true;
+                            retries = 0;
                         } else {
                             continue;
                         }
@@ -215,53 +810,53 @@ impl Client {
                         continue;
                     }
 
-                    let packet = Packet::deserialize(&buf[..amt])?;
+                    let packet = Packet::deserialize(&buf[..amt])
+                        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+                    log::trace!("  Received {packet}");
                     match packet {
                         Packet::Ack(block) => {
                             if block == block_num {
+                                acked += data.len() as u64;
+                                self.report_progress(acked, Some(size));
+
                                 if finished {
                                     break;
                                 }
 
+                                // Send next block
                                 block_num = block_num.wrapping_add(1);
-
-                                // Read next block
-                                let mut data = vec![0; self.block_size as usize];
-                                let n = file.read(&mut data)?;
-                                data.truncate(n);
-
-                                if n < self.block_size as usize {
-                                    finished = true;
-                                }
-
-                                // Send Data
-                                let data_packet = Packet::Data { block_num, data };
-                                socket.send_to(&data_packet.serialize()?, server_addr)?;
+                                data = read_block(&mut reader, block_size)?;
+                                finished = data.len() < block_size as usize;
+                                let data_packet = Packet::Data {
+                                    block_num,
+                                    data: data.clone(),
+                                };
+                                send_data(&socket, &data_packet, server_addr, block_size)?;
 
                                 retries = 0;
                             }
                         }
-                        Packet::Oack(_) => {
+                        Packet::Oack { options, custom } => {
                             if block_num == 0 {
+                                if let Some(msg) = refused_option(&requested, &options) {
+                                    return Err(refuse_options(&socket, server_addr, msg));
+                                }
+                                self.set_acknowledged(custom);
                                 // OACK received, start sending data (block 1)
                                 block_num = 1;
-
-                                let mut data = vec![0; self.block_size as usize];
-                                let n = file.read(&mut data)?;
-                                data.truncate(n);
-
-                                if n < self.block_size as usize {
-                                    finished = true;
-                                }
-
-                                let data_packet = Packet::Data { block_num, data };
-                                socket.send_to(&data_packet.serialize()?, server_addr)?;
+                                data = read_block(&mut reader, block_size)?;
+                                finished = data.len() < block_size as usize;
+                                let data_packet = Packet::Data {
+                                    block_num,
+                                    data: data.clone(),
+                                };
+                                send_data(&socket, &data_packet, server_addr, block_size)?;
 
                                 retries = 0;
                             }
                         }
                         Packet::Error { code, msg } => {
-                            return Err(anyhow::anyhow!("TFTP Error {:?}: {}", code, msg));
+                            return Err(ClientError::ServerError { code, msg });
                         }
                         _ => {}
                     }
@@ -270,39 +865,33 @@ impl Client {
                     if e.kind() == std::io::ErrorKind::WouldBlock
                         || e.kind() == std::io::ErrorKind::TimedOut =>
                 {
+                    // Without an answer the request, or the OACK, was lost
+                    let max_retries = if tid_set {
+                        max_retries
+                    } else {
+                        self.request_retries
+                    };
                     if retries >= max_retries {
-                        return Err(anyhow::anyhow!("Transfer timed out"));
+                        return Err(ClientError::Timeout);
                     }
                     retries += 1;
                     log::warn!("Timeout, retrying... ({}/{})", retries, max_retries);
 
                     // Resend last packet (WRQ or Data)
                     if block_num == 0 {
-                        // Resend WRQ
                         let wrq = Packet::Wrq {
                             filename: remote_file.to_string(),
                             mode: self.mode.clone(),
-                            options: self.build_options(file_size),
+                            options: self.build_options(block_size, 1, size),
+                            custom: self.custom_options.clone(),
                         };
-                        socket.send_to(&wrq.serialize()?, server_addr)?;
+                        self.send_request(&socket, &wrq, server_addr)?;
                     } else {
-                        // Resend Data
-                        // We need to seek back in file?
-                        // For simplicity in this refactor, we just error or warn.
-                        // Proper retry for data requires caching the last data packet or seeking.
-                        // Since we don't have the last data packet easily available here without restructuring,
-                        // we will just log a warning that retry might fail if we don't resend data.
-                        // Actually, we can seek back.
-
-                        let offset = (block_num as u64 - 1) * (self.block_size as u64);
-                        file.seek(std::io::SeekFrom::Start(offset))?;
-
-                        let mut data = vec![0; self.block_size as usize];
-                        let n = file.read(&mut data)?;
-                        data.truncate(n);
-
-                        let data_packet = Packet::Data { block_num, data };
-                        socket.send_to(&data_packet.serialize()?, server_addr)?;
+                        let data_packet = Packet::Data {
+                            block_num,
+                            data: data.clone(),
+                        };
+                        send_data(&socket, &data_packet, server_addr, block_size)?;
                     }
                 }
                 Err(e) => return Err(e.into()),
@@ -312,3 +901,191 @@ impl Client {
         Ok(())
     }
 }
+
+/// Converts the netascii downloaded to `local_file` to local line endings,
+/// once complete so that interrupted downloads resume at netascii offsets.
+fn from_netascii_in_place(local_file: &Path) -> Result<(), ClientError> {
+    let mut converted = local_file.as_os_str().to_owned();
+    converted.push(".netascii");
+    let mut writer = Convert::from_netascii(File::create(&converted)?);
+    std::io::copy(&mut File::open(local_file)?, &mut writer)?;
+    writer.end()?;
+    drop(writer);
+    std::fs::rename(&converted, local_file)?;
+    Ok(())
+}
+
+/// Reads up to `block_size` bytes, fewer only at the end of `reader`.
+fn read_block(reader: &mut impl Read, block_size: u16) -> Result<Vec<u8>, ClientError> {
+    let mut data = Vec::with_capacity(block_size as usize);
+    reader.take(block_size as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Truncates `file` to `len` bytes and moves the cursor to its end.
+fn rewind(file: &mut File, len: u64) -> Result<(), ClientError> {
+    file.set_len(len)?;
+    file.seek(SeekFrom::Start(len))?;
+    Ok(())
+}
+
+fn send_packet(
+    socket: &TransferSocket,
+    packet: &Packet,
+    to: SocketAddr,
+) -> Result<(), ClientError> {
+    log::trace!("  Sending {packet}");
+    let bytes = packet
+        .serialize()
+        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+    socket.send_to(&bytes, to)?;
+    Ok(())
+}
+
+/// Returns why the options of an OACK cannot be used, RFC 2347 letting servers
+/// only acknowledge the options requested and RFC 2348 and RFC 7440 only lower
+/// block and window sizes.
+fn refused_option(requested: &[TransferOption], acknowledged: &[TransferOption]) -> Option<String> {
+    acknowledged.iter().find_map(|ack| {
+        let name = ack.option.as_str();
+        let Some(request) = requested.iter().find(|opt| opt.option == ack.option) else {
+            return Some(format!(
+                "server acknowledged {name} which was not requested"
+            ));
+        };
+        match ack.option {
+            OptionType::BlockSize | OptionType::WindowSize if ack.value > request.value => {
+                Some(format!(
+                    "server acknowledged {name} {} above the {} requested",
+                    ack.value, request.value
+                ))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Ends option negotiation with an error 8 packet, as RFC 2347 defines for
+/// clients refusing the options acknowledged.
+fn refuse_options(socket: &TransferSocket, to: SocketAddr, msg: String) -> ClientError {
+    let error = Packet::Error {
+        code: ErrorCode::RefusedOption,
+        msg: msg.clone(),
+    };
+    let _ = send_packet(socket, &error, to);
+    ClientError::OptionNegotiation(msg)
+}
+
+/// Sends a data packet, turning an oversized datagram into a negotiation error
+/// since the block size cannot be changed once the transfer has started.
+fn send_data(
+    socket: &TransferSocket,
+    packet: &Packet,
+    to: SocketAddr,
+    block_size: u16,
+) -> Result<(), ClientError> {
+    match send_packet(socket, packet, to) {
+        Err(ClientError::Io(err)) if is_message_too_large(&err) => {
+            let error = Packet::Error {
+                code: ErrorCode::RefusedOption,
+                msg: "block size too large for network path".to_string(),
+            };
+            let _ = send_packet(socket, &error, to);
+            Err(ClientError::OptionNegotiation(format!(
+                "block size {} too large for network path",
+                block_size
+            )))
+        }
+        result => result,
+    }
+}
+
+/// TransferSocket `struct` is the socket of a transfer, recording the packets
+/// it sends and receives if the client records sessions, and capturing them
+/// if it captures packets.
+struct TransferSocket {
+    socket: Box<dyn TftpTransport>,
+    recorder: Option<SessionRecorder>,
+    pcap: Option<Arc<PcapWriter>>,
+}
+
+impl TransferSocket {
+    fn send_to(&self, bytes: &[u8], to: SocketAddr) -> std::io::Result<usize> {
+        let sent = self.socket.send(bytes, to)?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Flow::Sent, Some(to), bytes);
+        }
+        self.capture(Flow::Sent, to, bytes);
+        Ok(sent)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        let (amt, from) = self.socket.recv(buf)?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Flow::Received, Some(from), &buf[..amt]);
+        }
+        self.capture(Flow::Received, from, &buf[..amt]);
+        Ok((amt, from))
+    }
+
+    fn capture(&self, flow: Flow, peer: SocketAddr, bytes: &[u8]) {
+        if let Some(pcap) = &self.pcap
+            && let Ok(local) = self.socket.local_addr()
+        {
+            pcap.record(flow, local, peer, bytes);
+        }
+    }
+}
+
+/// DallySocket `struct` is the [`TransferSocket`] of a completed download
+/// handed to [`dally`], so that the retransmissions it acknowledges are
+/// recorded and captured like the rest of the transfer.
+struct DallySocket {
+    socket: TransferSocket,
+    server: SocketAddr,
+}
+
+impl Socket for DallySocket {
+    fn send(&self, packet: &Packet) -> anyhow::Result<()> {
+        self.send_to(packet, &self.server)
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
+        self.socket.send_to(&packet.serialize()?, *to)?;
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> anyhow::Result<Packet> {
+        loop {
+            let (packet, from) = self.recv_from_with_size(size)?;
+            if from == self.server {
+                return Ok(packet);
+            }
+        }
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> anyhow::Result<(Packet, SocketAddr)> {
+        let mut buf = vec![0; size + 4];
+        let (amt, from) = self.socket.recv_from(&mut buf)?;
+        Ok((Packet::deserialize(&buf[..amt])?, from))
+    }
+
+    fn remote_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.server)
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.socket.socket.set_read_timeout(Some(dur))?;
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.socket.socket.set_write_timeout(Some(dur))?;
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> anyhow::Result<()> {
+        self.socket.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+}
@@ -1,12 +1,20 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
+use super::resolve::ResolverConfig;
+use crate::tftp::core::{DigestAlgorithm, DtlsConfig};
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TftpcConfigFile {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub get: Option<ClientConfig>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub put: Option<ClientConfig>,
+    /// Resolution of server host names, unless set in `get` or `put`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<ResolverConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -19,10 +27,33 @@ pub struct ClientConfig {
     pub block_size: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none", with = "humantime_serde")]
     pub timeout: Option<Duration>,
+    /// Number of times a packet is resent after a timeout before giving up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u8>,
+    /// Number of times the request is resent while the server does not
+    /// answer, e.g. because its OACK was lost, `retries` by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_retries: Option<u8>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub window_size: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<DigestAlgorithm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_options: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<ResolverConfig>,
+    /// File the packets of each transfer are recorded to, for replay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<PathBuf>,
+    /// File the packets of every transfer are captured to, in pcap format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap: Option<PathBuf>,
+    /// Encrypts transfers with DTLS, the server port being that of its DTLS
+    /// endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dtls: Option<DtlsConfig>,
 }
 
 impl ClientConfig {
@@ -32,8 +63,16 @@ impl ClientConfig {
             port: Some(port),
             block_size: Some(512),
             timeout: Some(Duration::from_secs(5)),
+            retries: Some(5),
+            request_retries: None,
             window_size: Some(1),
             mode: Some("octet".to_string()),
+            checksum: None,
+            custom_options: None,
+            resolver: None,
+            record: None,
+            pcap: None,
+            dtls: None,
         }
     }
 
@@ -79,9 +118,75 @@ impl ClientConfig {
         self
     }
 
+    /// Sets how many times a packet is resent after a timeout, each retry
+    /// waiting for the full timeout again.
+    #[allow(dead_code)]
+    pub fn with_retries(mut self, retries: u8) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Sets how many times the read or write request is resent while the
+    /// server does not answer, before giving up.
+    #[allow(dead_code)]
+    pub fn with_request_retries(mut self, retries: u8) -> Self {
+        self.request_retries = Some(retries);
+        self
+    }
+
     #[allow(dead_code)]
     pub fn with_window_size(mut self, window_size: u16) -> Self {
         self.window_size = Some(window_size);
         self
     }
+
+    #[allow(dead_code)]
+    pub fn with_checksum(mut self, checksum: DigestAlgorithm) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Resolves a server given by host name with `resolver` instead of the
+    /// system resolver.
+    #[allow(dead_code)]
+    pub fn with_resolver(mut self, resolver: ResolverConfig) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Records the packets of each transfer to the session file `path`,
+    /// replaced by every transfer, see [`Session`].
+    ///
+    /// [`Session`]: crate::tftp::core::Session
+    #[allow(dead_code)]
+    pub fn with_record(mut self, path: PathBuf) -> Self {
+        self.record = Some(path);
+        self
+    }
+
+    /// Captures the packets of every transfer of the client to the pcap file
+    /// `path`, replaced when the client is created, see [`PcapWriter`].
+    ///
+    /// [`PcapWriter`]: crate::tftp::core::PcapWriter
+    #[allow(dead_code)]
+    pub fn with_pcap(mut self, path: PathBuf) -> Self {
+        self.pcap = Some(path);
+        self
+    }
+
+    /// Encrypts transfers with DTLS, see [`DtlsConfig`].
+    #[allow(dead_code)]
+    pub fn with_dtls(mut self, dtls: DtlsConfig) -> Self {
+        self.dtls = Some(dtls);
+        self
+    }
+
+    /// Adds an option unknown to the TFTP implementation to the requests.
+    #[allow(dead_code)]
+    pub fn with_custom_option(mut self, name: &str, value: &str) -> Self {
+        self.custom_options
+            .get_or_insert_with(BTreeMap::new)
+            .insert(name.to_string(), value.to_string());
+        self
+    }
 }
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::tftp::core::Convert;
+
+/// DNS-SD service type browsed for TFTP servers
+pub const TFTP_SERVICE: &str = "_tftp._udp.local";
+
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// Top bit of the question class requests a unicast response (RFC 6762 5.4)
+const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// Browses the local network with mDNS for `service` and returns the
+/// addresses of every instance that answered before `timeout` elapsed.
+pub fn browse(service: &str, timeout: Duration) -> std::io::Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(&build_query(service), MDNS_ADDR)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut found = Vec::new();
+    let mut buf = vec![0; 9000];
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+
+        match socket.recv_from(&mut buf) {
+            Ok((amt, from)) => match parse_response(&buf[..amt], from.ip()) {
+                Ok(addrs) => {
+                    for addr in addrs {
+                        if !found.contains(&addr) {
+                            log::debug!("  Discovered TFTP server {addr} (answer from {from})");
+                            found.push(addr);
+                        }
+                    }
+                }
+                Err(e) => log::debug!("  Ignoring malformed mDNS packet from {from}: {e}"),
+            },
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(found)
+}
+
+/// Builds an mDNS PTR query for `service`.
+fn build_query(service: &str) -> Vec<u8> {
+    // id, flags, qdcount = 1, ancount, nscount, arcount
+    let mut buf = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    encode_name(&mut buf, service);
+    buf.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    buf.extend_from_slice(&(CLASS_IN | UNICAST_RESPONSE).to_be_bytes());
+    buf
+}
+
+fn encode_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.') {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+}
+
+/// Reads a possibly compressed domain name starting at `start` and returns
+/// it along with the index just after the name.
+fn decode_name(buf: &[u8], start: usize) -> anyhow::Result<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut index = start;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *buf
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("Name out of bounds"))? as usize;
+
+        if len & 0xC0 == 0xC0 {
+            let pointer = (Convert::to_u16(&buf[index..])? & 0x3FFF) as usize;
+            end.get_or_insert(index + 2);
+            jumps += 1;
+            if jumps > 16 {
+                return Err(anyhow::anyhow!("Name compression loop"));
+            }
+            index = pointer;
+        } else if len == 0 {
+            end.get_or_insert(index + 1);
+            break;
+        } else {
+            let label = buf
+                .get(index + 1..index + 1 + len)
+                .ok_or_else(|| anyhow::anyhow!("Label out of bounds"))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            index += 1 + len;
+        }
+    }
+
+    Ok((labels.join("."), end.unwrap()))
+}
+
+/// Extracts the advertised server addresses from an mDNS response. Targets
+/// without an address record fall back to the address of the responder.
+fn parse_response(buf: &[u8], from: IpAddr) -> anyhow::Result<Vec<SocketAddr>> {
+    if buf.len() < 12 {
+        return Err(anyhow::anyhow!("Packet too short"));
+    }
+
+    let qdcount = Convert::to_u16(&buf[4..])?;
+    let records = Convert::to_u16(&buf[6..])? as usize
+        + Convert::to_u16(&buf[8..])? as usize
+        + Convert::to_u16(&buf[10..])? as usize;
+    let mut index = 12;
+
+    for _ in 0..qdcount {
+        index = decode_name(buf, index)?.1 + 4;
+    }
+
+    let mut services = Vec::new();
+    let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+
+    for _ in 0..records {
+        let (name, next) = decode_name(buf, index)?;
+        let header = buf
+            .get(next..next + 10)
+            .ok_or_else(|| anyhow::anyhow!("Record out of bounds"))?;
+        let rtype = Convert::to_u16(header)?;
+        let rdlen = Convert::to_u16(&header[8..])? as usize;
+        let rdata_start = next + 10;
+        let rdata = buf
+            .get(rdata_start..rdata_start + rdlen)
+            .ok_or_else(|| anyhow::anyhow!("Record data out of bounds"))?;
+
+        match rtype {
+            TYPE_SRV if rdlen >= 7 => {
+                let port = Convert::to_u16(&rdata[4..])?;
+                let (target, _) = decode_name(buf, rdata_start + 6)?;
+                services.push((target.to_lowercase(), port));
+            }
+            TYPE_A if rdlen == 4 => {
+                let ip = Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]);
+                hosts
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .push(ip.into());
+            }
+            TYPE_AAAA if rdlen == 16 => {
+                let octets: [u8; 16] = rdata.try_into()?;
+                let ip = Ipv6Addr::from(octets);
+                hosts
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .push(ip.into());
+            }
+            _ => {}
+        }
+
+        index = rdata_start + rdlen;
+    }
+
+    let mut addrs = Vec::new();
+    for (target, port) in services {
+        match hosts.get(&target) {
+            Some(ips) => addrs.extend(ips.iter().map(|ip| SocketAddr::new(*ip, port))),
+            None => addrs.push(SocketAddr::new(from, port)),
+        }
+    }
+
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(buf: &mut Vec<u8>, name: &str, rtype: u16, rdata: &[u8]) {
+        encode_name(buf, name);
+        buf.extend_from_slice(&rtype.to_be_bytes());
+        buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        buf.extend_from_slice(&120u32.to_be_bytes());
+        buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        buf.extend_from_slice(rdata);
+    }
+
+    #[test]
+    fn builds_ptr_query() {
+        let query = build_query(TFTP_SERVICE);
+        assert_eq!(&query[4..6], &[0, 1]);
+        assert_eq!(decode_name(&query, 12).unwrap().0, TFTP_SERVICE);
+        assert_eq!(&query[query.len() - 4..], &[0x00, 0x0C, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn decodes_compressed_name() {
+        let mut buf = vec![0; 12];
+        encode_name(&mut buf, "_tftp._udp.local");
+        // "lab" followed by a pointer to offset 12
+        buf.extend_from_slice(&[3, b'l', b'a', b'b', 0xC0, 12]);
+
+        let (name, end) = decode_name(&buf, 30).unwrap();
+        assert_eq!(name, "lab._tftp._udp.local");
+        assert_eq!(end, buf.len());
+    }
+
+    #[test]
+    fn parses_srv_and_address_records() {
+        let mut buf = vec![0, 0, 0x84, 0, 0, 0, 0, 3, 0, 0, 0, 0];
+        let mut ptr = Vec::new();
+        encode_name(&mut ptr, "lab._tftp._udp.local");
+        record(&mut buf, TFTP_SERVICE, TYPE_PTR, &ptr);
+
+        let mut srv = vec![0, 0, 0, 0, 0x1B, 0x39];
+        encode_name(&mut srv, "pxe.local");
+        record(&mut buf, "lab._tftp._udp.local", TYPE_SRV, &srv);
+        record(&mut buf, "pxe.local", TYPE_A, &[192, 168, 1, 10]);
+
+        let addrs = parse_response(&buf, "10.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!(addrs, vec!["192.168.1.10:6969".parse().unwrap()]);
+    }
+
+    #[test]
+    fn falls_back_to_responder_address() {
+        let mut buf = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+        let mut srv = vec![0, 0, 0, 0, 0, 69];
+        encode_name(&mut srv, "pxe.local");
+        record(&mut buf, "lab._tftp._udp.local", TYPE_SRV, &srv);
+
+        let addrs = parse_response(&buf, "10.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!(addrs, vec!["10.0.0.1:69".parse().unwrap()]);
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::tftp::core::ErrorCode;
+
+/// ClientError `enum` represents the reasons a [`Client`](super::Client)
+/// operation can fail, so callers can react to them without parsing messages.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::client::ClientError;
+/// use xtool::tftp::core::ErrorCode;
+///
+/// let err = ClientError::ServerError {
+///     code: ErrorCode::FileNotFound,
+///     msg: "file dump.bin does not exist".to_string(),
+/// };
+/// assert!(matches!(err, ClientError::ServerError { code: ErrorCode::FileNotFound, .. }));
+/// ```
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server stopped answering before the transfer completed
+    Timeout,
+    /// The server aborted the transfer with an error packet
+    ServerError { code: ErrorCode, msg: String },
+    /// The options acknowledged by the server cannot be used
+    OptionNegotiation(String),
+    /// The server address is missing or invalid
+    InvalidAddress(String),
+    /// A received packet is malformed or unexpected
+    Protocol(String),
+    /// The saved resume state cannot be read or written
+    ResumeState(String),
+    /// A download manifest is malformed or a listed file failed verification
+    Manifest(String),
+    /// Local socket or file error
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Timeout => write!(f, "Transfer timed out"),
+            ClientError::ServerError { code, msg } => write!(f, "TFTP Error {code}: {msg}"),
+            ClientError::OptionNegotiation(msg) => write!(f, "Option negotiation failed: {msg}"),
+            ClientError::InvalidAddress(msg) => write!(f, "Invalid server address: {msg}"),
+            ClientError::Protocol(msg) => write!(f, "Protocol error: {msg}"),
+            ClientError::ResumeState(msg) => write!(f, "Invalid resume state: {msg}"),
+            ClientError::Manifest(msg) => write!(f, "Manifest error: {msg}"),
+            ClientError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(err: std::io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
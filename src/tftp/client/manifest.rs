@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::error::ClientError;
+use crate::tftp::core::DigestAlgorithm;
+
+/// ManifestEntry `struct` is one file listed in a download manifest.
+///
+/// A manifest has one entry per line, made of the remote file name, its size
+/// in bytes and its SHA-256 in hex, separated by whitespace. Blank lines and
+/// lines starting with `#` are ignored.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::client::ManifestEntry;
+///
+/// let manifest = "# boot files\nzImage 4096 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08\n";
+/// let entries = ManifestEntry::parse(manifest).unwrap();
+/// assert_eq!(entries[0].name, "zImage");
+/// assert_eq!(entries[0].size, 4096);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// Remote file name, also used as path below the destination directory
+    pub name: String,
+    /// Expected size in bytes
+    pub size: u64,
+    /// Expected SHA-256 in lowercase hex
+    pub sha256: String,
+}
+
+impl ManifestEntry {
+    /// Parses the content of a manifest.
+    pub fn parse(content: &str) -> Result<Vec<ManifestEntry>, ClientError> {
+        let mut entries = Vec::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid =
+                |reason: &str| ClientError::Manifest(format!("line {}: {}", i + 1, reason));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, size, sha256] = fields[..] else {
+                return Err(invalid("expected NAME SIZE SHA256"));
+            };
+
+            // Names become local paths, they must stay inside the destination
+            if Path::new(name)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                return Err(invalid("file name must be a relative path"));
+            }
+            let size = size.parse().map_err(|_| invalid("invalid size"))?;
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid("invalid SHA-256"));
+            }
+
+            entries.push(ManifestEntry {
+                name: name.to_string(),
+                size,
+                sha256: sha256.to_ascii_lowercase(),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads and parses the manifest at `path`.
+    pub fn load(path: &Path) -> Result<Vec<ManifestEntry>, ClientError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the path of the entry below `dest_dir`.
+    pub fn local_path(&self, dest_dir: &Path) -> PathBuf {
+        dest_dir.join(&self.name)
+    }
+
+    /// Checks that `path` has the expected size and checksum.
+    pub fn verify(&self, path: &Path) -> Result<(), ClientError> {
+        let size = fs::metadata(path)?.len();
+        if size != self.size {
+            return Err(ClientError::Manifest(format!(
+                "{}: expected {} bytes, got {}",
+                self.name, self.size, size
+            )));
+        }
+
+        let digest = DigestAlgorithm::Sha256
+            .digest_file(path)
+            .map_err(|e| ClientError::Manifest(format!("{}: {}", self.name, e)))?;
+        if digest != self.sha256 {
+            return Err(ClientError::Manifest(format!(
+                "{}: expected sha256 {}, got {}",
+                self.name, self.sha256, digest
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Files handled by [`Client::get_manifest()`](super::Client::get_manifest)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestSummary {
+    /// Entries downloaded and verified
+    pub downloaded: Vec<String>,
+    /// Entries already present and valid locally
+    pub skipped: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR_NAME: &str = "target/test";
+    const SHA256_EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn parses_manifest() {
+        let content = format!(
+            "\n# comment\nboot/zImage 0 {}\n  dtb.bin\t12  {}  \n",
+            SHA256_EMPTY,
+            SHA256_EMPTY.to_uppercase()
+        );
+        let entries = ManifestEntry::parse(&content).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "boot/zImage");
+        assert_eq!(entries[1].size, 12);
+        assert_eq!(entries[1].sha256, SHA256_EMPTY);
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        for line in [
+            "zImage 12".to_string(),
+            format!("zImage twelve {}", SHA256_EMPTY),
+            "zImage 12 abcd".to_string(),
+            format!("../zImage 12 {}", SHA256_EMPTY),
+            format!("/boot/zImage 12 {}", SHA256_EMPTY),
+        ] {
+            assert!(matches!(
+                ManifestEntry::parse(&line),
+                Err(ClientError::Manifest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn verifies_file() {
+        let dir = Path::new(DIR_NAME);
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join("manifest_verify.bin");
+        fs::write(&path, b"").unwrap();
+
+        let entry = ManifestEntry {
+            name: "manifest_verify.bin".to_string(),
+            size: 0,
+            sha256: SHA256_EMPTY.to_string(),
+        };
+        assert!(entry.verify(&path).is_ok());
+
+        fs::write(&path, b"x").unwrap();
+        assert!(entry.verify(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+//! Interoperability matrix against a TFTP server
+//!
+//! Runs one transfer for every combination of block size, window size and
+//! timeout and reports which ones succeed and how fast, to find the settings
+//! a vendor device actually supports.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::Client;
+use super::config::ClientConfig;
+use crate::tftp::core::DigestAlgorithm;
+
+/// Parameters of a matrix run
+#[derive(Debug, Clone)]
+pub struct MatrixOptions {
+    pub block_sizes: Vec<u16>,
+    pub window_sizes: Vec<u16>,
+    pub timeouts: Vec<Duration>,
+    /// Retries of each transfer, kept low so unsupported settings fail fast
+    pub retries: u8,
+    /// Download this remote file, otherwise upload generated files
+    pub remote_file: Option<String>,
+    /// Size of the generated files uploaded when no remote file is given
+    pub upload_size: u64,
+}
+
+impl Default for MatrixOptions {
+    fn default() -> Self {
+        Self {
+            block_sizes: vec![512, 1024, 1428, 8192, 65464],
+            window_sizes: vec![1, 4, 16],
+            timeouts: vec![Duration::from_secs(1), Duration::from_secs(5)],
+            retries: 1,
+            remote_file: None,
+            upload_size: 256 * 1024,
+        }
+    }
+}
+
+/// Result of one combination
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub block_size: u16,
+    pub window_size: u16,
+    pub timeout: Duration,
+    /// Duration of the transfer, or the reason it failed
+    pub outcome: Result<Duration, String>,
+    pub bytes: u64,
+}
+
+impl Cell {
+    /// Bytes per second of a successful transfer
+    pub fn throughput(&self) -> Option<f64> {
+        let elapsed = self.outcome.as_ref().ok()?.as_secs_f64();
+        Some(self.bytes as f64 / elapsed.max(f64::EPSILON))
+    }
+}
+
+/// Results of a matrix run, displayed as a table
+#[derive(Debug, Clone, Default)]
+pub struct Matrix {
+    pub cells: Vec<Cell>,
+}
+
+impl Matrix {
+    pub fn passed(&self) -> usize {
+        self.cells.iter().filter(|c| c.outcome.is_ok()).count()
+    }
+
+    /// Fastest successful combination
+    pub fn best(&self) -> Option<&Cell> {
+        self.cells
+            .iter()
+            .filter_map(|c| c.throughput().map(|t| (c, t)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(c, _)| c)
+    }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>7}  {:>6}  {:>7}  {:<6}  {:>14}",
+            "blksize", "window", "timeout", "result", "throughput"
+        )?;
+        for cell in &self.cells {
+            let (result, detail) = match (&cell.outcome, cell.throughput()) {
+                (Ok(_), Some(throughput)) => ("pass", format!("{:.1} KiB/s", throughput / 1024.0)),
+                (Err(e), _) => ("fail", e.clone()),
+                _ => ("pass", String::new()),
+            };
+            writeln!(
+                f,
+                "{:>7}  {:>6}  {:>6}s  {:<6}  {:>14}",
+                cell.block_size,
+                cell.window_size,
+                cell.timeout.as_secs(),
+                result,
+                detail
+            )?;
+        }
+        write!(
+            f,
+            "{}/{} combinations passed",
+            self.passed(),
+            self.cells.len()
+        )
+    }
+}
+
+/// Runs the transfer grid described by `options` against `server:port`
+///
+/// Downloads are compared with the first successful one to catch settings
+/// that transfer without errors but corrupt the data. Uploads always use a
+/// window size of 1, so the window sizes only apply to downloads.
+pub fn run(server: &str, port: u16, options: &MatrixOptions) -> anyhow::Result<Matrix> {
+    let work_dir = std::env::temp_dir().join(format!("xtool-matrix-{}", std::process::id()));
+    fs::create_dir_all(&work_dir)?;
+
+    let upload = work_dir.join("upload.bin");
+    let window_sizes = match &options.remote_file {
+        Some(_) => options.window_sizes.clone(),
+        None => {
+            let content: Vec<u8> = (0..options.upload_size).map(|i| (i % 251) as u8).collect();
+            fs::write(&upload, content)?;
+            vec![1]
+        }
+    };
+
+    let mut matrix = Matrix::default();
+    let mut reference: Option<String> = None;
+
+    for &block_size in &options.block_sizes {
+        for &window_size in &window_sizes {
+            for &timeout in &options.timeouts {
+                let config = ClientConfig::new(server.to_string(), port)
+                    .with_block_size(block_size)
+                    .with_window_size(window_size)
+                    .with_timeout(timeout)
+                    .with_retries(options.retries);
+                let client = Client::new(config)?;
+
+                let start = Instant::now();
+                let result = match &options.remote_file {
+                    Some(remote_file) => download(&client, remote_file, &work_dir, &mut reference),
+                    None => {
+                        let remote_file = format!(
+                            "xtool-matrix-{}-{}-{}.bin",
+                            std::process::id(),
+                            block_size,
+                            timeout.as_secs()
+                        );
+                        client
+                            .put(&upload, &remote_file)
+                            .map(|_| options.upload_size)
+                            .map_err(anyhow::Error::from)
+                    }
+                };
+
+                let cell = match result {
+                    Ok(bytes) => Cell {
+                        block_size,
+                        window_size,
+                        timeout,
+                        outcome: Ok(start.elapsed()),
+                        bytes,
+                    },
+                    Err(e) => Cell {
+                        block_size,
+                        window_size,
+                        timeout,
+                        outcome: Err(e.to_string()),
+                        bytes: 0,
+                    },
+                };
+                log::info!(
+                    "blksize {} windowsize {} timeout {}s: {}",
+                    block_size,
+                    window_size,
+                    timeout.as_secs(),
+                    match &cell.outcome {
+                        Ok(_) => "pass",
+                        Err(_) => "fail",
+                    }
+                );
+                matrix.cells.push(cell);
+            }
+        }
+    }
+
+    let _ = fs::remove_dir_all(&work_dir);
+    Ok(matrix)
+}
+
+/// Downloads `remote_file` and checks it matches earlier downloads
+fn download(
+    client: &Client,
+    remote_file: &str,
+    work_dir: &Path,
+    reference: &mut Option<String>,
+) -> anyhow::Result<u64> {
+    let local_file = work_dir.join("download.bin");
+    client.get(remote_file, &local_file)?;
+
+    let digest = DigestAlgorithm::Sha256.digest_file(&local_file)?;
+    match reference {
+        Some(expected) if *expected != digest => {
+            anyhow::bail!("content differs from the first download")
+        }
+        Some(_) => {}
+        None => *reference = Some(digest),
+    }
+    Ok(fs::metadata(&local_file)?.len())
+}
@@ -3,7 +3,16 @@
 //! This module provides TFTP client functionality:
 //! - File download (GET/RRQ)
 //! - File upload (PUT/WRQ)
+//! - Server discovery over mDNS (`_tftp._udp.local`)
+//! - Reachability probe to wait for a server to come up
+//! - Soak testing a server with randomized transfers
+//! - Interoperability matrix of block size, window size and timeout
+//! - Resuming interrupted downloads from a sidecar state file
+//! - Downloading and verifying the files listed in a manifest
+//! - Mirroring the files of an xtool server, downloading only changed ones
 //! - Supports all TFTP option extensions
+//! - Host name resolution with a static hosts map, DNS-over-HTTPS or DNS-over-TLS
+//! - Typed [`ClientError`] failures that callers can match on
 //!
 //! # Usage Examples
 //!
@@ -37,18 +46,66 @@
 //! # Download file
 //! xtool tftpc get 192.168.1.100 remote.txt [local.txt]
 //!
+//! # Continue an interrupted download
+//! xtool tftpc get --resume 192.168.1.100 remote.txt [local.txt]
+//!
+//! # Download the files listed in a manifest into a directory
+//! xtool tftpc get 192.168.1.100 --manifest files.txt [DIR]
+//!
+//! # Download the files of an xtool server that changed, 4 at a time
+//! xtool tftpc mirror 192.168.1.100 [DIR] --jobs 4
+//!
 //! # Upload file
 //! xtool tftpc put 192.168.1.100 local.txt [remote.txt]
+//!
+//! # Upload generated data
+//! xtool tftpc put 192.168.1.100 --pattern random --size 100M test.bin
+//!
+//! # Find servers on the local network
+//! xtool tftpc discover
+//!
+//! # Wait up to 60 seconds for a server to answer
+//! xtool tftpc ping 192.168.1.100 --wait 60
+//!
+//! # Loop randomized uploads and downloads for 10 minutes
+//! xtool tftpc soak 192.168.1.100 --duration 600
+//!
+//! # Find the block and window sizes a device supports
+//! xtool tftpc matrix 192.168.1.100 u-boot.bin
 //! ```
 
+#[allow(clippy::module_inception)]
 pub mod client;
 pub mod config;
+mod discover;
+mod error;
+mod manifest;
+pub mod matrix;
+mod pattern;
+mod resolve;
+mod resume;
+pub mod soak;
 
 use anyhow::Result;
 use clap::Subcommand;
-use std::path::PathBuf;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::i18n::{tr, tr_args};
+use crate::tftp::core::{DigestAlgorithm, ErrorCode};
 
-pub use client::Client;
+pub use client::{Client, PingStatus};
+#[allow(unused_imports)]
+pub use error::ClientError;
+#[allow(unused_imports)]
+pub use manifest::{ManifestEntry, ManifestSummary};
+#[allow(unused_imports)]
+pub use pattern::{Pattern, PatternReader, parse_size};
+#[allow(unused_imports)]
+pub use resolve::ResolverConfig;
+#[allow(unused_imports)]
+pub use resume::ResumeState;
 
 #[derive(Subcommand)]
 pub enum TftpcAction {
@@ -57,8 +114,9 @@ pub enum TftpcAction {
         /// Server IP address or hostname
         server: String,
 
-        /// Remote file name on server
-        remote_file: String,
+        /// Remote file name on server, the destination directory with --manifest
+        #[arg(required_unless_present = "manifest")]
+        remote_file: Option<String>,
 
         /// Local file path (defaults to remote file name)
         #[arg(value_name = "LOCAL_FILE")]
@@ -75,6 +133,31 @@ pub enum TftpcAction {
         /// Timeout in seconds
         #[arg(short, long, default_value = "5")]
         timeout: u64,
+
+        /// Times a packet is resent after a timeout (default: 5)
+        #[arg(short, long)]
+        retries: Option<u8>,
+
+        /// Checksum algorithm to report after transfer (crc32, md5, sha256, blake3)
+        #[arg(long, value_name = "ALGORITHM")]
+        checksum: Option<DigestAlgorithm>,
+
+        /// Expected checksum (hex) of the file, defaults to sha256 if no algorithm is given
+        #[arg(long, value_name = "HEX")]
+        verify: Option<String>,
+
+        /// Extra option sent with the request, may be repeated
+        #[arg(short = 'o', long = "option", value_name = "NAME=VALUE")]
+        options: Vec<String>,
+
+        /// Continue an interrupted download from its saved state, or from the
+        /// length of the local file (xtool servers only)
+        #[arg(long)]
+        resume: bool,
+
+        /// Download and verify the files listed in a manifest (NAME SIZE SHA256 per line)
+        #[arg(short, long, value_name = "FILE", conflicts_with_all = ["resume", "verify", "local_file"])]
+        manifest: Option<PathBuf>,
     },
 
     /// Upload a file to TFTP server (WRQ)
@@ -82,8 +165,9 @@ pub enum TftpcAction {
         /// Server IP address or hostname
         server: String,
 
-        /// Local file path to upload
-        local_file: PathBuf,
+        /// Local file path to upload, the remote file name with --pattern
+        #[arg(required_unless_present = "pattern")]
+        local_file: Option<PathBuf>,
 
         /// Remote file name on server (defaults to local file name)
         #[arg(value_name = "REMOTE_FILE")]
@@ -100,6 +184,173 @@ pub enum TftpcAction {
         /// Timeout in seconds
         #[arg(short, long, default_value = "5")]
         timeout: u64,
+
+        /// Times a packet is resent after a timeout (default: 5)
+        #[arg(short, long)]
+        retries: Option<u8>,
+
+        /// Checksum algorithm to report after transfer (crc32, md5, sha256, blake3)
+        #[arg(long, value_name = "ALGORITHM")]
+        checksum: Option<DigestAlgorithm>,
+
+        /// Expected checksum (hex) of the file, defaults to sha256 if no algorithm is given
+        #[arg(long, value_name = "HEX")]
+        verify: Option<String>,
+
+        /// Extra option sent with the request, may be repeated
+        #[arg(short = 'o', long = "option", value_name = "NAME=VALUE")]
+        options: Vec<String>,
+
+        /// Upload generated data instead of a local file (random, zeros)
+        #[arg(long, requires = "size", conflicts_with_all = ["checksum", "verify"])]
+        pattern: Option<Pattern>,
+
+        /// Size of the generated data, e.g. 4096, 512K or 100M
+        #[arg(long, value_parser = parse_size, requires = "pattern")]
+        size: Option<u64>,
+    },
+
+    /// Download the files of an xtool server that are missing or changed locally
+    Mirror {
+        /// Server IP address or hostname
+        server: String,
+
+        /// Local directory to mirror into
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Server port
+        #[arg(short, long, default_value = "69")]
+        port: u16,
+
+        /// Block size (512-65464)
+        #[arg(short, long, default_value = "512")]
+        block_size: u16,
+
+        /// Timeout in seconds
+        #[arg(short, long, default_value = "5")]
+        timeout: u64,
+
+        /// Times a packet is resent after a timeout (default: 5)
+        #[arg(short, long)]
+        retries: Option<u8>,
+
+        /// Files downloaded at once
+        #[arg(short, long, default_value = "4")]
+        jobs: usize,
+    },
+
+    /// Discover TFTP servers on the local network via mDNS
+    Discover {
+        /// Time to wait for answers in seconds
+        #[arg(short, long, default_value = "2")]
+        timeout: u64,
+    },
+
+    /// Check whether a TFTP server answers requests
+    Ping {
+        /// Server IP address or hostname
+        server: String,
+
+        /// Server port
+        #[arg(short, long, default_value = "69")]
+        port: u16,
+
+        /// Timeout of each probe in seconds
+        #[arg(short, long, default_value = "1")]
+        timeout: u64,
+
+        /// Keep probing for up to this many seconds until the server answers
+        #[arg(short, long, value_name = "SECONDS")]
+        wait: Option<u64>,
+    },
+
+    /// Loop randomized transfers against a server, tracking failures and leaks
+    Soak {
+        /// Server IP address or hostname
+        server: String,
+
+        /// Server port
+        #[arg(short, long, default_value = "69")]
+        port: u16,
+
+        /// How long to run in seconds
+        #[arg(short, long, default_value = "60")]
+        duration: u64,
+
+        /// Smallest uploaded file size in bytes
+        #[arg(long, default_value = "0")]
+        min_size: u64,
+
+        /// Largest uploaded file size in bytes
+        #[arg(long, default_value = "1048576")]
+        max_size: u64,
+
+        /// Largest block size to pick (512-65464)
+        #[arg(long, default_value = "8192")]
+        max_block_size: u16,
+
+        /// Largest window size to pick
+        #[arg(long, default_value = "8")]
+        max_window: u16,
+
+        /// Timeout in seconds
+        #[arg(short, long, default_value = "2")]
+        timeout: u64,
+
+        /// Only download this remote file, for read-only servers
+        #[arg(short, long, value_name = "REMOTE_FILE")]
+        file: Option<String>,
+
+        /// Local process to watch for fd and memory leaks, e.g. the server (Linux only)
+        #[arg(long)]
+        pid: Option<u32>,
+
+        /// Seed of the random parameters, to reproduce a run
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Exit with an error if more than this percentage of transfers fail
+        #[arg(long, default_value = "0")]
+        max_failure_rate: f64,
+    },
+
+    /// Try every combination of block size, window size and timeout against a server
+    Matrix {
+        /// Server IP address or hostname
+        server: String,
+
+        /// Remote file to download, uploads generated files if omitted
+        #[arg(value_name = "REMOTE_FILE")]
+        remote_file: Option<String>,
+
+        /// Server port
+        #[arg(short, long, default_value = "69")]
+        port: u16,
+
+        /// Block sizes to try
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "512,1024,1428,8192,65464"
+        )]
+        block_sizes: Vec<u16>,
+
+        /// Window sizes to try, downloads only
+        #[arg(long, value_delimiter = ',', default_value = "1,4,16")]
+        window_sizes: Vec<u16>,
+
+        /// Timeouts to try in seconds
+        #[arg(long, value_delimiter = ',', default_value = "1,5")]
+        timeouts: Vec<u64>,
+
+        /// Times a packet is resent after a timeout
+        #[arg(short, long, default_value = "1")]
+        retries: u8,
+
+        /// Size of the generated files to upload
+        #[arg(long, default_value = "262144", value_name = "BYTES")]
+        upload_size: u64,
     },
 }
 
@@ -116,10 +367,43 @@ pub fn run_with_config(
             port,
             block_size,
             timeout,
+            retries,
+            checksum,
+            verify,
+            options,
+            resume,
+            manifest,
         } => {
             let client_config = config.and_then(|c| c.get.clone()).unwrap_or_default();
             let cfg = client_config.merge_cli(server.clone(), port, block_size, timeout);
+            let cfg = with_resolver(cfg, config);
+            let cfg = with_custom_options(cfg, &options)?;
+            let cfg = match retries {
+                Some(retries) => cfg.with_retries(retries),
+                None => cfg,
+            };
+
+            if let Some(manifest) = manifest {
+                // With a manifest the only positional argument is the destination
+                let dest_dir = remote_file
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("."));
+                log::info!(
+                    "Downloading files of {} into {}",
+                    manifest.display(),
+                    dest_dir.display()
+                );
 
+                let summary = Client::new(cfg)?.get_manifest(&manifest, &dest_dir)?;
+                log::info!(
+                    "Manifest complete: {} downloaded, {} already up to date",
+                    summary.downloaded.len(),
+                    summary.skipped.len()
+                );
+                return Ok(());
+            }
+
+            let remote_file = remote_file.unwrap_or_default();
             let local_path = local_file.unwrap_or_else(|| PathBuf::from(&remote_file));
 
             // Note: cfg.server is Option<String>, but merge_cli ensures it's set if cli_server is provided
@@ -134,10 +418,20 @@ pub fn run_with_config(
             );
             log::info!("Saving to: {}", local_path.display());
 
-            let client = Client::new(cfg)?;
-            client.get(&remote_file, &local_path)?;
+            let checksum = checksum.or(cfg.checksum);
+            let bar = progress_bar();
+            let client = Client::new(cfg)?.with_progress(update_progress(bar.clone()));
+            let result = if resume {
+                client.resume(&remote_file, &local_path)
+            } else {
+                client.get(&remote_file, &local_path)
+            };
+            bar.finish();
+            result?;
 
+            log_acknowledged(&client);
             log::info!("Download completed successfully");
+            check_integrity(&local_path, checksum, verify.as_deref())?;
         }
 
         TftpcAction::Put {
@@ -147,10 +441,56 @@ pub fn run_with_config(
             port,
             block_size,
             timeout,
+            retries,
+            checksum,
+            verify,
+            options,
+            pattern,
+            size,
         } => {
             let client_config = config.and_then(|c| c.put.clone()).unwrap_or_default();
             let cfg = client_config.merge_cli(server.clone(), port, block_size, timeout);
+            let cfg = with_resolver(cfg, config);
+            let cfg = with_custom_options(cfg, &options)?;
+            let cfg = match retries {
+                Some(retries) => cfg.with_retries(retries),
+                None => cfg,
+            };
 
+            if let (Some(pattern), Some(size)) = (pattern, size) {
+                // With a pattern the only positional argument is the remote file name
+                let (Some(remote_name), None) = (local_file, remote_file) else {
+                    return Err(anyhow::anyhow!(
+                        "--pattern takes the remote file name as only argument"
+                    ));
+                };
+                let remote_name = remote_name.to_string_lossy().into_owned();
+                log::info!(
+                    "Uploading {} bytes of {} data to {}:{}",
+                    size,
+                    pattern,
+                    cfg.server.as_deref().unwrap_or("unknown"),
+                    cfg.port.unwrap_or(69)
+                );
+                log::info!("Remote file: {}", remote_name);
+
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or_default();
+                let bar = progress_bar();
+                let client = Client::new(cfg)?.with_progress(update_progress(bar.clone()));
+                let result =
+                    client.put_reader(PatternReader::new(pattern, size, seed), size, &remote_name);
+                bar.finish();
+                result?;
+
+                log_acknowledged(&client);
+                log::info!("Upload completed successfully");
+                return Ok(());
+            }
+
+            let local_file = local_file.unwrap_or_default();
             if !local_file.exists() {
                 log::error!("Local file does not exist: {}", local_file.display());
                 return Err(anyhow::anyhow!("Local file does not exist"));
@@ -175,11 +515,278 @@ pub fn run_with_config(
             );
             log::info!("Remote file: {}", remote_name);
 
-            let client = Client::new(cfg)?;
-            client.put(&local_file, &remote_name)?;
+            let checksum = checksum.or(cfg.checksum);
+            let bar = progress_bar();
+            let client = Client::new(cfg)?.with_progress(update_progress(bar.clone()));
+            let result = client.put(&local_file, &remote_name);
+            bar.finish();
+            result?;
 
+            log_acknowledged(&client);
             log::info!("Upload completed successfully");
+            check_integrity(&local_file, checksum, verify.as_deref())?;
         }
+
+        TftpcAction::Mirror {
+            server,
+            dir,
+            port,
+            block_size,
+            timeout,
+            retries,
+            jobs,
+        } => {
+            let client_config = config.and_then(|c| c.get.clone()).unwrap_or_default();
+            let cfg = client_config.merge_cli(server.clone(), port, block_size, timeout);
+            let cfg = with_resolver(cfg, config);
+            let cfg = match retries {
+                Some(retries) => cfg.with_retries(retries),
+                None => cfg,
+            };
+
+            log::info!("Mirroring {}:{} into {}", server, port, dir.display());
+            let summary = match Client::new(cfg)?.mirror(&dir, jobs) {
+                Err(ClientError::ServerError {
+                    code: ErrorCode::FileNotFound,
+                    ..
+                }) => {
+                    return Err(anyhow::anyhow!(
+                        "{} does not serve a listing, set `listing = true` under [tftpd] on the server",
+                        server
+                    ));
+                }
+                result => result?,
+            };
+            log::info!(
+                "Mirror complete: {} downloaded, {} already up to date",
+                summary.downloaded.len(),
+                summary.skipped.len()
+            );
+        }
+
+        TftpcAction::Discover { timeout } => {
+            log::info!("Browsing for TFTP servers (_tftp._udp.local)...");
+            let servers = Client::discover(Duration::from_secs(timeout))?;
+
+            if servers.is_empty() {
+                println!("{}", tr("tftpc.no_servers"));
+            } else {
+                println!("{}", tr("tftpc.discovered"));
+                for server in servers {
+                    println!("  {}", server);
+                }
+            }
+        }
+
+        TftpcAction::Ping {
+            server,
+            port,
+            timeout,
+            wait,
+        } => {
+            let cfg = config::ClientConfig::new(server.clone(), port)
+                .with_timeout(Duration::from_secs(timeout.max(1)));
+            let cfg = with_resolver(cfg, config);
+            let client = Client::new(cfg)?;
+            let deadline = Instant::now() + Duration::from_secs(wait.unwrap_or(0));
+
+            loop {
+                match client.ping()? {
+                    PingStatus::Alive { rtt, .. } => {
+                        println!(
+                            "{}",
+                            tr_args(
+                                "tftpc.alive",
+                                &[&format!("{}:{}", server, port), &format!("{:?}", rtt)]
+                            )
+                        );
+                        break;
+                    }
+                    status if Instant::now() >= deadline => {
+                        return Err(anyhow::anyhow!(
+                            "{}:{} is not reachable: {:?}",
+                            server,
+                            port,
+                            status
+                        ));
+                    }
+                    status => {
+                        log::debug!("{}:{} not ready yet: {:?}", server, port, status);
+                        if status == PingStatus::Refused {
+                            // Refusals are immediate, avoid spinning
+                            std::thread::sleep(Duration::from_millis(500));
+                        }
+                    }
+                }
+            }
+        }
+
+        TftpcAction::Soak {
+            server,
+            port,
+            duration,
+            min_size,
+            max_size,
+            max_block_size,
+            max_window,
+            timeout,
+            file,
+            pid,
+            seed,
+            max_failure_rate,
+        } => {
+            let options = soak::SoakOptions {
+                duration: Duration::from_secs(duration),
+                min_size,
+                max_size,
+                max_block_size,
+                max_window,
+                timeout: Duration::from_secs(timeout.max(1)),
+                remote_file: file,
+                pid,
+                seed,
+            };
+
+            log::info!("Soaking {}:{} for {}s", server, port, duration);
+            let report = soak::run(&server, port, &options)?;
+            println!("{}", report);
+
+            if report.failure_rate() * 100.0 > max_failure_rate {
+                return Err(anyhow::anyhow!(
+                    "{} of {} transfers failed",
+                    report.failures,
+                    report.transfers
+                ));
+            }
+        }
+
+        TftpcAction::Matrix {
+            server,
+            remote_file,
+            port,
+            block_sizes,
+            window_sizes,
+            timeouts,
+            retries,
+            upload_size,
+        } => {
+            let options = matrix::MatrixOptions {
+                block_sizes,
+                window_sizes,
+                timeouts: timeouts.into_iter().map(Duration::from_secs).collect(),
+                retries,
+                remote_file,
+                upload_size,
+            };
+
+            let matrix = matrix::run(&server, port, &options)?;
+            println!("{}", matrix);
+
+            if let Some(best) = matrix.best() {
+                println!(
+                    "Fastest: blksize {} windowsize {} timeout {}s",
+                    best.block_size,
+                    best.window_size,
+                    best.timeout.as_secs()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the `NAME=VALUE` options given on the command line to `cfg`
+fn with_custom_options(
+    mut cfg: config::ClientConfig,
+    options: &[String],
+) -> Result<config::ClientConfig> {
+    for option in options {
+        let (name, value) = option
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid option '{}', expected NAME=VALUE", option))?;
+        cfg = cfg.with_custom_option(name, value);
     }
+    Ok(cfg)
+}
+
+/// Uses the resolver of the `[tftpc]` section, unless `cfg` has its own
+fn with_resolver(
+    cfg: config::ClientConfig,
+    config: Option<&config::TftpcConfigFile>,
+) -> config::ClientConfig {
+    match config.and_then(|c| c.resolver.clone()) {
+        Some(resolver) if cfg.resolver.is_none() => cfg.with_resolver(resolver),
+        _ => cfg,
+    }
+}
+
+/// Logs the custom options the server acknowledged
+fn log_acknowledged(client: &Client) {
+    for option in client.acknowledged_options() {
+        log::info!(
+            "Server acknowledged option {}={}",
+            option.name,
+            option.value
+        );
+    }
+}
+
+/// Creates the transfer progress bar, showing a byte counter until the size is known
+fn progress_bar() -> ProgressBar {
+    let bar = ProgressBar::no_length();
+    bar.set_style(
+        ProgressStyle::with_template("{spinner} {bytes} ({binary_bytes_per_sec})")
+            .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+    );
+    bar
+}
+
+/// Returns a progress callback that switches the bar to percent/ETA display
+/// once the negotiated transfer size is known
+fn update_progress(bar: ProgressBar) -> impl Fn(u64, Option<u64>) + Send + Sync + 'static {
+    move |transferred, total| {
+        if let Some(total) = total
+            && bar.length() != Some(total)
+        {
+            bar.set_length(total);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "[{bar:40.cyan/blue}] {percent:>3}% {bytes}/{total_bytes} \
+                     ({binary_bytes_per_sec}, ETA {eta})",
+                )
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("=> "),
+            );
+        }
+        bar.set_position(transferred);
+    }
+}
+
+/// Logs the checksum of a transferred file and compares it with the expected value
+fn check_integrity(
+    path: &Path,
+    checksum: Option<DigestAlgorithm>,
+    verify: Option<&str>,
+) -> Result<()> {
+    let algorithm = match (checksum, verify) {
+        (Some(algorithm), _) => algorithm,
+        (None, Some(_)) => DigestAlgorithm::Sha256,
+        (None, None) => return Ok(()),
+    };
+
+    let digest = algorithm.digest_file(path)?;
+    log::info!("{}: {}", algorithm, digest);
+
+    if let Some(expected) = verify
+        && !expected.trim().eq_ignore_ascii_case(&digest)
+    {
+        return Err(anyhow::anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            path.display(),
+            expected.trim(),
+            digest
+        ));
+    }
+
     Ok(())
 }
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// Pattern `enum` is the content of generated uploads, see [`PatternReader`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// Pseudo-random bytes, which defeat compression and deduplication
+    Random,
+    /// Zero bytes
+    Zeros,
+}
+
+impl FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_lowercase().as_str() {
+            "random" => Ok(Pattern::Random),
+            "zeros" | "zero" => Ok(Pattern::Zeros),
+            _ => Err(anyhow::anyhow!(
+                "Unknown pattern '{value}', expected random or zeros"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Random => write!(f, "random"),
+            Pattern::Zeros => write!(f, "zeros"),
+        }
+    }
+}
+
+/// PatternReader `struct` generates `size` bytes of a [`Pattern`], to upload
+/// synthetic data without creating a local file.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::Read;
+/// use xtool::tftp::client::{Pattern, PatternReader};
+///
+/// let mut content = Vec::new();
+/// PatternReader::new(Pattern::Zeros, 1000, 0)
+///     .read_to_end(&mut content)
+///     .unwrap();
+/// assert_eq!(content, vec![0; 1000]);
+/// ```
+pub struct PatternReader {
+    pattern: Pattern,
+    remaining: u64,
+    rng: Rng,
+    /// Random bytes not yet returned, so content does not depend on read sizes
+    pending: u64,
+    pending_len: u8,
+}
+
+impl PatternReader {
+    /// Creates a reader of `size` bytes, `seed` selecting the random content.
+    pub fn new(pattern: Pattern, size: u64, seed: u64) -> Self {
+        Self {
+            pattern,
+            remaining: size,
+            rng: Rng::new(seed),
+            pending: 0,
+            pending_len: 0,
+        }
+    }
+}
+
+impl Read for PatternReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        match self.pattern {
+            Pattern::Random => {
+                for byte in &mut buf[..size] {
+                    if self.pending_len == 0 {
+                        self.pending = self.rng.next();
+                        self.pending_len = 8;
+                    }
+                    *byte = self.pending as u8;
+                    self.pending >>= 8;
+                    self.pending_len -= 1;
+                }
+            }
+            Pattern::Zeros => buf[..size].fill(0),
+        }
+        self.remaining -= size as u64;
+        Ok(size)
+    }
+}
+
+/// Parses a size in bytes with an optional binary `K`, `M`, `G` or `T`
+/// suffix, e.g. `100M`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let digits = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+
+    let (number, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        Some('T') => (&digits[..digits.len() - 1], 40),
+        _ => (digits, 0),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size '{value}', expected e.g. 4096, 512K or 100M"))
+}
+
+/// Small xorshift generator, enough to vary transfer parameters and
+/// generated content reproducibly
+pub(super) struct Rng(u64);
+
+impl Rng {
+    pub(super) fn new(seed: u64) -> Self {
+        // xorshift gets stuck on 0
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a value in `min..=max`
+    pub(super) fn range(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min {
+            return min;
+        }
+        min + self.next() % (max - min + 1)
+    }
+
+    pub(super) fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_is_reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            let value = a.range(512, 8192);
+            assert_eq!(value, b.range(512, 8192));
+            assert!((512..=8192).contains(&value));
+        }
+        assert_eq!(a.range(7, 7), 7);
+    }
+
+    #[test]
+    fn generates_pattern() {
+        let mut content = Vec::new();
+        PatternReader::new(Pattern::Random, 10_001, 7)
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content.len(), 10_001);
+        assert!(content.iter().any(|&b| b != 0));
+
+        // Same content whatever the read sizes
+        let mut reader = PatternReader::new(Pattern::Random, 10_001, 7);
+        let mut again = vec![0; 10_001];
+        for chunk in again.chunks_mut(3) {
+            reader.read_exact(chunk).unwrap();
+        }
+        assert_eq!(content, again);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("100M"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1kb"), Ok(1024));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("99999999T").is_err());
+    }
+}
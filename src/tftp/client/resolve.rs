@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::error::ClientError;
+
+/// Port of DNS-over-TLS servers (RFC 7858)
+const DOT_PORT: u16 = 853;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// ResolverConfig `struct` selects how the client resolves a server given
+/// by host name, for provisioning hosts without a working system resolver.
+///
+/// Names are looked up in `hosts` first, then with the DNS-over-HTTPS or
+/// DNS-over-TLS server if one is set, and with the system resolver
+/// otherwise. Both encrypted servers are given by IP address, since no
+/// resolver is available to find them.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use xtool::tftp::client::ResolverConfig;
+///
+/// let resolver: ResolverConfig = toml::from_str(
+///     r#"
+///     doh = "https://1.1.1.1/dns-query"
+///     hosts = { board1 = "192.168.1.50" }
+///     "#,
+/// )
+/// .unwrap();
+/// let ip = resolver.resolve("board1", Duration::from_secs(1)).unwrap();
+/// assert_eq!(ip.to_string(), "192.168.1.50");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResolverConfig {
+    /// Addresses of host names, checked before any DNS server
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, IpAddr>,
+    /// DNS-over-HTTPS endpoint (RFC 8484), e.g. `https://1.1.1.1/dns-query`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doh: Option<String>,
+    /// DNS-over-TLS server, e.g. `1.1.1.1` or `9.9.9.9:853`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dot: Option<String>,
+    /// Name the certificate of the DNS-over-TLS server is checked against,
+    /// its IP address by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dot_name: Option<String>,
+}
+
+impl ResolverConfig {
+    /// Resolves `host`, an IP address or a host name, waiting up to
+    /// `timeout` for each DNS query.
+    pub fn resolve(&self, host: &str, timeout: Duration) -> Result<IpAddr, ClientError> {
+        if let Ok(ip) = host.parse() {
+            return Ok(ip);
+        }
+        if let Some((_, ip)) = self
+            .hosts
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        {
+            return Ok(*ip);
+        }
+
+        let unresolved = |reason: &dyn std::fmt::Display| {
+            ClientError::InvalidAddress(format!("cannot resolve '{}': {}", host, reason))
+        };
+        let addrs = if let Some(url) = &self.doh {
+            log::debug!("Resolving {} with {}", host, url);
+            lookup(host, |query| doh_query(url, query, timeout))?
+        } else if let Some(server) = &self.dot {
+            log::debug!("Resolving {} with {}", host, server);
+            lookup(host, |query| {
+                dot_query(server, self.dot_name.as_deref(), query, timeout)
+            })?
+        } else {
+            (host, 0)
+                .to_socket_addrs()
+                .map_err(|e| unresolved(&e))?
+                .map(|addr| addr.ip())
+                .collect()
+        };
+
+        addrs
+            .into_iter()
+            .next()
+            .ok_or_else(|| unresolved(&"no address found"))
+    }
+}
+
+/// Looks up the IPv4 then IPv6 addresses of `host`, sending queries with
+/// `exchange` which returns the response.
+fn lookup(
+    host: &str,
+    mut exchange: impl FnMut(&[u8]) -> Result<Vec<u8>, ClientError>,
+) -> Result<Vec<IpAddr>, ClientError> {
+    for qtype in [TYPE_A, TYPE_AAAA] {
+        let response = exchange(&encode_query(host, qtype)?)?;
+        let addrs = parse_response(&response).map_err(|e| {
+            ClientError::InvalidAddress(format!("cannot resolve '{}': {}", host, e))
+        })?;
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn doh_query(url: &str, query: &[u8], timeout: Duration) -> Result<Vec<u8>, ClientError> {
+    let failed = |e: ureq::Error| ClientError::InvalidAddress(format!("{}: {}", url, e));
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(timeout))
+        .build()
+        .into();
+    let mut response = agent
+        .post(url)
+        .header("Content-Type", "application/dns-message")
+        .header("Accept", "application/dns-message")
+        .send(query)
+        .map_err(failed)?;
+    response.body_mut().read_to_vec().map_err(failed)
+}
+
+fn dot_query(
+    server: &str,
+    name: Option<&str>,
+    query: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, ClientError> {
+    let failed =
+        |e: &dyn std::fmt::Display| ClientError::InvalidAddress(format!("{}: {}", server, e));
+    let addr = server
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            server
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DOT_PORT))
+        })
+        .map_err(|e| failed(&e))?;
+    let name = name
+        .map(str::to_string)
+        .unwrap_or_else(|| addr.ip().to_string());
+    let name = rustls::pki_types::ServerName::try_from(name).map_err(|e| failed(&e))?;
+
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connection =
+        rustls::ClientConnection::new(Arc::new(config), name).map_err(|e| failed(&e))?;
+
+    let socket = TcpStream::connect_timeout(&addr, timeout)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    let mut stream = rustls::StreamOwned::new(connection, socket);
+
+    // Messages over TCP are prefixed with their length
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(query);
+    stream.write_all(&message)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+/// Builds a recursive query for the `qtype` records of `host`.
+fn encode_query(host: &str, qtype: u16) -> Result<Vec<u8>, ClientError> {
+    // ID 0 as recommended for DNS-over-HTTPS, recursion desired, one question
+    let mut query = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(ClientError::InvalidAddress(format!(
+                "'{}' is not a valid host name",
+                host
+            )));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Returns the IPv4 and IPv6 addresses answered in `response`, none if the
+/// name does not exist.
+fn parse_response(response: &[u8]) -> Result<Vec<IpAddr>, String> {
+    let u16_at = |pos: usize| {
+        response
+            .get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| "truncated response".to_string())
+    };
+
+    match u16_at(2)? & 0x000f {
+        0 => {}
+        // Name error, the name does not exist
+        3 => return Ok(Vec::new()),
+        rcode => return Err(format!("server failure (rcode {})", rcode)),
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(response, pos)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(response, pos)?;
+        let rtype = u16_at(pos)?;
+        let len = u16_at(pos + 8)? as usize;
+        let data = response
+            .get(pos + 10..pos + 10 + len)
+            .ok_or_else(|| "truncated response".to_string())?;
+        if let Ok(octets) = <[u8; 4]>::try_from(data)
+            && rtype == TYPE_A
+        {
+            addrs.push(IpAddr::V4(Ipv4Addr::from(octets)));
+        } else if let Ok(octets) = <[u8; 16]>::try_from(data)
+            && rtype == TYPE_AAAA
+        {
+            addrs.push(IpAddr::V6(Ipv6Addr::from(octets)));
+        }
+        pos += 10 + len;
+    }
+    Ok(addrs)
+}
+
+/// Returns the position following the, possibly compressed, name at `pos`.
+fn skip_name(message: &[u8], mut pos: usize) -> Result<usize, String> {
+    loop {
+        let len = *message
+            .get(pos)
+            .ok_or_else(|| "truncated response".to_string())? as usize;
+        match len {
+            0 => return Ok(pos + 1),
+            // Pointer to a name elsewhere in the message
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += 1 + len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_query() {
+        let query = encode_query("tftp.lan.", TYPE_A).unwrap();
+        assert_eq!(&query[12..], b"\x04tftp\x03lan\x00\x00\x01\x00\x01");
+        assert!(encode_query("tftp..lan", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn parses_response() {
+        let mut response = encode_query("tftp.lan", TYPE_A).unwrap();
+        // Response with two answers pointing to the question name
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        response.extend_from_slice(b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04");
+        response.extend_from_slice(&[192, 168, 1, 50]);
+        response.extend_from_slice(b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x0e\x10\x00\x02\xc0\x0c");
+
+        let addrs = parse_response(&response).unwrap();
+        assert_eq!(addrs, ["192.168.1.50".parse::<IpAddr>().unwrap()]);
+
+        assert!(parse_response(&response[..20]).is_err());
+
+        // Name error
+        response[3] = 0x83;
+        assert!(parse_response(&response).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resolves_hosts() {
+        let resolver = ResolverConfig {
+            hosts: BTreeMap::from([("Board1".to_string(), "10.0.0.5".parse().unwrap())]),
+            ..Default::default()
+        };
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            resolver.resolve("board1", timeout).unwrap().to_string(),
+            "10.0.0.5"
+        );
+        assert_eq!(resolver.resolve("::1", timeout).unwrap().to_string(), "::1");
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::error::ClientError;
+
+/// Amount of data received between two saves of the resume state
+pub const RESUME_SAVE_INTERVAL: u64 = 1024 * 1024;
+
+/// ResumeState `struct` is the download state persisted next to the partial
+/// file, allowing `xtool tftpc get --resume` to continue an interrupted
+/// transfer with the `offset` extension.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::Path;
+/// use xtool::tftp::client::ResumeState;
+///
+/// let mut state = ResumeState::new("10.0.0.1:69", "dump.bin", Path::new("resume_example.bin"));
+/// state.received = 4096;
+/// state.save().unwrap();
+///
+/// let loaded = ResumeState::load(Path::new("resume_example.bin")).unwrap().unwrap();
+/// assert_eq!(loaded.received, 4096);
+/// loaded.remove();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResumeState {
+    /// Server address the file is downloaded from
+    pub server: String,
+    /// Remote file name
+    pub remote_file: String,
+    /// Local file holding the data received so far
+    pub partial_file: PathBuf,
+    /// Bytes written to the partial file
+    pub received: u64,
+    /// Negotiated block size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_size: Option<u16>,
+    /// Negotiated window size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_size: Option<u16>,
+    /// Negotiated size of the remote file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_size: Option<u64>,
+}
+
+impl ResumeState {
+    /// Creates an empty state for downloading `remote_file` from `server` into `partial_file`.
+    pub fn new(server: &str, remote_file: &str, partial_file: &Path) -> ResumeState {
+        ResumeState {
+            server: server.to_string(),
+            remote_file: remote_file.to_string(),
+            partial_file: partial_file.to_path_buf(),
+            received: 0,
+            block_size: None,
+            window_size: None,
+            transfer_size: None,
+        }
+    }
+
+    /// Returns the sidecar path storing the state of a download into `local_file`.
+    pub fn sidecar_path(local_file: &Path) -> PathBuf {
+        let mut name = local_file.file_name().unwrap_or_default().to_os_string();
+        name.push(".xtool-resume");
+        local_file.with_file_name(name)
+    }
+
+    /// Loads the state saved for `local_file`, if any.
+    pub fn load(local_file: &Path) -> Result<Option<ResumeState>, ClientError> {
+        let path = Self::sidecar_path(local_file);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        toml::from_str(&fs::read_to_string(path)?)
+            .map(Some)
+            .map_err(|e| ClientError::ResumeState(e.to_string()))
+    }
+
+    /// Writes the state to its sidecar file.
+    pub fn save(&self) -> Result<(), ClientError> {
+        let path = Self::sidecar_path(&self.partial_file);
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        // Write then rename so an interruption never leaves a truncated state
+        let content = toml::to_string(self).map_err(|e| ClientError::ResumeState(e.to_string()))?;
+        fs::write(&tmp_path, content)?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Deletes the sidecar file once the download is complete.
+    pub fn remove(&self) {
+        let path = Self::sidecar_path(&self.partial_file);
+        if path.exists() && fs::remove_file(&path).is_err() {
+            log::warn!("Could not remove resume state {}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR_NAME: &str = "target/test";
+
+    #[test]
+    fn builds_sidecar_path() {
+        assert_eq!(
+            ResumeState::sidecar_path(Path::new("/tmp/dump.bin")),
+            PathBuf::from("/tmp/dump.bin.xtool-resume")
+        );
+    }
+
+    #[test]
+    fn saves_and_loads_state() {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let local = PathBuf::from(DIR_NAME).join("saves_and_loads_state.bin");
+
+        let mut state = ResumeState::new("127.0.0.1:69", "dump.bin", &local);
+        state.received = 1024;
+        state.block_size = Some(1468);
+        state.transfer_size = Some(4096);
+        state.save().unwrap();
+
+        assert_eq!(ResumeState::load(&local).unwrap(), Some(state.clone()));
+
+        state.remove();
+        assert_eq!(ResumeState::load(&local).unwrap(), None);
+    }
+}
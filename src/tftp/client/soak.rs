@@ -0,0 +1,276 @@
+//! Soak testing of a TFTP server
+//!
+//! Loops transfers with randomized file sizes, block sizes and window sizes
+//! for a fixed duration, counting failures and sampling the descriptor count
+//! and resident memory of a process to reveal leaks.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::Client;
+use super::config::ClientConfig;
+use super::pattern::Rng;
+
+/// Parameters of a soak run
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    /// How long to keep starting transfers
+    pub duration: Duration,
+    /// Smallest uploaded file size in bytes
+    pub min_size: u64,
+    /// Largest uploaded file size in bytes
+    pub max_size: u64,
+    /// Largest block size to pick, at least 512
+    pub max_block_size: u16,
+    /// Largest window size to pick, at least 1
+    pub max_window: u16,
+    /// Timeout of each transfer packet
+    pub timeout: Duration,
+    /// Download this remote file repeatedly instead of uploading random files
+    pub remote_file: Option<String>,
+    /// Process to watch for leaks, defaults to the current process
+    pub pid: Option<u32>,
+    /// Seed of the random parameters, to reproduce a run
+    pub seed: Option<u64>,
+}
+
+impl Default for SoakOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(60),
+            min_size: 0,
+            max_size: 1024 * 1024,
+            max_block_size: 8192,
+            max_window: 8,
+            timeout: Duration::from_secs(2),
+            remote_file: None,
+            pid: None,
+            seed: None,
+        }
+    }
+}
+
+/// Descriptor count and resident memory of a process
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resources {
+    pub fds: usize,
+    pub rss_kib: u64,
+}
+
+impl Resources {
+    /// Samples `pid`, only supported on Linux
+    pub fn sample(pid: u32) -> Option<Self> {
+        let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+        let fds = fs::read_dir(proc_dir.join("fd")).ok()?.count();
+        let status = fs::read_to_string(proc_dir.join("status")).ok()?;
+        let rss_kib = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())?;
+        Some(Self { fds, rss_kib })
+    }
+}
+
+/// Results of a soak run
+#[derive(Debug, Clone, Default)]
+pub struct SoakReport {
+    pub seed: u64,
+    pub transfers: u64,
+    pub failures: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Resources of the watched process before the first and after the last transfer
+    pub resources: Option<(Resources, Resources)>,
+}
+
+impl SoakReport {
+    /// Share of failed transfers, 0 when nothing ran
+    pub fn failure_rate(&self) -> f64 {
+        if self.transfers == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.transfers as f64
+        }
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Seed:       {}", self.seed)?;
+        writeln!(f, "Duration:   {:.1?}", self.elapsed)?;
+        writeln!(f, "Transfers:  {}", self.transfers)?;
+        writeln!(
+            f,
+            "Failures:   {} ({:.2}%)",
+            self.failures,
+            self.failure_rate() * 100.0
+        )?;
+        write!(f, "Bytes:      {}", self.bytes)?;
+        if let Some((start, end)) = self.resources {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Open fds:   {} -> {} ({:+})",
+                start.fds,
+                end.fds,
+                end.fds as i64 - start.fds as i64
+            )?;
+            write!(
+                f,
+                "RSS:        {} KiB -> {} KiB ({:+} KiB)",
+                start.rss_kib,
+                end.rss_kib,
+                end.rss_kib as i64 - start.rss_kib as i64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs transfers against `server:port` until `options.duration` has elapsed
+///
+/// Uploads are named `xtool-soak-<pid>-<n>.bin` and downloaded again to
+/// compare their content, so the server must accept writes. Set
+/// `remote_file` to soak a read-only server with downloads only.
+pub fn run(server: &str, port: u16, options: &SoakOptions) -> anyhow::Result<SoakReport> {
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    let mut rng = Rng::new(seed);
+    let pid = options.pid.unwrap_or_else(std::process::id);
+
+    let work_dir = std::env::temp_dir().join(format!("xtool-soak-{}", std::process::id()));
+    fs::create_dir_all(&work_dir)?;
+
+    let mut report = SoakReport {
+        seed,
+        ..Default::default()
+    };
+    let start_resources = Resources::sample(pid);
+    let mut reference: Option<Vec<u8>> = None;
+    let start = Instant::now();
+
+    while start.elapsed() < options.duration {
+        let block_size = rng.range(512, options.max_block_size.max(512) as u64) as u16;
+        let window_size = rng.range(1, options.max_window.max(1) as u64) as u16;
+        let config = ClientConfig::new(server.to_string(), port)
+            .with_block_size(block_size)
+            .with_window_size(window_size)
+            .with_timeout(options.timeout);
+        let client = Client::new(config)?;
+
+        let n = report.transfers;
+        let result = match &options.remote_file {
+            Some(remote_file) => download(&client, remote_file, &work_dir, &mut reference),
+            None => {
+                let size = rng.range(options.min_size, options.max_size.max(options.min_size));
+                let remote_file = format!("xtool-soak-{}-{}.bin", std::process::id(), n);
+                round_trip(&client, &remote_file, &work_dir, &mut rng, size)
+            }
+        };
+
+        report.transfers += 1;
+        match result {
+            Ok(bytes) => report.bytes += bytes,
+            Err(e) => {
+                report.failures += 1;
+                log::warn!(
+                    "Transfer {} failed (blksize {}, windowsize {}): {}",
+                    n,
+                    block_size,
+                    window_size,
+                    e
+                );
+            }
+        }
+
+        if report.transfers.is_multiple_of(10) {
+            log::info!(
+                "{} transfers, {} failures, {:?}",
+                report.transfers,
+                report.failures,
+                Resources::sample(pid)
+            );
+        }
+    }
+
+    report.elapsed = start.elapsed();
+    report.resources = start_resources.zip(Resources::sample(pid));
+    let _ = fs::remove_dir_all(&work_dir);
+    Ok(report)
+}
+
+/// Uploads `size` random bytes and downloads them back, returning the bytes moved
+fn round_trip(
+    client: &Client,
+    remote_file: &str,
+    work_dir: &Path,
+    rng: &mut Rng,
+    size: u64,
+) -> anyhow::Result<u64> {
+    let upload = work_dir.join("upload.bin");
+    let download = work_dir.join("download.bin");
+
+    let mut content = vec![0; size as usize];
+    rng.fill(&mut content);
+    fs::write(&upload, &content)?;
+
+    client.put(&upload, remote_file)?;
+    client.get(remote_file, &download)?;
+
+    if fs::read(&download)? != content {
+        anyhow::bail!("content of {} differs after round trip", remote_file);
+    }
+    Ok(size * 2)
+}
+
+/// Downloads `remote_file`, comparing it with the content of the first download
+fn download(
+    client: &Client,
+    remote_file: &str,
+    work_dir: &Path,
+    reference: &mut Option<Vec<u8>>,
+) -> anyhow::Result<u64> {
+    let download = work_dir.join("download.bin");
+    client.get(remote_file, &download)?;
+
+    let content = fs::read(&download)?;
+    let size = content.len() as u64;
+    match reference {
+        Some(expected) if *expected != content => {
+            anyhow::bail!("content of {} differs from the first download", remote_file)
+        }
+        Some(_) => {}
+        None => *reference = Some(content),
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn samples_own_process() {
+        let resources = Resources::sample(std::process::id()).unwrap();
+        assert!(resources.fds > 0);
+        assert!(resources.rss_kib > 0);
+    }
+
+    #[test]
+    fn failure_rate() {
+        let report = SoakReport {
+            transfers: 4,
+            failures: 1,
+            ..Default::default()
+        };
+        assert_eq!(report.failure_rate(), 0.25);
+        assert_eq!(SoakReport::default().failure_rate(), 0.0);
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::{Mutex, OnceLock};
+
+/// Bytes of buffers kept by the shared pool at most
+const MAX_POOLED_BYTES: usize = 16 * 1024 * 1024;
+
+/// BufferPool `struct` keeps the buffers of the blocks sent and received for
+/// reuse, so that many transfers at once, as in mass PXE boots, do not
+/// allocate and free a buffer per block. [`Window`](super::Window),
+/// [`ReadAhead`](super::ReadAhead) and sockets share [`BufferPool::shared()`].
+///
+/// Buffers beyond `max_bytes` of capacity in total are freed when given back.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::BufferPool;
+///
+/// let pool = BufferPool::new(1024 * 1024);
+/// let buffer = pool.take(512);
+/// assert_eq!(buffer, vec![0; 512]);
+/// let ptr = buffer.as_ptr();
+/// pool.give(buffer);
+/// let reused = pool.take(100);
+/// assert_eq!(reused.as_ptr(), ptr);
+/// ```
+#[derive(Debug)]
+pub struct BufferPool {
+    max_bytes: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    buffers: Vec<Vec<u8>>,
+    /// Capacity of the buffers kept
+    bytes: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool keeping at most `max_bytes` of buffers.
+    pub fn new(max_bytes: usize) -> BufferPool {
+        BufferPool {
+            max_bytes,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the pool shared by all transfers.
+    pub fn shared() -> &'static BufferPool {
+        static SHARED: OnceLock<BufferPool> = OnceLock::new();
+        SHARED.get_or_init(|| BufferPool::new(MAX_POOLED_BYTES))
+    }
+
+    /// Returns a zeroed buffer of `len` bytes, reusing a buffer given back
+    /// large enough if any.
+    pub fn take(&self, len: usize) -> Vec<u8> {
+        let reused = {
+            let mut state = self.state.lock().unwrap();
+            let found = state
+                .buffers
+                .iter()
+                .rposition(|buffer| buffer.capacity() >= len);
+            found.map(|index| {
+                let buffer = state.buffers.swap_remove(index);
+                state.bytes -= buffer.capacity();
+                buffer
+            })
+        };
+        match reused {
+            Some(mut buffer) => {
+                buffer.clear();
+                buffer.resize(len, 0);
+                buffer
+            }
+            None => vec![0; len],
+        }
+    }
+
+    /// Returns `buffer` to the pool, to be reused by [`BufferPool::take()`].
+    pub fn give(&self, buffer: Vec<u8>) {
+        let capacity = buffer.capacity();
+        if capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.bytes + capacity <= self.max_bytes {
+            state.bytes += capacity;
+            state.buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_buffers() {
+        let pool = BufferPool::new(1024);
+        let small = pool.take(100);
+        let large = pool.take(600);
+        let (small_ptr, large_ptr) = (small.as_ptr(), large.as_ptr());
+        pool.give(small);
+        pool.give(large);
+
+        // Buffers too small are skipped, content is zeroed
+        let mut buffer = pool.take(500);
+        assert_eq!(buffer.as_ptr(), large_ptr);
+        assert_eq!(buffer.len(), 500);
+        buffer.fill(7);
+        pool.give(buffer);
+        assert_eq!(pool.take(50), vec![0; 50]);
+        let buffer = pool.take(50);
+        assert_eq!(buffer.as_ptr(), small_ptr);
+
+        // Buffers beyond the limit are freed
+        pool.give(vec![0; 800]);
+        pool.give(vec![0; 800]);
+        assert_eq!(pool.state.lock().unwrap().buffers.len(), 1);
+    }
+}
@@ -1,4 +1,7 @@
-/// Allows conversions between byte arrays and other types.
+use std::io::{self, Read, Write};
+
+/// Allows conversions between byte arrays and other types, and of files to
+/// and from the netascii mode of TFTP.
 ///
 /// # Example
 ///
@@ -25,6 +28,7 @@ impl Convert {
 
     /// Converts a zero-terminated [`u8`] slice to a [`String`], and returns the
     /// size of the [`String`]. Useful for TFTP packet conversions.
+    #[allow(dead_code)]
     pub fn to_string(buf: &[u8], start: usize) -> anyhow::Result<(String, usize)> {
         match buf[start..].iter().position(|&b| b == 0x00) {
             Some(index) => Ok((
@@ -34,6 +38,160 @@ impl Convert {
             None => Err(anyhow::anyhow!("Invalid string")),
         }
     }
+
+    /// Returns a reader converting the content of `reader` to netascii, as
+    /// defined by RFC 764: line feeds become CR LF and carriage returns
+    /// CR NUL.
+    pub fn to_netascii<R: Read>(reader: R) -> NetasciiReader<R> {
+        NetasciiReader {
+            reader,
+            raw: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Returns a writer converting netascii written to it back to local line
+    /// endings in `writer`, the reverse of [`Convert::to_netascii()`].
+    pub fn from_netascii<W: Write>(writer: W) -> NetasciiWriter<W> {
+        NetasciiWriter {
+            writer,
+            cr: false,
+            written: 0,
+        }
+    }
+
+    /// Returns the size of the content of `reader` once converted to
+    /// netascii.
+    pub fn netascii_len(mut reader: impl Read) -> io::Result<u64> {
+        let mut buf = [0; 8192];
+        let mut len = 0;
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => return Ok(len),
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            let expanded = buf[..read]
+                .iter()
+                .filter(|&&b| b == b'\n' || b == b'\r')
+                .count();
+            len += (read + expanded) as u64;
+        }
+    }
+}
+
+/// NetasciiReader `struct` reads the content of a reader converted to
+/// netascii. See [`Convert::to_netascii()`].
+pub struct NetasciiReader<R> {
+    reader: R,
+    /// Bytes read from `reader`, before conversion
+    raw: Vec<u8>,
+    /// Second byte of a converted line ending that did not fit in the buffer
+    pending: Option<u8>,
+}
+
+impl<R: Read> Read for NetasciiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut len = 0;
+        if let Some(byte) = self.pending.take() {
+            buf[0] = byte;
+            len = 1;
+            if buf.len() == 1 {
+                return Ok(len);
+            }
+        }
+
+        // Every byte read may take two in the buffer
+        self.raw.resize(((buf.len() - len) / 2).max(1), 0);
+        let read = self.reader.read(&mut self.raw)?;
+        for &byte in &self.raw[..read] {
+            let (first, second) = match byte {
+                b'\n' => (b'\r', Some(b'\n')),
+                b'\r' => (b'\r', Some(0)),
+                byte => (byte, None),
+            };
+            buf[len] = first;
+            len += 1;
+            if let Some(second) = second {
+                if len < buf.len() {
+                    buf[len] = second;
+                    len += 1;
+                } else {
+                    self.pending = Some(second);
+                }
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// NetasciiWriter `struct` writes netascii to a writer with local line
+/// endings. See [`Convert::from_netascii()`].
+pub struct NetasciiWriter<W> {
+    writer: W,
+    /// Set if the last byte written was a carriage return, its meaning
+    /// depending on the next byte
+    cr: bool,
+    /// Bytes written to `writer`
+    written: u64,
+}
+
+impl<W: Write> NetasciiWriter<W> {
+    /// Writes a carriage return ending the netascii written so far, which is
+    /// kept until the next byte otherwise. Returns the bytes written to the
+    /// underlying writer.
+    pub fn end(&mut self) -> io::Result<u64> {
+        if self.cr {
+            self.cr = false;
+            self.writer.write_all(b"\r")?;
+            self.written += 1;
+        }
+        Ok(self.written)
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+impl<W: Write> Write for NetasciiWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut converted = Vec::with_capacity(buf.len() + 1);
+        for &byte in buf {
+            if self.cr {
+                self.cr = false;
+                match byte {
+                    b'\n' => {
+                        converted.push(b'\n');
+                        continue;
+                    }
+                    0 => {
+                        converted.push(b'\r');
+                        continue;
+                    }
+                    // Not netascii, kept as received
+                    _ => converted.push(b'\r'),
+                }
+            }
+            if byte == b'\r' {
+                self.cr = true;
+            } else {
+                converted.push(byte);
+            }
+        }
+        self.writer.write_all(&converted)?;
+        self.written += converted.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
 }
 
 #[cfg(test)]
@@ -82,4 +240,135 @@ mod tests {
         assert_eq!(result, "world");
         assert_eq!(index, 11);
     }
+
+    #[test]
+    fn converts_to_netascii() {
+        let content: &[u8] = b"line\nend\r\ncr\r";
+        let netascii: &[u8] = b"line\r\nend\r\0\r\ncr\r\0";
+
+        let mut converted = Vec::new();
+        Convert::to_netascii(content)
+            .read_to_end(&mut converted)
+            .unwrap();
+        assert_eq!(converted, netascii);
+        assert_eq!(
+            Convert::netascii_len(content).unwrap(),
+            netascii.len() as u64
+        );
+
+        // Line endings split across reads
+        let mut reader = Convert::to_netascii(content);
+        let mut converted = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                read => converted.extend_from_slice(&buf[..read]),
+            }
+        }
+        assert_eq!(converted, netascii);
+    }
+
+    #[test]
+    fn converts_from_netascii() {
+        let mut writer = Convert::from_netascii(Vec::new());
+        // Line endings split across writes
+        for chunk in [&b"line\r"[..], b"\nend\r", b"\0\r\ncr\r", b"\0bare\rx\r"] {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.end().unwrap(), 20);
+        assert_eq!(writer.get_mut(), b"line\nend\r\ncr\rbare\rx\r");
+    }
+
+    /// Small xorshift generator for the property tests below
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        /// Content rich in line endings and NUL bytes
+        fn content(&mut self) -> Vec<u8> {
+            let len = self.below(300);
+            (0..len)
+                .map(|_| match self.below(8) {
+                    0 => b'\n',
+                    1 => b'\r',
+                    2 => 0,
+                    _ => self.next() as u8,
+                })
+                .collect()
+        }
+    }
+
+    /// Reader returning at most a random number of bytes per read, as
+    /// sockets and files may
+    struct ShortReads<'a> {
+        content: &'a [u8],
+        rng: Rng,
+    }
+
+    impl Read for ShortReads<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.content.len()).min(self.rng.below(7) + 1);
+            buf[..len].copy_from_slice(&self.content[..len]);
+            self.content = &self.content[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn netascii_round_trips_across_chunk_boundaries() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let content = rng.content();
+
+            // Encoded in reads of random sizes from short reads
+            let mut reader = Convert::to_netascii(ShortReads {
+                content: &content,
+                rng: Rng(rng.next() | 1),
+            });
+            let mut netascii = Vec::new();
+            let mut buf = [0; 16];
+            loop {
+                let len = rng.below(buf.len()) + 1;
+                match reader.read(&mut buf[..len]).unwrap() {
+                    0 => break,
+                    read => netascii.extend_from_slice(&buf[..read]),
+                }
+            }
+            assert_eq!(
+                Convert::netascii_len(content.as_slice()).unwrap(),
+                netascii.len() as u64
+            );
+
+            // Every CR is followed by LF or NUL, every LF follows a CR
+            for (index, &byte) in netascii.iter().enumerate() {
+                match byte {
+                    b'\r' => assert!(matches!(netascii.get(index + 1), Some(b'\n' | 0))),
+                    b'\n' => assert_eq!(netascii[index - 1], b'\r'),
+                    _ => {}
+                }
+            }
+
+            // Decoded in writes of random sizes
+            let mut writer = Convert::from_netascii(Vec::new());
+            let mut rest = netascii.as_slice();
+            while !rest.is_empty() {
+                let len = (rng.below(7) + 1).min(rest.len());
+                writer.write_all(&rest[..len]).unwrap();
+                rest = &rest[len..];
+            }
+            assert_eq!(writer.end().unwrap(), content.len() as u64);
+            assert_eq!(*writer.get_mut(), content);
+        }
+    }
 }
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Packet, Socket};
+
+/// Transfers dallying at once, the oldest stop dallying beyond it
+const MAX_DALLYING: usize = 64;
+/// How often dallying sockets are checked for retransmissions
+const DALLY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static DALLY: OnceLock<Mutex<Sender<Dallying>>> = OnceLock::new();
+/// Transfers handed to [`dally`] and not done dallying yet
+static PENDING: Mutex<usize> = Mutex::new(0);
+static DONE: Condvar = Condvar::new();
+
+struct Dallying {
+    socket: Box<dyn Socket>,
+    block_size: u16,
+    deadline: Instant,
+}
+
+/// Keeps acknowledging retransmissions of the last block received on
+/// `socket` for `timeout`, as per RFC 1350 section 6, so that the sender does
+/// not fail the transfer when the final acknowledgement was lost.
+///
+/// `socket` must only receive from the sender. All dallying sockets are
+/// served by a single background thread, started on first use.
+pub fn dally(mut socket: impl Socket, block_size: u16, timeout: Duration) {
+    if socket.set_nonblocking(true).is_err() {
+        return;
+    }
+    let dallying = Dallying {
+        socket: Box::new(socket),
+        block_size,
+        deadline: Instant::now() + timeout,
+    };
+    let sender = DALLY.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || run(receiver));
+        Mutex::new(sender)
+    });
+    *PENDING.lock().unwrap() += 1;
+    if sender.lock().unwrap().send(dallying).is_err() {
+        finished(1);
+    }
+}
+
+/// Blocks until every socket handed to [`dally`] is done dallying, so that a
+/// process about to exit still acknowledges the retransmissions.
+pub fn wait_for_dally() {
+    let mut pending = PENDING.lock().unwrap();
+    while *pending > 0 {
+        pending = DONE.wait(pending).unwrap();
+    }
+}
+
+fn finished(count: usize) {
+    if count > 0 {
+        *PENDING.lock().unwrap() -= count;
+        DONE.notify_all();
+    }
+}
+
+fn run(receiver: Receiver<Dallying>) {
+    let mut dallying = VecDeque::new();
+    loop {
+        // Sleep until there is something to dally on
+        let next = if dallying.is_empty() {
+            receiver.recv().ok()
+        } else {
+            receiver.recv_timeout(DALLY_POLL_INTERVAL).ok()
+        };
+        for transfer in next.into_iter().chain(receiver.try_iter()) {
+            if dallying.len() == MAX_DALLYING {
+                dallying.pop_front();
+                finished(1);
+            }
+            dallying.push_back(transfer);
+        }
+        let count = dallying.len();
+        dallying.retain(Dallying::poll);
+        finished(count - dallying.len());
+    }
+}
+
+impl Dallying {
+    /// Acknowledges the retransmissions received so far, returning `false`
+    /// once done dallying.
+    fn poll(&self) -> bool {
+        if Instant::now() >= self.deadline {
+            return false;
+        }
+        loop {
+            match self.socket.recv_with_size(self.block_size as usize) {
+                Ok(Packet::Data { block_num, .. }) => {
+                    log::debug!("  Acknowledging retransmitted block {block_num}");
+                    if self.socket.send(&Packet::Ack(block_num)).is_err() {
+                        return false;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    // Anything but an empty socket means the sender is gone
+                    return err
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|err| err.kind() == ErrorKind::WouldBlock);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn acknowledges_retransmissions() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.connect(sender.local_addr().unwrap()).unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        sender
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let start = Instant::now();
+        dally(receiver, 512, Duration::from_millis(500));
+        let data = Packet::Data {
+            block_num: 7,
+            data: vec![1; 10],
+        };
+        Socket::send(&sender, &data).unwrap();
+        assert!(matches!(Socket::recv(&sender).unwrap(), Packet::Ack(7)));
+
+        // Retransmissions past the timeout are ignored
+        thread::sleep(Duration::from_millis(600));
+        Socket::send(&sender, &data).unwrap();
+        sender
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(Socket::recv(&sender).is_err());
+
+        wait_for_dally();
+        assert!(start.elapsed() >= Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use md5::Digest as _;
+
+/// Digest `trait` is implemented by the checksum algorithms used to verify
+/// transferred files. Data is fed incrementally with [`Digest::update()`] and
+/// the final checksum is returned by [`Digest::finalize()`].
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::DigestAlgorithm;
+///
+/// let mut digest = DigestAlgorithm::Crc32.digest();
+/// digest.update(b"123456789");
+/// assert_eq!(digest.finalize_hex(), "cbf43926");
+/// ```
+pub trait Digest: Send {
+    /// Feeds `data` into the checksum.
+    fn update(&mut self, data: &[u8]);
+    /// Consumes the digest and returns the checksum bytes.
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+
+    /// Consumes the digest and returns the checksum as a lowercase hex string.
+    fn finalize_hex(self: Box<Self>) -> String {
+        to_hex(&self.finalize())
+    }
+}
+
+/// DigestAlgorithm `enum` represents the checksum algorithms that can be
+/// selected for integrity checks. CRC32 is cheap enough for constrained
+/// devices while SHA-256 and BLAKE3 are suitable for strong verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestAlgorithm {
+    /// CRC-32 (IEEE)
+    Crc32,
+    /// MD5
+    Md5,
+    /// SHA-256
+    Sha256,
+    /// BLAKE3 with 256 bit output
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// All the supported algorithms
+    pub const ALL: [DigestAlgorithm; 4] = [
+        DigestAlgorithm::Crc32,
+        DigestAlgorithm::Md5,
+        DigestAlgorithm::Sha256,
+        DigestAlgorithm::Blake3,
+    ];
+
+    /// Converts a [`DigestAlgorithm`] to a [`str`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Crc32 => "crc32",
+            DigestAlgorithm::Md5 => "md5",
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Creates a new [`Digest`] for this algorithm.
+    pub fn digest(&self) -> Box<dyn Digest> {
+        match self {
+            DigestAlgorithm::Crc32 => Box::new(Crc32Digest(crc32fast::Hasher::new())),
+            DigestAlgorithm::Md5 => Box::new(Md5Digest(md5::Md5::new())),
+            DigestAlgorithm::Sha256 => Box::new(Sha256Digest(sha2::Sha256::new())),
+            DigestAlgorithm::Blake3 => Box::new(Blake3Digest(blake3::Hasher::new())),
+        }
+    }
+
+    /// Computes the checksum of the file at `path` as a lowercase hex string.
+    pub fn digest_file(&self, path: &Path) -> anyhow::Result<String> {
+        self.digest_reader(File::open(path)?)
+    }
+
+    /// Computes the checksum of everything read from `reader` as a lowercase hex string.
+    pub fn digest_reader(&self, mut reader: impl Read) -> anyhow::Result<String> {
+        let mut digest = self.digest();
+        let mut buf = vec![0; 64 * 1024];
+
+        loop {
+            let size = reader.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+            digest.update(&buf[..size]);
+        }
+
+        Ok(digest.finalize_hex())
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = anyhow::Error;
+
+    /// Converts a [`str`] to a [`DigestAlgorithm`].
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value.to_lowercase().replace('-', "").as_str() {
+            "crc32" => Ok(DigestAlgorithm::Crc32),
+            "md5" => Ok(DigestAlgorithm::Md5),
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            _ => Err(anyhow::anyhow!("Unknown checksum algorithm '{value}'")),
+        }
+    }
+}
+
+struct Crc32Digest(crc32fast::Hasher);
+
+impl Digest for Crc32Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_be_bytes().to_vec()
+    }
+}
+
+struct Md5Digest(md5::Md5);
+
+impl Digest for Md5Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+struct Sha256Digest(sha2::Sha256);
+
+impl Digest for Sha256Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+struct Blake3Digest(blake3::Hasher);
+
+impl Digest for Blake3Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().as_bytes().to_vec()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_of(algorithm: DigestAlgorithm, data: &[u8]) -> String {
+        let mut digest = algorithm.digest();
+        digest.update(data);
+        digest.finalize_hex()
+    }
+
+    #[test]
+    fn computes_known_checksums() {
+        assert_eq!(hex_of(DigestAlgorithm::Crc32, b"abc"), "352441c2");
+        assert_eq!(
+            hex_of(DigestAlgorithm::Md5, b"abc"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            hex_of(DigestAlgorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex_of(DigestAlgorithm::Blake3, b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn computes_checksum_incrementally() {
+        let mut digest = DigestAlgorithm::Sha256.digest();
+        digest.update(b"a");
+        digest.update(b"bc");
+        assert_eq!(
+            digest.finalize_hex(),
+            hex_of(DigestAlgorithm::Sha256, b"abc")
+        );
+    }
+
+    #[test]
+    fn parses_algorithm_names() {
+        assert_eq!(
+            "SHA-256".parse::<DigestAlgorithm>().unwrap(),
+            DigestAlgorithm::Sha256
+        );
+        assert_eq!(
+            "blake3".parse::<DigestAlgorithm>().unwrap(),
+            DigestAlgorithm::Blake3
+        );
+        assert!("sha1".parse::<DigestAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::TftpTransport;
+#[cfg(feature = "dtls")]
+use super::dtls_transport::DtlsTransport;
+
+/// Largest block size whose data packets fit in a DTLS record, of at most
+/// 16384 bytes of content
+pub const MAX_DTLS_BLOCK_SIZE: u16 = 16380;
+/// Size of the datagrams of DTLS handshakes
+#[cfg(feature = "dtls")]
+pub(crate) const DTLS_MTU: u32 = 1400;
+
+/// Binds the transport of a transfer, see [`dtls_transport()`]
+pub type DtlsBindFn = dyn Fn(SocketAddr) -> io::Result<Box<dyn TftpTransport>> + Send + Sync;
+
+/// DtlsConfig `struct` holds the certificates of one end of DTLS sessions,
+/// encrypting the transfers between xtool clients and servers on untrusted
+/// networks. Using DTLS needs xtool built with the `dtls` feature.
+///
+/// Servers present `certificate`, and only accept clients presenting a
+/// certificate signed by `ca` if set. Clients check the certificate of the
+/// server against `ca`, the authorities of the system by default, and
+/// present `certificate` if set.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpc.get.dtls]
+/// ca = "/etc/xtool/lab-ca.pem"
+/// certificate = "/etc/xtool/bench-12.pem"
+/// private_key = "/etc/xtool/bench-12.key"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DtlsConfig {
+    /// PEM certificate chain presented to the other end
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<PathBuf>,
+    /// PEM private key of `certificate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<PathBuf>,
+    /// PEM certificates of the authorities trusted to sign the certificate of
+    /// the other end
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca: Option<PathBuf>,
+}
+
+/// Returns a function binding [`DtlsTransport`]s, whose sessions check that
+/// the certificate of the server is valid for `server_name`.
+#[cfg(feature = "dtls")]
+pub fn dtls_transport(config: &DtlsConfig, server_name: &str) -> io::Result<Box<DtlsBindFn>> {
+    use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslOptions};
+
+    let mut builder = SslConnector::builder(SslMethod::dtls()).map_err(io::Error::other)?;
+    builder.set_options(SslOptions::NO_QUERY_MTU);
+    if let Some(ca) = &config.ca {
+        builder.set_ca_file(ca).map_err(io::Error::other)?;
+    }
+    if let Some(certificate) = &config.certificate {
+        let private_key = config.private_key.as_ref().unwrap_or(certificate);
+        builder
+            .set_certificate_chain_file(certificate)
+            .and_then(|_| builder.set_private_key_file(private_key, SslFiletype::PEM))
+            .and_then(|_| builder.check_private_key())
+            .map_err(io::Error::other)?;
+    }
+    let connector = builder.build();
+    let server_name = server_name.to_string();
+    Ok(Box::new(move |addr| {
+        Ok(Box::new(DtlsTransport::new(
+            std::net::UdpSocket::bind(addr)?,
+            connector.clone(),
+            &server_name,
+        )))
+    }))
+}
+
+#[cfg(not(feature = "dtls"))]
+pub fn dtls_transport(_config: &DtlsConfig, _server_name: &str) -> io::Result<Box<DtlsBindFn>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xtool was built without the dtls feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_certificates() {
+        let config: DtlsConfig = toml::from_str(
+            r#"
+            ca = "/etc/xtool/lab-ca.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.ca, Some(PathBuf::from("/etc/xtool/lab-ca.pem")));
+        assert_eq!(config.certificate, None);
+        #[cfg(not(feature = "dtls"))]
+        assert!(dtls_transport(&config, "10.0.0.1").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use openssl::ssl::{self, SslConnector, SslStream};
+
+use super::TftpTransport;
+use super::dtls::DTLS_MTU;
+
+/// DtlsTransport `struct` is a [`TftpTransport`] encrypting datagrams
+/// with DTLS over a [`UdpSocket`]. The session is established with the
+/// remote of the first datagram sent, the request of the transfer, and
+/// datagrams are only exchanged with it from then on.
+pub struct DtlsTransport {
+    socket: UdpSocket,
+    connector: SslConnector,
+    /// Name the certificate of the server must be valid for
+    server_name: String,
+    session: Mutex<Option<(SslStream<Datagrams>, SocketAddr)>>,
+}
+
+/// Datagrams of a connected [`UdpSocket`], one per read or write
+struct Datagrams(UdpSocket);
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl DtlsTransport {
+    /// Creates a transport over `socket`, whose session is established by
+    /// `connector`.
+    pub fn new(socket: UdpSocket, connector: SslConnector, server_name: &str) -> DtlsTransport {
+        DtlsTransport {
+            socket,
+            connector,
+            server_name: server_name.to_string(),
+            session: Mutex::new(None),
+        }
+    }
+
+    fn handshake(&self, peer: SocketAddr) -> io::Result<SslStream<Datagrams>> {
+        self.socket.connect(peer)?;
+        let mut ssl = self
+            .connector
+            .configure()
+            .and_then(|config| config.into_ssl(&self.server_name))
+            .map_err(io::Error::other)?;
+        ssl.set_mtu(DTLS_MTU).map_err(io::Error::other)?;
+        let mut stream =
+            SslStream::new(ssl, Datagrams(self.socket.try_clone()?)).map_err(io::Error::other)?;
+        stream.connect().map_err(|err| {
+            let err = io_error(err);
+            io::Error::new(
+                err.kind(),
+                format!("DTLS handshake with {peer} failed: {err}"),
+            )
+        })?;
+        log::debug!("DTLS session established with {peer}");
+        Ok(stream)
+    }
+}
+
+/// Returns the IO error behind `err`, timeouts included.
+fn io_error(err: ssl::Error) -> io::Error {
+    if err.code() == ssl::ErrorCode::ZERO_RETURN {
+        return io::Error::new(ErrorKind::ConnectionAborted, "DTLS session closed");
+    }
+    match err.into_io_error() {
+        Ok(err) => err,
+        Err(err) => io::Error::other(err),
+    }
+}
+
+impl TftpTransport for DtlsTransport {
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize> {
+        let mut session = self.session.lock().unwrap();
+        if session.is_none() {
+            *session = Some((self.handshake(to)?, to));
+        }
+        let Some((stream, peer)) = session.as_mut() else {
+            unreachable!()
+        };
+        if to != *peer {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("DTLS session is with {peer}, not {to}"),
+            ));
+        }
+        stream.ssl_write(datagram).map_err(io_error)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut session = self.session.lock().unwrap();
+        let Some((stream, peer)) = session.as_mut() else {
+            return Err(io::Error::from(ErrorKind::NotConnected));
+        };
+        match stream.ssl_read(buf) {
+            Ok(0) => Err(io::Error::new(
+                ErrorKind::ConnectionAborted,
+                "DTLS session closed",
+            )),
+            Ok(amt) => Ok((amt, *peer)),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+
+    fn peer(&self) -> io::Result<SocketAddr> {
+        match self.session.lock().unwrap().as_ref() {
+            Some((_, peer)) => Ok(*peer),
+            None => Err(io::Error::from(ErrorKind::NotConnected)),
+        }
+    }
+
+    fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+        self.socket.connect(peer)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_write_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+}
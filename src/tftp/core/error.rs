@@ -0,0 +1,66 @@
+use std::fmt;
+
+use super::ErrorCode;
+
+/// PacketError `enum` represents the reasons a datagram is not a valid TFTP
+/// packet, returned by [`Packet::deserialize()`](super::Packet::deserialize),
+/// so that servers answer with the right [`ErrorCode`] and fuzzers classify
+/// the inputs they find.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::{ErrorCode, Packet, PacketError};
+///
+/// let err = Packet::deserialize(b"\x00\x01boot.img\0octet\0blksize\0big\0").unwrap_err();
+/// assert_eq!(
+///     err,
+///     PacketError::InvalidOption { name: "blksize".to_string(), value: "big".to_string() }
+/// );
+/// assert_eq!(err.error_code(), ErrorCode::RefusedOption);
+/// assert_eq!(Packet::deserialize(&[0x00, 0x09]).unwrap_err(), PacketError::BadOpcode(9));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    /// The packet ends before one of its fields
+    Truncated,
+    /// The opcode is not one of TFTP
+    BadOpcode(u16),
+    /// The error code of an error packet is not one of TFTP
+    BadErrorCode(u16),
+    /// A string field is not terminated by a NUL byte
+    MissingNul,
+    /// A string field is not valid UTF-8
+    InvalidUtf8,
+    /// The value of a known option cannot be parsed
+    InvalidOption { name: String, value: String },
+}
+
+impl PacketError {
+    /// Returns the code of the error packet answering the malformed packet,
+    /// option negotiation errors being refused options as defined by
+    /// RFC 2347.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            PacketError::InvalidOption { .. } => ErrorCode::RefusedOption,
+            _ => ErrorCode::IllegalOperation,
+        }
+    }
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Truncated => write!(f, "Truncated packet"),
+            PacketError::BadOpcode(opcode) => write!(f, "Invalid opcode {opcode}"),
+            PacketError::BadErrorCode(code) => write!(f, "Invalid error code {code}"),
+            PacketError::MissingNul => write!(f, "Unterminated string"),
+            PacketError::InvalidUtf8 => write!(f, "Invalid UTF-8 string"),
+            PacketError::InvalidOption { name, value } => {
+                write!(f, "Invalid value {value:?} for option {name}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
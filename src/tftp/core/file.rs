@@ -0,0 +1,63 @@
+use std::fs::File;
+
+/// Reserves `len` bytes of disk space for `file` before any data is written.
+///
+/// On Linux this uses `posix_fallocate` so that a full disk is reported
+/// immediately with `ENOSPC` instead of in the middle of a transfer. On other
+/// platforms, or on filesystems that do not support allocation, the file is
+/// simply extended with [`File::set_len`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::fs::{self, File};
+/// use xtool::tftp::core::preallocate;
+///
+/// let path = std::env::temp_dir().join(format!("preallocate_{}.bin", std::process::id()));
+/// let file = File::create(&path).unwrap();
+/// preallocate(&file, 4096).unwrap();
+/// assert_eq!(file.metadata().unwrap().len(), 4096);
+/// fs::remove_file(path).unwrap();
+/// ```
+pub fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let off_len = libc::off_t::try_from(len)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        // SAFETY: the descriptor is owned by `file` and stays open for the call.
+        match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, off_len) } {
+            0 => return Ok(()),
+            libc::EOPNOTSUPP | libc::EINVAL => {}
+            err => return Err(std::io::Error::from_raw_os_error(err)),
+        }
+    }
+
+    file.set_len(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write};
+
+    const DIR_NAME: &str = "target/test";
+
+    #[test]
+    fn preallocates_and_overwrites_from_start() {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let filename = DIR_NAME.to_string() + "/preallocates_and_overwrites_from_start.bin";
+
+        let mut file = File::create(&filename).unwrap();
+        preallocate(&file, 1024).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 1024);
+
+        file.write_all(b"Hello").unwrap();
+        file.set_len(5).unwrap();
+        drop(file);
+
+        assert_eq!(fs::read(&filename).unwrap(), b"Hello");
+        fs::remove_file(filename).unwrap();
+    }
+}
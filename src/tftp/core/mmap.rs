@@ -0,0 +1,96 @@
+use std::{fs::File, io, ops::Deref};
+
+/// MappedFile `struct` is a read-only memory mapping of a whole [`File`],
+/// from which DATA packets are built without reading the file into buffers.
+///
+/// The file must not be truncated while mapped: reading the missing pages
+/// would kill the process with `SIGBUS`. Only supported on Unix.
+///
+/// # Example
+/// ```rust
+/// use std::fs::{self, File};
+/// use xtool::tftp::core::MappedFile;
+///
+/// fs::write("mapped.txt", b"Hello, world!").unwrap();
+/// let mapping = MappedFile::open(&File::open("mapped.txt").unwrap()).unwrap();
+/// assert_eq!(&mapping[7..], b"world!");
+/// fs::remove_file("mapped.txt").unwrap();
+/// ```
+pub struct MappedFile {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is read-only and unmapped only once dropped
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps the whole content of `file`, hinting the system that it is read
+    /// sequentially.
+    #[cfg(unix)]
+    pub fn open(file: &File) -> io::Result<MappedFile> {
+        use std::os::fd::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        if len == 0 {
+            // Empty mappings are refused by the system
+            return Ok(MappedFile {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+
+        Ok(MappedFile {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(_file: &File) -> io::Result<MappedFile> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory mapping is only supported on Unix",
+        ))
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr.cast(), self.len) };
+        }
+    }
+}
@@ -2,20 +2,74 @@
 //!
 //! This module contains the core components of the TFTP protocol:
 //! - `packet`: Packet serialization and deserialization
+//! - `error`: Reasons datagrams are not valid packets
 //! - `socket`: Socket abstraction layer
+//! - `transport`: Datagram transports, UDP or in memory for tests
+//! - `dtls`: Transfers encrypted with DTLS (`dtls` feature)
 //! - `options`: Protocol options and parameters
 //! - `window`: Windowed transfer management
-//! - `convert`: Data conversion utilities
+//! - `buffers`: Pool of block buffers reused across transfers
+//! - `mmap`: Memory mapping of files sent without copies
+//! - `convert`: Data conversion utilities, netascii included
+//! - `digest`: Checksum algorithms for integrity checks
+//! - `file`: Destination file helpers
+//! - `rate`: Token bucket limiting the bandwidth of transfers
+//! - `dally`: Acknowledgement of retransmissions after a completed transfer
+//! - `session`: Recording and replay of the packets of transfers
+//! - `pcap`: Capture of the packets of transfers to pcap files
 
+mod buffers;
 mod convert;
+mod dally;
+mod digest;
+mod dtls;
+#[cfg(feature = "dtls")]
+mod dtls_transport;
+mod error;
+mod file;
+mod mmap;
 pub mod options;
 mod packet;
+mod pcap;
+mod rate;
+// Replay is only used through the library
+#[allow(dead_code)]
+mod session;
 mod socket;
+// In-memory transports are only used through the library
+#[allow(dead_code)]
+mod transport;
 mod window;
 
 // Public core types
-pub use convert::Convert;
-pub use options::{OptionType, TransferOption};
+pub use buffers::BufferPool;
+#[allow(unused_imports)]
+pub use convert::{Convert, NetasciiReader, NetasciiWriter};
+pub use dally::{dally, wait_for_dally};
+#[allow(unused_imports)]
+pub use digest::{Digest, DigestAlgorithm};
+#[cfg(feature = "dtls")]
+pub(crate) use dtls::DTLS_MTU;
+#[allow(unused_imports)]
+pub use dtls::{DtlsBindFn, DtlsConfig, MAX_DTLS_BLOCK_SIZE, dtls_transport};
+#[cfg(feature = "dtls")]
+#[allow(unused_imports)]
+pub use dtls_transport::DtlsTransport;
+pub use error::PacketError;
+pub use file::preallocate;
+pub use mmap::MappedFile;
+pub use options::{CustomOption, OptionType, TransferOption};
+pub(crate) use packet::Opcode;
 pub use packet::{ErrorCode, Packet};
-pub use socket::{ServerSocket, Socket};
-pub use window::Window;
+#[allow(unused_imports)]
+pub use pcap::PcapWriter;
+pub use rate::RateLimiter;
+#[allow(unused_imports)]
+pub use session::{Event, Flow, RecordingSocket, ReplaySocket, Session, SessionRecorder};
+pub(crate) use socket::MAX_REQUEST_PACKET_SIZE;
+pub use socket::{
+    PeerSocket, ServerSocket, Socket, is_message_too_large, max_block_size, reject_unknown_tid,
+};
+#[allow(unused_imports)]
+pub use transport::{MemoryNetwork, MemoryTransport, TftpTransport};
+pub use window::{MappedWindow, ReadAhead, Window};
@@ -2,12 +2,25 @@ use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
 
+use super::DigestAlgorithm;
+
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 pub const DEFAULT_BLOCK_SIZE: u16 = 512;
 pub const DEFAULT_WINDOW_SIZE: u16 = 1;
 pub const DEFAULT_WINDOW_WAIT: Duration = Duration::from_millis(0);
 pub const DEFAULT_MAX_RETRIES: usize = 6;
+pub const DEFAULT_READ_AHEAD: u16 = 1;
 pub const DEFAULT_ROLLOVER: Rollover = Rollover::Enforce0;
+/// Smallest block size allowed by RFC 2348
+pub const MIN_BLOCK_SIZE: u16 = 8;
+/// Largest block size allowed by RFC 2348
+pub const MAX_BLOCK_SIZE: u16 = 65464;
+/// Largest window size allowed by RFC 7440
+pub const MAX_WINDOW_SIZE: u16 = 65535;
+/// Largest timeout in seconds allowed by RFC 2349
+pub const MAX_TIMEOUT: u64 = 255;
+/// Value of the compress option asking for files compressed with gzip
+pub const COMPRESS_GZIP: u64 = 1;
 
 /// Request type (read or write)
 #[derive(Debug, PartialEq)]
@@ -44,6 +57,14 @@ pub struct OptionsPrivate {
     pub max_retries: usize,
     /// Block counter roll-over policy  (default: Enforce0)
     pub rollover: Rollover,
+    /// Checksum algorithm used to log digests of transferred files (default: None)
+    pub checksum: Option<DigestAlgorithm>,
+    /// Windows read from disk ahead of the one being sent, 0 to disable (default: 1)
+    pub read_ahead: u16,
+    /// Bytes per second each transfer sends at most (default: unlimited)
+    pub rate_limit: Option<u64>,
+    /// Size from which files are memory-mapped instead of read (default: never)
+    pub mmap_threshold: Option<u64>,
 }
 
 impl Default for OptionsPrivate {
@@ -53,6 +74,10 @@ impl Default for OptionsPrivate {
             clean_on_error: true,
             max_retries: DEFAULT_MAX_RETRIES,
             rollover: DEFAULT_ROLLOVER,
+            checksum: None,
+            read_ahead: DEFAULT_READ_AHEAD,
+            rate_limit: None,
+            mmap_threshold: None,
         }
     }
 }
@@ -74,6 +99,8 @@ pub struct OptionsProtocol {
     pub timeout: Duration,
     /// Size of the file to transfer (default: N/A)
     pub transfer_size: Option<u64>,
+    /// Byte offset a read transfer starts from, used to resume downloads (default: 0)
+    pub offset: u64,
 }
 
 impl OptionsProtocol {
@@ -96,9 +123,12 @@ impl OptionsProtocol {
                         // but we use 1-65464 as 1 is useful to speed up some tests
                         log::warn!("  Invalid block size 0. Changed to {DEFAULT_BLOCK_SIZE}.");
                         *value = DEFAULT_BLOCK_SIZE as u64;
-                    } else if 65464 < *value {
-                        log::warn!("  Invalid block size {}. Changed to 65464.", *value);
-                        *value = 65464;
+                    } else if (MAX_BLOCK_SIZE as u64) < *value {
+                        log::warn!(
+                            "  Invalid block size {}. Changed to {MAX_BLOCK_SIZE}.",
+                            *value
+                        );
+                        *value = MAX_BLOCK_SIZE as u64;
                     }
                     opt_common.block_size = *value as u16;
                 }
@@ -114,9 +144,12 @@ impl OptionsProtocol {
                         // RFC 2349 requests timeout to be in range 1-255
                         log::warn!("  Invalid timeout value 0. Changed to 1.");
                         *value = 1;
-                    } else if 255 < *value {
-                        log::warn!("  Invalid timeout value {}. Changed to 255.", *value);
-                        *value = 255;
+                    } else if MAX_TIMEOUT < *value {
+                        log::warn!(
+                            "  Invalid timeout value {}. Changed to {MAX_TIMEOUT}.",
+                            *value
+                        );
+                        *value = MAX_TIMEOUT;
                     }
                     opt_common.timeout = Duration::from_secs(*value);
                 }
@@ -132,15 +165,35 @@ impl OptionsProtocol {
                         // RFC 7440 requests window to be in range 1-65535
                         log::warn!("  Invalid window size 0. Changed to 1.");
                         *value = 1;
-                    } else if 65535 < *value {
-                        log::warn!("  Invalid window size {}. Changed to 65535.", *value);
-                        *value = 65535;
+                    } else if (MAX_WINDOW_SIZE as u64) < *value {
+                        log::warn!(
+                            "  Invalid window size {}. Changed to {MAX_WINDOW_SIZE}.",
+                            *value
+                        );
+                        *value = MAX_WINDOW_SIZE as u64;
                     }
                     opt_common.window_size = *value as u16;
                 }
                 OptionType::WindowWait => {
                     opt_common.window_wait = Duration::from_millis(*value);
                 }
+                OptionType::Offset => match request_type {
+                    RequestType::Read(size) => {
+                        if size < *value {
+                            log::warn!("  Invalid offset {}. Changed to {size}.", *value);
+                            *value = size;
+                        }
+                        opt_common.offset = *value;
+                    }
+                    RequestType::Write => {
+                        log::warn!("  Offset is only supported for reads. Changed to 0.");
+                        *value = 0;
+                    }
+                },
+                // Negotiated by the server along with the multicast group
+                OptionType::Multicast => {}
+                // Negotiated by the server along with the file served
+                OptionType::Compress => {}
             }
         }
 
@@ -156,6 +209,7 @@ impl Default for OptionsProtocol {
             window_wait: DEFAULT_WINDOW_WAIT,
             timeout: DEFAULT_TIMEOUT,
             transfer_size: None,
+            offset: 0,
         }
     }
 }
@@ -186,10 +240,66 @@ pub struct TransferOption {
 impl TransferOption {
     /// Converts a [`TransferOption`] to a [`Vec<u8>`].
     pub fn as_bytes(&self) -> Vec<u8> {
+        // Multicast is requested without a value
+        let value = match self.option {
+            OptionType::Multicast => String::new(),
+            OptionType::Compress if self.value == COMPRESS_GZIP => "gzip".to_string(),
+            _ => self.value.to_string(),
+        };
         [
             self.option.as_str().as_bytes(),
             &[0x00],
-            self.value.to_string().as_bytes(),
+            value.as_bytes(),
+            &[0x00],
+        ]
+        .concat()
+    }
+
+    /// Parses the `value` of an `option` received, the multicast option
+    /// carrying the master client flag after the group address and port.
+    pub fn parse_value(option: OptionType, value: &str) -> anyhow::Result<u64> {
+        match option {
+            OptionType::Multicast => Ok(value
+                .rsplit(',')
+                .next()
+                .and_then(|master| master.parse().ok())
+                .unwrap_or(0)),
+            // Compression formats other than gzip are declined
+            OptionType::Compress => Ok(match value.to_lowercase().as_str() {
+                "gzip" => COMPRESS_GZIP,
+                _ => 0,
+            }),
+            _ => Ok(value.parse()?),
+        }
+    }
+}
+
+/// CustomOption `struct` represents a TFTP option that has no [`OptionType`],
+/// such as a vendor-specific extension. Its name and value are kept verbatim.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::CustomOption;
+///
+/// let option = CustomOption { name: "vendor".to_string(), value: "on".to_string() };
+/// assert_eq!(option.as_bytes(), b"vendor\0on\0".to_vec());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CustomOption {
+    /// Name of the option
+    pub name: String,
+    /// Value of the option
+    pub value: String,
+}
+
+impl CustomOption {
+    /// Converts a [`CustomOption`] to a [`Vec<u8>`].
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [
+            self.name.as_bytes(),
+            &[0x00],
+            self.value.as_bytes(),
             &[0x00],
         ]
         .concat()
@@ -238,9 +348,30 @@ pub enum OptionType {
     WindowSize,
     /// Windowwait option type
     WindowWait,
+    /// Read offset option type (xtool extension)
+    Offset,
+    /// Multicast option type (RFC 2090), without a value in requests. Its
+    /// value is the master client flag in option acknowledgements.
+    Multicast,
+    /// Compressed read option type (xtool extension), its value being
+    /// [`COMPRESS_GZIP`] for `gzip` and 0 for other formats.
+    Compress,
 }
 
 impl OptionType {
+    /// All the options supported, in the order of the RFCs
+    pub const ALL: [OptionType; 9] = [
+        OptionType::BlockSize,
+        OptionType::TransferSize,
+        OptionType::Timeout,
+        OptionType::TimeoutMs,
+        OptionType::WindowSize,
+        OptionType::WindowWait,
+        OptionType::Offset,
+        OptionType::Multicast,
+        OptionType::Compress,
+    ];
+
     /// Converts an [`OptionType`] to a [`str`].
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -250,6 +381,9 @@ impl OptionType {
             OptionType::TimeoutMs => "timeoutms",
             OptionType::WindowSize => "windowsize",
             OptionType::WindowWait => "windowwait",
+            OptionType::Offset => "offset",
+            OptionType::Multicast => "multicast",
+            OptionType::Compress => "compress",
         }
     }
 }
@@ -266,6 +400,9 @@ impl FromStr for OptionType {
             "timeoutms" => Ok(OptionType::TimeoutMs),
             "windowsize" => Ok(OptionType::WindowSize),
             "windowwait" => Ok(OptionType::WindowWait),
+            "offset" => Ok(OptionType::Offset),
+            "multicast" => Ok(OptionType::Multicast),
+            "compress" => Ok(OptionType::Compress),
             _ => Err("Invalid option type"),
         }
     }
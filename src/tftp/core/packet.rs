@@ -1,7 +1,7 @@
 use std::fmt;
 use std::str::FromStr;
 
-use super::{Convert, OptionType, TransferOption};
+use super::{BufferPool, CustomOption, OptionType, PacketError, TransferOption};
 
 /// Packet `enum` represents the valid TFTP packet types.
 ///
@@ -16,8 +16,9 @@ use super::{Convert, OptionType, TransferOption};
 ///
 /// assert_eq!(packet.serialize().unwrap(), vec![0x00, 0x03, 0x00, 0x0F, 0x01, 0x02, 0x03]);
 /// assert_eq!(Packet::deserialize(&[0x00, 0x03, 0x00, 0x0F, 0x01, 0x02, 0x03]).unwrap(), packet);
+/// assert_eq!(packet.to_string(), "DATA #15 len=3");
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub enum Packet {
     /// Read Request `struct`
     Rrq {
@@ -27,6 +28,8 @@ pub enum Packet {
         mode: String,
         /// Transfer options
         options: Vec<TransferOption>,
+        /// Options unknown to [`OptionType`], such as vendor extensions
+        custom: Vec<CustomOption>,
     },
     /// Write Request `struct`
     Wrq {
@@ -36,6 +39,8 @@ pub enum Packet {
         mode: String,
         /// Transfer options
         options: Vec<TransferOption>,
+        /// Options unknown to [`OptionType`], such as vendor extensions
+        custom: Vec<CustomOption>,
     },
     /// Data `struct`
     Data {
@@ -53,17 +58,19 @@ pub enum Packet {
         /// Error message
         msg: String,
     },
-    /// Option acknowledgement `tuple` with transfer options
-    Oack(Vec<TransferOption>),
+    /// Option acknowledgement `struct`
+    Oack {
+        /// Transfer options
+        options: Vec<TransferOption>,
+        /// Options unknown to [`OptionType`], such as vendor extensions
+        custom: Vec<CustomOption>,
+    },
 }
 
 impl Packet {
     /// Deserializes a [`u8`] slice into a [`Packet`].
-    pub fn deserialize(buf: &[u8]) -> anyhow::Result<Packet> {
-        if buf.len() < 2 {
-            return Err(anyhow::anyhow!("Buffer too short to serialize"));
-        }
-        let opcode = Opcode::from_u16(Convert::to_u16(&buf[0..=1])?)?;
+    pub fn deserialize(buf: &[u8]) -> Result<Packet, PacketError> {
+        let opcode = Opcode::from_u16(read_u16(buf, 0)?)?;
 
         match opcode {
             Opcode::Rrq | Opcode::Wrq => parse_rq(buf, opcode),
@@ -81,16 +88,122 @@ impl Packet {
                 filename,
                 mode,
                 options,
-            } => Ok(serialize_rrq(filename, mode, options)),
+                custom,
+            } => Ok(serialize_rrq(filename, mode, options, custom)),
             Packet::Wrq {
                 filename,
                 mode,
                 options,
-            } => Ok(serialize_wrq(filename, mode, options)),
+                custom,
+            } => Ok(serialize_wrq(filename, mode, options, custom)),
             Packet::Data { block_num, data } => Ok(serialize_data(block_num, data)),
             Packet::Ack(block_num) => Ok(serialize_ack(block_num)),
             Packet::Error { code, msg } => Ok(serialize_error(code, msg)),
-            Packet::Oack(options) => Ok(serialize_oack(options)),
+            Packet::Oack { options, custom } => Ok(serialize_oack(options, custom)),
+        }
+    }
+}
+
+/// Formats packets for logs, as `RRQ file=vmlinuz mode=octet opts=[blksize=1428]`
+/// or `DATA #42 len=512`.
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+                custom,
+            } => {
+                write!(f, "RRQ file={filename} mode={mode}")?;
+                fmt_options(f, options, custom)
+            }
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+                custom,
+            } => {
+                write!(f, "WRQ file={filename} mode={mode}")?;
+                fmt_options(f, options, custom)
+            }
+            Packet::Data { block_num, data } => write!(f, "DATA #{block_num} len={}", data.len()),
+            Packet::Ack(block_num) => write!(f, "ACK #{block_num}"),
+            Packet::Error { code, msg } => {
+                write!(f, "ERROR {} ({code}) msg={msg:?}", *code as u16)
+            }
+            Packet::Oack { options, custom } => {
+                write!(f, "OACK")?;
+                fmt_options(f, options, custom)
+            }
+        }
+    }
+}
+
+/// Writes ` opts=[...]` with the options of a packet if it has any.
+fn fmt_options(
+    f: &mut fmt::Formatter<'_>,
+    options: &[TransferOption],
+    custom: &[CustomOption],
+) -> fmt::Result {
+    if options.is_empty() && custom.is_empty() {
+        return Ok(());
+    }
+    let options = options
+        .iter()
+        .map(|option| format!("{}={}", option.option.as_str(), option.value))
+        .chain(
+            custom
+                .iter()
+                .map(|option| format!("{}={}", option.name, option.value)),
+        );
+    write!(f, " opts=[{}]", options.collect::<Vec<_>>().join(", "))
+}
+
+/// Shows the length of data rather than its bytes.
+impl fmt::Debug for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+                custom,
+            } => f
+                .debug_struct("Rrq")
+                .field("filename", filename)
+                .field("mode", mode)
+                .field("options", options)
+                .field("custom", custom)
+                .finish(),
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+                custom,
+            } => f
+                .debug_struct("Wrq")
+                .field("filename", filename)
+                .field("mode", mode)
+                .field("options", options)
+                .field("custom", custom)
+                .finish(),
+            Packet::Data { block_num, data } => f
+                .debug_struct("Data")
+                .field("block_num", block_num)
+                .field("len", &data.len())
+                .finish(),
+            Packet::Ack(block_num) => f.debug_tuple("Ack").field(block_num).finish(),
+            Packet::Error { code, msg } => f
+                .debug_struct("Error")
+                .field("code", code)
+                .field("msg", msg)
+                .finish(),
+            Packet::Oack { options, custom } => f
+                .debug_struct("Oack")
+                .field("options", options)
+                .field("custom", custom)
+                .finish(),
         }
     }
 }
@@ -127,7 +240,7 @@ pub enum Opcode {
 
 impl Opcode {
     /// Converts a [`u16`] to an [`Opcode`].
-    pub fn from_u16(val: u16) -> anyhow::Result<Opcode> {
+    pub fn from_u16(val: u16) -> Result<Opcode, PacketError> {
         match val {
             0x0001 => Ok(Opcode::Rrq),
             0x0002 => Ok(Opcode::Wrq),
@@ -135,17 +248,19 @@ impl Opcode {
             0x0004 => Ok(Opcode::Ack),
             0x0005 => Ok(Opcode::Error),
             0x0006 => Ok(Opcode::Oack),
-            _ => Err(anyhow::anyhow!("Invalid opcode")),
+            _ => Err(PacketError::BadOpcode(val)),
         }
     }
 
     /// Converts a [`u16`] to a [`u8`] array with 2 elements.
+    #[allow(clippy::wrong_self_convention)]
     pub const fn as_bytes(self) -> [u8; 2] {
         (self as u16).to_be_bytes()
     }
 }
 
-/// ErrorCode `enum` represents the error codes used in the TFTP definition.
+/// ErrorCode `enum` represents the error codes used in the TFTP definition,
+/// codes 0 to 7 of RFC 1350 and code 8 of RFC 2347 ending option negotiation.
 ///
 /// This `enum` has function implementations for converting [`u16`]s to
 /// [`ErrorCode`]s and [`ErrorCode`]s to [`u8`] arrays.
@@ -157,6 +272,7 @@ impl Opcode {
 ///
 /// assert_eq!(ErrorCode::from_u16(3).unwrap(), ErrorCode::DiskFull);
 /// assert_eq!(ErrorCode::FileExists.as_bytes(), [0x00, 0x06]);
+/// assert_eq!(ErrorCode::RefusedOption.to_string(), "Option Negotiation Failed");
 /// ```
 #[repr(u16)]
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -177,13 +293,14 @@ pub enum ErrorCode {
     FileExists = 6,
     /// No such user error code
     NoSuchUser = 7,
-    /// Refused option error code
+    /// Option negotiation failed error code, sent by either end refusing the
+    /// options of the other
     RefusedOption = 8,
 }
 
 impl ErrorCode {
     /// Converts a [`u16`] to an [`ErrorCode`].
-    pub fn from_u16(code: u16) -> anyhow::Result<ErrorCode> {
+    pub fn from_u16(code: u16) -> Result<ErrorCode, PacketError> {
         match code {
             0 => Ok(ErrorCode::NotDefined),
             1 => Ok(ErrorCode::FileNotFound),
@@ -194,7 +311,7 @@ impl ErrorCode {
             6 => Ok(ErrorCode::FileExists),
             7 => Ok(ErrorCode::NoSuchUser),
             8 => Ok(ErrorCode::RefusedOption),
-            _ => Err(anyhow::anyhow!("Invalid error code")),
+            _ => Err(PacketError::BadErrorCode(code)),
         }
     }
 
@@ -215,83 +332,100 @@ impl fmt::Display for ErrorCode {
             ErrorCode::UnknownId => write!(f, "Unknown ID"),
             ErrorCode::FileExists => write!(f, "File Exists"),
             ErrorCode::NoSuchUser => write!(f, "No Such User"),
-            ErrorCode::RefusedOption => write!(f, "Refused option"),
+            ErrorCode::RefusedOption => write!(f, "Option Negotiation Failed"),
         }
     }
 }
 
-fn parse_rq(buf: &[u8], opcode: Opcode) -> anyhow::Result<Packet> {
-    let mut options = vec![];
-    let filename: String;
-    let mode: String;
-    let mut zero_index: usize;
+/// Reads the big-endian [`u16`] at `index` of `buf`.
+fn read_u16(buf: &[u8], index: usize) -> Result<u16, PacketError> {
+    match buf.get(index..index + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(PacketError::Truncated),
+    }
+}
 
-    (filename, zero_index) = Convert::to_string(buf, 2)?;
-    (mode, zero_index) = Convert::to_string(buf, zero_index + 1)?;
+/// Reads the zero-terminated string starting at `start` of `buf`, and returns
+/// it with the index of its terminating zero.
+fn read_string(buf: &[u8], start: usize) -> Result<(String, usize), PacketError> {
+    let rest = buf.get(start..).ok_or(PacketError::Truncated)?;
+    let index = rest
+        .iter()
+        .position(|&b| b == 0x00)
+        .ok_or(PacketError::MissingNul)?;
+    let string = String::from_utf8(rest[..index].to_vec()).map_err(|_| PacketError::InvalidUtf8)?;
+    Ok((string, start + index))
+}
 
+/// Parses the options of a request or option acknowledgement following the
+/// zero at `zero_index`, those unknown to [`OptionType`] kept verbatim.
+fn parse_options(
+    buf: &[u8],
+    mut zero_index: usize,
+) -> Result<(Vec<TransferOption>, Vec<CustomOption>), PacketError> {
+    let mut options = vec![];
+    let mut custom = vec![];
     let mut value: String;
-    let mut option;
+    let mut name: String;
     while zero_index < buf.len() - 1 {
-        (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-        (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-
-        if let Ok(option) = OptionType::from_str(option.to_lowercase().as_str()) {
-            options.push(TransferOption {
-                option,
-                value: value.parse()?,
-            });
+        (name, zero_index) = read_string(buf, zero_index + 1)?;
+        (value, zero_index) = read_string(buf, zero_index + 1)?;
+
+        match OptionType::from_str(name.to_lowercase().as_str()) {
+            Ok(option) => match TransferOption::parse_value(option, &value) {
+                Ok(value) => options.push(TransferOption { option, value }),
+                Err(_) => return Err(PacketError::InvalidOption { name, value }),
+            },
+            Err(_) => custom.push(CustomOption { name, value }),
         }
     }
 
+    Ok((options, custom))
+}
+
+fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, PacketError> {
+    let (filename, zero_index) = read_string(buf, 2)?;
+    let (mode, zero_index) = read_string(buf, zero_index + 1)?;
+    let (options, custom) = parse_options(buf, zero_index)?;
+
     match opcode {
         Opcode::Rrq => Ok(Packet::Rrq {
             filename,
             mode,
             options,
+            custom,
         }),
         Opcode::Wrq => Ok(Packet::Wrq {
             filename,
             mode,
             options,
+            custom,
         }),
-        _ => Err(anyhow::anyhow!("Non request opcode")),
+        _ => Err(PacketError::BadOpcode(opcode as u16)),
     }
 }
 
-fn parse_data(buf: &[u8]) -> anyhow::Result<Packet> {
-    Ok(Packet::Data {
-        block_num: Convert::to_u16(&buf[2..])?,
-        data: buf[4..].to_vec(),
-    })
+/// Data is copied to a buffer of the shared pool, given back once written
+/// by a [`Window`](super::Window).
+fn parse_data(buf: &[u8]) -> Result<Packet, PacketError> {
+    let block_num = read_u16(buf, 2)?;
+    let mut data = BufferPool::shared().take(buf.len() - 4);
+    data.copy_from_slice(&buf[4..]);
+    Ok(Packet::Data { block_num, data })
 }
 
-fn parse_ack(buf: &[u8]) -> anyhow::Result<Packet> {
-    Ok(Packet::Ack(Convert::to_u16(&buf[2..])?))
+fn parse_ack(buf: &[u8]) -> Result<Packet, PacketError> {
+    Ok(Packet::Ack(read_u16(buf, 2)?))
 }
 
-fn parse_oack(buf: &[u8]) -> anyhow::Result<Packet> {
-    let mut options = vec![];
-    let mut value: String;
-    let mut option;
-    let mut zero_index = 1usize;
-
-    while zero_index < buf.len() - 1 {
-        (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-        (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-        if let Ok(option) = OptionType::from_str(option.to_lowercase().as_str()) {
-            options.push(TransferOption {
-                option,
-                value: value.parse()?,
-            });
-        }
-    }
-
-    Ok(Packet::Oack(options))
+fn parse_oack(buf: &[u8]) -> Result<Packet, PacketError> {
+    let (options, custom) = parse_options(buf, 1)?;
+    Ok(Packet::Oack { options, custom })
 }
 
-fn parse_error(buf: &[u8]) -> anyhow::Result<Packet> {
-    let code = ErrorCode::from_u16(Convert::to_u16(&buf[2..])?)?;
-    if let Ok((msg, _)) = Convert::to_string(buf, 4) {
+fn parse_error(buf: &[u8]) -> Result<Packet, PacketError> {
+    let code = ErrorCode::from_u16(read_u16(buf, 2)?)?;
+    if let Ok((msg, _)) = read_string(buf, 4) {
         Ok(Packet::Error { code, msg })
     } else {
         Ok(Packet::Error {
@@ -301,7 +435,12 @@ fn parse_error(buf: &[u8]) -> anyhow::Result<Packet> {
     }
 }
 
-fn serialize_rrq(filename: &String, mode: &String, options: &Vec<TransferOption>) -> Vec<u8> {
+fn serialize_rrq(
+    filename: &String,
+    mode: &String,
+    options: &[TransferOption],
+    custom: &[CustomOption],
+) -> Vec<u8> {
     let mut buf = [
         &Opcode::Rrq.as_bytes(),
         filename.as_bytes(),
@@ -311,13 +450,16 @@ fn serialize_rrq(filename: &String, mode: &String, options: &Vec<TransferOption>
     ]
     .concat();
 
-    for option in options {
-        buf = [buf, option.as_bytes()].concat();
-    }
+    serialize_options(&mut buf, options, custom);
     buf
 }
 
-fn serialize_wrq(filename: &String, mode: &String, options: &Vec<TransferOption>) -> Vec<u8> {
+fn serialize_wrq(
+    filename: &String,
+    mode: &String,
+    options: &[TransferOption],
+    custom: &[CustomOption],
+) -> Vec<u8> {
     let mut buf = [
         &Opcode::Wrq.as_bytes(),
         filename.as_bytes(),
@@ -327,9 +469,7 @@ fn serialize_wrq(filename: &String, mode: &String, options: &Vec<TransferOption>
     ]
     .concat();
 
-    for option in options {
-        buf = [buf, option.as_bytes()].concat();
-    }
+    serialize_options(&mut buf, options, custom);
     buf
 }
 
@@ -356,19 +496,27 @@ fn serialize_error(code: &ErrorCode, msg: &String) -> Vec<u8> {
     .concat()
 }
 
-fn serialize_oack(options: &Vec<TransferOption>) -> Vec<u8> {
+fn serialize_oack(options: &[TransferOption], custom: &[CustomOption]) -> Vec<u8> {
     let mut buf = Opcode::Oack.as_bytes().to_vec();
 
+    serialize_options(&mut buf, options, custom);
+    buf
+}
+
+/// Appends `options`, then the `custom` ones, to `buf`.
+fn serialize_options(buf: &mut Vec<u8>, options: &[TransferOption], custom: &[CustomOption]) {
     for option in options {
-        buf = [buf, option.as_bytes()].concat();
+        buf.extend_from_slice(&option.as_bytes());
+    }
+    for option in custom {
+        buf.extend_from_slice(&option.as_bytes());
     }
-
-    buf
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tftp::core::options::COMPRESS_GZIP;
 
     #[test]
     fn parses_read_request() {
@@ -385,6 +533,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Rrq)
         {
             assert_eq!(filename, "test.png");
@@ -422,6 +571,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Rrq)
         {
             assert_eq!(filename, "test.png");
@@ -468,6 +618,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Wrq)
         {
             assert_eq!(filename, "test.png");
@@ -501,6 +652,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Wrq)
         {
             assert_eq!(filename, "test.png");
@@ -579,7 +731,7 @@ mod tests {
         ]
         .concat();
 
-        if let Ok(Packet::Oack(options)) = parse_oack(&buf) {
+        if let Ok(Packet::Oack { options, .. }) = parse_oack(&buf) {
             assert_eq!(options.len(), 3);
             assert_eq!(
                 options[0],
@@ -625,6 +777,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn maps_all_error_codes() {
+        for code in 0..=8 {
+            let error_code = ErrorCode::from_u16(code).unwrap();
+            assert_eq!(error_code as u16, code);
+            assert_eq!(error_code.as_bytes(), code.to_be_bytes());
+        }
+        assert_eq!(ErrorCode::from_u16(8).unwrap(), ErrorCode::RefusedOption);
+        assert_eq!(ErrorCode::from_u16(9), Err(PacketError::BadErrorCode(9)));
+        assert_eq!(ErrorCode::NoSuchUser.to_string(), "No Such User");
+    }
+
+    #[test]
+    fn formats_packets() {
+        let rrq = Packet::Rrq {
+            filename: "vmlinuz".to_string(),
+            mode: "octet".to_string(),
+            options: vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 1428,
+            }],
+            custom: vec![CustomOption {
+                name: "vendor".to_string(),
+                value: "on".to_string(),
+            }],
+        };
+        assert_eq!(
+            rrq.to_string(),
+            "RRQ file=vmlinuz mode=octet opts=[blksize=1428, vendor=on]"
+        );
+        let data = Packet::Data {
+            block_num: 42,
+            data: vec![0; 512],
+        };
+        assert_eq!(data.to_string(), "DATA #42 len=512");
+        assert_eq!(format!("{data:?}"), "Data { block_num: 42, len: 512 }");
+        assert_eq!(Packet::Ack(42).to_string(), "ACK #42");
+        let error = Packet::Error {
+            code: ErrorCode::FileNotFound,
+            msg: "no such file".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "ERROR 1 (File Not Found) msg=\"no such file\""
+        );
+        let oack = Packet::Oack {
+            options: vec![],
+            custom: vec![],
+        };
+        assert_eq!(oack.to_string(), "OACK");
+    }
+
     #[test]
     fn parses_error_without_message() {
         let buf = [
@@ -649,7 +853,7 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_rrq(&"test".into(), &"octet".into(), &vec![]),
+            serialize_rrq(&"test".into(), &"octet".into(), &[], &[]),
             serialized_data
         )
     }
@@ -667,7 +871,7 @@ mod tests {
             serialize_rrq(
                 &"test".into(),
                 &"octet".into(),
-                &vec![
+                &[
                     TransferOption {
                         option: OptionType::BlockSize,
                         value: 1468,
@@ -680,7 +884,8 @@ mod tests {
                         option: OptionType::Timeout,
                         value: 5,
                     }
-                ]
+                ],
+                &[]
             ),
             serialized_data
         )
@@ -693,7 +898,7 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_wrq(&"test".into(), &"octet".into(), &vec![]),
+            serialize_wrq(&"test".into(), &"octet".into(), &[], &[]),
             serialized_data
         )
     }
@@ -711,7 +916,7 @@ mod tests {
             serialize_wrq(
                 &"test".into(),
                 &"octet".into(),
-                &vec![
+                &[
                     TransferOption {
                         option: OptionType::BlockSize,
                         value: 1468,
@@ -724,7 +929,8 @@ mod tests {
                         option: OptionType::Timeout,
                         value: 5,
                     }
-                ]
+                ],
+                &[]
             ),
             serialized_data
         )
@@ -771,11 +977,142 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_oack(&vec![TransferOption {
-                option: OptionType::BlockSize,
-                value: 1432
-            }]),
+            serialize_oack(
+                &[TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1432
+                }],
+                &[]
+            ),
             serialized_oack
         );
     }
+
+    #[test]
+    fn serializes_and_parses_custom_options() {
+        let custom = vec![CustomOption {
+            name: "vendor".to_string(),
+            value: "fast".to_string(),
+        }];
+        let packet = Packet::Rrq {
+            filename: "test.png".to_string(),
+            mode: "octet".to_string(),
+            options: vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            }],
+            custom: custom.clone(),
+        };
+        let buf = packet.serialize().unwrap();
+        assert!(buf.ends_with(b"blksize\x001024\x00vendor\x00fast\x00"));
+        assert_eq!(Packet::deserialize(&buf).unwrap(), packet);
+
+        // Unknown options are kept in order, whatever their position
+        let buf = b"\x00\x06X-Boot\x00uefi\x00tsize\x00512\x00vendor\x00\x00";
+        assert_eq!(
+            Packet::deserialize(buf).unwrap(),
+            Packet::Oack {
+                options: vec![TransferOption {
+                    option: OptionType::TransferSize,
+                    value: 512,
+                }],
+                custom: vec![
+                    CustomOption {
+                        name: "X-Boot".to_string(),
+                        value: "uefi".to_string(),
+                    },
+                    CustomOption {
+                        name: "vendor".to_string(),
+                        value: String::new(),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_multicast_options() {
+        let packet = Packet::Rrq {
+            filename: "boot.img".to_string(),
+            mode: "octet".to_string(),
+            options: vec![TransferOption {
+                option: OptionType::Multicast,
+                value: 0,
+            }],
+            custom: vec![],
+        };
+        let buf = packet.serialize().unwrap();
+        assert!(buf.ends_with(b"multicast\0\0"));
+        assert_eq!(Packet::deserialize(&buf).unwrap(), packet);
+
+        let buf = b"\x00\x06multicast\x00239.255.0.1,1758,1\x00";
+        assert_eq!(
+            Packet::deserialize(buf).unwrap(),
+            Packet::Oack {
+                options: vec![TransferOption {
+                    option: OptionType::Multicast,
+                    value: 1,
+                }],
+                custom: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_compress_options() {
+        let packet = Packet::Oack {
+            options: vec![TransferOption {
+                option: OptionType::Compress,
+                value: COMPRESS_GZIP,
+            }],
+            custom: vec![],
+        };
+        let buf = packet.serialize().unwrap();
+        assert!(buf.ends_with(b"compress\0gzip\0"));
+        assert_eq!(Packet::deserialize(&buf).unwrap(), packet);
+
+        let buf = b"\x00\x01boot.img\x00octet\x00compress\x00zstd\x00";
+        assert_eq!(
+            Packet::deserialize(buf).unwrap(),
+            Packet::Rrq {
+                filename: "boot.img".to_string(),
+                mode: "octet".to_string(),
+                options: vec![TransferOption {
+                    option: OptionType::Compress,
+                    value: 0,
+                }],
+                custom: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn classifies_malformed_packets() {
+        let cases: [(&[u8], PacketError); 8] = [
+            (b"", PacketError::Truncated),
+            (b"\x00\x03\x00", PacketError::Truncated),
+            (b"\x00\x07\x00\x01", PacketError::BadOpcode(7)),
+            (b"\x00\x05\x00\x09oops\x00", PacketError::BadErrorCode(9)),
+            (b"\x00\x01boot.img", PacketError::MissingNul),
+            (
+                b"\x00\x02boot.img\x00octet\x00tsize",
+                PacketError::MissingNul,
+            ),
+            (b"\x00\x01\xffboot\x00octet\x00", PacketError::InvalidUtf8),
+            (
+                b"\x00\x06timeout\x00-1\x00",
+                PacketError::InvalidOption {
+                    name: "timeout".to_string(),
+                    value: "-1".to_string(),
+                },
+            ),
+        ];
+        for (buf, err) in cases {
+            assert_eq!(Packet::deserialize(buf), Err(err));
+        }
+        assert_eq!(
+            Packet::deserialize(b"\x00\x01boot.img\x00octet\x00vendor"),
+            Err(PacketError::MissingNul)
+        );
+    }
 }
@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Flow;
+
+/// Link type of packets starting with their IP header, IPv4 or IPv6
+const LINKTYPE_RAW: u32 = 101;
+/// Largest packet captured, that of a UDP datagram
+const SNAPLEN: u32 = 65535;
+const IPPROTO_UDP: u8 = 17;
+/// Hop limit of synthesized IP headers
+const TTL: u8 = 64;
+
+/// PcapWriter `struct` writes the datagrams of transfers to a pcap file,
+/// with synthesized IP and UDP headers, so that transfers failing against
+/// other TFTP stacks can be analyzed in Wireshark or tcpdump.
+///
+/// Datagrams are appended as they are sent and received, so that the capture
+/// survives a crash. They are recorded as they are on the wire, before being
+/// parsed, so that malformed packets are captured too. Addresses are those of
+/// the sockets, the unspecified address for a socket bound to every
+/// interface.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::{Flow, PcapWriter};
+///
+/// let path = std::env::temp_dir().join(format!("doc_{}.pcap", std::process::id()));
+/// let pcap = PcapWriter::create(&path).unwrap();
+/// let (local, server) = ("10.0.0.2:1069".parse().unwrap(), "10.0.0.1:69".parse().unwrap());
+/// pcap.record(Flow::Sent, local, server, &[0x00, 0x04, 0x00, 0x00]);
+/// // Header of 24 bytes, record of 16, IPv4 and UDP headers, then the Ack
+/// assert_eq!(std::fs::metadata(&path).unwrap().len(), 24 + 16 + 20 + 8 + 4);
+/// std::fs::remove_file(path).unwrap();
+/// ```
+pub struct PcapWriter {
+    file: Mutex<File>,
+}
+
+impl PcapWriter {
+    /// Creates the capture file at `path`, replacing any existing one.
+    pub fn create(path: &Path) -> io::Result<PcapWriter> {
+        let mut file = File::create(path)?;
+        let header = [
+            &0xa1b2c3d4u32.to_le_bytes()[..],
+            &2u16.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            // Time zone and accuracy of timestamps
+            &0u32.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &SNAPLEN.to_le_bytes(),
+            &LINKTYPE_RAW.to_le_bytes(),
+        ]
+        .concat();
+        file.write_all(&header)?;
+        Ok(PcapWriter {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records a datagram sent from `local` to `peer`, or received by `local`
+    /// from `peer`. Failures are only logged, a capture never fails the
+    /// transfer.
+    pub fn record(&self, flow: Flow, local: SocketAddr, peer: SocketAddr, payload: &[u8]) {
+        let (src, dst) = match flow {
+            Flow::Sent => (local, peer),
+            Flow::Received => (peer, local),
+        };
+        let packet = ip_packet(src, dst, payload);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        let length = (packet.len() as u32).to_le_bytes();
+        let record = [
+            &(time.as_secs() as u32).to_le_bytes()[..],
+            &time.subsec_micros().to_le_bytes(),
+            // Captured and original lengths
+            &length,
+            &length,
+            &packet,
+        ]
+        .concat();
+        let written = match self.file.lock() {
+            Ok(mut file) => file.write_all(&record),
+            Err(_) => return,
+        };
+        if let Err(err) = written {
+            log::warn!("Could not capture packet: {err}");
+        }
+    }
+}
+
+/// Returns `ip` as an IPv4 address if it is one, IPv4-mapped or unspecified.
+fn as_ipv4(ip: IpAddr) -> Option<Ipv4Addr> {
+    match ip {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(ip) if ip.is_unspecified() => Some(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped(),
+    }
+}
+
+/// Returns the IP packet carrying `payload` from `src` to `dst` in a UDP
+/// datagram, IPv4 if both addresses can be, IPv6 otherwise.
+fn ip_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let (header, pseudo_header) = match (as_ipv4(src.ip()), as_ipv4(dst.ip())) {
+        (Some(src_ip), Some(dst_ip)) => {
+            let mut header = [
+                &[0x45, 0x00][..],
+                &(20 + udp_len).to_be_bytes(),
+                // Identification, then don't fragment
+                &[0x00, 0x00, 0x40, 0x00, TTL, IPPROTO_UDP, 0x00, 0x00],
+                &src_ip.octets(),
+                &dst_ip.octets(),
+            ]
+            .concat();
+            let checksum = checksum(&header).to_be_bytes();
+            header[10..12].copy_from_slice(&checksum);
+            let pseudo_header = [
+                &src_ip.octets()[..],
+                &dst_ip.octets(),
+                &[0x00, IPPROTO_UDP],
+                &udp_len.to_be_bytes(),
+            ]
+            .concat();
+            (header, pseudo_header)
+        }
+        _ => {
+            let ipv6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let (src_ip, dst_ip) = (ipv6(src.ip()).octets(), ipv6(dst.ip()).octets());
+            let header = [
+                &[0x60, 0x00, 0x00, 0x00][..],
+                &udp_len.to_be_bytes(),
+                &[IPPROTO_UDP, TTL],
+                &src_ip,
+                &dst_ip,
+            ]
+            .concat();
+            let pseudo_header = [
+                &src_ip[..],
+                &dst_ip,
+                &(udp_len as u32).to_be_bytes(),
+                &[0x00, 0x00, 0x00, IPPROTO_UDP],
+            ]
+            .concat();
+            (header, pseudo_header)
+        }
+    };
+
+    let mut udp = [
+        &src.port().to_be_bytes()[..],
+        &dst.port().to_be_bytes(),
+        &udp_len.to_be_bytes(),
+        &[0x00, 0x00],
+        payload,
+    ]
+    .concat();
+    // A zero checksum means none, it is sent as all ones instead
+    let udp_checksum = match checksum(&[&pseudo_header[..], &udp].concat()) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&udp_checksum.to_be_bytes());
+    [header, udp].concat()
+}
+
+/// Returns the Internet checksum of `bytes`, as defined by RFC 1071.
+fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthesizes_udp_headers() {
+        let src: SocketAddr = "192.168.1.100:1069".parse().unwrap();
+        let dst: SocketAddr = "192.168.1.1:69".parse().unwrap();
+        let packet = ip_packet(src, dst, b"\x00\x04\x00\x01");
+        assert_eq!(packet.len(), 20 + 8 + 4);
+        assert_eq!(&packet[..4], [0x45, 0x00, 0x00, 32]);
+        assert_eq!(&packet[12..16], [192, 168, 1, 100]);
+        assert_eq!(&packet[20..26], [0x04, 0x2d, 0x00, 0x45, 0x00, 12]);
+        // Checksums over valid headers sum to zero
+        assert_eq!(checksum(&packet[..20]), 0);
+        let pseudo_header = [&packet[12..20], &[0x00, IPPROTO_UDP, 0x00, 12]].concat();
+        assert_eq!(checksum(&[&pseudo_header[..], &packet[20..]].concat()), 0);
+
+        // IPv4-mapped peers of dual-stack sockets are captured as IPv4
+        let mapped: SocketAddr = "[::ffff:192.168.1.100]:1069".parse().unwrap();
+        let any: SocketAddr = "[::]:69".parse().unwrap();
+        assert_eq!(ip_packet(mapped, any, b"")[0], 0x45);
+        let packet = ip_packet("[fe80::1]:1069".parse().unwrap(), any, b"ab");
+        assert_eq!(packet.len(), 40 + 8 + 2);
+        assert_eq!(&packet[4..8], [0x00, 10, IPPROTO_UDP, TTL]);
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// RateLimiter `struct` is a token bucket limiting the bytes sent per second.
+///
+/// The bucket holds up to one second of traffic, so a transfer starts at full
+/// speed and then settles at the rate. A limiter may be shared by several
+/// transfers, e.g. those of one client, which then share the rate.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Instant;
+/// use xtool::tftp::core::RateLimiter;
+///
+/// let limiter = RateLimiter::new(100_000);
+/// let start = Instant::now();
+/// for _ in 0..150 {
+///     limiter.acquire(1000);
+/// }
+/// assert!(start.elapsed().as_millis() >= 400);
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may be sent right away, negative while in debt
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `rate` bytes per second.
+    pub fn new(rate: u64) -> RateLimiter {
+        RateLimiter {
+            rate: rate.max(1),
+            state: Mutex::new(Bucket {
+                tokens: rate as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` from the bucket, sleeping until they are available.
+    pub fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.rate as f64;
+            bucket.tokens = (bucket.tokens + refill).min(self.rate as f64);
+            bucket.refilled = now;
+
+            // Borrow what is missing, later callers wait for the debt too
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.rate as f64)
+        };
+        thread::sleep(wait);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_rate() {
+        let limiter = RateLimiter::new(50_000);
+        // Drain the initial burst
+        limiter.acquire(50_000);
+
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..10 {
+                        limiter.acquire(1000);
+                    }
+                });
+            }
+        });
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(350), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1000), "{elapsed:?}");
+    }
+}
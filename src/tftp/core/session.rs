@@ -0,0 +1,571 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Packet, Socket};
+
+/// First line of session files
+const SESSION_HEADER: &str = "# xtool TFTP session";
+/// How often [`ReplaySocket::finish()`] checks whether the replay is done
+const FINISH_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Flow `enum` tells whether a packet of a [`Session`] was sent or received
+/// by the side that recorded it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Sent,
+    Received,
+}
+
+/// Event `struct` is a packet of a recorded [`Session`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Time since the session started
+    pub elapsed: Duration,
+    pub flow: Flow,
+    /// Address the packet was sent to or received from, if known
+    pub peer: Option<SocketAddr>,
+    /// The packet as sent on the wire
+    pub bytes: Vec<u8>,
+}
+
+impl Event {
+    /// Returns the parsed packet.
+    pub fn packet(&self) -> anyhow::Result<Packet> {
+        Ok(Packet::deserialize(&self.bytes)?)
+    }
+}
+
+/// Session `struct` holds the packets of a transfer as seen by one side,
+/// recorded with a [`SessionRecorder`] and replayed with a [`ReplaySocket`],
+/// so that an interoperability problem seen in the field can be turned into
+/// a deterministic regression test.
+///
+/// Sessions are stored as text, one `<ms> <send|recv> <peer> <hex>` line
+/// per packet after a `#` header. Lines starting with `#` are comments, so
+/// that recordings can be annotated.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::{Flow, Packet, Session};
+///
+/// let session: Session = "0 recv 10.0.0.2:1069 00040000\n".parse().unwrap();
+/// assert_eq!(session.events[0].flow, Flow::Received);
+/// assert_eq!(session.events[0].packet().unwrap(), Packet::Ack(0));
+/// assert_eq!(session.mirror().events[0].flow, Flow::Sent);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub events: Vec<Event>,
+}
+
+impl Session {
+    /// Reads the session recorded at `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Session> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|err| anyhow::anyhow!("Invalid session {}: {err}", path.display()))
+    }
+
+    /// Writes the session to `path`.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Returns the session as seen by the other side, e.g. the server side
+    /// of a session recorded by a client.
+    pub fn mirror(&self) -> Session {
+        let events = self
+            .events
+            .iter()
+            .map(|event| Event {
+                flow: match event.flow {
+                    Flow::Sent => Flow::Received,
+                    Flow::Received => Flow::Sent,
+                },
+                ..event.clone()
+            })
+            .collect();
+        Session { events }
+    }
+}
+
+impl FromStr for Session {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Session> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                anyhow::anyhow!(
+                    "line {}: expected <ms> <send|recv> <peer> <hex>",
+                    number + 1
+                )
+            };
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [elapsed, flow, peer, hex] = fields[..] else {
+                return Err(invalid());
+            };
+            let flow = match flow {
+                "send" => Flow::Sent,
+                "recv" => Flow::Received,
+                _ => return Err(invalid()),
+            };
+            let peer = match peer {
+                "-" => None,
+                peer => Some(peer.parse().map_err(|_| invalid())?),
+            };
+            events.push(Event {
+                elapsed: Duration::from_millis(elapsed.parse().map_err(|_| invalid())?),
+                flow,
+                peer,
+                bytes: decode_hex(hex).ok_or_else(invalid)?,
+            });
+        }
+        Ok(Session { events })
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{SESSION_HEADER}")?;
+        for event in &self.events {
+            writeln!(f, "{}", EventFmt(event))?;
+        }
+        Ok(())
+    }
+}
+
+struct EventFmt<'a>(&'a Event);
+
+impl fmt::Display for EventFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let event = self.0;
+        let flow = match event.flow {
+            Flow::Sent => "send",
+            Flow::Received => "recv",
+        };
+        let peer = event.peer.map_or("-".to_string(), |peer| peer.to_string());
+        write!(f, "{} {flow} {peer} ", event.elapsed.as_millis())?;
+        event
+            .bytes
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// SessionRecorder `struct` appends the packets of a transfer to a
+/// [`Session`] file as they are sent and received, so that the recording
+/// survives a crash.
+pub struct SessionRecorder {
+    file: Mutex<File>,
+    start: Instant,
+}
+
+impl SessionRecorder {
+    /// Creates the session file at `path`, replacing any existing one.
+    pub fn create(path: &Path) -> io::Result<SessionRecorder> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{SESSION_HEADER}")?;
+        Ok(SessionRecorder {
+            file: Mutex::new(file),
+            start: Instant::now(),
+        })
+    }
+
+    /// Records a packet sent to or received from `peer`. Failures are only
+    /// logged, a recording never fails the transfer.
+    pub fn record(&self, flow: Flow, peer: Option<SocketAddr>, bytes: &[u8]) {
+        let event = Event {
+            elapsed: self.start.elapsed(),
+            flow,
+            peer,
+            bytes: bytes.to_vec(),
+        };
+        let written = match self.file.lock() {
+            Ok(mut file) => writeln!(file, "{}", EventFmt(&event)),
+            Err(_) => return,
+        };
+        if let Err(err) = written {
+            log::warn!("Could not record packet: {err}");
+        }
+    }
+
+    /// Records a parsed packet, see [`SessionRecorder::record()`].
+    pub fn record_packet(&self, flow: Flow, peer: Option<SocketAddr>, packet: &Packet) {
+        if let Ok(bytes) = packet.serialize() {
+            self.record(flow, peer, &bytes);
+        }
+    }
+}
+
+/// RecordingSocket `struct` wraps a [`Socket`], recording the packets it
+/// sends and receives with a [`SessionRecorder`].
+pub struct RecordingSocket<T: Socket + ?Sized> {
+    inner: Box<T>,
+    recorder: Arc<SessionRecorder>,
+}
+
+impl<T: Socket + ?Sized> RecordingSocket<T> {
+    /// Records the packets of `inner` with `recorder`.
+    pub fn new(inner: Box<T>, recorder: Arc<SessionRecorder>) -> RecordingSocket<T> {
+        RecordingSocket { inner, recorder }
+    }
+}
+
+impl<T: Socket + ?Sized> Socket for RecordingSocket<T> {
+    fn send(&self, packet: &Packet) -> anyhow::Result<()> {
+        self.inner.send(packet)?;
+        let peer = self.inner.remote_addr().ok();
+        self.recorder.record_packet(Flow::Sent, peer, packet);
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
+        self.inner.send_to(packet, to)?;
+        self.recorder.record_packet(Flow::Sent, Some(*to), packet);
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> anyhow::Result<Packet> {
+        let packet = self.inner.recv_with_size(size)?;
+        let peer = self.inner.remote_addr().ok();
+        self.recorder.record_packet(Flow::Received, peer, &packet);
+        Ok(packet)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> anyhow::Result<(Packet, SocketAddr)> {
+        let (packet, from) = self.inner.recv_from_with_size(size)?;
+        self.recorder
+            .record_packet(Flow::Received, Some(from), &packet);
+        Ok((packet, from))
+    }
+
+    fn remote_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.inner.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.inner.set_write_timeout(dur)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> anyhow::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
+/// ReplaySocket `struct` is a [`Socket`] playing the remote side of a
+/// recorded [`Session`], for the side that recorded it.
+///
+/// Receiving returns the next recorded received packet, once every packet
+/// recorded as sent before it was sent again. Receiving earlier times out
+/// at once, as the recording side must have done, so that replays do not
+/// depend on timing. Sent packets must match the recorded ones in order,
+/// the replay diverges otherwise and every further call fails. Clones share
+/// the progress of the replay, use one to check it with
+/// [`ReplaySocket::finish()`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use xtool::tftp::core::{Packet, ReplaySocket, Socket};
+///
+/// // A one byte block received, then acknowledged
+/// let session = "0 recv 10.0.0.2:1069 0003000161\n1 send 10.0.0.2:1069 00040001\n";
+/// let socket = ReplaySocket::new(&session.parse().unwrap());
+/// let data = Packet::Data { block_num: 1, data: b"a".to_vec() };
+/// assert_eq!(socket.recv().unwrap(), data);
+/// socket.send(&Packet::Ack(1)).unwrap();
+/// socket.finish(Duration::ZERO).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ReplaySocket {
+    state: Arc<Mutex<Replay>>,
+    peer: SocketAddr,
+    nonblocking: bool,
+}
+
+struct Replay {
+    events: Vec<Event>,
+    /// Events before these were sent or received
+    sent: usize,
+    received: usize,
+    divergence: Option<String>,
+}
+
+impl Replay {
+    fn next(&self, from: usize, flow: Flow) -> Option<usize> {
+        (from..self.events.len()).find(|&i| self.events[i].flow == flow)
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        match &self.divergence {
+            // An IO error, so that the transfer gives up as on a broken socket
+            Some(divergence) => {
+                Err(io::Error::other(format!("Replay diverged: {divergence}")).into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn diverge(&mut self, divergence: String) -> anyhow::Error {
+        log::error!("Replay diverged: {divergence}");
+        self.divergence = Some(divergence);
+        self.check().unwrap_err()
+    }
+
+    /// Returns the number of events not replayed yet.
+    fn remaining(&self) -> usize {
+        let pending = |from: usize, flow: Flow| {
+            self.events[from.min(self.events.len())..]
+                .iter()
+                .filter(|event| event.flow == flow)
+                .count()
+        };
+        pending(self.sent, Flow::Sent) + pending(self.received, Flow::Received)
+    }
+}
+
+impl ReplaySocket {
+    /// Creates a socket replaying `session`.
+    pub fn new(session: &Session) -> ReplaySocket {
+        let peer = session
+            .events
+            .iter()
+            .find_map(|event| event.peer)
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+        ReplaySocket {
+            state: Arc::new(Mutex::new(Replay {
+                events: session.events.clone(),
+                sent: 0,
+                received: 0,
+                divergence: None,
+            })),
+            peer,
+            nonblocking: false,
+        }
+    }
+
+    /// Waits up to `timeout` for every recorded packet to be replayed, then
+    /// fails if the replay diverged from the recording or did not complete.
+    pub fn finish(&self, timeout: Duration) -> anyhow::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            {
+                let state = self.state.lock().unwrap();
+                state.check()?;
+                let pending = state.remaining();
+                if pending == 0 {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    return Err(anyhow::anyhow!(
+                        "Replay incomplete, {pending} recorded packets were not replayed"
+                    ));
+                }
+            }
+            thread::sleep(FINISH_POLL_INTERVAL);
+        }
+    }
+
+    fn timed_out(&self) -> anyhow::Error {
+        let kind = if self.nonblocking {
+            ErrorKind::WouldBlock
+        } else {
+            ErrorKind::TimedOut
+        };
+        io::Error::from(kind).into()
+    }
+}
+
+impl Socket for ReplaySocket {
+    fn send(&self, packet: &Packet) -> anyhow::Result<()> {
+        let bytes = packet.serialize()?;
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+
+        let Some(index) = state.next(state.sent, Flow::Sent) else {
+            return Err(state.diverge(format!("sent {packet} past the end of the session")));
+        };
+        if state.events[index].bytes != bytes {
+            let recorded = state.events[index]
+                .packet()
+                .map_or_else(|err| err.to_string(), |packet| packet.to_string());
+            return Err(state.diverge(format!(
+                "packet {} sent {packet}, recorded {recorded}",
+                index + 1
+            )));
+        }
+        state.sent = index + 1;
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, _to: &SocketAddr) -> anyhow::Result<()> {
+        self.send(packet)
+    }
+
+    fn recv_with_size(&self, _size: usize) -> anyhow::Result<Packet> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+
+        let Some(index) = state.next(state.received, Flow::Received) else {
+            return Err(self.timed_out());
+        };
+        // The recording side sent something before receiving this packet
+        if state
+            .next(state.sent, Flow::Sent)
+            .is_some_and(|sent| sent < index)
+        {
+            return Err(self.timed_out());
+        }
+        state.received = index + 1;
+        state.events[index].packet()
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> anyhow::Result<(Packet, SocketAddr)> {
+        Ok((self.recv_with_size(size)?, self.peer))
+    }
+
+    fn remote_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    fn set_read_timeout(&mut self, _dur: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, _dur: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> anyhow::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(block_num: u16) -> Packet {
+        Packet::Data {
+            block_num,
+            data: vec![block_num as u8; 4],
+        }
+    }
+
+    fn session(events: &[(Flow, Packet)]) -> Session {
+        let events = events
+            .iter()
+            .map(|(flow, packet)| Event {
+                elapsed: Duration::ZERO,
+                flow: *flow,
+                peer: Some("10.0.0.2:1069".parse().unwrap()),
+                bytes: packet.serialize().unwrap(),
+            })
+            .collect();
+        Session { events }
+    }
+
+    #[test]
+    fn formats_and_parses() {
+        let session = session(&[(Flow::Received, Packet::Ack(0)), (Flow::Sent, data(1))]);
+        let text = session.to_string();
+        assert!(text.starts_with(SESSION_HEADER));
+        assert_eq!(text.parse::<Session>().unwrap(), session);
+
+        let annotated = format!("{text}# client gave up here\n\n");
+        assert_eq!(annotated.parse::<Session>().unwrap(), session);
+        assert!("0 recv - 0004000".parse::<Session>().is_err());
+        assert!("0 sent - 00040000".parse::<Session>().is_err());
+    }
+
+    #[test]
+    fn replays_windows_and_timeouts() {
+        // A window of two blocks, the second block lost and resent
+        let socket = ReplaySocket::new(&session(&[
+            (Flow::Sent, data(1)),
+            (Flow::Sent, data(2)),
+            (Flow::Received, Packet::Ack(1)),
+            (Flow::Sent, data(2)),
+            (Flow::Received, Packet::Ack(2)),
+        ]));
+
+        socket.send(&data(1)).unwrap();
+        socket.send(&data(2)).unwrap();
+        assert_eq!(socket.recv().unwrap(), Packet::Ack(1));
+        // The resent block was sent after a timeout
+        let err = socket.recv().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            ErrorKind::TimedOut
+        );
+        assert!(socket.finish(Duration::ZERO).is_err());
+        socket.send(&data(2)).unwrap();
+        assert_eq!(socket.recv().unwrap(), Packet::Ack(2));
+        socket.finish(Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let socket = ReplaySocket::new(&session(&[(Flow::Sent, data(1))]));
+        assert!(socket.send(&data(2)).is_err());
+        // Nothing is replayed past a divergence
+        assert!(socket.send(&data(1)).is_err());
+        let err = socket.finish(Duration::ZERO).unwrap_err();
+        assert!(err.to_string().contains("diverged"), "{err}");
+    }
+
+    #[test]
+    fn records_sockets() {
+        let path = Path::new("target/test/records_sockets.session");
+        let _ = fs::create_dir_all("target/test");
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.connect(sender.local_addr().unwrap()).unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+
+        let recorder = Arc::new(SessionRecorder::create(path).unwrap());
+        let socket = RecordingSocket::new(Box::new(receiver), recorder);
+        Socket::send(&sender, &data(1)).unwrap();
+        assert_eq!(socket.recv_with_size(512).unwrap(), data(1));
+        socket.send(&Packet::Ack(1)).unwrap();
+
+        let session = Session::load(path).unwrap();
+        let flows: Vec<Flow> = session.events.iter().map(|event| event.flow).collect();
+        assert_eq!(flows, [Flow::Received, Flow::Sent]);
+        assert_eq!(session.events[1].packet().unwrap(), Packet::Ack(1));
+        assert_eq!(session.events[1].peer, Some(sender.local_addr().unwrap()));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,15 +1,40 @@
-use super::Packet;
+use super::options::DEFAULT_BLOCK_SIZE;
+use super::packet::ErrorCode;
+use super::packet::Opcode;
+use super::{BufferPool, Flow, Packet, PcapWriter, TftpTransport};
+use socket2::SockRef;
 use std::{
-    io::{Error as IoError, ErrorKind},
+    io::{Error as IoError, ErrorKind, IoSlice},
     net::{SocketAddr, UdpSocket},
     sync::{
-        Mutex,
-        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
     },
     time::Duration,
 };
 
-const MAX_REQUEST_PACKET_SIZE: usize = 512;
+/// Largest request accepted, as large as the data of a block by default
+pub(crate) const MAX_REQUEST_PACKET_SIZE: usize = 512;
+
+#[cfg(windows)]
+const EMSGSIZE: i32 = 10040;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+const EMSGSIZE: i32 = 40;
+#[cfg(not(any(
+    windows,
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+const EMSGSIZE: i32 = 90;
 
 /// Socket `trait` is used to allow building custom sockets to be used for
 /// TFTP communication.
@@ -18,6 +43,15 @@ pub trait Socket: Send + Sync + 'static {
     fn send(&self, packet: &Packet) -> anyhow::Result<()>;
     /// Sends a [`Packet`] to the specified remote [`Socket`].
     fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()>;
+    /// Sends a [`Packet::Data`] of `data` to the socket's connected remote
+    /// [`Socket`]. UDP sockets send `data` as is, without copying it into a
+    /// [`Packet`] first.
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        self.send(&Packet::Data {
+            block_num,
+            data: data.to_vec(),
+        })
+    }
     /// Receives a [`Packet`] from the socket's connected remote [`Socket`]. This
     /// function cannot handle large data packets due to the limited buffer size,
     /// so it is intended for only accepting incoming requests. For handling data
@@ -59,6 +93,13 @@ impl Socket for UdpSocket {
         Ok(())
     }
 
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        let header = data_header(block_num);
+        SockRef::from(self).send_vectored(&[IoSlice::new(&header), IoSlice::new(data)])?;
+
+        Ok(())
+    }
+
     fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
         self.send_to(&packet.serialize()?, to)?;
 
@@ -66,19 +107,25 @@ impl Socket for UdpSocket {
     }
 
     fn recv_with_size(&self, size: usize) -> anyhow::Result<Packet> {
-        let mut buf = vec![0; size + 4];
-        let amt = self.recv(&mut buf)?;
-        let packet = Packet::deserialize(&buf[..amt])?;
-
-        Ok(packet)
+        let mut buf = BufferPool::shared().take(size + 4);
+        let packet = self
+            .recv(&mut buf)
+            .map_err(anyhow::Error::from)
+            .and_then(|amt| Ok(Packet::deserialize(&buf[..amt])?));
+        BufferPool::shared().give(buf);
+
+        packet
     }
 
     fn recv_from_with_size(&self, size: usize) -> anyhow::Result<(Packet, SocketAddr)> {
-        let mut buf = vec![0; size + 4];
-        let (amt, addr) = self.recv_from(&mut buf)?;
-        let packet = Packet::deserialize(&buf[..amt])?;
-
-        Ok((packet, addr))
+        let mut buf = BufferPool::shared().take(size + 4);
+        let packet = self
+            .recv_from(&mut buf)
+            .map_err(anyhow::Error::from)
+            .and_then(|(amt, addr)| Ok((Packet::deserialize(&buf[..amt])?, addr)));
+        BufferPool::shared().give(buf);
+
+        packet
     }
 
     fn remote_addr(&self) -> anyhow::Result<SocketAddr> {
@@ -106,7 +153,10 @@ impl Socket for UdpSocket {
 
 /// ServerSocket `struct` is used as an abstraction layer for a server
 /// [`Socket`]. This `struct` is used for abstraction of single socket
-/// communication.
+/// communication: it only receives the packets routed to it from its remote,
+/// the server answering packets of unknown remotes with an
+/// [`ErrorCode::UnknownId`] error. The datagrams it sends are captured with
+/// [`ServerSocket::with_pcap()`], those it receives by the server.
 ///
 /// # Example
 ///
@@ -130,6 +180,7 @@ pub struct ServerSocket {
     receiver: Mutex<Receiver<Packet>>,
     timeout: Duration,
     nonblocking: bool,
+    pcap: Option<Arc<PcapWriter>>,
 }
 
 impl Socket for ServerSocket {
@@ -138,7 +189,32 @@ impl Socket for ServerSocket {
     }
 
     fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
-        self.socket.send_to(&packet.serialize()?, to)?;
+        let datagram = packet.serialize()?;
+        self.socket.send_to(&datagram, to)?;
+        capture(
+            self.pcap.as_deref(),
+            Flow::Sent,
+            &self.socket,
+            *to,
+            &[&datagram],
+        );
+
+        Ok(())
+    }
+
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        let header = data_header(block_num);
+        SockRef::from(&self.socket).send_to_vectored(
+            &[IoSlice::new(&header), IoSlice::new(data)],
+            &self.remote.into(),
+        )?;
+        capture(
+            self.pcap.as_deref(),
+            Flow::Sent,
+            &self.socket,
+            self.remote,
+            &[&header, data],
+        );
 
         Ok(())
     }
@@ -151,10 +227,16 @@ impl Socket for ServerSocket {
                 } else {
                     Err(IoError::from(ErrorKind::WouldBlock).into())
                 }
-            } else if let Ok(packet) = receiver.recv_timeout(self.timeout) {
-                Ok(packet)
             } else {
-                Err(anyhow::anyhow!("Failed to receive"))
+                match receiver.recv_timeout(self.timeout) {
+                    Ok(packet) => Ok(packet),
+                    Err(RecvTimeoutError::Timeout) => {
+                        Err(IoError::from(ErrorKind::TimedOut).into())
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        Err(anyhow::anyhow!("Failed to receive"))
+                    }
+                }
             }
         } else {
             Err(anyhow::anyhow!("Failed to lock mutex"))
@@ -200,15 +282,166 @@ impl ServerSocket {
             receiver: Mutex::new(receiver),
             timeout,
             nonblocking: false,
+            pcap: None,
         }
     }
 
+    /// Captures the datagrams sent with `pcap`, if set.
+    pub fn with_pcap(mut self, pcap: Option<Arc<PcapWriter>>) -> Self {
+        self.pcap = pcap;
+        self
+    }
+
     /// Returns a [`Sender`] for sending [`Packet`]s to the remote [`Socket`].
     pub fn sender(&self) -> Sender<Packet> {
         self.sender.lock().unwrap().clone()
     }
 }
 
+/// PeerSocket `struct` is a [`TftpTransport`], a [`UdpSocket`] by default,
+/// exchanging packets with a single remote [`Socket`], the transfer ID (TID)
+/// of RFC 1350. Unlike a connected [`UdpSocket`], it still receives the
+/// packets of other remotes, so that they are answered with an
+/// [`ErrorCode::UnknownId`] error instead of being dropped by the network
+/// stack. The datagrams it sends and receives are captured as they are on the
+/// wire with [`PeerSocket::with_pcap()`], before being parsed.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::{SocketAddr, UdpSocket};
+/// use std::str::FromStr;
+/// use xtool::tftp::core::{Socket, PeerSocket, Packet};
+///
+/// let socket = PeerSocket::new(
+///     UdpSocket::bind("127.0.0.1:0").unwrap(),
+///     SocketAddr::from_str("127.0.0.1:50000").unwrap(),
+/// );
+/// socket.send(&Packet::Ack(1)).unwrap();
+/// ```
+pub struct PeerSocket<T: TftpTransport = UdpSocket> {
+    socket: T,
+    remote: SocketAddr,
+    pcap: Option<Arc<PcapWriter>>,
+}
+
+impl<T: TftpTransport> Socket for PeerSocket<T> {
+    fn send(&self, packet: &Packet) -> anyhow::Result<()> {
+        self.send_to(packet, &self.remote)
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
+        let datagram = packet.serialize()?;
+        self.socket.send(&datagram, *to)?;
+        capture(
+            self.pcap.as_deref(),
+            Flow::Sent,
+            &self.socket,
+            *to,
+            &[&datagram],
+        );
+
+        Ok(())
+    }
+
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        let header = data_header(block_num);
+        self.socket
+            .send_vectored(&[IoSlice::new(&header), IoSlice::new(data)], self.remote)?;
+        capture(
+            self.pcap.as_deref(),
+            Flow::Sent,
+            &self.socket,
+            self.remote,
+            &[&header, data],
+        );
+
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> anyhow::Result<Packet> {
+        loop {
+            let (packet, from) = self.recv_from_with_size(size)?;
+            if from == self.remote {
+                return Ok(packet);
+            }
+            reject_unknown_tid(self, &packet, &from);
+        }
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> anyhow::Result<(Packet, SocketAddr)> {
+        let mut buf = BufferPool::shared().take(size + 4);
+        let packet = self
+            .socket
+            .recv(&mut buf)
+            .map_err(anyhow::Error::from)
+            .and_then(|(amt, addr)| {
+                capture(
+                    self.pcap.as_deref(),
+                    Flow::Received,
+                    &self.socket,
+                    addr,
+                    &[&buf[..amt]],
+                );
+                Ok((Packet::deserialize(&buf[..amt])?, addr))
+            });
+        BufferPool::shared().give(buf);
+
+        packet
+    }
+
+    fn remote_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.remote)
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.socket.set_read_timeout(Some(dur))?;
+
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.socket.set_write_timeout(Some(dur))?;
+
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> anyhow::Result<()> {
+        self.socket.set_nonblocking(nonblocking)?;
+
+        Ok(())
+    }
+}
+
+impl<T: TftpTransport> PeerSocket<T> {
+    /// Creates a new [`PeerSocket`] from an unconnected transport and its
+    /// remote [`SocketAddr`].
+    pub fn new(socket: T, remote: SocketAddr) -> Self {
+        Self {
+            socket,
+            remote,
+            pcap: None,
+        }
+    }
+
+    /// Captures the datagrams sent and received with `pcap`, if set.
+    pub fn with_pcap(mut self, pcap: Option<Arc<PcapWriter>>) -> Self {
+        self.pcap = pcap;
+        self
+    }
+}
+
+impl PeerSocket {
+    /// Creates a new independently owned handle to the same socket.
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            remote: self.remote,
+            pcap: self.pcap.clone(),
+        })
+    }
+}
+
 impl<T: Socket + ?Sized> Socket for Box<T> {
     fn send(&self, packet: &Packet) -> anyhow::Result<()> {
         (**self).send(packet)
@@ -218,6 +451,10 @@ impl<T: Socket + ?Sized> Socket for Box<T> {
         (**self).send_to(packet, to)
     }
 
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        (**self).send_data(block_num, data)
+    }
+
     fn recv_with_size(&self, size: usize) -> anyhow::Result<Packet> {
         (**self).recv_with_size(size)
     }
@@ -243,12 +480,172 @@ impl<T: Socket + ?Sized> Socket for Box<T> {
     }
 }
 
+/// Answers `packet`, received by `socket` from `from` while exchanging with
+/// another remote, with an unknown transfer ID error, as RFC 1350 requires.
+/// Errors are never answered with errors.
+pub fn reject_unknown_tid<T: Socket + ?Sized>(socket: &T, packet: &Packet, from: &SocketAddr) {
+    log::warn!("Received packet from unknown transfer ID {from}");
+    if matches!(packet, Packet::Error { .. }) {
+        return;
+    }
+    let error = Packet::Error {
+        code: ErrorCode::UnknownId,
+        msg: "Unknown transfer ID".to_string(),
+    };
+    if socket.send_to(&error, from).is_err() {
+        log::error!("Could not send error packet to {from}");
+    }
+}
+
+/// Captures the datagram made of `parts`, sent or received by `socket` with
+/// `peer`, with `pcap` if set.
+fn capture<T: TftpTransport + ?Sized>(
+    pcap: Option<&PcapWriter>,
+    flow: Flow,
+    socket: &T,
+    peer: SocketAddr,
+    parts: &[&[u8]],
+) {
+    let Some(pcap) = pcap else {
+        return;
+    };
+    if let Ok(local) = socket.local_addr() {
+        pcap.record(flow, local, peer, &parts.concat());
+    }
+}
+
+/// Returns the opcode and block number starting a data packet.
+fn data_header(block_num: u16) -> [u8; 4] {
+    let [opcode_hi, opcode_lo] = Opcode::Data.as_bytes();
+    let [block_hi, block_lo] = block_num.to_be_bytes();
+    [opcode_hi, opcode_lo, block_hi, block_lo]
+}
+
+/// Returns `true` if `err` reports a datagram larger than the network stack
+/// accepts (`EMSGSIZE`).
+pub fn is_message_too_large(err: &IoError) -> bool {
+    err.raw_os_error() == Some(EMSGSIZE)
+}
+
+/// Returns the largest block size, up to `block_size`, whose data packets the
+/// local network stack accepts. Some systems cap UDP datagrams well below the
+/// 65464 bytes allowed by RFC 2348 (e.g. 9216 bytes on macOS), so sizes are
+/// probed by sending to a loopback socket.
+pub fn max_block_size(block_size: u16) -> u16 {
+    if block_size <= DEFAULT_BLOCK_SIZE {
+        return block_size;
+    }
+
+    let Ok(probe) = UdpSocket::bind("127.0.0.1:0") else {
+        return block_size;
+    };
+    let Ok(addr) = probe.local_addr() else {
+        return block_size;
+    };
+    let fits = |size: u16| match probe.send_to(&vec![0; size as usize + 4], addr) {
+        Ok(_) => true,
+        Err(e) => !is_message_too_large(&e),
+    };
+
+    if fits(block_size) {
+        return block_size;
+    }
+
+    // Largest size known to fit and smallest size known to fail
+    let (mut low, mut high) = (DEFAULT_BLOCK_SIZE, block_size);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    low
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use std::str::FromStr;
 
+    #[test]
+    fn detects_message_too_large() {
+        assert!(is_message_too_large(&IoError::from_raw_os_error(EMSGSIZE)));
+        assert!(!is_message_too_large(&IoError::from(ErrorKind::TimedOut)));
+    }
+
+    #[test]
+    fn keeps_block_size_that_fits() {
+        assert_eq!(max_block_size(512), 512);
+        assert_eq!(max_block_size(1468), 1468);
+    }
+
+    #[test]
+    fn sends_data_without_packet() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(receiver.local_addr().unwrap()).unwrap();
+        Socket::send_data(&socket, 258, b"boot").unwrap();
+
+        let server_socket = ServerSocket::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            receiver.local_addr().unwrap(),
+            Duration::from_secs(3),
+        );
+        server_socket.send_data(259, b"").unwrap();
+
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(3)))
+            .unwrap();
+        let packet = Socket::recv(&receiver).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Data {
+                block_num: 258,
+                data: b"boot".to_vec()
+            }
+        );
+        let (packet, _) = Socket::recv_from(&receiver).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Data {
+                block_num: 259,
+                data: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_transfer_ids() {
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut socket = PeerSocket::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            remote.local_addr().unwrap(),
+        );
+        socket.set_read_timeout(Duration::from_secs(3)).unwrap();
+        let addr = UdpSocket::local_addr(&socket.socket).unwrap();
+
+        Socket::send_to(&stranger, &Packet::Ack(1), &addr).unwrap();
+        Socket::send_to(&remote, &Packet::Ack(2), &addr).unwrap();
+        assert_eq!(socket.recv().unwrap(), Packet::Ack(2));
+
+        stranger
+            .set_read_timeout(Some(Duration::from_secs(3)))
+            .unwrap();
+        let (packet, _) = Socket::recv_from(&stranger).unwrap();
+        assert!(matches!(
+            packet,
+            Packet::Error {
+                code: ErrorCode::UnknownId,
+                ..
+            }
+        ));
+    }
+
     #[test]
     fn test_recv() {
         let socket = ServerSocket::new(
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, IoSlice};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use socket2::SockRef;
+
+/// First port given to transports bound to port 0 on a [`MemoryNetwork`]
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// TftpTransport `trait` carries the datagrams of TFTP transfers, so that
+/// clients and servers run over UDP sockets as well as over a
+/// [`MemoryNetwork`] in tests, without real sockets nor their timing.
+///
+/// Methods follow those of [`UdpSocket`], datagrams being sent and received
+/// whole or not at all.
+pub trait TftpTransport: Send + Sync + 'static {
+    /// Sends `datagram` to `to`, returning the number of bytes sent.
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize>;
+    /// Sends the concatenation of `bufs` as a single datagram to `to`.
+    fn send_vectored(&self, bufs: &[IoSlice<'_>], to: SocketAddr) -> io::Result<usize> {
+        let datagram: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.send(&datagram, to)
+    }
+    /// Receives a datagram into `buf`, returning its size and sender. The
+    /// datagram is truncated to the size of `buf`.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    /// Returns the remote the transport is connected to.
+    fn peer(&self) -> io::Result<SocketAddr>;
+    /// Connects the transport to `peer`, only receiving its datagrams from
+    /// then on.
+    fn connect(&self, peer: SocketAddr) -> io::Result<()>;
+    /// Returns the address the transport is bound to.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    /// Sets the read timeout, `None` blocking indefinitely.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// Sets the write timeout, `None` blocking indefinitely.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// Sets the transport as blocking or not.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl TftpTransport for UdpSocket {
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize> {
+        self.send_to(datagram, to)
+    }
+
+    fn send_vectored(&self, bufs: &[IoSlice<'_>], to: SocketAddr) -> io::Result<usize> {
+        SockRef::from(self).send_to_vectored(bufs, &to.into())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.recv_from(buf)
+    }
+
+    fn peer(&self) -> io::Result<SocketAddr> {
+        self.peer_addr()
+    }
+
+    fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+        UdpSocket::connect(self, peer)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl<T: TftpTransport + ?Sized> TftpTransport for Box<T> {
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize> {
+        (**self).send(datagram, to)
+    }
+
+    fn send_vectored(&self, bufs: &[IoSlice<'_>], to: SocketAddr) -> io::Result<usize> {
+        (**self).send_vectored(bufs, to)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        (**self).recv(buf)
+    }
+
+    fn peer(&self) -> io::Result<SocketAddr> {
+        (**self).peer()
+    }
+
+    fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+        (**self).connect(peer)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+}
+
+/// A datagram and its sender
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// MemoryNetwork `struct` routes datagrams between the [`MemoryTransport`]s
+/// bound on it, through channels. Datagrams are never lost nor reordered, and
+/// those sent to an address nothing is bound to are dropped, as with UDP.
+/// Clones share the network.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::{MemoryNetwork, TftpTransport};
+///
+/// let network = MemoryNetwork::new();
+/// let server = network.bind("10.0.0.1:69".parse().unwrap()).unwrap();
+/// let client = network.bind("10.0.0.2:0".parse().unwrap()).unwrap();
+/// client.send(b"boot", server.local_addr().unwrap()).unwrap();
+///
+/// let mut buf = [0; 16];
+/// let (amt, from) = server.recv(&mut buf).unwrap();
+/// assert_eq!(&buf[..amt], b"boot");
+/// assert_eq!(from, client.local_addr().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<Routes>>,
+}
+
+#[derive(Debug, Default)]
+struct Routes {
+    bound: HashMap<SocketAddr, Sender<Datagram>>,
+    /// Last port given to a transport bound to port 0
+    ephemeral: Option<u16>,
+}
+
+impl MemoryNetwork {
+    /// Creates a network nothing is bound to.
+    pub fn new() -> MemoryNetwork {
+        MemoryNetwork::default()
+    }
+
+    /// Binds a transport to `addr`, to a free port of its IP if the port is
+    /// 0.
+    pub fn bind(&self, mut addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut routes = self.state.lock().unwrap();
+        if addr.port() == 0 {
+            let mut port = routes.ephemeral.map_or(FIRST_EPHEMERAL_PORT, |port| {
+                port.checked_add(1).unwrap_or(FIRST_EPHEMERAL_PORT)
+            });
+            while routes.bound.contains_key(&SocketAddr::new(addr.ip(), port)) {
+                port = port.checked_add(1).unwrap_or(FIRST_EPHEMERAL_PORT);
+            }
+            routes.ephemeral = Some(port);
+            addr.set_port(port);
+        } else if routes.bound.contains_key(&addr) {
+            return Err(io::Error::from(ErrorKind::AddrInUse));
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        routes.bound.insert(addr, sender);
+        Ok(MemoryTransport {
+            network: self.clone(),
+            addr,
+            receiver: Mutex::new(receiver),
+            peer: Mutex::new(None),
+            read_timeout: Mutex::new(None),
+            nonblocking: AtomicBool::new(false),
+        })
+    }
+}
+
+/// MemoryTransport `struct` is a [`TftpTransport`] bound to an address of a
+/// [`MemoryNetwork`], see [`MemoryNetwork::bind()`].
+#[derive(Debug)]
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    receiver: Mutex<Receiver<Datagram>>,
+    peer: Mutex<Option<SocketAddr>>,
+    read_timeout: Mutex<Option<Duration>>,
+    nonblocking: AtomicBool,
+}
+
+impl MemoryTransport {
+    /// Returns the next datagram received, waiting as configured.
+    fn next(&self) -> io::Result<Datagram> {
+        let receiver = self.receiver.lock().unwrap();
+        let received = if self.nonblocking.load(Ordering::Relaxed) {
+            receiver.try_recv().map_err(|err| match err {
+                TryRecvError::Empty => ErrorKind::WouldBlock,
+                TryRecvError::Disconnected => ErrorKind::NotConnected,
+            })
+        } else {
+            match *self.read_timeout.lock().unwrap() {
+                Some(timeout) => receiver.recv_timeout(timeout).map_err(|err| match err {
+                    RecvTimeoutError::Timeout => ErrorKind::TimedOut,
+                    RecvTimeoutError::Disconnected => ErrorKind::NotConnected,
+                }),
+                None => receiver.recv().map_err(|_| ErrorKind::NotConnected),
+            }
+        };
+        received.map_err(io::Error::from)
+    }
+}
+
+impl TftpTransport for MemoryTransport {
+    fn send(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize> {
+        let routes = self.network.state.lock().unwrap();
+        if let Some(sender) = routes.bound.get(&to) {
+            let _ = sender.send((datagram.to_vec(), self.addr));
+        }
+        Ok(datagram.len())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            let (datagram, from) = self.next()?;
+            if self.peer.lock().unwrap().is_some_and(|peer| peer != from) {
+                continue;
+            }
+            let amt = datagram.len().min(buf.len());
+            buf[..amt].copy_from_slice(&datagram[..amt]);
+            return Ok((amt, from));
+        }
+    }
+
+    fn peer(&self) -> io::Result<SocketAddr> {
+        self.peer
+            .lock()
+            .unwrap()
+            .ok_or_else(|| io::Error::from(ErrorKind::NotConnected))
+    }
+
+    fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+        *self.peer.lock().unwrap() = Some(peer);
+        Ok(())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::from(ErrorKind::InvalidInput));
+        }
+        *self.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        // Sending never blocks
+        Ok(())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        if let Ok(mut routes) = self.network.state.lock() {
+            routes.bound.remove(&self.addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_datagrams() {
+        let network = MemoryNetwork::new();
+        let server = network.bind("10.0.0.1:69".parse().unwrap()).unwrap();
+        assert_eq!(
+            network
+                .bind("10.0.0.1:69".parse().unwrap())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::AddrInUse
+        );
+        let client = network.bind("10.0.0.2:0".parse().unwrap()).unwrap();
+        let stranger = network.bind("10.0.0.3:0".parse().unwrap()).unwrap();
+        let client_addr = client.local_addr().unwrap();
+        assert_eq!(client_addr.port(), FIRST_EPHEMERAL_PORT);
+
+        // Truncated as with UDP, dropped when nothing is bound
+        let mut buf = [0; 4];
+        client
+            .send(b"request", server.local_addr().unwrap())
+            .unwrap();
+        client
+            .send(b"lost", "10.0.0.9:69".parse().unwrap())
+            .unwrap();
+        assert_eq!(server.recv(&mut buf).unwrap(), (4, client_addr));
+        assert_eq!(&buf, b"requ");
+
+        // Connected transports only receive from their peer
+        server.connect(client_addr).unwrap();
+        assert_eq!(server.peer().unwrap(), client_addr);
+        stranger.send(b"ack", server.local_addr().unwrap()).unwrap();
+        server
+            .send_vectored(&[IoSlice::new(b"da"), IoSlice::new(b"ta")], client_addr)
+            .unwrap();
+        client.send(b"ack", server.local_addr().unwrap()).unwrap();
+        assert_eq!(server.recv(&mut buf).unwrap(), (3, client_addr));
+        assert_eq!(
+            client.recv(&mut buf).unwrap(),
+            (4, server.local_addr().unwrap())
+        );
+        assert_eq!(&buf, b"data");
+
+        server.set_nonblocking(true).unwrap();
+        assert_eq!(
+            server.recv(&mut buf).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        client
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(
+            client.recv(&mut buf).unwrap_err().kind(),
+            ErrorKind::TimedOut
+        );
+
+        // Addresses are freed once dropped
+        drop(server);
+        network.bind("10.0.0.1:69".parse().unwrap()).unwrap();
+    }
+}
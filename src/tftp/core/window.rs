@@ -1,13 +1,31 @@
 use std::{
     collections::VecDeque,
     fs::File,
-    io::{Read, Write},
+    io::{self, Read, Write},
+    sync::mpsc::{self, Receiver},
+    thread,
 };
 
+use super::BufferPool;
+use super::options::Rollover;
+
 /// Window `struct` is used to store chunks of data from a file. It is
 /// used to help store the data that is being sent or received for the
 /// [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize option.
 ///
+/// Chunks are read from or written to a [`File`] by default, any other
+/// reader can be used for sending. Their buffers come from and go back to
+/// [`BufferPool::shared()`].
+///
+/// Each chunk is tagged with its block number, from 1 and wrapping as set by
+/// [`Window::with_rollover()`], so that senders retransmit exactly the blocks
+/// after the last one acknowledged with [`Window::resend_from()`].
+///
+/// The window slides: acknowledged chunks are removed and the next ones filled
+/// behind them, which senders keep sending as Acks cover every chunk sent. An
+/// Ack below the last block sent reports a loss, the chunks after it are sent
+/// again as defined by RFC 7440.
+///
 /// # Example
 /// ```rust
 /// use std::{fs::{self, OpenOptions, File}, io::Write};
@@ -18,58 +36,100 @@ use std::{
 /// file.flush().unwrap();
 ///
 /// let file = File::open("test.txt").unwrap();
-/// let mut window = Window::new(5, 512, file);
+/// let mut window = Window::new(5, 4, file);
 /// window.fill().unwrap();
+/// assert_eq!(window.get(0), Some((1, &b"Hell"[..])));
+///
+/// // Block 2 is acknowledged, blocks 3 and 4 are sent again
+/// let blocks: Vec<u16> = window.resend_from(2).unwrap().map(|(block, _)| block).collect();
+/// assert_eq!(blocks, [3, 4]);
 /// fs::remove_file("test.txt").unwrap();
 /// ```
-pub struct Window {
-    elements: VecDeque<Vec<u8>>,
+pub struct Window<F = File> {
+    /// Chunks with their block numbers
+    elements: VecDeque<(u16, Vec<u8>)>,
     size: u16,
     chunk_size: u16,
-    file: File,
+    file: F,
+    /// Block number of the last chunk added
+    last_block: u16,
+    /// Block number preceding the first chunk, the last one acknowledged
+    acked: u16,
+    rollover: Rollover,
 }
 
-impl Window {
+impl<F> Window<F> {
     /// Creates a new `Window` with the supplied size and chunk size.
-    pub fn new(size: u16, chunk_size: u16, file: File) -> Window {
+    pub fn new(size: u16, chunk_size: u16, file: F) -> Window<F> {
         Window {
             elements: VecDeque::new(),
             size,
             chunk_size,
             file,
+            last_block: 0,
+            acked: 0,
+            rollover: Rollover::Enforce0,
         }
     }
 
+    /// Sets the block number following 65535, 1 with [`Rollover::Enforce1`]
+    /// and 0 otherwise.
+    pub fn with_rollover(mut self, rollover: Rollover) -> Window<F> {
+        self.rollover = rollover;
+        self
+    }
+
+    /// Returns the block number of the next chunk added.
+    fn next_block(&self) -> u16 {
+        match self.last_block.checked_add(1) {
+            Some(block) => block,
+            None if self.rollover == Rollover::Enforce1 => 1,
+            None => 0,
+        }
+    }
+
+    /// Appends `chunk` with the next block number.
+    fn push(&mut self, chunk: Vec<u8>) {
+        self.last_block = self.next_block();
+        self.elements.push_back((self.last_block, chunk));
+    }
+}
+
+impl<F: Read> Window<F> {
     /// Fills the `Window` with chunks of data from the file.
     /// Returns `true` if the `Window` is full.
     pub fn fill(&mut self) -> anyhow::Result<bool> {
         for _ in self.len()..self.size {
-            let mut chunk = vec![0; self.chunk_size as usize];
-            let size = self.file.read(&mut chunk)?;
+            let mut chunk = BufferPool::shared().take(self.chunk_size as usize);
+            let size = read_chunk(&mut self.file, &mut chunk)?;
 
             if size != self.chunk_size as usize {
                 chunk.truncate(size);
-                self.elements.push_back(chunk);
+                self.push(chunk);
                 return Ok(false);
             }
 
-            self.elements.push_back(chunk);
+            self.push(chunk);
         }
 
         Ok(true)
     }
+}
 
+impl<F: Write> Window<F> {
     /// Empties the `Window` by writing the data to the file.
     pub fn empty(&mut self) -> anyhow::Result<()> {
-        for data in &self.elements {
+        for (_, data) in &self.elements {
             self.file.write_all(data)?;
         }
 
-        self.elements.clear();
+        self.clear();
 
         Ok(())
     }
+}
 
+impl<F> Window<F> {
     /// Removes the first `amount` of elements from the `Window`.
     pub fn remove(&mut self, amount: u16) -> anyhow::Result<()> {
         if amount > self.len() {
@@ -78,31 +138,80 @@ impl Window {
             ));
         }
 
-        drop(self.elements.drain(0..amount as usize));
+        for (block, chunk) in self.elements.drain(0..amount as usize) {
+            self.acked = block;
+            BufferPool::shared().give(chunk);
+        }
 
         Ok(())
     }
 
+    /// Removes the chunks up to block `block_num`, acknowledged, and returns
+    /// the block numbers and data of the chunks left to send again. Fails if
+    /// `block_num` is neither in the `Window` nor the block preceding it.
+    pub fn resend_from(
+        &mut self,
+        block_num: u16,
+    ) -> anyhow::Result<impl Iterator<Item = (u16, &[u8])>> {
+        let amount = match self
+            .elements
+            .iter()
+            .position(|(block, _)| *block == block_num)
+        {
+            Some(index) => index as u16 + 1,
+            None if block_num == self.acked => 0,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "block {block_num} is not in the window, blocks {} to {} are",
+                    self.acked,
+                    self.last_block
+                ));
+            }
+        };
+        self.remove(amount)?;
+
+        Ok(self
+            .elements
+            .iter()
+            .map(|(block, chunk)| (*block, chunk.as_slice())))
+    }
+
     /// Adds a data `Vec<u8>` to the `Window`.
     pub fn add(&mut self, data: Vec<u8>) -> anyhow::Result<()> {
         if self.len() == self.size {
             return Err(anyhow::anyhow!("cannot add to a full window"));
         }
 
-        self.elements.push_back(data);
+        self.push(data);
 
         Ok(())
     }
 
-    /// Returns a reference to the `VecDeque` containing the elements.
-    pub fn get_elements(&self) -> &VecDeque<Vec<u8>> {
+    /// Returns a reference to the `VecDeque` containing the elements, with
+    /// their block numbers.
+    #[allow(dead_code)]
+    pub fn get_elements(&self) -> &VecDeque<(u16, Vec<u8>)> {
         &self.elements
     }
 
+    /// Returns the block number and data of the chunk at `index`.
+    pub fn get(&self, index: u16) -> Option<(u16, &[u8])> {
+        self.elements
+            .get(index as usize)
+            .map(|(block, chunk)| (*block, chunk.as_slice()))
+    }
+
     /// Clears all elements from the `Window`.
-    #[allow(dead_code)]
     pub fn clear(&mut self) {
-        self.elements.clear();
+        for (_, chunk) in self.elements.drain(..) {
+            BufferPool::shared().give(chunk);
+        }
+        self.acked = self.last_block;
+    }
+
+    /// Returns the file of the `Window`.
+    pub fn into_inner(self) -> F {
+        self.file
     }
 
     /// Returns the length of the `Window`.
@@ -119,13 +228,192 @@ impl Window {
     pub fn is_full(&self) -> bool {
         self.elements.len() as u16 == self.size
     }
+}
+
+/// MappedWindow `struct` is the [`Window`] of a file already in memory, such
+/// as a [`MappedFile`](super::MappedFile). Its chunks are slices of the
+/// content instead of buffers filled from a reader, so no data is copied
+/// before being sent.
+///
+/// # Example
+/// ```rust
+/// use xtool::tftp::core::MappedWindow;
+///
+/// let mut window = MappedWindow::new(2, 5, &b"Hello, world!"[..], 0);
+/// assert!(window.fill());
+/// assert_eq!(window.get(1), Some(&b", wor"[..]));
+/// window.remove(2).unwrap();
+/// assert!(!window.fill());
+/// assert_eq!(window.get(0), Some(&b"ld!"[..]));
+/// ```
+pub struct MappedWindow<M> {
+    content: M,
+    /// Position of the first chunk in the content
+    offset: usize,
+    /// Chunks removed from the start of the content
+    removed: u64,
+    len: u16,
+    size: u16,
+    chunk_size: u16,
+}
+
+impl<M: AsRef<[u8]>> MappedWindow<M> {
+    /// Creates a new `MappedWindow` with the supplied size and chunk size,
+    /// over the bytes of `content` from `offset`.
+    pub fn new(size: u16, chunk_size: u16, content: M, offset: u64) -> MappedWindow<M> {
+        let offset = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(content.as_ref().len());
+        MappedWindow {
+            content,
+            offset,
+            removed: 0,
+            len: 0,
+            size,
+            chunk_size,
+        }
+    }
+
+    /// Returns the chunks left from the start of the `MappedWindow`, ending
+    /// with a short or empty one as a [`Window`] would.
+    fn remaining(&self) -> u64 {
+        let chunks = (self.content.as_ref().len() - self.offset) as u64 / self.chunk_size as u64;
+        chunks + 1 - self.removed
+    }
+
+    /// Fills the `MappedWindow` with the next chunks of the content.
+    /// Returns `true` if the `MappedWindow` is full and more chunks follow.
+    pub fn fill(&mut self) -> bool {
+        let remaining = self.remaining();
+        self.len = remaining.min(self.size as u64) as u16;
+        remaining > self.size as u64
+    }
+
+    /// Returns the chunk at `index` in the `MappedWindow`.
+    pub fn get(&self, index: u16) -> Option<&[u8]> {
+        if index >= self.len {
+            return None;
+        }
+        let content = self.content.as_ref();
+        let chunk_size = self.chunk_size as usize;
+        let start = self.offset + (self.removed as usize + index as usize) * chunk_size;
+        Some(&content[start..(start + chunk_size).min(content.len())])
+    }
+
+    /// Removes the first `amount` of chunks from the `MappedWindow`.
+    pub fn remove(&mut self, amount: u16) -> anyhow::Result<()> {
+        if amount > self.len {
+            return Err(anyhow::anyhow!(
+                "amount cannot be larger than length of window"
+            ));
+        }
+        self.removed += amount as u64;
+        self.len -= amount;
+
+        Ok(())
+    }
+
+    /// Returns the length of the `MappedWindow`.
+    pub fn len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns `true` if the `MappedWindow` is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// ReadAhead `struct` reads from another reader on a background thread,
+/// keeping up to `depth` buffers ready. Wrapping the file of a sending
+/// [`Window`] lets the next windows be read from disk while the current one
+/// awaits acknowledgement.
+///
+/// # Example
+/// ```rust
+/// use xtool::tftp::core::{ReadAhead, Window};
+///
+/// let reader = ReadAhead::new(&b"Hello, world!"[..], 10, 2);
+/// let mut window = Window::new(2, 5, reader);
+/// assert!(window.fill().unwrap());
+/// assert_eq!(window.get(1), Some((2, &b", wor"[..])));
+/// ```
+pub struct ReadAhead {
+    buffers: Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl ReadAhead {
+    /// Starts reading `reader` in buffers of `buffer_size` bytes, at most
+    /// `depth` of them ahead of the consumer.
+    pub fn new<R: Read + Send + 'static>(
+        mut reader: R,
+        buffer_size: usize,
+        depth: usize,
+    ) -> ReadAhead {
+        let (sender, buffers) = mpsc::sync_channel(depth.max(1));
+
+        thread::spawn(move || {
+            loop {
+                let mut buffer = BufferPool::shared().take(buffer_size.max(1));
+                let result = read_chunk(&mut reader, &mut buffer).map(|size| {
+                    buffer.truncate(size);
+                    buffer
+                });
+                let last = !matches!(&result, Ok(buffer) if !buffer.is_empty());
+
+                // Fails once the consumer is dropped
+                if sender.send(result).is_err() || last {
+                    break;
+                }
+            }
+        });
+
+        ReadAhead {
+            buffers,
+            current: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.current.len() {
+            match self.buffers.recv() {
+                Ok(buffer) => {
+                    let used = std::mem::replace(&mut self.current, buffer?);
+                    BufferPool::shared().give(used);
+                }
+                // The reader thread stops after the end of the file
+                Err(_) => return Ok(0),
+            }
+            self.position = 0;
+        }
 
-    /// Returns the length of the file
-    pub fn file_len(&self) -> anyhow::Result<u64> {
-        Ok(self.file.metadata()?.len())
+        let size = buf.len().min(self.current.len() - self.position);
+        buf[..size].copy_from_slice(&self.current[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
     }
 }
 
+/// Reads until `chunk` is full or the reader is exhausted, since readers such
+/// as decompressors may return less than requested before the end.
+fn read_chunk(reader: &mut impl Read, chunk: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < chunk.len() {
+        match reader.read(&mut chunk[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -150,17 +438,17 @@ mod tests {
         let mut window = Window::new(2, 5, file);
         window.fill().unwrap();
         assert_eq!(window.elements.len(), 2);
-        assert_eq!(window.elements[0], b"Hello"[..]);
-        assert_eq!(window.elements[1], b", wor"[..]);
+        assert_eq!(window.elements[0].1, b"Hello"[..]);
+        assert_eq!(window.elements[1].1, b", wor"[..]);
 
         window.remove(1).unwrap();
         assert_eq!(window.elements.len(), 1);
-        assert_eq!(window.elements[0], b", wor"[..]);
+        assert_eq!(window.elements[0].1, b", wor"[..]);
 
         window.fill().unwrap();
         assert_eq!(window.elements.len(), 2);
-        assert_eq!(window.elements[0], b", wor"[..]);
-        assert_eq!(window.elements[1], b"ld!"[..]);
+        assert_eq!(window.elements[0].1, b", wor"[..]);
+        assert_eq!(window.elements[1].1, b"ld!"[..]);
 
         clean(FILENAME);
     }
@@ -174,18 +462,18 @@ mod tests {
         let mut window = Window::new(3, 5, file);
         window.add(b"Hello".to_vec()).unwrap();
         assert_eq!(window.elements.len(), 1);
-        assert_eq!(window.elements[0], b"Hello"[..]);
+        assert_eq!(window.elements[0].1, b"Hello"[..]);
 
         window.add(b", wor".to_vec()).unwrap();
         assert_eq!(window.elements.len(), 2);
-        assert_eq!(window.elements[0], b"Hello"[..]);
-        assert_eq!(window.elements[1], b", wor"[..]);
+        assert_eq!(window.elements[0].1, b"Hello"[..]);
+        assert_eq!(window.elements[1].1, b", wor"[..]);
 
         window.add(b"ld!".to_vec()).unwrap();
         assert_eq!(window.elements.len(), 3);
-        assert_eq!(window.elements[0], b"Hello"[..]);
-        assert_eq!(window.elements[1], b", wor"[..]);
-        assert_eq!(window.elements[2], b"ld!"[..]);
+        assert_eq!(window.elements[0].1, b"Hello"[..]);
+        assert_eq!(window.elements[1].1, b", wor"[..]);
+        assert_eq!(window.elements[2].1, b"ld!"[..]);
 
         window.empty().unwrap();
         assert_eq!(window.elements.len(), 0);
@@ -201,6 +489,123 @@ mod tests {
         clean(FILENAME);
     }
 
+    #[test]
+    fn resends_from_acked_block() {
+        let mut window = Window::new(3, 2, &b"abcdefghij"[..]);
+        window.fill().unwrap();
+        let blocks: Vec<(u16, &[u8])> = window.resend_from(0).unwrap().collect();
+        assert_eq!(blocks, [(1, &b"ab"[..]), (2, b"cd"), (3, b"ef")]);
+
+        let blocks: Vec<(u16, &[u8])> = window.resend_from(1).unwrap().collect();
+        assert_eq!(blocks, [(2, &b"cd"[..]), (3, b"ef")]);
+        // Acknowledged again, as by a duplicate Ack
+        assert_eq!(window.resend_from(1).unwrap().count(), 2);
+        assert!(window.resend_from(0).is_err());
+        assert!(window.resend_from(4).is_err());
+
+        window.fill().unwrap();
+        assert_eq!(window.get(2), Some((4, &b"gh"[..])));
+        assert_eq!(window.resend_from(4).unwrap().count(), 0);
+        window.fill().unwrap();
+        assert_eq!(window.get(0), Some((5, &b"ij"[..])));
+    }
+
+    #[test]
+    fn tags_blocks_after_rollover() {
+        let content = [0; 2];
+        for (rollover, expected) in [(Rollover::Enforce0, 0), (Rollover::Enforce1, 1)] {
+            let mut window = Window::new(2, 1, &content[..]).with_rollover(rollover);
+            window.last_block = u16::MAX - 1;
+            window.acked = u16::MAX - 1;
+            window.fill().unwrap();
+            assert_eq!(window.get(0).unwrap().0, u16::MAX);
+            assert_eq!(window.get(1).unwrap().0, expected);
+            assert_eq!(window.resend_from(u16::MAX).unwrap().count(), 1);
+        }
+    }
+
+    #[test]
+    fn fills_from_short_reads() {
+        // Yields one byte per read, like a decompressor may do
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let Some((first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = *first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+
+        let mut window = Window::new(3, 5, Trickle(b"Hello, world!"));
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements.len(), 3);
+        assert_eq!(window.elements[1].1, b", wor"[..]);
+        assert_eq!(window.elements[2].1, b"ld!"[..]);
+    }
+
+    #[test]
+    fn reads_ahead() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        // Buffers that do not line up with chunks
+        let mut window = Window::new(
+            4,
+            512,
+            ReadAhead::new(io::Cursor::new(data.clone()), 700, 3),
+        );
+        let mut content = Vec::new();
+        loop {
+            let more = window.fill().unwrap();
+            for (_, chunk) in window.get_elements() {
+                content.extend_from_slice(chunk);
+            }
+            window.remove(window.len()).unwrap();
+            if !more {
+                break;
+            }
+        }
+        assert_eq!(content, data);
+    }
+
+    #[test]
+    fn reads_ahead_errors() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk failure"))
+            }
+        }
+
+        let mut window = Window::new(2, 5, ReadAhead::new(Failing, 10, 1));
+        assert!(window.fill().is_err());
+    }
+
+    #[test]
+    fn maps_the_chunks_of_a_window() {
+        let chunks = |mut window: MappedWindow<&[u8]>| {
+            let mut chunks = Vec::new();
+            loop {
+                let more = window.fill();
+                chunks.extend((0..window.len()).map(|i| window.get(i).unwrap().to_vec()));
+                window.remove(window.len()).unwrap();
+                if !more {
+                    return chunks;
+                }
+            }
+        };
+
+        let sent = chunks(MappedWindow::new(2, 5, &b"Hello, world!"[..], 2));
+        assert_eq!(sent, [&b"llo, "[..], b"world", b"!"]);
+        // A file ending on a chunk boundary is followed by an empty chunk
+        let sent = chunks(MappedWindow::new(2, 5, &b"Hello, wor"[..], 0));
+        assert_eq!(sent, [&b"Hello"[..], b", wor", b""]);
+        let sent = chunks(MappedWindow::new(2, 5, &b"Hello"[..], 8));
+        assert_eq!(sent, [b""]);
+    }
+
     fn initialize(filename: &str) -> File {
         let filename = DIR_NAME.to_string() + "/" + filename;
 
@@ -16,7 +16,9 @@
 //! │   ├── socket      # Socket abstraction layer
 //! │   ├── options     # Protocol options
 //! │   ├── window      # Windowed transfer
-//! │   └── convert     # Data conversion utilities
+//! │   ├── convert     # Data conversion utilities
+//! │   ├── digest      # Checksum algorithms
+//! │   └── file        # Destination file helpers
 //! │
 //! ├── server/         # TFTP server
 //! │   ├── server      # Main server logic
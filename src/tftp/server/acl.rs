@@ -0,0 +1,60 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/// Acl `struct` decides which clients a server answers, from the allowed and
+/// denied networks of its [`Config`](super::Config).
+///
+/// A denied network always wins. If any network is allowed, clients outside
+/// of the allowed networks are refused too.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    allowed: Vec<IpNet>,
+    denied: Vec<IpNet>,
+}
+
+impl Acl {
+    /// Creates an `Acl` from allowed and denied networks.
+    pub fn new(allowed: Vec<IpNet>, denied: Vec<IpNet>) -> Acl {
+        Acl { allowed, denied }
+    }
+
+    /// Returns `true` if requests from `ip` may be served.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of dual-stack sockets show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+        if self.denied.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|net| net.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn permits_clients() {
+        let open = Acl::default();
+        assert!(open.permits("203.0.113.7".parse().unwrap()));
+
+        let acl = Acl::new(
+            nets(&["192.168.1.0/24", "fd00::/8"]),
+            nets(&["192.168.1.13/32"]),
+        );
+        assert!(acl.permits("192.168.1.20".parse().unwrap()));
+        assert!(acl.permits("fd12::1".parse().unwrap()));
+        assert!(acl.permits("::ffff:192.168.1.20".parse().unwrap()));
+        assert!(!acl.permits("192.168.1.13".parse().unwrap()));
+        assert!(!acl.permits("192.168.2.1".parse().unwrap()));
+
+        let deny_only = Acl::new(Vec::new(), nets(&["10.0.0.0/8"]));
+        assert!(!deny_only.permits("10.1.2.3".parse().unwrap()));
+        assert!(deny_only.permits("192.168.1.1".parse().unwrap()));
+    }
+}
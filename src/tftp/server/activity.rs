@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Activity `struct` records when a transfer last heard from its client, so
+/// that the [`Server`](super::Server) reaps transfers whose client vanished,
+/// e.g. a PXE NIC resetting mid-boot, instead of letting them retry for
+/// their whole timeout.
+///
+/// Reaping only flags the transfer: its worker stops once it wakes up from
+/// waiting for a packet, within a timeout, freeing its socket and thread.
+/// Transfers aborted by an administrator are flagged the same way, their
+/// client being sent an error.
+#[derive(Debug)]
+pub(super) struct Activity {
+    started: Instant,
+    /// Milliseconds from `started` to the last packet received
+    last: AtomicU64,
+    reaped: AtomicBool,
+    aborted: AtomicBool,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity {
+            started: Instant::now(),
+            last: AtomicU64::new(0),
+            reaped: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+        }
+    }
+
+    /// Records a packet received from the client.
+    pub fn touch(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last.store(now, Ordering::Relaxed);
+    }
+
+    /// Returns the time since the last packet received from the client, or
+    /// since the transfer started.
+    pub fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+
+    /// Flags the transfer to be stopped. Returns `false` if already flagged.
+    pub fn reap(&self) -> bool {
+        !self.reaped.swap(true, Ordering::SeqCst)
+    }
+
+    /// Returns `true` once the transfer is flagged to be stopped.
+    pub fn is_reaped(&self) -> bool {
+        self.reaped.load(Ordering::SeqCst)
+    }
+
+    /// Flags the transfer to be aborted. Returns `false` if already flagged.
+    pub fn abort(&self) -> bool {
+        !self.aborted.swap(true, Ordering::SeqCst)
+    }
+
+    /// Returns `true` once the transfer is flagged to be aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_idle_time() {
+        let activity = Activity::new();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(activity.idle() >= Duration::from_millis(30));
+        activity.touch();
+        assert!(activity.idle() < Duration::from_millis(30));
+
+        assert!(!activity.is_reaped());
+        assert!(activity.reap());
+        assert!(!activity.reap());
+        assert!(activity.is_reaped());
+
+        assert!(!activity.is_aborted());
+        assert!(activity.abort());
+        assert!(!activity.abort());
+        assert!(activity.is_aborted());
+    }
+}
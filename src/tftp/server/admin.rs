@@ -0,0 +1,224 @@
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::handler::Direction;
+use super::metrics::Metrics;
+use super::reload::request_reload;
+use super::server::ShutdownHandle;
+use super::status::SessionsHandle;
+
+/// AdminSocket `struct` is the control socket of a server, a Unix socket
+/// accepting line commands so that operators manage a running server from
+/// scripts, e.g. with `socat - UNIX-CONNECT:/run/xtool-tftpd.sock`.
+///
+/// Each command is answered with its output, if any, then a last line `ok`
+/// or `error: <reason>`:
+/// - `stats`: transfers in progress, then the metrics if served
+/// - `sessions`: `<id> <client> <read|write> <bytes> <size|-> <progress|->
+///   <name>` per transfer in progress
+/// - `abort <id>`: aborts the transfer `id`
+/// - `reload`: reloads `.xtool.toml`, as on SIGHUP
+/// - `shutdown`: stops the server, as on Ctrl+C
+/// - `drain`: refuses new requests, then stops the server once the transfers
+///   in progress are complete
+///
+/// The socket is created bound before privileges are dropped, readable and
+/// writable by its owner only. A stale socket left at its path is replaced.
+pub(super) struct AdminSocket {
+    #[cfg(unix)]
+    listener: std::os::unix::net::UnixListener,
+}
+
+/// Handles of the server the commands of an [`AdminSocket`] act on
+#[derive(Clone)]
+pub(super) struct AdminHandles {
+    pub sessions: SessionsHandle,
+    pub shutdown: ShutdownHandle,
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl AdminSocket {
+    /// Binds the control socket at `path`.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> anyhow::Result<AdminSocket> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        use std::os::unix::net::UnixListener;
+
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| anyhow::anyhow!("Cannot bind the admin socket {}: {e}", path.display()))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(AdminSocket { listener })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(path: &Path) -> anyhow::Result<AdminSocket> {
+        Err(anyhow::anyhow!(
+            "Cannot bind the admin socket {}, only supported on Unix",
+            path.display()
+        ))
+    }
+
+    /// Answers the commands received on the socket from a background
+    /// thread, one thread per connection.
+    #[cfg(unix)]
+    pub fn serve(self, handles: AdminHandles) {
+        use std::io::{BufRead, BufReader, Write};
+
+        std::thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let handles = handles.clone();
+                std::thread::spawn(move || {
+                    let Ok(reader) = stream.try_clone() else {
+                        return;
+                    };
+                    for line in BufReader::new(reader).lines() {
+                        let Ok(line) = line else {
+                            break;
+                        };
+                        let (reply, stop) = respond(&handles, line.trim());
+                        if stream.write_all(reply.as_bytes()).is_err() {
+                            break;
+                        }
+                        match stop {
+                            Some(Stop::Shutdown) => {
+                                log::info!("Shutdown requested on the admin socket");
+                                handles.shutdown.shutdown();
+                                break;
+                            }
+                            Some(Stop::Drain) => {
+                                log::info!("Drain requested on the admin socket");
+                                handles.shutdown.drain();
+                                break;
+                            }
+                            None => {}
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    pub fn serve(self, _handles: AdminHandles) {}
+}
+
+/// How the server is stopped once the reply to a command is sent
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Shutdown,
+    Drain,
+}
+
+/// Returns the reply to `command`, and whether the server is to be stopped
+/// once it is sent.
+fn respond(handles: &AdminHandles, command: &str) -> (String, Option<Stop>) {
+    let mut reply = String::new();
+    let mut words = command.split_whitespace();
+    let result = match (words.next(), words.next(), words.next()) {
+        (Some("stats"), None, _) => {
+            let _ = writeln!(reply, "sessions {}", handles.sessions.sessions().len());
+            if let Some(metrics) = &handles.metrics {
+                reply.push_str(&metrics.render());
+            }
+            Ok(())
+        }
+        (Some("sessions"), None, _) => {
+            for session in handles.sessions.sessions() {
+                let direction = match session.direction {
+                    Direction::Read => "read",
+                    Direction::Write => "write",
+                };
+                let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+                let _ = writeln!(
+                    reply,
+                    "{} {} {direction} {} {} {} {}",
+                    session.id,
+                    session.client,
+                    session.bytes,
+                    optional(session.size.map(|size| size.to_string())),
+                    optional(session.progress().map(|progress| format!("{progress:.1}"))),
+                    session.filename
+                );
+            }
+            Ok(())
+        }
+        (Some("abort"), Some(id), None) => match id.parse() {
+            Ok(id) if handles.sessions.abort(id) => Ok(()),
+            Ok(id) => Err(format!("no transfer {id} in progress")),
+            Err(_) => Err(format!("invalid transfer id {id}")),
+        },
+        (Some("reload"), None, _) => match request_reload() {
+            true => Ok(()),
+            false => Err("reloading is only available from the command line".to_string()),
+        },
+        (Some("shutdown"), None, _) => {
+            reply.push_str("ok\n");
+            return (reply, Some(Stop::Shutdown));
+        }
+        (Some("drain"), None, _) => {
+            reply.push_str("ok\n");
+            return (reply, Some(Stop::Drain));
+        }
+        _ => Err(format!("unknown command {command:?}")),
+    };
+    match result {
+        Ok(()) => reply.push_str("ok\n"),
+        Err(err) => {
+            let _ = writeln!(reply, "error: {err}");
+        }
+    }
+    (reply, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::server::{Config, Server};
+
+    #[test]
+    fn answers_commands() {
+        let config =
+            Config::default().merge_cli("127.0.0.1".to_string(), 0, ".".into(), true, false);
+        let server = Server::new(&config).unwrap();
+        let handles = AdminHandles {
+            sessions: server.sessions_handle(),
+            shutdown: server.shutdown_handle(),
+            metrics: Some(Arc::new(Metrics::default())),
+        };
+
+        let (reply, stop) = respond(&handles, "stats");
+        assert!(reply.starts_with("sessions 0\n# HELP"));
+        assert!(reply.ends_with("\nok\n"));
+        assert_eq!(stop, None);
+        assert_eq!(respond(&handles, "sessions"), ("ok\n".to_string(), None));
+        assert_eq!(
+            respond(&handles, "abort 7").0,
+            "error: no transfer 7 in progress\n"
+        );
+        assert_eq!(
+            respond(&handles, "abort x").0,
+            "error: invalid transfer id x\n"
+        );
+        // Only the command line knows where to reload the configuration from
+        assert!(respond(&handles, "reload").0.starts_with("error: "));
+        assert_eq!(
+            respond(&handles, "restart").0,
+            "error: unknown command \"restart\"\n"
+        );
+        assert_eq!(
+            respond(&handles, "shutdown"),
+            ("ok\n".to_string(), Some(Stop::Shutdown))
+        );
+        assert_eq!(
+            respond(&handles, "drain"),
+            ("ok\n".to_string(), Some(Stop::Drain))
+        );
+    }
+}
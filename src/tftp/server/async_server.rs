@@ -0,0 +1,823 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::UdpSocket;
+use tokio::time::{self, Instant};
+
+use crate::tftp::core::options::{OptionsPrivate, OptionsProtocol, RequestType, Rollover};
+use crate::tftp::core::{Convert, ErrorCode, OptionType, Packet, TransferOption, Window};
+
+use super::acl::Acl;
+use super::bind::{bind_udp, listen_addr, transfer_addr};
+use super::boot_map::BootMap;
+use super::compress::take_compress;
+use super::fs::{DiskFs, TftpFs};
+use super::json_log::LogFormat;
+use super::multicast::take_multicast;
+use super::overwrite::OverwritePolicy;
+use super::provider::normalize_name;
+use super::rewrite::Rewriter;
+use super::roots::Roots;
+use super::server::{
+    OptionLimits, check_file_exists, clamp_block_size, clamp_to_limits, drop_disabled_options,
+    is_netascii, resolve_file_path, transfer_directory,
+};
+use super::symlinks::Symlinks;
+use super::worker::{ack_distance, log_checksum};
+use super::{Config, open_archive};
+
+/// Largest request a client can send, as for the blocking server
+const MAX_REQUEST_SIZE: usize = 65468;
+const DEFAULT_DUPLICATE_DELAY: Duration = Duration::from_millis(1);
+
+type Reader = Box<dyn Read + Send>;
+
+/// AsyncServer `struct` is a TFTP server running on the tokio runtime, so it
+/// can share a runtime with other services such as the serial net server.
+/// Each transfer runs as a task with its own socket instead of a thread.
+///
+/// It takes the same [`Config`] as [`Server`](super::Server), of which it
+/// supports a subset: the listen address and dual stack, the served and
+/// upload directories, read-only mode, overwriting or refusing existing
+/// files, case insensitivity, symbolic links, roots, archives, access lists,
+/// rewrites, boot maps, option limits and disabled options, the timeout,
+/// retries, rollover, checksums and cleaning on errors. Files are converted
+/// in netascii mode as by the blocking server, and the read ahead is ignored.
+///
+/// [`AsyncServer::new()`] fails if any other setting is set, such as single
+/// port mode, privilege drop, protected paths or the upload quota, rather
+/// than serving with fewer protections than configured.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use xtool::tftp::server::{AsyncServer, Config};
+/// use std::path::PathBuf;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let config = Config::with_defaults().merge_cli(
+///         "127.0.0.1".to_string(),
+///         69,
+///         PathBuf::from("/tmp/tftp"),
+///         false,
+///         false,
+///     );
+///     let server = AsyncServer::new(&config).await?;
+///     server.listen().await
+/// }
+/// ```
+pub struct AsyncServer {
+    socket: UdpSocket,
+    /// Set if IPv4 clients are served on an IPv6 socket
+    dual_stack: bool,
+    /// Directories downloads are served from and uploads written to
+    send_directory: PathBuf,
+    receive_directory: PathBuf,
+    read_only: bool,
+    overwrite: bool,
+    case_insensitive: bool,
+    symlinks: Symlinks,
+    roots: Roots,
+    opt_local: OptionsPrivate,
+    fs: Option<Arc<dyn TftpFs>>,
+    acl: Acl,
+    rewriter: Rewriter,
+    boot_map: BootMap,
+    limits: OptionLimits,
+    disabled_options: Vec<OptionType>,
+}
+
+impl AsyncServer {
+    /// Creates the TFTP server with the supplied [`Config`].
+    pub async fn new(config: &Config) -> anyhow::Result<AsyncServer> {
+        let dual_stack = config.dual_stack.unwrap_or(false);
+        let socket = bind_async(listen_addr(config)?, dual_stack)?;
+
+        let unsupported = unsupported_settings(config);
+        if !unsupported.is_empty() {
+            anyhow::bail!(
+                "Settings not supported by the async server: {}",
+                unsupported.join(", ")
+            );
+        }
+
+        let directory = config
+            .directory
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        let directory = std::fs::canonicalize(&directory).unwrap_or(directory);
+
+        // Archives are mounted as a virtual read-only root
+        let fs = if directory.is_file() {
+            log::info!("TFTP root archive: {}", directory.display());
+            Some(open_archive(&directory)?)
+        } else {
+            log::info!("TFTP root directory: {}", directory.display());
+            None
+        };
+
+        Ok(AsyncServer {
+            socket,
+            dual_stack,
+            send_directory: transfer_directory(config.send_directory.as_ref(), &directory),
+            receive_directory: transfer_directory(config.receive_directory.as_ref(), &directory),
+            read_only: config.read_only.unwrap_or(false) || fs.is_some(),
+            overwrite: config.overwrite.unwrap_or_default() != OverwritePolicy::Reject,
+            case_insensitive: config.case_insensitive.unwrap_or(false),
+            symlinks: config.get_symlinks(),
+            roots: config.get_roots(),
+            opt_local: config.get_options(),
+            fs,
+            acl: config.get_acl(),
+            rewriter: config.get_rewriter()?,
+            boot_map: config.get_boot_map(),
+            limits: config.get_limits()?,
+            disabled_options: config.get_disabled_options()?,
+        })
+    }
+
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Listens for requests and starts a task for each transfer. Only returns
+    /// on errors of the listening socket, drop the future to stop the server.
+    pub async fn listen(&self) -> anyhow::Result<()> {
+        let mut buffer = vec![0; MAX_REQUEST_SIZE];
+
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                // Late ICMP errors from earlier replies on some platforms
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let packet = Packet::deserialize(&buffer[..size]).map(|mut packet| {
+                self.rewriter.rewrite_request(&mut packet);
+                self.boot_map.map_request(&mut packet, from.ip());
+                packet
+            });
+            // Clients asking for multicast or compression fall back to
+            // unicast and files as is
+            let packet = packet.map(|mut packet| {
+                if let Packet::Rrq { options, .. } | Packet::Wrq { options, .. } = &mut packet {
+                    take_multicast(options);
+                    take_compress(options);
+                }
+                packet
+            });
+            let result = match packet {
+                Ok(Packet::Rrq { .. } | Packet::Wrq { .. }) if !self.acl.permits(from.ip()) => {
+                    log::warn!("Refused request from {from}, not an allowed network");
+                    self.send_error(ErrorCode::AccessViolation, "Access violation", from)
+                        .await
+                }
+                Ok(Packet::Rrq {
+                    filename,
+                    mode,
+                    mut options,
+                    ..
+                }) => {
+                    log::info!("Received Read request from {from}: {filename}");
+                    drop_disabled_options(&mut options, &self.disabled_options);
+                    self.handle_rrq(&filename, is_netascii(&mode), options, from)
+                        .await
+                }
+                Ok(Packet::Wrq {
+                    filename,
+                    mode,
+                    mut options,
+                    ..
+                }) => {
+                    if self.read_only {
+                        log::warn!("Received write request while in read-only mode");
+                        self.send_error(ErrorCode::AccessViolation, "server is read-only", from)
+                            .await
+                    } else {
+                        log::info!("Received Write request from {from}: {filename}");
+                        drop_disabled_options(&mut options, &self.disabled_options);
+                        self.handle_wrq(&filename, is_netascii(&mode), options, from)
+                            .await
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Received malformed packet from {from}: {err}");
+                    self.send_error(err.error_code(), &err.to_string(), from)
+                        .await
+                }
+                _ => {
+                    log::warn!("Received invalid request");
+                    self.send_error(ErrorCode::IllegalOperation, "invalid request", from)
+                        .await
+                }
+            };
+
+            if let Err(err) = result {
+                log::error!("Error while handling request from {from}: {err}");
+            }
+        }
+    }
+
+    async fn handle_rrq(
+        &self,
+        filename: &str,
+        netascii: bool,
+        mut options: Vec<TransferOption>,
+        to: SocketAddr,
+    ) -> anyhow::Result<()> {
+        let (file_path, size) = match self.find(filename) {
+            Ok(found) => found,
+            Err((code, msg)) => {
+                log::warn!("Cannot send requested file: {msg}");
+                return self.send_error(code, &msg, to).await;
+            }
+        };
+
+        // Sizes are those of the content sent, longer once converted
+        let size = if netascii {
+            Convert::netascii_len(self.open(&file_path, 0)?)?
+        } else {
+            size
+        };
+        let mut worker_options = OptionsProtocol::parse(&mut options, RequestType::Read(size))?;
+        clamp_to_limits(&mut options, &mut worker_options, &self.limits);
+        clamp_block_size(&mut options, &mut worker_options);
+        let offset = worker_options.offset;
+        if offset > 0 {
+            log::info!("  Resuming at offset {offset}");
+        }
+        let reader: Reader = if netascii {
+            // Offsets are those of the converted content
+            let mut reader = Convert::to_netascii(self.open(&file_path, 0)?);
+            io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+            Box::new(reader)
+        } else {
+            self.open(&file_path, offset)?
+        };
+
+        let transfer = Transfer::new(
+            self.transfer_socket(to).await?,
+            self.opt_local.clone(),
+            worker_options,
+        );
+        let checksum = self.opt_local.checksum;
+        let fs = self.fs.clone();
+        let name = file_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        tokio::spawn(async move {
+            match transfer.send(reader, options).await {
+                Ok(()) => {
+                    log::info!("Sent {name} to {to}");
+                    if checksum.is_some() {
+                        let _ = tokio::task::spawn_blocking(move || {
+                            log_checksum(checksum, &file_path, fs.as_deref().unwrap_or(&DiskFs))
+                        })
+                        .await;
+                    }
+                }
+                Err(err) => log::error!("Error \"{err}\", while sending {name} to {to}"),
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn handle_wrq(
+        &self,
+        filename: &str,
+        netascii: bool,
+        mut options: Vec<TransferOption>,
+        to: SocketAddr,
+    ) -> anyhow::Result<()> {
+        let (directory, name) = self.roots.resolve(&self.receive_directory, filename);
+        let file_path = resolve_file_path(directory, &name, self.case_insensitive);
+        match check_file_exists(&file_path, directory, &self.symlinks) {
+            ErrorCode::FileExists if !self.overwrite => {
+                log::error!("File {} already exists", file_path.display());
+                return self
+                    .send_error(ErrorCode::FileExists, "requested file already exists", to)
+                    .await;
+            }
+            ErrorCode::AccessViolation => {
+                log::error!("Access violation detected for file {}", file_path.display());
+                let msg = format!("file access violation: {}", file_path.display());
+                return self.send_error(ErrorCode::AccessViolation, &msg, to).await;
+            }
+            _ => {}
+        }
+
+        let mut worker_options = OptionsProtocol::parse(&mut options, RequestType::Write)?;
+        clamp_to_limits(&mut options, &mut worker_options, &self.limits);
+        let transfer = Transfer::new(
+            self.transfer_socket(to).await?,
+            self.opt_local.clone(),
+            worker_options,
+        );
+        let clean_on_error = self.opt_local.clean_on_error;
+        let checksum = self.opt_local.checksum;
+        let name = file_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        tokio::spawn(async move {
+            match transfer.receive(&file_path, netascii, options).await {
+                Ok(size) => {
+                    log::info!("Received {name} ({size} bytes) from {to}");
+                    if checksum.is_some() {
+                        let _ = tokio::task::spawn_blocking(move || {
+                            log_checksum(checksum, &file_path, &DiskFs)
+                        })
+                        .await;
+                    }
+                }
+                Err(err) => {
+                    log::error!("Error \"{err}\", while receiving {name} from {to}");
+                    if clean_on_error && tokio::fs::remove_file(&file_path).await.is_err() {
+                        log::error!("Error while cleaning {}", file_path.display());
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Returns the path and size of the requested file, or the error to reply with.
+    fn find(&self, filename: &str) -> Result<(PathBuf, u64), (ErrorCode, String)> {
+        if let Some(fs) = &self.fs {
+            let name = normalize_name(filename);
+            return match fs.metadata(Path::new(&name)) {
+                Ok(metadata) => Ok((PathBuf::from(name), metadata.len)),
+                Err(_) => Err((
+                    ErrorCode::FileNotFound,
+                    format!("file {name} does not exist"),
+                )),
+            };
+        }
+
+        let (directory, name) = self.roots.resolve(&self.send_directory, filename);
+        let file_path = resolve_file_path(directory, &name, self.case_insensitive);
+        match check_file_exists(&file_path, directory, &self.symlinks) {
+            ErrorCode::FileExists => match file_path.metadata() {
+                Ok(metadata) => Ok((file_path, metadata.len())),
+                Err(e) => Err((ErrorCode::AccessViolation, e.to_string())),
+            },
+            ErrorCode::AccessViolation => Err((
+                ErrorCode::AccessViolation,
+                format!("file access violation: {}", file_path.display()),
+            )),
+            _ => Err((
+                ErrorCode::FileNotFound,
+                format!("file {} does not exist", file_path.display()),
+            )),
+        }
+    }
+
+    fn open(&self, file_path: &Path, offset: u64) -> io::Result<Reader> {
+        match &self.fs {
+            Some(fs) => fs.open_read(file_path, offset),
+            None => DiskFs.open_read(file_path, offset),
+        }
+    }
+
+    async fn transfer_socket(&self, to: SocketAddr) -> anyhow::Result<UdpSocket> {
+        let socket = bind_async(transfer_addr(self.local_addr()?), self.dual_stack)?;
+        socket.connect(to).await?;
+        Ok(socket)
+    }
+
+    async fn send_error(&self, code: ErrorCode, msg: &str, to: SocketAddr) -> anyhow::Result<()> {
+        let packet = Packet::Error {
+            code,
+            msg: msg.to_string(),
+        };
+        self.socket.send_to(&packet.serialize()?, to).await?;
+        Ok(())
+    }
+}
+
+/// Returns the settings of `config` the async server does not support, which
+/// it refuses rather than running with fewer protections or features than
+/// configured.
+fn unsupported_settings(config: &Config) -> Vec<&'static str> {
+    let versioned = matches!(
+        config.overwrite,
+        Some(OverwritePolicy::RenameWithSuffix | OverwritePolicy::KeepVersioned)
+    );
+    [
+        ("addresses", config.addresses.is_some()),
+        ("single_port", config.single_port.unwrap_or(false)),
+        ("user", config.user.is_some()),
+        ("group", config.group.is_some()),
+        ("chroot", config.chroot.unwrap_or(false)),
+        ("protected_paths", config.protected_paths.is_some()),
+        ("upload_quota", config.upload_quota.is_some()),
+        ("journal", config.journal.is_some()),
+        ("overwrite", versioned),
+        ("atomic_uploads", config.atomic_uploads.unwrap_or(false)),
+        ("manifest", config.manifest.unwrap_or(false)),
+        ("upload_webhook", config.upload_webhook.is_some()),
+        ("admin_socket", config.admin_socket.is_some()),
+        ("dscp", config.dscp.is_some()),
+        ("templates", config.templates.is_some()),
+        ("compress", config.compress.unwrap_or(false)),
+        ("mirror", config.mirror.is_some()),
+        ("s3", config.s3.is_some()),
+        ("dtls", config.dtls.is_some()),
+        ("cache_size", config.cache_size.is_some()),
+        ("mmap_threshold", config.mmap_threshold.is_some()),
+        ("transfer_log", config.transfer_log.is_some()),
+        ("log_format", config.log_format == Some(LogFormat::Json)),
+        ("metrics_addr", config.metrics_addr.is_some()),
+        ("pcap", config.pcap.is_some()),
+        ("session_dir", config.session_dir.is_some()),
+        ("dynamic", !config.dynamic.is_empty()),
+        ("rate_limit", config.rate_limit.is_some()),
+        ("client_rate_limit", config.client_rate_limit.is_some()),
+        ("total_rate_limit", config.total_rate_limit.is_some()),
+        ("listing", config.listing.unwrap_or(false)),
+        ("max_transfers", config.max_transfers.is_some()),
+        ("max_queued", config.max_queued.is_some()),
+        ("priorities", config.priorities.is_some()),
+        ("worker_threads", config.worker_threads.is_some()),
+        ("core_threads", config.core_threads.is_some()),
+        ("max_threads", config.max_threads.is_some()),
+        ("thread_queue", config.thread_queue.is_some()),
+        ("thread_idle_timeout", config.thread_idle_timeout.is_some()),
+        (
+            "transfer_idle_timeout",
+            config.transfer_idle_timeout.is_some(),
+        ),
+        ("multicast_address", config.multicast_address.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect()
+}
+
+/// Transfer `struct` is the async counterpart of [`Worker`](super::Worker),
+/// it sends or receives one file over a connected socket.
+struct Transfer {
+    socket: UdpSocket,
+    opt_local: OptionsPrivate,
+    opt_common: OptionsProtocol,
+    buffer: Vec<u8>,
+}
+
+impl Transfer {
+    fn new(socket: UdpSocket, opt_local: OptionsPrivate, opt_common: OptionsProtocol) -> Self {
+        let buffer = vec![0; opt_common.block_size as usize + 4];
+        Transfer {
+            socket,
+            opt_local,
+            opt_common,
+            buffer,
+        }
+    }
+
+    async fn send(mut self, reader: Reader, options: Vec<TransferOption>) -> anyhow::Result<()> {
+        let window = Window::new(
+            self.opt_common.window_size,
+            self.opt_common.block_size,
+            reader,
+        )
+        .with_rollover(self.opt_local.rollover);
+        let (mut window, mut more) = fill(window).await?;
+
+        if !options.is_empty() {
+            self.send_packet(&Packet::Oack {
+                options,
+                custom: vec![],
+            })
+            .await?;
+            let deadline = Instant::now() + self.opt_common.timeout;
+            let response = self.recv_until(deadline).await?;
+            // Error packets are not answered, error 8 ends option negotiation
+            if let Some(Packet::Error { code, msg }) = response {
+                anyhow::bail!("Client ended option negotiation with error {code}: {msg}");
+            }
+            if !matches!(response, Some(Packet::Ack(0))) {
+                self.send_error(ErrorCode::IllegalOperation, "invalid oack response")
+                    .await?;
+                match response {
+                    Some(packet) => {
+                        anyhow::bail!("Unexpected packet {packet} received instead of Ack(0)")
+                    }
+                    None => anyhow::bail!("No packet received instead of Ack(0)"),
+                }
+            }
+        }
+
+        let mut block_seq_win: u16 = 0;
+        let mut retry_cnt = 0;
+
+        loop {
+            for (i, (block_num, data)) in window.resend_from(block_seq_win)?.enumerate() {
+                if i > 0 && !self.opt_common.window_wait.is_zero() {
+                    time::sleep(self.opt_common.window_wait).await;
+                }
+                if block_num == 0 && self.opt_local.rollover == Rollover::None {
+                    return Err(self.rollover_error().await);
+                }
+                let packet = Packet::Data {
+                    block_num,
+                    data: data.to_vec(),
+                };
+                self.send_packet(&packet).await?;
+            }
+
+            match self.recv_ack(block_seq_win, window.len()).await? {
+                Some((ack, diff)) => {
+                    // The window slides when the Ack covers every frame sent,
+                    // otherwise it reports a loss and sending restarts right
+                    // after it, as defined by RFC 7440
+                    block_seq_win = ack;
+                    window.remove(diff)?;
+                    retry_cnt = 0;
+                    if !more && window.is_empty() {
+                        return Ok(());
+                    }
+                    if more {
+                        (window, more) = fill(window).await?;
+                    }
+                }
+                None => {
+                    log::info!("  Ack timeout {}/{}", retry_cnt, self.opt_local.max_retries);
+                    if retry_cnt == self.opt_local.max_retries {
+                        anyhow::bail!(
+                            "Transfer timed out after {} tries",
+                            self.opt_local.max_retries
+                        );
+                    }
+                    retry_cnt += 1;
+                }
+            }
+        }
+    }
+
+    /// Waits for an Ack moving the window of `sent` frames, and returns the
+    /// highest one received with its distance from the window start.
+    async fn recv_ack(
+        &mut self,
+        block_seq_win: u16,
+        sent: u16,
+    ) -> anyhow::Result<Option<(u16, u16)>> {
+        let deadline = Instant::now() + self.opt_common.timeout;
+        let mut best_ack: Option<(u16, u16)> = None;
+
+        loop {
+            let packet = match best_ack {
+                // Drains the Acks already queued before moving the window
+                Some(_) => match self.socket.try_recv(&mut self.buffer) {
+                    Ok(size) => match Packet::deserialize(&self.buffer[..size]) {
+                        Ok(packet) => packet,
+                        Err(err) => {
+                            log::warn!("  Ignoring malformed packet: {err}");
+                            continue;
+                        }
+                    },
+                    Err(_) => return Ok(best_ack),
+                },
+                None => match self.recv_until(deadline).await? {
+                    Some(packet) => packet,
+                    None => return Ok(None),
+                },
+            };
+
+            match packet {
+                Packet::Ack(ack) => {
+                    let diff = ack_distance(block_seq_win, ack, self.opt_local.rollover);
+                    if diff == 0 || diff > sent {
+                        log::debug!(
+                            "      Ignoring stale or duplicate Ack {ack} (prev {block_seq_win})"
+                        );
+                    } else if best_ack.is_none_or(|(_, best)| diff > best) {
+                        best_ack = Some((ack, diff));
+                    }
+                }
+                Packet::Error { code, msg } => {
+                    anyhow::bail!("Received error code {code}: {msg}");
+                }
+                packet => log::info!("  Received unexpected packet {packet}"),
+            }
+        }
+    }
+
+    async fn receive(
+        mut self,
+        file_path: &Path,
+        netascii: bool,
+        options: Vec<TransferOption>,
+    ) -> anyhow::Result<u64> {
+        let mut file = tokio::fs::File::create(file_path).await?;
+        // Blocks are converted to local line endings before being written
+        let mut convert = netascii.then(|| Convert::from_netascii(Vec::new()));
+        let block_size = self.opt_common.block_size as usize;
+
+        let mut block_number: u16 = 0;
+        let mut received: u64 = 0;
+        let mut in_window: u16 = 0;
+        let mut retry_cnt = 0;
+        self.send_ack(block_number, received, &options).await?;
+
+        loop {
+            let deadline = Instant::now() + self.opt_common.timeout;
+            match self.recv_until(deadline).await? {
+                Some(Packet::Data {
+                    block_num: received_block_number,
+                    data,
+                }) => {
+                    let expected = self.next_block(block_number, received_block_number).await?;
+                    if received_block_number != expected {
+                        log::debug!(
+                            "  Data packet mismatch. Received {received_block_number} instead of {expected}."
+                        );
+                        self.send_ack(block_number, received, &options).await?;
+                        in_window = 0;
+                        continue;
+                    }
+
+                    block_number = received_block_number;
+                    retry_cnt = 0;
+                    let last = data.len() < block_size;
+                    match &mut convert {
+                        Some(convert) => {
+                            convert.write_all(&data)?;
+                            if last {
+                                convert.end()?;
+                            }
+                            file.write_all(convert.get_mut()).await?;
+                            convert.get_mut().clear();
+                        }
+                        None => file.write_all(&data).await?,
+                    }
+                    received += data.len() as u64;
+                    in_window += 1;
+
+                    if last {
+                        // Written before the final Ack, which lets the client read the file
+                        file.flush().await?;
+                    }
+                    if last || in_window == self.opt_common.window_size {
+                        self.send_ack(block_number, received, &options).await?;
+                        in_window = 0;
+                    }
+                    if last {
+                        // Acknowledges retransmissions if the final Ack gets lost
+                        tokio::spawn(self.dally());
+                        return Ok(received);
+                    }
+                }
+                Some(Packet::Error { code, msg }) => {
+                    anyhow::bail!("Received error '{code}': {msg}");
+                }
+                Some(packet) => log::info!("  Received unexpected packet {packet}"),
+                None => {
+                    log::debug!(
+                        "  Data timeout {}/{}",
+                        retry_cnt,
+                        self.opt_local.max_retries
+                    );
+                    if retry_cnt == self.opt_local.max_retries {
+                        anyhow::bail!(
+                            "Transfer timed out after {} tries",
+                            self.opt_local.max_retries
+                        );
+                    }
+                    retry_cnt += 1;
+                    self.send_ack(block_number, received, &options).await?;
+                    in_window = 0;
+                }
+            }
+        }
+    }
+
+    /// Keeps acknowledging retransmissions of the last block for the timeout,
+    /// as per RFC 1350 section 6.
+    async fn dally(mut self) {
+        let deadline = Instant::now() + self.opt_common.timeout;
+        while let Ok(Some(packet)) = self.recv_until(deadline).await {
+            if let Packet::Data { block_num, .. } = packet {
+                log::debug!("  Acknowledging retransmitted block {block_num}");
+                if self.send_packet(&Packet::Ack(block_num)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Acknowledges `block_number`, repeating the OACK until data arrives.
+    async fn send_ack(
+        &self,
+        block_number: u16,
+        received: u64,
+        options: &[TransferOption],
+    ) -> anyhow::Result<()> {
+        if block_number == 0 && received == 0 && !options.is_empty() {
+            return self
+                .send_packet(&Packet::Oack {
+                    options: options.to_vec(),
+                    custom: vec![],
+                })
+                .await;
+        }
+        self.send_packet(&Packet::Ack(block_number)).await
+    }
+
+    /// Returns the block number expected after `block_number`, following the
+    /// rollover policy when the counter wraps.
+    async fn next_block(&self, block_number: u16, received: u16) -> anyhow::Result<u16> {
+        let next = block_number.wrapping_add(1);
+        if next != 0 {
+            return Ok(next);
+        }
+
+        match self.opt_local.rollover {
+            Rollover::None => Err(self.rollover_error().await),
+            Rollover::Enforce0 => Ok(0),
+            Rollover::Enforce1 => Ok(1),
+            Rollover::DontCare => Ok(if received == 1 { 1 } else { 0 }),
+        }
+    }
+
+    async fn rollover_error(&self) -> anyhow::Error {
+        if let Err(err) = self
+            .send_error(ErrorCode::IllegalOperation, "Block counter rollover error")
+            .await
+        {
+            log::error!("Error: error '{err:?}' while sending error code");
+        }
+        anyhow::anyhow!("Block counter rollover error")
+    }
+
+    /// Receives the next packet, or `None` once `deadline` is reached.
+    async fn recv_until(&mut self, deadline: Instant) -> anyhow::Result<Option<Packet>> {
+        loop {
+            match time::timeout_at(deadline, self.socket.recv(&mut self.buffer)).await {
+                Err(_) => return Ok(None),
+                Ok(Ok(size)) => match Packet::deserialize(&self.buffer[..size]) {
+                    Ok(packet) => {
+                        log::trace!("  Received {packet}");
+                        return Ok(Some(packet));
+                    }
+                    Err(err) => log::warn!("  Ignoring malformed packet: {err}"),
+                },
+                Ok(Err(e)) => {
+                    log::info!("  IO error during reception {e:?}");
+                    // Avoids spinning on errors reported again right away
+                    time::sleep(DEFAULT_DUPLICATE_DELAY).await;
+                }
+            }
+        }
+    }
+
+    async fn send_packet(&self, packet: &Packet) -> anyhow::Result<()> {
+        log::trace!("  Sending {packet}");
+        let bytes = packet.serialize()?;
+        for i in 0..self.opt_local.repeat_count {
+            if i > 0 {
+                time::sleep(DEFAULT_DUPLICATE_DELAY).await;
+            }
+            self.socket.send(&bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_error(&self, code: ErrorCode, msg: &str) -> anyhow::Result<()> {
+        let packet = Packet::Error {
+            code,
+            msg: msg.to_string(),
+        };
+        self.socket.send(&packet.serialize()?).await?;
+        Ok(())
+    }
+}
+
+/// Binds a UDP socket of the tokio runtime, see [`bind_udp()`].
+fn bind_async(addr: SocketAddr, dual_stack: bool) -> io::Result<UdpSocket> {
+    let socket = bind_udp(addr, dual_stack)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+/// Fills `window` from its reader on the blocking thread pool.
+async fn fill(mut window: Window<Reader>) -> anyhow::Result<(Window<Reader>, bool)> {
+    tokio::task::spawn_blocking(move || {
+        let more = window.fill()?;
+        Ok((window, more))
+    })
+    .await?
+}
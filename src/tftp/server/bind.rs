@@ -0,0 +1,243 @@
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use super::Config;
+
+/// Returns the addresses the server listens on: those of `addresses` in
+/// `config` if set, each an IP address or the name of a network interface
+/// standing for all of its addresses, or else the address of `ip`.
+pub(super) fn listen_addrs(config: &Config) -> anyhow::Result<Vec<SocketAddr>> {
+    let Some(addresses) = config.addresses.as_ref().filter(|a| !a.is_empty()) else {
+        return Ok(vec![listen_addr(config)?]);
+    };
+    let port = config.port.unwrap_or(69);
+    let mut addrs = Vec::new();
+    for address in addresses {
+        match parse_addr(address, port) {
+            Some(addr) => addrs.push(addr),
+            None => addrs.extend(interface_addrs(address, port)?),
+        }
+    }
+    Ok(addrs)
+}
+
+/// Returns the address the server listens on, from the `ip` and `port` of
+/// `config`. IPv6 addresses may carry the numeric scope of a link-local
+/// address, e.g. `fe80::1%2`.
+///
+/// In dual-stack mode the server listens on `[::]`, serving IPv4 clients as
+/// IPv4-mapped addresses, so `ip` must be left unspecified.
+pub(super) fn listen_addr(config: &Config) -> anyhow::Result<SocketAddr> {
+    let ip = config.ip.as_deref().unwrap_or("0.0.0.0");
+    let port = config.port.unwrap_or(69);
+    let addr =
+        parse_addr(ip, port).ok_or_else(|| anyhow::anyhow!("Invalid listen address {ip}"))?;
+
+    if !config.dual_stack.unwrap_or(false) {
+        return Ok(addr);
+    }
+    if !addr.ip().is_unspecified() {
+        return Err(anyhow::anyhow!(
+            "Dual-stack mode listens on all addresses, {ip} cannot be set"
+        ));
+    }
+    Ok(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))
+}
+
+/// Returns `address` on `port`, if an IP address, possibly with a scope.
+fn parse_addr(address: &str, port: u16) -> Option<SocketAddr> {
+    match address.parse::<IpAddr>() {
+        Ok(ip) => Some(SocketAddr::from((ip, port))),
+        Err(_) => format!("[{address}]:{port}").parse().ok(),
+    }
+}
+
+/// Returns the addresses of the network interface `name` on `port`, with
+/// the scope of the interface for IPv6 link-local ones.
+#[cfg(unix)]
+fn interface_addrs(name: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    use std::net::{Ipv4Addr, SocketAddrV6};
+
+    let mut interfaces = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut interfaces) } != 0 {
+        let err = io::Error::last_os_error();
+        return Err(anyhow::anyhow!("Cannot list the network interfaces: {err}"));
+    }
+    let mut addrs = Vec::new();
+    let mut interface = interfaces;
+    while !interface.is_null() {
+        let entry = unsafe { &*interface };
+        interface = entry.ifa_next;
+        let interface_name = unsafe { std::ffi::CStr::from_ptr(entry.ifa_name) };
+        if entry.ifa_addr.is_null() || interface_name.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        match unsafe { (*entry.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+                addrs.push(SocketAddr::from((ip, port)));
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                // Only link-local addresses are scoped to the interface
+                let scope = if ip.is_unicast_link_local() {
+                    addr.sin6_scope_id
+                } else {
+                    0
+                };
+                addrs.push(SocketAddrV6::new(ip, port, 0, scope).into());
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(interfaces) };
+
+    if addrs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Invalid listen address {name}, neither an IP address nor an interface with one"
+        ));
+    }
+    Ok(addrs)
+}
+
+#[cfg(not(unix))]
+fn interface_addrs(name: &str, _port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    Err(anyhow::anyhow!(
+        "Invalid listen address {name}, interface names are only supported on Unix"
+    ))
+}
+
+/// Binds a UDP socket to `addr`. An IPv6 socket also receives from IPv4
+/// clients if `dual_stack`, whatever the default of the system.
+pub(super) fn bind_udp(addr: SocketAddr, dual_stack: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() && dual_stack {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Marks the packets sent from `socket` with the DSCP value `dscp`, in the
+/// traffic class of IPv6 packets or the type of service of IPv4 ones.
+pub(super) fn set_dscp(socket: &UdpSocket, dscp: u8) -> io::Result<()> {
+    // DSCP takes the upper six bits, the lower two are left to ECN
+    let tos = u32::from(dscp) << 2;
+    let socket = SockRef::from(socket);
+    if socket.local_addr()?.is_ipv4() {
+        return socket.set_tos_v4(tos);
+    }
+    // IPv4 clients of dual-stack sockets are sent IPv4 packets
+    let _ = socket.set_tos_v4(tos);
+    set_tclass_v6(&socket, tos)
+}
+
+#[cfg(unix)]
+fn set_tclass_v6(socket: &SockRef, tclass: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let tclass = tclass as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &tclass as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_tclass_v6(_socket: &SockRef, _tclass: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the traffic class of IPv6 packets is only set on Unix",
+    ))
+}
+
+/// Returns the address the socket of a transfer binds to, the IP of the
+/// server address `listen` with its scope, on any port.
+pub(super) fn transfer_addr(listen: SocketAddr) -> SocketAddr {
+    let mut addr = listen;
+    addr.set_port(0);
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listen_addresses() {
+        let listen = |ip: &str, dual_stack| {
+            let config = Config::with_defaults().with_dual_stack(dual_stack);
+            listen_addr(&Config {
+                ip: Some(ip.to_string()),
+                ..config
+            })
+        };
+
+        assert_eq!(listen("0.0.0.0", false).unwrap().to_string(), "0.0.0.0:69");
+        assert_eq!(listen("::1", false).unwrap().to_string(), "[::1]:69");
+        let link_local = listen("fe80::1%2", false).unwrap();
+        assert_eq!(link_local.to_string(), "[fe80::1%2]:69");
+        assert_eq!(transfer_addr(link_local).to_string(), "[fe80::1%2]:0");
+        assert!(listen("tftp.local", false).is_err());
+
+        assert_eq!(listen("0.0.0.0", true).unwrap().to_string(), "[::]:69");
+        assert_eq!(listen("::", true).unwrap().to_string(), "[::]:69");
+        assert!(listen("192.168.1.1", true).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resolves_interface_names() {
+        let config = Config {
+            addresses: Some(vec!["127.0.0.2".to_string(), "lo".to_string()]),
+            ..Config::with_defaults()
+        };
+        let addrs = listen_addrs(&config).unwrap();
+        assert_eq!(addrs[0].to_string(), "127.0.0.2:69");
+        assert!(addrs[1..].contains(&"127.0.0.1:69".parse().unwrap()));
+
+        let config = Config {
+            addresses: Some(vec!["no-such-interface0".to_string()]),
+            ..Config::with_defaults()
+        };
+        assert!(listen_addrs(&config).is_err());
+    }
+
+    #[test]
+    fn serves_both_families_in_dual_stack() {
+        let server = bind_udp("[::]:0".parse().unwrap(), true).unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"v4", ("127.0.0.1", port)).unwrap();
+        let mut buf = [0; 2];
+        let (_, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(from.ip().to_canonical(), client.local_addr().unwrap().ip());
+    }
+
+    #[test]
+    fn marks_packets_with_dscp() {
+        let socket = bind_udp("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        set_dscp(&socket, 46).unwrap();
+        assert_eq!(SockRef::from(&socket).tos_v4().unwrap(), 46 << 2);
+
+        #[cfg(unix)]
+        {
+            let socket = bind_udp("[::]:0".parse().unwrap(), true).unwrap();
+            set_dscp(&socket, 10).unwrap();
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use super::provider::normalize_name;
+use crate::tftp::core::Packet;
+
+/// BootMapping `struct` serves another boot tree, or other files, to the
+/// clients of a network, so that one server boots several pools of devices
+/// differently.
+///
+/// The names requested by the `clients` are looked up in `files` first, the
+/// file they map to being served instead. Other names are served below
+/// `prefix`, a directory of the root or a prefix of the
+/// [`roots`](super::Config::with_root). Mappings apply before the requested
+/// names are resolved, after the rewrites, and the mapping of the smallest
+/// network holding the client wins.
+///
+/// # Example
+///
+/// ```toml
+/// [[tftpd.boot_maps]]
+/// clients = "192.168.10.0/24"
+/// prefix = "rescue/"
+///
+/// [[tftpd.boot_maps]]
+/// clients = "192.168.10.42/32"
+/// files = { "pxelinux.0" = "debug/pxelinux.0" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootMapping {
+    pub clients: IpNet,
+    /// Prefix of the names requested by the clients
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Files served instead of the requested ones, keyed by requested name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+}
+
+/// BootMap `struct` applies the [`BootMapping`]s of a server.
+#[derive(Debug, Clone, Default)]
+pub(super) struct BootMap {
+    mappings: Vec<BootMapping>,
+}
+
+impl BootMap {
+    pub fn new(mappings: &[BootMapping]) -> BootMap {
+        let mut mappings: Vec<BootMapping> = mappings
+            .iter()
+            .map(|mapping| BootMapping {
+                files: mapping
+                    .files
+                    .iter()
+                    .map(|(name, file)| (normalize_name(name), file.clone()))
+                    .collect(),
+                ..mapping.clone()
+            })
+            .collect();
+        mappings.sort_by_key(|mapping| std::cmp::Reverse(mapping.clients.prefix_len()));
+        BootMap { mappings }
+    }
+
+    /// Returns the name served for `filename` requested by `client`, if a
+    /// mapping applies.
+    pub fn map(&self, client: IpAddr, filename: &str) -> Option<String> {
+        // IPv4 clients of dual-stack sockets show up as ::ffff:a.b.c.d
+        let client = client.to_canonical();
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.clients.contains(&client))?;
+        let name = normalize_name(filename);
+        if let Some(file) = mapping.files.get(&name) {
+            return Some(file.clone());
+        }
+        let prefix = mapping.prefix.as_deref()?;
+        Some(format!("{}/{name}", prefix.trim_end_matches(['/', '\\'])))
+    }
+
+    /// Maps the file name of a read or write request from `client` in place.
+    pub fn map_request(&self, packet: &mut Packet, client: IpAddr) {
+        if self.mappings.is_empty() {
+            return;
+        }
+        if let Packet::Rrq { filename, .. } | Packet::Wrq { filename, .. } = packet
+            && let Some(mapped) = self.map(client, filename)
+        {
+            log::debug!("Mapped {filename} requested by {client} to {mapped}");
+            *filename = mapped;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_most_specific_network() {
+        let map = BootMap::new(&[
+            BootMapping {
+                clients: "192.168.10.0/24".parse().unwrap(),
+                prefix: Some("rescue/".to_string()),
+                files: BTreeMap::new(),
+            },
+            BootMapping {
+                clients: "192.168.10.42/32".parse().unwrap(),
+                prefix: None,
+                files: BTreeMap::from([(
+                    "/pxelinux.0".to_string(),
+                    "debug/pxelinux.0".to_string(),
+                )]),
+            },
+        ]);
+        let ip = |ip: &str| ip.parse().unwrap();
+
+        assert_eq!(
+            map.map(ip("192.168.10.7"), "/pxelinux.0").as_deref(),
+            Some("rescue/pxelinux.0")
+        );
+        assert_eq!(
+            map.map(ip("::ffff:192.168.10.7"), "boot/vmlinuz")
+                .as_deref(),
+            Some("rescue/boot/vmlinuz")
+        );
+        assert_eq!(
+            map.map(ip("192.168.10.42"), "pxelinux.0").as_deref(),
+            Some("debug/pxelinux.0")
+        );
+        // Only the files of the host are mapped
+        assert_eq!(map.map(ip("192.168.10.42"), "boot/vmlinuz"), None);
+        assert_eq!(map.map(ip("192.168.11.7"), "pxelinux.0"), None);
+    }
+}
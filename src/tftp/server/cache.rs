@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::fs::{DiskFs, Metadata, TftpFs};
+
+/// Largest file kept by default, enough for most kernels and initramfs
+pub(super) const DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// FileCache `struct` keeps the content of the small files downloaded from
+/// disk in memory, so that many machines booting at once read their boot
+/// loader, configuration and kernel from RAM instead of a slow SD card.
+///
+/// Files up to `max_file_size` bytes are kept, evicting the least recently
+/// used ones beyond `capacity` bytes. A file is read again from disk once its
+/// size or modification time changed, e.g. after an upload replaced it.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::Read;
+/// use xtool::tftp::server::{FileCache, TftpFs};
+///
+/// let path = std::env::temp_dir().join("xtool-cache-example.cfg");
+/// std::fs::write(&path, b"default linux").unwrap();
+///
+/// let cache = FileCache::new(1024 * 1024, 64 * 1024);
+/// let mut content = String::new();
+/// cache.open_read(&path, 8).unwrap().read_to_string(&mut content).unwrap();
+/// assert_eq!(content, "linux");
+/// assert_eq!(cache.size(), 13);
+/// ```
+#[derive(Debug)]
+pub struct FileCache {
+    capacity: u64,
+    max_file_size: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    files: HashMap<PathBuf, Entry>,
+    /// Bytes of the files kept
+    size: u64,
+    /// Incremented on every use of a file, to find the least recently used
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    content: Arc<[u8]>,
+    modified: Option<SystemTime>,
+    used: u64,
+}
+
+impl FileCache {
+    /// Creates a cache keeping files up to `max_file_size` bytes, at most
+    /// `capacity` bytes in total.
+    pub fn new(capacity: u64, max_file_size: u64) -> FileCache {
+        FileCache {
+            capacity,
+            max_file_size,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the bytes of the files kept.
+    #[allow(dead_code)]
+    pub fn size(&self) -> u64 {
+        self.state.lock().unwrap().size
+    }
+
+    /// Returns the content of the file at `path`, read from disk unless kept
+    /// and unchanged, or `None` if the file is too large to be kept.
+    pub fn get(&self, path: &Path) -> io::Result<Option<Arc<[u8]>>> {
+        let metadata = path.metadata()?;
+        let len = metadata.len();
+        if len > self.max_file_size.min(self.capacity) {
+            return Ok(None);
+        }
+        let modified = metadata.modified().ok();
+
+        // Kept locked while reading, so that simultaneous requests read the file once
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(entry) = state.files.get_mut(path)
+            && entry.content.len() as u64 == len
+            && entry.modified == modified
+        {
+            entry.used = clock;
+            return Ok(Some(entry.content.clone()));
+        }
+
+        let content: Arc<[u8]> = std::fs::read(path)?.into();
+        if let Some(previous) = state.files.remove(path) {
+            state.size -= previous.content.len() as u64;
+        }
+        while state.size + content.len() as u64 > self.capacity {
+            let Some(oldest) = state
+                .files
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = state.files.remove(&oldest) {
+                log::debug!("  Evicted {} from the file cache", oldest.display());
+                state.size -= entry.content.len() as u64;
+            }
+        }
+        state.size += content.len() as u64;
+        state.files.insert(
+            path.to_path_buf(),
+            Entry {
+                content: content.clone(),
+                modified,
+                used: clock,
+            },
+        );
+        Ok(Some(content))
+    }
+}
+
+/// Reads the files of the local filesystem, through the cache when small
+/// enough.
+impl TftpFs for FileCache {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        DiskFs.metadata(path)
+    }
+
+    fn open_read(&self, path: &Path, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        match self.get(path)? {
+            Some(content) => {
+                let mut reader = io::Cursor::new(content);
+                reader.set_position(offset);
+                Ok(Box::new(reader))
+            }
+            None => DiskFs.open_read(path, offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used_files() {
+        let dir = std::env::temp_dir().join(format!("xtool-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, len: usize| {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0x42; len]).unwrap();
+            path
+        };
+        let pxelinux = file("pxelinux.0", 40);
+        let grub = file("grub.cfg", 40);
+        let kernel = file("vmlinuz", 40);
+        let initrd = file("initrd.img", 200);
+
+        let cache = FileCache::new(100, 100);
+        cache.get(&pxelinux).unwrap().unwrap();
+        cache.get(&grub).unwrap().unwrap();
+        cache.get(&pxelinux).unwrap().unwrap();
+        assert_eq!(cache.size(), 80);
+
+        // grub.cfg is the least recently used
+        cache.get(&kernel).unwrap().unwrap();
+        assert_eq!(cache.size(), 80);
+        {
+            let state = cache.state.lock().unwrap();
+            assert!(state.files.contains_key(&pxelinux));
+            assert!(!state.files.contains_key(&grub));
+        }
+
+        // Too large to be kept
+        assert!(cache.get(&initrd).unwrap().is_none());
+
+        // Changed files are read again
+        std::fs::write(&pxelinux, b"updated").unwrap();
+        assert_eq!(&*cache.get(&pxelinux).unwrap().unwrap(), b"updated");
+        assert_eq!(cache.size(), 47);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
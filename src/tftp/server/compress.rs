@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::tftp::core::options::COMPRESS_GZIP;
+use crate::tftp::core::{OptionType, TransferOption};
+
+/// Bytes of compressed files kept by default
+pub(super) const DEFAULT_CAPACITY: u64 = 64 * 1024 * 1024;
+
+/// Compressor `struct` compresses the files served with gzip on the fly, so
+/// that large text artifacts such as logs, kernel configurations or SBOMs
+/// cross slow lab links in a fraction of the time.
+///
+/// A file is compressed once and kept until its size or modification time
+/// changes, evicting the least recently served ones beyond `capacity` bytes
+/// of compressed content.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd]
+/// compress = true
+/// ```
+#[derive(Debug)]
+pub(super) struct Compressor {
+    capacity: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    files: HashMap<PathBuf, Entry>,
+    /// Bytes of the compressed files kept
+    size: u64,
+    /// Incremented on every use of a file, to find the least recently used
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    content: Arc<[u8]>,
+    len: u64,
+    modified: Option<SystemTime>,
+    used: u64,
+}
+
+impl Compressor {
+    /// Creates a compressor keeping at most `capacity` bytes of compressed
+    /// files.
+    pub fn new(capacity: u64) -> Compressor {
+        Compressor {
+            capacity,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the content of the file at `path` compressed with gzip,
+    /// compressed again only if the file changed since.
+    pub fn compress(&self, path: &Path) -> io::Result<Arc<[u8]>> {
+        let metadata = path.metadata()?;
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+
+        // Kept locked while compressing, so that simultaneous requests compress the file once
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(entry) = state.files.get_mut(path)
+            && entry.len == len
+            && entry.modified == modified
+        {
+            entry.used = clock;
+            return Ok(entry.content.clone());
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&std::fs::read(path)?)?;
+        let content: Arc<[u8]> = encoder.finish()?.into();
+        log::info!(
+            "  Compressed {} from {len} to {} bytes",
+            path.display(),
+            content.len()
+        );
+
+        if let Some(previous) = state.files.remove(path) {
+            state.size -= previous.content.len() as u64;
+        }
+        if content.len() as u64 > self.capacity {
+            return Ok(content);
+        }
+        while state.size + content.len() as u64 > self.capacity {
+            let Some(oldest) = state
+                .files
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = state.files.remove(&oldest) {
+                state.size -= entry.content.len() as u64;
+            }
+        }
+        state.size += content.len() as u64;
+        state.files.insert(
+            path.to_path_buf(),
+            Entry {
+                content: content.clone(),
+                len,
+                modified,
+                used: clock,
+            },
+        );
+        Ok(content)
+    }
+}
+
+/// Removes the compress option from `options`, returning whether the client
+/// asked for the file compressed with gzip.
+pub(super) fn take_compress(options: &mut Vec<TransferOption>) -> bool {
+    let gzip = options
+        .iter()
+        .any(|option| option.option == OptionType::Compress && option.value == COMPRESS_GZIP);
+    options.retain(|option| option.option != OptionType::Compress);
+    gzip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn compresses_files_once() {
+        let path = std::env::temp_dir().join(format!("xtool-compress-{}.log", std::process::id()));
+        std::fs::write(&path, "boot ok\n".repeat(1000)).unwrap();
+
+        let compressor = Compressor::new(DEFAULT_CAPACITY);
+        let content = compressor.compress(&path).unwrap();
+        assert!(content.len() < 8000);
+        let mut text = String::new();
+        GzDecoder::new(&content[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "boot ok\n".repeat(1000));
+        assert!(Arc::ptr_eq(&content, &compressor.compress(&path).unwrap()));
+
+        // Compressed again once changed
+        std::fs::write(&path, "boot failed\n").unwrap();
+        let changed = compressor.compress(&path).unwrap();
+        assert!(!Arc::ptr_eq(&content, &changed));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn takes_compress_option() {
+        let option = |option, value| TransferOption { option, value };
+        let mut options = vec![
+            option(OptionType::BlockSize, 1024),
+            option(OptionType::Compress, COMPRESS_GZIP),
+        ];
+        assert!(take_compress(&mut options));
+        assert_eq!(options, vec![option(OptionType::BlockSize, 1024)]);
+
+        let mut options = vec![option(OptionType::Compress, 0)];
+        assert!(!take_compress(&mut options));
+        assert!(options.is_empty());
+    }
+}
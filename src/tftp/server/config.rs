@@ -1,6 +1,30 @@
-use crate::tftp::core::options::{OptionsPrivate, Rollover};
+use crate::tftp::core::options::{
+    DEFAULT_READ_AHEAD, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, OptionsPrivate, Rollover,
+};
+use crate::tftp::core::{DigestAlgorithm, OptionType};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::acl::Acl;
+use super::boot_map::{BootMap, BootMapping};
+use super::dtls::DtlsListener;
+use super::dynamic::DynamicContent;
+use super::handler::TransferInfo;
+use super::json_log::LogFormat;
+use super::overwrite::{OverwritePolicy, deserialize_policy};
+use super::pool::{DEFAULT_CORE_THREADS, DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_THREADS, ThreadPool};
+use super::priority::PriorityClass;
+use super::rewrite::{RewriteRule, Rewriter};
+use super::roots::Roots;
+use super::s3::S3Config;
+use super::server::OptionLimits;
+use super::symlinks::{SymlinkPolicy, Symlinks};
+use super::transfer_log::LogRotation;
 
 /// TFTP server configuration
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -9,14 +33,192 @@ pub struct Config {
     pub ip: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
+    /// Addresses or interface names listened on instead of `ip`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<Vec<String>>,
+    /// Listen on `[::]` for both IPv6 and IPv4 clients
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dual_stack: Option<bool>,
+    /// DSCP value the packets sent by the server are marked with, 0 to 63
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dscp: Option<u8>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub directory: Option<PathBuf>,
+    /// Directory uploads are written to, instead of `directory`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receive_directory: Option<PathBuf>,
+    /// Directory downloads are served from, instead of `directory`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_directory: Option<PathBuf>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub single_port: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub read_only: Option<bool>,
+    /// Patterns of the text files whose `${client_ip}`, `${client_mac_hint}`
+    /// and `${server_ip}` are expanded before they are served, e.g. `*.ipxe`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templates: Option<Vec<String>>,
+    /// Serve `name.gz` compressed on the fly when only `name` exists, and
+    /// files compressed to the clients sending the `compress` option
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+    /// HTTP(S) URL the files missing from the served directory are fetched
+    /// from and kept, e.g. `https://boot.example.com/tftp`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    /// Bucket of an S3-compatible storage served instead of `directory`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3: Option<S3Config>,
+    /// DTLS endpoint encrypting transfers with xtool clients
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dtls: Option<DtlsListener>,
+    /// Patterns of the files uploads may not write, e.g. `boot/**`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protected_paths: Option<Vec<String>>,
+    /// User the server runs as once the port is bound (Unix)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Group the server runs as once the port is bound, by default the group of `user`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Change root to `directory` before dropping privileges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chroot: Option<bool>,
+    /// What becomes of an existing file when a client uploads one of the same name
+    #[serde(
+        default,
+        deserialize_with = "deserialize_policy",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub overwrite: Option<OverwritePolicy>,
+    /// Match requested file names ignoring case
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub case_insensitive: Option<bool>,
+    /// Whether symbolic links leading out of the served directory are followed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlinks: Option<SymlinkPolicy>,
+    /// Directories symbolic links may lead to with the `allowlist` policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_allowlist: Option<Vec<PathBuf>>,
+    /// Directories serving the names starting with a prefix, instead of `directory`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roots: Option<BTreeMap<String, PathBuf>>,
+    /// Turn `\` into `/` in requested file names
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backslashes: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal: Option<PathBuf>,
+    /// Write uploads to a temporary file renamed once complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub atomic_uploads: Option<bool>,
+    /// File a line is appended to for every transfer, in the xferlog format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_log: Option<PathBuf>,
+    /// When the transfer log is rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_log_rotation: Option<LogRotation>,
+    /// Format of the log, `json` for an object per transfer event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+    /// Address metrics are served on over HTTP, at `/metrics`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Unix socket accepting line commands to manage the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_socket: Option<PathBuf>,
+    /// Directory the packets of every transfer are recorded to, for replay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_dir: Option<PathBuf>,
+    /// File the packets of every transfer are captured to, in pcap format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pcap: Option<PathBuf>,
+    /// Record completed uploads in `MANIFEST.sha256` in the upload directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<bool>,
+    /// URL a JSON object is POSTed to after every completed upload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_webhook: Option<String>,
+    /// Bytes uploads may write in total, further write requests are refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_quota: Option<u64>,
+    /// Bytes of small downloaded files kept in memory, no cache when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<u64>,
+    /// Largest file kept in the cache in bytes, 16 MiB by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_max_file_size: Option<u64>,
+    /// Serve the list of files of the root to `xtool tftpc mirror`
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub overwrite: Option<bool>,
+    pub listing: Option<bool>,
+    /// Only clients in these networks are served, when set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_networks: Option<Vec<IpNet>>,
+    /// Clients in these networks are refused, even if allowed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denied_networks: Option<Vec<IpNet>>,
+    /// Transfers in progress at once, further requests are queued or refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_transfers: Option<usize>,
+    /// Requests waiting for a transfer to complete, further ones are refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queued: Option<usize>,
+    /// Order in which waiting requests are started, first match wins
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priorities: Option<Vec<PriorityClass>>,
+    /// Fixed number of transfer threads, instead of `core_threads` and
+    /// `max_threads`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_threads: Option<usize>,
+    /// Transfer threads kept alive while idle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core_threads: Option<usize>,
+    /// Transfer threads running at once, further requests are queued or refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_threads: Option<usize>,
+    /// Transfers waiting for a busy core thread before more threads are started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_queue: Option<usize>,
+    /// Seconds threads beyond the core ones are kept while idle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_idle_timeout: Option<u64>,
+    /// Seconds without packets from the client after which a transfer is stopped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_idle_timeout: Option<u64>,
+    /// Group address files are multicast to, for the clients asking for it
+    /// with the multicast option of RFC 2090
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multicast_address: Option<Ipv4Addr>,
+    /// First port of the multicast groups, one per file being sent (default 1758)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multicast_port: Option<u16>,
+    /// Time to live of the multicast packets (default 1, the local network)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multicast_ttl: Option<u32>,
+    /// Rewrites of the requested file names, applied in order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrites: Option<Vec<RewriteRule>>,
+    /// Boot trees or files served to the clients of a network
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_maps: Option<Vec<BootMapping>>,
+    /// Options never negotiated, ignored in requests, e.g. `["windowsize"]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_options: Option<Vec<String>>,
+    /// Largest block size clients may negotiate, larger requests are clamped,
+    /// e.g. 1428 so that blocks are not fragmented on paths with a standard MTU
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_block_size: Option<u16>,
+    /// Largest window size clients may negotiate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_window_size: Option<u16>,
+    /// Timeout in seconds of the transfers whose clients negotiate none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Shortest timeout in seconds clients may negotiate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_timeout: Option<u64>,
+    /// Longest timeout in seconds clients may negotiate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_timeout: Option<u64>,
 
     // OptionsPrivate fields flattened
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -25,8 +227,32 @@ pub struct Config {
     pub clean_on_error: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_retries: Option<usize>,
+    /// Block number following 65535 in transfers of more blocks: `enforce0`
+    /// (default), `enforce1` for clients wrapping to 1, `dont_care` to accept
+    /// both in uploads, or `none` to refuse such transfers
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rollover: Option<Rollover>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<DigestAlgorithm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_ahead: Option<u16>,
+    /// Files of at least this many bytes are memory-mapped and sent without
+    /// copies. Mapped files must not be truncated, so uploads replacing them
+    /// should be atomic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mmap_threshold: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u64>,
+    /// Bytes per second shared by the transfers of each client IP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_rate_limit: Option<u64>,
+    /// Bytes per second shared by all transfers of the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_rate_limit: Option<u64>,
+
+    /// Generated files, only set through the library
+    #[serde(skip)]
+    pub dynamic: DynamicContent,
 }
 
 impl Config {
@@ -34,14 +260,77 @@ impl Config {
         Self {
             ip: Some("0.0.0.0".to_string()),
             port: Some(69),
+            addresses: None,
+            dual_stack: None,
+            dscp: None,
             directory: Some(PathBuf::from(".")),
+            receive_directory: None,
+            send_directory: None,
             single_port: Some(false),
             read_only: Some(false),
-            overwrite: Some(true),
+            protected_paths: None,
+            templates: None,
+            compress: None,
+            mirror: None,
+            s3: None,
+            dtls: None,
+            user: None,
+            group: None,
+            chroot: None,
+            overwrite: Some(OverwritePolicy::Overwrite),
+            case_insensitive: None,
+            symlinks: None,
+            symlink_allowlist: None,
+            backslashes: None,
+            roots: None,
+            journal: None,
+            atomic_uploads: None,
+            manifest: None,
+            upload_webhook: None,
+            session_dir: None,
+            pcap: None,
+            transfer_log: None,
+            transfer_log_rotation: None,
+            log_format: None,
+            metrics_addr: None,
+            admin_socket: None,
+            upload_quota: None,
+            cache_size: None,
+            cache_max_file_size: None,
+            listing: None,
+            allowed_networks: None,
+            denied_networks: None,
+            max_transfers: None,
+            max_queued: None,
+            priorities: None,
+            rewrites: None,
+            boot_maps: None,
+            max_block_size: None,
+            max_window_size: None,
+            disabled_options: None,
+            timeout: None,
+            min_timeout: None,
+            max_timeout: None,
+            worker_threads: None,
+            core_threads: None,
+            max_threads: None,
+            thread_queue: None,
+            thread_idle_timeout: None,
+            transfer_idle_timeout: None,
+            multicast_address: None,
+            multicast_port: None,
+            multicast_ttl: None,
             repeat_count: Some(1),
             clean_on_error: Some(true),
             max_retries: Some(6),
             rollover: Some(Rollover::Enforce0),
+            checksum: None,
+            read_ahead: Some(DEFAULT_READ_AHEAD),
+            mmap_threshold: None,
+            rate_limit: None,
+            client_rate_limit: None,
+            total_rate_limit: None,
+            dynamic: DynamicContent::default(),
         }
     }
 
@@ -71,7 +360,7 @@ impl Config {
 
         // Set defaults for others if not present
         if self.overwrite.is_none() {
-            self.overwrite = Some(true);
+            self.overwrite = Some(OverwritePolicy::Overwrite);
         }
         if self.repeat_count.is_none() {
             self.repeat_count = Some(1);
@@ -89,12 +378,724 @@ impl Config {
         self
     }
 
+    /// Merges the upload and download directories given on the command line,
+    /// keeping those of the configuration file.
+    pub fn merge_cli_directories(
+        mut self,
+        cli_receive_directory: Option<PathBuf>,
+        cli_send_directory: Option<PathBuf>,
+    ) -> Self {
+        self.receive_directory = self.receive_directory.or(cli_receive_directory);
+        self.send_directory = self.send_directory.or(cli_send_directory);
+        self
+    }
+
+    /// Merges the retries and timeout given on the command line, unless set
+    /// in the configuration file.
+    pub fn merge_cli_tuning(
+        mut self,
+        cli_retries: Option<usize>,
+        cli_timeout: Option<u64>,
+    ) -> Self {
+        self.max_retries = self.max_retries.or(cli_retries);
+        self.timeout = self.timeout.or(cli_timeout);
+        self
+    }
+
+    /// Listens on each of `addresses` instead of `ip`, all on the same port.
+    /// Addresses may also name a network interface, standing for all of its
+    /// addresses, e.g. the management and lab interfaces of a provisioning
+    /// host. Requests are answered from the address they were received on.
+    #[allow(dead_code)]
+    pub fn with_addresses(mut self, addresses: Vec<String>) -> Self {
+        self.addresses = Some(addresses);
+        self
+    }
+
+    /// Listens on `[::]` for IPv6 clients and IPv4 clients alike, seen as
+    /// IPv4-mapped addresses, whatever the default of the system. The `ip`
+    /// must be left unspecified.
+    #[allow(dead_code)]
+    pub fn with_dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = Some(dual_stack);
+        self
+    }
+
+    /// Marks the packets sent by the server with the DSCP value `dscp`, from
+    /// 0 to 63, e.g. 46 for expedited forwarding, so that the lab network
+    /// classifies and prioritizes provisioning traffic.
+    #[allow(dead_code)]
+    pub fn with_dscp(mut self, dscp: u8) -> Self {
+        self.dscp = Some(dscp);
+        self
+    }
+
+    /// Writes uploads to `directory` instead of the root directory.
+    #[allow(dead_code)]
+    pub fn with_receive_directory(mut self, directory: PathBuf) -> Self {
+        self.receive_directory = Some(directory);
+        self
+    }
+
+    /// Serves downloads from `directory` instead of the root directory.
+    #[allow(dead_code)]
+    pub fn with_send_directory(mut self, directory: PathBuf) -> Self {
+        self.send_directory = Some(directory);
+        self
+    }
+
+    /// Switches to `user` and `group` once the port is bound, so that the
+    /// server can be started as root to bind port 69 without serving files
+    /// as root. Users and groups are given by name or numeric id. Only
+    /// supported on Unix.
+    #[allow(dead_code)]
+    pub fn with_run_as(mut self, user: &str, group: &str) -> Self {
+        self.user = Some(user.to_string());
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// Changes root to the served directory before dropping privileges.
+    /// Every other path of the configuration must be inside it.
+    #[allow(dead_code)]
+    pub fn with_chroot(mut self, chroot: bool) -> Self {
+        self.chroot = Some(chroot);
+        self
+    }
+
+    /// Matches requested file names ignoring case when they are not found as
+    /// is, so that `BOOTX64.EFI` finds `bootx64.efi` on case-sensitive
+    /// filesystems. Only applies to the served directory.
+    #[allow(dead_code)]
+    pub fn with_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = Some(case_insensitive);
+        self
+    }
+
+    /// Sets whether the requested paths whose symbolic links lead out of
+    /// the served directory are served. Every requested path is resolved to
+    /// its canonical form first.
+    #[allow(dead_code)]
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = Some(policy);
+        self
+    }
+
+    /// Sets the directories symbolic links may lead to with
+    /// [`SymlinkPolicy::Allowlist`].
+    #[allow(dead_code)]
+    pub fn with_symlink_allowlist(mut self, directories: Vec<PathBuf>) -> Self {
+        self.symlink_allowlist = Some(directories);
+        self
+    }
+
+    /// Serves the requested names starting with `prefix` from `directory`,
+    /// e.g. `uefi/` from `/srv/uefi`, the prefix removed. The longest
+    /// matching prefix wins. Only applies when serving a directory.
+    #[allow(dead_code)]
+    pub fn with_root(mut self, prefix: &str, directory: PathBuf) -> Self {
+        self.roots
+            .get_or_insert_default()
+            .insert(prefix.to_string(), directory);
+        self
+    }
+
+    /// Turns the `\` separators of requested file names into `/` before any
+    /// rewrite rule, so that legacy PXE ROMs requesting
+    /// `pxelinux\pxelinux.0` find `pxelinux/pxelinux.0`.
+    #[allow(dead_code)]
+    pub fn with_backslashes(mut self, backslashes: bool) -> Self {
+        self.backslashes = Some(backslashes);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_journal(mut self, journal: PathBuf) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Appends a line for every completed or failed transfer to the log file
+    /// `path`, in the xferlog format of wu-ftpd, see [`TransferLog`].
+    ///
+    /// [`TransferLog`]: super::TransferLog
+    #[allow(dead_code)]
+    pub fn with_transfer_log(mut self, path: PathBuf) -> Self {
+        self.transfer_log = Some(path);
+        self
+    }
+
+    /// Rotates the transfer log by size or age as `rotation` tells, see
+    /// [`LogRotation`].
+    #[allow(dead_code)]
+    pub fn with_transfer_log_rotation(mut self, rotation: LogRotation) -> Self {
+        self.transfer_log_rotation = Some(rotation);
+        self
+    }
+
+    /// Logs the requests, starts, completions and failures of transfers in
+    /// `format`. With [`LogFormat::Json`], they are written to the standard
+    /// output as JSON objects, see [`JsonLog`].
+    ///
+    /// [`JsonLog`]: super::JsonLog
+    #[allow(dead_code)]
+    pub fn with_log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = Some(format);
+        self
+    }
+
+    /// Serves the active transfers, bytes transferred, errors by code and
+    /// retransmissions of the server in the Prometheus text format, over
+    /// HTTP at `/metrics` on `addr`.
+    #[allow(dead_code)]
+    pub fn with_metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Accepts line commands on the Unix socket `path`, so that operators
+    /// list and abort transfers, read the statistics, reload the
+    /// configuration or stop the server from scripts. Only supported on
+    /// Unix, see [`AdminSocket`].
+    ///
+    /// [`AdminSocket`]: super::admin::AdminSocket
+    #[allow(dead_code)]
+    pub fn with_admin_socket(mut self, path: PathBuf) -> Self {
+        self.admin_socket = Some(path);
+        self
+    }
+
+    /// Records the packets of every transfer to a session file in
+    /// `session_dir`, named after the time and the client, for replay with
+    /// [`replay()`](super::replay).
+    #[allow(dead_code)]
+    pub fn with_session_dir(mut self, session_dir: PathBuf) -> Self {
+        self.session_dir = Some(session_dir);
+        self
+    }
+
+    /// Captures the packets of every transfer, requests and errors answering
+    /// them included, to the pcap file `path`, replaced when the server
+    /// starts, see [`PcapWriter`].
+    ///
+    /// [`PcapWriter`]: crate::tftp::core::PcapWriter
+    #[allow(dead_code)]
+    pub fn with_pcap(mut self, path: PathBuf) -> Self {
+        self.pcap = Some(path);
+        self
+    }
+
+    /// Appends the name, size, SHA-256, client and time of every completed
+    /// upload to `MANIFEST.sha256` in the upload directory, see [`Manifest`].
+    /// Only supported on the local filesystem.
+    ///
+    /// [`Manifest`]: super::Manifest
+    #[allow(dead_code)]
+    pub fn with_manifest(mut self, manifest: bool) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// POSTs the name, size, client IP and SHA-256 of every completed upload
+    /// to `url` as a JSON object, so that pipelines react to the files
+    /// uploaded by devices, see [`UploadWebhook`].
+    ///
+    /// [`UploadWebhook`]: super::UploadWebhook
+    #[allow(dead_code)]
+    pub fn with_upload_webhook(mut self, url: String) -> Self {
+        self.upload_webhook = Some(url);
+        self
+    }
+
+    /// Serves the list of files of the root directory, with their size and
+    /// SHA-256, as the virtual file [`LISTING_FILENAME`](super::LISTING_FILENAME),
+    /// so that clients can mirror the directory. Not available for archive
+    /// roots.
+    /// Writes uploads to `<name>.tftp-tmp` and renames them to `<name>` only
+    /// once complete, so that programs watching the directory never see a
+    /// partial file.
+    #[allow(dead_code)]
+    pub fn with_atomic_uploads(mut self, atomic: bool) -> Self {
+        self.atomic_uploads = Some(atomic);
+        self
+    }
+
+    /// Sets what becomes of an existing file when a client uploads one of
+    /// the same name.
+    #[allow(dead_code)]
+    pub fn with_overwrite(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = Some(policy);
+        self
+    }
+
+    /// Expands the `${client_ip}`, `${client_mac_hint}` and `${server_ip}`
+    /// variables of the text files matching one of `patterns` before serving
+    /// them, so that one boot script fits every host. Patterns are matched
+    /// like those of [`Config::with_dynamic()`]. Only applies to the served
+    /// directory.
+    #[allow(dead_code)]
+    pub fn with_templates(mut self, patterns: Vec<String>) -> Self {
+        self.templates = Some(patterns);
+        self
+    }
+
+    /// Serves the files of the served directory compressed with gzip on the
+    /// fly when `name.gz` is requested and only `name` exists, or when the
+    /// client sends the `compress` option with the value `gzip`. Compressed
+    /// files are kept in memory until they change.
+    #[allow(dead_code)]
+    pub fn with_compress(mut self, compress: bool) -> Self {
+        self.compress = Some(compress);
+        self
+    }
+
+    /// Fetches the files missing from the served directory from the HTTP(S)
+    /// mirror at `url`, keeping them in the directory for the next requests.
+    /// Requests are answered once sent again by their client after the file
+    /// is fetched, the server serving other clients meanwhile.
+    #[allow(dead_code)]
+    pub fn with_mirror(mut self, url: String) -> Self {
+        self.mirror = Some(url);
+        self
+    }
+
+    /// Serves the objects of the S3-compatible `bucket` instead of the
+    /// directory, read-only, see [`S3Config`]. Needs the `s3` feature.
+    #[allow(dead_code)]
+    pub fn with_s3(mut self, bucket: S3Config) -> Self {
+        self.s3 = Some(bucket);
+        self
+    }
+
+    /// Encrypts the transfers of xtool clients with DTLS on the port of
+    /// `listener`, see [`DtlsListener`]. Needs the `dtls` feature.
+    #[allow(dead_code)]
+    pub fn with_dtls(mut self, listener: DtlsListener) -> Self {
+        self.dtls = Some(listener);
+        self
+    }
+
+    /// Refuses uploads to the files matching one of `patterns` with an
+    /// access violation error, the rest of the tree staying writable.
+    /// Patterns are matched like those of [`Config::with_dynamic()`], and
+    /// `**` also matches `/`, e.g. `boot/**` for all files under `boot`.
+    #[allow(dead_code)]
+    pub fn with_protected_paths(mut self, patterns: Vec<String>) -> Self {
+        self.protected_paths = Some(patterns);
+        self
+    }
+
+    /// Refuses uploads once they have written `bytes` in total, and fails
+    /// the upload exceeding it.
+    #[allow(dead_code)]
+    pub fn with_upload_quota(mut self, bytes: u64) -> Self {
+        self.upload_quota = Some(bytes);
+        self
+    }
+
+    /// Keeps downloaded files up to `max_file_size` bytes in memory, at most
+    /// `size` bytes in total, evicting the least recently used ones.
+    #[allow(dead_code)]
+    pub fn with_cache(mut self, size: u64, max_file_size: u64) -> Self {
+        self.cache_size = Some(size);
+        self.cache_max_file_size = Some(max_file_size);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_listing(mut self, listing: bool) -> Self {
+        self.listing = Some(listing);
+        self
+    }
+
+    /// Only serves clients in `networks`, refusing others with an access
+    /// violation error.
+    #[allow(dead_code)]
+    pub fn with_allowed_networks(mut self, networks: Vec<IpNet>) -> Self {
+        self.allowed_networks = Some(networks);
+        self
+    }
+
+    /// Refuses clients in `networks` with an access violation error, even if
+    /// they are in an allowed network.
+    #[allow(dead_code)]
+    pub fn with_denied_networks(mut self, networks: Vec<IpNet>) -> Self {
+        self.denied_networks = Some(networks);
+        self
+    }
+
+    /// Limits the transfers in progress at once to `max_transfers`, so that
+    /// a crowd of booting clients cannot exhaust the threads of a small host.
+    /// Further requests are refused with an error, unless queued, see
+    /// [`Config::with_max_queued()`].
+    #[allow(dead_code)]
+    pub fn with_max_transfers(mut self, max_transfers: usize) -> Self {
+        self.max_transfers = Some(max_transfers);
+        self
+    }
+
+    /// Lets up to `max_queued` requests wait while `max_transfers` transfers
+    /// are in progress. Queued requests are started by priority as transfers
+    /// complete, see [`Config::with_priority()`].
+    #[allow(dead_code)]
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = Some(max_queued);
+        self
+    }
+
+    /// Adds a priority class for queued requests, see [`PriorityClass`].
+    /// Classes are checked in the order they were added.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use xtool::tftp::server::{Config, Priority, PriorityClass};
+    ///
+    /// let config = Config::with_defaults()
+    ///     .with_max_transfers(16)
+    ///     .with_max_queued(64)
+    ///     .with_priority(PriorityClass {
+    ///         priority: Priority::High,
+    ///         pattern: Some("vmlinuz*".to_string()),
+    ///         networks: None,
+    ///     });
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_priority(mut self, class: PriorityClass) -> Self {
+        self.priorities.get_or_insert_default().push(class);
+        self
+    }
+
+    /// Adds a rule rewriting the requested file names, see [`RewriteRule`].
+    /// Rules are applied in the order they were added.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use xtool::tftp::server::{Config, RewriteRule};
+    ///
+    /// let config = Config::with_defaults().with_rewrite(RewriteRule {
+    ///     pattern: "^grub/(.*)".to_string(),
+    ///     replace: Some("boot/grub2/$1".to_string()),
+    ///     lowercase: false,
+    /// });
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_rewrite(mut self, rule: RewriteRule) -> Self {
+        self.rewrites.get_or_insert_default().push(rule);
+        self
+    }
+
+    /// Serves the clients of `mapping` their own boot tree or files, the
+    /// mapping of the smallest network holding a client winning.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use xtool::tftp::server::{BootMapping, Config};
+    ///
+    /// let config = Config::with_defaults().with_boot_mapping(BootMapping {
+    ///     clients: "192.168.10.0/24".parse().unwrap(),
+    ///     prefix: Some("rescue/".to_string()),
+    ///     files: Default::default(),
+    /// });
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_boot_mapping(mut self, mapping: BootMapping) -> Self {
+        self.boot_maps.get_or_insert_default().push(mapping);
+        self
+    }
+
+    /// Runs transfers on a pool of `core_threads` threads kept alive, growing
+    /// up to `max_threads` once `queue` transfers wait for a thread. Threads
+    /// beyond the core ones exit after `idle_timeout` without a transfer.
+    #[allow(dead_code)]
+    pub fn with_threads(
+        mut self,
+        core_threads: usize,
+        max_threads: usize,
+        queue: usize,
+        idle_timeout: Duration,
+    ) -> Self {
+        self.core_threads = Some(core_threads);
+        self.max_threads = Some(max_threads);
+        self.thread_queue = Some(queue);
+        self.thread_idle_timeout = Some(idle_timeout.as_secs());
+        self
+    }
+
+    /// Runs transfers on a fixed pool of `threads` threads, started as needed
+    /// and kept alive, so that hundreds of clients booting at once do not
+    /// start hundreds of threads on a small host. Requests beyond them wait
+    /// in the queue of [`Config::with_thread_queue()`] or are refused.
+    /// Overrides the thread counts of [`Config::with_threads()`].
+    #[allow(dead_code)]
+    pub fn with_worker_threads(mut self, threads: usize) -> Self {
+        self.worker_threads = Some(threads);
+        self
+    }
+
+    /// Lets up to `queue` transfers wait for a busy thread of the pool.
+    #[allow(dead_code)]
+    pub fn with_thread_queue(mut self, queue: usize) -> Self {
+        self.thread_queue = Some(queue);
+        self
+    }
+
+    /// Stops the transfers that received no packet from their client for
+    /// `idle_timeout`, e.g. when a PXE client resets mid-boot, whatever the
+    /// timeout and retries they negotiated.
+    #[allow(dead_code)]
+    pub fn with_transfer_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.transfer_idle_timeout = Some(idle_timeout.as_secs());
+        self
+    }
+
+    /// Multicasts the files requested with the multicast option to groups
+    /// of `address`, from `port` upward, one per file being sent.
+    #[allow(dead_code)]
+    pub fn with_multicast(mut self, address: Ipv4Addr, port: u16) -> Self {
+        self.multicast_address = Some(address);
+        self.multicast_port = Some(port);
+        self
+    }
+
+    /// Clamps the block and window sizes clients negotiate to at most
+    /// `max_block_size` and `max_window_size`, bounding the memory of each
+    /// transfer.
+    #[allow(dead_code)]
+    pub fn with_max_sizes(mut self, max_block_size: u16, max_window_size: u16) -> Self {
+        self.max_block_size = Some(max_block_size);
+        self.max_window_size = Some(max_window_size);
+        self
+    }
+
+    /// Never negotiates `option`, ignoring it in requests as if clients had
+    /// not asked for it, for firmware mishandling an option it requests.
+    #[allow(dead_code)]
+    pub fn with_disabled_option(mut self, option: OptionType) -> Self {
+        self.disabled_options
+            .get_or_insert_default()
+            .push(option.as_str().to_string());
+        self
+    }
+
+    /// Gives up a transfer once a packet was resent `retries` times without
+    /// an answer (default 6).
+    #[allow(dead_code)]
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.max_retries = Some(retries);
+        self
+    }
+
+    /// Resends a packet after `timeout` without an answer in the transfers
+    /// whose clients negotiate no timeout (default 5s). Rounded down to the
+    /// second, as negotiated timeouts are.
+    #[allow(dead_code)]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout.as_secs());
+        self
+    }
+
+    /// Clamps the timeouts clients negotiate to between `min` and `max`.
+    #[allow(dead_code)]
+    pub fn with_timeout_range(mut self, min: Duration, max: Duration) -> Self {
+        self.min_timeout = Some(min.as_secs());
+        self.max_timeout = Some(max.as_secs());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_rollover(mut self, rollover: Rollover) -> Self {
+        self.rollover = Some(rollover);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_checksum(mut self, checksum: DigestAlgorithm) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_read_ahead(mut self, read_ahead: u16) -> Self {
+        self.read_ahead = Some(read_ahead);
+        self
+    }
+
+    /// Memory-maps downloaded files of at least `threshold` bytes.
+    #[allow(dead_code)]
+    pub fn with_mmap_threshold(mut self, threshold: u64) -> Self {
+        self.mmap_threshold = Some(threshold);
+        self
+    }
+
+    /// Limits each download to `rate` bytes per second.
+    #[allow(dead_code)]
+    pub fn with_rate_limit(mut self, rate: u64) -> Self {
+        self.rate_limit = Some(rate);
+        self
+    }
+
+    /// Limits the downloads of each client IP to `rate` bytes per second in
+    /// total, however many files it requests at once.
+    #[allow(dead_code)]
+    pub fn with_client_rate_limit(mut self, rate: u64) -> Self {
+        self.client_rate_limit = Some(rate);
+        self
+    }
+
+    /// Limits all downloads of the server to `rate` bytes per second in
+    /// total, so that many clients booting at once do not saturate the link.
+    #[allow(dead_code)]
+    pub fn with_total_rate_limit(mut self, rate: u64) -> Self {
+        self.total_rate_limit = Some(rate);
+        self
+    }
+
+    /// Generates the files matching `pattern`, e.g. `pxelinux.cfg/*`, with
+    /// `generate` instead of serving them from the root, for instance to
+    /// build boot configurations for the requesting client. Files for which
+    /// `generate` returns `None` are served as usual. See [`DynamicContent`].
+    ///
+    /// Generators run on the thread listening for requests, so they should
+    /// return quickly.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use xtool::tftp::server::Config;
+    ///
+    /// let config = Config::with_defaults().with_dynamic("pxelinux.cfg/*", |transfer| {
+    ///     let config = format!("DEFAULT linux\nAPPEND ip={}\n", transfer.peer.ip());
+    ///     Some(config.into_bytes())
+    /// });
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_dynamic(
+        mut self,
+        pattern: &str,
+        generate: impl Fn(&TransferInfo) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        self.dynamic.add(pattern, Arc::new(generate));
+        self
+    }
+
+    pub(super) fn get_pool(&self) -> ThreadPool {
+        let core_threads = self.core_threads.unwrap_or(DEFAULT_CORE_THREADS);
+        let max_threads = self.max_threads.unwrap_or(DEFAULT_MAX_THREADS);
+        let (core_threads, max_threads) = self
+            .worker_threads
+            .map_or((core_threads, max_threads), |threads| (threads, threads));
+        ThreadPool::new(
+            core_threads,
+            max_threads,
+            self.thread_queue.unwrap_or(0),
+            self.thread_idle_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_IDLE_TIMEOUT),
+        )
+    }
+
+    pub(super) fn get_rewriter(&self) -> anyhow::Result<Rewriter> {
+        let backslashes = self.backslashes.unwrap_or(false).then(|| RewriteRule {
+            pattern: r"\\".to_string(),
+            replace: Some("/".to_string()),
+            lowercase: false,
+        });
+        let rules: Vec<RewriteRule> = backslashes
+            .into_iter()
+            .chain(self.rewrites.iter().flatten().cloned())
+            .collect();
+        Rewriter::new(&rules)
+    }
+
+    pub(super) fn get_boot_map(&self) -> BootMap {
+        BootMap::new(self.boot_maps.as_deref().unwrap_or_default())
+    }
+
+    pub(super) fn get_symlinks(&self) -> Symlinks {
+        Symlinks::new(
+            self.symlinks.unwrap_or_default(),
+            self.symlink_allowlist.as_deref().unwrap_or_default(),
+        )
+    }
+
+    pub(super) fn get_roots(&self) -> Roots {
+        self.roots.as_ref().map(Roots::new).unwrap_or_default()
+    }
+
+    /// Returns the options never negotiated, or an error if one is unknown.
+    pub(super) fn get_disabled_options(&self) -> anyhow::Result<Vec<OptionType>> {
+        self.disabled_options
+            .iter()
+            .flatten()
+            .map(|name| {
+                name.to_lowercase()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid disabled option {name}"))
+            })
+            .collect()
+    }
+
+    /// Returns the limits of the negotiated options, or an error if they
+    /// cannot be advertised to clients.
+    pub(super) fn get_limits(&self) -> anyhow::Result<OptionLimits> {
+        if let Some(max) = self.max_block_size
+            && !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&max)
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid max_block_size {max}, must be between {MIN_BLOCK_SIZE} and {MAX_BLOCK_SIZE}"
+            ));
+        }
+        if self.max_window_size == Some(0) {
+            return Err(anyhow::anyhow!(
+                "Invalid max_window_size 0, must be at least 1"
+            ));
+        }
+        if self.timeout == Some(0) {
+            return Err(anyhow::anyhow!(
+                "Invalid timeout 0, must be at least 1 second"
+            ));
+        }
+        if self.transfer_idle_timeout == Some(0) {
+            return Err(anyhow::anyhow!(
+                "Invalid transfer_idle_timeout 0, must be at least 1 second"
+            ));
+        }
+        if let (Some(min), Some(max)) = (self.min_timeout, self.max_timeout)
+            && min > max
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid timeouts, min_timeout {min} is above max_timeout {max}"
+            ));
+        }
+        Ok(OptionLimits {
+            max_block_size: self.max_block_size,
+            max_window_size: self.max_window_size,
+            timeout: self.timeout,
+            min_timeout: self.min_timeout,
+            max_timeout: self.max_timeout,
+        })
+    }
+
+    pub fn get_acl(&self) -> Acl {
+        Acl::new(
+            self.allowed_networks.clone().unwrap_or_default(),
+            self.denied_networks.clone().unwrap_or_default(),
+        )
+    }
+
     pub fn get_options(&self) -> OptionsPrivate {
         OptionsPrivate {
             repeat_count: self.repeat_count.unwrap_or(1),
             clean_on_error: self.clean_on_error.unwrap_or(true),
             max_retries: self.max_retries.unwrap_or(6),
             rollover: self.rollover.unwrap_or(Rollover::Enforce0),
+            checksum: self.checksum,
+            read_ahead: self.read_ahead.unwrap_or(DEFAULT_READ_AHEAD),
+            rate_limit: self.rate_limit,
+            mmap_threshold: self.mmap_threshold,
         }
     }
 }
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+#[cfg(not(feature = "dtls"))]
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "dtls"))]
+use super::acl::Acl;
+use crate::tftp::core::DtlsConfig;
+
+/// DtlsListener `struct` describes the DTLS endpoint of a server, encrypting
+/// transfers with xtool clients configured with a [`DtlsConfig`] on
+/// untrusted networks.
+///
+/// Sessions are decrypted on `port` and relayed to the TFTP port of the
+/// server through the loopback, so that every feature of the server applies
+/// to them. The access list of the server is checked against the clients of
+/// the sessions rather than the relays. Blocks are limited to
+/// [`MAX_DTLS_BLOCK_SIZE`](crate::tftp::core::MAX_DTLS_BLOCK_SIZE) bytes, the
+/// largest fitting in a DTLS record.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd.dtls]
+/// port = 6969
+/// certificate = "/etc/xtool/tftp.pem"
+/// private_key = "/etc/xtool/tftp.key"
+/// # Only clients with a certificate of the lab
+/// ca = "/etc/xtool/lab-ca.pem"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DtlsListener {
+    pub port: u16,
+    #[serde(flatten)]
+    pub certificates: DtlsConfig,
+}
+
+/// Addresses the relays of DTLS sessions send from
+pub(super) type Relays = Arc<Mutex<HashSet<SocketAddr>>>;
+
+#[cfg(feature = "dtls")]
+pub(super) use super::dtls_terminator::DtlsTerminator;
+
+/// Never created without the `dtls` feature, [`DtlsTerminator::new()`] failing.
+#[cfg(not(feature = "dtls"))]
+pub(super) enum DtlsTerminator {}
+
+#[cfg(not(feature = "dtls"))]
+impl DtlsTerminator {
+    pub fn new(listener: &DtlsListener, _tftp: SocketAddr, _acl: Acl) -> anyhow::Result<Self> {
+        Err(anyhow::anyhow!(
+            "Cannot listen for DTLS on port {}, xtool was built without the dtls feature",
+            listener.port
+        ))
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        match *self {}
+    }
+
+    pub fn relays(&self) -> Relays {
+        match *self {}
+    }
+
+    pub fn run(&self, _stop: Arc<AtomicBool>) {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_listener_settings() {
+        let listener: DtlsListener = toml::from_str(
+            r#"
+            port = 6969
+            certificate = "/etc/xtool/tftp.pem"
+            private_key = "/etc/xtool/tftp.key"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(listener.port, 6969);
+        assert_eq!(listener.certificates.ca, None);
+        assert_eq!(
+            listener.certificates.private_key,
+            Some("/etc/xtool/tftp.key".into())
+        );
+        #[cfg(not(feature = "dtls"))]
+        assert!(
+            DtlsTerminator::new(&listener, "127.0.0.1:69".parse().unwrap(), Acl::default())
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use openssl::ssl::{
+    ErrorCode, Ssl, SslContext, SslFiletype, SslMethod, SslOptions, SslStream, SslVerifyMode,
+};
+
+use super::acl::Acl;
+use super::dtls::{DtlsListener, Relays};
+use crate::tftp::core::DTLS_MTU;
+
+/// How often the stop flag is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long clients are given to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a session is kept without datagrams
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Sessions at once, clients beyond it are ignored
+const MAX_SESSIONS: usize = 256;
+/// Largest datagram received
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Datagram to be relayed by a session
+enum Input {
+    /// Encrypted, from the client
+    Client(Vec<u8>),
+    /// In clear, from the server and its sender
+    Server(Vec<u8>, SocketAddr),
+}
+
+/// DtlsTerminator `struct` decrypts the DTLS sessions of a
+/// [`DtlsListener`], one thread per session.
+pub(super) struct DtlsTerminator {
+    socket: Arc<UdpSocket>,
+    context: SslContext,
+    /// Request port of the server
+    tftp: SocketAddr,
+    acl: Acl,
+    relays: Relays,
+}
+
+impl DtlsTerminator {
+    /// Binds the DTLS endpoint of `listener` on the IP of `tftp`, the
+    /// TFTP port sessions are relayed to.
+    pub fn new(listener: &DtlsListener, tftp: SocketAddr, acl: Acl) -> anyhow::Result<Self> {
+        let certificates = &listener.certificates;
+        let Some(certificate) = &certificates.certificate else {
+            return Err(anyhow::anyhow!("DTLS needs a server certificate"));
+        };
+        let private_key = certificates.private_key.as_ref().unwrap_or(certificate);
+        let mut builder = SslContext::builder(SslMethod::dtls())?;
+        builder.set_options(SslOptions::NO_QUERY_MTU);
+        builder.set_certificate_chain_file(certificate)?;
+        builder.set_private_key_file(private_key, SslFiletype::PEM)?;
+        builder.check_private_key()?;
+        if let Some(ca) = &certificates.ca {
+            builder.set_ca_file(ca)?;
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        let socket = UdpSocket::bind(SocketAddr::new(tftp.ip(), listener.port))?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        // Sessions are relayed to the server through the loopback when it listens everywhere
+        let tftp = match tftp.ip() {
+            ip if ip.is_unspecified() && ip.is_ipv4() => {
+                SocketAddr::from(([127, 0, 0, 1], tftp.port()))
+            }
+            ip if ip.is_unspecified() => SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], tftp.port())),
+            _ => tftp,
+        };
+        Ok(DtlsTerminator {
+            socket: Arc::new(socket),
+            context: builder.build(),
+            tftp,
+            acl,
+            relays: Arc::new(Mutex::new(Default::default())),
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Returns the addresses of the relays of the sessions, the sources
+    /// of the requests of DTLS clients.
+    pub fn relays(&self) -> Relays {
+        self.relays.clone()
+    }
+
+    /// Dispatches the datagrams received to their sessions until `stop`
+    /// is set.
+    pub fn run(&self, stop: Arc<AtomicBool>) {
+        log::info!("Listening for DTLS on {:?}", self.socket.local_addr());
+        let mut sessions: HashMap<SocketAddr, (Sender<Input>, JoinHandle<()>)> = HashMap::new();
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        while !stop.load(Ordering::SeqCst) {
+            let (amt, client) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue;
+                }
+                Err(err) => {
+                    log::debug!("DTLS socket error: {err}");
+                    continue;
+                }
+            };
+            let datagram = Input::Client(buf[..amt].to_vec());
+
+            sessions.retain(|_, (_, session)| !session.is_finished());
+            if let Some((sender, _)) = sessions.get(&client) {
+                let _ = sender.send(datagram);
+                continue;
+            }
+            if !self.acl.permits(client.ip()) {
+                log::warn!("Refused DTLS session from {client}, not an allowed network");
+                continue;
+            }
+            if sessions.len() >= MAX_SESSIONS {
+                log::warn!("Refused DTLS session from {client}, too many sessions");
+                continue;
+            }
+
+            let (sender, inputs) = mpsc::channel();
+            let _ = sender.send(datagram);
+            let session = Session {
+                socket: self.socket.clone(),
+                client,
+                pending: VecDeque::new(),
+            };
+            let (context, tftp, relays) = (self.context.clone(), self.tftp, self.relays.clone());
+            let (relay_sender, stop) = (sender.clone(), stop.clone());
+            let spawned = thread::Builder::new()
+                .name("tftp-dtls".to_string())
+                .spawn(move || {
+                    let relay = Relay {
+                        tftp,
+                        relays,
+                        sender: relay_sender,
+                        stop,
+                    };
+                    if let Err(err) = relay.serve(&context, session, inputs) {
+                        log::warn!("DTLS session with {client} failed: {err:#}");
+                    }
+                });
+            match spawned {
+                Ok(handle) => {
+                    sessions.insert(client, (sender, handle));
+                }
+                Err(err) => log::error!("Cannot start DTLS session with {client}: {err}"),
+            }
+        }
+    }
+}
+
+/// Datagrams of the session with `client`, written to the shared socket
+/// and read from those dispatched to the session.
+struct Session {
+    socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl Read for Session {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(datagram) = self.pending.pop_front() else {
+            return Err(io::Error::from(ErrorKind::WouldBlock));
+        };
+        let amt = datagram.len().min(buf.len());
+        buf[..amt].copy_from_slice(&datagram[..amt]);
+        Ok(amt)
+    }
+}
+
+impl Write for Session {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(buf, self.client)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Relays the decrypted datagrams of a session to the server
+struct Relay {
+    tftp: SocketAddr,
+    relays: Relays,
+    /// Where the datagrams of the server are sent to
+    sender: Sender<Input>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Relay {
+    fn serve(
+        self,
+        context: &SslContext,
+        session: Session,
+        inputs: Receiver<Input>,
+    ) -> anyhow::Result<()> {
+        let client = session.client;
+        let mut ssl = Ssl::new(context)?;
+        ssl.set_mtu(DTLS_MTU)?;
+        let mut stream = SslStream::new(ssl, session)?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            match stream.accept() {
+                Ok(()) => break,
+                Err(err) if err.code() == ErrorCode::WANT_READ => {
+                    match inputs.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(Input::Client(datagram)) => stream.get_mut().pending.push_back(datagram),
+                        Ok(Input::Server(..)) => {}
+                        Err(_) => return Err(anyhow::anyhow!("handshake timed out")),
+                    }
+                }
+                Err(err) => return Err(anyhow::anyhow!("handshake failed: {err}")),
+            }
+        }
+
+        let relay = UdpSocket::bind(SocketAddr::new(self.tftp.ip(), 0))?;
+        relay.set_read_timeout(Some(POLL_INTERVAL))?;
+        let relay_addr = relay.local_addr()?;
+        log::info!("DTLS session established with {client}, relayed from {relay_addr}");
+        self.relays.lock().unwrap().insert(relay_addr);
+        let closed = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (relay, sender, closed) = (relay.try_clone()?, self.sender.clone(), closed.clone());
+            thread::spawn(move || forward(relay, sender, closed))
+        };
+
+        let result = self.relay(&mut stream, &relay, &inputs);
+        closed.store(true, Ordering::SeqCst);
+        self.relays.lock().unwrap().remove(&relay_addr);
+        let _ = stream.shutdown();
+        let _ = reader.join();
+        log::debug!("DTLS session with {client} closed");
+        result
+    }
+
+    fn relay(
+        &self,
+        stream: &mut SslStream<Session>,
+        relay: &UdpSocket,
+        inputs: &Receiver<Input>,
+    ) -> anyhow::Result<()> {
+        // Transfer ID of the server, its request port until it answers
+        let mut tid = None;
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let mut active = Instant::now();
+        while !self.stop.load(Ordering::SeqCst) && active.elapsed() < IDLE_TIMEOUT {
+            match inputs.recv_timeout(POLL_INTERVAL) {
+                Ok(Input::Client(datagram)) => {
+                    active = Instant::now();
+                    stream.get_mut().pending.push_back(datagram);
+                    loop {
+                        match stream.ssl_read(&mut buf) {
+                            Ok(0) => return Ok(()),
+                            Ok(amt) => {
+                                relay.send_to(&buf[..amt], tid.unwrap_or(self.tftp))?;
+                            }
+                            Err(err) if err.code() == ErrorCode::WANT_READ => break,
+                            Err(err) if err.code() == ErrorCode::ZERO_RETURN => return Ok(()),
+                            Err(err) => return Err(err.into()),
+                        }
+                    }
+                }
+                Ok(Input::Server(datagram, from)) => {
+                    active = Instant::now();
+                    if *tid.get_or_insert(from) == from {
+                        stream.ssl_write(&datagram)?;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forwards the datagrams received by `relay` from the server to its
+/// session until `closed` is set.
+fn forward(relay: UdpSocket, sender: Sender<Input>, closed: Arc<AtomicBool>) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    while !closed.load(Ordering::SeqCst) {
+        match relay.recv_from(&mut buf) {
+            Ok((amt, from)) => {
+                if sender
+                    .send(Input::Server(buf[..amt].to_vec(), from))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => {
+                log::debug!("DTLS relay error: {err}");
+                return;
+            }
+        }
+    }
+}
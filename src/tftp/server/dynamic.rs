@@ -0,0 +1,134 @@
+use std::fmt;
+use std::sync::Arc;
+
+use super::handler::TransferInfo;
+use super::provider::normalize_name;
+
+/// Generates the content of a requested file, or `None` to serve the file
+/// from the root as usual.
+pub type ContentFn = dyn Fn(&TransferInfo) -> Option<Vec<u8>> + Send + Sync;
+
+/// DynamicContent `struct` holds the generators of files registered with
+/// [`Config::with_dynamic()`](super::Config::with_dynamic), by file name
+/// pattern.
+///
+/// Patterns are matched against the requested name with `/` separators and no
+/// leading slash. `*` matches any characters and `?` a single one, except `/`,
+/// while `**` matches `/` too.
+#[derive(Clone, Default)]
+pub struct DynamicContent {
+    generators: Vec<(String, Arc<ContentFn>)>,
+}
+
+impl DynamicContent {
+    /// Generates the files matching `pattern` with `generate`.
+    pub fn add(&mut self, pattern: &str, generate: Arc<ContentFn>) {
+        self.generators.push((normalize_name(pattern), generate));
+    }
+
+    /// Returns `true` if no generator is registered.
+    pub fn is_empty(&self) -> bool {
+        self.generators.is_empty()
+    }
+
+    /// Returns the content of the requested file from the first generator
+    /// whose pattern matches and that produces it.
+    pub fn generate(&self, transfer: &TransferInfo) -> Option<Vec<u8>> {
+        let name = normalize_name(&transfer.filename);
+        self.generators
+            .iter()
+            .filter(|(pattern, _)| glob_matches(pattern, &name))
+            .find_map(|(_, generate)| generate(transfer))
+    }
+}
+
+impl fmt::Debug for DynamicContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.generators.iter().map(|(pattern, _)| pattern))
+            .finish()
+    }
+}
+
+/// Returns `true` if the normalized file `name` matches `pattern`, where `*`
+/// matches any characters and `?` a single one, except `/`, and `**` any
+/// characters. A leading `**/` also matches no directory at all.
+pub(super) fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            matches(rest, name)
+                || rest.first() == Some(&'/') && matches(&rest[1..], name)
+                || !name.is_empty() && matches(pattern, &name[1..])
+        }
+        (Some('*'), _) => {
+            matches(&pattern[1..], name)
+                || name.first().is_some_and(|&c| c != '/') && matches(pattern, &name[1..])
+        }
+        (Some('?'), Some(&c)) if c != '/' => matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(c)) if p == c => matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::server::Direction;
+
+    #[test]
+    fn matches_patterns() {
+        assert!(glob_matches(
+            "pxelinux.cfg/*",
+            "pxelinux.cfg/01-aa-bb-cc-dd-ee-ff"
+        ));
+        assert!(glob_matches("pxelinux.cfg/*", "pxelinux.cfg/"));
+        assert!(!glob_matches("pxelinux.cfg/*", "pxelinux.cfg/sub/default"));
+        assert!(glob_matches("*.ipxe", "boot.ipxe"));
+        assert!(!glob_matches("*.ipxe", "boot.ipxe.bak"));
+        assert!(glob_matches("grub/grub.cfg-??", "grub/grub.cfg-0a"));
+        assert!(!glob_matches("grub/grub.cfg-??", "grub/grub.cfg-0"));
+        assert!(glob_matches("boot.cfg", "boot.cfg"));
+        assert!(glob_matches("boot/**", "boot/dtbs/board.dtb"));
+        assert!(!glob_matches("boot/**", "bootfs/board.dtb"));
+        assert!(glob_matches("**/*.cfg", "pxelinux.cfg/sub/boot.cfg"));
+        assert!(glob_matches("**/*.cfg", "boot.cfg"));
+    }
+
+    #[test]
+    fn generates_from_first_match() {
+        let mut dynamic = DynamicContent::default();
+        dynamic.add(
+            "/pxelinux.cfg/*",
+            Arc::new(|transfer: &TransferInfo| {
+                (!transfer.filename.ends_with("default")).then(|| b"generated".to_vec())
+            }),
+        );
+        dynamic.add(
+            "pxelinux.cfg/*",
+            Arc::new(|_: &TransferInfo| Some(b"fallback".to_vec())),
+        );
+
+        let mut transfer = TransferInfo {
+            peer: "127.0.0.1:1234".parse().unwrap(),
+            filename: "pxelinux.cfg\\01-aa".to_string(),
+            direction: Direction::Read,
+            netascii: false,
+            custom_options: vec![],
+        };
+        assert_eq!(dynamic.generate(&transfer).unwrap(), b"generated");
+
+        transfer.filename = "pxelinux.cfg/default".to_string();
+        assert_eq!(dynamic.generate(&transfer).unwrap(), b"fallback");
+
+        transfer.filename = "vmlinuz".to_string();
+        assert_eq!(dynamic.generate(&transfer), None);
+    }
+}
@@ -0,0 +1,182 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::tftp::core::{MappedFile, NetasciiWriter, preallocate};
+
+use super::provider::FileProvider;
+
+/// Metadata `struct` describes a file of a [`TftpFs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Size of the file in bytes
+    pub len: u64,
+}
+
+/// TftpFs `trait` abstracts the storage a [`Worker`](super::Worker) reads
+/// files from and writes uploads to, so a server can serve files from memory,
+/// an archive or a database instead of a directory.
+///
+/// Paths are those resolved by the server: the path inside the root directory
+/// for [`DiskFs`], the requested name with `/` separators and no leading slash
+/// for virtual roots, see [`normalize_name()`](super::provider::normalize_name).
+///
+/// Any [`FileProvider`] is a read-only `TftpFs`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::{Read, Write};
+/// use std::path::Path;
+/// use xtool::tftp::server::{MemoryFs, TftpFs};
+///
+/// let fs = MemoryFs::new();
+/// let mut writer = fs.open_write(Path::new("hello.txt")).unwrap();
+/// writer.write_all(b"Hello, world!").unwrap();
+/// writer.finish(13).unwrap();
+///
+/// let mut content = String::new();
+/// fs.open_read(Path::new("hello.txt"), 7)
+///     .unwrap()
+///     .read_to_string(&mut content)
+///     .unwrap();
+/// assert_eq!(content, "world!");
+/// ```
+pub trait TftpFs: Send + Sync {
+    /// Returns the metadata of the file at `path`, or a
+    /// [`io::ErrorKind::NotFound`] error.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Opens the file at `path` for reading, skipping its first `offset` bytes.
+    fn open_read(&self, path: &Path, offset: u64) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Maps the file at `path` in memory, to be sent without reading it into
+    /// buffers. Returns `None` if the filesystem has no files to map.
+    fn map(&self, _path: &Path) -> io::Result<Option<MappedFile>> {
+        Ok(None)
+    }
+
+    /// Creates or truncates the file at `path` for writing. Read-only
+    /// filesystems return a [`io::ErrorKind::PermissionDenied`] error.
+    fn open_write(&self, _path: &Path) -> io::Result<Box<dyn FileWriter>> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "filesystem is read-only",
+        ))
+    }
+
+    /// Creates the empty file at `path`, reserving its name for an upload, or
+    /// returns a [`io::ErrorKind::AlreadyExists`] error if it exists.
+    fn create_new(&self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "filesystem is read-only",
+        ))
+    }
+
+    /// Removes what was written of the file at `path` after a failed upload.
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the file at `to` with the one at `from`, for uploads written
+    /// to a temporary file first.
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "filesystem cannot rename files",
+        ))
+    }
+}
+
+/// FileWriter `trait` is implemented by the files returned by
+/// [`TftpFs::open_write()`].
+pub trait FileWriter: Write + Send {
+    /// Reserves space for `len` bytes before any data is written, so that a
+    /// full storage is reported before the transfer starts.
+    fn allocate(&mut self, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Completes the file once all of its `len` bytes are written.
+    fn finish(&mut self, _len: u64) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl FileWriter for File {
+    fn allocate(&mut self, len: u64) -> io::Result<()> {
+        preallocate(self, len)
+    }
+
+    fn finish(&mut self, len: u64) -> io::Result<()> {
+        // Drop any preallocated space the peer did not end up sending
+        self.set_len(len)
+    }
+}
+
+/// Space is not reserved for netascii uploads, the size of the converted file
+/// being unknown until complete.
+impl FileWriter for NetasciiWriter<Box<dyn FileWriter>> {
+    fn finish(&mut self, _len: u64) -> io::Result<()> {
+        let written = self.end()?;
+        self.get_mut().finish(written)
+    }
+}
+
+/// DiskFs `struct` is the default [`TftpFs`], reading and writing the files
+/// of the local filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskFs;
+
+impl TftpFs for DiskFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(Metadata {
+            len: path.metadata()?.len(),
+        })
+    }
+
+    fn open_read(&self, path: &Path, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = File::open(path)?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(Box::new(file))
+    }
+
+    fn map(&self, path: &Path) -> io::Result<Option<MappedFile>> {
+        MappedFile::open(&File::open(path)?).map(Some)
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn FileWriter>> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map(|_| ())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+}
+
+impl<P: FileProvider> TftpFs for P {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(Metadata {
+            len: self.size(&path.to_string_lossy())?,
+        })
+    }
+
+    fn open_read(&self, path: &Path, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        self.open(&path.to_string_lossy(), offset)
+    }
+}
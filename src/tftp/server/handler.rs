@@ -0,0 +1,155 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::events::{Event, EventBus};
+use crate::tftp::core::{CustomOption, ErrorCode, TransferOption};
+
+/// Direction of a transfer, seen from the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Read request, the server sends the file
+    Read,
+    /// Write request, the server receives the file
+    Write,
+}
+
+/// Transfer a [`ServerHandler`] is notified about
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransferInfo {
+    /// Address of the client
+    pub peer: SocketAddr,
+    /// File name as requested by the client
+    pub filename: String,
+    pub direction: Direction,
+    /// Set if the file is transferred in netascii mode, its line endings
+    /// converted
+    pub netascii: bool,
+    /// Options of the request unknown to the server, such as vendor
+    /// extensions, which are not acknowledged
+    pub custom_options: Vec<CustomOption>,
+}
+
+/// ServerHandler `trait` receives the lifecycle events of the transfers of a
+/// [`Server`](super::Server), for auditing, access control or telemetry
+/// without changing the server itself.
+///
+/// Every method has an empty default implementation. Callbacks run on the
+/// server and worker threads, so they should return quickly.
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::core::ErrorCode;
+/// use xtool::tftp::server::{Direction, ServerHandler, TransferInfo};
+///
+/// /// Refuses uploads from outside the lab network
+/// struct LabOnly;
+///
+/// impl ServerHandler for LabOnly {
+///     fn on_request(&self, transfer: &TransferInfo) -> Result<(), (ErrorCode, String)> {
+///         match (transfer.direction, transfer.peer.ip().to_string()) {
+///             (Direction::Write, ip) if !ip.starts_with("10.0.") => Err((
+///                 ErrorCode::AccessViolation,
+///                 "uploads are restricted".to_string(),
+///             )),
+///             _ => Ok(()),
+///         }
+///     }
+/// }
+/// ```
+pub trait ServerHandler: Send + Sync {
+    /// Called for each read or write request before it is served. Returning
+    /// an error refuses the request, the code and message are sent to the
+    /// client.
+    fn on_request(&self, _transfer: &TransferInfo) -> Result<(), (ErrorCode, String)> {
+        Ok(())
+    }
+
+    /// Called when the request is accepted, with the options negotiated,
+    /// empty if the client sent none.
+    fn on_accept(&self, _transfer: &TransferInfo, _options: &[TransferOption]) {}
+
+    /// Called once the options are negotiated and the transfer begins.
+    fn on_transfer_start(&self, _transfer: &TransferInfo) {}
+
+    /// Called as blocks are acknowledged when sending, or received when
+    /// receiving, with the latest block number and the bytes transferred.
+    fn on_block(&self, _transfer: &TransferInfo, _block: u16, _bytes: u64) {}
+
+    /// Called when the transfer completed, with the bytes transferred.
+    fn on_complete(&self, _transfer: &TransferInfo, _bytes: u64) {}
+
+    /// Called when the transfer failed.
+    fn on_error(&self, _transfer: &TransferInfo, _error: &anyhow::Error) {}
+}
+
+/// Handlers `struct` notifies several [`ServerHandler`]s in turn. A request
+/// is refused by the first handler refusing it.
+pub(super) struct Handlers(pub Vec<Arc<dyn ServerHandler>>);
+
+impl ServerHandler for Handlers {
+    fn on_request(&self, transfer: &TransferInfo) -> Result<(), (ErrorCode, String)> {
+        self.0
+            .iter()
+            .try_for_each(|handler| handler.on_request(transfer))
+    }
+
+    fn on_accept(&self, transfer: &TransferInfo, options: &[TransferOption]) {
+        for handler in &self.0 {
+            handler.on_accept(transfer, options);
+        }
+    }
+
+    fn on_transfer_start(&self, transfer: &TransferInfo) {
+        for handler in &self.0 {
+            handler.on_transfer_start(transfer);
+        }
+    }
+
+    fn on_block(&self, transfer: &TransferInfo, block: u16, bytes: u64) {
+        for handler in &self.0 {
+            handler.on_block(transfer, block, bytes);
+        }
+    }
+
+    fn on_complete(&self, transfer: &TransferInfo, bytes: u64) {
+        for handler in &self.0 {
+            handler.on_complete(transfer, bytes);
+        }
+    }
+
+    fn on_error(&self, transfer: &TransferInfo, error: &anyhow::Error) {
+        for handler in &self.0 {
+            handler.on_error(transfer, error);
+        }
+    }
+}
+
+/// Publishes the transfers of a server on the bus, e.g. with
+/// `Server::with_handler(Arc::new(EventBus::global().clone()))`.
+impl ServerHandler for EventBus {
+    fn on_transfer_start(&self, transfer: &TransferInfo) {
+        self.publish(Event::TransferStarted(transfer.clone()));
+    }
+
+    fn on_block(&self, transfer: &TransferInfo, _block: u16, bytes: u64) {
+        self.publish(Event::TransferProgress {
+            transfer: transfer.clone(),
+            bytes,
+        });
+    }
+
+    fn on_complete(&self, transfer: &TransferInfo, bytes: u64) {
+        self.publish(Event::TransferCompleted {
+            transfer: transfer.clone(),
+            bytes,
+        });
+    }
+
+    fn on_error(&self, transfer: &TransferInfo, error: &anyhow::Error) {
+        self.publish(Event::TransferFailed {
+            transfer: transfer.clone(),
+            error: error.to_string(),
+        });
+    }
+}
@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::{Config, Server, ShutdownHandle};
+
+/// TestServer `struct` is a [`Server`] listening on an ephemeral port of the
+/// loopback interface, so that tests run clients against a real server
+/// without root nor fixed ports. The server is stopped when dropped,
+/// abandoning the transfers in progress.
+///
+/// This `struct` is meant to be created by [`Server::spawn_for_test()`].
+///
+/// # Example
+///
+/// ```rust
+/// use xtool::tftp::client::{Client, config::ClientConfig};
+/// use xtool::tftp::server::Server;
+///
+/// let dir = std::env::temp_dir().join("xtool_spawn_for_test_doc");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("hello.txt"), b"hello").unwrap();
+///
+/// let server = Server::spawn_for_test(&dir).unwrap();
+/// let addr = server.addr();
+/// let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+/// client.get("hello.txt", &dir.join("copy.txt")).unwrap();
+/// server.shutdown();
+/// # std::fs::remove_dir_all(dir).unwrap();
+/// ```
+#[allow(dead_code)]
+pub struct TestServer {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    listener: Option<JoinHandle<()>>,
+}
+
+#[allow(dead_code)]
+impl TestServer {
+    /// Returns the address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a handle that stops the server, e.g. from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.handle.clone()
+    }
+
+    /// Stops the server, giving the transfers in progress the time of
+    /// [`ShutdownHandle::shutdown()`] to complete.
+    pub fn shutdown(mut self) {
+        self.handle.shutdown();
+        self.join();
+    }
+
+    fn join(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if self.listener.is_some() {
+            self.handle.shutdown_within(Duration::ZERO);
+            self.join();
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Server {
+    /// Serves `directory` on an ephemeral port of `127.0.0.1` from another
+    /// thread, returning once the server is bound.
+    pub fn spawn_for_test(directory: impl Into<PathBuf>) -> anyhow::Result<TestServer> {
+        let config =
+            Config::default().merge_cli("127.0.0.1".to_string(), 0, directory.into(), false, false);
+        Server::spawn_for_test_with(&config)
+    }
+
+    /// Runs a server with `config` from another thread like
+    /// [`Server::spawn_for_test()`], for tests needing more than a
+    /// directory. `config` should listen on port 0.
+    pub fn spawn_for_test_with(config: &Config) -> anyhow::Result<TestServer> {
+        let mut server = Server::new(config)?;
+        let addr = server.local_addr()?;
+        let handle = server.shutdown_handle();
+        let listener = thread::Builder::new()
+            .name(format!("tftp-test-{}", addr.port()))
+            .spawn(move || server.listen())?;
+
+        Ok(TestServer {
+            addr,
+            handle,
+            listener: Some(listener),
+        })
+    }
+}
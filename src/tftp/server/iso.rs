@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::provider::{FileProvider, normalize_name};
+
+const SECTOR_SIZE: u64 = 2048;
+/// Volume descriptors start after the 32 KiB system area
+const FIRST_DESCRIPTOR: u64 = 16;
+const MAX_DESCRIPTORS: u64 = 64;
+const MAX_DEPTH: usize = 32;
+const MAX_DIRECTORY_SIZE: u64 = 16 * 1024 * 1024;
+
+const TYPE_PRIMARY: u8 = 1;
+const TYPE_SUPPLEMENTARY: u8 = 2;
+const TYPE_TERMINATOR: u8 = 255;
+const FLAG_DIRECTORY: u8 = 0x02;
+
+/// Location of a file in the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Extent {
+    lba: u32,
+    size: u64,
+}
+
+/// IsoImage `struct` serves the files of an ISO 9660 image, such as boot
+/// media, without extracting it. The directory tree is walked once when the
+/// image is opened and kept in memory.
+///
+/// Joliet names are used when present. Lookups ignore case, and the `;1`
+/// version suffix of plain ISO 9660 names is dropped.
+pub struct IsoImage {
+    path: PathBuf,
+    files: HashMap<String, Extent>,
+}
+
+impl IsoImage {
+    /// Opens the image at `path` and caches its directory tree.
+    pub fn open(path: &Path) -> anyhow::Result<IsoImage> {
+        let mut file = File::open(path)?;
+        let (root, joliet) = read_root(&mut file)?;
+
+        let mut files = HashMap::new();
+        let mut visited = HashSet::new();
+        walk(&mut file, root, "", joliet, 0, &mut visited, &mut files)?;
+        log::info!("Serving {} files from {}", files.len(), path.display());
+
+        Ok(IsoImage {
+            path: path.to_path_buf(),
+            files,
+        })
+    }
+
+    fn extent(&self, name: &str) -> io::Result<Extent> {
+        self.files
+            .get(&normalize_name(name).to_lowercase())
+            .copied()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+impl FileProvider for IsoImage {
+    fn size(&self, name: &str) -> io::Result<u64> {
+        Ok(self.extent(name)?.size)
+    }
+
+    fn open(&self, name: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let extent = self.extent(name)?;
+        let offset = offset.min(extent.size);
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(extent.lba as u64 * SECTOR_SIZE + offset))?;
+        Ok(Box::new(file.take(extent.size - offset)))
+    }
+}
+
+/// Returns the root directory of the Joliet descriptor if there is one,
+/// otherwise of the primary descriptor, and whether it is Joliet.
+fn read_root(file: &mut File) -> anyhow::Result<(Extent, bool)> {
+    let mut primary = None;
+    let mut sector = vec![0; SECTOR_SIZE as usize];
+
+    for index in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+        file.seek(SeekFrom::Start(index * SECTOR_SIZE))?;
+        file.read_exact(&mut sector)?;
+        if &sector[1..6] != b"CD001" {
+            break;
+        }
+
+        match sector[0] {
+            TYPE_PRIMARY => primary = Some(record_extent(&sector[156..190])),
+            // UCS-2 escape sequences %/@, %/C and %/E mark Joliet
+            TYPE_SUPPLEMENTARY
+                if sector[88] == b'%'
+                    && sector[89] == b'/'
+                    && matches!(sector[90], b'@' | b'C' | b'E') =>
+            {
+                return Ok((record_extent(&sector[156..190]), true));
+            }
+            TYPE_TERMINATOR => break,
+            _ => {}
+        }
+    }
+
+    primary
+        .map(|root| (root, false))
+        .ok_or_else(|| anyhow::anyhow!("not an ISO 9660 image"))
+}
+
+/// Adds the files below the directory at `dir` to `files`.
+fn walk(
+    file: &mut File,
+    dir: Extent,
+    prefix: &str,
+    joliet: bool,
+    depth: usize,
+    visited: &mut HashSet<u32>,
+    files: &mut HashMap<String, Extent>,
+) -> anyhow::Result<()> {
+    // Guards against loops in corrupted images
+    if depth > MAX_DEPTH || !visited.insert(dir.lba) {
+        return Ok(());
+    }
+    if dir.size > MAX_DIRECTORY_SIZE {
+        anyhow::bail!("directory of {} bytes is too large", dir.size);
+    }
+
+    let mut data = vec![0; dir.size as usize];
+    file.seek(SeekFrom::Start(dir.lba as u64 * SECTOR_SIZE))?;
+    file.read_exact(&mut data)?;
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let len = data[pos] as usize;
+        if len == 0 {
+            // Records do not cross sectors, the rest of this one is padding
+            pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            continue;
+        }
+        if len < 34 || pos + len > data.len() {
+            anyhow::bail!("invalid directory record");
+        }
+
+        let record = &data[pos..pos + len];
+        pos += len;
+
+        let name_len = record[32] as usize;
+        if 33 + name_len > record.len() {
+            anyhow::bail!("invalid directory record");
+        }
+        let raw_name = &record[33..33 + name_len];
+        // "." and ".." entries
+        if raw_name == [0] || raw_name == [1] {
+            continue;
+        }
+
+        let name = decode_name(raw_name, joliet);
+        let path = format!("{prefix}{name}");
+        let extent = record_extent(record);
+
+        if record[25] & FLAG_DIRECTORY != 0 {
+            walk(
+                file,
+                extent,
+                &format!("{path}/"),
+                joliet,
+                depth + 1,
+                visited,
+                files,
+            )?;
+        } else {
+            files.insert(path.to_lowercase(), extent);
+        }
+    }
+
+    Ok(())
+}
+
+fn record_extent(record: &[u8]) -> Extent {
+    Extent {
+        lba: u32::from_le_bytes(record[2..6].try_into().unwrap()),
+        size: u32::from_le_bytes(record[10..14].try_into().unwrap()) as u64,
+    }
+}
+
+fn decode_name(raw: &[u8], joliet: bool) -> String {
+    let name = if joliet {
+        let units: Vec<u16> = raw
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(raw).to_string()
+    };
+
+    let name = name.split(';').next().unwrap_or_default();
+    if joliet {
+        name.to_string()
+    } else {
+        // Plain names without an extension end with a dot
+        name.trim_end_matches('.').to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const DIR_NAME: &str = "target/test";
+
+    fn record(name: &[u8], lba: u32, size: u32, flags: u8) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[6..10].copy_from_slice(&lba.to_be_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[14..18].copy_from_slice(&size.to_be_bytes());
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn directory(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut sector = records.concat();
+        sector.resize(SECTOR_SIZE as usize, 0);
+        sector
+    }
+
+    /// Builds an image with `/BOOT/VMLINUZ;1` and `/README.TXT;1`
+    fn build_iso(kernel: &[u8]) -> Vec<u8> {
+        const ROOT: u32 = 18;
+        const BOOT: u32 = 19;
+        const README: u32 = 20;
+        const KERNEL: u32 = 21;
+        let sector = SECTOR_SIZE as usize;
+
+        let mut iso = vec![0; 16 * sector];
+
+        let mut primary = vec![0; sector];
+        primary[0] = TYPE_PRIMARY;
+        primary[1..6].copy_from_slice(b"CD001");
+        primary[6] = 1;
+        let root = record(&[0], ROOT, SECTOR_SIZE as u32, FLAG_DIRECTORY);
+        primary[156..156 + root.len()].copy_from_slice(&root);
+        iso.extend_from_slice(&primary);
+
+        let mut terminator = vec![0; sector];
+        terminator[0] = TYPE_TERMINATOR;
+        terminator[1..6].copy_from_slice(b"CD001");
+        iso.extend_from_slice(&terminator);
+
+        iso.extend_from_slice(&directory(&[
+            record(&[0], ROOT, SECTOR_SIZE as u32, FLAG_DIRECTORY),
+            record(&[1], ROOT, SECTOR_SIZE as u32, FLAG_DIRECTORY),
+            record(b"BOOT", BOOT, SECTOR_SIZE as u32, FLAG_DIRECTORY),
+            record(b"README.TXT;1", README, 11, 0),
+        ]));
+        iso.extend_from_slice(&directory(&[
+            record(&[0], BOOT, SECTOR_SIZE as u32, FLAG_DIRECTORY),
+            record(&[1], ROOT, SECTOR_SIZE as u32, FLAG_DIRECTORY),
+            record(b"VMLINUZ.;1", KERNEL, kernel.len() as u32, 0),
+        ]));
+
+        let mut readme = b"Hello, iso!".to_vec();
+        readme.resize(sector, 0);
+        iso.extend_from_slice(&readme);
+        iso.extend_from_slice(kernel);
+        iso.resize(iso.len().div_ceil(sector) * sector, 0);
+        iso
+    }
+
+    #[test]
+    fn serves_files_from_image() {
+        let kernel: Vec<u8> = (0..5_000u32).map(|i| (i % 17) as u8).collect();
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        let path = PathBuf::from(DIR_NAME).join("serves_files_from_image.iso");
+        fs::write(&path, build_iso(&kernel)).unwrap();
+        let image = IsoImage::open(&path).unwrap();
+
+        assert_eq!(image.files.len(), 2);
+        assert_eq!(image.size("readme.txt").unwrap(), 11);
+        assert_eq!(image.size("/boot/vmlinuz").unwrap(), kernel.len() as u64);
+
+        let mut content = Vec::new();
+        image
+            .open("BOOT/VMLINUZ", 4_000)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, &kernel[4_000..]);
+        assert_eq!(
+            image.size("boot").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn decodes_names() {
+        assert_eq!(decode_name(b"VMLINUZ.;1", false), "VMLINUZ");
+        assert_eq!(decode_name(b"README.TXT;1", false), "README.TXT");
+        assert_eq!(
+            decode_name(
+                &[0, b'i', 0, b'n', 0, b'i', 0, b't', 0, b';', 0, b'1'],
+                true
+            ),
+            "init"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::tftp::core::DigestAlgorithm;
+
+/// Algorithm used to fingerprint uploads recorded in the [`Journal`]
+pub const JOURNAL_DIGEST: DigestAlgorithm = DigestAlgorithm::Sha256;
+
+/// Journal `struct` records completed uploads (file path and content hash) so
+/// that a client re-uploading identical content can be acknowledged without
+/// the stored file being rewritten.
+///
+/// The journal only saves the rewrite: an upload is still received in full
+/// next to its target and hashed once complete. The target is never read
+/// again, its recorded hash is trusted as long as its size and modification
+/// time are those it had when recorded, or when the journal was opened.
+///
+/// The journal file uses the `sha256sum` line format (`<hash>  <path>`) and is
+/// only ever appended to; later lines take precedence over earlier ones.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::Path;
+/// use xtool::tftp::server::Journal;
+///
+/// std::fs::write("journal_example.bin", b"dump").unwrap();
+/// let journal = Journal::open(Path::new("journal_example.txt")).unwrap();
+/// journal.record(Path::new("journal_example.bin"), "abcd").unwrap();
+/// assert!(journal.contains(Path::new("journal_example.bin"), "abcd"));
+///
+/// std::fs::write("journal_example.bin", b"edited").unwrap();
+/// assert!(!journal.contains(Path::new("journal_example.bin"), "abcd"));
+/// std::fs::remove_file("journal_example.bin").unwrap();
+/// std::fs::remove_file("journal_example.txt").unwrap();
+/// ```
+pub struct Journal {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+/// Hash of a recorded file, with the size and modification time it had then
+struct Entry {
+    hash: String,
+    stamp: Option<(u64, SystemTime)>,
+}
+
+/// Returns the size and modification time of `file`, if it exists.
+fn stamp(file: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(file).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+impl Journal {
+    /// Opens the journal at `path`, loading existing entries if the file exists.
+    pub fn open(path: &Path) -> anyhow::Result<Journal> {
+        let mut entries = HashMap::new();
+
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                if let Some((hash, file)) = line.split_once("  ") {
+                    let file = PathBuf::from(file);
+                    let entry = Entry {
+                        hash: hash.to_string(),
+                        stamp: stamp(&file),
+                    };
+                    entries.insert(file, entry);
+                }
+            }
+        }
+
+        Ok(Journal {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Returns `true` if `file` was last recorded with content hash `hash` and
+    /// was not modified since.
+    pub fn contains(&self, file: &Path, hash: &str) -> bool {
+        self.entries
+            .lock()
+            .map(|entries| {
+                entries.get(file).is_some_and(|entry| {
+                    entry.hash == hash && entry.stamp.is_some() && entry.stamp == stamp(file)
+                })
+            })
+            .unwrap_or(false)
+    }
+
+    /// Records that `file` now holds content with hash `hash`. `file` is
+    /// expected to be in place, so that later modifications are detected.
+    pub fn record(&self, file: &Path, hash: &str) -> anyhow::Result<()> {
+        let mut entries = self
+            .entries
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock journal"))?;
+
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(journal, "{}  {}", hash, file.display())?;
+
+        let entry = Entry {
+            hash: hash.to_string(),
+            stamp: stamp(file),
+        };
+        entries.insert(file.to_path_buf(), entry);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR_NAME: &str = "target/test";
+
+    #[test]
+    fn records_and_reloads_entries() {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let path = PathBuf::from(DIR_NAME).join("records_and_reloads_entries.journal");
+        let _ = fs::remove_file(&path);
+        let a = PathBuf::from(DIR_NAME).join("records_and_reloads_entries.a");
+        let b = PathBuf::from(DIR_NAME).join("records_and_reloads_entries.b");
+        let c = PathBuf::from(DIR_NAME).join("records_and_reloads_entries.c");
+        fs::write(&a, b"a").unwrap();
+        fs::write(&b, b"b").unwrap();
+
+        let journal = Journal::open(&path).unwrap();
+        journal.record(&a, "1111").unwrap();
+        journal.record(&b, "2222").unwrap();
+        journal.record(&a, "3333").unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        assert!(journal.contains(&a, "3333"));
+        assert!(!journal.contains(&a, "1111"));
+        assert!(journal.contains(&b, "2222"));
+        assert!(!journal.contains(&c, "2222"));
+
+        // Modified files are no longer trusted
+        fs::write(&b, b"edited").unwrap();
+        assert!(!journal.contains(&b, "2222"));
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(a).unwrap();
+        fs::remove_file(b).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::tftp::core::{ErrorCode, TransferOption};
+
+use super::handler::{Direction, ServerHandler, TransferInfo};
+
+/// Format of the log of a server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lines of text meant to be read
+    #[default]
+    Text,
+    /// JSON objects, one per line, for log pipelines such as Loki or ELK
+    Json,
+}
+
+/// JsonLog `struct` writes an event for every request, start, completion
+/// and failure of a transfer as a JSON object on its own line, so that log
+/// pipelines ingest the activity of the server without parsing text.
+///
+/// Every object holds the `time`, the `event`, the `client`, the `file` and
+/// the `direction`, `read` or `write`. Transfers that started also hold the
+/// negotiated `options`, completed and failed ones the `bytes` transferred
+/// and the `duration_ms`, and failed ones the `error`:
+///
+/// ```json
+/// {"bytes":4194304,"client":"192.168.1.50:1069","direction":"read","duration_ms":812,"event":"complete","file":"zImage","options":{"blksize":1432},"time":"2026-10-15T07:26:22.183+02:00"}
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use xtool::tftp::server::{Config, JsonLog, Server};
+///
+/// let config = Config::with_defaults();
+/// let server = Server::new(&config)
+///     .unwrap()
+///     .with_handler(Arc::new(JsonLog::new(Box::new(std::io::sink()))));
+/// ```
+pub struct JsonLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    /// Options, start time and bytes transferred of the transfers in progress
+    transfers: Mutex<HashMap<TransferInfo, Transfer>>,
+}
+
+struct Transfer {
+    options: Map<String, Value>,
+    start: Instant,
+    bytes: u64,
+}
+
+impl JsonLog {
+    /// Creates the log of the transfers, written to `writer`.
+    pub fn new(writer: Box<dyn Write + Send>) -> JsonLog {
+        JsonLog {
+            writer: Mutex::new(writer),
+            transfers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates the log of the transfers, written to the standard output.
+    pub fn stdout() -> JsonLog {
+        JsonLog::new(Box::new(io::stdout()))
+    }
+
+    /// Writes `event` of `transfer` with `fields`, followed by the options,
+    /// bytes and duration of the transfer if it `ended`.
+    fn write(&self, event: &str, transfer: &TransferInfo, fields: Value, ended: bool) {
+        let mut object = json!({
+            "time": chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            "event": event,
+            "client": transfer.peer.to_string(),
+            "file": transfer.filename,
+            "direction": match transfer.direction {
+                Direction::Read => "read",
+                Direction::Write => "write",
+            },
+        });
+        let state = self.transfers.lock().ok().and_then(|mut transfers| {
+            if ended {
+                transfers.remove(transfer)
+            } else {
+                None
+            }
+        });
+        if let Some(object) = object.as_object_mut() {
+            if let Some(state) = state {
+                object.insert("options".to_string(), Value::Object(state.options));
+                object.insert("bytes".to_string(), json!(state.bytes));
+                let duration = state.start.elapsed().as_millis() as u64;
+                object.insert("duration_ms".to_string(), json!(duration));
+            }
+            if let Value::Object(fields) = fields {
+                object.extend(fields);
+            }
+        }
+
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(writer, "{object}").and_then(|_| writer.flush());
+        }
+    }
+}
+
+impl ServerHandler for JsonLog {
+    fn on_request(&self, transfer: &TransferInfo) -> Result<(), (ErrorCode, String)> {
+        self.write("request", transfer, json!({}), false);
+        Ok(())
+    }
+
+    fn on_accept(&self, transfer: &TransferInfo, options: &[TransferOption]) {
+        let options: Map<String, Value> = options
+            .iter()
+            .map(|option| (option.option.as_str().to_string(), json!(option.value)))
+            .collect();
+        if let Ok(mut transfers) = self.transfers.lock() {
+            transfers.insert(
+                transfer.clone(),
+                Transfer {
+                    options: options.clone(),
+                    start: Instant::now(),
+                    bytes: 0,
+                },
+            );
+        }
+        self.write("start", transfer, json!({ "options": options }), false);
+    }
+
+    fn on_block(&self, transfer: &TransferInfo, _block: u16, bytes: u64) {
+        if let Ok(mut transfers) = self.transfers.lock()
+            && let Some(state) = transfers.get_mut(transfer)
+        {
+            state.bytes = bytes;
+        }
+    }
+
+    fn on_complete(&self, transfer: &TransferInfo, bytes: u64) {
+        self.write("complete", transfer, json!({ "bytes": bytes }), true);
+    }
+
+    fn on_error(&self, transfer: &TransferInfo, error: &anyhow::Error) {
+        let fields = json!({ "error": error.to_string() });
+        self.write("error", transfer, fields, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::core::OptionType;
+    use std::sync::Arc;
+
+    /// Writer whose content the test reads back
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_events_as_json_lines() {
+        let buffer = Buffer::default();
+        let log = JsonLog::new(Box::new(buffer.clone()));
+        let transfer = TransferInfo {
+            peer: "192.168.1.20:1069".parse().unwrap(),
+            filename: "boot/zImage".to_string(),
+            direction: Direction::Read,
+            netascii: false,
+            custom_options: vec![],
+        };
+        let options = [TransferOption {
+            option: OptionType::BlockSize,
+            value: 1432,
+        }];
+
+        log.on_request(&transfer).unwrap();
+        log.on_accept(&transfer, &options);
+        log.on_block(&transfer, 1, 1432);
+        log.on_complete(&transfer, 2048);
+        log.on_error(&transfer, &anyhow::anyhow!("Transfer timed out"));
+
+        let content = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0]["event"], "request");
+        assert_eq!(events[0]["client"], "192.168.1.20:1069");
+        assert_eq!(events[0]["file"], "boot/zImage");
+        assert_eq!(events[0]["direction"], "read");
+        assert_eq!(events[1]["event"], "start");
+        assert_eq!(events[1]["options"], json!({ "blksize": 1432 }));
+        assert_eq!(events[2]["event"], "complete");
+        assert_eq!(events[2]["bytes"], 2048);
+        assert_eq!(events[2]["options"], json!({ "blksize": 1432 }));
+        assert!(events[2]["duration_ms"].is_u64());
+        // Nothing is known of a transfer which already ended
+        assert_eq!(events[3]["event"], "error");
+        assert_eq!(events[3]["error"], "Transfer timed out");
+        assert!(events[3].get("bytes").is_none());
+        assert!(log.transfers.lock().unwrap().is_empty());
+    }
+}
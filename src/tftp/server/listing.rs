@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::tftp::core::DigestAlgorithm;
+
+use super::worker::UPLOAD_TMP_SUFFIX;
+
+/// Name of the virtual file listing the files of the root, requested by
+/// `xtool tftpc mirror`
+pub const LISTING_FILENAME: &str = ".xtool-listing";
+
+/// Listing `struct` builds the list of the files of a served directory, in
+/// the manifest format of the client: one `<name> <size> <sha256>` line per
+/// file, with `/` separators.
+///
+/// Checksums are cached by size and modification time, so only files that
+/// changed are hashed again. Files whose name contains whitespace cannot be
+/// listed, and files of xtool itself such as resume state are left out.
+#[derive(Debug, Default)]
+pub struct Listing {
+    cache: HashMap<PathBuf, (u64, SystemTime, String)>,
+}
+
+impl Listing {
+    /// Returns the listing of the files below `root`.
+    pub fn generate(&mut self, root: &Path) -> anyhow::Result<String> {
+        let mut files = Vec::new();
+        collect_files(root, root, &mut files)?;
+        files.sort();
+
+        let mut listing = String::new();
+        for (name, path) in files {
+            let metadata = fs::metadata(&path)?;
+            let modified = metadata.modified()?;
+            let sha256 = match self.cache.get(&path) {
+                Some((len, time, sha256)) if *len == metadata.len() && *time == modified => {
+                    sha256.clone()
+                }
+                _ => {
+                    let sha256 = DigestAlgorithm::Sha256.digest_file(&path)?;
+                    self.cache
+                        .insert(path, (metadata.len(), modified, sha256.clone()));
+                    sha256
+                }
+            };
+            listing.push_str(&format!("{name} {} {sha256}\n", metadata.len()));
+        }
+        Ok(listing)
+    }
+}
+
+/// Adds the files below `dir` to `files`, as their name relative to `root`
+/// and their path. Symbolic links to directories are not followed.
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(String, PathBuf)>,
+) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+            continue;
+        }
+        if !path.is_file() {
+            continue;
+        }
+
+        let name = path
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        // Resume state and other files of xtool itself, and uploads in progress
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.contains(".xtool-") || file_name.ends_with(UPLOAD_TMP_SUFFIX) {
+            continue;
+        }
+        if name.contains(char::is_whitespace) {
+            log::debug!("Cannot list {name}, its name contains whitespace");
+            continue;
+        }
+        files.push((name, path));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR_NAME: &str = "target/test/listing";
+    const SHA256_EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn lists_files() {
+        let root = Path::new(DIR_NAME);
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("boot")).unwrap();
+        fs::write(root.join("boot/zImage"), b"").unwrap();
+        fs::write(root.join("a b.txt"), b"").unwrap();
+        fs::write(root.join("dtb.xtool-resume"), b"").unwrap();
+        fs::write(root.join("dtb"), b"hello").unwrap();
+
+        let mut listing = Listing::default();
+        let content = listing.generate(root).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("boot/zImage 0 {SHA256_EMPTY}"));
+        assert!(lines[1].starts_with("dtb 5 "));
+
+        // Changed files are hashed again
+        fs::write(root.join("boot/zImage"), b"hello").unwrap();
+        let content = listing.generate(root).unwrap();
+        assert!(content.starts_with("boot/zImage 5 "));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
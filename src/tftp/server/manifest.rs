@@ -0,0 +1,121 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+
+/// Name of the manifest kept in the root directory
+pub const MANIFEST_FILENAME: &str = "MANIFEST.sha256";
+
+/// Manifest `struct` appends an entry to `MANIFEST.sha256` in the root
+/// directory for every completed upload, an integrity record operators can
+/// check without external tooling.
+///
+/// Each line reads `<sha256> <size> <client> <timestamp> <name>`, the name
+/// last and relative to the root with `/` separators, the timestamp in RFC
+/// 3339 UTC. Later lines for a name supersede earlier ones.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::Path;
+/// use xtool::tftp::server::Manifest;
+///
+/// let manifest = Manifest::new(Path::new("."));
+/// assert!(manifest.path().ends_with("MANIFEST.sha256"));
+/// ```
+#[derive(Debug)]
+pub struct Manifest {
+    root: PathBuf,
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Manifest {
+    /// Creates the manifest of the uploads received in `root`.
+    pub fn new(root: &Path) -> Manifest {
+        Manifest {
+            root: root.to_path_buf(),
+            path: root.join(MANIFEST_FILENAME),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns the path of the manifest file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the entry of `file`, an upload of `size` bytes with content
+    /// hash `sha256` received from `client`.
+    pub fn record(
+        &self,
+        file: &Path,
+        size: u64,
+        sha256: &str,
+        client: &SocketAddr,
+    ) -> anyhow::Result<()> {
+        let name = upload_name(file, &self.root);
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock manifest"))?;
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(manifest, "{sha256} {size} {client} {timestamp} {name}")?;
+
+        Ok(())
+    }
+}
+
+/// Returns the name of the upload `file` relative to `root`, with `/`
+/// separators.
+pub(super) fn upload_name(file: &Path, root: &Path) -> String {
+    file.strip_prefix(root)
+        .unwrap_or(file)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn appends_entries() {
+        let root = PathBuf::from("target/test/manifest_appends_entries");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let client: SocketAddr = "192.168.1.20:1069".parse().unwrap();
+
+        let manifest = Manifest::new(&root);
+        manifest
+            .record(&root.join("boot").join("zImage"), 42, "abcd", &client)
+            .unwrap();
+        manifest
+            .record(&root.join("my image.bin"), 7, "ef01", &client)
+            .unwrap();
+
+        let content = fs::read_to_string(root.join(MANIFEST_FILENAME)).unwrap();
+        let lines: Vec<Vec<&str>> = content
+            .lines()
+            .map(|line| line.splitn(5, ' ').collect())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][..3], ["abcd", "42", "192.168.1.20:1069"]);
+        assert_eq!(lines[0][4], "boot/zImage");
+        assert!(lines[0][3].ends_with('Z'));
+        assert_eq!(lines[1][4], "my image.bin");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use super::fs::{FileWriter, Metadata, TftpFs};
+
+type Files = Arc<RwLock<HashMap<String, Arc<[u8]>>>>;
+
+/// MemoryFs `struct` is a [`TftpFs`] keeping its files in memory, e.g. to
+/// serve generated boot configurations or collect uploads without a disk.
+///
+/// Clones share the same files. Uploads become visible once complete.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: Files,
+}
+
+impl MemoryFs {
+    /// Creates an empty `MemoryFs`.
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds or replaces the file `name`.
+    pub fn insert(&self, name: &str, content: impl Into<Vec<u8>>) {
+        self.files
+            .write()
+            .unwrap()
+            .insert(name.to_string(), content.into().into());
+    }
+
+    /// Returns the content of the file `name`.
+    pub fn get(&self, name: &str) -> Option<Vec<u8>> {
+        self.files.read().unwrap().get(name).map(|c| c.to_vec())
+    }
+
+    fn file(&self, path: &Path) -> io::Result<Arc<[u8]>> {
+        self.files
+            .read()
+            .unwrap()
+            .get(path.to_string_lossy().as_ref())
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+impl TftpFs for MemoryFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(Metadata {
+            len: self.file(path)?.len() as u64,
+        })
+    }
+
+    fn open_read(&self, path: &Path, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut reader = io::Cursor::new(self.file(path)?);
+        reader.set_position(offset);
+        Ok(Box::new(reader))
+    }
+
+    fn open_write(&self, path: &Path) -> io::Result<Box<dyn FileWriter>> {
+        Ok(Box::new(MemoryWriter {
+            files: self.files.clone(),
+            name: path.to_string_lossy().into_owned(),
+            content: Vec::new(),
+        }))
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        let mut files = self.files.write().unwrap();
+        let name = path.to_string_lossy();
+        if files.contains_key(name.as_ref()) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        files.insert(name.into_owned(), Default::default());
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.files
+            .write()
+            .unwrap()
+            .remove(path.to_string_lossy().as_ref());
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files.write().unwrap();
+        let content = files
+            .remove(from.to_string_lossy().as_ref())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        files.insert(to.to_string_lossy().into_owned(), content);
+        Ok(())
+    }
+}
+
+struct MemoryWriter {
+    files: Files,
+    name: String,
+    content: Vec<u8>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.content.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileWriter for MemoryWriter {
+    fn allocate(&mut self, len: u64) -> io::Result<()> {
+        self.content
+            .try_reserve_exact(len.try_into().unwrap_or(usize::MAX))
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))
+    }
+
+    fn finish(&mut self, len: u64) -> io::Result<()> {
+        self.content.truncate(len.try_into().unwrap_or(usize::MAX));
+        let content = std::mem::take(&mut self.content);
+        self.files
+            .write()
+            .unwrap()
+            .insert(self.name.clone(), content.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let fs = MemoryFs::new();
+        fs.insert("boot/vmlinuz", b"kernel".to_vec());
+        assert_eq!(fs.metadata(Path::new("boot/vmlinuz")).unwrap().len, 6);
+        assert_eq!(
+            fs.metadata(Path::new("missing")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let mut writer = fs.open_write(Path::new("upload.bin")).unwrap();
+        writer.allocate(4).unwrap();
+        writer.write_all(b"data").unwrap();
+        // Not visible until complete
+        assert_eq!(fs.get("upload.bin"), None);
+        writer.finish(4).unwrap();
+        assert_eq!(fs.get("upload.bin").unwrap(), b"data");
+
+        let mut content = Vec::new();
+        fs.open_read(Path::new("upload.bin"), 2)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"ta");
+
+        fs.remove(Path::new("upload.bin")).unwrap();
+        assert_eq!(fs.get("upload.bin"), None);
+    }
+}
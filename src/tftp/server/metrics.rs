@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::tftp::core::{ErrorCode, Packet, Socket};
+
+use super::handler::Direction;
+
+/// Time a scrape may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Metrics `struct` counts the activity of a server, exposed in the
+/// Prometheus text format by [`Metrics::serve()`] so that lab servers can be
+/// monitored like everything else.
+///
+/// Transfers are counted through their [`MetricsSocket`], errors through
+/// [`Metrics::error_sent()`] for those sent by the server itself.
+#[derive(Debug, Default)]
+pub(super) struct Metrics {
+    active: AtomicI64,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    retransmissions: AtomicU64,
+    /// Error packets sent, by code
+    errors: Mutex<BTreeMap<u16, u64>>,
+}
+
+impl Metrics {
+    /// Counts an error packet sent with `code`.
+    pub fn error_sent(&self, code: ErrorCode) {
+        if let Ok(mut errors) = self.errors.lock() {
+            *errors.entry(code as u16).or_default() += 1;
+        }
+    }
+
+    /// Returns `job`, the worker of a transfer, counted as active while it
+    /// runs. Uploads are no longer active while dallying.
+    pub fn track<T>(
+        self: &Arc<Self>,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> impl FnOnce() -> T + Send + 'static {
+        let metrics = self.clone();
+        move || {
+            metrics.active.fetch_add(1, Ordering::Relaxed);
+            let _active = Active(metrics);
+            job()
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+            let _ = writeln!(text, "# HELP {name} {help}");
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{name}{labels} {value}");
+            }
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        metric(
+            "xtool_tftp_active_transfers",
+            "gauge",
+            "Transfers in progress.",
+            &[("", self.active.load(Ordering::Relaxed).max(0) as u64)],
+        );
+        metric(
+            "xtool_tftp_transfers_total",
+            "counter",
+            "Transfers started, by direction.",
+            &[
+                ("{direction=\"read\"}", load(&self.reads)),
+                ("{direction=\"write\"}", load(&self.writes)),
+            ],
+        );
+        metric(
+            "xtool_tftp_bytes_sent_total",
+            "counter",
+            "Bytes of file data sent, retransmissions excluded.",
+            &[("", load(&self.bytes_sent))],
+        );
+        metric(
+            "xtool_tftp_bytes_received_total",
+            "counter",
+            "Bytes of file data received, duplicates excluded.",
+            &[("", load(&self.bytes_received))],
+        );
+        metric(
+            "xtool_tftp_retransmissions_total",
+            "counter",
+            "Data blocks and acknowledgements sent again.",
+            &[("", load(&self.retransmissions))],
+        );
+        let errors = self
+            .errors
+            .lock()
+            .map(|errors| errors.clone())
+            .unwrap_or_default();
+        let labels: Vec<(String, u64)> = errors
+            .into_iter()
+            .map(|(code, count)| (format!("{{code=\"{code}\"}}"), count))
+            .collect();
+        let samples: Vec<(&str, u64)> = labels
+            .iter()
+            .map(|(labels, count)| (labels.as_str(), *count))
+            .collect();
+        metric(
+            "xtool_tftp_errors_total",
+            "counter",
+            "Error packets sent to clients, by error code.",
+            &samples,
+        );
+        text
+    }
+
+    /// Serves the metrics over HTTP on `addr`, at `/metrics`, from a
+    /// background thread. Fails if `addr` cannot be bound.
+    pub fn serve(self: &Arc<Self>, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| anyhow::anyhow!("Cannot serve metrics on {addr}: {e}"))?;
+        let local_addr = listener.local_addr()?;
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                if let Err(err) = metrics.respond(stream) {
+                    log::debug!("Metrics request failed: {err}");
+                }
+            }
+        });
+        Ok(local_addr)
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Headers are not used, but are read so that the client sees the whole answer
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        let (status, content_type, body) = match path.split('?').next() {
+            Some("/metrics") => ("200 OK", "text/plain; version=0.0.4", self.render()),
+            _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        Ok(())
+    }
+}
+
+/// Counts a transfer as no longer active once dropped, even if its worker
+/// panicked.
+struct Active(Arc<Metrics>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// MetricsSocket `struct` wraps the [`Socket`] of a transfer, counting the
+/// transfer along with the data, retransmissions and errors it carries.
+pub(super) struct MetricsSocket<T: Socket + ?Sized> {
+    inner: Box<T>,
+    metrics: Arc<Metrics>,
+    /// Highest block sent, of data or acknowledgements
+    sent: Mutex<Option<u16>>,
+    /// Highest data block received
+    received: Mutex<Option<u16>>,
+}
+
+impl<T: Socket + ?Sized> MetricsSocket<T> {
+    /// Counts the transfer in `direction` using `inner` in `metrics`.
+    pub fn new(inner: Box<T>, metrics: Arc<Metrics>, direction: Direction) -> MetricsSocket<T> {
+        match direction {
+            Direction::Read => metrics.reads.fetch_add(1, Ordering::Relaxed),
+            Direction::Write => metrics.writes.fetch_add(1, Ordering::Relaxed),
+        };
+        MetricsSocket {
+            inner,
+            metrics,
+            sent: Mutex::new(None),
+            received: Mutex::new(None),
+        }
+    }
+
+    fn count_sent(&self, packet: &Packet) {
+        let (block, len) = match packet {
+            Packet::Data { block_num, data } => (*block_num, data.len()),
+            Packet::Ack(block_num) => (*block_num, 0),
+            Packet::Error { code, .. } => return self.metrics.error_sent(*code),
+            _ => return,
+        };
+        self.count_sent_block(block, len);
+    }
+
+    /// Counts the block `block` sent with `len` bytes of data.
+    fn count_sent_block(&self, block: u16, len: usize) {
+        if !advance(&self.sent, block) {
+            self.metrics.retransmissions.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let len = len as u64;
+            self.metrics.bytes_sent.fetch_add(len, Ordering::Relaxed);
+        }
+    }
+
+    fn count_received(&self, packet: &Packet) {
+        if let Packet::Data { block_num, data } = packet
+            && advance(&self.received, *block_num)
+        {
+            let len = data.len() as u64;
+            self.metrics
+                .bytes_received
+                .fetch_add(len, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Moves `highest` to `block` if it is past it, accounting for the block
+/// counter rolling over. Returns whether it moved.
+fn advance(highest: &Mutex<Option<u16>>, block: u16) -> bool {
+    let Ok(mut highest) = highest.lock() else {
+        return true;
+    };
+    match *highest {
+        Some(previous) if (block.wrapping_sub(previous) as i16) <= 0 => false,
+        _ => {
+            *highest = Some(block);
+            true
+        }
+    }
+}
+
+impl<T: Socket + ?Sized> Socket for MetricsSocket<T> {
+    fn send(&self, packet: &Packet) -> anyhow::Result<()> {
+        self.inner.send(packet)?;
+        self.count_sent(packet);
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
+        self.inner.send_to(packet, to)?;
+        self.count_sent(packet);
+        Ok(())
+    }
+
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        self.inner.send_data(block_num, data)?;
+        self.count_sent_block(block_num, data.len());
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> anyhow::Result<Packet> {
+        let packet = self.inner.recv_with_size(size)?;
+        self.count_received(&packet);
+        Ok(packet)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> anyhow::Result<(Packet, SocketAddr)> {
+        let (packet, from) = self.inner.recv_from_with_size(size)?;
+        self.count_received(&packet);
+        Ok((packet, from))
+    }
+
+    fn remote_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.inner.remote_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.inner.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> anyhow::Result<()> {
+        self.inner.set_write_timeout(dur)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> anyhow::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::core::ReplaySocket;
+
+    #[test]
+    fn counts_transfers() {
+        let metrics = Arc::new(Metrics::default());
+        let data = |block_num| Packet::Data {
+            block_num,
+            data: vec![0; 512],
+        };
+
+        let socket = MetricsSocket::new(
+            Box::new(ReplaySocket::new(&Default::default())),
+            metrics.clone(),
+            Direction::Read,
+        );
+        let active = metrics.clone();
+        metrics.track(move || assert_eq!(active.active.load(Ordering::Relaxed), 1))();
+        socket.count_sent(&data(65535));
+        socket.count_sent(&data(0));
+        socket.count_sent(&data(65535));
+        socket.count_sent(&Packet::Error {
+            code: ErrorCode::DiskFull,
+            msg: String::new(),
+        });
+        drop(socket);
+        metrics.error_sent(ErrorCode::FileNotFound);
+
+        let text = metrics.render();
+        for line in [
+            "xtool_tftp_active_transfers 0",
+            "xtool_tftp_transfers_total{direction=\"read\"} 1",
+            "xtool_tftp_bytes_sent_total 1024",
+            "xtool_tftp_retransmissions_total 1",
+            "xtool_tftp_errors_total{code=\"1\"} 1",
+            "xtool_tftp_errors_total{code=\"3\"} 1",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{line} missing from {text}"
+            );
+        }
+    }
+}
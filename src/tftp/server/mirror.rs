@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::tftp::core::Packet;
+
+/// How long a file is given to be downloaded from the mirror
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Mirror `struct` fetches the files missing from the served directory from
+/// an HTTP(S) mirror, keeping them there for the next requests, so that the
+/// server acts as a lazy caching boot proxy in front of a remote repository.
+///
+/// A file is fetched from `<url>/<name>` on another thread, so that the
+/// server goes on serving other clients meanwhile. The requests for it wait
+/// until the fetch finishes, to be served from disk then, or answered with a
+/// file not found error if the mirror failed.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd]
+/// mirror = "https://boot.example.com/tftp"
+/// ```
+#[derive(Debug)]
+pub(super) struct Mirror {
+    url: String,
+    /// Files being fetched, mapped to the result of the fetch once finished
+    fetches: Fetches,
+    /// Requests waiting for the files being fetched
+    waiting: Mutex<HashMap<PathBuf, Vec<Waiting>>>,
+}
+
+type Fetches = Arc<Mutex<HashMap<PathBuf, Option<Result<(), String>>>>>;
+
+/// Request waiting for a file, its client and the index of its socket
+pub(super) type Waiting = (Packet, SocketAddr, usize);
+
+/// State of the fetch of a missing file
+#[derive(Debug, PartialEq)]
+pub(super) enum Fetch {
+    /// Being downloaded, the request waits until [`Mirror::finished()`]
+    Pending,
+    /// The fetch could not be started
+    Failed(String),
+}
+
+impl Mirror {
+    pub fn new(url: &str) -> Mirror {
+        Mirror {
+            url: url.trim_end_matches('/').to_string(),
+            fetches: Arc::new(Mutex::new(HashMap::new())),
+            waiting: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Fetches the requested `name` to `file_path`, unless already being
+    /// fetched, `request` waiting until the fetch finishes. Retransmissions
+    /// of a waiting request are ignored.
+    pub fn fetch(&self, name: &str, file_path: &Path, request: Waiting) -> Fetch {
+        let mut fetches = self.fetches.lock().unwrap();
+        let mut waiting = self.waiting.lock().unwrap();
+        let requests = waiting.entry(file_path.to_path_buf()).or_default();
+        if !requests.iter().any(|(_, from, _)| *from == request.1) {
+            requests.push(request);
+        }
+        if fetches.contains_key(file_path) {
+            return Fetch::Pending;
+        }
+
+        let url = format!("{}/{}", self.url, name.trim_start_matches('/'));
+        log::info!("  Fetching {name} from {url}");
+        fetches.insert(file_path.to_path_buf(), None);
+        let (shared, path) = (self.fetches.clone(), file_path.to_path_buf());
+        let spawned = thread::Builder::new()
+            .name("tftp-mirror".to_string())
+            .spawn(move || {
+                let downloaded = download(&url, &path);
+                let mut fetches = shared.lock().unwrap();
+                match downloaded {
+                    Ok(size) => {
+                        log::info!("  Fetched {url} ({size} bytes)");
+                        fetches.insert(path, Some(Ok(())));
+                    }
+                    Err(err) => {
+                        log::warn!("  Cannot fetch {url}: {err}");
+                        fetches.insert(path, Some(Err(err.to_string())));
+                    }
+                }
+            });
+        match spawned {
+            Ok(_) => Fetch::Pending,
+            Err(err) => {
+                fetches.remove(file_path);
+                waiting.remove(file_path);
+                Fetch::Failed(err.to_string())
+            }
+        }
+    }
+
+    /// Returns the requests waiting for the fetches finished since the last
+    /// call, with the result of their fetch.
+    pub fn finished(&self) -> Vec<(Vec<Waiting>, Result<(), String>)> {
+        let mut fetches = self.fetches.lock().unwrap();
+        let mut waiting = self.waiting.lock().unwrap();
+        let done: Vec<PathBuf> = fetches
+            .iter()
+            .filter(|(_, result)| result.is_some())
+            .map(|(path, _)| path.clone())
+            .collect();
+        done.into_iter()
+            .map(|path| {
+                let result = fetches.remove(&path).flatten().unwrap_or(Ok(()));
+                (waiting.remove(&path).unwrap_or_default(), result)
+            })
+            .collect()
+    }
+}
+
+/// Downloads `url` to `file_path` through a temporary file next to it, so
+/// that partial downloads are never served.
+fn download(url: &str, file_path: &Path) -> anyhow::Result<u64> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(MIRROR_TIMEOUT))
+        .build()
+        .into();
+    let mut response = agent.get(url).call()?;
+
+    if let Some(parent) = file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut partial = file_path.as_os_str().to_owned();
+    partial.push(".mirror");
+    let partial = PathBuf::from(partial);
+    let copied = File::create(&partial).and_then(|mut file| {
+        let size = std::io::copy(&mut response.body_mut().as_reader(), &mut file)?;
+        file.sync_all()?;
+        Ok(size)
+    });
+    let size = match copied {
+        Ok(size) => size,
+        Err(err) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(err.into());
+        }
+    };
+    std::fs::rename(&partial, file_path)?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn fetches_missing_files() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tftp/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(["200 OK", "404 Not Found"]) {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+                assert!(lines.next().unwrap().unwrap().starts_with("GET /tftp/"));
+                while !lines.next().unwrap().unwrap().is_empty() {}
+                let body = if status.starts_with("200") {
+                    "kernel"
+                } else {
+                    ""
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+
+        let dir = std::env::temp_dir().join(format!("xtool-mirror-{}", std::process::id()));
+        let mirror = Mirror::new(&url);
+        assert_eq!(mirror.url(), url.trim_end_matches('/'));
+
+        let request = |from: &str| {
+            let packet = Packet::Rrq {
+                filename: "boot/vmlinuz".to_string(),
+                mode: "octet".to_string(),
+                options: vec![],
+                custom: vec![],
+            };
+            (packet, from.parse().unwrap(), 0)
+        };
+        let wait = || loop {
+            match mirror.finished().pop() {
+                Some(finished) => break finished,
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        let file_path = dir.join("boot").join("vmlinuz");
+        let fetch = |request| mirror.fetch("boot/vmlinuz", &file_path, request);
+        assert_eq!(fetch(request("10.0.0.1:1069")), Fetch::Pending);
+        assert_eq!(fetch(request("10.0.0.2:1069")), Fetch::Pending);
+        // Retransmitted
+        assert_eq!(fetch(request("10.0.0.1:1069")), Fetch::Pending);
+        let (requests, result) = wait();
+        assert_eq!(
+            requests,
+            [request("10.0.0.1:1069"), request("10.0.0.2:1069")]
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "kernel");
+
+        let missing = dir.join("missing");
+        let fetch = mirror.fetch("missing", &missing, request("10.0.0.1:1069"));
+        assert_eq!(fetch, Fetch::Pending);
+        let (requests, result) = wait();
+        assert_eq!(requests.len(), 1);
+        assert!(result.unwrap_err().contains("404"));
+        assert!(mirror.finished().is_empty());
+        assert!(!missing.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
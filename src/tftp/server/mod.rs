@@ -2,19 +2,168 @@
 //!
 //! This module provides complete TFTP server functionality:
 //! - `server`: Main server logic, handles client requests
+//! - `async_server`: Server running on the tokio runtime, one task per transfer
 //! - `worker`: Worker threads, handles file transfers
+//! - `pool`: Bounded pool of reused threads the transfers run on
+//! - `activity`: Last packet of each transfer, to reap those whose client vanished
 //! - `config`: Server configuration
+//! - `bind`: Addresses and sockets the server listens on, IPv4, IPv6 or both
+//! - `acl`: Networks allowed or denied access to the server
+//! - `priority`: Order in which requests are started when transfers are limited
+//! - `privileges`: Privilege drop once the server port is bound
+//! - `quota`: Limit on the bytes written by uploads
+//! - `multicast`: Files sent once to the group of clients downloading them (RFC 2090)
+//! - `overwrite`: What becomes of existing files replaced by uploads
+//! - `cache`: Small files kept in memory for many clients downloading them
+//! - `compress`: Files compressed with gzip on the fly for slow links
+//! - `reload`: Reload of the configuration on SIGHUP
+//! - `harness`: Servers spawned on an ephemeral port for tests
+//! - `service`: Install and run of the server as a Windows service
+//! - `replay`: Replay of recorded sessions against the transfer logic
+//! - `rewrite`: Rules rewriting the requested file names
+//! - `roots`: Directories serving the names starting with a prefix
+//! - `boot_map`: Boot trees or files served to the clients of a network
+//! - `symlinks`: Policy for the symbolic links leading out of the served directory
+//! - `sessions`: Routing of the packets received in single port mode
+//! - `dynamic`: Files generated on request instead of served from the root
+//! - `templates`: Variables of the client expanded in the text files served
+//! - `mirror`: Missing files fetched from an HTTP mirror and kept in the root
+//! - `handler`: Callbacks notified of the lifecycle of transfers
+//! - `status`: Progress of the transfers in progress, for admin interfaces
+//! - `admin`: Unix socket accepting commands to manage a running server
+//! - `journal`: Record of completed uploads for duplicate detection
+//! - `manifest`: Integrity record of the completed uploads
+//! - `webhook`: Completed uploads POSTed to a URL as JSON
+//! - `metrics`: Counters of the server, served to Prometheus over HTTP
+//! - `json_log`: Transfer events written as JSON objects for log pipelines
+//! - `transfer_log`: Log of the transfers in the xferlog format of wu-ftpd
+//! - `listing`: List of the files served, for clients mirroring the root
+//! - `fs`: Storage the files are served from, on disk by default
+//! - `memory`: Files kept in memory, served instead of a directory
+//! - `s3`: Bucket of an S3-compatible object storage served instead of a directory
+//! - `s3_fs`: Objects of a bucket read through a cache on disk (`s3` feature)
+//! - `dtls`: Endpoint relaying the transfers of DTLS sessions to the server
+//! - `dtls_terminator`: Sessions decrypted and relayed, one thread each (`dtls` feature)
+//! - `provider`: Read-only roots served from `.zip` and `.tar` archives and `.iso` images
 
+// Only used through the library
+mod acl;
+mod activity;
+mod admin;
+#[allow(dead_code)]
+mod async_server;
+mod bind;
+mod boot_map;
+mod cache;
+mod compress;
 pub mod config;
+mod dtls;
+#[cfg(feature = "dtls")]
+mod dtls_terminator;
+mod dynamic;
+mod fs;
+mod handler;
+mod harness;
+mod iso;
+mod journal;
+mod json_log;
+mod listing;
+mod manifest;
+mod metrics;
+mod mirror;
+mod multicast;
+mod overwrite;
+// Only used through the library
+#[allow(dead_code)]
+mod memory;
+mod pool;
+mod priority;
+mod privileges;
+mod provider;
+mod quota;
+mod reload;
+// Only used through the library
+#[allow(dead_code)]
+mod replay;
+mod rewrite;
+mod roots;
+mod s3;
+#[cfg(feature = "s3")]
+mod s3_fs;
+#[allow(clippy::module_inception)]
 mod server;
+pub mod service;
+mod sessions;
+mod status;
+mod symlinks;
+mod tar;
+mod templates;
+mod transfer_log;
+mod webhook;
 mod worker;
+mod zip;
 
 use anyhow::Result;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 // Public server types
+#[allow(unused_imports)]
+pub use acl::Acl;
+#[allow(unused_imports)]
+pub use async_server::AsyncServer;
+#[allow(unused_imports)]
+pub use boot_map::BootMapping;
+#[allow(unused_imports)]
+pub use cache::FileCache;
 pub use config::Config;
+#[allow(unused_imports)]
+pub use dtls::DtlsListener;
+#[allow(unused_imports)]
+pub use dynamic::{ContentFn, DynamicContent};
+#[allow(unused_imports)]
+pub use fs::{DiskFs, FileWriter, Metadata, TftpFs};
+#[allow(unused_imports)]
+pub use handler::{Direction, ServerHandler, TransferInfo};
+#[allow(unused_imports)]
+pub use harness::TestServer;
+pub use journal::Journal;
+#[allow(unused_imports)]
+pub use json_log::{JsonLog, LogFormat};
+#[allow(unused_imports)]
+pub use listing::{LISTING_FILENAME, Listing};
+#[allow(unused_imports)]
+pub use manifest::{MANIFEST_FILENAME, Manifest};
+#[allow(unused_imports)]
+pub use memory::MemoryFs;
+#[allow(unused_imports)]
+pub use overwrite::OverwritePolicy;
+#[allow(unused_imports)]
+pub use priority::{Priority, PriorityClass};
+#[allow(unused_imports)]
+pub use provider::{FileProvider, open_archive};
+#[allow(unused_imports)]
+pub use quota::{QuotaReservation, UploadQuota};
+#[allow(unused_imports)]
+pub use replay::replay;
+#[allow(unused_imports)]
+pub use rewrite::RewriteRule;
+#[allow(unused_imports)]
+pub use s3::{S3Config, open_bucket};
+#[cfg(feature = "s3")]
+#[allow(unused_imports)]
+pub use s3_fs::S3Fs;
 pub use server::Server;
+#[allow(unused_imports)]
+pub use server::{ReloadHandle, ShutdownHandle};
+#[allow(unused_imports)]
+pub use status::{SessionsHandle, TransferStatus};
+#[allow(unused_imports)]
+pub use symlinks::SymlinkPolicy;
+#[allow(unused_imports)]
+pub use transfer_log::{LogRotation, TransferLog};
+#[allow(unused_imports)]
+pub use webhook::UploadWebhook;
 pub use worker::Worker;
 
 /// Run the TFTP server with CLI arguments and optional configuration
@@ -26,32 +175,79 @@ pub fn run_with_config(
     single_port: bool,
     config: Option<Config>,
 ) -> Result<()> {
+    let mut server = start(ip, port, path, read_only, single_port, config)?;
+    log::info!("TFTP server listening, press Ctrl+C to stop");
+    server.listen();
+
+    Ok(())
+}
+
+/// Run the `--service` action of the TFTP server, see [`service`]
+pub fn run_service(
+    action: service::ServiceAction,
+    ip: String,
+    port: u16,
+    path: PathBuf,
+    read_only: bool,
+    single_port: bool,
+    config: Option<Config>,
+) -> Result<()> {
+    service::run(action, move || {
+        start(ip, port, path, read_only, single_port, config)
+    })
+}
+
+/// Creates the TFTP server with CLI arguments and optional configuration
+fn start(
+    ip: String,
+    port: u16,
+    path: PathBuf,
+    read_only: bool,
+    single_port: bool,
+    config: Option<Config>,
+) -> Result<Server> {
     let server_config = config.unwrap_or_default();
+    let cli = (ip.clone(), path.clone());
     let config = server_config.merge_cli(ip, port, path, read_only, single_port);
 
-    let ip = config.ip.as_deref().unwrap_or("0.0.0.0");
-    let port = config.port.unwrap_or(69);
+    let addrs: Vec<String> = bind::listen_addrs(&config)?
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect();
     let directory = config
         .directory
         .clone()
         .unwrap_or_else(|| PathBuf::from("."));
-    let read_only = config.read_only.unwrap_or(false);
+    // Archives are always served read-only
+    let read_only = config.read_only.unwrap_or(false) || directory.is_file();
     let single_port = config.single_port.unwrap_or(false);
 
-    log::info!("Starting TFTP server on {}:{}", ip, port);
+    log::info!("Starting TFTP server on {}", addrs.join(", "));
     log::info!("Read-only mode: {}", read_only);
     log::info!("Single port mode: {}", single_port);
 
-    // Ensure directory exists
+    // Ensure directory or archive exists
     if !directory.exists() {
         log::error!("Directory does not exist: {}", directory.display());
         return Err(anyhow::anyhow!("Directory does not exist"));
     }
+    for directory in [&config.receive_directory, &config.send_directory]
+        .into_iter()
+        .flatten()
+    {
+        if !directory.is_dir() {
+            log::error!("Directory does not exist: {}", directory.display());
+            return Err(anyhow::anyhow!("Directory does not exist"));
+        }
+    }
 
-    let mut server = Server::new(&config)?;
-
-    log::info!("TFTP server listening, press Ctrl+C to stop");
-    server.listen();
+    let server = Server::new(&config)?;
+    reload::reload_on_hangup(server.reload_handle(), move || {
+        let (ip, path) = cli.clone();
+        let loaded = crate::config::AppConfig::load_from_file(".xtool.toml")?;
+        let config = loaded.tftpd.unwrap_or_default();
+        Ok(config.merge_cli(ip, port, path, read_only, single_port))
+    })?;
 
-    Ok(())
+    Ok(server)
 }
@@ -0,0 +1,375 @@
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::tftp::core::{CustomOption, OptionType, Packet, Socket, TransferOption};
+
+use super::fs::{DiskFs, TftpFs};
+use super::handler::{ServerHandler, TransferInfo};
+use super::pool::{Task, ThreadPool};
+
+/// First port of the multicast groups, by default
+pub(super) const DEFAULT_MULTICAST_PORT: u16 = 1758;
+/// Time to live of the multicast packets by default, not leaving the local
+/// network
+pub(super) const DEFAULT_MULTICAST_TTL: u32 = 1;
+/// How often a group checks for clients joining it
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Multicast `struct` sends the files requested with the multicast option
+/// of RFC 2090 to a multicast group of their own, so that the clients
+/// downloading a file at once share a single stream of blocks.
+///
+/// The first client of a group, the master client, acknowledges the blocks
+/// sent to the group. Once it has the whole file, the next client is made
+/// master and acknowledges the blocks it missed, until every client of the
+/// group has the file. Groups use `address` and the first free port from
+/// `port`, one per file and block size being sent.
+pub(super) struct Multicast {
+    address: Ipv4Addr,
+    port: u16,
+    ttl: u32,
+    groups: Vec<Arc<Group>>,
+}
+
+/// File sent to a group, as negotiated by its first client
+pub(super) struct Stream {
+    pub file_path: PathBuf,
+    pub size: u64,
+    pub block_size: u16,
+    pub timeout: Duration,
+    pub max_retries: usize,
+    /// Read from the local filesystem if `None`
+    pub fs: Option<Arc<dyn TftpFs>>,
+}
+
+/// Client receiving the file of a group
+pub(super) struct Member {
+    pub info: TransferInfo,
+    /// Options acknowledged along with the multicast option
+    pub options: Vec<TransferOption>,
+}
+
+struct Group {
+    file_path: PathBuf,
+    block_size: u16,
+    addr: SocketAddrV4,
+    joining: Mutex<Joining>,
+}
+
+#[derive(Default)]
+struct Joining {
+    members: Vec<Member>,
+    /// Set once the group has no client left and stopped
+    closed: bool,
+}
+
+/// Packet the master client is expected to acknowledge
+#[derive(Clone, Copy)]
+enum Pending {
+    Oack,
+    Data(u16),
+}
+
+impl Multicast {
+    pub fn new(address: Ipv4Addr, port: u16, ttl: u32) -> anyhow::Result<Multicast> {
+        if !address.is_multicast() {
+            return Err(anyhow::anyhow!(
+                "Invalid multicast_address {address}, must be between 224.0.0.0 and 239.255.255.255"
+            ));
+        }
+        Ok(Multicast {
+            address,
+            port,
+            ttl,
+            groups: Vec::new(),
+        })
+    }
+
+    /// Returns whether `stream` can be multicast: only files of less than
+    /// 65535 blocks are, the block numbers acknowledged by a new master
+    /// client being ambiguous once rolled over.
+    pub fn supports(stream: &Stream) -> bool {
+        stream.size / (stream.block_size as u64) < u16::MAX as u64
+    }
+
+    /// Adds `member` to the group sending `stream`, starting the group on
+    /// `pool` if none is. Groups are sent from `local`, the address of the
+    /// server the request was received on. Returns the task of the group
+    /// started, if any.
+    pub fn join(
+        &mut self,
+        stream: Stream,
+        member: Member,
+        local: SocketAddr,
+        pool: &ThreadPool,
+        handler: Option<Arc<dyn ServerHandler>>,
+    ) -> anyhow::Result<Option<Task>> {
+        self.groups
+            .retain(|group| !group.joining.lock().unwrap().closed);
+        let mut member = Some(member);
+        for group in &self.groups {
+            if group.file_path != stream.file_path || group.block_size != stream.block_size {
+                continue;
+            }
+            let mut joining = group.joining.lock().unwrap();
+            if !joining.closed {
+                joining.members.extend(member.take());
+                return Ok(None);
+            }
+        }
+
+        let port = (self.port..=u16::MAX)
+            .find(|port| self.groups.iter().all(|group| group.addr.port() != *port))
+            .ok_or_else(|| anyhow::anyhow!("No multicast port left"))?;
+        let interface = match local.ip() {
+            std::net::IpAddr::V4(ip) => ip,
+            std::net::IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+        };
+        let socket = UdpSocket::bind((interface, 0))?;
+        if !interface.is_unspecified() {
+            socket2::SockRef::from(&socket).set_multicast_if_v4(&interface)?;
+        }
+        socket.set_multicast_ttl_v4(self.ttl)?;
+        socket.set_read_timeout(Some(JOIN_POLL_INTERVAL))?;
+
+        let group = Arc::new(Group {
+            file_path: stream.file_path.clone(),
+            block_size: stream.block_size,
+            addr: SocketAddrV4::new(self.address, port),
+            joining: Mutex::new(Joining {
+                members: member.into_iter().collect(),
+                closed: false,
+            }),
+        });
+        log::info!(
+            "  Sending {} to multicast group {}",
+            stream.file_path.display(),
+            group.addr
+        );
+        let task = pool.execute({
+            let group = group.clone();
+            move || group.run(socket, stream, handler)
+        })?;
+        self.groups.push(group);
+        Ok(Some(task))
+    }
+}
+
+impl Group {
+    /// Takes the clients that joined, or closes the group if there are none
+    /// and `idle`.
+    fn accept(&self, idle: bool) -> Option<Vec<Member>> {
+        let mut joining = self.joining.lock().unwrap();
+        if idle && joining.members.is_empty() {
+            joining.closed = true;
+            return None;
+        }
+        Some(std::mem::take(&mut joining.members))
+    }
+
+    fn run(&self, socket: UdpSocket, stream: Stream, handler: Option<Arc<dyn ServerHandler>>) {
+        let mut blocks = Blocks {
+            stream: &stream,
+            reader: None,
+            next: 0,
+        };
+        let last_block = (stream.size / stream.block_size as u64 + 1) as u16;
+        let mut members: Vec<Member> = Vec::new();
+        let mut pending = Pending::Oack;
+        let mut sent_at = Instant::now();
+        let mut retries = 0;
+
+        let send = |pending: Pending, members: &[Member], blocks: &mut Blocks| match pending {
+            Pending::Oack => self.send_oack(&socket, &members[0], true),
+            Pending::Data(block) => blocks.read(block).and_then(|data| {
+                let packet = Packet::Data {
+                    block_num: block,
+                    data,
+                };
+                Socket::send_to(&socket, &packet, &SocketAddr::V4(self.addr))
+            }),
+        };
+        // Makes the next client master, which acknowledges the blocks it missed
+        let promote = |members: &[Member]| {
+            if let Some(master) = members.first() {
+                log::info!(
+                    "  {} is now master of group {}",
+                    master.info.peer,
+                    self.addr
+                );
+                if self.send_oack(&socket, master, true).is_err() {
+                    log::error!("Could not send OACK to {}", master.info.peer);
+                }
+            }
+        };
+
+        while let Some(joined) = self.accept(members.is_empty()) {
+            for member in joined {
+                if let Some(index) = members.iter().position(|m| m.info.peer == member.info.peer) {
+                    // Retransmitted request
+                    let _ = self.send_oack(&socket, &members[index], index == 0);
+                    continue;
+                }
+                let master = members.is_empty();
+                log::info!(
+                    "  {} joined multicast group {}{}",
+                    member.info.peer,
+                    self.addr,
+                    if master { " as master" } else { "" }
+                );
+                if let Some(handler) = &handler {
+                    handler.on_transfer_start(&member.info);
+                }
+                if self.send_oack(&socket, &member, master).is_err() {
+                    log::error!("Could not send OACK to {}", member.info.peer);
+                }
+                if master {
+                    (pending, sent_at, retries) = (Pending::Oack, Instant::now(), 0);
+                }
+                members.push(member);
+            }
+            if members.is_empty() {
+                continue;
+            }
+
+            match Socket::recv_from(&socket) {
+                Ok((Packet::Ack(block), from)) if from == members[0].info.peer => {
+                    if block == last_block {
+                        let master = members.remove(0);
+                        log::info!(
+                            "  Sent {} to {} over multicast",
+                            stream.file_path.display(),
+                            master.info.peer
+                        );
+                        if let Some(handler) = &handler {
+                            handler.on_complete(&master.info, stream.size);
+                        }
+                        promote(&members);
+                        (pending, sent_at, retries) = (Pending::Oack, Instant::now(), 0);
+                        continue;
+                    }
+                    (pending, sent_at, retries) = (Pending::Data(block + 1), Instant::now(), 0);
+                    if let Err(err) = send(pending, &members, &mut blocks) {
+                        log::error!("Could not send block {} to {}: {err}", block + 1, self.addr);
+                    }
+                }
+                Ok((Packet::Error { code, msg }, from)) => {
+                    let Some(index) = members.iter().position(|m| m.info.peer == from) else {
+                        continue;
+                    };
+                    let member = members.remove(index);
+                    log::warn!("  {from} left multicast group {}: {code} {msg}", self.addr);
+                    if let Some(handler) = &handler {
+                        let err = anyhow::anyhow!("Client sent error: {code} {msg}");
+                        handler.on_error(&member.info, &err);
+                    }
+                    if index == 0 {
+                        promote(&members);
+                        (pending, sent_at, retries) = (Pending::Oack, Instant::now(), 0);
+                    }
+                }
+                // Only the master client acknowledges
+                Ok(_) => {}
+                Err(_) if sent_at.elapsed() < stream.timeout => {}
+                Err(_) if retries < stream.max_retries => {
+                    retries += 1;
+                    sent_at = Instant::now();
+                    if let Err(err) = send(pending, &members, &mut blocks) {
+                        log::error!("Could not resend to group {}: {err}", self.addr);
+                    }
+                }
+                Err(_) => {
+                    let master = members.remove(0);
+                    log::warn!(
+                        "  Master {} of multicast group {} stopped answering",
+                        master.info.peer,
+                        self.addr
+                    );
+                    if let Some(handler) = &handler {
+                        let err = anyhow::anyhow!("Transfer timed out");
+                        handler.on_error(&master.info, &err);
+                    }
+                    promote(&members);
+                    (pending, sent_at, retries) = (Pending::Oack, Instant::now(), 0);
+                }
+            }
+        }
+        log::info!("  Multicast group {} has no client left", self.addr);
+    }
+
+    /// Sends the options of `member` along with the multicast option, whose
+    /// value is the address of the group and whether `member` is master.
+    fn send_oack(&self, socket: &UdpSocket, member: &Member, master: bool) -> anyhow::Result<()> {
+        let multicast = CustomOption {
+            name: OptionType::Multicast.as_str().to_string(),
+            value: format!("{},{},{}", self.addr.ip(), self.addr.port(), master as u8),
+        };
+        let oack = Packet::Oack {
+            options: member.options.clone(),
+            custom: vec![multicast],
+        };
+        let buf = oack.serialize()?;
+        socket.send_to(&buf, member.info.peer)?;
+        Ok(())
+    }
+}
+
+/// Blocks of the file of a group, read sequentially unless a new master
+/// client starts over
+struct Blocks<'a> {
+    stream: &'a Stream,
+    reader: Option<Box<dyn Read + Send>>,
+    /// Block the reader is at
+    next: u16,
+}
+
+impl Blocks<'_> {
+    fn read(&mut self, block: u16) -> anyhow::Result<Vec<u8>> {
+        let reader = match &mut self.reader {
+            Some(reader) if self.next == block => reader,
+            _ => {
+                let offset = (block as u64 - 1) * self.stream.block_size as u64;
+                let path = &self.stream.file_path;
+                let reader: io::Result<_> = match &self.stream.fs {
+                    Some(fs) => fs.open_read(path, offset),
+                    None => DiskFs.open_read(path, offset),
+                };
+                self.reader.insert(reader?)
+            }
+        };
+        let mut data = Vec::with_capacity(self.stream.block_size as usize);
+        reader
+            .take(self.stream.block_size as u64)
+            .read_to_end(&mut data)?;
+        self.next = block + 1;
+        Ok(data)
+    }
+}
+
+/// Removes the multicast option from `options`, returning whether the
+/// client asked to join a multicast group.
+pub(super) fn take_multicast(options: &mut Vec<TransferOption>) -> bool {
+    let len = options.len();
+    options.retain(|option| option.option != OptionType::Multicast);
+    options.len() != len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_multicast_option() {
+        let option = |option, value| TransferOption { option, value };
+        let mut options = vec![
+            option(OptionType::BlockSize, 1432),
+            option(OptionType::Multicast, 0),
+        ];
+        assert!(take_multicast(&mut options));
+        assert_eq!(options, [option(OptionType::BlockSize, 1432)]);
+        assert!(!take_multicast(&mut options));
+    }
+}
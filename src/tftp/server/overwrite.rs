@@ -0,0 +1,139 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::fs::TftpFs;
+
+/// OverwritePolicy `enum` tells what becomes of an existing file when a
+/// client uploads a file of the same name.
+///
+/// In the configuration file, `overwrite = true` and `overwrite = false`
+/// still stand for [`OverwritePolicy::Overwrite`] and
+/// [`OverwritePolicy::Reject`].
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd]
+/// overwrite = "keep_versioned"
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverwritePolicy {
+    /// Refuse the upload with a file exists error
+    Reject,
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Keep the existing file, writing the upload to the first free
+    /// `<name>.1`, `<name>.2`…
+    RenameWithSuffix,
+    /// Write the upload to `<name>` once complete, moving the existing file
+    /// to the first free `<name>.1`, `<name>.2`…
+    KeepVersioned,
+}
+
+/// Deserializes an [`OverwritePolicy`], or the boolean `overwrite` of
+/// earlier configuration files.
+pub(super) fn deserialize_policy<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<OverwritePolicy>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Bool(bool),
+        Policy(OverwritePolicy),
+    }
+
+    Ok(
+        Option::<Setting>::deserialize(deserializer)?.map(|setting| match setting {
+            Setting::Bool(true) => OverwritePolicy::Overwrite,
+            Setting::Bool(false) => OverwritePolicy::Reject,
+            Setting::Policy(policy) => policy,
+        }),
+    )
+}
+
+/// Creates the first free `<name>.1`, `<name>.2`… next to `file_path` in
+/// `fs`, so that concurrent uploads are never given the same version.
+pub(super) fn reserve_version(fs: &dyn TftpFs, file_path: &Path) -> io::Result<PathBuf> {
+    let name = file_path.file_name().unwrap_or_default();
+    for version in 1u64.. {
+        let mut versioned = name.to_os_string();
+        versioned.push(format!(".{version}"));
+        let path = file_path.with_file_name(versioned);
+        match fs.create_new(&path) {
+            Ok(()) => return Ok(path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+/// Moves the existing file at `file_path`, if any, to its next version.
+pub(super) fn keep_version(fs: &dyn TftpFs, file_path: &Path) -> io::Result<()> {
+    if fs.metadata(file_path).is_err() {
+        return Ok(());
+    }
+    let version = reserve_version(fs, file_path)?;
+    log::info!(
+        "  Keeping previous {} as {}",
+        file_path.display(),
+        version.display()
+    );
+    fs.rename(file_path, &version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::server::MemoryFs;
+
+    #[test]
+    fn finds_free_versions() {
+        let fs = MemoryFs::new();
+        let write = |name: &str, content: &[u8]| {
+            let mut writer = fs.open_write(Path::new(name)).unwrap();
+            writer.write_all(content).unwrap();
+            writer.finish(content.len() as u64).unwrap();
+        };
+        write("router.cfg", b"v1");
+        write("router.cfg.1", b"v0");
+
+        let path = Path::new("router.cfg");
+        assert_eq!(
+            reserve_version(&fs, path).unwrap(),
+            Path::new("router.cfg.2")
+        );
+        // Reserved versions are not given twice
+        assert_eq!(
+            reserve_version(&fs, path).unwrap(),
+            Path::new("router.cfg.3")
+        );
+        keep_version(&fs, path).unwrap();
+        assert!(fs.metadata(path).is_err());
+        assert_eq!(fs.metadata(Path::new("router.cfg.4")).unwrap().len, 2);
+        // Nothing to keep
+        keep_version(&fs, path).unwrap();
+    }
+
+    #[test]
+    fn reads_earlier_booleans() {
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(default, deserialize_with = "deserialize_policy")]
+            overwrite: Option<OverwritePolicy>,
+        }
+        let policy = |toml: &str| toml::from_str::<Config>(toml).unwrap().overwrite;
+
+        assert_eq!(policy("overwrite = false"), Some(OverwritePolicy::Reject));
+        assert_eq!(policy("overwrite = true"), Some(OverwritePolicy::Overwrite));
+        assert_eq!(
+            policy("overwrite = \"rename_with_suffix\""),
+            Some(OverwritePolicy::RenameWithSuffix)
+        );
+        assert_eq!(policy(""), None);
+    }
+}
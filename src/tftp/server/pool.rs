@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Threads kept alive while idle, by default
+pub const DEFAULT_CORE_THREADS: usize = 4;
+/// Threads running transfers at once, by default
+pub const DEFAULT_MAX_THREADS: usize = 256;
+/// How long threads beyond the core ones are kept while idle, by default
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// ThreadPool `struct` runs the transfers of a [`Server`](super::Server) on
+/// a bounded set of reused threads, instead of one new thread per transfer.
+///
+/// Up to `core_threads` threads are kept once started. When they are all
+/// busy, jobs wait in a queue of up to `queue` jobs, and once it is full
+/// further threads are started, up to `max_threads`. These extra threads
+/// exit after being idle for `idle_timeout`. Jobs beyond all of this are
+/// rejected.
+pub(super) struct ThreadPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    core_threads: usize,
+    max_threads: usize,
+    queue: usize,
+    idle_timeout: Duration,
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    closed: bool,
+}
+
+/// Task `struct` tracks a job run by a [`ThreadPool`].
+#[derive(Clone)]
+pub(super) struct Task {
+    finished: Arc<AtomicBool>,
+}
+
+impl Task {
+    /// Returns `true` once the job has returned or panicked.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
+
+impl ThreadPool {
+    pub fn new(
+        core_threads: usize,
+        max_threads: usize,
+        queue: usize,
+        idle_timeout: Duration,
+    ) -> ThreadPool {
+        let max_threads = max_threads.max(1);
+        ThreadPool {
+            shared: Arc::new(Shared {
+                core_threads: core_threads.min(max_threads),
+                max_threads,
+                queue,
+                idle_timeout,
+                state: Mutex::new(State::default()),
+                available: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Returns `true` if a job submitted now would be rejected.
+    pub fn is_full(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        state.idle <= state.jobs.len()
+            && state.threads >= self.shared.max_threads
+            && state.jobs.len() >= self.shared.queue
+    }
+
+    /// Runs `job` on an idle or new thread, or queues it, failing if the
+    /// pool is full.
+    pub fn execute<T>(&self, job: impl FnOnce() -> T + Send + 'static) -> anyhow::Result<Task> {
+        let finished = Arc::new(AtomicBool::new(false));
+        let done = finished.clone();
+        let job: Job = Box::new(move || {
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                log::error!("Transfer thread panicked");
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+
+        let mut state = self.shared.state.lock().unwrap();
+        let shared = &self.shared;
+        if state.idle > state.jobs.len()
+            || (state.threads >= shared.core_threads && state.jobs.len() < shared.queue)
+        {
+            state.jobs.push_back(job);
+            shared.available.notify_one();
+        } else if state.threads < shared.max_threads {
+            state.threads += 1;
+            let shared = shared.clone();
+            if let Err(err) = thread::Builder::new()
+                .name("tftp-transfer".to_string())
+                .spawn(move || shared.run(job))
+            {
+                state.threads -= 1;
+                return Err(err.into());
+            }
+        } else {
+            return Err(anyhow::anyhow!(
+                "all {} transfer threads are busy",
+                shared.max_threads
+            ));
+        }
+
+        Ok(Task { finished })
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Idle threads exit, busy ones once their queued jobs are done
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.available.notify_all();
+    }
+}
+
+impl Shared {
+    fn run(&self, first: Job) {
+        first();
+        while let Some(job) = self.next_job() {
+            job();
+        }
+    }
+
+    /// Waits for the next queued job, returning `None` once the thread
+    /// should exit.
+    fn next_job(&self) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                return Some(job);
+            }
+            if state.closed {
+                state.threads -= 1;
+                return None;
+            }
+
+            state.idle += 1;
+            let timed_out = if state.threads > self.core_threads {
+                let (guard, result) = self
+                    .available
+                    .wait_timeout(state, self.idle_timeout)
+                    .unwrap();
+                state = guard;
+                result.timed_out()
+            } else {
+                state = self.available.wait(state).unwrap();
+                false
+            };
+            state.idle -= 1;
+
+            if timed_out && state.jobs.is_empty() && state.threads > self.core_threads {
+                state.threads -= 1;
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    fn wait_for(task: &Task) {
+        let start = Instant::now();
+        while !task.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn bounds_and_reuses_threads() {
+        let pool = ThreadPool::new(1, 2, 1, Duration::from_millis(100));
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        let blocked = || {
+            let release_rx = release_rx.clone();
+            move || {
+                let _ = release_rx.lock().unwrap().recv();
+            }
+        };
+
+        // A core thread, a queued job, then an extra thread
+        let tasks: Vec<Task> = (0..3).map(|_| pool.execute(blocked()).unwrap()).collect();
+        assert!(pool.is_full());
+        assert!(pool.execute(|| {}).is_err());
+
+        for _ in 0..3 {
+            release_tx.send(()).unwrap();
+        }
+        tasks.iter().for_each(wait_for);
+        assert!(!pool.is_full());
+
+        // The extra thread is reaped once idle, the core one kept
+        thread::sleep(Duration::from_millis(300));
+        let state = pool.shared.state.lock().unwrap();
+        assert_eq!(state.threads, 1);
+        assert_eq!(state.idle, 1);
+    }
+
+    #[test]
+    fn survives_panics() {
+        let pool = ThreadPool::new(1, 1, 0, DEFAULT_IDLE_TIMEOUT);
+        wait_for(&pool.execute(|| panic!("transfer failed")).unwrap());
+        let (tx, rx) = mpsc::channel();
+        wait_for(&pool.execute(move || tx.send(()).unwrap()).unwrap());
+        assert!(rx.recv().is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::tftp::core::Packet;
+
+use super::dynamic::glob_matches;
+use super::provider::normalize_name;
+
+/// Queued requests are dropped once their client has been silent this long
+const QUEUE_EXPIRY: Duration = Duration::from_secs(10);
+
+/// Priority `enum` orders the requests waiting for a transfer slot of a
+/// server whose `max_transfers` are all in progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Bulk files such as root filesystem images
+    Low,
+    #[default]
+    Normal,
+    /// Boot-critical files such as network bootstrap programs and kernels
+    High,
+}
+
+/// PriorityClass `struct` gives a [`Priority`] to the requests for files
+/// matching `pattern` from clients in `networks`. A class without a pattern
+/// matches any file, one without networks any client.
+///
+/// Patterns are matched like those of [`DynamicContent`](super::DynamicContent).
+///
+/// # Example
+///
+/// ```toml
+/// [[tftpd.priorities]]
+/// pattern = "*.efi"
+/// priority = "high"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriorityClass {
+    pub priority: Priority,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub networks: Option<Vec<IpNet>>,
+}
+
+impl PriorityClass {
+    /// Returns `true` if the request for `filename` from `peer` is in the class.
+    pub fn matches(&self, filename: &str, peer: &SocketAddr) -> bool {
+        let pattern = self.pattern.as_ref().is_none_or(|pattern| {
+            glob_matches(&normalize_name(pattern), &normalize_name(filename))
+        });
+        let ip = peer.ip().to_canonical();
+        let network = self
+            .networks
+            .as_ref()
+            .is_none_or(|networks| networks.iter().any(|net| net.contains(&ip)));
+        pattern && network
+    }
+}
+
+/// Returns the priority of the first class matching the request, or
+/// [`Priority::Normal`].
+pub fn classify(classes: &[PriorityClass], filename: &str, peer: &SocketAddr) -> Priority {
+    classes
+        .iter()
+        .find(|class| class.matches(filename, peer))
+        .map(|class| class.priority)
+        .unwrap_or_default()
+}
+
+struct Queued {
+    priority: Priority,
+    packet: Packet,
+    from: SocketAddr,
+    /// Socket of the server the request was received on
+    listener: usize,
+    last_seen: Instant,
+}
+
+/// AdmissionQueue `struct` holds the requests a saturated server has not
+/// started yet, admitting the highest priority first and requests of equal
+/// priority in arrival order.
+#[derive(Default)]
+pub(super) struct AdmissionQueue {
+    queued: Vec<Queued>,
+}
+
+impl AdmissionQueue {
+    /// Queues the request `packet` from `from`, received on the socket
+    /// `listener`, unless already queued.
+    pub fn push(&mut self, priority: Priority, packet: Packet, from: SocketAddr, listener: usize) {
+        if !self.touch(&from) {
+            self.queued.push(Queued {
+                priority,
+                packet,
+                from,
+                listener,
+                last_seen: Instant::now(),
+            });
+        }
+    }
+
+    /// Records that `from` is still waiting, returning `true` if it has a
+    /// queued request.
+    pub fn touch(&mut self, from: &SocketAddr) -> bool {
+        match self.queued.iter_mut().find(|queued| queued.from == *from) {
+            Some(queued) => {
+                queued.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the next request to start.
+    pub fn pop(&mut self) -> Option<(Packet, SocketAddr, usize)> {
+        self.queued
+            .retain(|queued| queued.last_seen.elapsed() < QUEUE_EXPIRY);
+        // max_by_key returns the last maximum, so search from the back
+        let index = self
+            .queued
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, queued)| queued.priority)
+            .map(|(index, _)| index)?;
+        let queued = self.queued.remove(index);
+        Some((queued.packet, queued.from, queued.listener))
+    }
+
+    /// Removes and returns every queued request.
+    pub fn drain(&mut self) -> Vec<(Packet, SocketAddr, usize)> {
+        self.queued
+            .drain(..)
+            .map(|queued| (queued.packet, queued.from, queued.listener))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rrq(filename: &str) -> Packet {
+        Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: Vec::new(),
+            custom: vec![],
+        }
+    }
+
+    #[test]
+    fn classifies_requests() {
+        let classes = vec![
+            PriorityClass {
+                priority: Priority::High,
+                pattern: Some("*.efi".to_string()),
+                networks: None,
+            },
+            PriorityClass {
+                priority: Priority::Low,
+                pattern: Some("images/*".to_string()),
+                networks: Some(vec!["10.0.0.0/8".parse().unwrap()]),
+            },
+        ];
+        let lab = "10.1.2.3:1234".parse().unwrap();
+        let office = "192.168.1.2:1234".parse().unwrap();
+
+        assert_eq!(classify(&classes, "/grubx64.efi", &office), Priority::High);
+        assert_eq!(
+            classify(&classes, "images/rootfs.ext4", &lab),
+            Priority::Low
+        );
+        assert_eq!(
+            classify(&classes, "images/rootfs.ext4", &office),
+            Priority::Normal
+        );
+    }
+
+    #[test]
+    fn admits_by_priority() {
+        let client = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let mut queue = AdmissionQueue::default();
+        queue.push(Priority::Low, rrq("rootfs.img"), client(1), 0);
+        queue.push(Priority::Normal, rrq("initrd"), client(2), 0);
+        queue.push(Priority::High, rrq("vmlinuz"), client(3), 0);
+        queue.push(Priority::Normal, rrq("dtb"), client(4), 0);
+        queue.push(Priority::High, rrq("vmlinuz"), client(3), 0);
+        assert_eq!(queue.len(), 4);
+
+        let order: Vec<u16> = std::iter::from_fn(|| queue.pop())
+            .map(|(_, from, _)| from.port())
+            .collect();
+        assert_eq!(order, [3, 2, 4, 1]);
+    }
+}
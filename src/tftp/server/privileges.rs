@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+
+use super::Config;
+
+/// Drops the privileges of the process once the server port is bound, to
+/// the configured user and group, after a chroot into the root `directory`
+/// if configured.
+///
+/// Returns `config` and `directory` as seen by the process afterwards, the
+/// paths of a chrooted process being remapped below `/`. Every path of the
+/// configuration must then be inside the root directory.
+pub(super) fn drop_privileges(
+    config: &Config,
+    directory: &Path,
+) -> anyhow::Result<(Config, PathBuf)> {
+    let chroot = config.chroot.unwrap_or(false);
+    if config.user.is_none() && config.group.is_none() && !chroot {
+        return Ok((config.clone(), directory.to_path_buf()));
+    }
+
+    let mut config = config.clone();
+    let mut directory = directory.to_path_buf();
+    if chroot {
+        if !directory.is_dir() {
+            return Err(anyhow::anyhow!(
+                "Cannot chroot into {}, not a directory",
+                directory.display()
+            ));
+        }
+        chroot_config(&mut config, &directory)?;
+        log::info!("Changing root to {}", directory.display());
+    }
+
+    switch_user(
+        config.user.as_deref(),
+        config.group.as_deref(),
+        chroot.then_some(directory.as_path()),
+    )?;
+    if chroot {
+        directory = PathBuf::from("/");
+    }
+    Ok((config, directory))
+}
+
+/// Remaps the paths of `config` to those seen after a chroot into `root`.
+fn chroot_config(config: &mut Config, root: &Path) -> anyhow::Result<()> {
+    config.directory = Some(PathBuf::from("/"));
+    for path in [
+        &mut config.receive_directory,
+        &mut config.send_directory,
+        &mut config.journal,
+        &mut config.session_dir,
+        &mut config.pcap,
+        &mut config.transfer_log,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *path = chroot_path(path, root)?;
+    }
+    for path in config.roots.iter_mut().flat_map(|roots| roots.values_mut()) {
+        *path = chroot_path(path, root)?;
+    }
+    Ok(())
+}
+
+/// Returns `path` as seen after a chroot into `root`.
+pub(super) fn chroot_path(path: &Path, root: &Path) -> anyhow::Result<PathBuf> {
+    let absolute = std::path::absolute(path)?;
+    let absolute = std::fs::canonicalize(&absolute).unwrap_or(absolute);
+    absolute
+        .strip_prefix(root)
+        .map(|relative| Path::new("/").join(relative))
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Cannot chroot into {}, {} is outside of it",
+                root.display(),
+                path.display()
+            )
+        })
+}
+
+/// Changes root to `chroot` if given, then switches to `group`, by default
+/// the group of `user`, and to `user`. Accounts are looked up by name or
+/// numeric id.
+#[cfg(unix)]
+fn switch_user(
+    user: Option<&str>,
+    group: Option<&str>,
+    chroot: Option<&Path>,
+) -> anyhow::Result<()> {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+
+    let check = |result: libc::c_int, action: &str| {
+        if result != 0 {
+            let err = io::Error::last_os_error();
+            return Err(anyhow::anyhow!("Cannot {action}: {err}"));
+        }
+        Ok(())
+    };
+
+    // Accounts are looked up before the chroot hides /etc
+    let account = user.map(lookup_user).transpose()?;
+    let gid = match group {
+        Some(group) => Some(lookup_group(group)?),
+        None => account.map(|(_, gid)| gid),
+    };
+
+    if let Some(root) = chroot {
+        let root = CString::new(root.as_os_str().as_bytes())?;
+        check(unsafe { libc::chroot(root.as_ptr()) }, "chroot")?;
+        std::env::set_current_dir("/")?;
+    }
+    if let Some(gid) = gid {
+        check(
+            unsafe { libc::setgroups(1, &gid) },
+            "set supplementary groups",
+        )?;
+        check(unsafe { libc::setgid(gid) }, "set group")?;
+    }
+    if let Some((uid, _)) = account {
+        check(unsafe { libc::setuid(uid) }, "set user")?;
+    }
+    log::info!(
+        "Running as uid {} and gid {}",
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() }
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn switch_user(
+    _user: Option<&str>,
+    _group: Option<&str>,
+    _chroot: Option<&Path>,
+) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "Dropping privileges is only supported on Unix"
+    ))
+}
+
+/// Returns the user and primary group ids of `user`.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> anyhow::Result<(libc::uid_t, libc::gid_t)> {
+    let name = std::ffi::CString::new(user)?;
+    // Only called while the server starts, before any other thread reads the database
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !passwd.is_null() {
+        return Ok(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) });
+    }
+    match user.parse() {
+        Ok(uid) => {
+            let passwd = unsafe { libc::getpwuid(uid) };
+            let gid = if passwd.is_null() {
+                uid
+            } else {
+                unsafe { (*passwd).pw_gid }
+            };
+            Ok((uid, gid))
+        }
+        Err(_) => Err(anyhow::anyhow!("Unknown user {user}")),
+    }
+}
+
+/// Returns the id of `group`.
+#[cfg(unix)]
+fn lookup_group(group: &str) -> anyhow::Result<libc::gid_t> {
+    let name = std::ffi::CString::new(group)?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if !entry.is_null() {
+        return Ok(unsafe { (*entry).gr_gid });
+    }
+    group
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Unknown group {group}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaps_paths_into_chroot() {
+        let root = Path::new("/srv/tftp");
+        let config = Config::with_defaults()
+            .with_receive_directory(PathBuf::from("/srv/tftp/incoming"))
+            .with_root("uefi/", PathBuf::from("/srv/tftp/uefi"))
+            .with_journal(PathBuf::from("/srv/tftp/uploads.journal"));
+
+        let mut chrooted = config.clone();
+        chroot_config(&mut chrooted, root).unwrap();
+        assert_eq!(chrooted.directory, Some(PathBuf::from("/")));
+        assert_eq!(chrooted.receive_directory, Some(PathBuf::from("/incoming")));
+        assert_eq!(chrooted.journal, Some(PathBuf::from("/uploads.journal")));
+        assert_eq!(chrooted.roots.unwrap()["uefi/"], PathBuf::from("/uefi"));
+
+        // Paths outside of the root are refused
+        let mut outside = config.with_session_dir(PathBuf::from("/var/log/xtool"));
+        assert!(chroot_config(&mut outside, root).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn looks_up_accounts() {
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        assert_eq!(lookup_user("0").unwrap(), (0, 0));
+        assert_eq!(lookup_group("0").unwrap(), 0);
+        assert!(lookup_user("no-such-user-xtool").is_err());
+    }
+}
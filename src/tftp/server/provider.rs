@@ -0,0 +1,84 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::fs::TftpFs;
+use super::iso::IsoImage;
+use super::tar::TarArchive;
+use super::zip::ZipArchive;
+
+/// FileProvider `trait` is implemented by the virtual read-only roots the
+/// server can serve files from instead of a directory, such as archives.
+///
+/// Names are the file names of read requests, with `/` separators and no
+/// leading slash, see [`normalize_name()`].
+pub trait FileProvider: Send + Sync {
+    /// Returns the size of the file `name`, or a [`io::ErrorKind::NotFound`] error.
+    fn size(&self, name: &str) -> io::Result<u64>;
+
+    /// Opens the file `name` for reading, skipping its first `offset` bytes.
+    fn open(&self, name: &str, offset: u64) -> io::Result<Box<dyn Read + Send>>;
+}
+
+/// Opens the archive at `path` as a read-only [`TftpFs`], based on its
+/// extension.
+///
+/// Supports `.zip` and `.tar` archives, the latter possibly compressed
+/// (`.tar.gz`, `.tgz`), and `.iso` (ISO 9660) images.
+pub fn open_archive(path: &Path) -> anyhow::Result<Arc<dyn TftpFs>> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_ascii_lowercase())
+        .unwrap_or_default();
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension);
+
+    match extension {
+        Some("zip") => Ok(Arc::new(ZipArchive::open(path)?)),
+        Some("iso") => Ok(Arc::new(IsoImage::open(path)?)),
+        Some("tar" | "tgz") => Ok(Arc::new(TarArchive::open(path)?)),
+        Some("gz") if name.ends_with(".tar.gz") => Ok(Arc::new(TarArchive::open(path)?)),
+        _ => Err(anyhow::anyhow!(
+            "Cannot serve {}: only directories, .zip, .tar, .tar.gz and .iso files are supported",
+            path.display()
+        )),
+    }
+}
+
+/// Converts a requested file name to the form used by providers, accepting
+/// both separators and ignoring leading ones.
+pub fn normalize_name(name: &str) -> String {
+    name.replace('\\', "/").trim_start_matches('/').to_string()
+}
+
+/// Skips `offset` bytes of a reader that cannot seek.
+pub(super) fn skip(reader: &mut impl Read, offset: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(offset), &mut io::sink())?;
+    if skipped < offset {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "offset past end of file",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(normalize_name("/boot/vmlinuz"), "boot/vmlinuz");
+        assert_eq!(
+            normalize_name("pxelinux.cfg\\default"),
+            "pxelinux.cfg/default"
+        );
+        assert_eq!(normalize_name("initrd.img"), "initrd.img");
+    }
+
+    #[test]
+    fn rejects_unknown_archives() {
+        assert!(open_archive(Path::new("image.tar")).is_err());
+    }
+}
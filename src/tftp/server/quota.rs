@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// UploadQuota `struct` counts the bytes written by the uploads of a server,
+/// refusing further uploads once `limit` bytes were written so that a rogue
+/// client cannot fill the storage of the device.
+///
+/// Every byte received counts, including those of files overwritten or
+/// removed since, until the server restarts. The size announced by an upload
+/// is reserved when it is accepted, so that concurrent uploads cannot exceed
+/// the quota together.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use xtool::tftp::server::UploadQuota;
+///
+/// let quota = Arc::new(UploadQuota::new(1000));
+/// assert!(quota.consume(600));
+/// assert_eq!(quota.remaining(), 400);
+///
+/// let reservation = quota.reserve(300).unwrap();
+/// assert!(quota.reserve(300).is_none());
+/// drop(reservation);
+/// assert_eq!(quota.remaining(), 400);
+///
+/// assert!(!quota.consume(600));
+/// assert_eq!(quota.remaining(), 0);
+/// ```
+#[derive(Debug)]
+pub struct UploadQuota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl UploadQuota {
+    /// Creates a quota allowing uploads to write `limit` bytes.
+    pub fn new(limit: u64) -> UploadQuota {
+        UploadQuota {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of bytes uploads may still write.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used.load(Ordering::SeqCst))
+    }
+
+    /// Counts `bytes` written, returning `false` if they exceed the quota.
+    pub fn consume(&self, bytes: u64) -> bool {
+        self.used.fetch_add(bytes, Ordering::SeqCst) + bytes <= self.limit
+    }
+
+    /// Reserves `bytes` for an upload about to start, or returns `None` if
+    /// the quota has less left, or nothing left at all.
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Option<QuotaReservation> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used < self.limit && used + bytes <= self.limit).then_some(used + bytes)
+            })
+            .ok()?;
+        Some(QuotaReservation {
+            quota: self.clone(),
+            reserved: bytes,
+        })
+    }
+}
+
+/// QuotaReservation `struct` holds the bytes reserved by an upload in an
+/// [`UploadQuota`]. The bytes not received are given back when it is dropped,
+/// so that a failed upload does not keep them.
+#[derive(Debug)]
+pub struct QuotaReservation {
+    quota: Arc<UploadQuota>,
+    reserved: u64,
+}
+
+impl QuotaReservation {
+    /// Counts `bytes` received, from the reservation first, returning `false`
+    /// if they exceed the quota.
+    pub fn consume(&mut self, bytes: u64) -> bool {
+        let reserved = bytes.min(self.reserved);
+        self.reserved -= reserved;
+        bytes == reserved || self.quota.consume(bytes - reserved)
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        self.quota.used.fetch_sub(self.reserved, Ordering::SeqCst);
+    }
+}
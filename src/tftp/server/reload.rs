@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Config, ReloadHandle};
+
+/// Set when a reload is requested, by SIGHUP or [`request_reload()`]
+static HANGUP: AtomicBool = AtomicBool::new(false);
+/// Set once [`reload_on_hangup()`] watches for reload requests
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Requests a reload of the configuration as SIGHUP does. Returns `false` if
+/// nothing reloads it, e.g. when the server is embedded in another program.
+pub(super) fn request_reload() -> bool {
+    if !WATCHING.load(Ordering::SeqCst) {
+        return false;
+    }
+    HANGUP.store(true, Ordering::SeqCst);
+    true
+}
+
+/// Reloads the configuration returned by `load` into the server of `handle`
+/// whenever the process receives SIGHUP, or a reload is requested with
+/// [`request_reload()`]. Errors of `load` are logged, the server keeping its
+/// previous configuration.
+#[cfg(unix)]
+pub(super) fn reload_on_hangup<F>(handle: ReloadHandle, load: F) -> anyhow::Result<()>
+where
+    F: Fn() -> anyhow::Result<Config> + Send + 'static,
+{
+    use std::time::Duration;
+
+    extern "C" fn on_hangup(_: libc::c_int) {
+        HANGUP.store(true, Ordering::SeqCst);
+    }
+
+    let handler = on_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGHUP, handler) } == libc::SIG_ERR {
+        let err = std::io::Error::last_os_error();
+        return Err(anyhow::anyhow!("Cannot handle SIGHUP: {err}"));
+    }
+
+    // The handler only sets a flag, the configuration is loaded on this thread
+    WATCHING.store(true, Ordering::SeqCst);
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_millis(200));
+            if !HANGUP.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            log::info!("Reload requested, reloading the configuration");
+            match load() {
+                Ok(config) => handle.reload(config),
+                Err(err) => log::error!("Cannot reload the configuration: {err:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Signals are Unix only, the configuration is reloaded through
+/// [`ReloadHandle`] elsewhere.
+#[cfg(not(unix))]
+pub(super) fn reload_on_hangup<F>(_handle: ReloadHandle, _load: F) -> anyhow::Result<()>
+where
+    F: Fn() -> anyhow::Result<Config> + Send + 'static,
+{
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::tftp::core::options::{OptionsProtocol, RequestType};
+use crate::tftp::core::{Flow, Packet, ReplaySocket, Session};
+
+use super::fs::TftpFs;
+use super::provider::normalize_name;
+use super::server::{accept_request, clamp_block_size, clamp_to_limits};
+use super::{Config, Worker};
+
+/// Time left after a transfer for its last packets to be replayed, such as
+/// the retransmissions acknowledged while dallying
+const REPLAY_GRACE: Duration = Duration::from_millis(500);
+
+/// Replays a `session` recorded by a [`Server`](super::Server) with
+/// [`Config::with_session_dir()`] against the transfer logic of the server,
+/// configured by `config` and serving the files of `fs`.
+///
+/// The session must start with the request of the transfer. Returns whether
+/// the replayed transfer succeeded, and fails if the packets it sent
+/// diverged from the recording. A session recorded by a client can be
+/// replayed once turned into the server side with [`Session::mirror()`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use xtool::tftp::core::Session;
+/// use xtool::tftp::server::{Config, MemoryFs, replay};
+///
+/// // A read request for hello.txt without options, the data then its ACK
+/// let session: Session = "\
+///     0 recv 10.0.0.2:1069 000168656c6c6f2e747874006f6374657400\n\
+///     1 send 10.0.0.2:1069 0003000168656c6c6f\n\
+///     2 recv 10.0.0.2:1069 00040001\n"
+///     .parse()
+///     .unwrap();
+/// let fs = MemoryFs::new();
+/// fs.insert("hello.txt", b"hello".to_vec());
+///
+/// assert!(replay(&session, &Config::with_defaults(), Arc::new(fs)).unwrap());
+/// ```
+pub fn replay(session: &Session, config: &Config, fs: Arc<dyn TftpFs>) -> anyhow::Result<bool> {
+    let request = session
+        .events
+        .first()
+        .filter(|event| event.flow == Flow::Received)
+        .map(|event| event.packet())
+        .transpose()?;
+    let transfer = Session {
+        events: session.events[1.min(session.events.len())..].to_vec(),
+    };
+    let socket = ReplaySocket::new(&transfer);
+
+    let (succeeded, timeout) = match request {
+        Some(Packet::Rrq {
+            filename,
+            mut options,
+            ..
+        }) => {
+            let file_path = PathBuf::from(normalize_name(&filename));
+            let size = fs.metadata(&file_path)?.len;
+            let mut worker_options = OptionsProtocol::parse(&mut options, RequestType::Read(size))?;
+            clamp_to_limits(&mut options, &mut worker_options, &config.get_limits()?);
+            clamp_block_size(&mut options, &mut worker_options);
+            accept_request(&socket, &options, RequestType::Read(size))?;
+
+            let timeout = worker_options.timeout;
+            let worker = Worker::new(
+                Box::new(socket.clone()),
+                file_path,
+                config.get_options(),
+                worker_options,
+            )
+            .with_fs(fs);
+            (worker.send_job(!options.is_empty())(), timeout)
+        }
+        Some(Packet::Wrq {
+            filename,
+            mut options,
+            ..
+        }) => {
+            let file_path = PathBuf::from(normalize_name(&filename));
+            let mut worker_options = OptionsProtocol::parse(&mut options, RequestType::Write)?;
+            clamp_to_limits(&mut options, &mut worker_options, &config.get_limits()?);
+            accept_request(&socket, &options, RequestType::Write)?;
+
+            let timeout = worker_options.timeout;
+            let worker = Worker::new(
+                Box::new(socket.clone()),
+                file_path,
+                config.get_options(),
+                worker_options,
+            )
+            .with_fs(fs);
+            (worker.receive_job()(), timeout)
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Session does not start with a received request"
+            ));
+        }
+    };
+
+    socket.finish(timeout + REPLAY_GRACE)?;
+    Ok(succeeded)
+}
@@ -0,0 +1,144 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::tftp::core::Packet;
+
+/// RewriteRule `struct` rewrites the file names requested by clients before
+/// they are resolved, for boot firmware requesting absolute, backslashed or
+/// oddly cased paths.
+///
+/// Matches of the regular expression `pattern` are replaced with `replace`,
+/// which may refer to capture groups as `$1` or `$name`, and the whole name
+/// is lowercased if `lowercase` is set. Names not matching `pattern` are left
+/// unchanged. Rules are applied in order, each to the name rewritten by the
+/// previous ones.
+///
+/// # Example
+///
+/// ```toml
+/// [[tftpd.rewrites]]
+/// pattern = '\\'
+/// replace = "/"
+///
+/// [[tftpd.rewrites]]
+/// pattern = "^/?grub/(.*)"
+/// replace = "boot/grub2/$1"
+/// lowercase = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RewriteRule {
+    pub pattern: String,
+    /// Replacement of the matches, none keeps them as is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lowercase: bool,
+}
+
+/// Rewriter `struct` applies the compiled [`RewriteRule`]s of a server.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Rewriter {
+    rules: Vec<(Regex, RewriteRule)>,
+}
+
+impl Rewriter {
+    /// Compiles `rules`, failing on an invalid pattern.
+    pub fn new(rules: &[RewriteRule]) -> anyhow::Result<Rewriter> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Regex::new(&rule.pattern)
+                    .map(|regex| (regex, rule.clone()))
+                    .map_err(|e| anyhow::anyhow!("Invalid rewrite pattern {}: {e}", rule.pattern))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Rewriter { rules })
+    }
+
+    /// Returns `filename` rewritten by the rules.
+    pub fn rewrite(&self, filename: &str) -> String {
+        let mut filename = filename.to_string();
+        for (regex, rule) in &self.rules {
+            if !regex.is_match(&filename) {
+                continue;
+            }
+            if let Some(replace) = &rule.replace {
+                filename = regex.replace_all(&filename, replace.as_str()).into_owned();
+            }
+            if rule.lowercase {
+                filename = filename.to_lowercase();
+            }
+        }
+        filename
+    }
+
+    /// Rewrites the file name of a read or write request in place.
+    pub fn rewrite_request(&self, packet: &mut Packet) {
+        if self.rules.is_empty() {
+            return;
+        }
+        if let Packet::Rrq { filename, .. } | Packet::Wrq { filename, .. } = packet {
+            let rewritten = self.rewrite(filename);
+            if rewritten != *filename {
+                log::debug!("Rewrote requested file {filename} to {rewritten}");
+                *filename = rewritten;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replace: Option<&str>, lowercase: bool) -> RewriteRule {
+        RewriteRule {
+            pattern: pattern.to_string(),
+            replace: replace.map(str::to_string),
+            lowercase,
+        }
+    }
+
+    #[test]
+    fn applies_rules_in_order() {
+        let rewriter = Rewriter::new(&[
+            rule(r"\\", Some("/"), false),
+            rule("^/+", Some(""), false),
+            rule("^grub/(.*)", Some("boot/grub2/$1"), false),
+            rule("(?i)^boot/", None, true),
+        ])
+        .unwrap();
+
+        // Patterns are case sensitive unless they say otherwise
+        assert_eq!(
+            rewriter.rewrite(r"\GRUB\x86_64-EFI\Normal.mod"),
+            "GRUB/x86_64-EFI/Normal.mod"
+        );
+        assert_eq!(
+            rewriter.rewrite(r"\grub\x86_64-efi\Normal.mod"),
+            "boot/grub2/x86_64-efi/normal.mod"
+        );
+        assert_eq!(rewriter.rewrite("/pxelinux.0"), "pxelinux.0");
+        assert_eq!(rewriter.rewrite("Kernel.img"), "Kernel.img");
+    }
+
+    #[test]
+    fn rewrites_requests() {
+        let rewriter = Rewriter::new(&[rule("^/", Some(""), true)]).unwrap();
+        let mut packet = Packet::Rrq {
+            filename: "/EFI/BOOTX64.EFI".to_string(),
+            mode: "octet".to_string(),
+            options: Vec::new(),
+            custom: vec![],
+        };
+        rewriter.rewrite_request(&mut packet);
+        assert!(
+            matches!(packet, Packet::Rrq { ref filename, .. } if filename == "efi/bootx64.efi")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(Rewriter::new(&[rule("(", None, false)]).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::provider::normalize_name;
+
+/// Roots `struct` maps prefixes of the requested file names to the
+/// directories serving them, e.g. `uefi/` to `/srv/uefi`, so that one server
+/// holds the trees of several boot flavors. Names without a mapped prefix
+/// are served from the root directory.
+///
+/// The longest matching prefix wins, and is removed from the name resolved
+/// in its directory.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Roots {
+    prefixes: Vec<(String, PathBuf)>,
+}
+
+impl Roots {
+    /// Creates the mapping of `roots`, keyed by prefix.
+    pub fn new(roots: &BTreeMap<String, PathBuf>) -> Roots {
+        let mut prefixes: Vec<(String, PathBuf)> = roots
+            .iter()
+            .map(|(prefix, directory)| {
+                let directory = std::fs::canonicalize(directory).unwrap_or(directory.clone());
+                log::info!("TFTP root for {prefix}: {}", directory.display());
+                (normalize_name(prefix), directory)
+            })
+            .collect();
+        prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Roots { prefixes }
+    }
+
+    /// Returns the directory serving `filename`, `directory` if no prefix
+    /// matches, with the name to resolve in it.
+    pub fn resolve<'a>(&'a self, directory: &'a PathBuf, filename: &str) -> (&'a PathBuf, String) {
+        let name = normalize_name(filename);
+        self.prefixes
+            .iter()
+            .find_map(|(prefix, root)| {
+                name.strip_prefix(prefix.as_str())
+                    .map(|rest| (root, rest.to_string()))
+            })
+            .unwrap_or((directory, filename.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_longest_prefix() {
+        let roots = Roots::new(&BTreeMap::from([
+            ("uefi/".to_string(), PathBuf::from("/srv/uefi")),
+            ("/uefi/arm64/".to_string(), PathBuf::from("/srv/arm64")),
+            ("bios/".to_string(), PathBuf::from("/srv/bios")),
+        ]));
+        let directory = PathBuf::from("/srv/tftp");
+
+        let resolve = |filename| {
+            let (root, name) = roots.resolve(&directory, filename);
+            (root.clone(), name)
+        };
+        assert_eq!(
+            resolve("uefi/grubx64.efi"),
+            (PathBuf::from("/srv/uefi"), "grubx64.efi".to_string())
+        );
+        assert_eq!(
+            resolve(r"\uefi\arm64\grubaa64.efi"),
+            (PathBuf::from("/srv/arm64"), "grubaa64.efi".to_string())
+        );
+        assert_eq!(
+            resolve("/bios/pxelinux.0"),
+            (PathBuf::from("/srv/bios"), "pxelinux.0".to_string())
+        );
+        assert_eq!(
+            resolve("uefi.cfg"),
+            (PathBuf::from("/srv/tftp"), "uefi.cfg".to_string())
+        );
+    }
+}
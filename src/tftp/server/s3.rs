@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::fs::TftpFs;
+
+/// S3Config `struct` describes the bucket of an S3-compatible object storage
+/// served instead of a directory, so that the boot artifacts of a fleet are
+/// hosted centrally while edge servers serve them over TFTP. Objects are
+/// downloaded once to `cache_dir` and served from there until their ETag
+/// changes, or while the storage cannot be reached.
+///
+/// Requests are addressed path-style, `<endpoint>/<bucket>/<prefix><name>`,
+/// and signed with the credentials given, or those of the
+/// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
+/// They are anonymous without either, for public buckets. Serving a bucket
+/// needs xtool built with the `s3` feature.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd.s3]
+/// endpoint = "https://s3.eu-west-1.amazonaws.com"
+/// bucket = "fleet-boot"
+/// region = "eu-west-1"
+/// prefix = "edge/"
+/// cache_dir = "/var/cache/xtool/s3"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct S3Config {
+    /// URL of the storage, e.g. `http://minio.lab:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    /// Region requests are signed for (default: us-east-1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Prepended to the requested names to make the keys of the objects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+    /// Directory the objects are cached in (default: `xtool-s3` in the
+    /// temporary directory)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl S3Config {
+    /// Returns the access key ID and secret access key requests are signed
+    /// with, if any.
+    #[allow(dead_code)]
+    pub(super) fn credentials(&self) -> Option<(String, String)> {
+        let access_key_id = self
+            .access_key_id
+            .clone()
+            .or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())?;
+        let secret_access_key = self
+            .secret_access_key
+            .clone()
+            .or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())?;
+        Some((access_key_id, secret_access_key))
+    }
+}
+
+/// Opens the bucket of `config` as a read-only [`TftpFs`].
+#[cfg(feature = "s3")]
+pub fn open_bucket(config: &S3Config) -> anyhow::Result<Arc<dyn TftpFs>> {
+    Ok(Arc::new(super::s3_fs::S3Fs::new(config)?))
+}
+
+#[cfg(not(feature = "s3"))]
+pub fn open_bucket(config: &S3Config) -> anyhow::Result<Arc<dyn TftpFs>> {
+    Err(anyhow::anyhow!(
+        "Cannot serve bucket {}, xtool was built without the s3 feature",
+        config.bucket
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_bucket_settings() {
+        let bucket: S3Config = toml::from_str(
+            r#"
+            endpoint = "http://minio.lab:9000"
+            bucket = "fleet-boot"
+            access_key_id = "edge"
+            secret_access_key = "secret"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bucket.region, None);
+        assert_eq!(
+            bucket.credentials(),
+            Some(("edge".to_string(), "secret".to_string()))
+        );
+        #[cfg(not(feature = "s3"))]
+        assert!(open_bucket(&bucket).is_err());
+    }
+}
@@ -1,19 +1,61 @@
-use std::cmp::max;
 use std::collections::HashMap;
-use std::net::{SocketAddr, UdpSocket};
-use std::path::{MAIN_SEPARATOR, Path, PathBuf};
-use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::{Component, MAIN_SEPARATOR, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::tftp::core::options::{
-    DEFAULT_BLOCK_SIZE, OptionFmt, OptionsPrivate, OptionsProtocol, RequestType,
+    COMPRESS_GZIP, DEFAULT_BLOCK_SIZE, OptionFmt, OptionsPrivate, OptionsProtocol, RequestType,
+    Rollover,
+};
+use crate::tftp::core::{
+    BufferPool, Convert, CustomOption, ErrorCode, Flow, MAX_REQUEST_PACKET_SIZE, Opcode,
+    OptionType, Packet, PacketError, PcapWriter, PeerSocket, RateLimiter, RecordingSocket,
+    ServerSocket, SessionRecorder, Socket, TransferOption, max_block_size,
 };
-use crate::tftp::core::{ErrorCode, Packet, ServerSocket, Socket, TransferOption};
 
-use super::{Config, Worker};
+use super::acl::Acl;
+use super::activity::Activity;
+use super::admin::{AdminHandles, AdminSocket};
+use super::bind::{bind_udp, listen_addrs, set_dscp, transfer_addr};
+use super::boot_map::BootMap;
+use super::cache::{DEFAULT_MAX_FILE_SIZE, FileCache};
+use super::compress::{Compressor, DEFAULT_CAPACITY, take_compress};
+use super::dtls::{DtlsTerminator, Relays};
+use super::dynamic::{DynamicContent, glob_matches};
+use super::fs::{DiskFs, TftpFs};
+use super::handler::{Direction, Handlers, ServerHandler, TransferInfo};
+use super::json_log::{JsonLog, LogFormat};
+use super::listing::{LISTING_FILENAME, Listing};
+use super::manifest::{MANIFEST_FILENAME, Manifest};
+use super::metrics::{Metrics, MetricsSocket};
+use super::mirror::{Fetch, Mirror};
+use super::multicast::{
+    DEFAULT_MULTICAST_PORT, DEFAULT_MULTICAST_TTL, Member, Multicast, Stream, take_multicast,
+};
+use super::overwrite::{OverwritePolicy, reserve_version};
+use super::pool::{Task, ThreadPool};
+use super::priority::{AdmissionQueue, PriorityClass, classify};
+use super::privileges::{chroot_path, drop_privileges};
+use super::provider::normalize_name;
+use super::rewrite::Rewriter;
+use super::roots::Roots;
+use super::sessions::{Route, Sessions};
+use super::status::{SessionsHandle, TransferStatus, Transfers};
+use super::symlinks::Symlinks;
+use super::templates::Templates;
+use super::transfer_log::TransferLog;
+use super::webhook::UploadWebhook;
+use super::{Config, Journal, MemoryFs, UploadQuota, Worker, open_archive, open_bucket};
 
-#[cfg(test)]
-use crate::tftp::core::OptionType;
+/// How often [`Server::listen()`] checks for a shutdown request
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long [`ShutdownHandle::shutdown()`] waits for in-flight transfers
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the progress of the transfers is logged while draining
+const DRAIN_LOG_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Server `struct` is used for handling incoming TFTP requests.
 ///
@@ -36,266 +78,1605 @@ use crate::tftp::core::OptionType;
 /// let server = Server::new(&config).unwrap();
 /// ```
 pub struct Server {
-    socket: UdpSocket,
-    directory: PathBuf,
+    /// Sockets listened on, each by its own acceptor thread
+    sockets: Vec<UdpSocket>,
+    /// Index of the socket the packet being handled was received on
+    listener: usize,
+    /// Set if IPv4 clients are served on an IPv6 socket
+    dual_stack: bool,
+    dscp: Option<u8>,
+    /// Directories downloads are served from and uploads written to
+    send_directory: PathBuf,
+    receive_directory: PathBuf,
     single_port: bool,
     read_only: bool,
-    overwrite: bool,
-    largest_block_size: u16,
-    clients: HashMap<SocketAddr, Sender<Packet>>,
+    /// Normalized patterns of the files uploads may not write
+    protected_paths: Vec<String>,
+    overwrite: OverwritePolicy,
+    case_insensitive: bool,
+    symlinks: Symlinks,
+    roots: Roots,
+    /// Largest block size of the transfers in single port mode, received
+    /// by the acceptors
+    largest_block_size: Arc<AtomicU16>,
+    /// Transfers the packets received in single port mode are routed to
+    sessions: Sessions,
     opt_local: OptionsPrivate,
+    journal: Option<Arc<Journal>>,
+    quota: Option<Arc<UploadQuota>>,
+    /// Set if small files are served from memory
+    cache: Option<Arc<FileCache>>,
+    atomic_uploads: bool,
+    /// Set if completed uploads are recorded in the manifest of `directory`
+    manifest: Option<Arc<Manifest>>,
+    /// Set if completed uploads are POSTed to a webhook
+    webhook: Option<Arc<UploadWebhook>>,
+    transfers: Arc<Transfers>,
+    /// Set if the packets of transfers are recorded to session files there
+    session_dir: Option<PathBuf>,
+    /// Set if the packets of transfers are captured to a pcap file
+    pcap: Option<Arc<PcapWriter>>,
+    /// Virtual root served instead of `directory`
+    fs: Option<Arc<dyn TftpFs>>,
+    dynamic: DynamicContent,
+    templates: Templates,
+    /// Set if files are served compressed with gzip on request
+    compressor: Option<Compressor>,
+    /// Set if missing files are fetched from an HTTP mirror
+    mirror: Option<Mirror>,
+    /// Set if the listing of the root is served
+    listing: Option<Listing>,
+    acl: Acl,
+    /// Set if the transfers of DTLS sessions are relayed to the server
+    dtls: Option<Arc<DtlsTerminator>>,
+    /// Addresses the requests of DTLS clients come from, allowed by `acl`
+    relays: Relays,
+    rewriter: Rewriter,
+    boot_map: BootMap,
+    limits: OptionLimits,
+    disabled_options: Vec<OptionType>,
+    max_transfers: Option<usize>,
+    max_queued: usize,
+    priorities: Vec<PriorityClass>,
+    /// Requests waiting for a transfer to complete
+    queue: AdmissionQueue,
+    client_rate_limit: Option<u64>,
+    total_rate_limit: Option<u64>,
+    /// Bandwidth shared by all transfers
+    total_limiter: Option<Arc<RateLimiter>>,
+    /// Bandwidth shared by the transfers of each client
+    client_limiters: HashMap<IpAddr, Arc<RateLimiter>>,
+    handler: Option<Arc<dyn ServerHandler>>,
+    /// Set if the metrics of the server are served over HTTP
+    metrics: Option<Arc<Metrics>>,
+    /// Threads the transfers run on
+    pool: ThreadPool,
+    workers: Vec<Task>,
+    /// Transfers without packets from their client for longer are stopped
+    transfer_idle_timeout: Option<Duration>,
+    /// Set if files are multicast to the clients asking for it
+    multicast: Option<Multicast>,
+    /// Requests being served, answered again when retransmitted
+    requests: HashMap<SocketAddr, Request>,
+    /// Root directory the process changed root to, if any
+    chroot: Option<PathBuf>,
+    /// Configuration to reload, set by a [`ReloadHandle`]
+    reload: Arc<Mutex<Option<Config>>>,
+    shutdown: Arc<ShutdownState>,
+    /// Last time the progress of the transfers was logged while draining
+    drain_logged: Option<Instant>,
+}
+
+/// ShutdownHandle `struct` stops a [`Server`] that is listening, possibly
+/// from another thread.
+///
+/// This `struct` is meant to be created by [`Server::shutdown_handle()`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use xtool::tftp::server::{Config, Server};
+/// use std::path::PathBuf;
+/// use std::thread;
+///
+/// let config = Config::with_defaults().merge_cli(
+///     "127.0.0.1".to_string(),
+///     6969,
+///     PathBuf::from("/tmp/tftp"),
+///     false,
+///     false,
+/// );
+/// let mut server = Server::new(&config).unwrap();
+/// let handle = server.shutdown_handle();
+/// let listener = thread::spawn(move || server.listen());
+///
+/// handle.shutdown();
+/// listener.join().unwrap();
+/// ```
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+/// ReloadHandle `struct` reloads the configuration of a [`Server`] that is
+/// listening, possibly from another thread. See [`Server::reload()`].
+///
+/// This `struct` is meant to be created by [`Server::reload_handle()`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use xtool::tftp::server::{Config, Server};
+/// use std::path::PathBuf;
+/// use std::thread;
+///
+/// let config = Config::with_defaults().merge_cli(
+///     "127.0.0.1".to_string(),
+///     6969,
+///     PathBuf::from("/tmp/tftp"),
+///     false,
+///     false,
+/// );
+/// let mut server = Server::new(&config).unwrap();
+/// let handle = server.reload_handle();
+/// thread::spawn(move || server.listen());
+///
+/// handle.reload(config.with_client_rate_limit(1_000_000));
+/// ```
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct ReloadHandle {
+    config: Arc<Mutex<Option<Config>>>,
+}
+
+#[allow(dead_code)]
+impl ReloadHandle {
+    /// Reloads `config` once the server is done with the packet at hand,
+    /// replacing a configuration not reloaded yet. Errors are logged, the
+    /// server keeping its previous configuration.
+    pub fn reload(&self, config: Config) {
+        *self.config.lock().unwrap() = Some(config);
+    }
+}
+
+/// Packet received by an [`Acceptor`], its sender and the index of its socket
+type Received = (Result<Packet, PacketError>, SocketAddr, usize);
+
+/// Acceptor `struct` receives the packets of one of the sockets of a
+/// [`Server`], handing them to its listening loop along with the index of
+/// the socket, until stopped. Malformed packets are handed as their
+/// [`PacketError`] to be answered, unless they are error packets.
+struct Acceptor {
+    socket: UdpSocket,
+    listener: usize,
+    single_port: bool,
+    largest_block_size: Arc<AtomicU16>,
+    stop: Arc<AtomicBool>,
+    sender: mpsc::Sender<Received>,
+    pcap: Option<Arc<PcapWriter>>,
+}
+
+impl Acceptor {
+    fn run(self) {
+        if let Err(err) = self.socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL)) {
+            log::warn!("Cannot poll for shutdown requests: {err}");
+        }
+        while !self.stop.load(Ordering::SeqCst) {
+            let size = if self.single_port {
+                self.largest_block_size.load(Ordering::Relaxed) as usize
+            } else {
+                MAX_REQUEST_PACKET_SIZE
+            };
+            let mut buf = BufferPool::shared().take(size + 4);
+            let received = self.socket.recv_from(&mut buf).map(|(amt, from)| {
+                // Captured as received, malformed or before being rewritten
+                if let Some(pcap) = &self.pcap
+                    && let Ok(local) = self.socket.local_addr()
+                {
+                    pcap.record(Flow::Received, local, from, &buf[..amt]);
+                }
+                let is_error = buf[..amt].starts_with(&Opcode::Error.as_bytes());
+                (Packet::deserialize(&buf[..amt]), from, is_error)
+            });
+            BufferPool::shared().give(buf);
+            match received {
+                // Errors are never answered with errors
+                Ok((Err(_), _, true)) | Err(_) => {}
+                Ok((packet, from, _)) => {
+                    if self.sender.send((packet, from, self.listener)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Request `struct` is a transfer started by the server, kept so that a
+/// client which lost the answer to its request gets it again.
+struct Request {
+    filename: String,
+    /// OACK or ACK the transfer was started with, if any
+    reply: Option<Packet>,
+    /// Socket of the transfer, unless in single port mode
+    socket: Option<PeerSocket>,
+    task: Task,
+    activity: Arc<Activity>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    /// In-flight transfers are abandoned past it, waited for without one
+    deadline: Mutex<Option<Instant>>,
+    listening: Mutex<bool>,
+    stopped: Condvar,
+}
+
+#[allow(dead_code)]
+impl ShutdownHandle {
+    /// Stops the server, giving in-flight transfers up to 10 seconds to
+    /// complete. See [`ShutdownHandle::shutdown_within()`].
+    pub fn shutdown(&self) {
+        self.shutdown_within(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    /// Stops the server from accepting new requests, then blocks until
+    /// [`Server::listen()`] has returned. In-flight transfers are given up
+    /// to `timeout` to complete, after which they are abandoned.
+    pub fn shutdown_within(&self, timeout: Duration) {
+        *self.state.deadline.lock().unwrap() = Some(Instant::now() + timeout);
+        self.stop();
+    }
+
+    /// Stops the server from accepting new requests, then blocks until the
+    /// transfers in progress are complete, however long they take, and
+    /// [`Server::listen()`] has returned. Their progress is logged every 5
+    /// seconds meanwhile, so that an upgrade waits for the devices being
+    /// provisioned instead of interrupting them.
+    pub fn drain(&self) {
+        *self.state.deadline.lock().unwrap() = None;
+        log::info!("Draining, new requests are refused");
+        self.stop();
+    }
+
+    fn stop(&self) {
+        self.state.requested.store(true, Ordering::SeqCst);
+
+        let listening = self.state.listening.lock().unwrap();
+        let _stopped = self
+            .state
+            .stopped
+            .wait_while(listening, |listening| *listening)
+            .unwrap();
+    }
 }
 
 impl Server {
     /// Creates the TFTP Server with the supplied [`Config`].
     pub fn new(config: &Config) -> anyhow::Result<Server> {
-        let ip_str = config.ip.as_deref().unwrap_or("0.0.0.0");
-        let ip_addr: std::net::IpAddr = ip_str.parse()?;
-        let port = config.port.unwrap_or(69);
-
-        let socket = UdpSocket::bind(SocketAddr::from((ip_addr, port))).map_err(|e| {
-            if e.kind() == std::io::ErrorKind::PermissionDenied && port < 1024 {
-                anyhow::anyhow!(
-                    "Permission denied binding to port {}. \n\
-                    Hint: Ports below 1024 require elevated privileges.\n\
-                    Try: sudo setcap cap_net_bind_service=+eip $(which xtool)\n\
-                    Or run with sudo.\n\
-                    Original error: {}",
-                    port,
-                    e
-                )
-            } else {
-                anyhow::Error::new(e)
+        let dual_stack = config.dual_stack.unwrap_or(false);
+        let bind = |addr: SocketAddr| {
+            let port = addr.port();
+            bind_udp(addr, dual_stack).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::PermissionDenied && port < 1024 {
+                    anyhow::anyhow!(
+                        "Permission denied binding to port {}. \n\
+                        Hint: Ports below 1024 require elevated privileges.\n\
+                        Try: sudo setcap cap_net_bind_service=+eip $(which xtool)\n\
+                        Or run with sudo, setting user under [tftpd] to drop privileges.\n\
+                        Original error: {}",
+                        port,
+                        e
+                    )
+                } else {
+                    anyhow::Error::new(e).context(format!("Cannot listen on {addr}"))
+                }
+            })
+        };
+        let sockets = listen_addrs(config)?
+            .into_iter()
+            .map(bind)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Server::from_sockets(sockets, config)
+    }
+
+    /// Creates the TFTP Server with the supplied [`Config`], serving on
+    /// `socket` instead of binding its own, e.g. a socket handed over by
+    /// inetd, systemd or a test harness. The listen addresses of `config`
+    /// are ignored, transfers are served from the IP address of `socket`.
+    #[allow(dead_code)]
+    pub fn from_socket(socket: UdpSocket, config: &Config) -> anyhow::Result<Server> {
+        // Inherited sockets may be non-blocking, the acceptor polls with timeouts
+        socket.set_nonblocking(false)?;
+        Server::from_sockets(vec![socket], config)
+    }
+
+    fn from_sockets(sockets: Vec<UdpSocket>, config: &Config) -> anyhow::Result<Server> {
+        let dual_stack = config.dual_stack.unwrap_or(false);
+        if let Some(dscp) = config.dscp {
+            if dscp > 63 {
+                return Err(anyhow::anyhow!(
+                    "Invalid DSCP value {dscp}, must be 0 to 63"
+                ));
             }
-        })?;
+            // Errors and single port transfers are sent from the listen sockets
+            for socket in &sockets {
+                set_dscp(socket, dscp)
+                    .map_err(|e| anyhow::anyhow!("Cannot mark packets with DSCP {dscp}: {e}"))?;
+            }
+        }
+        // Bound along with the server ports, as they may be privileged too
+        let admin = config
+            .admin_socket
+            .as_deref()
+            .map(AdminSocket::bind)
+            .transpose()?;
+        let dtls = match (&config.dtls, sockets.first()) {
+            (Some(listener), Some(socket)) => {
+                let terminator =
+                    DtlsTerminator::new(listener, socket.local_addr()?, config.get_acl())?;
+                log::info!("DTLS endpoint: {}", terminator.local_addr()?);
+                Some(Arc::new(terminator))
+            }
+            _ => None,
+        };
+        let relays = dtls
+            .as_ref()
+            .map(|terminator| terminator.relays())
+            .unwrap_or_default();
+        let metrics = match config.metrics_addr {
+            Some(addr) => {
+                let metrics = Arc::new(Metrics::default());
+                let addr = metrics.serve(addr)?;
+                log::info!("Serving metrics on http://{addr}/metrics");
+                Some(metrics)
+            }
+            None => None,
+        };
+        let mirror = match &config.mirror {
+            Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+                return Err(anyhow::anyhow!("Invalid mirror {url}, not an HTTP URL"));
+            }
+            Some(url) => {
+                let mirror = Mirror::new(url);
+                log::info!("Mirror: {}", mirror.url());
+                Some(mirror)
+            }
+            None => None,
+        };
 
         let directory = config
             .directory
             .clone()
             .unwrap_or_else(|| PathBuf::from("."));
         let directory = std::fs::canonicalize(&directory).unwrap_or(directory);
-        log::info!("TFTP root directory: {}", directory.display());
+
+        // Buckets and archives are mounted as a virtual read-only root
+        let fs = if let Some(bucket) = &config.s3 {
+            log::info!("TFTP root bucket: {}/{}", bucket.endpoint, bucket.bucket);
+            Some(open_bucket(bucket)?)
+        } else if directory.is_file() {
+            log::info!("TFTP root archive: {}", directory.display());
+            Some(open_archive(&directory)?)
+        } else {
+            log::info!("TFTP root directory: {}", directory.display());
+            None
+        };
+
+        let chroot = config.chroot.unwrap_or(false).then(|| directory.clone());
+        // Privileges are dropped once the port is bound, before other files are opened
+        let (config, directory) = drop_privileges(config, &directory)?;
+        let config = &config;
+
+        let send_directory = transfer_directory(config.send_directory.as_ref(), &directory);
+        let receive_directory = transfer_directory(config.receive_directory.as_ref(), &directory);
+        if fs.is_none() && send_directory != directory {
+            log::info!("Download directory: {}", send_directory.display());
+        }
+        if fs.is_none() && receive_directory != directory {
+            log::info!("Upload directory: {}", receive_directory.display());
+        }
+
+        let journal = match &config.journal {
+            Some(path) => {
+                log::info!("Upload journal: {}", path.display());
+                Some(Arc::new(Journal::open(path)?))
+            }
+            None => None,
+        };
+
+        let manifest = (config.manifest.unwrap_or(false) && fs.is_none()).then(|| {
+            let manifest = Manifest::new(&receive_directory);
+            log::info!("Upload manifest: {}", manifest.path().display());
+            Arc::new(manifest)
+        });
+        let webhook = match &config.upload_webhook {
+            Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+                return Err(anyhow::anyhow!(
+                    "Invalid upload webhook {url}, not an HTTP URL"
+                ));
+            }
+            Some(url) => {
+                let webhook = UploadWebhook::new(url, &receive_directory);
+                log::info!("Upload webhook: {}", webhook.url());
+                Some(Arc::new(webhook))
+            }
+            None => None,
+        };
+
+        let cache = config.cache_size.map(|size| {
+            let max_file_size = config.cache_max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE);
+            log::info!("File cache: {size} bytes, files up to {max_file_size} bytes");
+            Arc::new(FileCache::new(size, max_file_size))
+        });
+
+        let multicast = match config.multicast_address {
+            Some(address) => {
+                let port = config.multicast_port.unwrap_or(DEFAULT_MULTICAST_PORT);
+                log::info!("Multicast groups: {address} from port {port}");
+                let ttl = config.multicast_ttl.unwrap_or(DEFAULT_MULTICAST_TTL);
+                Some(Multicast::new(address, port, ttl)?)
+            }
+            None => None,
+        };
+
+        // Tracks the transfers in progress for Server::sessions()
+        let transfers = Arc::new(Transfers::default());
+        let mut handlers: Vec<Arc<dyn ServerHandler>> = vec![transfers.clone()];
+        if let Some(path) = &config.transfer_log {
+            let transfer_log = TransferLog::new(path)
+                .with_rotation(config.transfer_log_rotation.unwrap_or_default());
+            log::info!("Transfer log: {}", transfer_log.path().display());
+            handlers.push(Arc::new(transfer_log));
+        }
+        if config.log_format == Some(LogFormat::Json) {
+            handlers.push(Arc::new(JsonLog::stdout()));
+        }
+        let handler = match handlers.len() {
+            0 => None,
+            1 => handlers.pop(),
+            _ => Some(Arc::new(Handlers(handlers)) as Arc<dyn ServerHandler>),
+        };
+
+        if let Some(session_dir) = &config.session_dir {
+            std::fs::create_dir_all(session_dir)?;
+            log::info!("Recording sessions to {}", session_dir.display());
+        }
+        let pcap = match &config.pcap {
+            Some(path) => {
+                log::info!("Capturing packets to {}", path.display());
+                Some(Arc::new(PcapWriter::create(path)?))
+            }
+            None => None,
+        };
 
         let server = Server {
-            socket,
-            directory,
+            sockets,
+            listener: 0,
+            dual_stack,
+            dscp: config.dscp,
+            send_directory,
+            receive_directory,
             single_port: config.single_port.unwrap_or(false),
-            read_only: config.read_only.unwrap_or(false),
-            overwrite: config.overwrite.unwrap_or(true),
-            largest_block_size: DEFAULT_BLOCK_SIZE,
-            clients: HashMap::new(),
+            read_only: config.read_only.unwrap_or(false) || fs.is_some(),
+            protected_paths: config
+                .protected_paths
+                .iter()
+                .flatten()
+                .map(|pattern| normalize_name(pattern))
+                .collect(),
+            overwrite: config.overwrite.unwrap_or_default(),
+            case_insensitive: config.case_insensitive.unwrap_or(false),
+            symlinks: config.get_symlinks(),
+            roots: config.get_roots(),
+            largest_block_size: Arc::new(AtomicU16::new(DEFAULT_BLOCK_SIZE)),
+            sessions: Sessions::default(),
             opt_local: config.get_options(),
+            journal,
+            quota: config
+                .upload_quota
+                .map(|limit| Arc::new(UploadQuota::new(limit))),
+            cache,
+            atomic_uploads: config.atomic_uploads.unwrap_or(false),
+            manifest,
+            webhook,
+            transfers,
+            session_dir: config.session_dir.clone(),
+            pcap,
+            fs,
+            dynamic: config.dynamic.clone(),
+            templates: Templates::new(config.templates.as_deref().unwrap_or_default()),
+            compressor: config
+                .compress
+                .unwrap_or_default()
+                .then(|| Compressor::new(DEFAULT_CAPACITY)),
+            mirror,
+            listing: config.listing.unwrap_or(false).then(Listing::default),
+            acl: config.get_acl(),
+            dtls,
+            relays,
+            rewriter: config.get_rewriter()?,
+            boot_map: config.get_boot_map(),
+            limits: config.get_limits()?,
+            disabled_options: config.get_disabled_options()?,
+            max_transfers: config.max_transfers,
+            max_queued: config.max_queued.unwrap_or(0),
+            priorities: config.priorities.clone().unwrap_or_default(),
+            queue: AdmissionQueue::default(),
+            client_rate_limit: config.client_rate_limit,
+            total_rate_limit: config.total_rate_limit,
+            total_limiter: config
+                .total_rate_limit
+                .map(|rate| Arc::new(RateLimiter::new(rate))),
+            client_limiters: HashMap::new(),
+            handler,
+            metrics,
+            pool: config.get_pool(),
+            workers: Vec::new(),
+            transfer_idle_timeout: config.transfer_idle_timeout.map(Duration::from_secs),
+            multicast,
+            requests: HashMap::new(),
+            chroot,
+            reload: Arc::new(Mutex::new(None)),
+            shutdown: Arc::new(ShutdownState::default()),
+            drain_logged: None,
         };
 
+        if let Some(admin) = admin {
+            log::info!(
+                "Admin socket: {}",
+                config.admin_socket.as_ref().unwrap().display()
+            );
+            admin.serve(AdminHandles {
+                sessions: server.sessions_handle(),
+                shutdown: server.shutdown_handle(),
+                metrics: server.metrics.clone(),
+            });
+        }
+
         Ok(server)
     }
 
-    /// Starts listening for connections. Note that this function does not finish running until termination.
+    /// Stops accepting new requests, letting the transfers in progress
+    /// finish, after which [`Server::listen()`] returns. See
+    /// [`ShutdownHandle::drain()`], to be used while the server listens.
+    #[allow(dead_code)]
+    pub fn drain(&self) {
+        self.shutdown_handle().drain()
+    }
+
+    /// Returns the transfers in progress, with the bytes transferred so far,
+    /// ordered by client. Use [`Server::sessions_handle()`] while the server
+    /// listens.
+    #[allow(dead_code)]
+    pub fn sessions(&self) -> Vec<TransferStatus> {
+        self.transfers.list()
+    }
+
+    /// Returns a handle that lists the transfers in progress while the
+    /// server listens, e.g. for an admin interface.
+    #[allow(dead_code)]
+    pub fn sessions_handle(&self) -> SessionsHandle {
+        self.transfers.handle()
+    }
+
+    /// Aborts the transfer `id` of [`Server::sessions()`], sending an error
+    /// to its client. Its worker stops within a timeout at most. Returns
+    /// `false` if the transfer is not in progress. Use
+    /// [`Server::sessions_handle()`] while the server listens.
+    #[allow(dead_code)]
+    pub fn abort(&self, id: u64) -> bool {
+        self.transfers.abort(id)
+    }
+
+    /// Notifies `handler` of the requests and transfers of the server. May
+    /// be called several times, handlers are then notified in turn.
+    #[allow(dead_code)]
+    pub fn with_handler(mut self, handler: Arc<dyn ServerHandler>) -> Server {
+        self.handler = Some(match self.handler.take() {
+            Some(previous) => Arc::new(Handlers(vec![previous, handler])),
+            None => handler,
+        });
+        self
+    }
+
+    /// Serves the files of `fs` instead of the configured directory, such as
+    /// a [`MemoryFs`](super::MemoryFs). Uploads are written to `fs` unless the
+    /// server is read-only, and are neither journaled nor recorded in a
+    /// manifest.
+    #[allow(dead_code)]
+    pub fn with_fs(mut self, fs: Arc<dyn TftpFs>) -> Server {
+        log::info!("TFTP root: virtual filesystem");
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Returns the address the server listens on, the first one if it
+    /// listens on several, e.g. to find the port bound for port 0.
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.sockets[0].local_addr()?)
+    }
+
+    /// Returns the address of the DTLS endpoint of the server, if any.
+    #[allow(dead_code)]
+    pub fn dtls_addr(&self) -> Option<SocketAddr> {
+        self.dtls
+            .as_ref()
+            .and_then(|terminator| terminator.local_addr().ok())
+    }
+
+    /// Returns a handle that stops [`Server::listen()`] when shut down.
+    #[allow(dead_code)]
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            state: self.shutdown.clone(),
+        }
+    }
+
+    /// Returns a handle that reloads the configuration of the server while
+    /// it listens.
+    #[allow(dead_code)]
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            config: self.reload.clone(),
+        }
+    }
+
+    /// Reloads the access lists, rewrite rules, boot mappings, roots and rate
+    /// limits of `config`, leaving the rest of the configuration of the
+    /// server as it was created. Transfers in progress go on unchanged, the new settings
+    /// apply to the requests received afterwards.
+    ///
+    /// Nothing is reloaded if `config` is invalid.
+    #[allow(dead_code)]
+    pub fn reload(&mut self, config: &Config) -> anyhow::Result<()> {
+        let mut config = config.clone();
+        if let Some(root) = &self.chroot {
+            for path in config.roots.iter_mut().flat_map(|roots| roots.values_mut()) {
+                *path = chroot_path(path, root)?;
+            }
+        }
+        let rewriter = config.get_rewriter()?;
+
+        self.acl = config.get_acl();
+        self.rewriter = rewriter;
+        self.boot_map = config.get_boot_map();
+        self.roots = config.get_roots();
+        self.opt_local.rate_limit = config.rate_limit;
+        if self.client_rate_limit != config.client_rate_limit {
+            // Clients in progress keep sharing their previous limiter
+            self.client_limiters.clear();
+            self.client_rate_limit = config.client_rate_limit;
+        }
+        if self.total_rate_limit != config.total_rate_limit {
+            self.total_limiter = config
+                .total_rate_limit
+                .map(|rate| Arc::new(RateLimiter::new(rate)));
+            self.total_rate_limit = config.total_rate_limit;
+        }
+        log::info!("Configuration reloaded");
+        Ok(())
+    }
+
+    /// Starts listening for connections. Note that this function does not
+    /// finish running until termination, or until stopped by a
+    /// [`ShutdownHandle`].
     pub fn listen(&mut self) {
-        loop {
-            let received = if self.single_port {
-                self.socket
-                    .recv_from_with_size(self.largest_block_size as usize)
-            } else {
-                Socket::recv_from(&self.socket)
+        *self.shutdown.listening.lock().unwrap() = true;
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let mut acceptors = Vec::new();
+        for (listener, socket) in self.sockets.iter().enumerate() {
+            let acceptor = Acceptor {
+                socket: match socket.try_clone() {
+                    Ok(socket) => socket,
+                    Err(err) => {
+                        log::error!("Cannot listen on {:?}: {err}", socket.local_addr());
+                        continue;
+                    }
+                },
+                listener,
+                single_port: self.single_port,
+                largest_block_size: self.largest_block_size.clone(),
+                stop: stop.clone(),
+                sender: sender.clone(),
+                pcap: self.pcap.clone(),
             };
+            acceptors.push(thread::spawn(move || acceptor.run()));
+        }
+        drop(sender);
+        if let Some(terminator) = self.dtls.clone() {
+            let stop = stop.clone();
+            acceptors.push(thread::spawn(move || terminator.run(stop)));
+        }
 
-            if let Ok((packet, from)) = received {
-                match packet {
-                    Packet::Rrq {
-                        filename,
-                        mut options,
-                        ..
-                    } => {
-                        log::info!("Received Read request from {from}: {filename}");
-                        if let Err(err) = self.handle_rrq(filename.clone(), &mut options, &from) {
-                            log::error!("Error while sending file: {err}")
-                        }
+        loop {
+            let reload = self.reload.lock().unwrap().take();
+            if let Some(config) = reload
+                && let Err(err) = self.reload(&config)
+            {
+                log::error!("Cannot reload the configuration: {err:#}");
+            }
+            self.workers.retain(|worker| !worker.is_finished());
+            self.requests
+                .retain(|_, request| !request.task.is_finished());
+            self.reap_idle();
+            self.sessions.expire();
+            if self.shutdown.requested.load(Ordering::SeqCst) {
+                for (_, from, listener) in self.queue.drain() {
+                    self.listener = listener;
+                    self.refuse_shutting_down(&from);
+                }
+                if self.drained() {
+                    break;
+                }
+            }
+            while !self.saturated()
+                && let Some((packet, from, listener)) = self.queue.pop()
+            {
+                self.listener = listener;
+                self.handle_request(packet, &from);
+            }
+            self.answer_fetched();
+
+            if let Ok((packet, from, listener)) = receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+                self.listener = listener;
+                let mut packet = match packet {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        self.refuse_malformed(&err, &from);
+                        continue;
                     }
-                    Packet::Wrq {
-                        filename,
-                        mut options,
-                        ..
-                    } => {
-                        if self.read_only {
-                            if Socket::send_to(
-                                &self.socket,
-                                &Packet::Error {
-                                    code: ErrorCode::AccessViolation,
-                                    msg: "server is read-only".to_string(),
-                                },
+                };
+                self.rewriter.rewrite_request(&mut packet);
+                self.boot_map.map_request(&mut packet, from.ip());
+                let is_error = matches!(packet, Packet::Error { .. });
+                match packet {
+                    Packet::Rrq { .. } | Packet::Wrq { .. }
+                        if !self.acl.permits(from.ip())
+                            && !self.relays.lock().unwrap().contains(&from) =>
+                    {
+                        if self
+                            .send_error(
+                                ErrorCode::AccessViolation,
+                                "Access violation".to_string(),
                                 &from,
                             )
                             .is_err()
-                            {
-                                log::error!("Could not send error packet");
-                            };
-                            log::warn!("Received write request while in read-only mode");
-                            continue;
-                        }
-                        log::info!("Received Write request from {from}: {filename}");
-                        if let Err(err) = self.handle_wrq(filename, &mut options, &from) {
-                            log::error!("Error while receiving file: {err}")
+                        {
+                            log::error!("Could not send error packet");
+                        };
+                        log::warn!("Refused request from {from}, not an allowed network");
+                    }
+                    Packet::Rrq { ref filename, .. } | Packet::Wrq { ref filename, .. }
+                        if self.is_retransmission(filename, &from) =>
+                    {
+                        self.answer_again(&from)
+                    }
+                    Packet::Rrq { ref filename, .. } | Packet::Wrq { ref filename, .. }
+                        if !self.shutdown.requested.load(Ordering::SeqCst) =>
+                    {
+                        if self.saturated() {
+                            if self.queue.touch(&from) {
+                                continue;
+                            }
+                            if self.queue.len() >= self.max_queued {
+                                if self
+                                    .send_error(
+                                        ErrorCode::NotDefined,
+                                        "too many transfers, try again later".to_string(),
+                                        &from,
+                                    )
+                                    .is_err()
+                                {
+                                    log::error!("Could not send error packet");
+                                };
+                                log::warn!("Refused request from {from}, too many transfers");
+                                continue;
+                            }
+                            let priority = classify(&self.priorities, filename, &from);
+                            log::info!(
+                                "Queued request from {from}: {filename} ({priority:?} priority, {} waiting)",
+                                self.queue.len() + 1
+                            );
+                            self.queue.push(priority, packet, from, self.listener);
+                        } else {
+                            self.handle_request(packet, &from);
                         }
                     }
-                    _ => {
-                        if self.route_packet(packet, &from).is_err() {
-                            if Socket::send_to(
-                                &self.socket,
-                                &Packet::Error {
-                                    code: ErrorCode::IllegalOperation,
-                                    msg: "invalid request".to_string(),
-                                },
-                                &from,
-                            )
-                            .is_err()
-                            {
+                    Packet::Rrq { .. } | Packet::Wrq { .. } => self.refuse_shutting_down(&from),
+                    // Retransmissions of a client waiting its turn
+                    _ if self.queue.touch(&from) => {}
+                    _ => match self.sessions.route(packet, &from) {
+                        Route::Delivered => {}
+                        Route::Late => log::debug!("Dropped late packet from {from}"),
+                        // Errors are never answered with errors
+                        Route::Unknown if is_error => {
+                            log::warn!("Received error from {from} without transfer")
+                        }
+                        Route::Unknown => {
+                            let (code, msg) = if self.single_port {
+                                (ErrorCode::UnknownId, "unknown transfer ID")
+                            } else {
+                                (ErrorCode::IllegalOperation, "invalid request")
+                            };
+                            if self.send_error(code, msg.to_string(), &from).is_err() {
                                 log::error!("Could not send error packet");
                             };
-                            log::warn!("Received invalid request");
+                            log::warn!("Received packet from {from} without transfer");
                         }
-                    }
+                    },
                 };
             }
         }
+
+        stop.store(true, Ordering::SeqCst);
+        for acceptor in acceptors {
+            let _ = acceptor.join();
+        }
+        log::info!("TFTP server stopped");
+        *self.shutdown.listening.lock().unwrap() = false;
+        self.shutdown.stopped.notify_all();
+    }
+
+    /// Starts serving a read or write request.
+    fn handle_request(&mut self, packet: Packet, from: &SocketAddr) {
+        log::debug!("Received {packet} from {from}");
+        match packet {
+            Packet::Rrq {
+                filename,
+                mode,
+                mut options,
+                custom,
+            } => {
+                log::info!("Received Read request from {from}: {filename}");
+                drop_disabled_options(&mut options, &self.disabled_options);
+                let netascii = is_netascii(&mode);
+                if let Err(err) =
+                    self.handle_rrq(filename.clone(), netascii, &mut options, custom, from)
+                {
+                    log::error!("Error while sending file: {err}")
+                }
+            }
+            Packet::Wrq {
+                filename,
+                mode,
+                mut options,
+                custom,
+            } => {
+                if self.read_only {
+                    if self
+                        .send_error(
+                            ErrorCode::AccessViolation,
+                            "server is read-only".to_string(),
+                            from,
+                        )
+                        .is_err()
+                    {
+                        log::error!("Could not send error packet");
+                    };
+                    log::warn!("Received write request while in read-only mode");
+                    return;
+                }
+                log::info!("Received Write request from {from}: {filename}");
+                drop_disabled_options(&mut options, &self.disabled_options);
+                // Only downloads are multicast or compressed
+                take_multicast(&mut options);
+                take_compress(&mut options);
+                let netascii = is_netascii(&mode);
+                if let Err(err) = self.handle_wrq(filename, netascii, &mut options, custom, from) {
+                    log::error!("Error while receiving file: {err}")
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn refuse_shutting_down(&self, from: &SocketAddr) {
+        if self
+            .send_error(
+                ErrorCode::NotDefined,
+                "server is shutting down".to_string(),
+                from,
+            )
+            .is_err()
+        {
+            log::error!("Could not send error packet");
+        };
+        log::warn!("Refused request from {from} while shutting down");
+    }
+
+    /// Returns whether the request for `filename` from `from` is already
+    /// being served, the client having retransmitted it.
+    fn is_retransmission(&self, filename: &str, from: &SocketAddr) -> bool {
+        self.requests
+            .get(from)
+            .is_some_and(|request| request.filename == filename)
+    }
+
+    /// Serves the requests waiting for the files fetched from the mirror, or
+    /// answers them with the error of the mirror.
+    fn answer_fetched(&mut self) {
+        let Some(mirror) = &self.mirror else {
+            return;
+        };
+        for (requests, result) in mirror.finished() {
+            for (packet, from, listener) in requests {
+                self.listener = listener;
+                match (&result, &packet) {
+                    (Ok(()), Packet::Rrq { filename, .. })
+                        if self.is_retransmission(filename, &from) => {}
+                    (Ok(()), _) => self.handle_request(packet, &from),
+                    (Err(err), Packet::Rrq { filename, .. }) => {
+                        let msg = format!("file {filename} does not exist: {err}");
+                        if self
+                            .send_error(ErrorCode::FileNotFound, msg, &from)
+                            .is_err()
+                        {
+                            log::error!("Could not send error packet");
+                        }
+                    }
+                    (Err(_), _) => {}
+                }
+            }
+        }
+    }
+
+    /// Sends the answer to a retransmitted request again, as the client
+    /// resends its request when the OACK or ACK starting the transfer is lost.
+    fn answer_again(&self, from: &SocketAddr) {
+        let Some(request) = self.requests.get(from) else {
+            return;
+        };
+        // Without options, reads start with data which the worker resends
+        let Some(reply) = &request.reply else {
+            return;
+        };
+        log::debug!("Retransmitted request from {from}, answering again");
+        let sent = match &request.socket {
+            Some(socket) => Socket::send(socket, reply),
+            None => self.send_from_listener(reply, from),
+        };
+        if sent.is_err() {
+            log::error!("Could not resend answer to {from}");
+        }
+    }
+
+    /// Returns whether `max_transfers` transfers are in progress, or no
+    /// thread is left to run another one.
+    fn saturated(&self) -> bool {
+        self.max_transfers
+            .is_some_and(|max_transfers| self.workers.len() >= max_transfers)
+            || self.pool.is_full()
+    }
+
+    /// Returns whether all transfers are done or the shutdown deadline passed,
+    /// logging the progress of the transfers left while draining.
+    fn drained(&mut self) -> bool {
+        if self.workers.is_empty() {
+            return true;
+        }
+
+        let deadline = *self.shutdown.deadline.lock().unwrap();
+        match deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                log::warn!(
+                    "Abandoning {} transfers still in progress",
+                    self.workers.len()
+                );
+                return true;
+            }
+            Some(_) => {}
+            None if self
+                .drain_logged
+                .is_some_and(|logged| logged.elapsed() < DRAIN_LOG_INTERVAL) => {}
+            None => {
+                self.drain_logged = Some(Instant::now());
+                log::info!(
+                    "Draining, waiting for {} transfers in progress",
+                    self.workers.len()
+                );
+                for session in self.transfers.list() {
+                    let progress = session
+                        .progress()
+                        .map(|progress| format!(" ({progress:.1}%)"))
+                        .unwrap_or_default();
+                    log::info!(
+                        "  {} {}: {} bytes{progress}",
+                        session.client,
+                        session.filename,
+                        session.bytes
+                    );
+                }
+            }
+        }
+        false
     }
 
     fn handle_rrq(
         &mut self,
         filename: String,
-        options: &mut [TransferOption],
+        netascii: bool,
+        options: &mut Vec<TransferOption>,
+        custom_options: Vec<CustomOption>,
         to: &SocketAddr,
     ) -> anyhow::Result<()> {
-        let file_path = convert_file_path(&filename);
-        let file_path = &self.directory.join(file_path);
-        match check_file_exists(file_path, &self.directory) {
+        let info = TransferInfo {
+            peer: *to,
+            filename: filename.clone(),
+            direction: Direction::Read,
+            netascii,
+            custom_options,
+        };
+        if !self.allowed(&info)? {
+            return Ok(());
+        }
+        let compress = take_compress(options);
+
+        if let Some(content) = self.dynamic.generate(&info) {
+            return self.send_generated(content, info, options, to);
+        }
+
+        if self.fs.is_none()
+            && normalize_name(&filename) == LISTING_FILENAME
+            && let Some(listing) = &mut self.listing
+        {
+            let content = listing.generate(&self.send_directory)?.into_bytes();
+            return self.send_generated(content, info, options, to);
+        }
+
+        if let Some(fs) = self.fs.clone() {
+            let name = normalize_name(&filename);
+            let file_path = PathBuf::from(&name);
+            return match fs.metadata(&file_path) {
+                Ok(metadata) => {
+                    self.start_send(file_path, metadata.len, Some(fs), info, options, to)
+                }
+                Err(e) => {
+                    log::warn!("Cannot open requested file {name}: {e}");
+                    self.send_error(
+                        ErrorCode::FileNotFound,
+                        format!("file {name} does not exist"),
+                        to,
+                    )
+                }
+            };
+        }
+
+        let (directory, name) = self.roots.resolve(&self.send_directory, &filename);
+        let file_path = &resolve_file_path(directory, &name, self.case_insensitive);
+        match check_file_exists(file_path, directory, &self.symlinks) {
+            // Compressed versions of the files are served unless they exist
+            ErrorCode::FileNotFound
+                if self.compressor.is_some()
+                    && !netascii
+                    && file_path
+                        .extension()
+                        .is_some_and(|extension| extension == "gz")
+                    && check_file_exists(
+                        &file_path.with_extension(""),
+                        directory,
+                        &self.symlinks,
+                    ) == ErrorCode::FileExists =>
+            {
+                self.send_compressed(&file_path.with_extension(""), info, options, to)
+            }
+            ErrorCode::FileNotFound if let Some(mirror) = &self.mirror => {
+                let request = Packet::Rrq {
+                    filename: filename.clone(),
+                    mode: transfer_mode(netascii),
+                    options: options.to_vec(),
+                    custom: info.custom_options.clone(),
+                };
+                match mirror.fetch(&name, file_path, (request, *to, self.listener)) {
+                    // Answered once the fetch finishes
+                    Fetch::Pending => Ok(()),
+                    Fetch::Failed(err) => self.send_error(
+                        ErrorCode::FileNotFound,
+                        format!("file {name} does not exist: {err}"),
+                        to,
+                    ),
+                }
+            }
             ErrorCode::FileNotFound => {
                 log::warn!("Cannot find requested file: {}", file_path.display());
-                Socket::send_to(
-                    &self.socket,
-                    &Packet::Error {
-                        code: ErrorCode::FileNotFound,
-                        msg: format!("file {} does not exist", file_path.display()),
-                    },
+                self.send_error(
+                    ErrorCode::FileNotFound,
+                    format!("file {} does not exist", file_path.display()),
                     to,
                 )
             }
             ErrorCode::AccessViolation => {
                 log::warn!("Cannot access requested file: {}", file_path.display());
-                Socket::send_to(
-                    &self.socket,
-                    &Packet::Error {
-                        code: ErrorCode::AccessViolation,
-                        msg: format!("file access violation: {}", file_path.display()),
-                    },
+                self.send_error(
+                    ErrorCode::AccessViolation,
+                    format!("file access violation: {}", file_path.display()),
                     to,
                 )
             }
+            ErrorCode::FileExists if self.templates.matches(&name) => {
+                let content = std::fs::read(file_path)?;
+                let content = match String::from_utf8(content) {
+                    Ok(text) => {
+                        let local = self.socket().local_addr()?;
+                        Templates::expand(&text, &info.filename, to, &local).into_bytes()
+                    }
+                    Err(err) => {
+                        log::warn!("Serving {} as is, not a text file", file_path.display());
+                        err.into_bytes()
+                    }
+                };
+                self.send_generated(content, info, options, to)
+            }
+            ErrorCode::FileExists if compress && self.compressor.is_some() && !netascii => {
+                options.push(TransferOption {
+                    option: OptionType::Compress,
+                    value: COMPRESS_GZIP,
+                });
+                self.send_compressed(file_path, info, options, to)
+            }
             ErrorCode::FileExists => {
-                let worker_options = OptionsProtocol::parse(
-                    options,
-                    RequestType::Read(file_path.metadata()?.len()),
-                )?;
-                let mut socket: Box<dyn Socket>;
-
-                if self.single_port {
-                    let single_socket =
-                        create_single_socket(&self.socket, to, worker_options.timeout)?;
-                    self.clients.insert(*to, single_socket.sender());
-                    self.largest_block_size =
-                        max(self.largest_block_size, worker_options.block_size);
-
-                    socket = Box::new(single_socket);
-                } else {
-                    socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
-                }
-
-                socket.set_read_timeout(worker_options.timeout)?;
-                socket.set_write_timeout(worker_options.timeout)?;
+                let size = file_path.metadata()?.len();
+                let fs = self.cache.clone().map(|cache| cache as Arc<dyn TftpFs>);
+                self.start_send(file_path.clone(), size, fs, info, options, to)
+            }
+            _ => Err(anyhow::anyhow!("Unexpected error code when checking file")),
+        }
+    }
 
-                log::debug!("  Accepted options: {}", OptionFmt(options));
+    /// Sends the file at `file_path` compressed with gzip as the requested
+    /// file.
+    fn send_compressed(
+        &mut self,
+        file_path: &Path,
+        info: TransferInfo,
+        options: &mut [TransferOption],
+        to: &SocketAddr,
+    ) -> anyhow::Result<()> {
+        let Some(compressor) = &self.compressor else {
+            return Err(anyhow::anyhow!("Compression is disabled"));
+        };
+        let content = compressor.compress(file_path)?;
+        self.send_generated(content.to_vec(), info, options, to)
+    }
 
-                accept_request(
-                    &socket,
-                    options,
-                    RequestType::Read(file_path.metadata()?.len()),
-                )?;
+    /// Sends `content` generated on request as the requested file.
+    fn send_generated(
+        &mut self,
+        content: Vec<u8>,
+        info: TransferInfo,
+        options: &mut [TransferOption],
+        to: &SocketAddr,
+    ) -> anyhow::Result<()> {
+        let name = normalize_name(&info.filename);
+        log::info!("  Generated {name} ({} bytes)", content.len());
+        let size = content.len() as u64;
+        let fs = MemoryFs::new();
+        fs.insert(&name, content);
+        let file_path = PathBuf::from(name);
+        self.start_send(file_path, size, Some(Arc::new(fs)), info, options, to)
+    }
 
-                let worker = Worker::new(
-                    socket,
-                    file_path.clone(),
-                    self.opt_local.clone(),
-                    worker_options.clone(),
-                );
-                worker.send(!options.is_empty())?;
-                Ok(())
+    /// Negotiates the options of a read request and starts the worker
+    /// sending `file_path`, read from `fs` if given.
+    fn start_send(
+        &mut self,
+        file_path: PathBuf,
+        size: u64,
+        fs: Option<Arc<dyn TftpFs>>,
+        info: TransferInfo,
+        options: &mut [TransferOption],
+        to: &SocketAddr,
+    ) -> anyhow::Result<()> {
+        let request = Packet::Rrq {
+            filename: info.filename.clone(),
+            mode: transfer_mode(info.netascii),
+            options: options.to_vec(),
+            custom: info.custom_options.clone(),
+        };
+        // Sizes are those of the content sent, longer once converted
+        let size = if info.netascii {
+            let file = match &fs {
+                Some(fs) => fs.open_read(&file_path, 0)?,
+                None => DiskFs.open_read(&file_path, 0)?,
+            };
+            Convert::netascii_len(file)?
+        } else {
+            size
+        };
+        let mut worker_options = OptionsProtocol::parse(options, RequestType::Read(size))?;
+        clamp_to_limits(options, &mut worker_options, &self.limits);
+        clamp_block_size(options, &mut worker_options);
+        if self.opt_local.rollover == Rollover::None
+            && size / worker_options.block_size as u64 >= u16::MAX as u64
+        {
+            // Refused before any data is sent rather than failing at block 65535
+            self.send_error(
+                ErrorCode::IllegalOperation,
+                "file too large without block counter rollover".to_string(),
+                to,
+            )?;
+            return Err(anyhow::anyhow!(
+                "File of {size} bytes needs more than 65535 blocks of {} bytes, rollover is disabled",
+                worker_options.block_size
+            ));
+        }
+        let mut options = options.to_vec();
+        if take_multicast(&mut options) {
+            let stream = Stream {
+                file_path: file_path.clone(),
+                size,
+                block_size: worker_options.block_size,
+                timeout: worker_options.timeout,
+                max_retries: self.opt_local.max_retries,
+                fs: fs.clone(),
+            };
+            if self.join_multicast(stream, &info, &options, &worker_options)? {
+                return Ok(());
             }
-            _ => Err(anyhow::anyhow!("Unexpected error code when checking file")),
         }
+        let options = &mut options[..];
+        let socket: Box<dyn Socket>;
+        let mut resend_socket = None;
+
+        if self.single_port {
+            let single_socket = create_single_socket(self.socket(), to, worker_options.timeout)?
+                .with_pcap(self.pcap.clone());
+            self.sessions
+                .insert(*to, single_socket.sender(), worker_options.timeout);
+            self.largest_block_size
+                .fetch_max(worker_options.block_size, Ordering::Relaxed);
+
+            socket = Box::new(single_socket);
+        } else {
+            let multi_socket =
+                create_multi_socket(&self.socket().local_addr()?, to, self.dual_stack, self.dscp)?
+                    .with_pcap(self.pcap.clone());
+            resend_socket = Some(multi_socket.try_clone()?);
+            socket = Box::new(multi_socket);
+        }
+        let socket = record_session(self.session_dir.as_deref(), socket, &request, to);
+        let mut socket = count_transfer(self.metrics.as_ref(), socket, Direction::Read);
+
+        socket.set_read_timeout(worker_options.timeout)?;
+        socket.set_write_timeout(worker_options.timeout)?;
+
+        log::debug!("  Accepted options: {}", OptionFmt(options));
+
+        let reply = accept_request(&socket, options, RequestType::Read(size))?;
+        if let Some(handler) = &self.handler {
+            handler.on_accept(&info, options);
+        }
+        let filename = info.filename.clone();
+        let activity = Arc::new(Activity::new());
+        self.transfers.accept(&info, Some(size), activity.clone());
+
+        let mut worker = Worker::new(
+            socket,
+            file_path,
+            self.opt_local.clone(),
+            worker_options.clone(),
+        )
+        .with_netascii(info.netascii)
+        .with_activity(activity.clone());
+        if let Some(fs) = fs {
+            worker = worker.with_fs(fs);
+        }
+        if let Some(limiter) = self.client_limiter(to.ip()) {
+            worker = worker.with_rate_limiter(limiter);
+        }
+        if let Some(limiter) = &self.total_limiter {
+            worker = worker.with_rate_limiter(limiter.clone());
+        }
+        if let Some(handler) = &self.handler {
+            worker = worker.with_handler(handler.clone(), info.clone());
+        }
+        let job = worker.send_job(!options.is_empty());
+        let task = match &self.metrics {
+            Some(metrics) => self.pool.execute(metrics.track(job)),
+            None => self.pool.execute(job),
+        }
+        .inspect_err(|_| self.transfers.remove(&info))?;
+        self.sessions.started(to, task.clone());
+        self.requests.insert(
+            *to,
+            Request {
+                filename,
+                reply,
+                socket: resend_socket,
+                task: task.clone(),
+                activity,
+            },
+        );
+        self.workers.push(task);
+        Ok(())
+    }
+
+    /// Adds the client of `info` to the multicast group sending `stream`,
+    /// returning `false` if multicast is disabled or unsuitable, the file
+    /// being sent with unicast instead.
+    fn join_multicast(
+        &mut self,
+        stream: Stream,
+        info: &TransferInfo,
+        options: &[TransferOption],
+        worker_options: &OptionsProtocol,
+    ) -> anyhow::Result<bool> {
+        if self.multicast.is_none() {
+            log::info!("  Multicast is disabled, sending with unicast");
+            return Ok(false);
+        }
+        if info.netascii
+            || !info.peer.is_ipv4()
+            || worker_options.offset > 0
+            || !Multicast::supports(&stream)
+        {
+            log::info!("  Multicast is unsuitable for this transfer, sending with unicast");
+            return Ok(false);
+        }
+
+        // Groups send one block at a time, from the start of the file
+        let options: Vec<TransferOption> = options
+            .iter()
+            .filter(|option| {
+                !matches!(
+                    option.option,
+                    OptionType::WindowSize | OptionType::WindowWait | OptionType::Offset
+                )
+            })
+            .copied()
+            .collect();
+        if let Some(handler) = &self.handler {
+            handler.on_accept(info, &options);
+        }
+        let local = self.socket().local_addr()?;
+        let member = Member {
+            info: info.clone(),
+            options,
+        };
+        if let Some(multicast) = &mut self.multicast
+            && let Some(task) =
+                multicast.join(stream, member, local, &self.pool, self.handler.clone())?
+        {
+            self.workers.push(task);
+        }
+        Ok(true)
+    }
+
+    /// Returns the rate limiter shared by the transfers of `ip`, if limited.
+    fn client_limiter(&mut self, ip: IpAddr) -> Option<Arc<RateLimiter>> {
+        let rate = self.client_rate_limit?;
+        // Forget the clients without transfers in progress
+        self.client_limiters
+            .retain(|_, limiter| Arc::strong_count(limiter) > 1);
+        let limiter = self
+            .client_limiters
+            .entry(ip.to_canonical())
+            .or_insert_with(|| Arc::new(RateLimiter::new(rate)));
+        Some(limiter.clone())
     }
 
     fn handle_wrq(
         &mut self,
         filename: String,
+        netascii: bool,
         options: &mut [TransferOption],
+        custom_options: Vec<CustomOption>,
         to: &SocketAddr,
     ) -> anyhow::Result<()> {
-        let file_path = convert_file_path(&filename);
-        let file_path = &self.directory.join(file_path);
+        let info = TransferInfo {
+            peer: *to,
+            filename: filename.clone(),
+            direction: Direction::Write,
+            netascii,
+            custom_options,
+        };
+        if !self.allowed(&info)? {
+            return Ok(());
+        }
+
+        let name = normalize_name(&filename);
+        if self
+            .protected_paths
+            .iter()
+            .any(|pattern| glob_matches(pattern, &name))
+        {
+            log::warn!("Refused write request from {to}, {filename} is write-protected");
+            return self.send_error(
+                ErrorCode::AccessViolation,
+                "file is write-protected".to_string(),
+                to,
+            );
+        }
+
+        // Released if the upload is refused or fails before its end
+        let mut reservation = None;
+        if let Some(quota) = &self.quota {
+            let size = options
+                .iter()
+                .find(|opt| opt.option == OptionType::TransferSize)
+                .map_or(0, |opt| opt.value);
+            reservation = quota.reserve(size);
+            if reservation.is_none() {
+                log::warn!(
+                    "Refused write request from {to}, upload quota exceeded ({} bytes left)",
+                    quota.remaining()
+                );
+                return self.send_error(
+                    ErrorCode::DiskFull,
+                    "upload quota exceeded".to_string(),
+                    to,
+                );
+            }
+        }
+
+        let (file_path, status) = match &self.fs {
+            Some(fs) => {
+                let file_path = PathBuf::from(normalize_name(&filename));
+                let status = match fs.metadata(&file_path) {
+                    Ok(_) => ErrorCode::FileExists,
+                    Err(_) => ErrorCode::FileNotFound,
+                };
+                (file_path, status)
+            }
+            None => {
+                let (directory, name) = self.roots.resolve(&self.receive_directory, &filename);
+                let file_path = resolve_file_path(directory, &name, self.case_insensitive);
+                let status = check_file_exists(&file_path, directory, &self.symlinks);
+                (file_path, status)
+            }
+        };
+        if self.fs.is_none()
+            && let Some(manifest) = &self.manifest
+            && file_path == manifest.path()
+        {
+            log::warn!("Refused write request from {to}, the manifest is kept by the server");
+            return self.send_error(
+                ErrorCode::AccessViolation,
+                format!("{MANIFEST_FILENAME} is kept by the server"),
+                to,
+            );
+        }
+
+        // Existing files are kept, the upload written next to them to a
+        // version reserved before the next request is handled
+        let file_path = match (status, self.overwrite) {
+            (ErrorCode::FileExists, OverwritePolicy::RenameWithSuffix) => {
+                let fs = self.fs.as_deref().unwrap_or(&DiskFs);
+                let version = reserve_version(fs, &file_path)?;
+                log::info!(
+                    "  {} exists, writing upload to {}",
+                    file_path.display(),
+                    version.display()
+                );
+                version
+            }
+            _ => file_path,
+        };
+        let file_path = &file_path;
+        let listener = &self.sockets[self.listener];
         let initialize_write = &mut || -> anyhow::Result<()> {
-            let worker_options = OptionsProtocol::parse(options, RequestType::Write)?;
-            let mut socket: Box<dyn Socket>;
+            let request = Packet::Wrq {
+                filename: filename.clone(),
+                mode: transfer_mode(netascii),
+                options: options.to_vec(),
+                custom: info.custom_options.clone(),
+            };
+            let mut worker_options = OptionsProtocol::parse(options, RequestType::Write)?;
+            clamp_to_limits(options, &mut worker_options, &self.limits);
+            let socket: Box<dyn Socket>;
+            let mut resend_socket = None;
 
             if self.single_port {
-                let single_socket = create_single_socket(&self.socket, to, worker_options.timeout)?;
-                self.clients.insert(*to, single_socket.sender());
-                self.largest_block_size = max(self.largest_block_size, worker_options.block_size);
+                let single_socket = create_single_socket(listener, to, worker_options.timeout)?
+                    .with_pcap(self.pcap.clone());
+                self.sessions
+                    .insert(*to, single_socket.sender(), worker_options.timeout);
+                self.largest_block_size
+                    .fetch_max(worker_options.block_size, Ordering::Relaxed);
 
                 socket = Box::new(single_socket);
             } else {
-                socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
+                let multi_socket =
+                    create_multi_socket(&listener.local_addr()?, to, self.dual_stack, self.dscp)?
+                        .with_pcap(self.pcap.clone());
+                resend_socket = Some(multi_socket.try_clone()?);
+                socket = Box::new(multi_socket);
             }
+            let socket = record_session(self.session_dir.as_deref(), socket, &request, to);
+            let mut socket = count_transfer(self.metrics.as_ref(), socket, Direction::Write);
 
             socket.set_read_timeout(worker_options.timeout)?;
             socket.set_write_timeout(worker_options.timeout)?;
 
             log::debug!("  Accepted options: {}", OptionFmt(options));
-            accept_request(&socket, options, RequestType::Write)?;
+            let reply = accept_request(&socket, options, RequestType::Write)?;
+            if let Some(handler) = &self.handler {
+                handler.on_accept(&info, options);
+            }
+            let tsize = options
+                .iter()
+                .find(|option| option.option == OptionType::TransferSize)
+                .map(|option| option.value);
+            let activity = Arc::new(Activity::new());
+            self.transfers.accept(&info, tsize, activity.clone());
 
-            let worker = Worker::new(
+            let mut worker = Worker::new(
                 socket,
                 file_path.clone(),
                 self.opt_local.clone(),
                 worker_options.clone(),
+            )
+            .with_atomic_upload(self.atomic_uploads)
+            .with_versioning(self.overwrite == OverwritePolicy::KeepVersioned)
+            .with_netascii(netascii)
+            .with_activity(activity.clone());
+            if let Some(fs) = &self.fs {
+                worker = worker.with_fs(fs.clone());
+            } else if let Some(journal) = &self.journal {
+                worker = worker.with_journal(journal.clone());
+            }
+            if self.fs.is_none()
+                && let Some(manifest) = &self.manifest
+            {
+                worker = worker.with_manifest(manifest.clone());
+            }
+            if let Some(webhook) = &self.webhook {
+                worker = worker.with_webhook(webhook.clone());
+            }
+            if let Some(reservation) = reservation.take() {
+                worker = worker.with_quota(reservation);
+            }
+            if let Some(handler) = &self.handler {
+                worker = worker.with_handler(handler.clone(), info.clone());
+            }
+            let job = worker.receive_job();
+            let task = match &self.metrics {
+                Some(metrics) => self.pool.execute(metrics.track(job)),
+                None => self.pool.execute(job),
+            }
+            .inspect_err(|_| self.transfers.remove(&info))?;
+            self.sessions.started(to, task.clone());
+            self.requests.insert(
+                *to,
+                Request {
+                    filename: filename.clone(),
+                    reply,
+                    socket: resend_socket,
+                    task: task.clone(),
+                    activity,
+                },
             );
-            worker.receive()?;
+            self.workers.push(task);
             Ok(())
         };
 
-        match check_file_exists(file_path, &self.directory) {
+        match status {
             ErrorCode::FileExists => {
-                if self.overwrite {
-                    initialize_write()
+                if self.overwrite != OverwritePolicy::Reject {
+                    initialize_write().inspect_err(|_| {
+                        if self.overwrite == OverwritePolicy::RenameWithSuffix {
+                            let fs = self.fs.as_deref().unwrap_or(&DiskFs);
+                            let _ = fs.remove(file_path);
+                        }
+                    })
                 } else {
                     log::error!("File {} already exists", file_path.display());
-                    Socket::send_to(
-                        &self.socket,
-                        &Packet::Error {
-                            code: ErrorCode::FileExists,
-                            msg: "requested file already exists".to_string(),
-                        },
+                    self.send_error(
+                        ErrorCode::FileExists,
+                        "requested file already exists".to_string(),
                         to,
                     )
                 }
             }
             ErrorCode::AccessViolation => {
                 log::error!("Access violation detected for file {}", file_path.display());
-                Socket::send_to(
-                    &self.socket,
-                    &Packet::Error {
-                        code: ErrorCode::AccessViolation,
-                        msg: format!("file access violation: {}", file_path.display()),
-                    },
+                self.send_error(
+                    ErrorCode::AccessViolation,
+                    format!("file access violation: {}", file_path.display()),
                     to,
                 )
             }
@@ -304,14 +1685,74 @@ impl Server {
         }
     }
 
-    fn route_packet(&self, packet: Packet, to: &SocketAddr) -> anyhow::Result<()> {
-        if self.clients.contains_key(to) {
-            self.clients[to].send(packet)?;
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("No client found for packet"))
+    /// Flags the transfers without packets from their client for longer than
+    /// the idle timeout to be stopped, freeing their socket and thread.
+    fn reap_idle(&self) {
+        let Some(limit) = self.transfer_idle_timeout else {
+            return;
+        };
+        for (client, request) in &self.requests {
+            let idle = request.activity.idle();
+            if idle > limit && request.activity.reap() {
+                log::warn!(
+                    "Reaping transfer of {} with {client}, idle for {}s",
+                    request.filename,
+                    idle.as_secs()
+                );
+            }
+        }
+    }
+
+    /// Asks the handler whether to serve a request, replying with its error
+    /// if refused.
+    fn allowed(&self, info: &TransferInfo) -> anyhow::Result<bool> {
+        let Some(handler) = &self.handler else {
+            return Ok(true);
+        };
+
+        match handler.on_request(info) {
+            Ok(()) => Ok(true),
+            Err((code, msg)) => {
+                log::warn!("Request from {} refused: {msg}", info.peer);
+                self.send_error(code, msg, &info.peer)?;
+                Ok(false)
+            }
         }
     }
+
+    /// Returns the socket the packet being handled was received on.
+    fn socket(&self) -> &UdpSocket {
+        &self.sockets[self.listener]
+    }
+
+    /// Answers a malformed packet with the error code matching `err`.
+    fn refuse_malformed(&self, err: &PacketError, from: &SocketAddr) {
+        log::warn!("Received malformed packet from {from}: {err}");
+        if self
+            .send_error(err.error_code(), err.to_string(), from)
+            .is_err()
+        {
+            log::error!("Could not send error packet");
+        }
+    }
+
+    /// Sends an error packet to `to`, counted in the metrics.
+    fn send_error(&self, code: ErrorCode, msg: String, to: &SocketAddr) -> anyhow::Result<()> {
+        if let Some(metrics) = &self.metrics {
+            metrics.error_sent(code);
+        }
+        self.send_from_listener(&Packet::Error { code, msg }, to)
+    }
+
+    /// Sends `packet` to `to` from the listening socket, captured if set.
+    fn send_from_listener(&self, packet: &Packet, to: &SocketAddr) -> anyhow::Result<()> {
+        let datagram = packet.serialize()?;
+        self.socket().send_to(&datagram, to)?;
+        if let Some(pcap) = &self.pcap {
+            pcap.record(Flow::Sent, self.socket().local_addr()?, *to, &datagram);
+        }
+        Ok(())
+    }
 }
 
 pub fn convert_file_path(filename: &str) -> PathBuf {
@@ -332,6 +1773,157 @@ pub fn convert_file_path(filename: &str) -> PathBuf {
     PathBuf::from(normalized_filename)
 }
 
+/// Returns the directory transfers in one direction use, `path` if
+/// configured, otherwise the root `directory`.
+pub(super) fn transfer_directory(path: Option<&PathBuf>, directory: &Path) -> PathBuf {
+    match path {
+        Some(path) => std::fs::canonicalize(path).unwrap_or(path.clone()),
+        None => directory.to_path_buf(),
+    }
+}
+
+/// Returns the path of the requested `filename` below `directory`. With
+/// `case_insensitive`, each name not found as is matches an entry differing
+/// only in case, the first in sorted order if there are several.
+pub(super) fn resolve_file_path(
+    directory: &Path,
+    filename: &str,
+    case_insensitive: bool,
+) -> PathBuf {
+    let relative = convert_file_path(filename);
+    let file_path = directory.join(&relative);
+    if !case_insensitive || file_path.exists() {
+        return file_path;
+    }
+
+    let mut path = directory.to_path_buf();
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            path.push(component);
+            continue;
+        };
+        if path.join(name).exists() {
+            path.push(name);
+            continue;
+        }
+        let lowercase = name.to_string_lossy().to_lowercase();
+        let found = std::fs::read_dir(&path).ok().and_then(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name())
+                .filter(|entry| entry.to_string_lossy().to_lowercase() == lowercase)
+                .min()
+        });
+        path.push(found.as_deref().unwrap_or(name));
+    }
+    path
+}
+
+/// OptionLimits `struct` bounds the options a client may negotiate. Options
+/// beyond a limit are clamped and the clamped value answered in the OACK.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(super) struct OptionLimits {
+    pub max_block_size: Option<u16>,
+    pub max_window_size: Option<u16>,
+    /// Timeout in seconds if the client negotiates none
+    pub timeout: Option<u64>,
+    pub min_timeout: Option<u64>,
+    pub max_timeout: Option<u64>,
+}
+
+/// Drops the requested options the server never negotiates, as if the
+/// client had not requested them.
+pub(super) fn drop_disabled_options(options: &mut Vec<TransferOption>, disabled: &[OptionType]) {
+    options.retain(|option| {
+        let disabled = disabled.contains(&option.option);
+        if disabled {
+            log::debug!("  Ignoring disabled option {}", option.option.as_str());
+        }
+        !disabled
+    });
+}
+
+/// Clamps the requested options to `limits`, updating `worker_options`, or
+/// applies the timeout of `limits` if the client requested none.
+pub(super) fn clamp_to_limits(
+    options: &mut [TransferOption],
+    worker_options: &mut OptionsProtocol,
+    limits: &OptionLimits,
+) {
+    let clamp = |name: &str, value: &mut u64, min: Option<u64>, max: Option<u64>| {
+        let clamped = (*value).clamp(min.unwrap_or(0), max.unwrap_or(u64::MAX).max(1));
+        if clamped != *value {
+            log::info!("  {name} {value} is beyond the configured limits. Changed to {clamped}.");
+            *value = clamped;
+        }
+    };
+
+    for option in options.iter_mut() {
+        match option.option {
+            OptionType::BlockSize => {
+                let max = limits.max_block_size.map(u64::from);
+                clamp("Block size", &mut option.value, None, max);
+                worker_options.block_size = option.value as u16;
+            }
+            OptionType::WindowSize => {
+                let max = limits.max_window_size.map(u64::from);
+                clamp("Window size", &mut option.value, None, max);
+                worker_options.window_size = option.value as u16;
+            }
+            OptionType::Timeout => {
+                clamp(
+                    "Timeout",
+                    &mut option.value,
+                    limits.min_timeout,
+                    limits.max_timeout,
+                );
+                worker_options.timeout = Duration::from_secs(option.value);
+            }
+            OptionType::TimeoutMs => {
+                let to_ms = |secs: u64| secs.saturating_mul(1000);
+                clamp(
+                    "Timeout",
+                    &mut option.value,
+                    limits.min_timeout.map(to_ms),
+                    limits.max_timeout.map(to_ms),
+                );
+                worker_options.timeout = Duration::from_millis(option.value);
+            }
+            _ => {}
+        }
+    }
+
+    let negotiated = options
+        .iter()
+        .any(|option| matches!(option.option, OptionType::Timeout | OptionType::TimeoutMs));
+    if let Some(timeout) = limits.timeout
+        && !negotiated
+    {
+        worker_options.timeout = Duration::from_secs(timeout);
+    }
+}
+
+/// Lowers the negotiated block size if this host cannot send datagrams that large.
+pub(super) fn clamp_block_size(
+    options: &mut [TransferOption],
+    worker_options: &mut OptionsProtocol,
+) {
+    let block_size = max_block_size(worker_options.block_size);
+    if block_size < worker_options.block_size {
+        log::warn!(
+            "  Block size {} exceeds the largest datagram this host can send. Changed to {block_size}.",
+            worker_options.block_size
+        );
+        worker_options.block_size = block_size;
+        for option in options
+            .iter_mut()
+            .filter(|option| option.option == OptionType::BlockSize)
+        {
+            option.value = block_size as u64;
+        }
+    }
+}
+
 fn create_single_socket(
     socket: &UdpSocket,
     remote: &SocketAddr,
@@ -342,29 +1934,104 @@ fn create_single_socket(
     Ok(socket)
 }
 
-fn create_multi_socket(addr: &SocketAddr, remote: &SocketAddr) -> anyhow::Result<UdpSocket> {
-    let socket = UdpSocket::bind(SocketAddr::from((addr.ip(), 0)))?;
-    socket.connect(remote)?;
+/// Binds the socket of a transfer with `remote`. The socket is not connected,
+/// so that packets from other remotes are answered with an unknown transfer
+/// ID error rather than dropped.
+fn create_multi_socket(
+    addr: &SocketAddr,
+    remote: &SocketAddr,
+    dual_stack: bool,
+    dscp: Option<u8>,
+) -> anyhow::Result<PeerSocket> {
+    let socket = bind_udp(transfer_addr(*addr), dual_stack)?;
+    if let Some(dscp) = dscp {
+        set_dscp(&socket, dscp)?;
+    }
 
-    Ok(socket)
+    Ok(PeerSocket::new(socket, *remote))
+}
+
+/// Returns whether `mode` of a request is netascii, files being transferred
+/// as is in any other mode.
+pub(super) fn is_netascii(mode: &str) -> bool {
+    mode.eq_ignore_ascii_case("netascii")
+}
+
+/// Returns the mode of the requests of transfers, for recorded sessions.
+fn transfer_mode(netascii: bool) -> String {
+    if netascii { "netascii" } else { "octet" }.to_string()
+}
+
+/// Records the packets of the transfer started by `request` from `to` on
+/// `socket` to a new session file in `session_dir`, beginning with the
+/// request itself. Returns `socket` as is if sessions are not recorded.
+fn record_session(
+    session_dir: Option<&Path>,
+    socket: Box<dyn Socket>,
+    request: &Packet,
+    to: &SocketAddr,
+) -> Box<dyn Socket> {
+    let Some(session_dir) = session_dir else {
+        return socket;
+    };
+    let name = format!(
+        "{}-{}.session",
+        chrono::Local::now().format("%Y%m%dT%H%M%S%.3f"),
+        to.to_string().replace([':', '[', ']'], "_")
+    );
+    match SessionRecorder::create(&session_dir.join(name)) {
+        Ok(recorder) => {
+            recorder.record_packet(Flow::Received, Some(*to), request);
+            Box::new(RecordingSocket::new(socket, Arc::new(recorder)))
+        }
+        Err(err) => {
+            log::warn!("Could not record session of {to}: {err}");
+            socket
+        }
+    }
+}
+
+/// Counts the transfer in `direction` on `socket` in `metrics`. Returns
+/// `socket` as is if the server has no metrics.
+fn count_transfer(
+    metrics: Option<&Arc<Metrics>>,
+    socket: Box<dyn Socket>,
+    direction: Direction,
+) -> Box<dyn Socket> {
+    match metrics {
+        Some(metrics) => Box::new(MetricsSocket::new(socket, metrics.clone(), direction)),
+        None => socket,
+    }
 }
 
-fn accept_request<T: Socket>(
+/// Answers a request with an OACK of the accepted options, or an ACK if a
+/// write request has none, returning the packet sent.
+pub(super) fn accept_request<T: Socket + ?Sized>(
     socket: &T,
     options: &[TransferOption],
     request_type: RequestType,
-) -> anyhow::Result<()> {
-    if !options.is_empty() {
-        socket.send(&Packet::Oack(options.to_vec()))?;
+) -> anyhow::Result<Option<Packet>> {
+    let reply = if !options.is_empty() {
+        Packet::Oack {
+            options: options.to_vec(),
+            custom: vec![],
+        }
     } else if request_type == RequestType::Write {
-        socket.send(&Packet::Ack(0))?;
-    }
+        Packet::Ack(0)
+    } else {
+        return Ok(None);
+    };
+    socket.send(&reply)?;
 
-    Ok(())
+    Ok(Some(reply))
 }
 
-fn check_file_exists(file: &Path, directory: &PathBuf) -> ErrorCode {
-    if !validate_file_path(file, directory) {
+pub(super) fn check_file_exists(
+    file: &Path,
+    directory: &PathBuf,
+    symlinks: &Symlinks,
+) -> ErrorCode {
+    if !validate_file_path(file, directory) || !symlinks.permits(file, directory) {
         return ErrorCode::AccessViolation;
     }
 
@@ -419,6 +2086,24 @@ mod tests {
         assert_eq!(path, correct_path);
     }
 
+    #[test]
+    fn resolves_file_path_ignoring_case() {
+        let root = PathBuf::from("target/test/resolves_file_path_ignoring_case");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("EFI/boot")).unwrap();
+        std::fs::write(root.join("EFI/boot/bootx64.efi"), b"").unwrap();
+
+        let resolved = resolve_file_path(&root, "/efi/BOOT/BOOTX64.EFI", true);
+        assert_eq!(resolved, root.join("EFI/boot/bootx64.efi"));
+        // Missing names are kept as requested, below the matched directories
+        let resolved = resolve_file_path(&root, "efi/BOOT/grubx64.efi", true);
+        assert_eq!(resolved, root.join("EFI/boot/grubx64.efi"));
+        let resolved = resolve_file_path(&root, "efi/BOOT/BOOTX64.EFI", false);
+        assert_eq!(resolved, root.join("efi/BOOT/BOOTX64.EFI"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
     #[test]
     fn validates_file_path() {
         assert!(validate_file_path(
@@ -494,6 +2179,46 @@ mod tests {
         assert_eq!(options[2].value, worker_options.timeout.as_secs());
     }
 
+    #[test]
+    fn clamps_options_to_limits() {
+        let mut options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 8192,
+            },
+            TransferOption {
+                option: OptionType::WindowSize,
+                value: 4,
+            },
+            TransferOption {
+                option: OptionType::TimeoutMs,
+                value: 100,
+            },
+        ];
+        let mut worker_options = OptionsProtocol::parse(&mut options, RequestType::Write).unwrap();
+        let limits = OptionLimits {
+            max_block_size: Some(1468),
+            max_window_size: Some(8),
+            timeout: Some(3),
+            min_timeout: Some(1),
+            max_timeout: Some(10),
+        };
+
+        clamp_to_limits(&mut options, &mut worker_options, &limits);
+
+        assert_eq!(options[0].value, 1468);
+        assert_eq!(worker_options.block_size, 1468);
+        assert_eq!(options[1].value, 4);
+        assert_eq!(worker_options.window_size, 4);
+        assert_eq!(options[2].value, 1000);
+        assert_eq!(worker_options.timeout, Duration::from_secs(1));
+
+        // The configured timeout applies when the client negotiates none
+        let mut worker_options = OptionsProtocol::default();
+        clamp_to_limits(&mut [], &mut worker_options, &limits);
+        assert_eq!(worker_options.timeout, Duration::from_secs(3));
+    }
+
     #[test]
     fn parses_default_options() {
         assert_eq!(
@@ -0,0 +1,374 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::Server;
+
+/// Name the server is registered under with the service control manager
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const SERVICE_NAME: &str = "xtool-tftpd";
+
+/// ServiceAction `enum` is the `--service` mode of `xtool tftpd`, running
+/// the server as a native Windows service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ServiceAction {
+    /// Register the server, with the other arguments given, to start with
+    /// Windows
+    Install,
+    /// Stop and unregister the server
+    Uninstall,
+    /// Run as a service, only meant to be started by the service control
+    /// manager
+    Run,
+}
+
+/// Performs `action`, `start` creating the server once the service runs.
+///
+/// The service is started from the directory it was installed in, so that
+/// relative paths and `.xtool.toml` are found as they were at install time.
+#[cfg(windows)]
+pub fn run<F>(action: ServiceAction, start: F) -> Result<()>
+where
+    F: FnOnce() -> Result<Server> + Send + 'static,
+{
+    match action {
+        ServiceAction::Install => {
+            let dir = std::env::current_dir()?;
+            let exe = std::env::current_exe()?;
+            let args = service_args(std::env::args_os().skip(1), &dir);
+            let command_line = std::iter::once(exe.into_os_string())
+                .chain(args)
+                .map(|arg| quote(&arg.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            windows::install(&command_line)?;
+            log::info!("Installed the {SERVICE_NAME} service, started with Windows");
+            Ok(())
+        }
+        ServiceAction::Uninstall => {
+            windows::uninstall()?;
+            log::info!("Uninstalled the {SERVICE_NAME} service");
+            Ok(())
+        }
+        ServiceAction::Run => windows::run(Box::new(start)),
+    }
+}
+
+/// Services are Windows only.
+#[cfg(not(windows))]
+pub fn run<F>(_action: ServiceAction, _start: F) -> Result<()>
+where
+    F: FnOnce() -> Result<Server> + Send + 'static,
+{
+    Err(anyhow::anyhow!(
+        "--service is only supported on Windows, use a systemd unit or an init script instead"
+    ))
+}
+
+/// Returns the arguments the installed service is started with: those of
+/// `xtool tftpd --service install`, running the service from `dir` instead.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn service_args(args: impl Iterator<Item = OsString>, dir: &Path) -> Vec<OsString> {
+    let mut rewritten = Vec::new();
+    let mut action_follows = false;
+    for arg in args {
+        if action_follows {
+            rewritten.push(OsString::from("run"));
+            action_follows = false;
+        } else if arg == "--service" {
+            rewritten.push(arg);
+            action_follows = true;
+        } else if arg.to_string_lossy().starts_with("--service=") {
+            rewritten.push(OsString::from("--service=run"));
+        } else {
+            rewritten.push(arg);
+        }
+    }
+    rewritten.push(OsString::from("--service-dir"));
+    rewritten.push(dir.as_os_str().to_os_string());
+    rewritten
+}
+
+/// Quotes `arg` for a Windows command line, unless it needs none.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote are escaped, as is the quote
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    // Backslashes before the closing quote are escaped
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::{OsStr, c_void};
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::{null, null_mut};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    use anyhow::Result;
+    use windows_sys::Win32::Foundation::{
+        ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_NOT_ACTIVE, ERROR_SERVICE_SPECIFIC_ERROR,
+        NO_ERROR,
+    };
+    use windows_sys::Win32::System::Services::*;
+
+    use super::super::{Server, ShutdownHandle};
+    use super::SERVICE_NAME;
+
+    type Start = Box<dyn FnOnce() -> Result<Server> + Send>;
+
+    /// State shared with the callbacks of the service control manager
+    static START: Mutex<Option<Start>> = Mutex::new(None);
+    static SHUTDOWN: Mutex<Option<ShutdownHandle>> = Mutex::new(None);
+    static STATUS: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+    /// Closes a handle of the service control manager once dropped
+    struct ScHandle(SC_HANDLE);
+
+    impl ScHandle {
+        fn new(handle: SC_HANDLE, what: &str) -> Result<ScHandle> {
+            if handle.is_null() {
+                let err = std::io::Error::last_os_error();
+                return Err(anyhow::anyhow!("Cannot open {what}: {err}"));
+            }
+            Ok(ScHandle(handle))
+        }
+    }
+
+    impl Drop for ScHandle {
+        fn drop(&mut self) {
+            unsafe { CloseServiceHandle(self.0) };
+        }
+    }
+
+    fn wide(s: impl AsRef<OsStr>) -> Vec<u16> {
+        s.as_ref().encode_wide().chain(Some(0)).collect()
+    }
+
+    fn manager() -> Result<ScHandle> {
+        let handle = unsafe { OpenSCManagerW(null(), null(), SC_MANAGER_ALL_ACCESS) };
+        ScHandle::new(handle, "the service control manager, run as Administrator")
+    }
+
+    pub(super) fn install(command_line: &str) -> Result<()> {
+        let manager = manager()?;
+        let name = wide(SERVICE_NAME);
+        let service = unsafe {
+            CreateServiceW(
+                manager.0,
+                name.as_ptr(),
+                wide("xtool TFTP server").as_ptr(),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                wide(command_line).as_ptr(),
+                null(),
+                null_mut(),
+                null(),
+                null(),
+                null(),
+            )
+        };
+        let service = ScHandle::new(service, "a new service")?;
+
+        let mut description = wide("Serves files over TFTP, e.g. to boot machines over PXE");
+        let info = SERVICE_DESCRIPTIONW {
+            lpDescription: description.as_mut_ptr(),
+        };
+        unsafe {
+            ChangeServiceConfig2W(
+                service.0,
+                SERVICE_CONFIG_DESCRIPTION,
+                &info as *const _ as *const c_void,
+            )
+        };
+        Ok(())
+    }
+
+    pub(super) fn uninstall() -> Result<()> {
+        let manager = manager()?;
+        let name = wide(SERVICE_NAME);
+        let service = unsafe { OpenServiceW(manager.0, name.as_ptr(), SERVICE_ALL_ACCESS) };
+        let service = ScHandle::new(service, &format!("the {SERVICE_NAME} service"))?;
+
+        let mut status: SERVICE_STATUS = unsafe { std::mem::zeroed() };
+        if unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) } == 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(ERROR_SERVICE_NOT_ACTIVE as i32) {
+                log::warn!("Cannot stop the {SERVICE_NAME} service: {err}");
+            }
+        }
+        if unsafe { DeleteService(service.0) } == 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(anyhow::anyhow!(
+                "Cannot delete the {SERVICE_NAME} service: {err}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Hands the process over to the service control manager, which calls
+    /// [`service_main()`] on another thread, until the service stops.
+    pub(super) fn run(start: Start) -> Result<()> {
+        *START.lock().unwrap() = Some(start);
+        let mut name = wide(SERVICE_NAME);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: null_mut(),
+                lpServiceProc: None,
+            },
+        ];
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(anyhow::anyhow!(
+                "Cannot connect to the service control manager, --service run is only \
+                 started by Windows: {err}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_status(state: u32, exit_code: u32) {
+        let accepted = if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        };
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: accepted,
+            dwWin32ExitCode: if exit_code == 0 {
+                NO_ERROR
+            } else {
+                ERROR_SERVICE_SPECIFIC_ERROR
+            },
+            dwServiceSpecificExitCode: exit_code,
+            dwCheckPoint: 0,
+            dwWaitHint: if state == SERVICE_STOP_PENDING {
+                15_000
+            } else {
+                0
+            },
+        };
+        unsafe { SetServiceStatus(STATUS.load(Ordering::SeqCst), &status) };
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+        let name = wide(SERVICE_NAME);
+        let status = unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control), null()) };
+        if status.is_null() {
+            log::error!(
+                "Cannot register the service control handler: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        STATUS.store(status, Ordering::SeqCst);
+        set_status(SERVICE_START_PENDING, 0);
+
+        let Some(start) = START.lock().unwrap().take() else {
+            set_status(SERVICE_STOPPED, 1);
+            return;
+        };
+        let mut server = match start() {
+            Ok(server) => server,
+            Err(err) => {
+                log::error!("Cannot start the TFTP server: {err:#}");
+                set_status(SERVICE_STOPPED, 1);
+                return;
+            }
+        };
+        *SHUTDOWN.lock().unwrap() = Some(server.shutdown_handle());
+        set_status(SERVICE_RUNNING, 0);
+        log::info!("TFTP server listening as the {SERVICE_NAME} service");
+        server.listen();
+        set_status(SERVICE_STOPPED, 0);
+    }
+
+    unsafe extern "system" fn control(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_status(SERVICE_STOP_PENDING, 0);
+                // Stopping blocks until the transfers end, the handler must return at once
+                if let Some(handle) = SHUTDOWN.lock().unwrap().clone() {
+                    std::thread::spawn(move || handle.shutdown());
+                }
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_the_installed_arguments() {
+        let args = ["tftpd", "-p", "6969", "--service", "install", "boot files"];
+        let rewritten = service_args(args.into_iter().map(OsString::from), Path::new("C:\\tftp"));
+        assert_eq!(
+            rewritten,
+            [
+                "tftpd",
+                "-p",
+                "6969",
+                "--service",
+                "run",
+                "boot files",
+                "--service-dir",
+                "C:\\tftp"
+            ]
+        );
+
+        let args = ["tftpd", "--service=install", "."];
+        let rewritten = service_args(args.into_iter().map(OsString::from), Path::new("D:\\"));
+        assert_eq!(rewritten[1], "--service=run");
+    }
+
+    #[test]
+    fn quotes_command_line_arguments() {
+        assert_eq!(quote("tftpd"), "tftpd");
+        assert_eq!(quote("C:\\boot files"), "\"C:\\boot files\"");
+        assert_eq!(quote("C:\\boot files\\"), "\"C:\\boot files\\\\\"");
+        assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::tftp::core::Packet;
+
+use super::pool::Task;
+
+/// Time late packets of a session are expected beyond its timeout, during
+/// which uploads dally
+const LATE_PACKETS: Duration = Duration::from_secs(5);
+
+/// Sessions `struct` routes the packets received on the port of a server in
+/// single port mode to the transfers they belong to, keyed by client IP and
+/// port, as clients behind a NAT or in a container only reach that port.
+///
+/// A session lingers once its transfer ended, for its timeout and a few more
+/// seconds, so that retransmissions reach the worker dallying on it and late
+/// packets are dropped instead of answered with an error. It is forgotten
+/// afterwards, as is a session whose transfer never started.
+#[derive(Default)]
+pub(super) struct Sessions {
+    sessions: HashMap<SocketAddr, Session>,
+}
+
+struct Session {
+    sender: Sender<Packet>,
+    timeout: Duration,
+    /// Worker of the transfer, once started
+    task: Option<Task>,
+    created: Instant,
+    ended: Option<Instant>,
+}
+
+/// Route `enum` tells what became of a packet handed to [`Sessions`].
+#[derive(Debug, PartialEq)]
+pub(super) enum Route {
+    /// Handed to the worker of the session
+    Delivered,
+    /// Dropped, the transfer of the session having ended
+    Late,
+    /// No session of the client
+    Unknown,
+}
+
+impl Sessions {
+    /// Adds the session of `client`, whose packets are sent to `sender`,
+    /// replacing a previous one. Its transfer times out after `timeout`.
+    pub fn insert(&mut self, client: SocketAddr, sender: Sender<Packet>, timeout: Duration) {
+        self.sessions.insert(
+            client,
+            Session {
+                sender,
+                timeout,
+                task: None,
+                created: Instant::now(),
+                ended: None,
+            },
+        );
+    }
+
+    /// Records `task` as the worker of the session of `client`, so that the
+    /// session expires once it is finished.
+    pub fn started(&mut self, client: &SocketAddr, task: Task) {
+        if let Some(session) = self.sessions.get_mut(client) {
+            session.task = Some(task);
+        }
+    }
+
+    /// Hands `packet` from `from` to the worker of its session.
+    pub fn route(&mut self, packet: Packet, from: &SocketAddr) -> Route {
+        let Some(session) = self.sessions.get_mut(from) else {
+            return Route::Unknown;
+        };
+        if session.sender.send(packet).is_ok() {
+            return Route::Delivered;
+        }
+        session.ended.get_or_insert_with(Instant::now);
+        Route::Late
+    }
+
+    /// Forgets the sessions ended for longer than their timeout and the
+    /// time late packets are expected.
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.sessions.retain(|_, session| {
+            if session.ended.is_none() && session.task.as_ref().is_some_and(Task::is_finished) {
+                session.ended = Some(now);
+            }
+            let linger = session.timeout + LATE_PACKETS;
+            match (&session.task, session.ended) {
+                (_, Some(ended)) => now.duration_since(ended) < linger,
+                (None, None) => now.duration_since(session.created) < linger,
+                (Some(_), None) => true,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::server::pool::ThreadPool;
+    use std::sync::mpsc;
+
+    #[test]
+    fn routes_and_expires_sessions() {
+        let mut sessions = Sessions::default();
+        let pool = ThreadPool::new(1, 1, 0, Duration::from_secs(1));
+        let client: SocketAddr = "192.168.1.20:1069".parse().unwrap();
+        let other: SocketAddr = "192.168.1.20:1070".parse().unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        sessions.insert(client, sender, Duration::ZERO);
+        assert_eq!(sessions.route(Packet::Ack(1), &client), Route::Delivered);
+        assert_eq!(receiver.try_recv().unwrap(), Packet::Ack(1));
+        // Sessions are told apart by port
+        assert_eq!(sessions.route(Packet::Ack(1), &other), Route::Unknown);
+
+        let task = pool.execute(move || drop(receiver)).unwrap();
+        sessions.started(&client, task.clone());
+        while !task.is_finished() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(sessions.route(Packet::Ack(2), &client), Route::Late);
+        sessions.expire();
+        assert_eq!(sessions.sessions.len(), 1);
+
+        // Ended long enough ago
+        sessions.sessions.get_mut(&client).unwrap().ended =
+            Instant::now().checked_sub(LATE_PACKETS);
+        sessions.expire();
+        assert_eq!(sessions.sessions.len(), 0);
+        assert_eq!(sessions.route(Packet::Ack(3), &client), Route::Unknown);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::activity::Activity;
+use super::handler::{Direction, ServerHandler, TransferInfo};
+
+/// TransferStatus `struct` is a snapshot of a transfer in progress, as
+/// returned by [`Server::sessions()`](super::Server::sessions).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferStatus {
+    /// Identifier of the transfer, for [`Server::abort()`](super::Server::abort)
+    pub id: u64,
+    /// Address of the client
+    pub client: SocketAddr,
+    /// File name as requested by the client
+    pub filename: String,
+    pub direction: Direction,
+    /// Bytes sent and acknowledged, or received
+    pub bytes: u64,
+    /// Size of the file, unknown for uploads without a `tsize` option
+    pub size: Option<u64>,
+    /// Time since the transfer was accepted
+    pub elapsed: Duration,
+}
+
+#[allow(dead_code)]
+impl TransferStatus {
+    /// Returns the percentage of the file transferred, if its size is known.
+    pub fn progress(&self) -> Option<f64> {
+        self.size.map(|size| match size {
+            0 => 100.0,
+            size => (self.bytes as f64 * 100.0 / size as f64).min(100.0),
+        })
+    }
+}
+
+/// SessionsHandle `struct` lists and aborts the transfers of a [`Server`]
+/// in progress from another thread while it listens.
+///
+/// [`Server`]: super::Server
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::PathBuf;
+/// use std::thread;
+/// use xtool::tftp::server::{Config, Server};
+///
+/// let config = Config::default().merge_cli(
+///     "127.0.0.1".to_string(),
+///     0,
+///     PathBuf::from("/tmp/tftp"),
+///     false,
+///     false,
+/// );
+/// let mut server = Server::new(&config).unwrap();
+/// let handle = server.sessions_handle();
+/// thread::spawn(move || server.listen());
+///
+/// for session in handle.sessions() {
+///     println!("{} {} {:?}%", session.client, session.filename, session.progress());
+///     if session.filename.ends_with(".iso") {
+///         handle.abort(session.id);
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SessionsHandle {
+    transfers: Arc<Transfers>,
+}
+
+#[allow(dead_code)]
+impl SessionsHandle {
+    /// Returns the transfers in progress, ordered by client.
+    pub fn sessions(&self) -> Vec<TransferStatus> {
+        self.transfers.list()
+    }
+
+    /// Aborts the transfer `id`, see [`Server::abort()`](super::Server::abort).
+    pub fn abort(&self, id: u64) -> bool {
+        self.transfers.abort(id)
+    }
+}
+
+/// Transfers `struct` tracks the transfers in progress of a server, from
+/// their acceptance until they complete or fail.
+#[derive(Debug, Default)]
+pub(super) struct Transfers {
+    transfers: Mutex<HashMap<TransferInfo, Progress>>,
+    last_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Progress {
+    id: u64,
+    activity: Arc<Activity>,
+    accepted: Instant,
+    bytes: u64,
+    size: Option<u64>,
+}
+
+impl Transfers {
+    /// Records the accepted transfer `info`, of a file of `size` bytes if
+    /// known, stopped through `activity`.
+    pub fn accept(&self, info: &TransferInfo, size: Option<u64>, activity: Arc<Activity>) {
+        let progress = Progress {
+            id: self.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            activity,
+            accepted: Instant::now(),
+            bytes: 0,
+            size,
+        };
+        self.transfers
+            .lock()
+            .unwrap()
+            .insert(info.clone(), progress);
+    }
+
+    /// Forgets the transfer `info`, e.g. once it could not be started.
+    pub fn remove(&self, info: &TransferInfo) {
+        self.transfers.lock().unwrap().remove(info);
+    }
+
+    /// Flags the transfer `id` to be aborted. Returns `false` if it is not
+    /// in progress or already aborted.
+    pub fn abort(&self, id: u64) -> bool {
+        let transfers = self.transfers.lock().unwrap();
+        let Some((info, progress)) = transfers.iter().find(|(_, progress)| progress.id == id)
+        else {
+            return false;
+        };
+        if !progress.activity.abort() {
+            return false;
+        }
+        log::warn!("Aborting transfer of {} with {}", info.filename, info.peer);
+        true
+    }
+
+    pub fn handle(self: &Arc<Self>) -> SessionsHandle {
+        SessionsHandle {
+            transfers: self.clone(),
+        }
+    }
+
+    pub fn list(&self) -> Vec<TransferStatus> {
+        let mut sessions: Vec<TransferStatus> = self
+            .transfers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(info, progress)| TransferStatus {
+                id: progress.id,
+                client: info.peer,
+                filename: info.filename.clone(),
+                direction: info.direction,
+                bytes: progress.bytes,
+                size: progress.size,
+                elapsed: progress.accepted.elapsed(),
+            })
+            .collect();
+        sessions.sort_by(|a, b| (a.client, &a.filename).cmp(&(b.client, &b.filename)));
+        sessions
+    }
+}
+
+impl ServerHandler for Transfers {
+    fn on_block(&self, transfer: &TransferInfo, _block: u16, bytes: u64) {
+        if let Some(progress) = self.transfers.lock().unwrap().get_mut(transfer) {
+            progress.bytes = bytes;
+        }
+    }
+
+    fn on_complete(&self, transfer: &TransferInfo, _bytes: u64) {
+        self.remove(transfer);
+    }
+
+    fn on_error(&self, transfer: &TransferInfo, _error: &anyhow::Error) {
+        self.remove(transfer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_transfers_in_progress() {
+        let transfers = Arc::new(Transfers::default());
+        let handle = transfers.handle();
+        let transfer = |port: u16, direction| TransferInfo {
+            peer: SocketAddr::from(([192, 168, 1, 20], port)),
+            filename: "zImage".to_string(),
+            direction,
+            netascii: false,
+            custom_options: vec![],
+        };
+        let (download, upload) = (
+            transfer(1070, Direction::Read),
+            transfer(1069, Direction::Write),
+        );
+
+        let activity = Arc::new(Activity::new());
+        transfers.accept(&download, Some(4096), activity.clone());
+        transfers.accept(&upload, None, Arc::new(Activity::new()));
+        transfers.on_block(&download, 2, 1024);
+        let sessions = handle.sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].client.port(), 1069);
+        assert_eq!(sessions[0].progress(), None);
+        assert_eq!(sessions[1].bytes, 1024);
+        assert_eq!(sessions[1].progress(), Some(25.0));
+
+        assert!(handle.abort(sessions[1].id));
+        assert!(activity.is_aborted());
+        assert!(!handle.abort(sessions[1].id));
+        assert!(!handle.abort(0));
+
+        transfers.on_complete(&download, 4096);
+        transfers.on_error(&upload, &anyhow::anyhow!("Transfer timed out"));
+        assert!(handle.sessions().is_empty());
+    }
+}
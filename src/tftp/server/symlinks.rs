@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// SymlinkPolicy `enum` tells whether requested paths are served when their
+/// symbolic links lead out of the served directory.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd]
+/// symlinks = "allowlist"
+/// symlink_allowlist = ["/srv/images"]
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Follow links wherever they lead
+    #[default]
+    Follow,
+    /// Refuse the paths leading out of the served directory with an access
+    /// violation error
+    Refuse,
+    /// Follow the links leading into one of the allowlisted directories
+    /// only, refusing the others
+    Allowlist,
+}
+
+/// Symlinks `struct` checks the canonical paths of the requested files
+/// against the served directory, as its [`SymlinkPolicy`] tells.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Symlinks {
+    policy: SymlinkPolicy,
+    /// Canonical allowlisted directories
+    allowlist: Vec<PathBuf>,
+}
+
+impl Symlinks {
+    pub fn new(policy: SymlinkPolicy, allowlist: &[PathBuf]) -> Symlinks {
+        Symlinks {
+            policy,
+            allowlist: allowlist.iter().map(|path| canonicalize(path)).collect(),
+        }
+    }
+
+    /// Returns whether `file` may be served from `directory`, `file` being
+    /// resolved through its symbolic links first.
+    pub fn permits(&self, file: &Path, directory: &Path) -> bool {
+        if self.policy == SymlinkPolicy::Follow {
+            return true;
+        }
+        let file = canonicalize(file);
+        if file.starts_with(canonicalize(directory)) {
+            return true;
+        }
+        self.policy == SymlinkPolicy::Allowlist
+            && self
+                .allowlist
+                .iter()
+                .any(|allowed| file.starts_with(allowed))
+    }
+}
+
+/// Returns the canonical form of `path`. Components that do not exist yet,
+/// e.g. the file of an upload, are appended as is to the canonical form of
+/// the deepest existing ancestor.
+fn canonicalize(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut ancestor = path;
+    loop {
+        if let Ok(mut canonical) = std::fs::canonicalize(ancestor) {
+            canonical.extend(missing.iter().rev());
+            return canonical;
+        }
+        match (ancestor.file_name(), ancestor.parent()) {
+            (Some(name), Some(parent)) => {
+                missing.push(name);
+                ancestor = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn checks_links_leaving_the_root() {
+        let base = std::env::current_dir()
+            .unwrap()
+            .join("target/test/checks_links_leaving_the_root");
+        let _ = std::fs::remove_dir_all(&base);
+        let (root, images, secrets) =
+            (base.join("root"), base.join("images"), base.join("secrets"));
+        for dir in [&root, &images, &secrets] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::write(images.join("linux.img"), b"").unwrap();
+        std::fs::write(secrets.join("shadow"), b"").unwrap();
+        std::os::unix::fs::symlink(&images, root.join("images")).unwrap();
+        std::os::unix::fs::symlink(secrets.join("shadow"), root.join("shadow")).unwrap();
+        std::fs::create_dir(root.join("boot")).unwrap();
+
+        let image = root.join("images/linux.img");
+        let shadow = root.join("shadow");
+        let upload = root.join("boot/new.cfg");
+
+        let follow = Symlinks::default();
+        assert!(follow.permits(&shadow, &root));
+
+        let refuse = Symlinks::new(SymlinkPolicy::Refuse, &[]);
+        assert!(!refuse.permits(&image, &root));
+        assert!(!refuse.permits(&shadow, &root));
+        assert!(refuse.permits(&upload, &root));
+        assert!(!refuse.permits(&root.join("images/new.img"), &root));
+
+        let allowlist = Symlinks::new(SymlinkPolicy::Allowlist, &[images]);
+        assert!(allowlist.permits(&image, &root));
+        assert!(!allowlist.permits(&shadow, &root));
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+
+use super::provider::{FileProvider, normalize_name, skip};
+
+const BLOCK_SIZE: u64 = 512;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Largest GNU long name or PAX header read in memory
+const MAX_HEADER_DATA: u64 = 1024 * 1024;
+
+const TYPE_REGULAR: u8 = b'0';
+const TYPE_REGULAR_OLD: u8 = 0;
+const TYPE_CONTIGUOUS: u8 = b'7';
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+const TYPE_PAX: u8 = b'x';
+
+/// Location of a file in the uncompressed archive
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TarEntry {
+    offset: u64,
+    size: u64,
+}
+
+/// TarArchive `struct` serves the files of a `.tar` archive without
+/// extracting it, such as the artifact bundle of a CI pipeline. The archive
+/// is scanned once when opened, and the location of each file kept in
+/// memory.
+///
+/// Archives compressed with gzip (`.tar.gz`, `.tgz`) are supported too.
+/// A gzip stream cannot be seeked, so each request decompresses the archive
+/// from its start up to the requested file.
+///
+/// Regular files are served, in the ustar, GNU and PAX formats, while links
+/// and other special entries are skipped.
+pub struct TarArchive {
+    path: PathBuf,
+    gzip: bool,
+    entries: HashMap<String, TarEntry>,
+}
+
+impl TarArchive {
+    /// Opens the archive at `path` and caches the location of its files.
+    pub fn open(path: &Path) -> anyhow::Result<TarArchive> {
+        let mut file = File::open(path)?;
+        let mut magic = [0; 2];
+        let gzip = file.read(&mut magic)? == magic.len() && magic == GZIP_MAGIC;
+        file.rewind()?;
+
+        let entries = if gzip {
+            read_entries(&mut MultiGzDecoder::new(file))?
+        } else {
+            read_entries(&mut file)?
+        };
+        log::info!("Serving {} files from {}", entries.len(), path.display());
+
+        Ok(TarArchive {
+            path: path.to_path_buf(),
+            gzip,
+            entries,
+        })
+    }
+
+    fn entry(&self, name: &str) -> io::Result<TarEntry> {
+        self.entries
+            .get(&normalize_name(name))
+            .copied()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+impl FileProvider for TarArchive {
+    fn size(&self, name: &str) -> io::Result<u64> {
+        Ok(self.entry(name)?.size)
+    }
+
+    fn open(&self, name: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let entry = self.entry(name)?;
+        let offset = offset.min(entry.size);
+
+        let mut file = File::open(&self.path)?;
+        if self.gzip {
+            let mut reader = MultiGzDecoder::new(file);
+            skip(&mut reader, entry.offset + offset)?;
+            Ok(Box::new(reader.take(entry.size - offset)))
+        } else {
+            file.seek(SeekFrom::Start(entry.offset + offset))?;
+            Ok(Box::new(file.take(entry.size - offset)))
+        }
+    }
+}
+
+/// Stream `trait` reads an archive, skipping the content of its files by
+/// seeking when it can.
+trait Stream: Read {
+    fn skip_bytes(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl Stream for File {
+    fn skip_bytes(&mut self, len: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Current(len as i64))?;
+        Ok(())
+    }
+}
+
+impl Stream for MultiGzDecoder<File> {
+    fn skip_bytes(&mut self, len: u64) -> io::Result<()> {
+        skip(self, len)
+    }
+}
+
+fn read_entries(stream: &mut impl Stream) -> anyhow::Result<HashMap<String, TarEntry>> {
+    let mut entries = HashMap::new();
+    let mut header = [0; BLOCK_SIZE as usize];
+    let mut pos = 0;
+    // Name and size of the next entry, from a GNU long name or PAX header
+    let mut long_name = None;
+    let mut pax_size = None;
+
+    loop {
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers leave out the end of archive blocks
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && pos > 0 => break,
+            Err(e) => return Err(e.into()),
+        }
+        pos += BLOCK_SIZE;
+        if header.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        if !is_valid_header(&header) {
+            anyhow::bail!("invalid tar header at offset {}", pos - BLOCK_SIZE);
+        }
+
+        let size = match pax_size.take() {
+            Some(size) => size,
+            None => parse_number(&header[124..136])?,
+        };
+        let padded = size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        match header[156] {
+            TYPE_GNU_LONG_NAME | TYPE_PAX => {
+                if size > MAX_HEADER_DATA {
+                    anyhow::bail!("extended tar header of {size} bytes is too large");
+                }
+                let mut data = vec![0; size as usize];
+                stream.read_exact(&mut data)?;
+                stream.skip_bytes(padded - size)?;
+                if header[156] == TYPE_GNU_LONG_NAME {
+                    long_name = Some(c_string(&data));
+                } else {
+                    for (key, value) in pax_records(&data) {
+                        match key {
+                            "path" => long_name = Some(value.to_string()),
+                            "size" => pax_size = value.parse().ok(),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            TYPE_REGULAR | TYPE_REGULAR_OLD | TYPE_CONTIGUOUS => {
+                let name = long_name.take().unwrap_or_else(|| header_name(&header));
+                entries.insert(normalize_name(&name), TarEntry { offset: pos, size });
+                stream.skip_bytes(padded)?;
+            }
+            // Directories, links, devices and global headers
+            _ => {
+                long_name = None;
+                stream.skip_bytes(padded)?;
+            }
+        }
+        pos += padded;
+    }
+
+    Ok(entries)
+}
+
+/// Returns whether the checksum of `header` matches, computed with its own
+/// field as spaces.
+fn is_valid_header(header: &[u8]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as u64)
+        .sum();
+    parse_number(&header[148..156]).is_ok_and(|checksum| checksum == sum)
+}
+
+/// Returns the name of the entry of `header`, joined to its ustar prefix.
+fn header_name(header: &[u8]) -> String {
+    let name = c_string(&header[..100]);
+    let prefix = c_string(&header[345..500]);
+    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+        format!("{prefix}/{name}")
+    } else {
+        name
+    }
+}
+
+/// Parses a numeric field, in octal or in the base-256 of GNU tar for
+/// values too large for octal.
+fn parse_number(field: &[u8]) -> anyhow::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..]
+            .iter()
+            .fold((field[0] & 0x7f) as u64, |value, &byte| {
+                (value << 8) | byte as u64
+            }));
+    }
+    let digits = String::from_utf8_lossy(field);
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| anyhow::anyhow!("invalid tar number {digits:?}"))
+}
+
+/// Returns the records of a PAX extended header, `<len> <key>=<value>\n`.
+fn pax_records(data: &[u8]) -> Vec<(&str, &str)> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|&byte| byte == b' ') {
+        let Some(len) = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len > space && len <= rest.len())
+        else {
+            break;
+        };
+        if let Ok(record) = std::str::from_utf8(&rest[space + 1..len])
+            && let Some((key, value)) = record.trim_end_matches('\n').split_once('=')
+        {
+            records.push((key, value));
+        }
+        rest = &rest[len..];
+    }
+    records
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::GzEncoder};
+    use std::{fs, io::Write};
+
+    const DIR_NAME: &str = "target/test";
+
+    /// Returns a ustar header of `size` bytes for `name`
+    fn header(name: &str, size: u64, kind: u8) -> Vec<u8> {
+        let mut header = vec![0; BLOCK_SIZE as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{size:011o}").as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].fill(b' ');
+        let sum: u64 = header.iter().map(|&byte| byte as u64).sum();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        header
+    }
+
+    /// Builds a tar archive holding `files`, the long names in PAX headers
+    fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        let mut append = |header: Vec<u8>, data: &[u8]| {
+            tar.extend_from_slice(&header);
+            tar.extend_from_slice(data);
+            tar.resize(
+                tar.len().div_ceil(BLOCK_SIZE as usize) * BLOCK_SIZE as usize,
+                0,
+            );
+        };
+        for (name, content) in files {
+            if name.len() > 100 {
+                let record = format!(" path={name}\n");
+                // The length counts its own digits
+                let mut len = record.len() + 1;
+                while len != record.len() + len.to_string().len() {
+                    len = record.len() + len.to_string().len();
+                }
+                let pax = format!("{len}{record}");
+                append(
+                    header("PaxHeader", pax.len() as u64, TYPE_PAX),
+                    pax.as_bytes(),
+                );
+                append(
+                    header("truncated", content.len() as u64, TYPE_REGULAR),
+                    content,
+                );
+            } else if name.ends_with('/') {
+                append(header(name, 0, b'5'), b"");
+            } else {
+                append(header(name, content.len() as u64, TYPE_REGULAR), content);
+            }
+        }
+        tar.extend_from_slice(&[0; 2 * BLOCK_SIZE as usize]);
+        tar
+    }
+
+    fn read_all(archive: &TarArchive, name: &str, offset: u64) -> Vec<u8> {
+        let mut content = Vec::new();
+        archive
+            .open(name, offset)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn serves_plain_and_compressed_archives() {
+        let kernel: Vec<u8> = (0..10_000u32).map(|i| (i % 13) as u8).collect();
+        let long_name = format!("images/{}/initrd.img", "x".repeat(100));
+        let tar = build_tar(&[
+            ("boot/", b""),
+            ("boot/vmlinuz", &kernel),
+            (&long_name, b"initrd"),
+            ("readme.txt", b"Hello, tar!"),
+        ]);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar).unwrap();
+        let tar_gz = encoder.finish().unwrap();
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        for (name, content) in [("bundle.tar", tar), ("bundle.tar.gz", tar_gz)] {
+            let path = PathBuf::from(DIR_NAME).join(format!("serves_{name}"));
+            fs::write(&path, content).unwrap();
+            let archive = TarArchive::open(&path).unwrap();
+
+            assert_eq!(archive.entries.len(), 3);
+            assert_eq!(archive.size("/boot/vmlinuz").unwrap(), kernel.len() as u64);
+            assert_eq!(read_all(&archive, "boot\\vmlinuz", 0), kernel);
+            assert_eq!(read_all(&archive, "boot/vmlinuz", 9_000), &kernel[9_000..]);
+            assert_eq!(read_all(&archive, &long_name, 0), b"initrd");
+            assert_eq!(read_all(&archive, "readme.txt", 7), b"tar!");
+            assert_eq!(
+                archive.size("boot").unwrap_err().kind(),
+                io::ErrorKind::NotFound
+            );
+
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn parses_numbers() {
+        assert_eq!(parse_number(b"00000001750\0").unwrap(), 1000);
+        assert_eq!(parse_number(b"     144 ").unwrap(), 100);
+        assert_eq!(
+            parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0]).unwrap(),
+            1 << 33
+        );
+        assert!(parse_number(b"9\0").is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let path = PathBuf::from(DIR_NAME).join("rejects_other_files.tar");
+        fs::write(&path, vec![b'x'; 1024]).unwrap();
+        assert!(TarArchive::open(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use super::dynamic::glob_matches;
+use super::provider::normalize_name;
+
+/// Templates `struct` expands variables in the text files matching its
+/// patterns before they are served, so that one boot script or menu fits
+/// every host instead of one generated per host. Patterns are matched like
+/// those of [`Config::with_dynamic()`](super::Config::with_dynamic).
+///
+/// Variables are written `${name}`:
+/// - `client_ip`: IP address of the client
+/// - `client_mac_hint`: MAC address found in the requested name, as in the
+///   `pxelinux.cfg/01-aa-bb-cc-dd-ee-ff` of PXELINUX, written
+///   `aa:bb:cc:dd:ee:ff`, or empty. TFTP requests do not carry the MAC
+///   address of the client otherwise.
+/// - `server_ip`: IP address the client reaches the server at
+///
+/// Other variables are left as is.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd]
+/// templates = ["*.ipxe", "pxelinux.cfg/*"]
+/// ```
+#[derive(Debug, Clone, Default)]
+pub(super) struct Templates {
+    patterns: Vec<String>,
+}
+
+impl Templates {
+    pub fn new(patterns: &[String]) -> Templates {
+        Templates {
+            patterns: patterns
+                .iter()
+                .map(|pattern| normalize_name(pattern))
+                .collect(),
+        }
+    }
+
+    /// Returns whether the variables of the requested `filename` are expanded.
+    pub fn matches(&self, filename: &str) -> bool {
+        let name = normalize_name(filename);
+        self.patterns
+            .iter()
+            .any(|pattern| glob_matches(pattern, &name))
+    }
+
+    /// Returns `content` with the variables of the request for `filename`
+    /// from `client` expanded, received on `local`.
+    pub fn expand(
+        content: &str,
+        filename: &str,
+        client: &SocketAddr,
+        local: &SocketAddr,
+    ) -> String {
+        let client_ip = client.ip().to_canonical();
+        let variables = [
+            ("client_ip", client_ip.to_string()),
+            ("client_mac_hint", mac_hint(filename).unwrap_or_default()),
+            ("server_ip", server_ip(local, client).to_string()),
+        ];
+
+        let mut expanded = String::with_capacity(content.len());
+        let mut rest = content;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let variable = rest[start + 2..].find('}').and_then(|end| {
+                let name = &rest[start + 2..start + 2 + end];
+                variables
+                    .iter()
+                    .find(|(variable, _)| *variable == name)
+                    .map(|(_, value)| (value, start + 3 + end))
+            });
+            match variable {
+                Some((value, next)) => {
+                    expanded.push_str(value);
+                    rest = &rest[next..];
+                }
+                None => {
+                    expanded.push_str("${");
+                    rest = &rest[start + 2..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+/// Returns the last MAC address written as six hexadecimal pairs separated
+/// by `-` or `:` in `filename`, the last so that the `01-` hardware type of
+/// PXELINUX names is not taken for part of it.
+fn mac_hint(filename: &str) -> Option<String> {
+    let bytes = filename.as_bytes();
+    (0..bytes.len().saturating_sub(16)).rev().find_map(|start| {
+        let candidate = &bytes[start..start + 17];
+        let separator = candidate[2];
+        let is_mac = matches!(separator, b'-' | b':')
+            && candidate.chunks(3).all(|pair| {
+                pair[..2].iter().all(u8::is_ascii_hexdigit)
+                    && pair.get(2).is_none_or(|&c| c == separator)
+            });
+        is_mac.then(|| {
+            String::from_utf8_lossy(candidate)
+                .replace('-', ":")
+                .to_lowercase()
+        })
+    })
+}
+
+/// Returns the address the client reaches the server at, that of the route
+/// to the client if the server listens on all addresses.
+fn server_ip(local: &SocketAddr, client: &SocketAddr) -> IpAddr {
+    if !local.ip().is_unspecified() {
+        return local.ip().to_canonical();
+    }
+    let bind: SocketAddr = match client {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    // Connecting a UDP socket sends nothing, it only picks the route
+    UdpSocket::bind(bind)
+        .and_then(|socket| {
+            socket.connect(client)?;
+            socket.local_addr()
+        })
+        .map(|addr| addr.ip().to_canonical())
+        .unwrap_or(local.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_variables() {
+        let templates = Templates::new(&["*.ipxe".to_string(), "/pxelinux.cfg/*".to_string()]);
+        assert!(templates.matches("boot.ipxe"));
+        assert!(templates.matches("pxelinux.cfg\\default"));
+        assert!(!templates.matches("vmlinuz"));
+
+        let client = "192.168.1.20:2000".parse().unwrap();
+        let local = "192.168.1.1:69".parse().unwrap();
+        let content = "chain http://${server_ip}/boot?ip=${client_ip}&mac=${client_mac_hint}\n\
+                       echo ${unknown} ${client_ip";
+        assert_eq!(
+            Templates::expand(
+                content,
+                "pxelinux.cfg/01-AA-bb-cc-dd-ee-ff",
+                &client,
+                &local
+            ),
+            "chain http://192.168.1.1/boot?ip=192.168.1.20&mac=aa:bb:cc:dd:ee:ff\n\
+             echo ${unknown} ${client_ip"
+        );
+        assert_eq!(
+            Templates::expand("[${client_mac_hint}]", "boot.ipxe", &client, &local),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn finds_mac_hints() {
+        assert_eq!(
+            mac_hint("pxelinux.cfg/01-08-00-27-aa-bb-cc").as_deref(),
+            Some("08:00:27:aa:bb:cc")
+        );
+        assert_eq!(
+            mac_hint("hosts/00:1A:2b:3c:4d:5e.ipxe").as_deref(),
+            Some("00:1a:2b:3c:4d:5e")
+        );
+        assert_eq!(mac_hint("pxelinux.cfg/C0A80114"), None);
+        assert_eq!(mac_hint("aa-bb-cc-dd-ee:ff"), None);
+    }
+
+    #[test]
+    fn finds_server_ip_of_route() {
+        let client = "127.0.0.1:2000".parse().unwrap();
+        let any = "0.0.0.0:69".parse().unwrap();
+        assert_eq!(server_ip(&any, &client), IpAddr::from([127, 0, 0, 1]));
+    }
+}
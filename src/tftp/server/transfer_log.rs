@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use super::handler::{Direction, ServerHandler, TransferInfo};
+use super::provider::normalize_name;
+
+/// Number of rotated transfer logs kept by default
+const DEFAULT_KEEP: usize = 5;
+
+/// LogRotation `struct` tells when the [`TransferLog`] is rotated, so that
+/// long running servers do not fill their storage with it. The log is
+/// rotated before a line would take it over `max_size` bytes, or once it was
+/// started `max_age` seconds ago. `xferlog` is then renamed `xferlog.1`, the
+/// previous `xferlog.1` renamed `xferlog.2` and so on, up to `keep` files
+/// (default 5), the oldest being removed.
+///
+/// # Example
+///
+/// ```toml
+/// [tftpd.transfer_log_rotation]
+/// max_size = 10485760
+/// max_age = 86400
+/// keep = 7
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRotation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Age in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+/// TransferLog `struct` appends a line for every completed or failed
+/// transfer to a log file in the `xferlog` format of wu-ftpd, so that
+/// existing FTP log parsers and statistics tools read TFTP transfers too.
+///
+/// Each line reads `<time> <seconds> <client ip> <bytes> <name> <b|a> _ <i|o>
+/// a anonymous tftp 0 * <c|i>`: the local time of the end of the transfer, its
+/// duration, `a` for netascii transfers, `o` for downloads and `i` for
+/// uploads, and `c` if it completed, `i` if it failed. Names start with `/`, their whitespace replaced by `_`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::Path;
+/// use xtool::tftp::server::TransferLog;
+///
+/// let log = TransferLog::new(Path::new("/var/log/xtool/xferlog"));
+/// assert_eq!(log.path(), Path::new("/var/log/xtool/xferlog"));
+/// ```
+#[derive(Debug)]
+pub struct TransferLog {
+    path: PathBuf,
+    rotation: LogRotation,
+    /// Start time and bytes transferred of the transfers in progress
+    transfers: Mutex<HashMap<TransferInfo, (Instant, u64)>>,
+    /// Time the log file was started, known once written to, held while
+    /// writing so that lines are not written while it is rotated
+    started: Mutex<Option<SystemTime>>,
+}
+
+impl TransferLog {
+    /// Creates the log of the transfers, appended to `path`.
+    pub fn new(path: &Path) -> TransferLog {
+        TransferLog {
+            path: path.to_path_buf(),
+            rotation: LogRotation::default(),
+            transfers: Mutex::new(HashMap::new()),
+            started: Mutex::new(None),
+        }
+    }
+
+    /// Rotates the log as `rotation` tells, see [`LogRotation`].
+    #[allow(dead_code)]
+    pub fn with_rotation(mut self, rotation: LogRotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Returns the path of the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the line of `transfer`, which ended after `bytes` bytes.
+    fn record(&self, transfer: &TransferInfo, bytes: Option<u64>, completed: bool) {
+        let started = self
+            .transfers
+            .lock()
+            .ok()
+            .and_then(|mut transfers| transfers.remove(transfer));
+        let duration = started.map_or(Duration::ZERO, |(start, _)| start.elapsed());
+        let bytes = bytes.unwrap_or(started.map_or(0, |(_, bytes)| bytes));
+        let line = format_line(transfer, duration, bytes, completed);
+
+        let Ok(mut started) = self.started.lock() else {
+            return;
+        };
+        if let Err(err) = self.rotate_before(&mut started, line.len() as u64 + 1) {
+            log::warn!(
+                "  Could not rotate the transfer log {}: {err}",
+                self.path.display()
+            );
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut log| writeln!(log, "{line}"));
+        if let Err(err) = written {
+            log::warn!(
+                "  Could not write the transfer log {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// Rotates the log if appending `bytes` to it would take it over the
+    /// limits of its rotation. `started` is the time the log was started.
+    fn rotate_before(&self, started: &mut Option<SystemTime>, bytes: u64) -> std::io::Result<()> {
+        let LogRotation {
+            max_size, max_age, ..
+        } = self.rotation;
+        if max_size.is_none() && max_age.is_none() {
+            return Ok(());
+        }
+        let Ok(metadata) = self.path.metadata() else {
+            *started = Some(SystemTime::now());
+            return Ok(());
+        };
+        let start = *started.get_or_insert_with(|| {
+            metadata
+                .created()
+                .or_else(|_| metadata.modified())
+                .unwrap_or_else(|_| SystemTime::now())
+        });
+        let too_large =
+            max_size.is_some_and(|max| metadata.len() > 0 && metadata.len() + bytes > max);
+        let too_old = max_age
+            .is_some_and(|max| start.elapsed().unwrap_or_default() >= Duration::from_secs(max));
+        if !too_large && !too_old {
+            return Ok(());
+        }
+
+        let keep = self.rotation.keep.unwrap_or(DEFAULT_KEEP);
+        let rotated = |index: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{index}"));
+            PathBuf::from(name)
+        };
+        if keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..keep).rev() {
+                if rotated(index).exists() {
+                    std::fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        log::info!("Rotated the transfer log {}", self.path.display());
+        *started = Some(SystemTime::now());
+        Ok(())
+    }
+}
+
+impl ServerHandler for TransferLog {
+    fn on_transfer_start(&self, transfer: &TransferInfo) {
+        if let Ok(mut transfers) = self.transfers.lock() {
+            transfers.insert(transfer.clone(), (Instant::now(), 0));
+        }
+    }
+
+    fn on_block(&self, transfer: &TransferInfo, _block: u16, bytes: u64) {
+        if let Ok(mut transfers) = self.transfers.lock()
+            && let Some((_, transferred)) = transfers.get_mut(transfer)
+        {
+            *transferred = bytes;
+        }
+    }
+
+    fn on_complete(&self, transfer: &TransferInfo, bytes: u64) {
+        self.record(transfer, Some(bytes), true);
+    }
+
+    fn on_error(&self, transfer: &TransferInfo, _error: &anyhow::Error) {
+        self.record(transfer, None, false);
+    }
+}
+
+fn format_line(transfer: &TransferInfo, duration: Duration, bytes: u64, completed: bool) -> String {
+    let time = Local::now().format("%a %b %e %H:%M:%S %Y");
+    let seconds = duration.as_secs_f64().round() as u64;
+    let name: String = format!("/{}", normalize_name(&transfer.filename))
+        .chars()
+        .map(|c| if c.is_whitespace() { '_' } else { c })
+        .collect();
+    let direction = match transfer.direction {
+        Direction::Read => 'o',
+        Direction::Write => 'i',
+    };
+    let kind = if transfer.netascii { 'a' } else { 'b' };
+    let status = if completed { 'c' } else { 'i' };
+    format!(
+        "{time} {seconds} {} {bytes} {name} {kind} _ {direction} a anonymous tftp 0 * {status}",
+        transfer.peer.ip()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn appends_xferlog_lines() {
+        let dir = PathBuf::from("target/test/transfer_log_appends_lines");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("xferlog");
+        let transfer = |filename: &str, direction| TransferInfo {
+            peer: "192.168.1.20:1069".parse().unwrap(),
+            filename: filename.to_string(),
+            direction,
+            netascii: false,
+            custom_options: vec![],
+        };
+
+        let log = TransferLog::new(&path);
+        let download = transfer("/boot/my kernel", Direction::Read);
+        log.on_transfer_start(&download);
+        log.on_complete(&download, 4096);
+        let upload = transfer("dump.bin", Direction::Write);
+        log.on_transfer_start(&upload);
+        log.on_block(&upload, 2, 1024);
+        log.on_error(&upload, &anyhow::anyhow!("Transfer timed out"));
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<Vec<&str>> = content
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        // The time takes the first five fields
+        assert_eq!(
+            lines[0][5..],
+            [
+                "0",
+                "192.168.1.20",
+                "4096",
+                "/boot/my_kernel",
+                "b",
+                "_",
+                "o",
+                "a",
+                "anonymous",
+                "tftp",
+                "0",
+                "*",
+                "c"
+            ]
+        );
+        assert_eq!(lines[1][7..9], ["1024", "/dump.bin"]);
+        assert_eq!(lines[1][11], "i");
+        assert_eq!(lines[1][17], "i");
+        assert!(log.transfers.lock().unwrap().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = PathBuf::from("target/test/transfer_log_rotates_by_size");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("xferlog");
+        let transfer = TransferInfo {
+            peer: "192.168.1.20:1069".parse().unwrap(),
+            filename: "zImage".to_string(),
+            direction: Direction::Read,
+            netascii: false,
+            custom_options: vec![],
+        };
+
+        // Lines are about 80 bytes long, two fit in a log
+        let log = TransferLog::new(&path).with_rotation(LogRotation {
+            max_size: Some(200),
+            max_age: None,
+            keep: Some(2),
+        });
+        for bytes in 1..=7 {
+            log.on_transfer_start(&transfer);
+            log.on_complete(&transfer, bytes);
+        }
+
+        let sizes = |name: &str| -> Vec<String> {
+            fs::read_to_string(dir.join(name))
+                .unwrap()
+                .lines()
+                .map(|line| line.split_whitespace().nth(7).unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(sizes("xferlog"), ["7"]);
+        assert_eq!(sizes("xferlog.1"), ["5", "6"]);
+        assert_eq!(sizes("xferlog.2"), ["3", "4"]);
+        assert!(!dir.join("xferlog.3").exists());
+
+        // A log older than its maximum age is rotated on the next line
+        let log = TransferLog::new(&path).with_rotation(LogRotation {
+            max_size: None,
+            max_age: Some(0),
+            keep: Some(2),
+        });
+        log.on_transfer_start(&transfer);
+        log.on_complete(&transfer, 8);
+        assert_eq!(sizes("xferlog"), ["8"]);
+        assert_eq!(sizes("xferlog.1"), ["7"]);
+        assert_eq!(sizes("xferlog.2"), ["5", "6"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
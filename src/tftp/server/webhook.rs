@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use super::manifest::upload_name;
+
+/// How long the endpoint of a webhook is given to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// UploadWebhook `struct` POSTs a JSON object to a URL after every completed
+/// upload, so that pipelines react to the crash dumps or configuration
+/// backups uploaded by devices without polling the upload directory.
+///
+/// The object holds the `filename` relative to the upload directory with `/`
+/// separators, the `size` in bytes, the IP address of the `client` and the
+/// `sha256` of the content:
+///
+/// ```json
+/// {"client":"192.168.1.50","filename":"dumps/core.1234","sha256":"9f86d0…","size":4096}
+/// ```
+///
+/// Requests are sent from another thread, once the upload is complete, and
+/// failures are only logged.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::Path;
+/// use xtool::tftp::server::UploadWebhook;
+///
+/// let webhook = UploadWebhook::new("http://ci.local/hooks/tftp", Path::new("/srv/tftp"));
+/// assert_eq!(webhook.url(), "http://ci.local/hooks/tftp");
+/// ```
+#[derive(Debug)]
+pub struct UploadWebhook {
+    url: String,
+    root: PathBuf,
+}
+
+impl UploadWebhook {
+    /// Creates the webhook POSTing to `url` the uploads received in `root`.
+    pub fn new(url: &str, root: &Path) -> UploadWebhook {
+        UploadWebhook {
+            url: url.to_string(),
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Returns the URL the uploads are POSTed to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// POSTs the upload of `file`, of `size` bytes with content hash
+    /// `sha256` received from `client`, from another thread.
+    pub fn post(&self, file: &Path, size: u64, sha256: &str, client: &SocketAddr) {
+        let payload = self.payload(file, size, sha256, client);
+        let url = self.url.clone();
+        let name = file.display().to_string();
+        let posted = thread::Builder::new()
+            .name("tftp-webhook".to_string())
+            .spawn(move || {
+                let agent: ureq::Agent = ureq::Agent::config_builder()
+                    .timeout_global(Some(WEBHOOK_TIMEOUT))
+                    .build()
+                    .into();
+                let sent = agent
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .send(payload.to_string());
+                if let Err(err) = sent {
+                    log::warn!("  Could not notify {url} of the upload of {name}: {err}");
+                }
+            });
+        if let Err(err) = posted {
+            log::warn!("  Could not notify {} of an upload: {err}", self.url);
+        }
+    }
+
+    fn payload(
+        &self,
+        file: &Path,
+        size: u64,
+        sha256: &str,
+        client: &SocketAddr,
+    ) -> serde_json::Value {
+        json!({
+            "filename": upload_name(file, &self.root),
+            "size": size,
+            "client": client.ip().to_canonical().to_string(),
+            "sha256": sha256,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_payload() {
+        let root = Path::new("/srv/tftp");
+        let webhook = UploadWebhook::new("http://127.0.0.1:1/hook", root);
+        let client = "[::ffff:192.168.1.20]:1069".parse().unwrap();
+
+        let payload = webhook.payload(&root.join("dumps").join("core.1"), 42, "abcd", &client);
+        assert_eq!(
+            payload,
+            json!({
+                "filename": "dumps/core.1",
+                "size": 42,
+                "client": "192.168.1.20",
+                "sha256": "abcd",
+            })
+        );
+    }
+}
@@ -1,15 +1,35 @@
 use std::{
-    fs::{self, File},
-    io::ErrorKind,
-    path::PathBuf,
+    fs,
+    io::{self, ErrorKind, Read},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
     thread,
     time::{Duration, Instant},
 };
 
 use crate::tftp::core::options::{OptionsPrivate, OptionsProtocol, Rollover};
-use crate::tftp::core::{ErrorCode, Packet, Socket, Window};
+use crate::tftp::core::{
+    Convert, DigestAlgorithm, ErrorCode, MappedFile, MappedWindow, Packet, RateLimiter, ReadAhead,
+    Socket, Window, dally, is_message_too_large, reject_unknown_tid,
+};
+
+use super::activity::Activity;
+use super::fs::{DiskFs, FileWriter, TftpFs};
+use super::handler::{ServerHandler, TransferInfo};
+use super::journal::{JOURNAL_DIGEST, Journal};
+use super::manifest::Manifest;
+use super::overwrite::keep_version;
+use super::quota::QuotaReservation;
+use super::webhook::UploadWebhook;
 
 const DEFAULT_DUPLICATE_DELAY: Duration = Duration::from_millis(1);
+/// Buffer size for the acknowledgements and errors of downloads
+const ACK_BUFFER_SIZE: usize = 512;
+/// Suffix of journaled uploads until compared with the existing file
+const JOURNAL_TMP_SUFFIX: &str = ".xtool-tmp";
+/// Suffix of atomic uploads until complete
+pub(super) const UPLOAD_TMP_SUFFIX: &str = ".tftp-tmp";
 
 /// Worker `struct` is used for multithreaded file sending and receiving.
 /// It creates a new socket using the Server's IP and a random port
@@ -44,6 +64,17 @@ pub struct Worker<T: Socket + ?Sized> {
     file_path: PathBuf,
     opt_local: OptionsPrivate,
     opt_common: OptionsProtocol,
+    journal: Option<Arc<Journal>>,
+    fs: Arc<dyn TftpFs>,
+    handler: Option<(Arc<dyn ServerHandler>, TransferInfo)>,
+    rate_limiters: Vec<Arc<RateLimiter>>,
+    quota: Option<QuotaReservation>,
+    atomic: bool,
+    versioned: bool,
+    manifest: Option<Arc<Manifest>>,
+    webhook: Option<Arc<UploadWebhook>>,
+    netascii: bool,
+    activity: Option<Arc<Activity>>,
 }
 
 impl<T: Socket + ?Sized> Worker<T> {
@@ -59,27 +90,159 @@ impl<T: Socket + ?Sized> Worker<T> {
             file_path,
             opt_local,
             opt_common,
+            journal: None,
+            fs: Arc::new(DiskFs),
+            handler: None,
+            rate_limiters: Vec::new(),
+            quota: None,
+            atomic: false,
+            versioned: false,
+            manifest: None,
+            webhook: None,
+            netascii: false,
+            activity: None,
+        }
+    }
+
+    /// Records received files in `journal`, skipping the rewrite of uploads
+    /// whose content is already recorded. Only supported on the local
+    /// filesystem.
+    pub fn with_journal(mut self, journal: Arc<Journal>) -> Worker<T> {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Reads and writes `file_path` in `fs` instead of the local filesystem.
+    pub fn with_fs(mut self, fs: Arc<dyn TftpFs>) -> Worker<T> {
+        self.fs = fs;
+        self
+    }
+
+    /// Notifies `handler` of the progress of the transfer described by `info`.
+    pub fn with_handler(
+        mut self,
+        handler: Arc<dyn ServerHandler>,
+        info: TransferInfo,
+    ) -> Worker<T> {
+        self.handler = Some((handler, info));
+        self
+    }
+
+    /// Paces sent data with `limiter`, shared with other transfers such as
+    /// those of the same client, on top of the `rate_limit` of the transfer.
+    /// May be called several times, data is then sent within every limit.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Worker<T> {
+        self.rate_limiters.push(limiter);
+        self
+    }
+
+    /// Writes received files to `<name>.tftp-tmp` and renames them once
+    /// complete, so that readers of `file_path` never see a partial file.
+    /// Concurrent uploads of the same file write to `<name>.1.tftp-tmp`…
+    pub fn with_atomic_upload(mut self, atomic: bool) -> Worker<T> {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Moves an existing `file_path` to its first free `<name>.1`,
+    /// `<name>.2`… once the received file is complete, instead of replacing
+    /// it. Received files are written to `<name>.tftp-tmp` until then.
+    pub fn with_versioning(mut self, versioned: bool) -> Worker<T> {
+        self.versioned = versioned;
+        self
+    }
+
+    /// Converts line endings to netascii when sending, and back to those of
+    /// the local files when receiving.
+    pub fn with_netascii(mut self, netascii: bool) -> Worker<T> {
+        self.netascii = netascii;
+        self
+    }
+
+    /// Records the packets received from the client in `activity`, stopping
+    /// the transfer once it is reaped.
+    pub(super) fn with_activity(mut self, activity: Arc<Activity>) -> Worker<T> {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// Appends an entry for the received file to `manifest` once complete.
+    pub fn with_manifest(mut self, manifest: Arc<Manifest>) -> Worker<T> {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// POSTs the received file to `webhook` once complete.
+    pub fn with_webhook(mut self, webhook: Arc<UploadWebhook>) -> Worker<T> {
+        self.webhook = Some(webhook);
+        self
+    }
+
+    /// Counts received data against the quota reserved for the upload,
+    /// failing it once exceeded.
+    pub fn with_quota(mut self, quota: QuotaReservation) -> Worker<T> {
+        self.quota = Some(quota);
+        self
+    }
+
+    fn notify(&self, event: impl FnOnce(&dyn ServerHandler, &TransferInfo)) {
+        if let Some((handler, info)) = &self.handler {
+            event(handler.as_ref(), info);
         }
     }
 
     /// Sends a file to the remote [`SocketAddr`] that has sent a read request using
     /// a random port, asynchronously.
+    #[allow(dead_code)]
     pub fn send(self, check_response: bool) -> anyhow::Result<thread::JoinHandle<bool>> {
+        Ok(thread::spawn(self.send_job(check_response)))
+    }
+
+    /// Returns the job sending the file, for [`Worker::send()`] or a pool of
+    /// threads.
+    pub(super) fn send_job(self, check_response: bool) -> impl FnOnce() -> bool + Send + 'static {
         let file_path = self.file_path.clone();
         let remote_addr = self.socket.remote_addr().unwrap();
+        let checksum = self.opt_local.checksum;
+        let offset = self.opt_common.offset;
+        let fs = self.fs.clone();
+        let handler = self.handler.clone();
 
-        let handle = thread::spawn(move || {
-            let handle_send = || -> anyhow::Result<()> {
-                self.send_file(File::open(&file_path)?, check_response)
+        move || {
+            let handle_send = || -> anyhow::Result<u64> {
+                if offset > 0 {
+                    log::info!("  Resuming at offset {offset}");
+                }
+                if self.netascii {
+                    // Offsets are those of the converted content
+                    let mut file = Convert::to_netascii(fs.open_read(&file_path, 0)?);
+                    io::copy(&mut (&mut file).take(offset), &mut io::sink())?;
+                    return self.send_file(file, check_response);
+                }
+                if let Some(mapping) = self.map_file() {
+                    let window = MappedWindow::new(
+                        self.opt_common.window_size,
+                        self.opt_common.block_size,
+                        mapping,
+                        offset,
+                    );
+                    return self.send_window(window, check_response);
+                }
+                let file = fs.open_read(&file_path, offset)?;
+                self.send_file(file, check_response)
             };
 
             match handle_send() {
-                Ok(_) => {
+                Ok(size) => {
                     log::info!(
                         "Sent {} to {}",
                         &file_path.file_name().unwrap().to_string_lossy(),
                         &remote_addr
                     );
+                    if let Some((handler, info)) = &handler {
+                        handler.on_complete(info, size);
+                    }
+                    log_checksum(checksum, &file_path, fs.as_ref());
                     true
                 }
                 Err(err) => {
@@ -88,33 +251,144 @@ impl<T: Socket + ?Sized> Worker<T> {
                         &file_path.file_name().unwrap().to_string_lossy(),
                         &remote_addr
                     );
+                    if let Some((handler, info)) = &handler {
+                        handler.on_error(info, &err);
+                    }
                     false
                 }
             }
-        });
-
-        Ok(handle)
+        }
     }
 
     /// Receives a file from the remote [`SocketAddr`] (client or server) using
     /// the supplied socket, asynchronously.
+    #[allow(dead_code)]
     pub fn receive(self) -> anyhow::Result<thread::JoinHandle<bool>> {
+        Ok(thread::spawn(self.receive_job()))
+    }
+
+    /// Returns the job receiving the file, for [`Worker::receive()`] or a
+    /// pool of threads.
+    pub(super) fn receive_job(self) -> impl FnOnce() -> bool + Send + 'static {
         let clean_on_error = self.opt_local.clean_on_error;
         let file_path = self.file_path.clone();
         let remote_addr = self.socket.remote_addr().unwrap();
         let opt_tsize = self.opt_common.transfer_size;
+        let checksum = self.opt_local.checksum;
+        let journal = self.journal.clone();
+        let handler = self.handler.clone();
+        let manifest = self.manifest.clone();
+        let webhook = self.webhook.clone();
+        let fs = self.fs.clone();
+        let versioned = self.versioned;
+        // Journaled uploads land next to the target and only replace it if the content changed
+        let tmp_suffix = match journal {
+            Some(_) => Some(JOURNAL_TMP_SUFFIX),
+            None if self.atomic || versioned => Some(UPLOAD_TMP_SUFFIX),
+            None => None,
+        };
+        // Nothing of a temporary file is worth keeping
+        let temporary = tmp_suffix.is_some();
+
+        move || {
+            let block_size = self.opt_common.block_size;
+            let timeout = self.opt_common.timeout;
+            let mut worker = self;
 
-        let handle = thread::spawn(move || {
-            let handle_receive =
-                || -> anyhow::Result<u64> { self.receive_file(File::create(&file_path)?) };
+            let notify = |result: anyhow::Result<u64>| {
+                if let Some((handler, info)) = &handler {
+                    match result {
+                        Ok(size) => handler.on_complete(info, size),
+                        Err(err) => handler.on_error(info, &err),
+                    }
+                }
+            };
+
+            let write_path = match tmp_suffix {
+                Some(suffix) => match create_tmp_file(fs.as_ref(), &file_path, suffix) {
+                    Ok(path) => path,
+                    Err(err) => {
+                        log::error!(
+                            "Error \"{err}\", while creating the temporary file of {}",
+                            file_path.display()
+                        );
+                        worker.refuse_upload("cannot write file");
+                        notify(Err(err.into()));
+                        return false;
+                    }
+                },
+                None => file_path.clone(),
+            };
+
+            let mut handle_receive = || -> anyhow::Result<(u64, u16)> {
+                let file = fs.open_write(&write_path)?;
+                if worker.netascii {
+                    return worker.receive_file(Box::new(Convert::from_netascii(file)));
+                }
+                worker.receive_file(file)
+            };
 
             match handle_receive() {
-                Ok(size) => {
-                    if let Some(tsize) = opt_tsize {
-                        if tsize != size {
-                            log::error!("Size mismatch, negotiated: {tsize}, transferred: {size}");
-                            return false;
+                Ok((size, last_block)) => {
+                    if let Some(tsize) = opt_tsize
+                        && tsize != size
+                    {
+                        log::error!("Size mismatch, negotiated: {tsize}, transferred: {size}");
+                        worker.refuse_upload("size mismatch with negotiated tsize");
+                        notify(Err(anyhow::anyhow!(
+                            "Size mismatch, negotiated: {tsize}, transferred: {size}"
+                        )));
+                        if temporary && fs.remove(&write_path).is_err() {
+                            log::error!("Error while cleaning {}", write_path.display());
+                        }
+                        return false;
+                    }
+
+                    if let Some(journal) = &journal {
+                        match commit_journaled(journal, &write_path, &file_path, versioned) {
+                            Ok(true) => {
+                                log::info!(
+                                    "Received duplicate of {} ({} bytes) from {}, keeping existing file",
+                                    &file_path.file_name().unwrap().to_string_lossy(),
+                                    size,
+                                    remote_addr
+                                );
+                                notify(Ok(size));
+                                record_upload(
+                                    manifest.as_deref(),
+                                    webhook.as_deref(),
+                                    &file_path,
+                                    size,
+                                    &remote_addr,
+                                    fs.as_ref(),
+                                );
+                                worker.acknowledge_upload(last_block, block_size, timeout);
+                                return true;
+                            }
+                            Ok(false) => {}
+                            Err(err) => {
+                                log::error!(
+                                    "Error \"{err}\", while journaling {}",
+                                    file_path.display()
+                                );
+                                worker.refuse_upload("cannot store file");
+                                notify(Err(err));
+                                let _ = fs::remove_file(&write_path);
+                                return false;
+                            }
                         }
+                    } else if temporary
+                        && let Err(err) =
+                            commit_upload(fs.as_ref(), &write_path, &file_path, versioned)
+                    {
+                        log::error!(
+                            "Error \"{err}\", while moving {} into place",
+                            write_path.display()
+                        );
+                        worker.refuse_upload("cannot store file");
+                        notify(Err(err.into()));
+                        let _ = fs.remove(&write_path);
+                        return false;
                     }
 
                     log::info!(
@@ -123,6 +397,17 @@ impl<T: Socket + ?Sized> Worker<T> {
                         size,
                         remote_addr
                     );
+                    notify(Ok(size));
+                    log_checksum(checksum, &file_path, fs.as_ref());
+                    record_upload(
+                        manifest.as_deref(),
+                        webhook.as_deref(),
+                        &file_path,
+                        size,
+                        &remote_addr,
+                        fs.as_ref(),
+                    );
+                    worker.acknowledge_upload(last_block, block_size, timeout);
                     true
                 }
                 Err(err) => {
@@ -131,29 +416,66 @@ impl<T: Socket + ?Sized> Worker<T> {
                         &file_path.file_name().unwrap().to_string_lossy(),
                         remote_addr
                     );
-                    if clean_on_error && fs::remove_file(&file_path).is_err() {
-                        log::error!("Error while cleaning {}", &file_path.to_str().unwrap());
+                    notify(Err(err));
+                    if (clean_on_error || temporary) && fs.remove(&write_path).is_err() {
+                        log::error!("Error while cleaning {}", &write_path.to_str().unwrap());
                     }
                     false
                 }
             }
-        });
+        }
+    }
 
-        Ok(handle)
+    /// Maps the file to send if of at least `mmap_threshold` bytes, unless
+    /// the filesystem cannot.
+    fn map_file(&self) -> Option<MappedFile> {
+        let threshold = self.opt_local.mmap_threshold?;
+        if self.fs.metadata(&self.file_path).ok()?.len < threshold {
+            return None;
+        }
+        match self.fs.map(&self.file_path) {
+            Ok(mapping) => mapping,
+            Err(err) => {
+                log::debug!(
+                    "  Cannot map {}, reading it: {err}",
+                    self.file_path.display()
+                );
+                None
+            }
+        }
     }
 
-    fn send_file(mut self, file: File, check_response: bool) -> anyhow::Result<()> {
-        let mut block_seq_win: u16 = 0;
-        let mut win_idx: u16 = 0;
-        let mut window = Window::new(
-            self.opt_common.window_size,
-            self.opt_common.block_size,
+    fn send_file(
+        self,
+        file: impl Read + Send + 'static,
+        check_response: bool,
+    ) -> anyhow::Result<u64> {
+        let window_size = self.opt_common.window_size;
+        let block_size = self.opt_common.block_size;
+
+        if self.opt_local.read_ahead == 0 {
+            return self.send_window(Window::new(window_size, block_size, file), check_response);
+        }
+        // Later windows are read while the current one awaits its Acks
+        let file = ReadAhead::new(
             file,
+            window_size as usize * block_size as usize,
+            self.opt_local.read_ahead as usize,
         );
+        self.send_window(Window::new(window_size, block_size, file), check_response)
+    }
+
+    fn send_window(mut self, mut window: impl Frames, check_response: bool) -> anyhow::Result<u64> {
+        let mut block_seq_win: u16 = 0;
+        let mut acked: u64 = 0;
+        let mut win_idx: u16 = 0;
+        // Frames of the current window sent at least once, retransmissions restart at 0
+        let mut win_sent: u16 = 0;
         let mut more = window.fill()?;
 
         let mut timeout_end = Instant::now() + self.opt_common.timeout;
         let mut retry_cnt = 0;
+        let transfer_limiter = self.opt_local.rate_limit.map(RateLimiter::new);
 
         if cfg!(windows) {
             // On Windows, recv can return up to 15ms before timeout
@@ -166,11 +488,13 @@ impl<T: Socket + ?Sized> Worker<T> {
         if check_response {
             self.check_response()?;
         }
+        self.notify(|handler, info| handler.on_transfer_start(info));
 
         self.socket.set_nonblocking(true)?;
 
         loop {
-            if let Some(frame) = window.get_elements().get(win_idx as usize) {
+            self.check_stopped()?;
+            if let Some(frame) = window.frame(win_idx) {
                 let mut block_seq_tx = block_seq_win.wrapping_add(win_idx + 1);
                 if block_seq_tx < block_seq_win {
                     match self.opt_local.rollover {
@@ -180,11 +504,15 @@ impl<T: Socket + ?Sized> Worker<T> {
                     }
                 }
 
-                self.send_packet(&Packet::Data {
-                    block_num: block_seq_tx,
-                    data: frame.to_vec(),
-                })?;
+                for limiter in transfer_limiter
+                    .iter()
+                    .chain(self.rate_limiters.iter().map(Arc::as_ref))
+                {
+                    limiter.acquire(frame.len());
+                }
+                self.send_data(block_seq_tx, frame)?;
                 win_idx += 1;
+                win_sent = win_sent.max(win_idx);
 
                 if win_idx < window.len() {
                     if !self.opt_common.window_wait.is_zero() {
@@ -196,14 +524,27 @@ impl<T: Socket + ?Sized> Worker<T> {
                 }
             }
 
-            let mut last_ack: Option<u16> = None;
+            // Highest Ack received for the frames sent so far, with its distance from the window start
+            let mut best_ack: Option<(u16, u16)> = None;
+            let mut draining = false;
             loop {
-                match self.socket.recv() {
+                match self.recv_packet(ACK_BUFFER_SIZE) {
                     Ok(Packet::Ack(block_seq_rx)) => {
-                        if last_ack.is_none() {
+                        if !draining {
                             self.socket.set_nonblocking(true)?;
+                            draining = true;
+                        }
+                        // Retransmissions make clients repeat Acks, which may arrive late or
+                        // out of order: only an Ack for a frame already sent moves the window
+                        let diff =
+                            ack_distance(block_seq_win, block_seq_rx, self.opt_local.rollover);
+                        if diff == 0 || diff > win_sent {
+                            log::debug!(
+                                "      Ignoring stale or duplicate Ack {block_seq_rx} (prev {block_seq_win})"
+                            );
+                        } else if best_ack.is_none_or(|(_, best)| diff > best) {
+                            best_ack = Some((block_seq_rx, diff));
                         }
-                        last_ack = Some(block_seq_rx);
                         continue;
                     }
 
@@ -211,37 +552,35 @@ impl<T: Socket + ?Sized> Worker<T> {
                         return Err(anyhow::anyhow!("Received error code {code}: {msg}"));
                     }
 
-                    Ok(_) => log::info!("  Received unexpected packet"),
+                    Ok(packet) => log::info!("  Received unexpected packet {packet}"),
 
                     Err(e) => {
                         if let Some(io_e) = e.downcast_ref::<std::io::Error>() {
                             match io_e.kind() {
                                 /* On non-blocking sockets, Windows returns WouldBlock and Unix TimedOut */
                                 ErrorKind::WouldBlock | ErrorKind::TimedOut => {
-                                    if let Some(ack) = last_ack {
-                                        let mut diff = ack.wrapping_sub(block_seq_win);
-                                        if ack < block_seq_win
-                                            && self.opt_local.rollover == Rollover::Enforce1
-                                        {
-                                            diff -= 1;
-                                        }
-
-                                        if diff == 0 {
-                                            break;
-                                        } else if diff <= self.opt_common.window_size {
-                                            block_seq_win = ack;
-                                            window.remove(diff)?;
-                                            if !more && window.is_empty() {
-                                                return Ok(());
-                                            }
-                                            more = more && window.fill()?;
-                                            win_idx = 0;
-                                            break;
-                                        } else {
-                                            log::debug!(
-                                                "      Received Ack with unexpected seq {ack} (prev {block_seq_win})"
-                                            );
+                                    if let Some((ack, diff)) = best_ack {
+                                        block_seq_win = ack;
+                                        acked += (0..diff)
+                                            .filter_map(|index| window.frame(index))
+                                            .map(|frame| frame.len() as u64)
+                                            .sum::<u64>();
+                                        window.remove(diff)?;
+                                        self.notify(|handler, info| {
+                                            handler.on_block(info, ack, acked)
+                                        });
+                                        if !more && window.is_empty() {
+                                            return Ok(acked);
                                         }
+                                        more = more && window.fill()?;
+                                        // The window slides when the Ack covers every frame
+                                        // sent, otherwise it reports a loss and sending
+                                        // restarts right after it, as defined by RFC 7440
+                                        win_idx = 0;
+                                        win_sent = 0;
+                                        timeout_end = Instant::now() + self.opt_common.timeout;
+                                        self.socket.set_nonblocking(win_idx < window.len())?;
+                                        break;
                                     }
                                     if win_idx < window.len() && Instant::now() < timeout_end {
                                         break;
@@ -287,8 +626,23 @@ impl<T: Socket + ?Sized> Worker<T> {
         anyhow::anyhow!("Block counter rollover error")
     }
 
-    fn receive_file(mut self, file: File) -> anyhow::Result<u64> {
+    /// Receives the file into `file`, returning its size and the number of
+    /// its last block, left unacknowledged until the upload is stored.
+    fn receive_file(&mut self, mut file: Box<dyn FileWriter>) -> anyhow::Result<(u64, u16)> {
+        if let Some(tsize) = self.opt_common.transfer_size
+            && let Err(err) = file.allocate(tsize)
+        {
+            self.send_packet(&Packet::Error {
+                code: ErrorCode::DiskFull,
+                msg: "cannot allocate space for file".to_string(),
+            })?;
+            return Err(anyhow::anyhow!("Cannot preallocate {tsize} bytes: {err}"));
+        }
+
+        self.notify(|handler, info| handler.on_transfer_start(info));
+
         let mut block_number: u16 = 0;
+        let mut received: u64 = 0;
         let mut window = Window::new(
             self.opt_common.window_size,
             self.opt_common.block_size,
@@ -300,12 +654,10 @@ impl<T: Socket + ?Sized> Worker<T> {
         let mut listen_all = false;
         let mut send_ack = false;
 
-        while !last {
+        loop {
             while !send_ack {
-                match self
-                    .socket
-                    .recv_with_size(self.opt_common.block_size as usize)
-                {
+                self.check_stopped()?;
+                match self.recv_packet(self.opt_common.block_size as usize) {
                     Ok(Packet::Data {
                         block_num: received_block_number,
                         data,
@@ -340,7 +692,20 @@ impl<T: Socket + ?Sized> Worker<T> {
                         if received_block_number == new_block_number {
                             block_number = received_block_number;
                             last = data.len() < self.opt_common.block_size as usize;
+                            received += data.len() as u64;
+                            if let Some(quota) = &mut self.quota
+                                && !quota.consume(data.len() as u64)
+                            {
+                                self.send_packet(&Packet::Error {
+                                    code: ErrorCode::DiskFull,
+                                    msg: "upload quota exceeded".to_string(),
+                                })?;
+                                return Err(anyhow::anyhow!("Upload quota exceeded"));
+                            }
                             window.add(data)?;
+                            self.notify(|handler, info| {
+                                handler.on_block(info, block_number, received)
+                            });
                             send_ack = window.is_full() || last;
                         } else {
                             log::debug!(
@@ -355,7 +720,7 @@ impl<T: Socket + ?Sized> Worker<T> {
                     Ok(Packet::Error { code, msg }) => {
                         return Err(anyhow::anyhow!("Received error '{code}': {msg}"));
                     }
-                    Ok(_) => log::info!("  Received unexpected packet"),
+                    Ok(packet) => log::info!("  Received unexpected packet {packet}"),
 
                     Err(e) => {
                         if let Some(io_e) = e.downcast_ref::<std::io::Error>() {
@@ -394,22 +759,94 @@ impl<T: Socket + ?Sized> Worker<T> {
             }
 
             window.empty()?;
+            if last {
+                break;
+            }
             self.send_packet(&Packet::Ack(block_number))?;
             send_ack = false;
         }
 
-        // we should wait and listen a bit more as per RFC 1350 section 6
+        if let Err(err) = window.into_inner().finish(received) {
+            self.refuse_upload("cannot write file");
+            return Err(err.into());
+        }
+
+        Ok((received, block_number))
+    }
+
+    /// Acknowledges the last block of a stored upload, then dallies for its
+    /// retransmissions.
+    fn acknowledge_upload(self, last_block: u16, block_size: u16, timeout: Duration) {
+        if let Err(err) = self.send_packet(&Packet::Ack(last_block)) {
+            log::warn!("  Cannot acknowledge block {last_block}: {err}");
+        }
+        dally(self.socket, block_size, timeout);
+    }
 
-        window.file_len()
+    /// Tells the client its upload was not stored, instead of acknowledging
+    /// its last block.
+    fn refuse_upload(&self, msg: &str) {
+        let _ = self.send_packet(&Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: msg.to_string(),
+        });
     }
 
     fn send_packet(&self, packet: &Packet) -> anyhow::Result<()> {
+        log::trace!("  Sending {packet}");
+        self.send_with(|| self.socket.send(packet))
+    }
+
+    /// Receives a packet from the client and records the activity of the
+    /// transfer. Packets from any other remote are answered with an unknown
+    /// transfer ID error, as RFC 1350 requires, and the wait goes on.
+    fn recv_packet(&self, size: usize) -> anyhow::Result<Packet> {
+        let remote = self.socket.remote_addr().ok();
+        loop {
+            let (packet, from) = self.socket.recv_from_with_size(size)?;
+            if remote.is_none_or(|remote| remote == from) {
+                if let Some(activity) = &self.activity {
+                    activity.touch();
+                }
+                log::trace!("  Received {packet}");
+                return Ok(packet);
+            }
+            reject_unknown_tid(&*self.socket, &packet, &from);
+        }
+    }
+
+    /// Fails once the transfer is reaped for being idle too long, or aborted
+    /// by an administrator, the client being sent an error then.
+    fn check_stopped(&self) -> anyhow::Result<()> {
+        match &self.activity {
+            Some(activity) if activity.is_aborted() => {
+                let _ = self.socket.send(&Packet::Error {
+                    code: ErrorCode::NotDefined,
+                    msg: "Transfer aborted by the server administrator".to_string(),
+                });
+                Err(anyhow::anyhow!("Transfer aborted by the administrator"))
+            }
+            Some(activity) if activity.is_reaped() => Err(anyhow::anyhow!(
+                "Transfer reaped after {}s without packets from the client",
+                activity.idle().as_secs()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn send_data(&self, block_num: u16, data: &[u8]) -> anyhow::Result<()> {
+        self.send_with(|| self.socket.send_data(block_num, data))
+    }
+
+    /// Sends with `send`, as many times as packets are repeated, waiting for
+    /// a full socket buffer to drain.
+    fn send_with(&self, send: impl Fn() -> anyhow::Result<()>) -> anyhow::Result<()> {
         for i in 0..self.opt_local.repeat_count {
             if i > 0 {
                 thread::sleep(DEFAULT_DUPLICATE_DELAY);
             }
             loop {
-                match self.socket.send(packet) {
+                match send() {
                     Ok(_) => break,
                     Err(e) => {
                         if let Some(io_e) = e.downcast_ref::<std::io::Error>() {
@@ -417,6 +854,17 @@ impl<T: Socket + ?Sized> Worker<T> {
                                 thread::sleep(DEFAULT_DUPLICATE_DELAY);
                                 continue;
                             }
+                            if is_message_too_large(io_e) {
+                                // The path MTU is only discovered once data flows, too late to renegotiate
+                                let _ = self.socket.send(&Packet::Error {
+                                    code: ErrorCode::RefusedOption,
+                                    msg: "block size too large for network path".to_string(),
+                                });
+                                return Err(anyhow::anyhow!(
+                                    "Block size {} too large for network path, retry with a smaller blksize",
+                                    self.opt_common.block_size
+                                ));
+                            }
                             return Err(e);
                         }
                     }
@@ -428,20 +876,226 @@ impl<T: Socket + ?Sized> Worker<T> {
     }
 
     fn check_response(&self) -> anyhow::Result<()> {
-        let pkt = self.socket.recv()?;
-        if let Packet::Ack(received_block_number) = pkt {
-            if received_block_number == 0 {
-                return Ok(());
+        // A client that lost the OACK resends its request, answered by the
+        // server, so its ACK may take a few timeouts to arrive
+        let mut retry_cnt = 0;
+        let pkt = loop {
+            self.check_stopped()?;
+            match self.recv_packet(ACK_BUFFER_SIZE) {
+                Ok(Packet::Ack(0)) => return Ok(()),
+                Ok(pkt) => break pkt,
+                Err(e) => match e.downcast_ref::<std::io::Error>() {
+                    Some(io_e)
+                        if matches!(io_e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                            && retry_cnt < self.opt_local.max_retries =>
+                    {
+                        retry_cnt += 1;
+                        log::debug!(
+                            "  Oack response timeout {}/{}",
+                            retry_cnt,
+                            self.opt_local.max_retries
+                        );
+                    }
+                    _ => return Err(e),
+                },
             }
-        }
+        };
 
+        // Error packets are not answered, error 8 ends option negotiation
+        if let Packet::Error { code, msg } = pkt {
+            return Err(anyhow::anyhow!(
+                "Client ended option negotiation with error {code}: {msg}"
+            ));
+        }
         self.socket.send(&Packet::Error {
             code: ErrorCode::IllegalOperation,
             msg: "invalid oack response".to_string(),
         })?;
 
         Err(anyhow::anyhow!(
-            "Unexpected packet received instead of Ack(0): {pkt:#?}"
+            "Unexpected packet {pkt} received instead of Ack(0)"
         ))
     }
 }
+
+pub(super) fn log_checksum(checksum: Option<DigestAlgorithm>, file_path: &Path, fs: &dyn TftpFs) {
+    if let Some(algorithm) = checksum {
+        let digest = fs
+            .open_read(file_path, 0)
+            .map_err(anyhow::Error::from)
+            .and_then(|reader| algorithm.digest_reader(reader));
+        match digest {
+            Ok(hex) => log::info!(
+                "  {algorithm} {} {hex}",
+                file_path.file_name().unwrap().to_string_lossy()
+            ),
+            Err(err) => log::warn!("  Could not compute {algorithm} checksum: {err}"),
+        }
+    }
+}
+
+/// Frames `trait` is what sending needs of a window, whether its frames are
+/// read into a [`Window`] or slices of a [`MappedWindow`].
+trait Frames {
+    /// Fills the window, returning `true` if more frames follow.
+    fn fill(&mut self) -> anyhow::Result<bool>;
+    /// Returns the frame at `index` in the window.
+    fn frame(&self, index: u16) -> Option<&[u8]>;
+    /// Removes the first `amount` of frames from the window.
+    fn remove(&mut self, amount: u16) -> anyhow::Result<()>;
+    fn len(&self) -> u16;
+    fn is_empty(&self) -> bool;
+}
+
+impl<R: Read> Frames for Window<R> {
+    fn fill(&mut self) -> anyhow::Result<bool> {
+        Window::fill(self)
+    }
+
+    fn frame(&self, index: u16) -> Option<&[u8]> {
+        self.get(index).map(|(_, frame)| frame)
+    }
+
+    fn remove(&mut self, amount: u16) -> anyhow::Result<()> {
+        Window::remove(self, amount)
+    }
+
+    fn len(&self) -> u16 {
+        Window::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Window::is_empty(self)
+    }
+}
+
+impl<M: AsRef<[u8]>> Frames for MappedWindow<M> {
+    fn fill(&mut self) -> anyhow::Result<bool> {
+        Ok(MappedWindow::fill(self))
+    }
+
+    fn frame(&self, index: u16) -> Option<&[u8]> {
+        self.get(index)
+    }
+
+    fn remove(&mut self, amount: u16) -> anyhow::Result<()> {
+        MappedWindow::remove(self, amount)
+    }
+
+    fn len(&self) -> u16 {
+        MappedWindow::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        MappedWindow::is_empty(self)
+    }
+}
+
+/// Appends the entry of the upload of `file_path` to `manifest` and POSTs it
+/// to `webhook`, if any. A failure is only logged, the upload itself
+/// succeeded.
+fn record_upload(
+    manifest: Option<&Manifest>,
+    webhook: Option<&UploadWebhook>,
+    file_path: &Path,
+    size: u64,
+    client: &SocketAddr,
+    fs: &dyn TftpFs,
+) {
+    if manifest.is_none() && webhook.is_none() {
+        return;
+    }
+    let sha256 = match fs
+        .open_read(file_path, 0)
+        .map_err(anyhow::Error::from)
+        .and_then(|reader| DigestAlgorithm::Sha256.digest_reader(reader))
+    {
+        Ok(sha256) => sha256,
+        Err(err) => {
+            log::warn!("  Could not hash {}: {err}", file_path.display());
+            return;
+        }
+    };
+    if let Some(manifest) = manifest
+        && let Err(err) = manifest.record(file_path, size, &sha256, client)
+    {
+        log::warn!(
+            "  Could not record {} in the manifest: {err}",
+            file_path.display()
+        );
+    }
+    if let Some(webhook) = webhook {
+        webhook.post(file_path, size, &sha256, client);
+    }
+}
+
+/// Number of blocks `ack` acknowledges past the window start `block_seq_win`,
+/// accounting for a block counter rolling over to 1 instead of 0.
+pub(super) fn ack_distance(block_seq_win: u16, ack: u16, rollover: Rollover) -> u16 {
+    let diff = ack.wrapping_sub(block_seq_win);
+    if ack < block_seq_win && rollover == Rollover::Enforce1 {
+        diff.wrapping_sub(1)
+    } else {
+        diff
+    }
+}
+
+/// Creates the temporary file of an upload to `file_path`, named after it
+/// with `suffix` appended: `<name><suffix>`, or `<name>.1<suffix>`,
+/// `<name>.2<suffix>`… while other uploads of the same file write to these.
+fn create_tmp_file(fs: &dyn TftpFs, file_path: &Path, suffix: &str) -> io::Result<PathBuf> {
+    let name = file_path.file_name().unwrap_or_default();
+    for index in 0u64.. {
+        let mut tmp_name = name.to_os_string();
+        if index > 0 {
+            tmp_name.push(format!(".{index}"));
+        }
+        tmp_name.push(suffix);
+        let path = file_path.with_file_name(tmp_name);
+        match fs.create_new(&path) {
+            Ok(()) => return Ok(path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+/// Moves a complete upload from `tmp_path` into place, keeping the file it
+/// replaces as a version if `versioned`.
+fn commit_upload(
+    fs: &dyn TftpFs,
+    tmp_path: &Path,
+    file_path: &Path,
+    versioned: bool,
+) -> io::Result<()> {
+    if versioned {
+        keep_version(fs, file_path)?;
+    }
+    fs.rename(tmp_path, file_path)
+}
+
+/// Moves a journaled upload into place. Returns `true` if the journal records
+/// identical content for the unmodified target, in which case the upload is
+/// discarded instead.
+fn commit_journaled(
+    journal: &Journal,
+    tmp_path: &Path,
+    file_path: &Path,
+    versioned: bool,
+) -> anyhow::Result<bool> {
+    let hash = JOURNAL_DIGEST.digest_file(tmp_path)?;
+
+    if journal.contains(file_path, &hash) {
+        fs::remove_file(tmp_path)?;
+        return Ok(true);
+    }
+
+    if versioned {
+        keep_version(&DiskFs, file_path)?;
+    }
+    fs::rename(tmp_path, file_path)?;
+    journal.record(file_path, &hash)?;
+
+    Ok(false)
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+
+use super::provider::{FileProvider, normalize_name, skip};
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+const CENTRAL_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_SIGNATURE: u32 = 0x0403_4b50;
+const EOCD_LEN: usize = 22;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// An entry of the central directory
+#[derive(Debug, Clone, PartialEq)]
+struct ZipEntry {
+    method: u16,
+    compressed_size: u64,
+    size: u64,
+    header_offset: u64,
+}
+
+/// ZipArchive `struct` serves the files of a `.zip` archive without
+/// extracting it. The central directory is read once when the archive is
+/// opened and kept in memory, so each request is a single lookup.
+///
+/// Stored and deflated entries are supported, including ZIP64 archives.
+pub struct ZipArchive {
+    path: PathBuf,
+    entries: HashMap<String, ZipEntry>,
+}
+
+impl ZipArchive {
+    /// Opens the archive at `path` and caches its central directory.
+    pub fn open(path: &Path) -> anyhow::Result<ZipArchive> {
+        let mut file = File::open(path)?;
+        let entries = read_central_directory(&mut file)?;
+        log::info!("Serving {} files from {}", entries.len(), path.display());
+
+        Ok(ZipArchive {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    fn entry(&self, name: &str) -> io::Result<&ZipEntry> {
+        self.entries
+            .get(&normalize_name(name))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+impl FileProvider for ZipArchive {
+    fn size(&self, name: &str) -> io::Result<u64> {
+        Ok(self.entry(name)?.size)
+    }
+
+    fn open(&self, name: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let entry = self.entry(name)?;
+        let mut file = File::open(&self.path)?;
+
+        // The local header repeats the name and may have a different extra field
+        let mut header = [0; 30];
+        file.seek(SeekFrom::Start(entry.header_offset))?;
+        file.read_exact(&mut header)?;
+        if u32_at(&header, 0) != LOCAL_SIGNATURE {
+            return Err(invalid("invalid local file header"));
+        }
+        let data_offset = entry.header_offset
+            + header.len() as u64
+            + u16_at(&header, 26) as u64
+            + u16_at(&header, 28) as u64;
+
+        match entry.method {
+            METHOD_STORED => {
+                let offset = offset.min(entry.size);
+                file.seek(SeekFrom::Start(data_offset + offset))?;
+                Ok(Box::new(file.take(entry.size - offset)))
+            }
+            METHOD_DEFLATED => {
+                file.seek(SeekFrom::Start(data_offset))?;
+                let mut reader = DeflateDecoder::new(file.take(entry.compressed_size));
+                skip(&mut reader, offset)?;
+                Ok(Box::new(reader))
+            }
+            method => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported compression method {method}"),
+            )),
+        }
+    }
+}
+
+fn read_central_directory(file: &mut File) -> anyhow::Result<HashMap<String, ZipEntry>> {
+    let len = file.metadata()?.len();
+    if len < EOCD_LEN as u64 {
+        anyhow::bail!("not a zip archive");
+    }
+    // The end record is followed by a comment of at most 64 KiB
+    let tail_len = len.min((EOCD_LEN + u16::MAX as usize) as u64);
+    let mut tail = vec![0; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    file.read_exact(&mut tail)?;
+
+    let eocd = (0..=tail.len().saturating_sub(EOCD_LEN))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == EOCD_SIGNATURE)
+        .ok_or_else(|| anyhow::anyhow!("not a zip archive"))?;
+
+    let mut count = u16_at(&tail, eocd + 10) as u64;
+    let mut cd_size = u32_at(&tail, eocd + 12) as u64;
+    let mut cd_offset = u32_at(&tail, eocd + 16) as u64;
+
+    if (count == 0xFFFF || cd_size == 0xFFFF_FFFF || cd_offset == 0xFFFF_FFFF)
+        && eocd >= 20
+        && u32_at(&tail, eocd - 20) == ZIP64_LOCATOR_SIGNATURE
+    {
+        let mut record = [0; 56];
+        file.seek(SeekFrom::Start(u64_at(&tail, eocd - 20 + 8)))?;
+        file.read_exact(&mut record)?;
+        if u32_at(&record, 0) != ZIP64_EOCD_SIGNATURE {
+            anyhow::bail!("invalid zip64 end of central directory");
+        }
+        count = u64_at(&record, 32);
+        cd_size = u64_at(&record, 40);
+        cd_offset = u64_at(&record, 48);
+    }
+
+    if cd_offset + cd_size > len {
+        anyhow::bail!("central directory past end of file");
+    }
+    let mut cd = vec![0; cd_size as usize];
+    file.seek(SeekFrom::Start(cd_offset))?;
+    file.read_exact(&mut cd)?;
+
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        if pos + 46 > cd.len() || u32_at(&cd, pos) != CENTRAL_SIGNATURE {
+            anyhow::bail!("invalid central directory entry");
+        }
+        let flags = u16_at(&cd, pos + 8);
+        let method = u16_at(&cd, pos + 10);
+        let mut compressed_size = u32_at(&cd, pos + 20) as u64;
+        let mut size = u32_at(&cd, pos + 24) as u64;
+        let name_len = u16_at(&cd, pos + 28) as usize;
+        let extra_len = u16_at(&cd, pos + 30) as usize;
+        let comment_len = u16_at(&cd, pos + 32) as usize;
+        let mut header_offset = u32_at(&cd, pos + 42) as u64;
+
+        let name_start = pos + 46;
+        let extra_start = name_start + name_len;
+        let next = extra_start + extra_len + comment_len;
+        if next > cd.len() {
+            anyhow::bail!("invalid central directory entry");
+        }
+        let name = String::from_utf8_lossy(&cd[name_start..extra_start]).to_string();
+
+        // ZIP64 values replace the 32 bit fields set to 0xFFFFFFFF, in this order
+        let mut extra = &cd[extra_start..extra_start + extra_len];
+        while extra.len() >= 4 {
+            let id = u16_at(extra, 0);
+            let len = (u16_at(extra, 2) as usize).min(extra.len() - 4);
+            if id == ZIP64_EXTRA_ID {
+                let mut values = extra[4..4 + len].chunks_exact(8).map(|v| u64_at(v, 0));
+                for field in [&mut size, &mut compressed_size, &mut header_offset] {
+                    if *field == 0xFFFF_FFFF
+                        && let Some(value) = values.next()
+                    {
+                        *field = value;
+                    }
+                }
+            }
+            extra = &extra[4 + len..];
+        }
+        pos = next;
+
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            log::warn!("Skipping encrypted zip entry {name}");
+            continue;
+        }
+
+        entries.insert(
+            normalize_name(&name),
+            ZipEntry {
+                method,
+                compressed_size,
+                size,
+                header_offset,
+            },
+        );
+    }
+
+    Ok(entries)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::DeflateEncoder};
+    use std::{fs, io::Write};
+
+    const DIR_NAME: &str = "target/test";
+
+    /// Builds a zip archive holding `files`, deflating those flagged
+    fn build_zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut central = Vec::new();
+
+        for (name, content, deflate) in files {
+            let (method, data) = if *deflate {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content).unwrap();
+                (METHOD_DEFLATED, encoder.finish().unwrap())
+            } else {
+                (METHOD_STORED, content.to_vec())
+            };
+            let crc = crc32fast::hash(content);
+            let offset = zip.len() as u32;
+
+            zip.extend_from_slice(&LOCAL_SIGNATURE.to_le_bytes());
+            zip.extend_from_slice(&[20, 0, 0, 0]);
+            zip.extend_from_slice(&method.to_le_bytes());
+            zip.extend_from_slice(&[0; 4]);
+            zip.extend_from_slice(&crc.to_le_bytes());
+            zip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            zip.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend_from_slice(&[0, 0]);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&data);
+
+            central.extend_from_slice(&CENTRAL_SIGNATURE.to_le_bytes());
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            central.extend_from_slice(&method.to_le_bytes());
+            central.extend_from_slice(&[0; 4]);
+            central.extend_from_slice(&crc.to_le_bytes());
+            central.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+
+        let cd_offset = zip.len() as u32;
+        zip.extend_from_slice(&central);
+        zip.extend_from_slice(&EOCD_SIGNATURE.to_le_bytes());
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&cd_offset.to_le_bytes());
+        zip.extend_from_slice(&[0, 0]);
+        zip
+    }
+
+    fn read_all(archive: &ZipArchive, name: &str, offset: u64) -> Vec<u8> {
+        let mut content = Vec::new();
+        archive
+            .open(name, offset)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn serves_stored_and_deflated_entries() {
+        let kernel: Vec<u8> = (0..10_000u32).map(|i| (i % 13) as u8).collect();
+        let zip = build_zip(&[
+            ("boot/", b"", false),
+            ("boot/vmlinuz", &kernel, true),
+            ("readme.txt", b"Hello, zip!", false),
+        ]);
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        let path = PathBuf::from(DIR_NAME).join("serves_stored_and_deflated_entries.zip");
+        fs::write(&path, zip).unwrap();
+        let archive = ZipArchive::open(&path).unwrap();
+
+        assert_eq!(archive.entries.len(), 2);
+        assert_eq!(archive.size("/boot/vmlinuz").unwrap(), kernel.len() as u64);
+        assert_eq!(read_all(&archive, "boot\\vmlinuz", 0), kernel);
+        assert_eq!(read_all(&archive, "boot/vmlinuz", 9_000), &kernel[9_000..]);
+        assert_eq!(read_all(&archive, "readme.txt", 7), b"zip!");
+        assert_eq!(
+            archive.size("boot").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let path = PathBuf::from(DIR_NAME).join("rejects_other_files.zip");
+        fs::write(&path, b"not a zip archive at all").unwrap();
+        assert!(ZipArchive::open(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Version and capability introspection
+//!
+//! `xtool version --json` describes the build for orchestration layers, which
+//! can gate their behavior on the capabilities of the deployed binary. Fields
+//! are only ever added to the JSON output, `format` changes if any existing
+//! field changes meaning.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::tftp::core::options::{
+    DEFAULT_BLOCK_SIZE, DEFAULT_WINDOW_SIZE, MAX_BLOCK_SIZE, MAX_TIMEOUT, MAX_WINDOW_SIZE,
+};
+use crate::tftp::core::{DigestAlgorithm, OptionType};
+
+/// Version of the JSON layout
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Description of this build
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub format: u32,
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Short hash of the commit built, `unknown` outside a git checkout
+    pub git_hash: &'static str,
+    /// Enabled cargo features
+    pub features: Vec<&'static str>,
+    pub platform: Platform,
+    pub tftp: TftpCapabilities,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Platform {
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// Target triple, e.g. `x86_64-unknown-linux-gnu`
+    pub target: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TftpCapabilities {
+    /// Options negotiated in requests, e.g. `blksize`
+    pub options: Vec<&'static str>,
+    pub blksize: Range,
+    pub windowsize: Range,
+    /// Timeout in seconds
+    pub timeout: Range,
+    /// Checksum algorithms of `--checksum` and `--verify`
+    pub checksums: Vec<&'static str>,
+}
+
+/// Accepted values of an option
+#[derive(Debug, Serialize)]
+pub struct Range {
+    pub min: u64,
+    pub max: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<u64>,
+}
+
+impl BuildInfo {
+    /// Describes the running binary
+    pub fn current() -> Self {
+        Self {
+            format: FORMAT_VERSION,
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("XTOOL_GIT_HASH"),
+            features: env!("XTOOL_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+            platform: Platform {
+                os: std::env::consts::OS,
+                arch: std::env::consts::ARCH,
+                target: env!("XTOOL_TARGET"),
+            },
+            tftp: TftpCapabilities {
+                options: OptionType::ALL.iter().map(|o| o.as_str()).collect(),
+                blksize: Range {
+                    min: 1,
+                    max: MAX_BLOCK_SIZE as u64,
+                    default: Some(DEFAULT_BLOCK_SIZE as u64),
+                },
+                windowsize: Range {
+                    min: 1,
+                    max: MAX_WINDOW_SIZE as u64,
+                    default: Some(DEFAULT_WINDOW_SIZE as u64),
+                },
+                timeout: Range {
+                    min: 1,
+                    max: MAX_TIMEOUT,
+                    default: None,
+                },
+                checksums: DigestAlgorithm::ALL.iter().map(|a| a.as_str()).collect(),
+            },
+        }
+    }
+}
+
+/// Prints the description of the build, as JSON if `json` is set
+pub fn run(json: bool) -> Result<()> {
+    let info = BuildInfo::current();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    println!("{} {} ({})", info.name, info.version, info.git_hash);
+    println!(
+        "platform:  {} {} ({})",
+        info.platform.os, info.platform.arch, info.platform.target
+    );
+    if !info.features.is_empty() {
+        println!("features:  {}", info.features.join(", "));
+    }
+    println!("options:   {}", info.tftp.options.join(", "));
+    println!(
+        "blksize:   {}-{}, windowsize: {}-{}",
+        info.tftp.blksize.min,
+        info.tftp.blksize.max,
+        info.tftp.windowsize.min,
+        info.tftp.windowsize.max
+    );
+    println!("checksums: {}", info.tftp.checksums.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_layout() {
+        let json = serde_json::to_value(BuildInfo::current()).unwrap();
+        assert_eq!(json["format"], FORMAT_VERSION);
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["tftp"]["blksize"]["max"], 65464);
+        assert_eq!(json["tftp"]["windowsize"]["max"], 65535);
+        assert!(json["tftp"]["timeout"].get("default").is_none());
+        assert!(
+            json["tftp"]["options"]
+                .as_array()
+                .unwrap()
+                .contains(&"windowsize".into())
+        );
+    }
+}
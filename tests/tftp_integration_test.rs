@@ -1,11 +1,24 @@
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Write};
+use std::net::UdpSocket;
 use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
-use xtool::tftp::client::Client;
+use xtool::events::{Event, EventBus};
 use xtool::tftp::client::config::ClientConfig;
-use xtool::tftp::server::{Config, Server};
+use xtool::tftp::client::matrix::{self, MatrixOptions};
+use xtool::tftp::client::soak::{self, SoakOptions};
+use xtool::tftp::client::{Client, ClientError, Pattern, PatternReader, PingStatus, ResumeState};
+use xtool::tftp::core::options::Rollover;
+use xtool::tftp::core::{
+    DigestAlgorithm, ErrorCode, MemoryNetwork, OptionType, Packet, PeerSocket, Session,
+    TftpTransport, TransferOption, wait_for_dally,
+};
+use xtool::tftp::server::{
+    AsyncServer, BootMapping, Config, Direction, JsonLog, MANIFEST_FILENAME, MemoryFs,
+    OverwritePolicy, Priority, PriorityClass, RewriteRule, Server, ServerHandler, ShutdownHandle,
+    SymlinkPolicy, TransferInfo, Worker, replay,
+};
 
 // Use serial_test to prevent port conflicts
 use serial_test::serial;
@@ -26,6 +39,25 @@ fn cleanup_test_env(test_dir: &PathBuf) {
     let _ = fs::remove_dir_all(test_dir);
 }
 
+/// Reads `path` once it has `lines` lines, which the server appends after the
+/// transfers end, or fails after 5 seconds.
+fn wait_for_lines(path: &PathBuf, lines: usize) -> String {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        if content.lines().count() >= lines {
+            return content;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "{} has {} lines, expected {lines}",
+            path.display(),
+            content.lines().count()
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
 fn start_test_server(port: u16, root_dir: PathBuf) -> thread::JoinHandle<()> {
     thread::spawn(move || {
         let config =
@@ -215,9 +247,4340 @@ fn test_nonexistent_file() {
     let result = client.get("nonexistent.txt", &local_file);
 
     assert!(
-        result.is_err(),
-        "Should fail when downloading non-existent file"
+        matches!(
+            result,
+            Err(ClientError::ServerError {
+                code: ErrorCode::FileNotFound,
+                ..
+            })
+        ),
+        "Should fail with FileNotFound when downloading non-existent file: {:?}",
+        result
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_resume_download() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    // Create test file
+    let test_content: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    fs::write(server_dir.join("resume.bin"), &test_content).unwrap();
+
+    // Simulate an interrupted download with trailing data past the checkpoint
+    let local_file = client_dir.join("resume.bin");
+    let mut partial = test_content[..40_000].to_vec();
+    partial.extend_from_slice(&[0xFF; 1000]);
+    fs::write(&local_file, &partial).unwrap();
+
+    let port = 7005;
+    let mut state = ResumeState::new(&format!("127.0.0.1:{port}"), "resume.bin", &local_file);
+    state.received = 40_000;
+    state.transfer_size = Some(test_content.len() as u64);
+    state.save().unwrap();
+
+    // Start server
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_block_size(512)
+        .with_timeout(Duration::from_secs(5));
+
+    let client = Client::new(config).unwrap();
+    let result = client.resume("resume.bin", &local_file);
+
+    assert!(result.is_ok(), "Resume failed: {:?}", result.err());
+    assert_eq!(fs::read(&local_file).unwrap(), test_content);
+    assert!(ResumeState::load(&local_file).unwrap().is_none());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_resume_from_partial_file() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content: Vec<u8> = (0..60_000).map(|i| (i % 241) as u8).collect();
+    fs::write(server_dir.join("partial.bin"), &test_content).unwrap();
+
+    // A partial download left behind by another tool, without saved state
+    let local_file = client_dir.join("partial.bin");
+    fs::write(&local_file, &test_content[..25_000]).unwrap();
+
+    let port = 7013;
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_block_size(512)
+        .with_timeout(Duration::from_secs(5));
+
+    let first_progress = std::sync::Arc::new(std::sync::Mutex::new(None));
+    let recorder = first_progress.clone();
+    let client = Client::new(config)
+        .unwrap()
+        .with_progress(move |transferred, _| {
+            recorder.lock().unwrap().get_or_insert(transferred);
+        });
+    let result = client.resume("partial.bin", &local_file);
+
+    assert!(result.is_ok(), "Resume failed: {:?}", result.err());
+    assert_eq!(fs::read(&local_file).unwrap(), test_content);
+    // The transfer continued from the local length instead of restarting
+    assert!(first_progress.lock().unwrap().unwrap() > 25_000);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_custom_options_ignored_by_server() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content = b"Vendor extension test";
+    fs::write(server_dir.join("custom.txt"), test_content).unwrap();
+
+    let port = 7006;
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_custom_option("x-vendor", "fast");
+
+    let client = Client::new(config).unwrap();
+    let local_file = client_dir.join("custom.txt");
+    let result = client.get("custom.txt", &local_file);
+
+    assert!(result.is_ok(), "Download failed: {:?}", result.err());
+    assert_eq!(fs::read(&local_file).unwrap(), test_content);
+    // The server does not know the option, so it must be left out of the OACK
+    assert!(client.acknowledged_options().is_empty());
+
+    let config =
+        ClientConfig::new("127.0.0.1".parse().unwrap(), port).with_custom_option("blksize", "1024");
+    assert!(matches!(
+        Client::new(config),
+        Err(ClientError::OptionNegotiation(_))
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_ping() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7007;
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_timeout(Duration::from_millis(300));
+    let client = Client::new(config).unwrap();
+
+    // Nothing listens yet
+    let status = client.ping().unwrap();
+    assert!(matches!(
+        status,
+        PingStatus::NoResponse | PingStatus::Refused
+    ));
+
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let status = client.ping().unwrap();
+    assert!(
+        matches!(
+            status,
+            PingStatus::Alive {
+                code: Some(ErrorCode::FileNotFound),
+                ..
+            }
+        ),
+        "Unexpected ping status: {:?}",
+        status
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_retries_exhausted() {
+    let (_server_dir, client_dir) = setup_test_env();
+    let test_dir = client_dir.parent().unwrap().to_path_buf();
+
+    // No server listens on this port, every request times out
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), 7008)
+        .with_timeout(Duration::from_millis(100))
+        .with_retries(2);
+    let client = Client::new(config).unwrap();
+
+    let start = std::time::Instant::now();
+    let result = client.get("missing.txt", &client_dir.join("missing.txt"));
+    assert!(matches!(result, Err(ClientError::Timeout)), "{:?}", result);
+    // The initial attempt and two retries
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_soak() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7009;
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let options = SoakOptions {
+        duration: Duration::from_secs(1),
+        max_size: 64 * 1024,
+        seed: Some(7),
+        ..Default::default()
+    };
+    let report = soak::run("127.0.0.1", port, &options).unwrap();
+
+    assert!(report.transfers > 0);
+    assert_eq!(report.failures, 0, "{}", report);
+    assert_eq!(report.seed, 7);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_matrix() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let content: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(server_dir.join("matrix.bin"), &content).unwrap();
+
+    let port = 7010;
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let options = MatrixOptions {
+        block_sizes: vec![512, 8192],
+        window_sizes: vec![1, 4],
+        timeouts: vec![Duration::from_secs(1)],
+        remote_file: Some("matrix.bin".to_string()),
+        ..Default::default()
+    };
+    let result = matrix::run("127.0.0.1", port, &options).unwrap();
+    assert_eq!(result.cells.len(), 4);
+    assert_eq!(result.passed(), 4, "{}", result);
+    assert!(result.best().is_some());
+
+    // Uploads ignore the window sizes
+    let options = MatrixOptions {
+        remote_file: None,
+        upload_size: 10_000,
+        ..options
+    };
+    let result = matrix::run("127.0.0.1", port, &options).unwrap();
+    assert_eq!(result.cells.len(), 2);
+    assert_eq!(result.passed(), 2, "{}", result);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_manifest_download() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let kernel: Vec<u8> = (0..20_000u32).map(|i| (i % 241) as u8).collect();
+    let dtb = b"device tree".to_vec();
+    fs::create_dir_all(server_dir.join("boot")).unwrap();
+    fs::write(server_dir.join("boot/zImage"), &kernel).unwrap();
+    fs::write(server_dir.join("board.dtb"), &dtb).unwrap();
+
+    let sha256 = |path: &PathBuf| DigestAlgorithm::Sha256.digest_file(path).unwrap();
+    let manifest = test_dir.join("manifest.txt");
+    fs::write(
+        &manifest,
+        format!(
+            "# boot files\nboot/zImage {} {}\nboard.dtb {} {}\n",
+            kernel.len(),
+            sha256(&server_dir.join("boot/zImage")),
+            dtb.len(),
+            sha256(&server_dir.join("board.dtb")),
+        ),
+    )
+    .unwrap();
+
+    let port = 7011;
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port);
+    let client = Client::new(config).unwrap();
+
+    let summary = client.get_manifest(&manifest, &client_dir).unwrap();
+    assert_eq!(summary.downloaded.len(), 2);
+    assert_eq!(fs::read(client_dir.join("boot/zImage")).unwrap(), kernel);
+
+    // Valid files are not downloaded again, a corrupted one is
+    fs::write(client_dir.join("board.dtb"), b"corrupted!!").unwrap();
+    let summary = client.get_manifest(&manifest, &client_dir).unwrap();
+    assert_eq!(summary.skipped, vec!["boot/zImage".to_string()]);
+    assert_eq!(summary.downloaded, vec!["board.dtb".to_string()]);
+    assert_eq!(fs::read(client_dir.join("board.dtb")).unwrap(), dtb);
+
+    // A file that does not match the manifest fails verification
+    fs::write(server_dir.join("board.dtb"), b"other tree!").unwrap();
+    fs::remove_file(client_dir.join("board.dtb")).unwrap();
+    assert!(matches!(
+        client.get_manifest(&manifest, &client_dir),
+        Err(ClientError::Manifest(_))
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+/// Builds a zip archive with the `files` stored uncompressed
+fn build_stored_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let mut central = Vec::new();
+
+    for (name, content) in files {
+        let offset = zip.len() as u32;
+        let mut header = Vec::new();
+        header.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        header.extend_from_slice(&crc32fast::hash(content).to_le_bytes());
+        header.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&[0, 0]);
+
+        zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        zip.extend_from_slice(&header);
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(content);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&[20, 0]);
+        central.extend_from_slice(&header);
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let cd_offset = zip.len() as u32;
+    zip.extend_from_slice(&central);
+    zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&[0; 4]);
+    zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&cd_offset.to_le_bytes());
+    zip.extend_from_slice(&[0, 0]);
+    zip
+}
+
+#[test]
+#[serial]
+fn test_serve_from_zip() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let kernel: Vec<u8> = (0..30_000u32).map(|i| (i % 199) as u8).collect();
+    let archive = test_dir.join("boot.zip");
+    fs::write(
+        &archive,
+        build_stored_zip(&[("boot/vmlinuz", &kernel), ("readme.txt", b"Hello")]),
+    )
+    .unwrap();
+
+    let port = 7012;
+    let _server_handle = start_test_server(port, archive);
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port);
+    let client = Client::new(config).unwrap();
+
+    let local_file = client_dir.join("vmlinuz");
+    client.get("/boot/vmlinuz", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), kernel);
+
+    let result = client.get("missing.txt", &client_dir.join("missing.txt"));
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::FileNotFound,
+            ..
+        })
+    ));
+
+    // Archives are read-only
+    let upload = client_dir.join("upload.txt");
+    fs::write(&upload, b"data").unwrap();
+    assert!(matches!(
+        client.put(&upload, "upload.txt"),
+        Err(ClientError::ServerError {
+            code: ErrorCode::AccessViolation,
+            ..
+        })
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+/// Builds a tar archive of the `files` in the ustar format
+fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = Vec::new();
+    for (name, content) in files {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(content);
+        tar.resize(tar.len().div_ceil(512) * 512, 0);
+    }
+    tar.extend_from_slice(&[0; 1024]);
+    tar
+}
+
+#[test]
+fn test_serve_from_tar_gz() {
+    let dir = std::env::temp_dir().join(format!("tftp_tar_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let kernel: Vec<u8> = (0..30_000u32).map(|i| (i % 199) as u8).collect();
+    let tar = build_tar(&[("boot/vmlinuz", &kernel), ("readme.txt", b"Hello")]);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&tar).unwrap();
+    let archive = dir.join("bundle.tar.gz");
+    fs::write(&archive, encoder.finish().unwrap()).unwrap();
+
+    let server = Server::spawn_for_test(&archive).unwrap();
+    let addr = server.addr();
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+
+    let local_file = dir.join("vmlinuz");
+    client.get("/boot/vmlinuz", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), kernel);
+    client.get("readme.txt", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"Hello");
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+#[serial]
+fn test_shutdown() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content: Vec<u8> = (0..1_000_000).map(|i| (i % 239) as u8).collect();
+    fs::write(server_dir.join("shutdown.bin"), &test_content).unwrap();
+
+    let port = 7014;
+    let config =
+        Config::default().merge_cli("127.0.0.1".to_string(), port, server_dir, false, false);
+    let mut server = Server::new(&config).unwrap();
+    let handle: ShutdownHandle = server.shutdown_handle();
+    let listener = thread::spawn(move || server.listen());
+
+    // Start a download and shut down while it is in flight
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let local_file = client_dir.join("shutdown.bin");
+    let download_file = local_file.clone();
+    let download = thread::spawn(move || {
+        let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port).with_block_size(512);
+        let client = Client::new(config).unwrap().with_progress(move |_, _| {
+            let _ = started_tx.send(());
+        });
+        client.get("shutdown.bin", &download_file)
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    handle.shutdown_within(Duration::from_secs(30));
+    listener.join().unwrap();
+
+    // The in-flight transfer was allowed to complete
+    let result = download.join().unwrap();
+    assert!(result.is_ok(), "Download failed: {:?}", result.err());
+    assert_eq!(fs::read(&local_file).unwrap(), test_content);
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_timeout(Duration::from_millis(300));
+    let status = Client::new(config).unwrap().ping().unwrap();
+    assert!(matches!(
+        status,
+        PingStatus::NoResponse | PingStatus::Refused
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_async_server() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content: Vec<u8> = (0..300_000).map(|i| (i % 233) as u8).collect();
+    fs::write(server_dir.join("async.bin"), &test_content).unwrap();
+
+    let port = 7015;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = AsyncServer::new(&config).await.unwrap();
+            server.listen().await.unwrap();
+        });
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_block_size(1024)
+        .with_window_size(4)
+        .with_timeout(Duration::from_secs(5));
+    let client = Client::new(config).unwrap();
+
+    let local_file = client_dir.join("async.bin");
+    let result = client.get("async.bin", &local_file);
+    assert!(result.is_ok(), "Download failed: {:?}", result.err());
+    assert_eq!(fs::read(&local_file).unwrap(), test_content);
+
+    let result = client.put(&local_file, "async_upload.bin");
+    assert!(result.is_ok(), "Upload failed: {:?}", result.err());
+    assert_eq!(
+        fs::read(server_dir.join("async_upload.bin")).unwrap(),
+        test_content
+    );
+
+    let result = client.get("missing.bin", &client_dir.join("missing.bin"));
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::FileNotFound,
+            ..
+        })
+    ));
+
+    // Line endings are converted in netascii mode, as by the blocking server
+    fs::write(server_dir.join("startup.cfg"), b"hostname sw1\nbanner \r\n").unwrap();
+    let client = Client::new(ClientConfig {
+        mode: Some("netascii".to_string()),
+        ..ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+    })
+    .unwrap();
+    let local_file = client_dir.join("startup.cfg");
+    client.get("startup.cfg", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"hostname sw1\nbanner \r\n");
+    client.put(&local_file, "running.cfg").unwrap();
+    assert_eq!(
+        fs::read(server_dir.join("running.cfg")).unwrap(),
+        b"hostname sw1\nbanner \r\n"
+    );
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "startup.cfg".to_string(),
+        mode: "netascii".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert_eq!(
+        packet,
+        Packet::Data {
+            block_num: 1,
+            data: b"hostname sw1\r\nbanner \r\0\r\n".to_vec(),
+        }
+    );
+
+    // Settings it does not support are refused rather than ignored
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, server_dir.clone(), false, false)
+        .with_protected_paths(vec!["*.img".to_string()])
+        .with_upload_quota(1 << 20);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let err = runtime
+        .block_on(AsyncServer::new(&config))
+        .err()
+        .expect("Unsupported settings accepted");
+    assert_eq!(
+        err.to_string(),
+        "Settings not supported by the async server: protected_paths, upload_quota"
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_put_pattern() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7016;
+    let _server_handle = start_test_server(port, server_dir.clone());
+    thread::sleep(Duration::from_millis(500));
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port).with_block_size(1024);
+    let client = Client::new(config).unwrap();
+
+    // Ends on a block boundary, so an empty last block is sent
+    let size = 64 * 1024;
+    let result = client.put_reader(
+        PatternReader::new(Pattern::Random, size, 3),
+        size,
+        "random.bin",
     );
+    assert!(result.is_ok(), "Upload failed: {:?}", result.err());
+
+    let mut expected = Vec::new();
+    PatternReader::new(Pattern::Random, size, 3)
+        .read_to_end(&mut expected)
+        .unwrap();
+    let uploaded = fs::read(server_dir.join("random.bin")).unwrap();
+    assert_eq!(uploaded.len() as u64, size);
+    assert_eq!(uploaded, expected);
 
     cleanup_test_env(&test_dir);
 }
+
+/// Records transfer events and refuses uploads named `secret.txt`
+#[derive(Default)]
+struct RecordingHandler {
+    events: std::sync::Mutex<Vec<String>>,
+}
+
+impl ServerHandler for RecordingHandler {
+    fn on_request(&self, transfer: &TransferInfo) -> Result<(), (ErrorCode, String)> {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("request {}", transfer.filename));
+        if transfer.direction == Direction::Write && transfer.filename == "secret.txt" {
+            return Err((ErrorCode::AccessViolation, "not allowed".to_string()));
+        }
+        Ok(())
+    }
+
+    fn on_transfer_start(&self, transfer: &TransferInfo) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("start {}", transfer.filename));
+    }
+
+    fn on_block(&self, _transfer: &TransferInfo, block: u16, bytes: u64) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("block {block} {bytes}"));
+    }
+
+    fn on_complete(&self, transfer: &TransferInfo, bytes: u64) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("complete {} {bytes}", transfer.filename));
+    }
+}
+
+#[test]
+#[serial]
+fn test_server_handler() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("hooks.bin"), vec![7; 1500]).unwrap();
+
+    let port = 7017;
+    let handler = std::sync::Arc::new(RecordingHandler::default());
+    let config =
+        Config::default().merge_cli("127.0.0.1".to_string(), port, server_dir, false, false);
+    let mut server = Server::new(&config).unwrap().with_handler(handler.clone());
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("hooks.bin");
+    client.get("hooks.bin", &local_file).unwrap();
+
+    let result = client.put(&local_file, "secret.txt");
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::AccessViolation,
+            ..
+        })
+    ));
+
+    // The worker reports completion after the client has its last Ack
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        *handler.events.lock().unwrap(),
+        [
+            "request hooks.bin",
+            "start hooks.bin",
+            "block 1 512",
+            "block 2 1024",
+            "block 3 1500",
+            "complete hooks.bin 1500",
+            "request secret.txt",
+        ]
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_memory_fs() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let memory = MemoryFs::new();
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    memory.insert("boot/kernel.img", content.clone());
+
+    let port = 7018;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    let mut server = Server::new(&config)
+        .unwrap()
+        .with_fs(std::sync::Arc::new(memory.clone()));
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("kernel.img");
+    client.get("/boot/kernel.img", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), content);
+
+    let missing = client.get("missing.img", &client_dir.join("missing.img"));
+    assert!(matches!(
+        missing,
+        Err(ClientError::ServerError {
+            code: ErrorCode::FileNotFound,
+            ..
+        })
+    ));
+
+    client.put(&local_file, "uploads/kernel.img").unwrap();
+    // The worker stores the upload after the client has its last Ack
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(memory.get("uploads/kernel.img").unwrap(), content);
+    assert!(!server_dir.join("uploads").exists());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_dynamic_content() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("pxelinux.cfg")).unwrap();
+    fs::write(server_dir.join("pxelinux.cfg/default"), "DEFAULT local\n").unwrap();
+
+    let port = 7019;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_dynamic("pxelinux.cfg/*", |transfer| {
+            (!transfer.filename.ends_with("default"))
+                .then(|| format!("APPEND ip={}\n", transfer.peer.ip()).into_bytes())
+        });
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("generated");
+    client
+        .get("pxelinux.cfg/01-aa-bb-cc-dd-ee-ff", &local_file)
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(&local_file).unwrap(),
+        "APPEND ip=127.0.0.1\n"
+    );
+
+    // Falls back to the static file when not generated
+    let local_file = client_dir.join("default");
+    client.get("pxelinux.cfg/default", &local_file).unwrap();
+    assert_eq!(fs::read_to_string(&local_file).unwrap(), "DEFAULT local\n");
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_allowed_networks() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("acl.bin"), vec![1; 100]).unwrap();
+
+    let port = 7020;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_allowed_networks(vec!["10.0.0.0/8".parse().unwrap()]);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("acl.bin");
+    for result in [
+        client.get("acl.bin", &local_file),
+        client.put(&test_dir.join("server/acl.bin"), "upload.bin"),
+    ] {
+        assert!(matches!(
+            result,
+            Err(ClientError::ServerError {
+                code: ErrorCode::AccessViolation,
+                ..
+            })
+        ));
+    }
+    assert!(!test_dir.join("server/upload.bin").exists());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_max_transfers() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content: Vec<u8> = (0..200_000).map(|i| (i % 229) as u8).collect();
+    for name in ["kernel.bin", "rootfs.bin", "initrd.bin"] {
+        fs::write(server_dir.join(name), &test_content).unwrap();
+    }
+
+    let port = 7021;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_max_transfers(1)
+        .with_max_queued(8)
+        .with_priority(PriorityClass {
+            priority: Priority::High,
+            pattern: Some("kernel.*".to_string()),
+            networks: None,
+        });
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Requests beyond the first wait for the transfer in progress
+    let downloads: Vec<_> = ["rootfs.bin", "initrd.bin", "kernel.bin"]
+        .into_iter()
+        .map(|name| {
+            let local_file = client_dir.join(name);
+            thread::spawn(move || {
+                let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+                    .with_timeout(Duration::from_secs(1));
+                let result = Client::new(config).unwrap().get(name, &local_file);
+                (result, local_file)
+            })
+        })
+        .collect();
+
+    for download in downloads {
+        let (result, local_file) = download.join().unwrap();
+        assert!(result.is_ok(), "Download failed: {:?}", result.err());
+        assert_eq!(fs::read(&local_file).unwrap(), test_content);
+    }
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_mirror() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("boot")).unwrap();
+    fs::write(server_dir.join("boot/zImage"), vec![1; 3000]).unwrap();
+    fs::write(server_dir.join("rootfs.img"), vec![2; 5000]).unwrap();
+    fs::write(server_dir.join("dtb"), vec![3; 100]).unwrap();
+
+    // One file up to date, one stale and one only present locally
+    fs::write(client_dir.join("rootfs.img"), vec![2; 5000]).unwrap();
+    fs::write(client_dir.join("dtb"), vec![4; 100]).unwrap();
+    fs::write(client_dir.join("local.txt"), b"local").unwrap();
+
+    let port = 7022;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_listing(true);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let mut summary = client.mirror(&client_dir, 2).unwrap();
+    summary.downloaded.sort();
+    assert_eq!(summary.downloaded, ["boot/zImage", "dtb"]);
+    assert_eq!(summary.skipped, ["rootfs.img"]);
+
+    for name in ["boot/zImage", "rootfs.img", "dtb"] {
+        assert_eq!(
+            fs::read(client_dir.join(name)).unwrap(),
+            fs::read(server_dir.join(name)).unwrap()
+        );
+    }
+    assert!(client_dir.join("local.txt").exists());
+    assert!(!client_dir.join(".xtool-listing").exists());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_max_transfers_refused() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("busy.bin"), vec![5; 1_000_000]).unwrap();
+
+    let port = 7023;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_max_transfers(1);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Keep a slow download in progress
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let slow_file = client_dir.join("slow.bin");
+    let download = thread::spawn(move || {
+        let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port);
+        let client = Client::new(config).unwrap().with_progress(move |_, _| {
+            let _ = started_tx.send(());
+            thread::sleep(Duration::from_millis(1));
+        });
+        client.get("busy.bin", &slow_file)
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let result = client.get("busy.bin", &client_dir.join("refused.bin"));
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::NotDefined,
+            ..
+        })
+    ));
+
+    assert!(download.join().unwrap().is_ok());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_thread_pool() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("busy.bin"), vec![6; 1_000_000]).unwrap();
+
+    let port = 7026;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_threads(1, 1, 0, Duration::from_secs(60));
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Keep the only thread busy with a slow download
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let slow_file = client_dir.join("slow.bin");
+    let download = thread::spawn(move || {
+        let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port);
+        let client = Client::new(config).unwrap().with_progress(move |_, _| {
+            let _ = started_tx.send(());
+            thread::sleep(Duration::from_millis(1));
+        });
+        client.get("busy.bin", &slow_file)
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let result = client.get("busy.bin", &client_dir.join("refused.bin"));
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::NotDefined,
+            ..
+        })
+    ));
+    assert!(download.join().unwrap().is_ok());
+
+    // The thread is reused once free
+    thread::sleep(Duration::from_millis(100));
+    let local_file = client_dir.join("reused.bin");
+    let result = client.get("busy.bin", &local_file);
+    assert!(result.is_ok(), "Download failed: {:?}", result.err());
+    assert_eq!(fs::read(&local_file).unwrap(), vec![6; 1_000_000]);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_client_rate_limit() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content: Vec<u8> = (0..150_000).map(|i| (i % 227) as u8).collect();
+    fs::write(server_dir.join("limited.bin"), &test_content).unwrap();
+
+    let port = 7024;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_client_rate_limit(100_000);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Both downloads share the bandwidth of the client
+    let start = std::time::Instant::now();
+    let downloads: Vec<_> = ["first.bin", "second.bin"]
+        .into_iter()
+        .map(|name| {
+            let local_file = client_dir.join(name);
+            thread::spawn(move || {
+                let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port);
+                let result = Client::new(config).unwrap().get("limited.bin", &local_file);
+                (result, local_file)
+            })
+        })
+        .collect();
+
+    for download in downloads {
+        let (result, local_file) = download.join().unwrap();
+        assert!(result.is_ok(), "Download failed: {:?}", result.err());
+        assert_eq!(fs::read(&local_file).unwrap(), test_content);
+    }
+    // 300 KB at 100 KB/s, after a burst of 100 KB
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_total_rate_limit() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let test_content: Vec<u8> = (0..150_000).map(|i| (i % 227) as u8).collect();
+    fs::write(server_dir.join("limited.bin"), &test_content).unwrap();
+
+    let port = 7025;
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), port, server_dir, false, false)
+        .with_total_rate_limit(100_000);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Both downloads share the bandwidth of the server
+    let start = std::time::Instant::now();
+    let downloads: Vec<_> = ["third.bin", "fourth.bin"]
+        .into_iter()
+        .map(|name| {
+            let local_file = client_dir.join(name);
+            thread::spawn(move || {
+                let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port);
+                let result = Client::new(config).unwrap().get("limited.bin", &local_file);
+                (result, local_file)
+            })
+        })
+        .collect();
+
+    for download in downloads {
+        let (result, local_file) = download.join().unwrap();
+        assert!(result.is_ok(), "Download failed: {:?}", result.err());
+        assert_eq!(fs::read(&local_file).unwrap(), test_content);
+    }
+    // 300 KB at 100 KB/s, after a burst of 100 KB
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+
+    cleanup_test_env(&test_dir);
+}
+
+/// Receives a packet on `socket`, failing after `timeout`.
+fn recv_packet(
+    socket: &UdpSocket,
+    timeout: Duration,
+) -> std::io::Result<(Packet, std::net::SocketAddr)> {
+    socket.set_read_timeout(Some(timeout))?;
+    let mut buf = [0; 1024];
+    let (amt, from) = socket.recv_from(&mut buf)?;
+    Ok((Packet::deserialize(&buf[..amt]).unwrap(), from))
+}
+
+#[test]
+#[serial]
+fn test_upload_dally() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7027;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+    check_upload_dally(port, &server_dir.join("dally.txt"));
+
+    // The async server dallies as well
+    let port = 7065;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = AsyncServer::new(&config).await.unwrap();
+            server.listen().await.unwrap();
+        });
+    });
+    thread::sleep(Duration::from_millis(500));
+    check_upload_dally(port, &server_dir.join("dally.txt"));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_async_malformed_packet() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7066;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = AsyncServer::new(&config).await.unwrap();
+            server.listen().await.unwrap();
+        });
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let wrq = Packet::Wrq {
+        filename: "garbled.txt".to_string(),
+        mode: "octet".to_string(),
+        options: Vec::new(),
+        custom: vec![],
+    };
+    socket
+        .send_to(&wrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Ack(0)));
+
+    // A malformed packet does not abort the transfer
+    socket.send_to(&[0, 42, 1], worker).unwrap();
+    let data = Packet::Data {
+        block_num: 1,
+        data: b"intact".to_vec(),
+    };
+    socket.send_to(&data.serialize().unwrap(), worker).unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Ack(1)));
+    assert_eq!(fs::read(server_dir.join("garbled.txt")).unwrap(), b"intact");
+
+    cleanup_test_env(&test_dir);
+}
+
+/// Uploads a single block to the server listening on `port`, then checks that
+/// its retransmission is acknowledged again.
+fn check_upload_dally(port: u16, stored: &PathBuf) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let wrq = Packet::Wrq {
+        filename: "dally.txt".to_string(),
+        mode: "octet".to_string(),
+        options: Vec::new(),
+        custom: vec![],
+    };
+    socket
+        .send_to(&wrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Ack(0)));
+
+    let data = Packet::Data {
+        block_num: 1,
+        data: b"last block".to_vec(),
+    };
+    socket.send_to(&data.serialize().unwrap(), worker).unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Ack(1)));
+
+    // As if the final ACK was lost, the retransmission is acknowledged again
+    socket.send_to(&data.serialize().unwrap(), worker).unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Ack(1)));
+    assert_eq!(fs::read(stored).unwrap(), b"last block");
+}
+
+#[test]
+fn test_download_dally() {
+    let dir = PathBuf::from("target/test/download_dally");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // A minimal server sending a single block
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let responder = thread::spawn(move || {
+        let (packet, client) = recv_packet(&server, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Rrq { .. }));
+
+        let transfer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let data = Packet::Data {
+            block_num: 1,
+            data: b"last block".to_vec(),
+        };
+        transfer
+            .send_to(&data.serialize().unwrap(), client)
+            .unwrap();
+        let (packet, _) = recv_packet(&transfer, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Ack(1)));
+
+        // As if the final ACK was lost, the retransmission is acknowledged again
+        transfer
+            .send_to(&data.serialize().unwrap(), client)
+            .unwrap();
+        let (packet, _) = recv_packet(&transfer, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Ack(1)));
+    });
+
+    let session = dir.join("dally.session");
+    let client = Client::new(
+        ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+            .with_timeout(Duration::from_secs(1))
+            .with_record(session.clone()),
+    )
+    .unwrap();
+    let local_file = dir.join("dally.txt");
+    client.get("dally.txt", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"last block");
+    responder.join().unwrap();
+
+    // Both acknowledgements are recorded, although the second is sent once
+    // the download returned
+    wait_for_dally();
+    let acks = Session::load(&session)
+        .unwrap()
+        .events
+        .iter()
+        .filter(|event| matches!(event.packet(), Ok(Packet::Ack(1))))
+        .count();
+    assert_eq!(acks, 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[serial]
+fn test_oack_loss_server() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("oack.txt"), b"options").unwrap();
+
+    let port = 7028;
+    let _server_handle = start_test_server(port, server_dir);
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "oack.txt".to_string(),
+        mode: "octet".to_string(),
+        options: vec![TransferOption {
+            option: OptionType::TransferSize,
+            value: 0,
+        }],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Oack { .. }));
+
+    // As if the OACK was lost, the retransmitted request is answered again
+    // by the same transfer
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, from) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Oack { .. }));
+    assert_eq!(from, worker);
+
+    socket
+        .send_to(&Packet::Ack(0).serialize().unwrap(), worker)
+        .unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, ref data } if data == b"options"));
+    socket
+        .send_to(&Packet::Ack(1).serialize().unwrap(), worker)
+        .unwrap();
+    // No second transfer was started
+    assert!(recv_packet(&socket, Duration::from_millis(500)).is_err());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+fn test_upload_refused_after_last_block() {
+    let dir = std::env::temp_dir().join(format!("tftp_last_block_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let server = Server::spawn_for_test_with(&config).unwrap();
+
+    // Fewer bytes than announced, the last block is refused instead of acknowledged
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let wrq = Packet::Wrq {
+        filename: "short.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![TransferOption {
+            option: OptionType::TransferSize,
+            value: 100,
+        }],
+        custom: vec![],
+    };
+    socket
+        .send_to(&wrq.serialize().unwrap(), server.addr())
+        .unwrap();
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Oack { .. }));
+    let data = Packet::Data {
+        block_num: 1,
+        data: vec![0x42; 10],
+    };
+    socket.send_to(&data.serialize().unwrap(), worker).unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Error { .. }), "{packet:?}");
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_oack_loss_client() {
+    let dir = PathBuf::from("target/test/oack_loss_client");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // A server ignoring the first request, as if its answer was lost
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let responder = thread::spawn(move || {
+        let (packet, client) = recv_packet(&server, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Rrq { .. }));
+        let (packet, resent_from) = recv_packet(&server, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Rrq { .. }));
+        assert_eq!(resent_from, client);
+
+        let transfer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let data = Packet::Data {
+            block_num: 1,
+            data: b"second try".to_vec(),
+        };
+        transfer
+            .send_to(&data.serialize().unwrap(), client)
+            .unwrap();
+        let (packet, _) = recv_packet(&transfer, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Ack(1)));
+    });
+
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_timeout(Duration::from_millis(200))
+        .with_retries(0)
+        .with_request_retries(2);
+    let client = Client::new(config).unwrap();
+    let local_file = dir.join("oack.txt");
+    client.get("oack.txt", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"second try");
+    responder.join().unwrap();
+
+    // Without an answer, the client gives up after its request retries
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let config = ClientConfig::new(
+        "127.0.0.1".parse().unwrap(),
+        silent.local_addr().unwrap().port(),
+    )
+    .with_timeout(Duration::from_millis(100))
+    .with_request_retries(1);
+    let start = std::time::Instant::now();
+    let result = Client::new(config)
+        .unwrap()
+        .get("oack.txt", &dir.join("silent.txt"));
+    assert!(matches!(result, Err(ClientError::Timeout)), "{:?}", result);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[serial]
+fn test_upload_quota() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7029;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_upload_quota(2000);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("quota.bin");
+    fs::write(&local_file, vec![1; 1200]).unwrap();
+    assert!(client.put(&local_file, "first.bin").is_ok());
+
+    // Announced too large to fit
+    let result = client.put(&local_file, "second.bin");
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::DiskFull,
+            ..
+        })
+    ));
+    assert!(!server_dir.join("second.bin").exists());
+
+    // Announced small, but sending more than fits
+    let result = client.put_reader(&[2; 1200][..], 100, "third.bin");
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::DiskFull,
+            ..
+        })
+    ));
+
+    // Once exceeded, any upload is refused
+    let result = client.put_reader(&[][..], 0, "fourth.bin");
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::DiskFull,
+            ..
+        })
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_atomic_upload() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7030;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_atomic_uploads(true);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let test_content: Vec<u8> = (0..100_000).map(|i| (i % 241) as u8).collect();
+    let local_file = client_dir.join("firmware.bin");
+    fs::write(&local_file, &test_content).unwrap();
+
+    // Midway, only the temporary file exists
+    let target = server_dir.join("firmware.bin");
+    let tmp = server_dir.join("firmware.bin.tftp-tmp");
+    let checked = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let seen = checked.clone();
+    let (watched_target, watched_tmp) = (target.clone(), tmp.clone());
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port))
+        .unwrap()
+        .with_progress(move |sent, _| {
+            if sent == 50_176 {
+                assert!(!watched_target.exists());
+                assert!(watched_tmp.exists());
+                seen.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+        });
+    let result = client.put(&local_file, "firmware.bin");
+    assert!(result.is_ok(), "Upload failed: {:?}", result.err());
+    assert!(checked.load(std::sync::atomic::Ordering::SeqCst));
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(fs::read(&target).unwrap(), test_content);
+    assert!(!tmp.exists());
+
+    // Concurrent uploads of the same file are written to their own temporary file
+    let wrq = Packet::Wrq {
+        filename: "firmware.bin".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    let uploads: Vec<_> = (0..2)
+        .map(|_| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .send_to(&wrq.serialize().unwrap(), ("127.0.0.1", port))
+                .unwrap();
+            let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+            assert_eq!(packet, Packet::Ack(0));
+            (socket, worker)
+        })
+        .collect();
+    let send = |(socket, worker): &(UdpSocket, std::net::SocketAddr), block_num, data| {
+        let data = Packet::Data { block_num, data };
+        socket.send_to(&data.serialize().unwrap(), worker).unwrap();
+        recv_packet(socket, Duration::from_secs(2)).unwrap().0
+    };
+    for upload in &uploads {
+        assert_eq!(send(upload, 1, vec![0x01; 512]), Packet::Ack(1));
+    }
+    assert!(tmp.exists());
+    assert!(server_dir.join("firmware.bin.1.tftp-tmp").exists());
+    for (upload, byte) in uploads.iter().zip([0x02, 0x03]) {
+        assert_eq!(send(upload, 2, vec![byte; 10]), Packet::Ack(2));
+    }
+    let mut expected = vec![0x01; 512];
+    expected.extend([0x03; 10]);
+    assert_eq!(fs::read(&target).unwrap(), expected);
+    assert!(!tmp.exists());
+    assert!(!server_dir.join("firmware.bin.1.tftp-tmp").exists());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_option_limits() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("limits.txt"), b"clamped").unwrap();
+
+    let port = 7031;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_max_sizes(1024, 4)
+        .with_timeout_range(Duration::from_secs(2), Duration::from_secs(5));
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "limits.txt".to_string(),
+        mode: "octet".to_string(),
+        options: vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 8192,
+            },
+            TransferOption {
+                option: OptionType::WindowSize,
+                value: 16,
+            },
+            TransferOption {
+                option: OptionType::Timeout,
+                value: 1,
+            },
+        ],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+
+    // Clamped rather than refused
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    let Packet::Oack { options, .. } = packet else {
+        panic!("expected an OACK, got {packet:?}");
+    };
+    let value = |option| options.iter().find(|o| o.option == option).unwrap().value;
+    assert_eq!(value(OptionType::BlockSize), 1024);
+    assert_eq!(value(OptionType::WindowSize), 4);
+    assert_eq!(value(OptionType::Timeout), 2);
+
+    socket
+        .send_to(&Packet::Ack(0).serialize().unwrap(), worker)
+        .unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, ref data } if data == b"clamped"));
+    socket
+        .send_to(&Packet::Ack(1).serialize().unwrap(), worker)
+        .unwrap();
+
+    // Limits that cannot be advertised are refused when the server is created
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, server_dir, false, false);
+    assert!(Server::new(&config.clone().with_max_sizes(1428, 8)).is_ok());
+    assert!(Server::new(&config.clone().with_max_sizes(0, 4)).is_err());
+    assert!(Server::new(&config.clone().with_max_sizes(7, 4)).is_err());
+    assert!(Server::new(&config.clone().with_max_sizes(8, 4)).is_ok());
+    assert!(Server::new(&config.clone().with_max_sizes(65535, 4)).is_err());
+    assert!(Server::new(&config.with_max_sizes(1428, 0)).is_err());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_rewrite_rules() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("boot/grub2")).unwrap();
+    fs::write(server_dir.join("boot/grub2/grub.cfg"), b"menuentry").unwrap();
+
+    let port = 7032;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_rewrite(RewriteRule {
+            pattern: r"\\".to_string(),
+            replace: Some("/".to_string()),
+            lowercase: false,
+        })
+        .with_rewrite(RewriteRule {
+            pattern: "(?i)^/?grub/(.*)".to_string(),
+            replace: Some("boot/grub2/$1".to_string()),
+            lowercase: true,
+        });
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("grub.cfg");
+    client.get(r"\GRUB\Grub.CFG", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"menuentry");
+
+    // Invalid patterns are refused when the server is created
+    let config = Config::default().with_rewrite(RewriteRule {
+        pattern: "(".to_string(),
+        replace: None,
+        lowercase: false,
+    });
+    assert!(Server::new(&config).is_err());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_upload_manifest() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let port = 7033;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_manifest(true);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("upload.bin");
+    fs::write(&local_file, b"abc").unwrap();
+    client.put(&local_file, "first.bin").unwrap();
+    client.put(&local_file, "second.bin").unwrap();
+
+    // Entries are appended once the server is done with the upload, in any order
+    let manifest = wait_for_lines(&server_dir.join(MANIFEST_FILENAME), 2);
+    let entries: Vec<Vec<&str>> = manifest
+        .lines()
+        .map(|line| line.splitn(5, ' ').collect())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    // SHA-256 of "abc"
+    let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    for entry in &entries {
+        assert_eq!(entry[..2], [sha256, "3"]);
+        assert!(entry[2].starts_with("127.0.0.1:"));
+    }
+    let names: std::collections::BTreeSet<&str> = entries.iter().map(|entry| entry[4]).collect();
+    assert_eq!(names, ["first.bin", "second.bin"].into());
+
+    // Clients cannot replace the manifest
+    let result = client.put(&local_file, MANIFEST_FILENAME);
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::AccessViolation,
+            ..
+        })
+    ));
+    assert_eq!(
+        fs::read_to_string(server_dir.join(MANIFEST_FILENAME)).unwrap(),
+        manifest
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_case_insensitive() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("efi/boot")).unwrap();
+    fs::write(server_dir.join("efi/boot/bootx64.efi"), b"shim").unwrap();
+
+    let port = 7034;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_case_insensitive(true);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("bootx64.efi");
+    client.get("EFI/BOOT/BOOTX64.EFI", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"shim");
+
+    // Uploads replace the existing file rather than adding a second one
+    fs::write(&local_file, b"signed shim").unwrap();
+    client.put(&local_file, "EFI/Boot/BootX64.efi").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        fs::read(server_dir.join("efi/boot/bootx64.efi")).unwrap(),
+        b"signed shim"
+    );
+    assert_eq!(
+        fs::read_dir(server_dir.join("efi/boot")).unwrap().count(),
+        1
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_session_replay() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+    let session_dir = test_dir.join("sessions");
+
+    let content: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    fs::write(server_dir.join("kernel.img"), &content).unwrap();
+
+    let port = 7035;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_session_dir(session_dir.clone());
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client_session = client_dir.join("upload.session");
+    let client = Client::new(
+        ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+            .with_block_size(1024)
+            .with_window_size(2)
+            .with_record(client_session.clone()),
+    )
+    .unwrap();
+    client
+        .get("kernel.img", &client_dir.join("kernel.img"))
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    // The download recorded by the server replays against the same file
+    let sessions: Vec<PathBuf> = fs::read_dir(&session_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(sessions.len(), 1);
+    let session = Session::load(&sessions[0]).unwrap();
+    let fs = MemoryFs::new();
+    fs.insert("kernel.img", content.clone());
+    assert!(replay(&session, &Config::with_defaults(), std::sync::Arc::new(fs)).unwrap());
+
+    // A different file diverges from the recording
+    let fs = MemoryFs::new();
+    fs.insert("kernel.img", vec![0; content.len()]);
+    assert!(replay(&session, &Config::with_defaults(), std::sync::Arc::new(fs)).is_err());
+
+    // The upload recorded by the client replays against the server side
+    fs::write(client_dir.join("dump.bin"), &content[..1500]).unwrap();
+    client
+        .put(&client_dir.join("dump.bin"), "dump.bin")
+        .unwrap();
+    let session = Session::load(&client_session).unwrap().mirror();
+    let fs = std::sync::Arc::new(MemoryFs::new());
+    assert!(replay(&session, &Config::with_defaults(), fs.clone()).unwrap());
+    assert_eq!(fs.get("dump.bin").unwrap(), content[..1500]);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_backslashes() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("pxelinux")).unwrap();
+    fs::write(server_dir.join("pxelinux/pxelinux.0"), b"pxelinux").unwrap();
+
+    let port = 7036;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_backslashes(true);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("pxelinux.0");
+    client.get(r"pxelinux\pxelinux.0", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"pxelinux");
+
+    // Uploads land in the directory rather than in a file named with a backslash
+    client.put(&local_file, r"pxelinux\upload.0").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        fs::read(server_dir.join("pxelinux/upload.0")).unwrap(),
+        b"pxelinux"
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_prefix_roots() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let uefi_dir = test_dir.join("uefi");
+    fs::create_dir_all(&uefi_dir).unwrap();
+    fs::write(uefi_dir.join("grubx64.efi"), b"grub").unwrap();
+    fs::write(server_dir.join("pxelinux.0"), b"pxelinux").unwrap();
+
+    let port = 7037;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_root("uefi/", uefi_dir.clone());
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("grubx64.efi");
+    client.get("uefi/grubx64.efi", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"grub");
+
+    // Names without a mapped prefix are served from the root directory
+    let local_file = client_dir.join("pxelinux.0");
+    client.get("pxelinux.0", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"pxelinux");
+
+    client.put(&local_file, "uefi/upload.bin").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(fs::read(uefi_dir.join("upload.bin")).unwrap(), b"pxelinux");
+
+    // Mapped directories do not give access to their parents
+    assert!(
+        client
+            .get("uefi/../server/pxelinux.0", &client_dir.join("escaped"))
+            .is_err()
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_transfer_directories() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let upload_dir = test_dir.join("incoming");
+    let download_dir = test_dir.join("images");
+    fs::create_dir_all(&upload_dir).unwrap();
+    fs::create_dir_all(&download_dir).unwrap();
+    fs::write(download_dir.join("zImage"), b"kernel").unwrap();
+
+    let port = 7038;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .merge_cli_directories(Some(upload_dir.clone()), None)
+        .with_send_directory(download_dir.clone());
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("zImage");
+    client.get("zImage", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"kernel");
+
+    client.put(&local_file, "dump.bin").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(fs::read(upload_dir.join("dump.bin")).unwrap(), b"kernel");
+    assert!(!download_dir.join("dump.bin").exists());
+    assert!(!server_dir.join("dump.bin").exists());
+
+    // Uploads cannot be downloaded back
+    assert!(
+        client
+            .get("dump.bin", &client_dir.join("dump.bin"))
+            .is_err()
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_event_bus() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("u-boot.bin"), vec![0x5a; 1200]).unwrap();
+
+    let port = 7039;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    let bus = EventBus::new();
+    let events = bus.subscribe();
+    let mut server = Server::new(&config)
+        .unwrap()
+        .with_handler(std::sync::Arc::new(bus));
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    client
+        .get("u-boot.bin", &client_dir.join("u-boot.bin"))
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let events: Vec<Event> = events.try_iter().collect();
+    let transfer = match events.first() {
+        Some(Event::TransferStarted(transfer)) => transfer.clone(),
+        event => panic!("Expected the transfer to start, got {event:?}"),
+    };
+    assert_eq!(transfer.filename, "u-boot.bin");
+    assert_eq!(transfer.direction, Direction::Read);
+    assert!(events.contains(&Event::TransferProgress {
+        transfer: transfer.clone(),
+        bytes: 512,
+    }));
+    assert_eq!(
+        events.last(),
+        Some(&Event::TransferCompleted {
+            transfer,
+            bytes: 1200,
+        })
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_transfer_log() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+    let transfer_log = test_dir.join("xferlog");
+
+    fs::write(server_dir.join("boot.scr"), vec![0x42; 700]).unwrap();
+
+    let port = 7040;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_transfer_log(transfer_log.clone());
+    // Handlers are notified along with the transfer log
+    let bus = EventBus::new();
+    let events = bus.subscribe();
+    let mut server = Server::new(&config)
+        .unwrap()
+        .with_handler(std::sync::Arc::new(bus));
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("boot.scr");
+    client.get("boot.scr", &local_file).unwrap();
+    client.put(&local_file, "saved env.bin").unwrap();
+
+    // The download may be logged after the upload, once its last Ack arrives
+    let content = wait_for_lines(&transfer_log, 2);
+    let mut lines: Vec<Vec<&str>> = content
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    lines.sort_by_key(|line| line[11] == "i");
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0][6..10], ["127.0.0.1", "700", "/boot.scr", "b"]);
+    assert_eq!(lines[0][11], "o");
+    assert_eq!(lines[0][17], "c");
+    assert_eq!(lines[1][8], "/saved_env.bin");
+    assert_eq!(lines[1][11], "i");
+    assert_eq!(lines[1][17], "c");
+    let completed = std::iter::from_fn(|| events.recv_timeout(Duration::from_secs(5)).ok())
+        .filter(|event| matches!(event, Event::TransferCompleted { .. }))
+        .take(2)
+        .count();
+    assert_eq!(completed, 2);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_metrics() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("zImage"), vec![0x42; 1500]).unwrap();
+
+    let port = 7041;
+    let metrics_addr = "127.0.0.1:7042".parse().unwrap();
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_metrics_addr(metrics_addr);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    client.get("zImage", &client_dir.join("zImage")).unwrap();
+    assert!(client.get("missing", &client_dir.join("missing")).is_err());
+    client
+        .put(&client_dir.join("zImage"), "upload.bin")
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let scrape = |path: &str| {
+        let mut stream = std::net::TcpStream::connect(metrics_addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let response = scrape("/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    for line in [
+        "xtool_tftp_active_transfers 0",
+        "xtool_tftp_transfers_total{direction=\"read\"} 1",
+        "xtool_tftp_transfers_total{direction=\"write\"} 1",
+        "xtool_tftp_bytes_sent_total 1500",
+        "xtool_tftp_bytes_received_total 1500",
+        "xtool_tftp_retransmissions_total 0",
+        "xtool_tftp_errors_total{code=\"1\"} 1",
+    ] {
+        assert!(
+            response.lines().any(|l| l == line),
+            "{line} missing from {response}"
+        );
+    }
+    assert!(scrape("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    cleanup_test_env(&test_dir);
+}
+
+/// Writer whose content a test reads back
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn test_json_log() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("zImage"), vec![0x42; 3000]).unwrap();
+
+    let port = 7043;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    let buffer = SharedBuffer::default();
+    let mut server = Server::new(&config)
+        .unwrap()
+        .with_handler(std::sync::Arc::new(JsonLog::new(Box::new(buffer.clone()))));
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client =
+        Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port).with_block_size(1024))
+            .unwrap();
+    client.get("zImage", &client_dir.join("zImage")).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let content = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let events: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["request", "start", "complete"]);
+    assert_eq!(events[2]["file"], "zImage");
+    assert_eq!(events[2]["direction"], "read");
+    assert_eq!(events[2]["bytes"], 3000);
+    assert_eq!(events[2]["options"]["blksize"], 1024);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_single_port_sessions() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("kernel.bin"), vec![0x42; 50_000]).unwrap();
+    fs::write(server_dir.join("small.txt"), b"one block").unwrap();
+
+    let port = 7044;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        true,
+    );
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Concurrent transfers share the server port
+    let transfers: Vec<_> = (0..3)
+        .map(|i| {
+            let client_dir = client_dir.clone();
+            thread::spawn(move || {
+                let client =
+                    Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+                let local_file = client_dir.join(format!("kernel-{i}.bin"));
+                client.get("kernel.bin", &local_file).unwrap();
+                client.put(&local_file, &format!("upload-{i}.bin")).unwrap();
+            })
+        })
+        .collect();
+    for transfer in transfers {
+        transfer.join().unwrap();
+    }
+    for i in 0..3 {
+        assert_eq!(
+            fs::read(server_dir.join(format!("upload-{i}.bin"))).unwrap(),
+            vec![0x42; 50_000]
+        );
+    }
+
+    // A late acknowledgement of a completed transfer is dropped
+    let addr: std::net::SocketAddr = ([127, 0, 0, 1], port).into();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let request = Packet::Rrq {
+        filename: "small.txt".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    client.send_to(&request.serialize().unwrap(), addr).unwrap();
+    let (packet, from) = recv_packet(&client, Duration::from_secs(2)).unwrap();
+    assert_eq!(from, addr);
+    assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+    client
+        .send_to(&Packet::Ack(1).serialize().unwrap(), addr)
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+    client
+        .send_to(&Packet::Ack(1).serialize().unwrap(), addr)
+        .unwrap();
+    assert!(recv_packet(&client, Duration::from_millis(500)).is_err());
+
+    // Packets of clients without a transfer are refused
+    let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+    stranger
+        .send_to(&Packet::Ack(1).serialize().unwrap(), addr)
+        .unwrap();
+    let (packet, _) = recv_packet(&stranger, Duration::from_secs(2)).unwrap();
+    assert!(matches!(
+        packet,
+        Packet::Error {
+            code: ErrorCode::UnknownId,
+            ..
+        }
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_dual_stack() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("grubx64.efi"), vec![0x42; 5000]).unwrap();
+
+    let port = 7045;
+    let config = Config::default()
+        .merge_cli("::".to_string(), port, server_dir.clone(), false, false)
+        .with_dual_stack(true);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    for ip in ["::1", "127.0.0.1"] {
+        let client = Client::new(ClientConfig::new(ip.parse().unwrap(), port)).unwrap();
+        let local_file = client_dir.join(format!("grubx64-{ip}.efi"));
+        client.get("grubx64.efi", &local_file).unwrap();
+        assert_eq!(fs::read(&local_file).unwrap(), vec![0x42; 5000]);
+        client
+            .put(&local_file, &format!("upload-{}.efi", ip.replace(':', "_")))
+            .unwrap();
+    }
+    assert!(server_dir.join("upload-__1.efi").exists());
+    assert!(server_dir.join("upload-127.0.0.1.efi").exists());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_several_addresses() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("pxelinux.0"), vec![0x42; 5000]).unwrap();
+
+    let port = 7046;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_addresses(vec!["127.0.0.2".to_string(), "lo".to_string()]);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // The loopback interface stands for both 127.0.0.1 and ::1
+    for ip in ["127.0.0.1", "127.0.0.2", "::1"] {
+        let client = Client::new(ClientConfig::new(ip.parse().unwrap(), port)).unwrap();
+        let local_file = client_dir.join(format!("pxelinux-{}.0", ip.replace(':', "_")));
+        client.get("pxelinux.0", &local_file).unwrap();
+        assert_eq!(fs::read(&local_file).unwrap(), vec![0x42; 5000]);
+    }
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_reload() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("initrd.img"), vec![0x42; 40_000]).unwrap();
+
+    let port = 7047;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_total_rate_limit(20_000);
+    let mut server = Server::new(&config).unwrap();
+    let handle = server.reload_handle();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Takes about a second at the total rate limit
+    let local_file = client_dir.join("initrd.img");
+    let download = {
+        let local_file = local_file.clone();
+        thread::spawn(move || {
+            let client =
+                Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+            client.get("initrd.img", &local_file)
+        })
+    };
+    thread::sleep(Duration::from_millis(300));
+
+    handle.reload(config.with_denied_networks(vec!["127.0.0.0/8".parse().unwrap()]));
+    thread::sleep(Duration::from_millis(300));
+
+    // The transfer in progress goes on, new requests are denied
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    assert!(
+        client
+            .get("initrd.img", &client_dir.join("denied.img"))
+            .is_err()
+    );
+    download.join().unwrap().unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), vec![0x42; 40_000]);
+
+    // Invalid configurations are not reloaded
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, server_dir, false, false);
+    let mut server = Server::new(&config).unwrap();
+    let invalid = config.with_rewrite(RewriteRule {
+        pattern: "(".to_string(),
+        replace: None,
+        lowercase: false,
+    });
+    assert!(server.reload(&invalid).is_err());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_netascii() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(
+        server_dir.join("startup.cfg"),
+        b"hostname sw1\ninterface ge0\n",
+    )
+    .unwrap();
+
+    let port = 7048;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Both ends convert line endings, files are the same on both sides
+    let client = Client::new(ClientConfig {
+        mode: Some("netascii".to_string()),
+        ..ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+    })
+    .unwrap();
+    let local_file = client_dir.join("startup.cfg");
+    client.get("startup.cfg", &local_file).unwrap();
+    assert_eq!(
+        fs::read(&local_file).unwrap(),
+        b"hostname sw1\ninterface ge0\n"
+    );
+
+    let upload = client_dir.join("running.cfg");
+    fs::write(&upload, b"hostname sw2\nbanner \r\n\r").unwrap();
+    client.put(&upload, "running.cfg").unwrap();
+    assert_eq!(
+        fs::read(server_dir.join("running.cfg")).unwrap(),
+        b"hostname sw2\nbanner \r\n\r"
+    );
+
+    // The converted content is sent as is to a server
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "running.cfg".to_string(),
+        mode: "netascii".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert_eq!(
+        packet,
+        Packet::Data {
+            block_num: 1,
+            data: b"hostname sw2\r\nbanner \r\0\r\n\r\0".to_vec(),
+        }
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_retries_and_timeout() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("boot.scr"), b"bootm").unwrap();
+
+    let port = 7049;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_retries(1)
+        .with_timeout(Duration::from_secs(1));
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "boot.scr".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+
+    // Never acknowledged, the block is resent once after a second
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+    let start = std::time::Instant::now();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(3)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+    assert!(start.elapsed() < Duration::from_secs(3));
+    let data = std::iter::from_fn(|| recv_packet(&socket, Duration::from_secs(3)).ok())
+        .filter(|(packet, _)| matches!(packet, Packet::Data { .. }))
+        .count();
+    assert_eq!(data, 0);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_file_cache() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("pxelinux.cfg"), b"default linux").unwrap();
+
+    let port = 7050;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_cache(1024 * 1024, 64 * 1024);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("pxelinux.cfg");
+    for _ in 0..2 {
+        client.get("pxelinux.cfg", &local_file).unwrap();
+        assert_eq!(fs::read(&local_file).unwrap(), b"default linux");
+    }
+
+    // A changed file is not served from memory
+    fs::write(server_dir.join("pxelinux.cfg"), b"default rescue").unwrap();
+    client.get("pxelinux.cfg", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"default rescue");
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_mapped_files() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let image: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(server_dir.join("rootfs.img"), &image).unwrap();
+    // Ends on a block boundary, followed by an empty block
+    fs::write(server_dir.join("dtb.img"), &image[..4096]).unwrap();
+    fs::write(server_dir.join("boot.scr"), b"bootm").unwrap();
+
+    let port = 7051;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_mmap_threshold(1024);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(
+        ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+            .with_block_size(1024)
+            .with_window_size(4),
+    )
+    .unwrap();
+    for (name, content) in [
+        ("rootfs.img", &image[..]),
+        ("dtb.img", &image[..4096]),
+        ("boot.scr", &b"bootm"[..]),
+    ] {
+        let local_file = client_dir.join(name);
+        client.get(name, &local_file).unwrap();
+        assert_eq!(fs::read(&local_file).unwrap(), content, "{name}");
+    }
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_block_rollover() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    // More blocks of 8 bytes than the 16-bit block counter holds
+    let image: Vec<u8> = (0..65_600u32 * 8 + 3).map(|i| (i % 251) as u8).collect();
+    fs::write(server_dir.join("rootfs.img"), &image).unwrap();
+
+    let start = |port, rollover| {
+        let config = Config::default()
+            .merge_cli(
+                "127.0.0.1".to_string(),
+                port,
+                server_dir.clone(),
+                false,
+                false,
+            )
+            .with_rollover(rollover);
+        let mut server = Server::new(&config).unwrap();
+        thread::spawn(move || server.listen());
+    };
+    start(7052, Rollover::Enforce0);
+    start(7053, Rollover::Enforce1);
+    start(7054, Rollover::None);
+    thread::sleep(Duration::from_millis(500));
+
+    // The client wraps to 0
+    let client = Client::new(
+        ClientConfig::new("127.0.0.1".parse().unwrap(), 7052)
+            .with_block_size(8)
+            .with_window_size(64),
+    )
+    .unwrap();
+    let local_file = client_dir.join("rootfs.img");
+    client.get("rootfs.img", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), image);
+
+    let request = |port| {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rrq = Packet::Rrq {
+            filename: "rootfs.img".to_string(),
+            mode: "octet".to_string(),
+            options: vec![
+                TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 8,
+                },
+                TransferOption {
+                    option: OptionType::WindowSize,
+                    value: 64,
+                },
+            ],
+            custom: vec![],
+        };
+        socket
+            .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+            .unwrap();
+        let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+        (socket, packet, worker)
+    };
+
+    // Blocks after 65535 start at 1
+    let (socket, packet, worker) = request(7053);
+    assert!(matches!(packet, Packet::Oack { .. }));
+    socket
+        .send_to(&Packet::Ack(0).serialize().unwrap(), worker)
+        .unwrap();
+    let mut received = Vec::new();
+    let mut blocks = Vec::new();
+    loop {
+        let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+        let Packet::Data { block_num, data } = packet else {
+            panic!("unexpected {packet:?}");
+        };
+        let expected = match blocks.last() {
+            Some(65535) => 1,
+            Some(last) => last + 1,
+            None => 1,
+        };
+        if block_num != expected {
+            continue;
+        }
+        blocks.push(block_num);
+        received.extend_from_slice(&data);
+        let last = data.len() < 8;
+        if last || blocks.len() % 64 == 0 {
+            socket
+                .send_to(&Packet::Ack(block_num).serialize().unwrap(), worker)
+                .unwrap();
+        }
+        if last {
+            break;
+        }
+    }
+    assert_eq!(received, image);
+    assert!(!blocks.contains(&0));
+
+    // Refused before sending anything
+    let (_socket, packet, _) = request(7054);
+    assert!(matches!(
+        packet,
+        Packet::Error {
+            code: ErrorCode::IllegalOperation,
+            ..
+        }
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_protected_paths() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("boot/dtbs")).unwrap();
+    fs::write(server_dir.join("boot/dtbs/board.dtb"), b"dtb").unwrap();
+
+    let port = 7055;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_protected_paths(vec!["boot/**".to_string(), "*.scr".to_string()]);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for filename in ["boot/dtbs/board.dtb", "/boot.scr"] {
+        let wrq = Packet::Wrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+            custom: vec![],
+        };
+        socket
+            .send_to(&wrq.serialize().unwrap(), ("127.0.0.1", port))
+            .unwrap();
+        let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+        assert!(
+            matches!(
+                packet,
+                Packet::Error {
+                    code: ErrorCode::AccessViolation,
+                    ..
+                }
+            ),
+            "{filename}"
+        );
+    }
+    assert_eq!(
+        fs::read(server_dir.join("boot/dtbs/board.dtb")).unwrap(),
+        b"dtb"
+    );
+
+    // The rest of the tree stays writable
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let upload = client_dir.join("uEnv.txt");
+    fs::write(&upload, b"bootdelay=1").unwrap();
+    client.put(&upload, "uEnv.txt").unwrap();
+    assert_eq!(
+        fs::read(server_dir.join("uEnv.txt")).unwrap(),
+        b"bootdelay=1"
+    );
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_overwrite_policies() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let start = |port, policy| {
+        let directory = server_dir.join(format!("{policy:?}"));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("router.cfg"), b"v0").unwrap();
+        let config = Config::default()
+            .merge_cli("127.0.0.1".to_string(), port, directory, false, false)
+            .with_overwrite(policy);
+        let mut server = Server::new(&config).unwrap();
+        thread::spawn(move || server.listen());
+    };
+    start(7056, OverwritePolicy::Reject);
+    start(7057, OverwritePolicy::RenameWithSuffix);
+    start(7058, OverwritePolicy::KeepVersioned);
+    thread::sleep(Duration::from_millis(500));
+
+    let upload = client_dir.join("router.cfg");
+    let put = |port, content: &[u8]| {
+        fs::write(&upload, content).unwrap();
+        let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+        let result = client.put(&upload, "router.cfg");
+        // Moved into place once the last block is acknowledged
+        thread::sleep(Duration::from_millis(100));
+        result
+    };
+    let read = |policy: OverwritePolicy, name: &str| {
+        fs::read(server_dir.join(format!("{policy:?}")).join(name)).ok()
+    };
+
+    assert!(put(7056, b"v1").is_err());
+    assert_eq!(read(OverwritePolicy::Reject, "router.cfg").unwrap(), b"v0");
+
+    put(7057, b"v1").unwrap();
+    put(7057, b"v2").unwrap();
+    let policy = OverwritePolicy::RenameWithSuffix;
+    assert_eq!(read(policy, "router.cfg").unwrap(), b"v0");
+    assert_eq!(read(policy, "router.cfg.1").unwrap(), b"v1");
+    assert_eq!(read(policy, "router.cfg.2").unwrap(), b"v2");
+
+    // Uploads started together are given different versions
+    let wrq = Packet::Wrq {
+        filename: "router.cfg".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    let uploads: Vec<_> = [b"v3", b"v4"]
+        .iter()
+        .map(|content| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .send_to(&wrq.serialize().unwrap(), "127.0.0.1:7057")
+                .unwrap();
+            (socket, content)
+        })
+        .collect();
+    for (socket, content) in uploads {
+        let (packet, from) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+        assert_eq!(packet, Packet::Ack(0));
+        let data = Packet::Data {
+            block_num: 1,
+            data: content.to_vec(),
+        };
+        socket.send_to(&data.serialize().unwrap(), from).unwrap();
+        assert_eq!(
+            recv_packet(&socket, Duration::from_secs(2)).unwrap().0,
+            Packet::Ack(1)
+        );
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(read(policy, "router.cfg.3").unwrap(), b"v3");
+    assert_eq!(read(policy, "router.cfg.4").unwrap(), b"v4");
+
+    put(7058, b"v1").unwrap();
+    put(7058, b"v2").unwrap();
+    let policy = OverwritePolicy::KeepVersioned;
+    assert_eq!(read(policy, "router.cfg").unwrap(), b"v2");
+    assert_eq!(read(policy, "router.cfg.1").unwrap(), b"v0");
+    assert_eq!(read(policy, "router.cfg.2").unwrap(), b"v1");
+    assert!(read(policy, "router.cfg.tftp-tmp").is_none());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_transfer_idle_timeout() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("pxelinux.0"), vec![0x42; 4096]).unwrap();
+
+    let port = 7059;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_retries(100)
+        .with_timeout(Duration::from_secs(1))
+        .with_transfer_idle_timeout(Duration::from_secs(2));
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "pxelinux.0".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+
+    // The client vanishes, the block is resent until the transfer is reaped
+    let start = std::time::Instant::now();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+    while recv_packet(&socket, Duration::from_secs(3)).is_ok() {}
+    assert!(start.elapsed() < Duration::from_secs(8));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_multicast() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let content: Vec<u8> = (0..1800).map(|i| (i % 251) as u8).collect();
+    fs::write(server_dir.join("image.bin"), &content).unwrap();
+
+    let port = 7060;
+    let group: std::net::Ipv4Addr = "239.255.42.1".parse().unwrap();
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_multicast(group, 17600);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    // Each client listens to the group next to its own unicast socket
+    let join = || {
+        let socket = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )
+        .unwrap();
+        socket.set_reuse_address(true).unwrap();
+        let addr: std::net::SocketAddr = (std::net::Ipv4Addr::UNSPECIFIED, 17600).into();
+        socket.bind(&addr.into()).unwrap();
+        socket
+            .join_multicast_v4(&group, &"127.0.0.1".parse().unwrap())
+            .unwrap();
+        let receiver: UdpSocket = socket.into();
+        (UdpSocket::bind("127.0.0.1:0").unwrap(), receiver)
+    };
+    let request = |socket: &UdpSocket| {
+        let rrq = Packet::Rrq {
+            filename: "image.bin".to_string(),
+            mode: "octet".to_string(),
+            options: vec![
+                TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 512,
+                },
+                TransferOption {
+                    option: OptionType::Multicast,
+                    value: 0,
+                },
+            ],
+            custom: vec![],
+        };
+        socket
+            .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+            .unwrap();
+    };
+    let oack = |socket: &UdpSocket| {
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut buf = [0; 512];
+        let (amt, from) = socket.recv_from(&mut buf).unwrap();
+        (String::from_utf8_lossy(&buf[..amt]).into_owned(), from)
+    };
+    let block = |receiver: &UdpSocket| match recv_packet(receiver, Duration::from_secs(2)) {
+        Ok((Packet::Data { block_num, data }, _)) => (block_num, data),
+        other => panic!("Expected data, got {other:?}"),
+    };
+
+    let (master, master_group) = join();
+    request(&master);
+    let (reply, group_tid) = oack(&master);
+    assert!(reply.ends_with("blksize\u{0}512\u{0}multicast\u{0}239.255.42.1,17600,1\u{0}"));
+
+    let (late, late_group) = join();
+    request(&late);
+    let (reply, _) = oack(&late);
+    assert!(reply.ends_with("multicast\u{0}239.255.42.1,17600,0\u{0}"));
+
+    // The master drives the stream, the other client missing block 2
+    let mut received = Vec::new();
+    let mut missed = std::collections::BTreeMap::new();
+    for block_num in 1..=4u16 {
+        master
+            .send_to(&Packet::Ack(block_num - 1).serialize().unwrap(), group_tid)
+            .unwrap();
+        let (num, data) = block(&master_group);
+        assert_eq!(num, block_num);
+        received.extend_from_slice(&data);
+        let (num, data) = block(&late_group);
+        if num != 2 {
+            missed.insert(num, data);
+        }
+    }
+    master
+        .send_to(&Packet::Ack(4).serialize().unwrap(), group_tid)
+        .unwrap();
+    assert_eq!(received, content);
+
+    // Made master once the first client is done, it asks for the block missed
+    let (reply, _) = oack(&late);
+    assert!(reply.ends_with("multicast\u{0}239.255.42.1,17600,1\u{0}"));
+    late.send_to(&Packet::Ack(1).serialize().unwrap(), group_tid)
+        .unwrap();
+    let (num, data) = block(&late_group);
+    assert_eq!(num, 2);
+    missed.insert(num, data);
+    late.send_to(&Packet::Ack(4).serialize().unwrap(), group_tid)
+        .unwrap();
+    assert_eq!(missed.into_values().flatten().collect::<Vec<u8>>(), content);
+
+    // A client without the option is sent the file with unicast
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = test_dir.join("client").join("image.bin");
+    client.get("image.bin", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), content);
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_unknown_tid() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("pxelinux.0"), vec![0x42; 600]).unwrap();
+
+    let port = 7061;
+    let config = Config::default().merge_cli(
+        "127.0.0.1".to_string(),
+        port,
+        server_dir.clone(),
+        false,
+        false,
+    );
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "pxelinux.0".to_string(),
+        mode: "octet".to_string(),
+        options: vec![],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+    let (packet, tid) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+
+    // Another client acknowledging on the transfer's port is told off
+    let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+    stranger
+        .send_to(&Packet::Ack(1).serialize().unwrap(), tid)
+        .unwrap();
+    let (packet, _) = recv_packet(&stranger, Duration::from_secs(2)).unwrap();
+    assert!(matches!(
+        packet,
+        Packet::Error {
+            code: ErrorCode::UnknownId,
+            ..
+        }
+    ));
+
+    // The transfer goes on with its client
+    socket
+        .send_to(&Packet::Ack(1).serialize().unwrap(), tid)
+        .unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    match packet {
+        Packet::Data { block_num, data } => {
+            assert_eq!(block_num, 2);
+            assert_eq!(data.len(), 88);
+        }
+        packet => panic!("Unexpected packet {packet:?}"),
+    }
+    socket
+        .send_to(&Packet::Ack(2).serialize().unwrap(), tid)
+        .unwrap();
+
+    cleanup_test_env(&test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_symlink_policy() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    let outside = test_dir.join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret.txt"), b"secret").unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), server_dir.join("secret.txt")).unwrap();
+    fs::write(server_dir.join("inside.txt"), b"inside").unwrap();
+
+    let port = 7062;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_symlinks(SymlinkPolicy::Refuse);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let request = |filename: &str| {
+        let rrq = Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+            custom: vec![],
+        };
+        socket
+            .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+            .unwrap();
+        let (packet, from) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+        if let Packet::Data { block_num, .. } = packet {
+            socket
+                .send_to(&Packet::Ack(block_num).serialize().unwrap(), from)
+                .unwrap();
+        }
+        packet
+    };
+
+    assert!(matches!(
+        request("secret.txt"),
+        Packet::Error {
+            code: ErrorCode::AccessViolation,
+            ..
+        }
+    ));
+    assert!(matches!(
+        request("inside.txt"),
+        Packet::Data { block_num: 1, .. }
+    ));
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_boot_mapping() {
+    let (server_dir, client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::create_dir_all(server_dir.join("rescue")).unwrap();
+    fs::write(server_dir.join("pxelinux.0"), b"default").unwrap();
+    fs::write(server_dir.join("rescue/pxelinux.0"), b"rescue").unwrap();
+    fs::write(server_dir.join("debug.cfg"), b"debug").unwrap();
+
+    let port = 7063;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_boot_mapping(BootMapping {
+            clients: "127.0.0.0/8".parse().unwrap(),
+            prefix: Some("rescue/".to_string()),
+            files: Default::default(),
+        })
+        .with_boot_mapping(BootMapping {
+            clients: "10.0.0.0/8".parse().unwrap(),
+            prefix: None,
+            files: [("pxelinux.0".to_string(), "debug.cfg".to_string())].into(),
+        });
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Client::new(ClientConfig::new("127.0.0.1".parse().unwrap(), port)).unwrap();
+    let local_file = client_dir.join("pxelinux.0");
+    client.get("pxelinux.0", &local_file).unwrap();
+    assert_eq!(fs::read(&local_file).unwrap(), b"rescue");
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+#[serial]
+fn test_disabled_options() {
+    let (server_dir, _client_dir) = setup_test_env();
+    let test_dir = server_dir.parent().unwrap().to_path_buf();
+
+    fs::write(server_dir.join("pxelinux.0"), b"firmware").unwrap();
+
+    let port = 7064;
+    let config = Config::default()
+        .merge_cli(
+            "127.0.0.1".to_string(),
+            port,
+            server_dir.clone(),
+            false,
+            false,
+        )
+        .with_disabled_option(OptionType::WindowSize)
+        .with_disabled_option(OptionType::TransferSize);
+    let mut server = Server::new(&config).unwrap();
+    thread::spawn(move || server.listen());
+    thread::sleep(Duration::from_millis(500));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "pxelinux.0".to_string(),
+        mode: "octet".to_string(),
+        options: vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            },
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 0,
+            },
+            TransferOption {
+                option: OptionType::WindowSize,
+                value: 8,
+            },
+        ],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), ("127.0.0.1", port))
+        .unwrap();
+
+    // Disabled options are left out of the OACK
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    let Packet::Oack { options, .. } = packet else {
+        panic!("expected an OACK, got {packet:?}");
+    };
+    assert_eq!(
+        options,
+        vec![TransferOption {
+            option: OptionType::BlockSize,
+            value: 1024,
+        }]
+    );
+    socket
+        .send_to(&Packet::Ack(0).serialize().unwrap(), worker)
+        .unwrap();
+    let (packet, _) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Data { block_num: 1, ref data } if data == b"firmware"));
+    socket
+        .send_to(&Packet::Ack(1).serialize().unwrap(), worker)
+        .unwrap();
+
+    // Unknown options are refused when the server is created
+    let mut config =
+        Config::default().merge_cli("127.0.0.1".to_string(), 0, server_dir, false, false);
+    config.disabled_options = Some(vec!["blocksize".to_string()]);
+    assert!(Server::new(&config).is_err());
+
+    cleanup_test_env(&test_dir);
+}
+
+#[test]
+fn test_from_socket() {
+    let dir = std::env::temp_dir().join(format!("tftp_from_socket_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pxelinux.0"), vec![0x42; 3000]).unwrap();
+
+    // The listen address of the configuration is ignored
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let addr = socket.local_addr().unwrap();
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 1, dir.clone(), false, false);
+    let mut server = Server::from_socket(socket, &config).unwrap();
+    assert_eq!(server.local_addr().unwrap(), addr);
+    let handle = server.shutdown_handle();
+    let listener = thread::spawn(move || server.listen());
+
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+    client.get("pxelinux.0", &dir.join("copy.0")).unwrap();
+    assert_eq!(fs::read(dir.join("copy.0")).unwrap(), vec![0x42; 3000]);
+
+    handle.shutdown();
+    listener.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_dscp() {
+    let dir = std::env::temp_dir().join(format!("tftp_dscp_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pxelinux.0"), vec![0x42; 3000]).unwrap();
+
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    assert!(Server::new(&config.clone().with_dscp(64)).is_err());
+
+    let server = Server::spawn_for_test_with(&config.with_dscp(46)).unwrap();
+    let addr = server.addr();
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+    client.get("pxelinux.0", &dir.join("copy.0")).unwrap();
+    assert_eq!(fs::read(dir.join("copy.0")).unwrap(), vec![0x42; 3000]);
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_worker_threads() {
+    let dir = std::env::temp_dir().join(format!("tftp_worker_threads_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("busy.bin"), vec![6; 200_000]).unwrap();
+
+    // One worker and one pending request, overriding the thread counts
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false)
+        .with_threads(4, 256, 0, Duration::from_secs(60))
+        .with_worker_threads(1)
+        .with_thread_queue(1);
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let addr = server.addr();
+    let client_config = ClientConfig::new(addr.ip().to_string(), addr.port());
+
+    let slow_download = |name: &str| {
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (config, file) = (client_config.clone(), dir.join(name));
+        let download = thread::spawn(move || {
+            let client = Client::new(config).unwrap().with_progress(move |_, _| {
+                let _ = started_tx.send(());
+                thread::sleep(Duration::from_millis(1));
+            });
+            client.get("busy.bin", &file)
+        });
+        (download, started_rx)
+    };
+    let (running, started) = slow_download("running.bin");
+    started.recv_timeout(Duration::from_secs(5)).unwrap();
+    let (queued, queued_started) = slow_download("queued.bin");
+    thread::sleep(Duration::from_millis(200));
+    assert!(queued_started.try_recv().is_err());
+
+    let client = Client::new(client_config.clone()).unwrap();
+    let result = client.get("busy.bin", &dir.join("refused.bin"));
+    assert!(matches!(
+        result,
+        Err(ClientError::ServerError {
+            code: ErrorCode::NotDefined,
+            ..
+        })
+    ));
+    assert!(running.join().unwrap().is_ok());
+    assert!(queued.join().unwrap().is_ok());
+    assert_eq!(fs::read(dir.join("queued.bin")).unwrap(), vec![6; 200_000]);
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_upload_webhook() {
+    let dir = std::env::temp_dir().join(format!("tftp_webhook_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let upload = dir.join("upload.bin");
+    fs::write(&upload, b"test").unwrap();
+
+    // Endpoint answering a single POST with its body
+    let endpoint = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/tftp", endpoint.local_addr().unwrap());
+    let hook = thread::spawn(move || {
+        let (mut stream, _) = endpoint.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        let body = loop {
+            let len = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..len]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n")
+                && let Some(length) = head.lines().find_map(|line| {
+                    line.to_lowercase()
+                        .strip_prefix("content-length: ")
+                        .map(str::to_string)
+                })
+                && body.len() >= length.trim().parse().unwrap()
+            {
+                assert!(head.starts_with("POST /hooks/tftp "));
+                break body.to_string();
+            }
+        };
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .unwrap();
+        body
+    });
+
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.join("root"), false, false)
+        .with_upload_webhook(url);
+    fs::create_dir_all(dir.join("root/dumps")).unwrap();
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let addr = server.addr();
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+    client.put(&upload, "dumps/core.1").unwrap();
+
+    let payload: serde_json::Value = serde_json::from_str(&hook.join().unwrap()).unwrap();
+    assert_eq!(
+        payload,
+        serde_json::json!({
+            "filename": "dumps/core.1",
+            "size": 4,
+            "client": "127.0.0.1",
+            "sha256": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+        })
+    );
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_sessions() {
+    let dir = std::env::temp_dir().join(format!("tftp_sessions_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("busy.bin"), vec![6; 200_000]).unwrap();
+
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let mut server = Server::new(&config).unwrap();
+    assert!(server.sessions().is_empty());
+    let addr = server.local_addr().unwrap();
+    let (handle, shutdown) = (server.sessions_handle(), server.shutdown_handle());
+    let listener = thread::spawn(move || server.listen());
+
+    // Keep a download in progress
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let file = dir.join("copy.bin");
+    let download = thread::spawn(move || {
+        let config = ClientConfig::new(addr.ip().to_string(), addr.port());
+        let client = Client::new(config).unwrap().with_progress(move |_, _| {
+            let _ = started_tx.send(());
+            thread::sleep(Duration::from_millis(1));
+        });
+        client.get("busy.bin", &file)
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let sessions = handle.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].filename, "busy.bin");
+    assert_eq!(sessions[0].direction, Direction::Read);
+    assert_eq!(sessions[0].client.ip(), addr.ip());
+    assert_eq!(sessions[0].size, Some(200_000));
+    let progress = sessions[0].progress().unwrap();
+    assert!(progress > 0.0 && progress < 100.0, "progress {progress}");
+
+    assert!(download.join().unwrap().is_ok());
+    thread::sleep(Duration::from_millis(200));
+    assert!(handle.sessions().is_empty());
+
+    shutdown.shutdown();
+    listener.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_abort() {
+    let dir = std::env::temp_dir().join(format!("tftp_abort_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("busy.bin"), vec![6; 1_000_000]).unwrap();
+
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let mut server = Server::new(&config).unwrap();
+    assert!(!server.abort(1));
+    let addr = server.local_addr().unwrap();
+    let (handle, shutdown) = (server.sessions_handle(), server.shutdown_handle());
+    let listener = thread::spawn(move || server.listen());
+
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let file = dir.join("copy.bin");
+    let download = thread::spawn(move || {
+        let config = ClientConfig::new(addr.ip().to_string(), addr.port());
+        let client = Client::new(config).unwrap().with_progress(move |_, _| {
+            let _ = started_tx.send(());
+            thread::sleep(Duration::from_millis(1));
+        });
+        client.get("busy.bin", &file)
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let sessions = handle.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert!(handle.abort(sessions[0].id));
+    assert!(matches!(
+        download.join().unwrap(),
+        Err(ClientError::ServerError {
+            code: ErrorCode::NotDefined,
+            ..
+        })
+    ));
+    thread::sleep(Duration::from_millis(200));
+    assert!(handle.sessions().is_empty());
+    assert!(!handle.abort(sessions[0].id));
+
+    // The server goes on serving other transfers
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+    client.get("busy.bin", &dir.join("copy.bin")).unwrap();
+
+    shutdown.shutdown();
+    listener.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_admin_socket() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+
+    let dir = std::env::temp_dir().join(format!("tftp_admin_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("admin.sock");
+    // A stale socket of a previous run is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false)
+        .with_metrics_addr("127.0.0.1:0".parse().unwrap())
+        .with_admin_socket(path.clone());
+    let mut server = Server::new(&config).unwrap();
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    let listener = thread::spawn(move || server.listen());
+
+    let stream = UnixStream::connect(&path).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut command = |command: &str| {
+        writeln!(&stream, "{command}").unwrap();
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_string();
+            let last = line == "ok" || line.starts_with("error: ");
+            lines.push(line);
+            if last {
+                return lines;
+            }
+        }
+    };
+
+    let stats = command("stats");
+    assert_eq!(stats[0], "sessions 0");
+    assert!(stats.contains(&"xtool_tftp_active_transfers 0".to_string()));
+    assert_eq!(command("sessions"), ["ok"]);
+    assert_eq!(command("abort 3"), ["error: no transfer 3 in progress"]);
+    assert_eq!(command("shutdown"), ["ok"]);
+    listener.join().unwrap();
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_compress() {
+    let dir = std::env::temp_dir().join(format!("tftp_compress_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = "[    0.000000] Booting Linux on physical CPU 0x0\n".repeat(2000);
+    fs::write(dir.join("dmesg.txt"), &log).unwrap();
+
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false)
+        .with_compress(true);
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let addr = server.addr();
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+
+    // The compressed version of a file is generated on request
+    let copy = dir.join("copy.gz");
+    client.get("dmesg.txt.gz", &copy).unwrap();
+    let compressed = fs::read(&copy).unwrap();
+    assert!(compressed.len() < log.len() / 10);
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(&compressed[..])
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text, log);
+    assert!(client.get("missing.txt.gz", &copy).is_err());
+
+    // Clients sending the compress option have it acknowledged
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let rrq = Packet::Rrq {
+        filename: "dmesg.txt".to_string(),
+        mode: "octet".to_string(),
+        options: vec![
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 0,
+            },
+            TransferOption {
+                option: OptionType::Compress,
+                value: 1,
+            },
+        ],
+        custom: vec![],
+    };
+    socket.send_to(&rrq.serialize().unwrap(), addr).unwrap();
+    let mut buf = [0; 512];
+    let (amt, tid) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(
+        Packet::deserialize(&buf[..amt]).unwrap(),
+        Packet::Oack {
+            options: vec![
+                TransferOption {
+                    option: OptionType::TransferSize,
+                    value: compressed.len() as u64,
+                },
+                TransferOption {
+                    option: OptionType::Compress,
+                    value: 1,
+                },
+            ],
+            custom: vec![],
+        }
+    );
+    let error = Packet::Error {
+        code: ErrorCode::NotDefined,
+        msg: "done".to_string(),
+    };
+    socket.send_to(&error.serialize().unwrap(), tid).unwrap();
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_drain() {
+    let dir = std::env::temp_dir().join(format!("tftp_drain_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("image.bin"), vec![8; 400_000]).unwrap();
+
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let mut server = Server::new(&config).unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let listener = thread::spawn(move || server.listen());
+
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let file = dir.join("copy.bin");
+    let download = thread::spawn(move || {
+        let config = ClientConfig::new(addr.ip().to_string(), addr.port());
+        let client = Client::new(config).unwrap().with_progress(move |_, _| {
+            let _ = started_tx.send(());
+            thread::sleep(Duration::from_millis(1));
+        });
+        client.get("image.bin", &file)
+    });
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let drain = thread::spawn(move || shutdown.drain());
+    thread::sleep(Duration::from_millis(300));
+    // New requests are refused while the transfer in progress goes on
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+    assert!(matches!(
+        client.get("image.bin", &dir.join("late.bin")),
+        Err(ClientError::ServerError {
+            code: ErrorCode::NotDefined,
+            ..
+        })
+    ));
+    assert!(!drain.is_finished());
+
+    download.join().unwrap().unwrap();
+    assert_eq!(fs::read(dir.join("copy.bin")).unwrap(), vec![8; 400_000]);
+    drain.join().unwrap();
+    listener.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_http_mirror() {
+    let dir = std::env::temp_dir().join(format!("tftp_http_mirror_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // Mirror serving the kernel once, then nothing
+    let mirror = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/boot", mirror.local_addr().unwrap());
+    thread::spawn(move || {
+        for (stream, kernel) in mirror.incoming().zip([true, false]) {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            let response = if kernel {
+                assert!(request.starts_with(b"GET /boot/images/vmlinuz "));
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: 3000\r\n\r\n{}",
+                    "k".repeat(3000)
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.join("root"), false, false)
+        .with_mirror(url);
+    fs::create_dir_all(dir.join("root")).unwrap();
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let addr = server.addr();
+    let mut config = ClientConfig::new(addr.ip().to_string(), addr.port());
+    config.timeout = Some(Duration::from_secs(5));
+    config.request_retries = Some(0);
+    let client = Client::new(config).unwrap();
+
+    // Answered once the file was fetched, without being requested again
+    client.get("images/vmlinuz", &dir.join("vmlinuz")).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("vmlinuz")).unwrap(),
+        "k".repeat(3000)
+    );
+    assert!(dir.join("root/images/vmlinuz").exists());
+    // Served from the root directory from then on
+    client.get("images/vmlinuz", &dir.join("vmlinuz")).unwrap();
+
+    assert!(matches!(
+        client.get("initrd.img", &dir.join("initrd.img")),
+        Err(ClientError::ServerError {
+            code: ErrorCode::FileNotFound,
+            ..
+        })
+    ));
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_memory_transport() {
+    let dir =
+        std::env::temp_dir().join(format!("tftp_memory_transport_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.join("kernel"), &content).unwrap();
+
+    // Server side answering a single request with a worker, without UDP sockets
+    let network = MemoryNetwork::new();
+    let listener = network.bind("10.0.0.1:69".parse().unwrap()).unwrap();
+    let (server_network, file_path) = (network.clone(), dir.join("kernel"));
+    let server = thread::spawn(move || {
+        let mut buf = [0; 512];
+        let (amt, from) = listener.recv(&mut buf).unwrap();
+        assert!(matches!(
+            Packet::deserialize(&buf[..amt]).unwrap(),
+            Packet::Rrq { filename, .. } if filename == "kernel"
+        ));
+        let socket = PeerSocket::new(
+            server_network.bind("10.0.0.1:0".parse().unwrap()).unwrap(),
+            from,
+        );
+        let worker = Worker::new(
+            Box::new(socket),
+            file_path,
+            Default::default(),
+            Default::default(),
+        );
+        worker.send(false).unwrap().join().unwrap()
+    });
+
+    let client = Client::new(ClientConfig::new("10.0.0.1".to_string(), 69))
+        .unwrap()
+        .with_transport(move |addr| Ok(Box::new(network.bind(addr)?)));
+    client.get("kernel", &dir.join("downloaded")).unwrap();
+    assert!(server.join().unwrap());
+    assert_eq!(fs::read(dir.join("downloaded")).unwrap(), content);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Refuses clients which do not announce their architecture with the
+/// `x-arch` vendor option
+struct ArchHandler;
+
+impl ServerHandler for ArchHandler {
+    fn on_request(&self, transfer: &TransferInfo) -> Result<(), (ErrorCode, String)> {
+        match transfer
+            .custom_options
+            .iter()
+            .find(|option| option.name == "x-arch")
+        {
+            Some(option) if option.value == "efi64" => Ok(()),
+            _ => Err((
+                ErrorCode::AccessViolation,
+                "unknown architecture".to_string(),
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_custom_options_reach_handler() {
+    let dir = std::env::temp_dir().join(format!("tftp_vendor_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("grubx64.efi"), b"grub").unwrap();
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let mut server = Server::new(&config)
+        .unwrap()
+        .with_handler(std::sync::Arc::new(ArchHandler));
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let listener = thread::spawn(move || server.listen());
+
+    let config = ClientConfig::new(addr.ip().to_string(), addr.port());
+    let client = Client::new(config.clone().with_custom_option("x-arch", "efi64")).unwrap();
+    client
+        .get("grubx64.efi", &dir.join("downloaded.efi"))
+        .unwrap();
+    assert_eq!(fs::read(dir.join("downloaded.efi")).unwrap(), b"grub");
+
+    let client = Client::new(config).unwrap();
+    assert!(matches!(
+        client.get("grubx64.efi", &dir.join("refused.efi")),
+        Err(ClientError::ServerError {
+            code: ErrorCode::AccessViolation,
+            ..
+        })
+    ));
+
+    shutdown.shutdown();
+    listener.join().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_sliding_window() {
+    let dir = std::env::temp_dir().join(format!("tftp_sliding_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("kernel.img"), vec![0x5a; 9 * 512 + 100]).unwrap();
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+
+    let check = |addr: std::net::SocketAddr| {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rrq = Packet::Rrq {
+            filename: "kernel.img".to_string(),
+            mode: "octet".to_string(),
+            options: vec![TransferOption {
+                option: OptionType::WindowSize,
+                value: 4,
+            }],
+            custom: vec![],
+        };
+        socket.send_to(&rrq.serialize().unwrap(), addr).unwrap();
+        let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+        assert!(matches!(packet, Packet::Oack { .. }));
+        let received = || match recv_packet(&socket, Duration::from_secs(2)).unwrap() {
+            (Packet::Data { block_num, .. }, _) => block_num,
+            (packet, _) => panic!("Unexpected packet {packet:?}"),
+        };
+        let ack = |block| {
+            socket
+                .send_to(&Packet::Ack(block).serialize().unwrap(), worker)
+                .unwrap();
+        };
+
+        ack(0);
+        assert_eq!((0..4).map(|_| received()).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        // An Ack below the last block sent reports the loss of block 3, sending
+        // restarts right after it without waiting for a timeout
+        ack(2);
+        assert_eq!((0..4).map(|_| received()).collect::<Vec<_>>(), [3, 4, 5, 6]);
+        ack(6);
+        assert_eq!(
+            (0..4).map(|_| received()).collect::<Vec<_>>(),
+            [7, 8, 9, 10]
+        );
+        ack(10);
+        assert!(recv_packet(&socket, Duration::from_millis(500)).is_err());
+    };
+
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    check(server.addr());
+    server.shutdown();
+
+    let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = AsyncServer::new(&config).await.unwrap();
+            addr_tx.send(server.local_addr().unwrap()).unwrap();
+            server.listen().await.unwrap();
+        });
+    });
+    check(addr_rx.recv().unwrap());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_refused_options() {
+    let dir = std::env::temp_dir().join(format!("tftp_refused_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // A server acknowledging a block size above the one requested
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let responder = thread::spawn(move || {
+        let (_, client) = recv_packet(&server, Duration::from_secs(2)).unwrap();
+        let oack = Packet::Oack {
+            options: vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 8192,
+            }],
+            custom: vec![],
+        };
+        server.send_to(&oack.serialize().unwrap(), client).unwrap();
+        recv_packet(&server, Duration::from_secs(2)).unwrap().0
+    });
+    let config = ClientConfig::new("127.0.0.1".parse().unwrap(), port)
+        .with_block_size(1024)
+        .with_timeout(Duration::from_millis(500));
+    let result = Client::new(config)
+        .unwrap()
+        .get("boot.img", &dir.join("boot.img"));
+    assert!(
+        matches!(result, Err(ClientError::OptionNegotiation(ref msg)) if msg.contains("blksize")),
+        "{result:?}"
+    );
+    assert!(matches!(
+        responder.join().unwrap(),
+        Packet::Error {
+            code: ErrorCode::RefusedOption,
+            ..
+        }
+    ));
+
+    // Servers do not answer clients refusing their OACK
+    fs::write(dir.join("boot.img"), b"image").unwrap();
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rrq = Packet::Rrq {
+        filename: "boot.img".to_string(),
+        mode: "octet".to_string(),
+        options: vec![TransferOption {
+            option: OptionType::TransferSize,
+            value: 0,
+        }],
+        custom: vec![],
+    };
+    socket
+        .send_to(&rrq.serialize().unwrap(), server.addr())
+        .unwrap();
+    let (packet, worker) = recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    assert!(matches!(packet, Packet::Oack { .. }));
+    let error = Packet::Error {
+        code: ErrorCode::RefusedOption,
+        msg: "tsize refused".to_string(),
+    };
+    socket.send_to(&error.serialize().unwrap(), worker).unwrap();
+    assert!(recv_packet(&socket, Duration::from_millis(500)).is_err());
+    server.shutdown();
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Returns the UDP ports and payloads of the IPv4 packets of a pcap file.
+fn read_pcap(path: &std::path::Path) -> Vec<(u16, u16, Vec<u8>)> {
+    let bytes = fs::read(path).unwrap();
+    assert_eq!(bytes[..4], 0xa1b2c3d4u32.to_le_bytes());
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let len = u32::from_le_bytes(bytes[offset + 8..offset + 12].try_into().unwrap()) as usize;
+        let packet = &bytes[offset + 16..offset + 16 + len];
+        let port = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]);
+        packets.push((port(20), port(22), packet[28..].to_vec()));
+        offset += 16 + len;
+    }
+    packets
+}
+
+#[test]
+fn test_pcap() {
+    let dir = std::env::temp_dir().join(format!("tftp_pcap_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("initrd.img"), vec![0x17; 1200]).unwrap();
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false)
+        .with_pcap(dir.join("server.pcap"));
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let port = server.addr().port();
+
+    let config = ClientConfig::new("127.0.0.1".to_string(), port)
+        .with_timeout(Duration::from_secs(2))
+        .with_pcap(dir.join("client.pcap"));
+    let client = Client::new(config).unwrap();
+    client.get("initrd.img", &dir.join("copy.img")).unwrap();
+    assert!(client.get("missing.img", &dir.join("pcap.img")).is_err());
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(b"\x00\x01boot.img", server.addr()).unwrap();
+    recv_packet(&socket, Duration::from_secs(2)).unwrap();
+    server.shutdown();
+
+    // The server captured the malformed request as it was sent, then its error
+    let mut captured = read_pcap(&dir.join("server.pcap"));
+    let error = captured.pop().unwrap();
+    let malformed = captured.pop().unwrap();
+    assert_eq!(malformed.2, b"\x00\x01boot.img");
+    assert!(matches!(
+        Packet::deserialize(&error.2).unwrap(),
+        Packet::Error {
+            code: ErrorCode::IllegalOperation,
+            ..
+        }
+    ));
+
+    // Both sides captured the requests, the OACK, three blocks, their Acks and the error
+    for (name, captured) in [
+        ("server.pcap", captured),
+        ("client.pcap", read_pcap(&dir.join("client.pcap"))),
+    ] {
+        let packets: Vec<Packet> = captured
+            .iter()
+            .map(|(_, _, payload)| Packet::deserialize(payload).unwrap())
+            .collect();
+        assert_eq!(packets.len(), 11, "{name}");
+        assert!(matches!(&packets[0], Packet::Rrq { filename, .. } if filename == "initrd.img"));
+        assert_eq!(captured[0].1, port);
+        assert!(matches!(packets[1], Packet::Oack { .. }));
+        let count = |matches: fn(&Packet) -> bool| packets.iter().filter(|p| matches(p)).count();
+        assert_eq!(count(|p| matches!(p, Packet::Data { .. })), 3);
+        assert_eq!(count(|p| matches!(p, Packet::Ack(_))), 4);
+        assert_eq!(
+            count(|p| matches!(
+                p,
+                Packet::Error {
+                    code: ErrorCode::FileNotFound,
+                    ..
+                }
+            )),
+            1
+        );
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_malformed_requests() {
+    let dir = std::env::temp_dir().join(format!("tftp_malformed_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::default().merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false);
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let addr = server.addr();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let expect_error = |datagram: &[u8], expected: ErrorCode| {
+        socket.send_to(datagram, addr).unwrap();
+        match recv_packet(&socket, Duration::from_secs(2)).unwrap().0 {
+            Packet::Error { code, .. } => assert_eq!(code, expected),
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+    };
+    expect_error(b"\x00\x01boot.img", ErrorCode::IllegalOperation);
+    expect_error(b"\x00\x09", ErrorCode::IllegalOperation);
+    expect_error(
+        b"\x00\x01boot.img\x00octet\x00blksize\x00large\x00",
+        ErrorCode::RefusedOption,
+    );
+
+    // Malformed errors are not answered
+    socket.send_to(b"\x00\x05\x00", addr).unwrap();
+    assert!(recv_packet(&socket, Duration::from_millis(300)).is_err());
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Writes a self-signed certificate valid for 127.0.0.1 and its key to `dir`.
+#[cfg(feature = "dtls")]
+fn write_certificate(dir: &std::path::Path) -> (PathBuf, PathBuf) {
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509, X509NameBuilder};
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "xtool test").unwrap();
+    let name = name.build();
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .ip("127.0.0.1")
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+    let (certificate, private_key) = (dir.join("tftp.pem"), dir.join("tftp.key"));
+    fs::write(&certificate, builder.build().to_pem().unwrap()).unwrap();
+    fs::write(&private_key, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    (certificate, private_key)
+}
+
+#[cfg(feature = "dtls")]
+#[test]
+fn test_dtls() {
+    use xtool::tftp::core::DtlsConfig;
+    use xtool::tftp::server::DtlsListener;
+
+    let dir = std::env::temp_dir().join(format!("tftp_dtls_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("root")).unwrap();
+    let (certificate, private_key) = write_certificate(&dir);
+    let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.join("root").join("config.bin"), &content).unwrap();
+
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.join("root"), false, false)
+        .with_dtls(DtlsListener {
+            port: 0,
+            certificates: DtlsConfig {
+                certificate: Some(certificate.clone()),
+                private_key: Some(private_key),
+                ca: None,
+            },
+        });
+    let mut server = Server::new(&config).unwrap();
+    let addr = server.dtls_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let listener = thread::spawn(move || server.listen());
+
+    let trusted = DtlsConfig {
+        ca: Some(certificate),
+        ..Default::default()
+    };
+    let mut config = ClientConfig::new(addr.ip().to_string(), addr.port()).with_dtls(trusted);
+    config.timeout = Some(Duration::from_secs(2));
+    let client = Client::new(config).unwrap();
+    client
+        .get("config.bin", &dir.join("downloaded.bin"))
+        .unwrap();
+    assert_eq!(fs::read(dir.join("downloaded.bin")).unwrap(), content);
+    client
+        .put(&dir.join("downloaded.bin"), "uploaded.bin")
+        .unwrap();
+    assert_eq!(
+        fs::read(dir.join("root").join("uploaded.bin")).unwrap(),
+        content
+    );
+
+    // Servers whose certificate is not trusted are refused
+    let mut config =
+        ClientConfig::new(addr.ip().to_string(), addr.port()).with_dtls(DtlsConfig::default());
+    config.timeout = Some(Duration::from_secs(2));
+    let client = Client::new(config).unwrap();
+    assert!(client.get("config.bin", &dir.join("refused.bin")).is_err());
+
+    shutdown.shutdown();
+    listener.join().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_spawn_for_test() {
+    let dir = std::env::temp_dir().join(format!("tftp_spawn_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pxelinux.0"), vec![0x42; 3000]).unwrap();
+
+    // Servers on ephemeral ports run side by side
+    let first = Server::spawn_for_test(&dir).unwrap();
+    let second = Server::spawn_for_test(&dir).unwrap();
+    assert_ne!(first.addr(), second.addr());
+    assert!(first.addr().ip().is_loopback());
+
+    let client = |addr: std::net::SocketAddr| {
+        let config = ClientConfig::new(addr.ip().to_string(), addr.port())
+            .with_timeout(Duration::from_millis(500))
+            .with_request_retries(1);
+        Client::new(config).unwrap()
+    };
+    let (first_client, second_client) = (client(first.addr()), client(second.addr()));
+    first_client.get("pxelinux.0", &dir.join("copy.0")).unwrap();
+    assert_eq!(fs::read(dir.join("copy.0")).unwrap(), vec![0x42; 3000]);
+    second_client
+        .get("pxelinux.0", &dir.join("copy.0"))
+        .unwrap();
+
+    first.shutdown();
+    assert!(first_client.get("pxelinux.0", &dir.join("copy.0")).is_err());
+    // Dropped servers are stopped too
+    drop(second);
+    assert!(
+        second_client
+            .get("pxelinux.0", &dir.join("copy.0"))
+            .is_err()
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_templates() {
+    let dir = std::env::temp_dir().join(format!("tftp_templates_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("pxelinux.cfg")).unwrap();
+    fs::write(
+        dir.join("boot.ipxe"),
+        "chain tftp://${server_ip}/${client_ip}",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("pxelinux.cfg/01-08-00-27-aa-bb-cc"),
+        "mac=${client_mac_hint}",
+    )
+    .unwrap();
+    fs::write(dir.join("plain.txt"), "${client_ip}").unwrap();
+
+    let config = Config::default()
+        .merge_cli("127.0.0.1".to_string(), 0, dir.clone(), false, false)
+        .with_templates(vec!["*.ipxe".to_string(), "pxelinux.cfg/*".to_string()]);
+    let server = Server::spawn_for_test_with(&config).unwrap();
+    let addr = server.addr();
+    let client = Client::new(ClientConfig::new(addr.ip().to_string(), addr.port())).unwrap();
+
+    let copy = dir.join("copy");
+    client.get("boot.ipxe", &copy).unwrap();
+    assert_eq!(
+        fs::read_to_string(&copy).unwrap(),
+        "chain tftp://127.0.0.1/127.0.0.1"
+    );
+    client
+        .get("pxelinux.cfg/01-08-00-27-aa-bb-cc", &copy)
+        .unwrap();
+    assert_eq!(fs::read_to_string(&copy).unwrap(), "mac=08:00:27:aa:bb:cc");
+    // Files not matching a pattern are served as is
+    client.get("plain.txt", &copy).unwrap();
+    assert_eq!(fs::read_to_string(&copy).unwrap(), "${client_ip}");
+
+    server.shutdown();
+    fs::remove_dir_all(dir).unwrap();
+}